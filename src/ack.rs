@@ -0,0 +1,193 @@
+//! Read receipts: a NIP-25 reaction to each accepted DM, enabled with `NPARROT_ACK_REACTIONS`
+//!
+//! `listen_for_messages` hands every message it accepts to `acknowledge` before any callback
+//! runs. Building and publishing the reaction happens on a background task fed through a bounded
+//! queue, so a slow or unreachable relay never holds up processing; when the rate limit or the
+//! queue is exhausted the ack is simply skipped.
+
+use crate::transport::SharedTransport;
+use nostr_sdk::prelude::*;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+/// The reaction clients render next to the acknowledged message
+const REACTION: &str = "✅";
+
+/// Acks that may go out back to back before the rate limit applies
+const BURST: u32 = 5;
+
+/// After a burst, one more ack is allowed per interval
+const REFILL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Acks waiting to be published before new ones are dropped
+const QUEUE_CAPACITY: usize = 32;
+
+lazy_static::lazy_static! {
+    static ref ACKNOWLEDGER: RwLock<Option<Arc<Acknowledger>>> = RwLock::new(None);
+}
+
+/// Starts publishing acks through `transport` and makes it the one `acknowledge` feeds
+pub fn init(transport: SharedTransport) {
+    let acknowledger = Arc::new(Acknowledger::start(transport));
+    if let Ok(mut guard) = ACKNOWLEDGER.write() {
+        *guard = Some(acknowledger);
+    }
+}
+
+/// Queues an ack for `message_id` from `sender`; does nothing unless `init` was called
+pub fn acknowledge(sender: PublicKey, message_id: EventId) {
+    let acknowledger = match ACKNOWLEDGER.read() {
+        Ok(guard) => guard.clone(),
+        Err(_) => None,
+    };
+    if let Some(acknowledger) = acknowledger {
+        acknowledger.acknowledge(sender, message_id);
+    }
+}
+
+#[derive(Debug)]
+pub struct Acknowledger {
+    queue: mpsc::Sender<(PublicKey, EventId)>,
+    limiter: RateLimiter,
+}
+
+impl Acknowledger {
+    pub fn start(transport: SharedTransport) -> Self {
+        let (queue, mut pending) = mpsc::channel::<(PublicKey, EventId)>(QUEUE_CAPACITY);
+        tokio::spawn(async move {
+            while let Some((sender, message_id)) = pending.recv().await {
+                let result = async {
+                    let event = transport
+                        .prepare_reaction(sender, message_id, REACTION.to_string())
+                        .await?;
+                    transport.send_event(&event).await
+                }
+                .await;
+                match result {
+                    Ok(_) => log::debug!("Acknowledged message {}", message_id),
+                    Err(e) => log::warn!("Could not acknowledge message {}: {}", message_id, e),
+                }
+            }
+        });
+        Self {
+            queue,
+            limiter: RateLimiter::new(BURST, REFILL_INTERVAL),
+        }
+    }
+
+    pub fn acknowledge(&self, sender: PublicKey, message_id: EventId) {
+        if !self.limiter.try_acquire(Instant::now()) {
+            log::debug!("Not acknowledging {}: rate limited", message_id);
+            return;
+        }
+        if let Err(e) = self.queue.try_send((sender, message_id)) {
+            log::debug!("Not acknowledging {}: {}", message_id, e);
+        }
+    }
+}
+
+/// A token bucket holding up to `burst` tokens, refilled one per `interval`
+#[derive(Debug)]
+struct RateLimiter {
+    burst: u32,
+    interval: Duration,
+    state: Mutex<(u32, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(burst: u32, interval: Duration) -> Self {
+        Self {
+            burst,
+            interval,
+            state: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    fn try_acquire(&self, now: Instant) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        let (tokens, refilled_at) = &mut *state;
+        let refills = (now.saturating_duration_since(*refilled_at).as_millis()
+            / self.interval.as_millis().max(1)) as u32;
+        if refills > 0 {
+            *tokens = (*tokens + refills).min(self.burst);
+            *refilled_at += self.interval * refills;
+        }
+        if *tokens == 0 {
+            return false;
+        }
+        *tokens -= 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+    use crate::utils::unwrap_gift_wrap;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(2));
+        let start = Instant::now();
+        assert!(limiter.try_acquire(start));
+        assert!(limiter.try_acquire(start));
+        assert!(!limiter.try_acquire(start));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(1)));
+        assert!(limiter.try_acquire(start + Duration::from_secs(2)));
+        assert!(!limiter.try_acquire(start + Duration::from_secs(3)));
+        // Never more than the burst, however long it was quiet
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.try_acquire(later));
+        assert!(limiter.try_acquire(later));
+        assert!(!limiter.try_acquire(later));
+    }
+
+    #[tokio::test]
+    async fn test_ack_is_a_wrapped_reaction() {
+        let ours = Keys::generate();
+        let user = Keys::generate();
+        let transport = FakeTransport::new(ours.clone());
+        let acknowledger = Acknowledger::start(Arc::new(transport.clone()));
+
+        let message_id = EventId::all_zeros();
+        acknowledger.acknowledge(user.public_key(), message_id);
+        while transport.published().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        let gift = unwrap_gift_wrap(&user, &transport.published()[0])
+            .await
+            .unwrap();
+        assert_eq!(gift.sender, ours.public_key());
+        assert_eq!(gift.rumor.kind, Kind::Reaction);
+        assert_eq!(gift.rumor.content, REACTION);
+        assert_eq!(gift.rumor.tags.event_ids().next(), Some(&message_id));
+        assert_eq!(
+            gift.rumor.tags.public_keys().next(),
+            Some(&user.public_key())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_ack_does_not_stop_later_ones() {
+        let transport = FakeTransport::new(Keys::generate());
+        let acknowledger = Acknowledger::start(Arc::new(transport.clone()));
+        let user = Keys::generate().public_key();
+
+        transport.fail_sends(true);
+        acknowledger.acknowledge(user, EventId::all_zeros());
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        transport.fail_sends(false);
+        acknowledger.acknowledge(user, EventId::all_zeros());
+        while transport.published().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(transport.published().len(), 1);
+    }
+}
@@ -0,0 +1,132 @@
+//! Startup and shutdown notices of the MCP servers (`NPARROT_ANNOUNCE_START=1`)
+//!
+//! One progress DM when a server is ready, with the version, the mode, the tools it offers and
+//! the relays it reached, and one when it stops gracefully; together they confirm a deploy.
+
+use crate::envelope::{self, MessageType};
+use crate::utils::send_private_msg;
+use nostr_sdk::prelude::*;
+use rmcp::model::Tool;
+
+/// What the startup notice reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct Startup {
+    /// The subcommand, e.g. `multi-agent-mcp`
+    pub mode: String,
+    pub tools: Vec<String>,
+    pub connected: Vec<String>,
+    pub disconnected: Vec<String>,
+    pub data_dir: String,
+}
+
+impl Startup {
+    /// The startup of `mode` with the relays `client` reached so far
+    pub async fn collect(client: &Client, mode: &str, tools: &[Tool], data_dir: &str) -> Self {
+        let (mut connected, mut disconnected): (Vec<String>, Vec<String>) =
+            (Vec::new(), Vec::new());
+        for (url, relay) in client.relays().await {
+            if relay.status() == RelayStatus::Connected {
+                connected.push(url.to_string());
+            } else {
+                disconnected.push(url.to_string());
+            }
+        }
+        connected.sort();
+        disconnected.sort();
+        Self {
+            mode: mode.to_string(),
+            tools: tools.iter().map(|tool| tool.name.to_string()).collect(),
+            connected,
+            disconnected,
+            data_dir: data_dir.to_string(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        let mut message = format!(
+            "🟢 nparrot {} started as {}\nTools ({}): {}\nRelays: {} of {} connected",
+            env!("CARGO_PKG_VERSION"),
+            self.mode,
+            self.tools.len(),
+            self.tools.join(", "),
+            self.connected.len(),
+            self.connected.len() + self.disconnected.len()
+        );
+        for url in &self.connected {
+            message.push_str(&format!("\n  ✅ {}", url));
+        }
+        for url in &self.disconnected {
+            message.push_str(&format!("\n  ❌ {}", url));
+        }
+        message.push_str(&format!("\nData dir: {}", self.data_dir));
+        message
+    }
+}
+
+pub fn shutdown_message(mode: &str) -> String {
+    format!(
+        "🔴 nparrot {} ({}) stopped",
+        env!("CARGO_PKG_VERSION"),
+        mode
+    )
+}
+
+/// Sends the notices of one server
+#[derive(Debug, Clone)]
+pub struct Announcer {
+    /// The main identity, whose relays the notice reports
+    pub client: Client,
+    /// The progress identity if there is one, else the main one
+    pub sender: Client,
+    pub target: PublicKey,
+    pub mode: String,
+    pub data_dir: String,
+    pub expire_after_secs: Option<u64>,
+}
+
+impl Announcer {
+    pub async fn started(&self, tools: &[Tool]) {
+        let startup = Startup::collect(&self.client, &self.mode, tools, &self.data_dir).await;
+        self.send(startup.message()).await;
+    }
+
+    pub async fn stopped(&self) {
+        self.send(shutdown_message(&self.mode)).await;
+    }
+
+    /// A failure is only logged, it never stops the server
+    async fn send(&self, message: String) {
+        let notice = envelope::wrap(MessageType::Progress, message);
+        if let Err(e) =
+            send_private_msg(&self.sender, self.target, notice, self.expire_after_secs).await
+        {
+            log::warn!("Could not send the start/stop notice: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_message() {
+        let startup = Startup {
+            mode: "combined-mcp".to_string(),
+            tools: vec!["send".to_string(), "wait".to_string()],
+            connected: vec!["wss://a.example".to_string()],
+            disconnected: vec!["wss://b.example".to_string()],
+            data_dir: "/var/lib/nparrot".to_string(),
+        };
+        let message = startup.message();
+        assert!(message.starts_with(&format!(
+            "🟢 nparrot {} started as combined-mcp",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(message.contains("Tools (2): send, wait"));
+        assert!(message
+            .contains("Relays: 1 of 2 connected\n  ✅ wss://a.example\n  ❌ wss://b.example"));
+        assert!(message.ends_with("Data dir: /var/lib/nparrot"));
+        assert!(shutdown_message("combined-mcp").contains("(combined-mcp) stopped"));
+    }
+}
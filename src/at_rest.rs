@@ -0,0 +1,394 @@
+//! At-rest encryption for the notes and events files
+//!
+//! A file is `MAGIC`, a format version byte, a random 24-byte nonce and the XChaCha20-Poly1305
+//! ciphertext of the JSON store. The key is derived from `NPARROT_DATA_KEY` when set, otherwise
+//! from the main nsec. Files without the magic are legacy plaintext: they are read as they are
+//! and encrypted by the next save.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const MAGIC: &[u8; 8] = b"NPARROT\0";
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + NONCE_LEN;
+
+/// Domain separation, so the data key never equals a key used for anything else
+const KDF_LABEL: &[u8] = b"nparrot data key v1";
+
+lazy_static::lazy_static! {
+    static ref GLOBAL: Vault = Vault::new(None);
+    static ref NEXT_KEY: Mutex<Option<DataKey>> = Mutex::new(None);
+}
+
+/// Sets the key `rotate_data_key` switches to (`NPARROT_NEW_DATA_KEY`); the operator chooses
+/// it, never the model
+pub fn set_next_key(key: Option<DataKey>) {
+    *NEXT_KEY.lock().unwrap_or_else(|e| e.into_inner()) = key;
+}
+
+pub fn next_key() -> Option<DataKey> {
+    NEXT_KEY.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// A 256-bit data key
+#[derive(Clone, PartialEq, Eq)]
+pub struct DataKey([u8; 32]);
+
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("DataKey(..)")
+    }
+}
+
+impl DataKey {
+    /// Derives the key from secret material: the nsec's bytes or a `NPARROT_DATA_KEY` passphrase
+    pub fn derive(secret: &[u8]) -> Self {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(KDF_LABEL).expect("any key size");
+        mac.update(secret);
+        Self(mac.finalize().into_bytes().into())
+    }
+
+    /// For clap: a passphrase given on the command line or in the environment
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value.trim().len() < 12 {
+            return Err("the data key must be at least 12 characters".to_string());
+        }
+        Ok(Self::derive(value.trim().as_bytes()))
+    }
+
+    /// HMAC-SHA256 of `data` under this key, hex encoded; `label` keeps different uses apart
+    pub fn keyed_hash(&self, label: &[u8], data: &[u8]) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0).expect("any key size");
+        mac.update(label);
+        mac.update(data);
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+/// Whether `bytes` are in the encrypted format (as opposed to legacy plaintext)
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn seal(key: &DataKey, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, plaintext)
+        .map_err(|e| format!("encryption failed: {}", e))?;
+    let mut sealed = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.push(VERSION);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts `bytes`, or returns them unchanged if they are legacy plaintext
+pub fn open(key: Option<&DataKey>, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_encrypted(bytes) {
+        return Ok(bytes.to_vec());
+    }
+    let key = key.ok_or("the file is encrypted but no data key is configured")?;
+    if bytes.len() < HEADER_LEN {
+        return Err("the encrypted file is truncated".to_string());
+    }
+    let version = bytes[MAGIC.len()];
+    if version != VERSION {
+        return Err(format!("unsupported encrypted file version {}", version));
+    }
+    let nonce = XNonce::from_slice(&bytes[MAGIC.len() + 1..HEADER_LEN]);
+    key.cipher()
+        .decrypt(nonce, &bytes[HEADER_LEN..])
+        .map_err(|_| "the file cannot be decrypted with this data key".to_string())
+}
+
+/// The data key plus the lock every read-modify-write of a store file goes through, so a key
+/// rotation never interleaves with a save
+#[derive(Debug)]
+pub struct Vault {
+    key: Mutex<Option<DataKey>>,
+}
+
+impl Vault {
+    pub fn new(key: Option<DataKey>) -> Self {
+        Self {
+            key: Mutex::new(key),
+        }
+    }
+
+    /// The one the notes and events stores use
+    pub fn global() -> &'static Vault {
+        &GLOBAL
+    }
+
+    pub fn set_key(&self, key: Option<DataKey>) {
+        *self.key.lock().unwrap_or_else(|e| e.into_inner()) = key;
+    }
+
+    /// The key in use, e.g. to undo what was re-encrypted when a rotation fails
+    pub fn key(&self) -> Option<DataKey> {
+        self.key.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// The plaintext of the file at `path`, or `None` if there is no such file
+    pub fn read(&self, path: &Path) -> Result<Option<Vec<u8>>, String> {
+        let key = self.key.lock().unwrap_or_else(|e| e.into_inner());
+        read_with(key.as_ref(), path)
+    }
+
+    /// Encrypts `plaintext` (if a key is set) and replaces the file at `path` in one step
+    pub fn write(&self, path: &Path, plaintext: &[u8]) -> Result<(), String> {
+        let key = self.key.lock().unwrap_or_else(|e| e.into_inner());
+        write_with(key.as_ref(), path, plaintext)
+    }
+
+    /// Encrypts `plaintext` on its own, e.g. one line of an append-only log; `None` without a key
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let key = self.key.lock().unwrap_or_else(|e| e.into_inner());
+        key.as_ref().map(|key| seal(key, plaintext)).transpose()
+    }
+
+    /// Decrypts what `seal` produced
+    pub fn open(&self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let key = self.key.lock().unwrap_or_else(|e| e.into_inner());
+        open(key.as_ref(), bytes)
+    }
+
+    /// Re-encrypts every file in `paths` with `new_key` and switches to it.
+    ///
+    /// The re-encrypted copies are written next to the originals and read back first, without
+    /// holding the lock; only if every one of them decrypts to the same content are the
+    /// originals replaced. The lock is taken for that swap alone, and a file saved in the
+    /// meantime aborts it. The old copies are kept as `<file>.old` until all files are switched;
+    /// if one of them can't be, those already switched are put back and the old key stays.
+    pub fn rotate(&self, paths: &[PathBuf], new_key: DataKey) -> Result<usize, String> {
+        let old_key = self.key.lock().unwrap_or_else(|e| e.into_inner()).clone();
+
+        let mut staged = Vec::new();
+        let result = (|| {
+            for path in paths {
+                let Some(stamp) = stamp(path)? else {
+                    continue;
+                };
+                let Some(plaintext) = read_with(old_key.as_ref(), path)? else {
+                    continue;
+                };
+                let staging = with_suffix(path, "rotating");
+                write_with(Some(&new_key), &staging, &plaintext)?;
+                staged.push(Staged {
+                    path: path.clone(),
+                    staging: staging.clone(),
+                    stamp,
+                });
+                let check = read_with(Some(&new_key), &staging)?;
+                if check.as_deref() != Some(plaintext.as_slice()) {
+                    return Err(format!(
+                        "{} did not read back correctly with the new key",
+                        path.display()
+                    ));
+                }
+            }
+            Ok(())
+        })();
+        let discard = |staged: &[Staged]| {
+            for file in staged {
+                let _ = fs::remove_file(&file.staging);
+            }
+        };
+        if let Err(e) = result {
+            discard(&staged);
+            return Err(e);
+        }
+
+        let mut key = self.key.lock().unwrap_or_else(|e| e.into_inner());
+        for file in &staged {
+            if *key != old_key || stamp(&file.path)? != Some(file.stamp) {
+                discard(&staged);
+                return Err(format!(
+                    "{} changed during the rotation; nothing was replaced, try again",
+                    file.path.display()
+                ));
+            }
+        }
+        for (swapped, file) in staged.iter().enumerate() {
+            if let Err(e) = swap(file) {
+                for file in staged[..swapped].iter().rev() {
+                    let _ = fs::rename(with_suffix(&file.path, "old"), &file.path);
+                }
+                discard(&staged[swapped..]);
+                return Err(e);
+            }
+        }
+        for file in &staged {
+            let _ = fs::remove_file(with_suffix(&file.path, "old"));
+        }
+        *key = Some(new_key);
+        Ok(staged.len())
+    }
+}
+
+/// A re-encrypted copy waiting to replace `path`
+struct Staged {
+    path: PathBuf,
+    staging: PathBuf,
+    /// Size and modification time of `path` when it was read
+    stamp: (u64, SystemTime),
+}
+
+/// Size and modification time of the file at `path`, `None` if there is none; every save
+/// replaces the file, so a save in between changes it
+fn stamp(path: &Path) -> Result<Option<(u64, SystemTime)>, String> {
+    match fs::metadata(path) {
+        Ok(metadata) => metadata
+            .modified()
+            .map(|modified| Some((metadata.len(), modified)))
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Moves the original aside as `<file>.old` and the staged copy into its place
+fn swap(file: &Staged) -> Result<(), String> {
+    let old = with_suffix(&file.path, "old");
+    fs::rename(&file.path, &old)
+        .map_err(|e| format!("Failed to replace {}: {}", file.path.display(), e))?;
+    if let Err(e) = fs::rename(&file.staging, &file.path) {
+        let _ = fs::rename(&old, &file.path);
+        return Err(format!("Failed to replace {}: {}", file.path.display(), e));
+    }
+    Ok(())
+}
+
+fn read_with(key: Option<&DataKey>, path: &Path) -> Result<Option<Vec<u8>>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    open(key, &bytes)
+        .map(Some)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+fn write_with(key: Option<&DataKey>, path: &Path, plaintext: &[u8]) -> Result<(), String> {
+    let bytes = match key {
+        Some(key) => seal(key, plaintext)?,
+        None => plaintext.to_vec(),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create storage directory: {}", e))?;
+    }
+    let temporary = with_suffix(path, "tmp");
+    fs::write(&temporary, bytes)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_open() {
+        let key = DataKey::derive(b"secret");
+        let sealed = seal(&key, b"{\"a\":1}").unwrap();
+        assert!(is_encrypted(&sealed));
+        // A fresh nonce per write
+        assert_ne!(sealed, seal(&key, b"{\"a\":1}").unwrap());
+        assert_eq!(open(Some(&key), &sealed).unwrap(), b"{\"a\":1}");
+
+        assert!(open(Some(&DataKey::derive(b"other")), &sealed).is_err());
+        assert!(open(None, &sealed).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(Some(&key), &tampered).is_err());
+        // Legacy plaintext passes through
+        assert_eq!(open(Some(&key), b"{}").unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_rotation_re_encrypts_every_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.json");
+        let events = dir.path().join("events.json");
+        let old_key = DataKey::derive(b"old");
+        let vault = Vault::new(Some(old_key.clone()));
+        vault.write(&notes, b"notes").unwrap();
+        // A legacy plaintext file is encrypted by the rotation too
+        fs::write(&events, b"events").unwrap();
+
+        let new_key = DataKey::derive(b"new");
+        let missing = dir.path().join("missing.json");
+        assert_eq!(
+            vault
+                .rotate(&[notes.clone(), events.clone(), missing], new_key.clone())
+                .unwrap(),
+            2
+        );
+        for (path, content) in [(&notes, &b"notes"[..]), (&events, b"events")] {
+            let bytes = fs::read(path).unwrap();
+            assert_eq!(open(Some(&new_key), &bytes).unwrap(), content);
+            assert!(open(Some(&old_key), &bytes).is_err());
+        }
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+        assert_eq!(vault.read(&notes).unwrap().unwrap(), b"notes");
+
+        // An unreadable file aborts the rotation before anything is replaced
+        let stuck = Vault::new(Some(DataKey::derive(b"wrong")));
+        assert!(stuck.rotate(std::slice::from_ref(&notes), old_key).is_err());
+        assert_eq!(
+            open(Some(&new_key), &fs::read(&notes).unwrap()).unwrap(),
+            b"notes"
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_failed_swap_puts_the_switched_files_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.json");
+        let events = dir.path().join("events.json");
+        let old_key = DataKey::derive(b"old");
+        let vault = Vault::new(Some(old_key.clone()));
+        vault.write(&notes, b"notes").unwrap();
+        vault.write(&events, b"events").unwrap();
+        // Moving events.json aside fails, after notes.json was already switched
+        fs::create_dir(with_suffix(&events, "old")).unwrap();
+        fs::write(with_suffix(&events, "old").join("keep"), b"").unwrap();
+
+        let new_key = DataKey::derive(b"new");
+        assert!(vault
+            .rotate(&[notes.clone(), events.clone()], new_key)
+            .is_err());
+        for (path, content) in [(&notes, &b"notes"[..]), (&events, b"events")] {
+            assert_eq!(vault.read(path).unwrap().unwrap(), content);
+            assert!(open(Some(&old_key), &fs::read(path).unwrap()).is_ok());
+        }
+        let mut left: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["events.json", "events.json.old", "notes.json"]);
+    }
+}
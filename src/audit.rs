@@ -0,0 +1,351 @@
+//! Audit log of the tool calls the long-running MCP servers handle
+//!
+//! Every call through `EnhancedMcpServer`, `CombinedServer` and `MultiAgentMcp` is recorded
+//! with the tool name, a hash and the shape of its arguments, how long it took and how it ended.
+//! The last entries are kept in memory for the `get_audit_log` tool, which only exists with
+//! `NPARROT_AUDIT_TOOL=1`, and every entry is appended to `audit.jsonl` in the data dir. The log
+//! is not encrypted, so argument values never go into it: strings are stored as their length,
+//! secret arguments such as `new_key` are dropped before hashing, and secrets are redacted from
+//! error messages. The hash is keyed with the data key (a random per-process key without one),
+//! so short arguments can't be recovered from it by trying values.
+
+use crate::at_rest::{DataKey, Vault};
+use crate::error::NparrotError;
+use chrono::{DateTime, Utc};
+use rmcp::model::{CallToolRequestParam, CallToolResult, Content};
+use rmcp::{schemars, Error as RmcpError};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+/// Entries kept for `get_audit_log`
+const CAPACITY: usize = 500;
+/// Characters of error messages kept in an entry
+const PREVIEW_CHARS: usize = 200;
+/// Arguments left out of an entry altogether, hash included
+const SECRET_ARGS: &[&str] = &[
+    "new_key",
+    "nsec",
+    "passphrase",
+    "password",
+    "secret",
+    "token",
+];
+/// Entries `get_audit_log` returns unless asked for another number
+const DEFAULT_LIMIT: usize = 20;
+
+pub const TOOL: &str = "get_audit_log";
+
+lazy_static::lazy_static! {
+    static ref LOG: AuditLog = AuditLog::new(CAPACITY);
+    static ref SETTINGS: RwLock<Settings> = RwLock::new(Settings::default());
+    static ref PROCESS_KEY: DataKey = DataKey::derive(&rand::random::<[u8; 32]>());
+}
+
+/// Label of the argument hash, so it never matches another use of the data key
+const HASH_LABEL: &[u8] = b"nparrot audit args\0";
+
+#[derive(Debug, Default)]
+struct Settings {
+    file: Option<PathBuf>,
+    tool_enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    /// The tool ran but reported a failure (`is_error`)
+    ToolError,
+    /// The call itself failed, e.g. invalid arguments
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub server: String,
+    pub tool: String,
+    /// HMAC-SHA256 of the arguments without the secret ones, to spot repeated calls
+    pub args_hash: String,
+    /// Each argument's name and shape, e.g. `{"content":"<340 chars>","confirm":true}`
+    pub args_preview: String,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct AuditLogRequest {
+    #[serde(default)]
+    #[schemars(description = "How many of the latest tool calls to return (default 20)")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug)]
+struct AuditLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<AuditEntry>>,
+}
+
+impl AuditLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn push(&self, entry: AuditEntry) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// The last `limit` entries, oldest first
+    fn latest(&self, limit: usize) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter()
+            .skip(entries.len().saturating_sub(limit))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Writes entries to `audit.jsonl` in `data_dir`; `tool_enabled` exposes `get_audit_log`
+pub fn init(data_dir: &str, tool_enabled: bool) {
+    if let Ok(mut settings) = SETTINGS.write() {
+        settings.file = Some(Path::new(data_dir).join("audit.jsonl"));
+        settings.tool_enabled = tool_enabled;
+    }
+}
+
+/// Whether the operator exposed `get_audit_log`
+pub fn tool_enabled() -> bool {
+    SETTINGS
+        .read()
+        .map(|settings| settings.tool_enabled)
+        .unwrap_or(false)
+}
+
+/// Runs a tool call and records it
+pub async fn record<F>(
+    server: &str,
+    request: &CallToolRequestParam,
+    call: F,
+) -> Result<CallToolResult, RmcpError>
+where
+    F: Future<Output = Result<CallToolResult, RmcpError>>,
+{
+    let mut arguments = request.arguments.clone().unwrap_or_default();
+    arguments.retain(|name, _| !SECRET_ARGS.contains(&name.as_str()));
+    let args_preview = if arguments.is_empty() {
+        String::new()
+    } else {
+        shape(&serde_json::Value::Object(arguments.clone())).to_string()
+    };
+    let arguments = if request.arguments.is_some() {
+        serde_json::Value::Object(arguments).to_string()
+    } else {
+        String::new()
+    };
+    let started = Instant::now();
+    let result = call.await;
+
+    let (outcome, error) = match &result {
+        Ok(result) if result.is_error == Some(true) => (
+            Outcome::ToolError,
+            result
+                .content
+                .first()
+                .and_then(|content| content.as_text())
+                .map(|text| preview(&text.text)),
+        ),
+        Ok(_) => (Outcome::Success, None),
+        Err(e) => (Outcome::Error, Some(preview(&e.message))),
+    };
+    let entry = AuditEntry {
+        timestamp: Utc::now(),
+        server: server.to_string(),
+        tool: request.name.to_string(),
+        args_hash: args_hash(&arguments),
+        args_preview,
+        duration_ms: started.elapsed().as_millis() as u64,
+        outcome,
+        error,
+    };
+    append(&entry);
+    LOG.push(entry);
+    result
+}
+
+fn args_hash(arguments: &str) -> String {
+    Vault::global()
+        .key()
+        .unwrap_or_else(|| PROCESS_KEY.clone())
+        .keyed_hash(HASH_LABEL, arguments.as_bytes())
+}
+
+fn append(entry: &AuditEntry) {
+    let Some(file) = SETTINGS
+        .read()
+        .ok()
+        .and_then(|settings| settings.file.clone())
+    else {
+        return;
+    };
+    let result = (|| -> std::io::Result<()> {
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)?
+            .write_all(&line)
+    })();
+    if let Err(e) = result {
+        log::error!("Failed to write audit log {}: {}", file.display(), e);
+    }
+}
+
+/// `value` with every string replaced by its length and every array by its item count
+fn shape(value: &serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::String(text) => Value::String(format!("<{} chars>", text.chars().count())),
+        Value::Array(items) => Value::String(format!("<{} items>", items.len())),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), shape(value)))
+                .collect(),
+        ),
+        scalar => scalar.clone(),
+    }
+}
+
+/// `text` with secrets redacted, cut to `PREVIEW_CHARS` characters
+fn preview(text: &str) -> String {
+    let redacted = crate::redact::redact(text);
+    match redacted.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}…", &redacted[..end]),
+        None => redacted.into_owned(),
+    }
+}
+
+/// What `get_audit_log` returns
+pub fn tool_result(limit: Option<usize>) -> Result<CallToolResult, RmcpError> {
+    if !tool_enabled() {
+        return Err(NparrotError::backend_missing(
+            "audit",
+            "The audit log is not exposed (NPARROT_AUDIT_TOOL)",
+        )
+        .into());
+    }
+    let entries = LOG.latest(limit.unwrap_or(DEFAULT_LIMIT));
+    Ok(CallToolResult::success(vec![
+        Content::text(format!("Last {} tool call(s), oldest first", entries.len())),
+        Content::json(entries)?,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::*;
+    use sha2::Digest;
+
+    #[test]
+    fn test_preview_redacts_secrets() {
+        let nsec = Keys::generate().secret_key().to_bech32().unwrap();
+        let hex = Keys::generate().secret_key().to_secret_hex();
+        let text = format!(
+            "Goose failed for {}: OPENAI_API_KEY: sk-proj-4fG7hJ2kL9mN1pQ3, secret_key={}, uri nostr+walletconnect://abc?secret=def",
+            nsec, hex
+        );
+        let redacted = preview(&text);
+        for secret in [nsec.as_str(), "sk-proj-4fG7", hex.as_str(), "secret=def"] {
+            assert!(!redacted.contains(secret), "{} in {}", secret, redacted);
+        }
+        assert!(redacted.contains(crate::redact::MASK));
+
+        let long = "é".repeat(PREVIEW_CHARS + 10);
+        assert_eq!(preview(&long).chars().count(), PREVIEW_CHARS + 1);
+    }
+
+    #[tokio::test]
+    async fn test_argument_values_stay_out_of_the_entry() {
+        let request: CallToolRequestParam = serde_json::from_value(serde_json::json!({
+            "name": "rotate_data_key",
+            "arguments": {
+                "new_key": "correct horse battery staple",
+                "content": "Dinner with Ada at 8",
+                "tags": ["a", "b"],
+                "confirm": true,
+            },
+        }))
+        .unwrap();
+        record("test", &request, async {
+            Ok(CallToolResult::success(vec![]))
+        })
+        .await
+        .unwrap();
+
+        let entry = LOG
+            .latest(CAPACITY)
+            .into_iter()
+            .rfind(|entry| entry.tool == "rotate_data_key")
+            .unwrap();
+        let preview: serde_json::Value = serde_json::from_str(&entry.args_preview).unwrap();
+        assert_eq!(
+            preview,
+            serde_json::json!({ "content": "<20 chars>", "tags": "<2 items>", "confirm": true })
+        );
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(!line.contains("horse") && !line.contains("Ada"));
+        // The hash doesn't depend on the secret either
+        let without_key = serde_json::json!({
+            "content": "Dinner with Ada at 8",
+            "tags": ["a", "b"],
+            "confirm": true,
+        })
+        .to_string();
+        assert_eq!(entry.args_hash, args_hash(&without_key));
+        // and is keyed, so it can't be checked against guesses without the key
+        assert_ne!(
+            entry.args_hash,
+            format!("{:x}", sha2::Sha256::digest(without_key.as_bytes()))
+        );
+    }
+
+    #[test]
+    fn test_ring_keeps_the_latest_entries() {
+        let log = AuditLog::new(2);
+        for tool in ["a", "b", "c"] {
+            log.push(AuditEntry {
+                timestamp: Utc::now(),
+                server: "test".to_string(),
+                tool: tool.to_string(),
+                args_hash: String::new(),
+                args_preview: String::new(),
+                duration_ms: 0,
+                outcome: Outcome::Success,
+                error: None,
+            });
+        }
+        let tools: Vec<_> = log.latest(5).into_iter().map(|entry| entry.tool).collect();
+        assert_eq!(tools, ["b", "c"]);
+        assert_eq!(log.latest(1)[0].tool, "c");
+    }
+}
@@ -0,0 +1,281 @@
+//! The texts nparrot itself says to the user (`--messages`)
+//!
+//! Every confirmation, notice and completion phrase is a `Key` with an English default. A TOML
+//! file of `key = "template"` lines replaces any of them, e.g. to translate them or change their
+//! tone; keys the file leaves out keep the default. Templates fill in `{placeholders}` (each key
+//! has its own set) and `{{` / `}}` stand for literal braces. The file is checked when nparrot
+//! starts, so an unknown key or placeholder stops it there instead of garbling a message.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+lazy_static::lazy_static! {
+    static ref CATALOG: RwLock<Catalog> = RwLock::new(Catalog::default());
+}
+
+macro_rules! keys {
+    ($($key:ident $name:literal [$($placeholder:literal),*] => $default:expr,)*) => {
+        /// A text nparrot sends, named in the messages file by its snake_case name
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Key {
+            $($key,)*
+        }
+
+        impl Key {
+            pub const ALL: &'static [Key] = &[$(Key::$key,)*];
+
+            /// The key's name in the messages file
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Key::$key => $name,)*
+                }
+            }
+
+            /// The placeholders its template may use
+            pub fn placeholders(self) -> &'static [&'static str] {
+                match self {
+                    $(Key::$key => &[$($placeholder),*],)*
+                }
+            }
+
+            pub fn default_template(self) -> &'static str {
+                match self {
+                    $(Key::$key => $default,)*
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    MessageSent "message_sent" [] => "Message sent!",
+    TaskCompleted "task_completed" [] => "Task completed",
+    StallNotice "stall_notice" [] =>
+        "⏳ The assistant appears to be delayed, still waiting for it to pick up your message…",
+    StallApology "stall_apology" [] =>
+        "Sorry, I'm stuck on your message and can't answer right now. Please send it again in a little while.",
+    AgentCompleted "agent_completed" ["name", "usage"] =>
+        "✅ Agent {name} has completed its task and stopped\n{usage}",
+    ProgressReminder "progress_reminder" ["tool"] =>
+        "CRITICAL: Before executing '{tool}', you MUST send a progress update using the 'progress' tool. \
+        This keeps the user informed that their request is being processed. \
+        Example: {{\"tool\": \"progress\", \"arguments\": {{\"message\": \"Processing your {tool} request...\"}}}}\n\n\
+        After completion, you MUST also send final results using the 'send' tool.",
+    NotesDeleted "notes_deleted" ["count"] => "🗑️ Deleted {count} notes",
+    NotesDeleteFailed "notes_delete_failed" ["error"] =>
+        "❌ Failed to delete notes, nothing was deleted: {error}",
+    NotePublished "note_published" ["naddr"] => "📰 Note published: nostr:{naddr}",
+    NotePublishFailed "note_publish_failed" ["error"] => "❌ Failed to publish note: {error}",
+    TaskSucceeded "task_succeeded" ["took", "output"] =>
+        "✅ Goose task completed successfully in {took}:\n\n{output}",
+    TaskFinished "task_finished" [] =>
+        "🔚 Task execution finished. Use 'killsessions' to cleanup and terminate.",
+    TaskFailed "task_failed" ["took", "code", "error"] =>
+        "❌ Goose task failed after {took} (exit code {code}):\n\n{error}",
+    SessionsBlockTask "sessions_block_task" [] =>
+        "⚠️ Active Goose sessions detected. Use 'killsessions' to terminate them before starting new tasks.",
+    SessionsActive "sessions_active" [] =>
+        "⚠️ Active Goose sessions detected - use killsessions to terminate",
+    NoActiveSessions "no_active_sessions" [] => "✅ No active Goose sessions",
+    SessionsKilled "sessions_killed" ["output"] => "🔚 All Goose sessions terminated:\n\n{output}",
+    KillFailed "kill_failed" ["code", "error"] =>
+        "❌ Failed to terminate sessions (exit code {code}):\n\n{error}",
+    SessionStarted "session_started" ["output", "session"] =>
+        "✅ Goose session started successfully:\n\n{output}\n\nSession id: {session}",
+    SessionStartFailed "session_start_failed" ["code", "error"] =>
+        "❌ Failed to start Goose session (exit code {code}):\n\n{error}",
+    NoSessionsFound "no_sessions_found" [] => "📋 No Goose sessions found.",
+    SessionsListed "sessions_listed" ["output"] => "📋 Goose sessions:\n\n{output}",
+    ListSessionsFailed "list_sessions_failed" ["code", "error"] =>
+        "❌ Failed to list sessions (exit code {code}):\n\n{error}",
+    SessionExported "session_exported" ["output"] =>
+        "✅ Session exported successfully:\n\n{output}",
+    ExportFailed "export_failed" ["code", "error"] =>
+        "❌ Failed to export session (exit code {code}):\n\n{error}",
+    GooseInfo "goose_info" ["output"] => "ℹ️ Goose system information:\n\n{output}",
+    InfoFailed "info_failed" ["code", "error"] =>
+        "❌ Failed to get Goose info (exit code {code}):\n\n{error}",
+    GooseVersion "goose_version" ["output"] => "🔢 Goose version:\n\n{output}",
+    VersionFailed "version_failed" ["code", "error"] =>
+        "❌ Failed to get Goose version (exit code {code}):\n\n{error}",
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// Splits `template` into literals and placeholders, allowing only those of `key`
+fn parse(key: Key, template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{{") {
+            literal.push('{');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("}}") {
+            literal.push('}');
+            rest = after;
+        } else if c == '{' {
+            let end = rest.find('}').ok_or_else(|| {
+                format!(
+                    "{}: unclosed '{{'; write '{{{{' for a literal brace",
+                    key.name()
+                )
+            })?;
+            let name = &rest[1..end];
+            if !key.placeholders().contains(&name) {
+                return Err(match key.placeholders() {
+                    [] => format!(
+                        "{}: unknown placeholder {{{}}}; it takes none",
+                        key.name(),
+                        name
+                    ),
+                    known => format!(
+                        "{}: unknown placeholder {{{}}}; use {}",
+                        key.name(),
+                        name,
+                        known
+                            .iter()
+                            .map(|p| format!("{{{}}}", p))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                });
+            }
+            parts.push(Part::Literal(std::mem::take(&mut literal)));
+            parts.push(Part::Placeholder(name.to_string()));
+            rest = &rest[end + 1..];
+        } else if c == '}' {
+            return Err(format!(
+                "{}: unmatched '}}'; write '}}}}' for a literal brace",
+                key.name()
+            ));
+        } else {
+            literal.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    parts.push(Part::Literal(literal));
+    parts.retain(|part| part != &Part::Literal(String::new()));
+    Ok(parts)
+}
+
+/// The templates in use: the defaults, with whatever a messages file replaced
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    overrides: HashMap<Key, Vec<Part>>,
+}
+
+impl Catalog {
+    /// Parses a messages file: top-level `key = "template"` lines
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut overrides = HashMap::new();
+        for (name, template) in crate::config::parse_top_level(text)? {
+            let key = Key::ALL
+                .iter()
+                .copied()
+                .find(|key| key.name() == name)
+                .ok_or_else(|| format!("Unknown message key '{}'", name))?;
+            overrides.insert(key, parse(key, &template)?);
+        }
+        Ok(Self { overrides })
+    }
+
+    /// The text of `key` with `values` filled in
+    pub fn text(&self, key: Key, values: &[(&str, &str)]) -> String {
+        let default;
+        let parts = match self.overrides.get(&key) {
+            Some(parts) => parts,
+            None => {
+                default = parse(key, key.default_template()).unwrap_or_default();
+                &default
+            }
+        };
+        parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.as_str(),
+                Part::Placeholder(name) => values
+                    .iter()
+                    .find(|(placeholder, _)| placeholder == name)
+                    .map(|(_, value)| *value)
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Replaces the catalog every text is taken from
+pub fn set(catalog: Catalog) {
+    *CATALOG.write().unwrap_or_else(|e| e.into_inner()) = catalog;
+}
+
+/// The text of `key` in the current catalog, with `values` filled in
+pub fn text(key: Key, values: &[(&str, &str)]) -> String {
+    CATALOG
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .text(key, values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_are_valid_templates() {
+        for key in Key::ALL {
+            assert!(parse(*key, key.default_template()).is_ok(), "{:?}", key);
+        }
+        let catalog = Catalog::default();
+        assert_eq!(catalog.text(Key::MessageSent, &[]), "Message sent!");
+        assert!(catalog
+            .text(Key::ProgressReminder, &[("tool", "runtask")])
+            .contains("{\"tool\": \"progress\", \"arguments\": {\"message\": \"Processing your runtask request...\"}}"));
+    }
+
+    #[test]
+    fn test_keys_the_file_leaves_out_keep_their_default() {
+        let catalog = Catalog::parse(
+            "task_completed = \"Aufgabe erledigt\"\n\
+             agent_completed = \"🤖 {name} ist fertig ({usage})\"\n",
+        )
+        .unwrap();
+        assert_eq!(catalog.text(Key::TaskCompleted, &[]), "Aufgabe erledigt");
+        assert_eq!(
+            catalog.text(
+                Key::AgentCompleted,
+                &[("name", "Ada"), ("usage", "0s · 1 message")]
+            ),
+            "🤖 Ada ist fertig (0s · 1 message)"
+        );
+        assert_eq!(catalog.text(Key::MessageSent, &[]), "Message sent!");
+        assert_eq!(
+            catalog.text(Key::NotesDeleted, &[("count", "3")]),
+            "🗑️ Deleted 3 notes"
+        );
+        assert_eq!(
+            Catalog::parse("").unwrap().text(Key::TaskCompleted, &[]),
+            "Task completed"
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys_and_placeholders() {
+        let unknown = Catalog::parse("task_done = \"Done\"").unwrap_err();
+        assert!(unknown.contains("task_done"));
+        let placeholder = Catalog::parse("notes_deleted = \"Deleted {n} notes\"").unwrap_err();
+        assert!(placeholder.contains("{n}") && placeholder.contains("{count}"));
+        assert!(Catalog::parse("message_sent = \"Sent {count}\"")
+            .unwrap_err()
+            .contains("takes none"));
+        assert!(Catalog::parse("message_sent = \"Sent {\"").is_err());
+        assert!(Catalog::parse("message_sent = \"Sent }\"").is_err());
+
+        let braces = Catalog::parse("message_sent = \"{{sent}}\"").unwrap();
+        assert_eq!(braces.text(Key::MessageSent, &[]), "{sent}");
+    }
+}
@@ -1,46 +1,151 @@
+use crate::audit::{self, AuditLogRequest};
+use crate::catalog::{self, Key};
+use crate::digest::{self, SetDigestIntervalRequest};
+use crate::dry_run;
+use crate::error::NparrotError;
+use crate::goose_mcp::guard::{self, SessionGuard};
 use crate::goose_mcp::{commands::GooseCommands, types::*};
-use crate::mcp::chat::{Chat, ProgressMessageRequest, SendMessageRequest};
+use crate::interrupt::{self, Interrupt};
+use crate::mcp::chat::{
+    CancelScheduledRequest, Chat, ProgressMessageRequest, RequestZapRequest, SendBatchRequest,
+    SendLaterRequest, SendMessageRequest, SummarizeConversationRequest, UploadFileRequest,
+    WaitRequest,
+};
+use crate::mcp::context::{
+    ClearContextRequest, ContextStore, GetContextRequest, SetContextRequest,
+};
+use crate::mcp::instructions::{
+    self,
+    InstructionProfile::{Compact, Full, Minimal},
+    Rule,
+};
+use crate::mcp::notebook::Notebook;
+use crate::mcp::prompts;
+use crate::mcp::server_common::{reply, reply_and_result, with_progress, ServerInfoBuilder};
+use crate::mcp::types::{
+    AddEventRequest, AddNoteRequest, DeleteEventRequest, DeleteNoteRequest, ListEventsRequest,
+    ListNotesRequest, SearchEventsRequest, SearchNotesRequest,
+};
+use crate::mcp::watchdog;
+use crate::process_management::stats::{format_history, format_stats, ProcessStatsRequest};
+use crate::process_management::{format_process_list, ProcessManager};
+use crate::progress_channels::{self, ProgressChannels};
+use crate::progress_history::ProgressHistoryRequest;
+use crate::relays::RelayInfoRequest;
+use crate::research::{self, ResearchAndBuildRequest};
+use crate::response_tracker::DeliveryStatusRequest;
 use crate::searxng_mcp::{SearXNGServer, SearXNGWebSearchRequest};
+use crate::selftest::SelftestRequest;
+use crate::wallet::{self, PayRequest, Wallet};
 use nostr_sdk::prelude::*;
 use rmcp::{
+    handler::server::tool::ToolCallContext,
     model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+        CallToolRequestParam, CallToolResult, Content, GetPromptRequestParam, GetPromptResult,
+        ListPromptsResult, ListToolsResult, PaginatedRequestParam, ServerInfo, Tool,
     },
-    tool, Error as RmcpError, ServerHandler,
+    service::RequestContext,
+    tool, Error as RmcpError, RoleServer, ServerHandler,
 };
+use std::sync::Arc;
+
+/// What `get_info` tells the model, by instruction profile
+pub const INSTRUCTIONS: &[Rule] = &[
+    Rule::new(
+        Minimal,
+        "This combined server provides Nostr chat with the user plus Goose command execution and web search.",
+    ),
+    Rule::new(
+        Minimal,
+        "Every user message is handled as: wait -> progress -> [work] -> send. The user only sees 'progress' and 'send' messages, so never end a turn without 'send'.",
+    ),
+    Rule::new(
+        Full,
+        "The 'handle_user_message', 'run_dev_task' and 'daily_summary' prompts spell out these workflows step by step with the exact tool calls.",
+    ),
+    Rule::about(
+        Full,
+        &["runtask", "startsession", "checksessions", "killsessions"],
+        "Goose: call 'checksessions' before 'runtask' or 'startsession', never run the same task twice, and call 'killsessions' when done. \"🔚 EXECUTION COMPLETED\" in the output marks a finished run. 'research_and_build' searches the web and runs a task with the results in one call.",
+    ),
+    Rule::about(
+        Full,
+        &["addnote", "addevent"],
+        "Notes and events: addnote/listnotes/searchnotes/deletenote and addevent/listevents/searchevents/deleteevent keep them in the data dir, shared with the enhanced server.",
+    ),
+    Rule::new(
+        Compact,
+        "Tool arguments must be a single JSON object with double quotes and nothing after the closing brace, e.g. {\"instructions\": \"analyze the code\"}.",
+    ),
+    Rule::new(
+        Compact,
+        "A failed tool call also returns JSON with 'code' and 'retryable': only retry when 'retryable' is true.",
+    ),
+    Rule::about(
+        Full,
+        wallet::TOOLS,
+        "Wallet: 'pay_invoice' pays small lightning invoices within a fixed per-payment and daily budget, and the user is told about each payment first. Only pay when the user's request calls for it; 'get_balance' shows what is left.",
+    ),
+];
 
 #[derive(Debug, Clone)]
 pub struct CombinedServer {
     chat: Chat,
     searxng: SearXNGServer,
+    /// Notes and events, in the same files as the enhanced server's
+    notebook: Notebook,
+    /// `pay_invoice` and `get_balance` are only listed and callable with a wallet
+    wallet: Option<Arc<Wallet>>,
 }
 
 #[tool(tool_box)]
 impl CombinedServer {
     pub fn new(
         client: Client,
-        progress_client: Option<Client>,
+        progress_clients: ProgressChannels<Client>,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
         searxng_url: String,
+        data_dir: Option<String>,
     ) -> Self {
+        let data_dir = data_dir.unwrap_or_else(|| "data".to_string());
         Self {
             chat: Chat::new(
                 client.clone(),
-                progress_client.clone(),
+                progress_clients.clone(),
                 our_pubkey,
                 target_pubkey,
-            ),
+            )
+            .with_context(Arc::new(ContextStore::in_memory()))
+            .with_watchdog(watchdog::stall_after()),
             searxng: SearXNGServer::new(
                 searxng_url,
                 client,
-                progress_client,
+                progress_clients,
                 our_pubkey,
                 target_pubkey,
             ),
+            notebook: Notebook::open(&data_dir),
+            wallet: None,
         }
     }
 
+    pub fn with_wallet(mut self, wallet: Option<Arc<Wallet>>) -> Self {
+        self.wallet = wallet;
+        self
+    }
+
+    /// Keeps the context tools' values in `context` instead of in memory only
+    pub fn with_context(mut self, context: Arc<ContextStore>) -> Self {
+        self.chat = self.chat.with_context(context);
+        self
+    }
+
+    /// The chat the schedule delivers through
+    pub fn chat(&self) -> &Chat {
+        &self.chat
+    }
+
     #[tool(description = "Send a message to the user via Nostr DM")]
     async fn send(
         &self,
@@ -49,6 +154,129 @@ impl CombinedServer {
         self.chat.send(request).await
     }
 
+    #[tool(
+        description = "Send several messages to the user in one call, e.g. a summary followed by code blocks; they arrive in the given order"
+    )]
+    async fn send_batch(
+        &self,
+        #[tool(aggr)] request: SendBatchRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.send_batch(request).await
+    }
+
+    #[tool(
+        description = "Send the user a file (e.g. a diagram or a log) that doesn't fit in a message: uploads it to the media server and sends its link, with an optional message"
+    )]
+    async fn upload_and_send_file(
+        &self,
+        #[tool(aggr)] request: UploadFileRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.upload_and_send_file(request).await
+    }
+
+    #[tool(
+        description = "Ask the user for a payment: gets a lightning invoice for the amount from the configured lightning address and sends it to the user"
+    )]
+    async fn request_zap(
+        &self,
+        #[tool(aggr)] request: RequestZapRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.request_zap(request).await
+    }
+
+    #[tool(
+        description = "Pay a lightning invoice from the connected wallet. Only small payments within the per-payment and daily budget go through, and the user is told about each one before it is made"
+    )]
+    async fn pay_invoice(
+        &self,
+        #[tool(aggr)] PayRequest {
+            invoice,
+            amount_sats,
+        }: PayRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let wallet = self.wallet()?;
+        let payment = wallet.authorize(&invoice, amount_sats)?;
+
+        // The user hears about every payment before the wallet is asked to make it
+        let sats = payment.amount_msats as f64 / 1000.0;
+        let report = self
+            .chat
+            .progress(ProgressMessageRequest {
+                message: format!(
+                    "💸 Paying {} sats ({}…) from the wallet",
+                    sats,
+                    payment.invoice.chars().take(24).collect::<String>()
+                ),
+                expire_after_secs: None,
+                channel: Some(progress_channels::STATUS.to_string()),
+            })
+            .await;
+        if !matches!(&report, Ok(result) if result.is_error != Some(true)) {
+            wallet.cancel(&payment);
+            return Err(NparrotError::relay_unavailable(
+                "Could not report the payment to the user, so it was not made",
+            )
+            .into());
+        }
+
+        let preimage = wallet.pay(&payment).await?;
+        Ok(CallToolResult::success(vec![
+            Content::text(format!("Paid {} sats", sats)),
+            Content::json(serde_json::json!({
+                "amount_msats": payment.amount_msats,
+                "preimage": preimage,
+                "spent_today_msats": wallet.spent_today_msats(),
+            }))?,
+        ]))
+    }
+
+    #[tool(
+        description = "Show the wallet balance and how much of the daily payment budget is used"
+    )]
+    async fn get_balance(&self) -> Result<CallToolResult, RmcpError> {
+        let wallet = self.wallet()?;
+        let balance_msats = wallet.balance_msats().await?;
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({
+                "balance_msats": balance_msats,
+                "spent_today_msats": wallet.spent_today_msats(),
+            }),
+        )?]))
+    }
+
+    /// The tools this server offers; the wallet tools only with a wallet
+    pub fn tools(&self) -> Vec<Tool> {
+        Self::tool_box()
+            .list()
+            .into_iter()
+            .filter(|tool| self.wallet.is_some() || !wallet::TOOLS.contains(&tool.name.as_ref()))
+            .filter(|tool| audit::tool_enabled() || tool.name != audit::TOOL)
+            .collect()
+    }
+
+    /// The `get_info` instructions in `profile`, without rules about tools that aren't listed
+    fn instructions(&self, profile: instructions::InstructionProfile) -> String {
+        let tools = self.tools();
+        let listed: Vec<&str> = tools.iter().map(|tool| tool.name.as_ref()).collect();
+        instructions::render(INSTRUCTIONS, profile, &listed)
+    }
+
+    #[tool(
+        description = "Show the latest tool calls with the shape and a keyed hash of their arguments, duration and outcome, for debugging"
+    )]
+    async fn get_audit_log(
+        &self,
+        #[tool(aggr)] AuditLogRequest { limit }: AuditLogRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        audit::tool_result(limit)
+    }
+
+    fn wallet(&self) -> Result<&Wallet, NparrotError> {
+        self.wallet
+            .as_deref()
+            .ok_or_else(|| NparrotError::backend_missing("nwc", "No wallet configured (NWC_URI)"))
+    }
+
     #[tool(description = "Send a progress/debug message to the user via the progress identity")]
     async fn progress(
         &self,
@@ -57,10 +285,193 @@ impl CombinedServer {
         self.chat.progress(request).await
     }
 
-    #[tool(description = "Listen and wait for the user's next message")]
-    async fn wait(&self) -> Result<CallToolResult, RmcpError> {
+    #[tool(
+        description = "Listen and wait for the user's next message, or for the next one of the sender given in `from`"
+    )]
+    async fn wait(&self, #[tool(aggr)] request: WaitRequest) -> Result<CallToolResult, RmcpError> {
         // The Chat wait method already includes response reminders
-        self.chat.wait().await
+        self.chat.wait(request).await
+    }
+
+    #[tool(
+        description = "Park a small piece of session state, e.g. current_repo=nmcpparrot, optionally expiring after ttl_secs; listed in every wait result. Not the Nostr-backed memory: nothing is published and values are limited to 2 KB"
+    )]
+    async fn set_context(
+        &self,
+        #[tool(aggr)] request: SetContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.set_context(request).await
+    }
+
+    #[tool(
+        description = "Read the session state stored with set_context (one key or all); separate from the Nostr-backed memory"
+    )]
+    async fn get_context(
+        &self,
+        #[tool(aggr)] request: GetContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.get_context(request).await
+    }
+
+    #[tool(
+        description = "Remove one key, or all of them, from the session state stored with set_context; does not touch the Nostr-backed memory"
+    )]
+    async fn clear_context(
+        &self,
+        #[tool(aggr)] request: ClearContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.clear_context(request).await
+    }
+
+    #[tool(
+        description = "Schedule a message to the user for later, e.g. a reminder; it is sent even if you are busy or the server restarts in between"
+    )]
+    async fn send_later(
+        &self,
+        #[tool(aggr)] request: SendLaterRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.send_later(request).await
+    }
+
+    #[tool(description = "List the messages scheduled with send_later that are not sent yet")]
+    async fn list_scheduled(&self) -> Result<CallToolResult, RmcpError> {
+        self.chat.list_scheduled().await
+    }
+
+    #[tool(description = "Cancel a message scheduled with send_later before it is sent")]
+    async fn cancel_scheduled(
+        &self,
+        #[tool(aggr)] request: CancelScheduledRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.cancel_scheduled(request).await
+    }
+
+    #[tool(
+        description = "Fetch the recent conversation with the user as a transcript (← user, → you), to summarize when earlier decisions have scrolled out of context"
+    )]
+    async fn summarize_conversation(
+        &self,
+        #[tool(aggr)] request: SummarizeConversationRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.summarize_conversation(request).await
+    }
+
+    #[tool(
+        description = "Show how many sent messages relays have acknowledged, which are still pending and which failed"
+    )]
+    async fn delivery_status(
+        &self,
+        #[tool(aggr)] request: DeliveryStatusRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.delivery_status(request).await
+    }
+
+    #[tool(
+        description = "Search the progress messages sent earlier, e.g. what was being done an hour ago: filter by since (\"2h\" or an ISO 8601 time) and contains, newest `limit` entries oldest first"
+    )]
+    async fn progress_history(
+        &self,
+        #[tool(aggr)] request: ProgressHistoryRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.progress_history(request).await
+    }
+
+    #[tool(
+        description = "Send a status digest (agents, tasks completed, failures, pending and queued messages) on the progress channel every interval, e.g. \"1h\", skipped when nothing changed; \"off\" stops it"
+    )]
+    async fn set_digest_interval(
+        &self,
+        #[tool(aggr)] request: SetDigestIntervalRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        digest::tool_result(request)
+    }
+
+    #[tool(
+        description = "Show what each configured relay published about itself (NIP-11 size and subscription limits, auth and payment requirements) whether NIP-42 authentication with it succeeded and how many subscriptions are open or queued on it"
+    )]
+    async fn relay_info(
+        &self,
+        #[tool(aggr)] request: RelayInfoRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.relay_info(request).await
+    }
+
+    #[tool(
+        description = "Check the DM path end to end: send a tagged message to ourselves through the relays and report how each stage went. The user never sees it"
+    )]
+    async fn selftest(
+        &self,
+        #[tool(aggr)] request: SelftestRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.selftest(request).await
+    }
+
+    #[tool(description = "Add a new note with content, optional tags, and metadata")]
+    async fn addnote(
+        &self,
+        #[tool(aggr)] request: AddNoteRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.addnote(&self.chat, request).await
+    }
+
+    #[tool(description = "List notes with optional filtering by tag, limit, and sort order")]
+    async fn listnotes(
+        &self,
+        #[tool(aggr)] request: ListNotesRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.listnotes(&self.chat, request).await
+    }
+
+    #[tool(description = "Search notes by content with optional tag filtering and result limit")]
+    async fn searchnotes(
+        &self,
+        #[tool(aggr)] request: SearchNotesRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.searchnotes(&self.chat, request).await
+    }
+
+    #[tool(description = "Delete a note by its ID")]
+    async fn deletenote(
+        &self,
+        #[tool(aggr)] request: DeleteNoteRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.deletenote(&self.chat, request).await
+    }
+
+    #[tool(
+        description = "Add a new event with title, description, type, optional times, tags, and metadata"
+    )]
+    async fn addevent(
+        &self,
+        #[tool(aggr)] request: AddEventRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.addevent(&self.chat, request).await
+    }
+
+    #[tool(description = "List events with optional filtering by type, tag, limit, and sort order")]
+    async fn listevents(
+        &self,
+        #[tool(aggr)] request: ListEventsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.listevents(&self.chat, request).await
+    }
+
+    #[tool(
+        description = "Search events by title and description with optional type and tag filtering"
+    )]
+    async fn searchevents(
+        &self,
+        #[tool(aggr)] request: SearchEventsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.searchevents(&self.chat, request).await
+    }
+
+    #[tool(description = "Delete an event by its ID")]
+    async fn deleteevent(
+        &self,
+        #[tool(aggr)] request: DeleteEventRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.deleteevent(&self.chat, request).await
     }
 
     #[tool(
@@ -71,58 +482,85 @@ impl CombinedServer {
         #[tool(aggr)] request: RunTaskRequest,
     ) -> Result<CallToolResult, RmcpError> {
         // Check for active sessions first
-        if GooseCommands::has_active_sessions() {
-            let warning_message = "⚠️ Active Goose sessions detected. Use 'killsessions' to terminate them before starting new tasks.".to_string();
-            let _ = self
-                .chat
-                .send(SendMessageRequest {
-                    message: warning_message,
-                })
-                .await;
-            return Ok(CallToolResult::error(vec![Content::text(
-                "Active sessions must be terminated first".to_string(),
-            )]));
+        if let Err((warning, refusal)) = SessionGuard::global().preflight() {
+            reply(&self.chat, warning).await;
+            return Ok(refusal);
         }
 
         // Send progress update
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Starting Goose task execution...".to_string(),
-            })
-            .await;
-
-        let result = GooseCommands::run_task(request).await;
+        let result = with_progress(
+            &self.chat,
+            "Starting Goose task execution...",
+            GooseCommands::run_task(request),
+        )
+        .await;
+        if result.is_interrupted() {
+            // Reported here, so the next tool call runs normally
+            Interrupt::global().take();
+            return Ok(interrupt::interrupted_result());
+        }
 
         // Send result to user via chat
-        let message = if result.success {
-            let has_completion_marker = result.output.contains("🔚 EXECUTION COMPLETED");
-            let base_message =
-                format!("✅ Goose task completed successfully:\n\n{}", result.output);
+        reply(&self.chat, guard::task_report(&result)).await;
 
-            if has_completion_marker {
-                format!("{}\n\n🔚 Task execution finished. Use 'killsessions' to cleanup and terminate.", base_message)
-            } else {
-                base_message
+        Self::convert_goose_result(result)
+    }
+
+    #[tool(
+        description = "Search the web, then run a Goose task with the top results written into its instructions at {{search_results}} (appended if missing). If the search fails or finds nothing, no task runs: ask the user, then call again with proceed_without_results set."
+    )]
+    async fn research_and_build(
+        &self,
+        #[tool(aggr)] request: ResearchAndBuildRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let top_n = request.top_n();
+        let search = with_progress(
+            &self.chat,
+            format!("🔍 Research phase: searching for {}", request.query),
+            self.searxng.search(SearXNGWebSearchRequest {
+                query: request.query.clone(),
+                count: Some(top_n),
+                offset: None,
+            }),
+        )
+        .await;
+        let proceed = request.proceed_without_results.unwrap_or(false);
+        let results = match search {
+            Ok(response) if !response.results.is_empty() => {
+                let results: Vec<_> = response.results.into_iter().take(top_n as usize).collect();
+                research::format_results(&results)
+            }
+            Ok(_) if proceed => "(The search found nothing.)".to_string(),
+            Ok(_) => {
+                return Ok(CallToolResult::error(vec![Content::text(format!(
+                    "The search for '{}' found nothing, so no task was started. Ask the user whether to build without search results, and call research_and_build again with proceed_without_results: true if they agree.",
+                    request.query
+                ))]))
+            }
+            Err(e) if proceed => format!("(The search failed: {})", e),
+            Err(e) => {
+                return Ok(e.to_result(format!(
+                    "The search for '{}' failed ({}), so no task was started. Ask the user whether to build without search results, and call research_and_build again with proceed_without_results: true if they agree.",
+                    request.query, e
+                )))
             }
-        } else {
-            let error_msg = result
-                .error
-                .clone()
-                .unwrap_or_else(|| "Unknown error".to_string());
-            format!(
-                "❌ Goose task failed (exit code {}):\n\n{}",
-                result.exit_code, error_msg
-            )
         };
 
-        let _ = self.chat.send(SendMessageRequest { message }).await;
-
-        Self::convert_goose_result(result)
+        with_progress(
+            &self.chat,
+            "🛠️ Build phase: starting the Goose task with the search results",
+            self.runtask(RunTaskRequest {
+                instructions: research::inject(&request.instructions, &results),
+                instruction_file: None,
+                max_turns: request.max_turns,
+                debug: request.debug,
+            }),
+        )
+        .await
     }
 
     #[tool(
-        description = "Start a new Goose session or resume an existing one with specified configuration."
+        description = "Start a new Goose session or resume an existing one with specified configuration. Returns the session id, for resumesession and removesession."
     )]
     async fn startsession(
         &self,
@@ -133,80 +571,98 @@ impl CombinedServer {
             .clone()
             .unwrap_or_else(|| "new session".to_string());
 
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Starting Goose session: {}", session_name),
-            })
-            .await;
-
-        let result = GooseCommands::start_session(request).await;
+        let result = with_progress(
+            &self.chat,
+            format!("Starting Goose session: {}", session_name),
+            GooseCommands::start_session(request),
+        )
+        .await;
 
         // Send result to user via chat
         let message = if result.success {
-            format!(
-                "✅ Goose session started successfully:\n\n{}",
-                result.output
+            catalog::text(
+                Key::SessionStarted,
+                &[
+                    ("output", &result.output),
+                    ("session", result.session.as_deref().unwrap_or("unknown")),
+                ],
             )
         } else {
             let error_msg = result
                 .error
                 .clone()
                 .unwrap_or_else(|| "Unknown error".to_string());
-            format!(
-                "❌ Failed to start Goose session (exit code {}):\n\n{}",
-                result.exit_code, error_msg
+            catalog::text(
+                Key::SessionStartFailed,
+                &[
+                    ("code", &result.exit_code.to_string()),
+                    ("error", &error_msg),
+                ],
             )
         };
 
-        let _ = self.chat.send(SendMessageRequest { message }).await;
+        reply(&self.chat, message).await;
 
         Self::convert_goose_result(result)
     }
 
+    #[tool(
+        description = "Resume a saved Goose session by the id startsession returned or listsessions shows."
+    )]
+    async fn resumesession(
+        &self,
+        #[tool(aggr)] request: ResumeSessionRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.startsession(request.into()).await
+    }
+
     #[tool(description = "List all saved Goose sessions with optional filtering and formatting.")]
     async fn listsessions(
         &self,
         #[tool(aggr)] request: SessionListRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Retrieving Goose sessions...".to_string(),
-            })
-            .await;
-
-        let result = GooseCommands::list_sessions(request).await;
+        let result = with_progress(
+            &self.chat,
+            "Retrieving Goose sessions...",
+            GooseCommands::list_sessions(request),
+        )
+        .await;
 
         // Send result to user via chat
         let message = if result.success {
             if result.output.trim().is_empty() {
-                "📋 No Goose sessions found.".to_string()
+                catalog::text(Key::NoSessionsFound, &[])
             } else {
-                format!("📋 Goose sessions:\n\n{}", result.output)
+                catalog::text(Key::SessionsListed, &[("output", &result.output)])
             }
         } else {
             let error_msg = result
                 .error
                 .clone()
                 .unwrap_or_else(|| "Unknown error".to_string());
-            format!(
-                "❌ Failed to list sessions (exit code {}):\n\n{}",
-                result.exit_code, error_msg
+            catalog::text(
+                Key::ListSessionsFailed,
+                &[
+                    ("code", &result.exit_code.to_string()),
+                    ("error", &error_msg),
+                ],
             )
         };
 
-        let _ = self.chat.send(SendMessageRequest { message }).await;
+        reply(&self.chat, message).await;
 
         Self::convert_goose_result(result)
     }
 
-    #[tool(description = "Remove one or more Goose sessions by ID, name, or regex pattern.")]
+    #[tool(
+        description = "Remove one or more Goose sessions by ID, name, or regex pattern; dry_run only lists what would be removed."
+    )]
     async fn removesession(
         &self,
         #[tool(aggr)] request: SessionRemoveRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let result = GooseCommands::remove_session(request).await;
+        let dry_run = dry_run::requested(request.dry_run);
+        let result = GooseCommands::remove_session(request, dry_run).await;
         Self::convert_goose_result(result)
     }
 
@@ -220,30 +676,31 @@ impl CombinedServer {
             .clone()
             .unwrap_or_else(|| "session".to_string());
 
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Exporting Goose session: {}", session_name),
-            })
-            .await;
-
-        let result = GooseCommands::export_session(request).await;
+        let result = with_progress(
+            &self.chat,
+            format!("Exporting Goose session: {}", session_name),
+            GooseCommands::export_session(request),
+        )
+        .await;
 
         // Send result to user via chat
         let message = if result.success {
-            format!("✅ Session exported successfully:\n\n{}", result.output)
+            catalog::text(Key::SessionExported, &[("output", &result.output)])
         } else {
             let error_msg = result
                 .error
                 .clone()
                 .unwrap_or_else(|| "Unknown error".to_string());
-            format!(
-                "❌ Failed to export session (exit code {}):\n\n{}",
-                result.exit_code, error_msg
+            catalog::text(
+                Key::ExportFailed,
+                &[
+                    ("code", &result.exit_code.to_string()),
+                    ("error", &error_msg),
+                ],
             )
         };
 
-        let _ = self.chat.send(SendMessageRequest { message }).await;
+        reply(&self.chat, message).await;
 
         Self::convert_goose_result(result)
     }
@@ -274,30 +731,31 @@ impl CombinedServer {
         description = "Show Goose information including version, configuration, and system details."
     )]
     async fn info(&self, #[tool(aggr)] request: InfoRequest) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Retrieving Goose system information...".to_string(),
-            })
-            .await;
-
-        let result = GooseCommands::info(request).await;
+        let result = with_progress(
+            &self.chat,
+            "Retrieving Goose system information...",
+            GooseCommands::info(request),
+        )
+        .await;
 
         // Send result to user via chat
         let message = if result.success {
-            format!("ℹ️ Goose system information:\n\n{}", result.output)
+            catalog::text(Key::GooseInfo, &[("output", &result.output)])
         } else {
             let error_msg = result
                 .error
                 .clone()
                 .unwrap_or_else(|| "Unknown error".to_string());
-            format!(
-                "❌ Failed to get Goose info (exit code {}):\n\n{}",
-                result.exit_code, error_msg
+            catalog::text(
+                Key::InfoFailed,
+                &[
+                    ("code", &result.exit_code.to_string()),
+                    ("error", &error_msg),
+                ],
             )
         };
 
-        let _ = self.chat.send(SendMessageRequest { message }).await;
+        reply(&self.chat, message).await;
 
         Self::convert_goose_result(result)
     }
@@ -308,19 +766,22 @@ impl CombinedServer {
 
         // Send result to user via chat
         let message = if result.success {
-            format!("🔢 Goose version:\n\n{}", result.output)
+            catalog::text(Key::GooseVersion, &[("output", &result.output)])
         } else {
             let error_msg = result
                 .error
                 .clone()
                 .unwrap_or_else(|| "Unknown error".to_string());
-            format!(
-                "❌ Failed to get Goose version (exit code {}):\n\n{}",
-                result.exit_code, error_msg
+            catalog::text(
+                Key::VersionFailed,
+                &[
+                    ("code", &result.exit_code.to_string()),
+                    ("error", &error_msg),
+                ],
             )
         };
 
-        let _ = self.chat.send(SendMessageRequest { message }).await;
+        reply(&self.chat, message).await;
 
         Self::convert_goose_result(result)
     }
@@ -368,51 +829,22 @@ impl CombinedServer {
 
     #[tool(description = "Force terminate all active Goose sessions and cleanup execution state.")]
     async fn killsessions(&self) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Terminating all active Goose sessions...".to_string(),
-            })
-            .await;
-
-        let result = GooseCommands::kill_all_sessions().await;
+        let result = with_progress(
+            &self.chat,
+            "Terminating all active Goose sessions...",
+            GooseCommands::kill_all_sessions(),
+        )
+        .await;
 
         // Send result to user via chat
-        let message = if result.success {
-            format!("🔚 All Goose sessions terminated:\n\n{}", result.output)
-        } else {
-            let error_msg = result
-                .error
-                .clone()
-                .unwrap_or_else(|| "Unknown error".to_string());
-            format!(
-                "❌ Failed to terminate sessions (exit code {}):\n\n{}",
-                result.exit_code, error_msg
-            )
-        };
-
-        let _ = self.chat.send(SendMessageRequest { message }).await;
+        reply(&self.chat, guard::kill_report(&result)).await;
         Self::convert_goose_result(result)
     }
 
     #[tool(description = "Check if any Goose sessions are currently active.")]
     async fn checksessions(&self) -> Result<CallToolResult, RmcpError> {
-        let has_active = GooseCommands::has_active_sessions();
-        let message = if has_active {
-            "⚠️ Active Goose sessions detected - use killsessions to terminate".to_string()
-        } else {
-            "✅ No active Goose sessions".to_string()
-        };
-
-        let _ = self.chat.send(SendMessageRequest { message }).await;
-        Ok(CallToolResult::success(vec![Content::text(
-            if has_active {
-                "Active sessions detected"
-            } else {
-                "No active sessions"
-            }
-            .to_string(),
-        )]))
+        let (message, summary) = SessionGuard::global().status();
+        reply_and_result(&self.chat, message, summary).await
     }
 
     #[tool(description = "Execute web searches with pagination")]
@@ -423,30 +855,166 @@ impl CombinedServer {
         self.searxng.searxng_web_search(request).await
     }
 
+    #[tool(
+        description = "Show CPU and memory usage of spawned processes; pass a pid for its recent history."
+    )]
+    async fn process_stats(
+        &self,
+        #[tool(aggr)] request: ProcessStatsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let text = match request.pid {
+            Some(pid) => match ProcessManager::global().history(pid) {
+                Some((label, samples)) => format_history(pid, &label, &samples),
+                None => {
+                    return Ok(CallToolResult::error(vec![Content::text(format!(
+                        "No managed process with PID {}",
+                        pid
+                    ))]))
+                }
+            },
+            None => format_stats(&ProcessManager::global().list()),
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "List processes spawned by this server (pid, label, uptime, status).")]
+    async fn list_processes(&self) -> Result<CallToolResult, RmcpError> {
+        let processes = ProcessManager::global().list();
+        Ok(CallToolResult::success(vec![Content::text(
+            format_process_list(&processes),
+        )]))
+    }
+
     fn convert_goose_result(result: CommandResult) -> Result<CallToolResult, RmcpError> {
-        if result.success {
-            Ok(CallToolResult::success(vec![Content::text(result.output)]))
-        } else {
-            let error_msg = result.error.unwrap_or_else(|| "Unknown error".to_string());
-            let formatted_error = format!(
-                "Command failed (exit code {}): {}",
-                result.exit_code, error_msg
-            );
-            Ok(CallToolResult::error(vec![Content::text(formatted_error)]))
-        }
+        Ok(result.tool_result())
     }
 }
 
-#[tool(tool_box)]
 impl ServerHandler for CombinedServer {
+    // Listed by hand so the wallet tools don't exist at all without a wallet
+    async fn list_tools(
+        &self,
+        _request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, RmcpError> {
+        Ok(ListToolsResult {
+            next_cursor: None,
+            tools: self.tools(),
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, RmcpError> {
+        if self.wallet.is_none() && wallet::TOOLS.contains(&request.name.as_ref())
+            || !audit::tool_enabled() && request.name == audit::TOOL
+        {
+            return Err(RmcpError::invalid_params("tool not found", None));
+        }
+        self.chat.tool_called();
+        let audited = request.clone();
+        audit::record(
+            "combined",
+            &audited,
+            Self::tool_box().call(ToolCallContext::new(self, request, context)),
+        )
+        .await
+    }
+
     fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some("This combined server provides both Nostr chat capabilities and comprehensive Goose AI agent command execution.\n\n🚨 ABSOLUTELY MANDATORY FOR EVERY USER MESSAGE:\n\n1️⃣ **IMMEDIATE PROGRESS RESPONSE**: The INSTANT you receive a user message, you MUST send a progress update\n   Example: {\"tool\": \"progress\", \"arguments\": {\"message\": \"I'm starting your Goose operation...\"}}\n\n2️⃣ **SESSION CHECK**: Before ANY Goose operation, check for active sessions\n   Example: {\"tool\": \"checksessions\"}\n\n3️⃣ **EXECUTE OPERATION**: Use requested Goose tool (runtask, startsession, etc.)\n\n4️⃣ **SESSION CLEANUP**: After completion, terminate sessions\n   Example: {\"tool\": \"killsessions\"}\n\n5️⃣ **MANDATORY FINAL SEND**: You MUST ALWAYS end with a 'send' tool call - NO EXCEPTIONS\n   Example: {\"tool\": \"send\", \"arguments\": {\"message\": \"✅ Goose operation completed and cleaned up\"}}\n\n🔴 CRITICAL: EVERY conversation turn MUST follow this pattern:\n   wait → progress → checksessions → [goose operations] → killsessions → send\n\n🚨 **DUPLICATE PREVENTION & SESSION MANAGEMENT**:\n• NEVER execute same command multiple times for one request\n• ALWAYS check sessions before starting new operations\n• ALWAYS terminate sessions after completion\n• If \"already being executed\" error: STOP and inform user\n• Look for \"🔚 EXECUTION COMPLETED\" marker in outputs\n• Use 'killsessions' to force cleanup when needed\n\n📢 USER VISIBILITY RULES:\n• Users can ONLY see messages sent via 'send' and 'progress' tools\n• Users CANNOT see your thinking, reasoning, or stdout output\n• If you don't use 'send', the user sees NOTHING\n• If you don't use 'progress', users think you're not working\n• Goose operations automatically send results, but you MUST still send final confirmation\n\n❌ FORBIDDEN BEHAVIORS:\n• Never end a turn without 'send'\n• Never start Goose work without 'progress'\n• Never execute operations without checking sessions first\n• Never leave sessions active after completion\n• Never execute duplicate commands\n• Never assume the user knows what you're doing\n• Never skip final confirmation even if Goose auto-sends results\n\n🛡️ **SESSION MANAGEMENT TOOLS**:\n• 'checksessions' - Check for active sessions (use before operations)\n• 'killsessions' - Force terminate all sessions (use after completion)\n• 'runtask' - Execute with deduplication protection\n• 'startsession' - Start with session tracking\n\n🔧 CRITICAL JSON PARAMETER RULES:\n• Parameters MUST be a SINGLE, complete JSON object: {\"instructions\": \"text\"}\n• Use ONLY double quotes, never single quotes\n• ABSOLUTELY NO text, characters, or content after the closing brace }\n• NO comments, explanations, or additional text outside the JSON\n• Properly escape quotes and backslashes inside strings\n• Example of CORRECT format: {\"instructions\": \"analyze the code\"}\n• Example of WRONG format: {\"instructions\": \"analyze code\"}\\nExecuting now...\n• Example of WRONG format: {\"instructions\": \"analyze code\"} // starting analysis\n\n⚠️ TRAILING CHARACTERS ERROR: If you see \"trailing characters\" errors, you have text after the JSON.\n\n💀 PARAMETER PARSING FAILURES WILL BREAK THE ENTIRE SYSTEM\n💀 SESSION MANAGEMENT FAILURES WILL CAUSE DUPLICATE RESPONSES".to_string()),
+        ServerInfoBuilder::new()
+            .with_prompts()
+            .instructions(self.instructions(instructions::profile()))
+            .build()
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, RmcpError> {
+        Ok(prompts::list(prompts::COMBINED))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, RmcpError> {
+        prompts::get(prompts::COMBINED, request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wallet_tools_only_exist_with_a_wallet() {
+        let keys = Keys::generate();
+        let server = CombinedServer::new(
+            Client::new(keys.clone()),
+            ProgressChannels::default(),
+            keys.public_key(),
+            Keys::generate().public_key(),
+            "http://127.0.0.1:1".to_string(),
+            None,
+        );
+        let names: Vec<String> = server
+            .tools()
+            .into_iter()
+            .map(|tool| tool.name.to_string())
+            .collect();
+        assert!(names.iter().any(|name| name == "send"));
+        assert!(!names
+            .iter()
+            .any(|name| wallet::TOOLS.contains(&name.as_str())));
+        let info = server.get_info().instructions.unwrap_or_default();
+        assert!(!info.contains("pay_invoice"));
+    }
+
+    #[tokio::test]
+    async fn test_notes_and_events_tools() {
+        let keys = Keys::generate();
+        let dir = tempfile::tempdir().unwrap();
+        let server = CombinedServer::new(
+            Client::new(keys.clone()),
+            ProgressChannels::default(),
+            keys.public_key(),
+            Keys::generate().public_key(),
+            "http://127.0.0.1:1".to_string(),
+            Some(dir.path().to_string_lossy().to_string()),
+        );
+        let names: Vec<String> = server
+            .tools()
+            .into_iter()
+            .map(|tool| tool.name.to_string())
+            .collect();
+        for tool in [
+            "addnote",
+            "listnotes",
+            "searchnotes",
+            "deletenote",
+            "addevent",
+            "listevents",
+            "searchevents",
+            "deleteevent",
+        ] {
+            assert!(names.iter().any(|name| name == tool), "{} missing", tool);
         }
+
+        server
+            .notebook
+            .notes()
+            .add_note(AddNoteRequest {
+                content: "buy milk".to_string(),
+                tags: Some(vec!["todo".to_string()]),
+                metadata: None,
+            })
+            .await
+            .unwrap();
+        assert!(dir.path().join(crate::mcp::notebook::NOTES_FILE).exists());
     }
 }
@@ -0,0 +1,186 @@
+//! Placeholders in the `onmessage` shell command
+//!
+//! `{content}`, `{sender}`, `{event_id}` and `{created_at}` are replaced per message by a single
+//! shell word in single quotes, so nothing in a message can break out of it. Placeholders must
+//! therefore stand on their own: one inside quotes would put message text in a context where the
+//! shell still expands it, so such commands are rejected up front.
+
+use crate::utils::IncomingMessage;
+
+const PLACEHOLDERS: &[&str] = &["content", "sender", "event_id", "created_at"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandTemplate {
+    parts: Vec<Part>,
+}
+
+impl CommandTemplate {
+    pub fn parse(command: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut quote: Option<char> = None;
+        let mut escaped = false;
+        let mut rest = command;
+
+        while let Some(c) = rest.chars().next() {
+            if !escaped && c == '{' {
+                if let Some(name) = placeholder_at(rest) {
+                    if let Some(quote) = quote {
+                        return Err(format!(
+                            "Placeholder {{{}}} must not be inside {} quotes: it is quoted automatically",
+                            name,
+                            if quote == '\'' { "single" } else { "double" }
+                        ));
+                    }
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    parts.push(Part::Placeholder(name));
+                    rest = &rest[name.len() + 2..];
+                    continue;
+                }
+            }
+
+            match (quote, c) {
+                _ if escaped => escaped = false,
+                (Some('\''), '\'') => quote = None,
+                (Some('\''), _) => {}
+                (_, '\\') => escaped = true,
+                (None, '\'' | '"') => quote = Some(c),
+                (Some('"'), '"') => quote = None,
+                _ => {}
+            }
+            literal.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        parts.push(Part::Literal(literal));
+        parts.retain(|part| part != &Part::Literal(String::new()));
+
+        Ok(Self { parts })
+    }
+
+    /// Without placeholders this is the command exactly as written
+    pub fn render(&self, message: &IncomingMessage) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Placeholder(name) => shell_quote(&value(name, message)),
+            })
+            .collect()
+    }
+}
+
+fn placeholder_at(text: &str) -> Option<&'static str> {
+    PLACEHOLDERS.iter().copied().find(|name| {
+        text.strip_prefix('{')
+            .and_then(|rest| rest.strip_prefix(name))
+            .is_some_and(|rest| rest.starts_with('}'))
+    })
+}
+
+fn value(name: &str, message: &IncomingMessage) -> String {
+    match name {
+        "content" => message.content.clone(),
+        "sender" => message.sender.to_hex(),
+        "event_id" => message.event_id.to_hex(),
+        "created_at" => message.created_at.as_u64().to_string(),
+        _ => unreachable!("unknown placeholder {}", name),
+    }
+}
+
+/// Single-quotes `value` for `sh`; inside single quotes nothing is special except the quote itself
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::*;
+    use std::process::Command;
+
+    fn message(content: &str) -> IncomingMessage {
+        let sender = Keys::generate();
+        let rumor = EventBuilder::private_msg_rumor(Keys::generate().public_key(), content)
+            .build(sender.public_key());
+        IncomingMessage::from_rumor(rumor)
+    }
+
+    /// Runs the rendered command the way `onmessage` does and returns its argv after `sh -c`
+    fn argv(template: &str, message: &IncomingMessage) -> Vec<String> {
+        let command = format!(
+            "{} {}",
+            CommandTemplate::parse(template).unwrap().render(message),
+            r#"for arg in "$@"; do printf '%s\0' "$arg"; done"#,
+        );
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .arg("sh")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .split_terminator('\0')
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_hostile_content_stays_one_argument() {
+        for content in [
+            "it's \"quoted\"",
+            "`touch /tmp/nparrot-pwned`",
+            "$(touch /tmp/nparrot-pwned) ${HOME}",
+            "line one\nline two; rm -rf ~",
+            "'; echo injected; '",
+            "{sender} \\ {content}",
+            "",
+        ] {
+            let message = message(content);
+            // `set --` turns the rendered words back into positional parameters
+            let args = argv("set -- {content} {sender};", &message);
+            assert_eq!(args, [content.to_string(), message.sender.to_hex()]);
+        }
+        assert!(!std::path::Path::new("/tmp/nparrot-pwned").exists());
+    }
+
+    #[test]
+    fn test_all_placeholders() {
+        let message = message("hi");
+        let args = argv(
+            "set -- x{event_id} {created_at} {content}{sender};",
+            &message,
+        );
+        assert_eq!(
+            args,
+            [
+                format!("x{}", message.event_id.to_hex()),
+                message.created_at.as_u64().to_string(),
+                format!("hi{}", message.sender.to_hex()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plain_commands_are_untouched() {
+        for command in ["cat", "jq '{content: .}'", "echo {unknown} {content"] {
+            let template = CommandTemplate::parse(command).unwrap();
+            assert_eq!(template.render(&message("hi")), command);
+        }
+    }
+
+    #[test]
+    fn test_quoted_placeholders_are_rejected() {
+        assert!(CommandTemplate::parse("echo '{content}'").is_err());
+        assert!(CommandTemplate::parse(r#"echo "x {sender}""#).is_err());
+        assert!(CommandTemplate::parse(r#"echo "it's" {content}"#).is_ok());
+        assert!(CommandTemplate::parse(r"echo \'{content}").is_ok());
+    }
+}
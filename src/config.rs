@@ -0,0 +1,758 @@
+//! Configuration file support
+//!
+//! Settings are read from `~/.config/nparrot/config.toml` (or `--config <path>` /
+//! `NPARROT_CONFIG`) and installed as clap defaults, so the precedence is
+//! CLI > env > config file > built-in default and `--help` shows the effective values.
+//!
+//! Only the TOML subset nparrot needs is understood: `[section]` and `[section.sub]` headers,
+//! and `key = value` pairs where the value is a string, integer, boolean or array of those.
+//!
+//! ```toml
+//! data_dir = "data"
+//! progress_expire_after = "1d"
+//!
+//! [identity]
+//! nsec = "nsec1..."
+//! progress_nsec = "nsec1..."
+//! target_pubkey = "npub1..."
+//!
+//! [relays]
+//! urls = ["wss://relay.damus.io#primary", "wss://nos.lol#bulk"]
+//! pow = "wss://nos.lol=20"
+//! routes = "main=all,debug=bulk"
+//! fallback = ["wss://relay.primal.net"]
+//! min_connected = 1
+//!
+//! [searxng]
+//! url = "https://searx.stream"
+//!
+//! [goose]
+//! binary = "/usr/local/bin/goose"
+//!
+//! [profiles.main]
+//! display_name = "My Bot"
+//! about = "Answers my DMs"
+//! ```
+
+pub mod units;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Overrides for a published kind-0 profile (`[profiles.main]`, `[profiles.progress]`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProfileConfig {
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub about: Option<String>,
+    pub picture: Option<String>,
+    pub banner: Option<String>,
+    pub nip05: Option<String>,
+    pub lud16: Option<String>,
+}
+
+impl ProfileConfig {
+    /// Parses a standalone profile file: top-level `name = "..."`, `about = "..."`, etc.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tables = parse_tables(text)?;
+        Ok(tables.get("").map(Self::from_table).unwrap_or_default())
+    }
+
+    fn from_table(values: &HashMap<String, Value>) -> Self {
+        let get = |key: &str| values.get(key).map(|v| v.to_setting());
+        Self {
+            name: get("name"),
+            display_name: get("display_name"),
+            about: get("about"),
+            picture: get("picture"),
+            banner: get("banner"),
+            nip05: get("nip05"),
+            lud16: get("lud16"),
+        }
+    }
+
+    /// Fills any field not set here from `other`
+    pub fn or(self, other: ProfileConfig) -> Self {
+        Self {
+            name: self.name.or(other.name),
+            display_name: self.display_name.or(other.display_name),
+            about: self.about.or(other.about),
+            picture: self.picture.or(other.picture),
+            banner: self.banner.or(other.banner),
+            nip05: self.nip05.or(other.nip05),
+            lud16: self.lud16.or(other.lud16),
+        }
+    }
+}
+
+/// The top-level settings of a standalone file (e.g. `--messages`), as typed on the command line
+pub fn parse_top_level(text: &str) -> Result<HashMap<String, String>, String> {
+    let tables = parse_tables(text)?;
+    Ok(tables
+        .get("")
+        .map(|values| {
+            values
+                .iter()
+                .map(|(key, value)| (key.clone(), value.to_setting()))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Overrides for a retry policy (`[retry]` for every subsystem, `[retry.goose]` for one)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetryConfig {
+    pub max_attempts: Option<u32>,
+    pub base_delay: Option<Duration>,
+    pub multiplier: Option<f64>,
+    pub max_delay: Option<Duration>,
+    pub jitter_percent: Option<u32>,
+}
+
+impl RetryConfig {
+    fn from_table(section: &str, values: &HashMap<String, Value>) -> Result<Self, String> {
+        let get = |key: &str| values.get(key).map(|v| v.to_setting());
+        let invalid = |key: &str, e: String| format!("[{}] {}: {}", section, key, e);
+        let number = |key: &str| {
+            get(key)
+                .map(|v| v.parse::<u32>().map_err(|e| invalid(key, e.to_string())))
+                .transpose()
+        };
+        let delay = |key: &str| {
+            get(key)
+                .map(|v| units::parse_duration(&v).map_err(|e| invalid(key, e)))
+                .transpose()
+        };
+        let multiplier = get("multiplier")
+            .map(|v| match v.parse::<f64>() {
+                Ok(m) if m >= 1.0 => Ok(m),
+                _ => Err(invalid(
+                    "multiplier",
+                    format!("expected 1 or more, got '{}'", v),
+                )),
+            })
+            .transpose()?;
+        Ok(Self {
+            max_attempts: number("max_attempts")?,
+            base_delay: delay("base_delay")?,
+            multiplier,
+            max_delay: delay("max_delay")?,
+            jitter_percent: number("jitter_percent")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Where the config was loaded from, if a file was found
+    pub path: Option<PathBuf>,
+    /// CLI argument id -> value, applied as clap defaults
+    settings: Vec<(&'static str, String)>,
+    pub profiles: HashMap<String, ProfileConfig>,
+    /// `[retry]` under `""`, `[retry.<subsystem>]` under the subsystem's name
+    pub retry: HashMap<String, RetryConfig>,
+}
+
+/// Maps config keys to the CLI arguments they provide defaults for
+const SETTINGS: &[(&str, &str, &str)] = &[
+    ("identity", "nsec", "nsec"),
+    ("identity", "progress_nsec", "progress_nsec"),
+    ("identity", "progress_channels", "progress_channels"),
+    ("identity", "target_pubkey", "target_pubkey"),
+    ("identity", "group", "group"),
+    ("identity", "allow_senders", "allow_senders"),
+    ("zaps", "lud16", "lud16"),
+    ("relays", "urls", "relay"),
+    ("relays", "progress_urls", "progress_relay"),
+    ("relays", "pow", "pow"),
+    ("relays", "routes", "relay_routes"),
+    ("relays", "fallback", "relay_fallback"),
+    ("relays", "min_connected", "min_relays"),
+    ("relays", "max_message_size", "max_message_size"),
+    ("relays", "max_subscriptions", "max_subscriptions"),
+    ("searxng", "url", "searxng_url"),
+    ("searxng", "timeout", "searxng_timeout"),
+    ("goose", "binary", "goose_bin"),
+    ("goose", "timeout", "goose_timeout"),
+    ("media", "server", "media_server"),
+    ("media", "protocol", "media_protocol"),
+    ("media", "max_size", "max_upload_size"),
+    ("wallet", "nwc_uri", "nwc_uri"),
+    ("wallet", "max_payment", "wallet_max_payment"),
+    ("wallet", "daily_budget", "wallet_daily_budget"),
+    ("", "data_dir", "data_dir"),
+    ("", "progress_expire_after", "progress_expire_after"),
+    ("", "delivery_retention", "delivery_retention"),
+    ("", "response_policy", "response_policy"),
+    ("", "timezone", "tz"),
+    ("", "event_duration", "event_duration"),
+    ("", "message_template", "message_template"),
+    ("", "messages", "messages"),
+    ("", "bot_name", "bot_name"),
+    ("", "coalesce_ms", "coalesce_ms"),
+    ("", "stall_after", "stall_after"),
+    ("", "dedup_window", "dedup_window"),
+    ("", "progress_dedup_window", "progress_dedup_window"),
+    ("", "progress_history_size", "progress_history_size"),
+    ("", "replay_max_age", "replay_max_age"),
+    ("", "multi_instance", "multi_instance"),
+    ("relays", "resend_attempts", "resend_attempts"),
+    ("mcp", "transport", "transport"),
+    ("mcp", "listen", "listen"),
+    ("mcp", "health_file", "health_file"),
+    ("mcp", "bearer_token", "mcp_token"),
+    ("mcp", "instructions", "instructions"),
+    ("commands", "prefix", "command_prefix"),
+    ("commands", "map", "commands"),
+    ("metrics", "listen", "metrics_listen"),
+    ("log", "file", "log_file"),
+    ("log", "max_size", "log_max_size"),
+    ("log", "keep", "log_keep"),
+    ("log", "format", "log_format"),
+    ("log", "redact_prefixes", "redact_prefixes"),
+    ("processes", "sample_interval", "sample_interval"),
+    ("processes", "max_child_rss", "max_child_rss"),
+    ("processes", "env_allowlist", "env_allowlist"),
+    ("multi_agent", "orchestrator_memory", "orchestrator_memory"),
+    ("multi_agent", "agent_naming", "agent_naming"),
+    ("multi_agent", "capability_ttl", "capability_ttl"),
+    ("", "digest_interval", "digest_interval"),
+];
+
+/// CLI arguments whose values must never be printed
+pub const SECRET_ARGS: &[&str] = &[
+    "nsec",
+    "progress_nsec",
+    "progress_channels",
+    "mcp_token",
+    "nwc_uri",
+];
+
+impl Config {
+    /// Loads the config file named by `--config`/`NPARROT_CONFIG`, or the default location.
+    ///
+    /// A missing default file is not an error; a missing explicitly requested one is.
+    pub fn load<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let explicit = config_path_from_args(args)
+            .or_else(|| std::env::var("NPARROT_CONFIG").ok().map(PathBuf::from));
+
+        let path = match explicit {
+            Some(path) => path,
+            None => match default_config_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let mut config =
+            Self::parse(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let tables = parse_tables(text)?;
+        let mut config = Config::default();
+
+        for (section, key, arg) in SETTINGS {
+            if let Some(value) = tables.get(*section).and_then(|t| t.get(*key)) {
+                config.settings.push((arg, value.to_setting()));
+            }
+        }
+
+        for (section, values) in &tables {
+            let Some(name) = section.strip_prefix("profiles.") else {
+                continue;
+            };
+            config
+                .profiles
+                .insert(name.to_string(), ProfileConfig::from_table(values));
+        }
+
+        for (section, values) in &tables {
+            let name = match section.strip_prefix("retry") {
+                Some("") => "",
+                Some(rest) => match rest.strip_prefix('.') {
+                    Some(name) if crate::retry::SUBSYSTEMS.contains(&name) => name,
+                    Some(name) => {
+                        return Err(format!(
+                            "unknown retry subsystem '{}', expected one of {}",
+                            name,
+                            crate::retry::SUBSYSTEMS.join(", ")
+                        ))
+                    }
+                    None => continue,
+                },
+                None => continue,
+            };
+            config
+                .retry
+                .insert(name.to_string(), RetryConfig::from_table(section, values)?);
+        }
+
+        Ok(config)
+    }
+
+    /// Installs config values as argument defaults so clap resolves CLI > env > config
+    pub fn apply_defaults(&self, mut command: Command) -> Command {
+        for (arg, value) in &self.settings {
+            // Settings of a subsystem left out by a cargo feature have no argument
+            if command.get_arguments().all(|a| a.get_id() != *arg) {
+                log::debug!("Ignoring config setting '{}', not built in", arg);
+                continue;
+            }
+            // clap needs 'static defaults; this runs once at startup
+            let value: &'static str = Box::leak(value.clone().into_boxed_str());
+            let secret = SECRET_ARGS.contains(arg);
+            command = command.mut_arg(*arg, |a| {
+                a.default_value(value)
+                    .required(false)
+                    .hide_default_value(secret)
+            });
+        }
+        command
+    }
+
+    fn provides(&self, arg: &str) -> bool {
+        self.settings.iter().any(|(a, _)| *a == arg)
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&ProfileConfig> {
+        self.profiles.get(name)
+    }
+
+    /// Renders the merged effective configuration, with secrets redacted and each value's origin
+    pub fn render_effective(&self, matches: &ArgMatches) -> String {
+        let mut out = String::new();
+        out.push_str("# Effective configuration (CLI > env > config file > default)\n");
+        match &self.path {
+            Some(path) => out.push_str(&format!("# Config file: {}\n", path.display())),
+            None => out.push_str("# Config file: none\n"),
+        }
+
+        let mut current_section = None;
+        let mut ordered: Vec<_> = SETTINGS.iter().collect();
+        ordered.sort_by_key(|(section, _, _)| !section.is_empty());
+
+        for (section, key, arg) in ordered {
+            // Not built in, see apply_defaults
+            if matches.try_get_raw(arg).is_err() {
+                continue;
+            }
+            if current_section != Some(*section) {
+                if !section.is_empty() {
+                    out.push_str(&format!("\n[{}]\n", section));
+                }
+                current_section = Some(*section);
+            }
+
+            let source = match matches.value_source(arg) {
+                Some(ValueSource::CommandLine) => "cli",
+                Some(ValueSource::EnvVariable) => "env",
+                Some(ValueSource::DefaultValue) if self.provides(arg) => "config file",
+                Some(_) => "default",
+                None => "unset",
+            };
+            match matches.get_raw(arg).and_then(|mut v| v.next()) {
+                Some(_) if SECRET_ARGS.contains(arg) => {
+                    out.push_str(&format!("{} = \"<redacted>\"  # {}\n", key, source))
+                }
+                Some(v) => out.push_str(&format!(
+                    "{} = {:?}  # {}\n",
+                    key,
+                    v.to_string_lossy(),
+                    source
+                )),
+                None => out.push_str(&format!("# {} is not set\n", key)),
+            }
+        }
+
+        let mut names: Vec<_> = self.profiles.keys().collect();
+        names.sort();
+        for name in names {
+            let profile = &self.profiles[name];
+            out.push_str(&format!("\n[profiles.{}]\n", name));
+            for (key, value) in [
+                ("name", &profile.name),
+                ("display_name", &profile.display_name),
+                ("about", &profile.about),
+                ("picture", &profile.picture),
+                ("banner", &profile.banner),
+                ("nip05", &profile.nip05),
+                ("lud16", &profile.lud16),
+            ] {
+                if let Some(value) = value {
+                    out.push_str(&format!("{} = {:?}\n", key, value));
+                }
+            }
+        }
+
+        let mut names: Vec<_> = self.retry.keys().collect();
+        names.sort();
+        for name in names {
+            let retry = &self.retry[name];
+            match name.as_str() {
+                "" => out.push_str("\n[retry]\n"),
+                name => out.push_str(&format!("\n[retry.{}]\n", name)),
+            }
+            if let Some(max_attempts) = retry.max_attempts {
+                out.push_str(&format!("max_attempts = {}\n", max_attempts));
+            }
+            for (key, delay) in [
+                ("base_delay", retry.base_delay),
+                ("max_delay", retry.max_delay),
+            ] {
+                if let Some(delay) = delay {
+                    out.push_str(&format!("{} = \"{}ms\"\n", key, delay.as_millis()));
+                }
+            }
+            if let Some(multiplier) = retry.multiplier {
+                out.push_str(&format!("multiplier = \"{}\"\n", multiplier));
+            }
+            if let Some(jitter_percent) = retry.jitter_percent {
+                out.push_str(&format!("jitter_percent = {}\n", jitter_percent));
+            }
+        }
+
+        out
+    }
+}
+
+/// `~/.config/nparrot/config.toml` (honoring `XDG_CONFIG_HOME`)
+pub fn default_config_path() -> Option<PathBuf> {
+    let base = std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var("HOME")
+                .ok()
+                .map(|home| Path::new(&home).join(".config"))
+        })?;
+    Some(base.join("nparrot").join("config.toml"))
+}
+
+/// Finds `--config <path>` / `--config=<path>` before clap runs, since the file feeds clap's defaults
+fn config_path_from_args<I>(args: I) -> Option<PathBuf>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Renders the value the way it would be typed on the command line
+    fn to_setting(&self) -> String {
+        match self {
+            Value::String(s) => s.clone(),
+            Value::Integer(i) => i.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Array(items) => items
+                .iter()
+                .map(|v| v.to_setting())
+                .collect::<Vec<_>>()
+                .join(","),
+        }
+    }
+}
+
+type Tables = HashMap<String, HashMap<String, Value>>;
+
+fn parse_tables(text: &str) -> Result<Tables, String> {
+    let mut tables: Tables = HashMap::new();
+    let mut section = String::new();
+    let mut lines = text.lines().enumerate();
+
+    while let Some((index, raw)) = lines.next() {
+        let line_no = index + 1;
+        let mut line = strip_comment(raw).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            let name = line
+                .strip_prefix('[')
+                .and_then(|l| l.strip_suffix(']'))
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with('['))
+                .ok_or_else(|| format!("line {}: invalid section header", line_no))?;
+            section = name.to_string();
+            continue;
+        }
+
+        // Arrays may span several lines
+        while line.contains('[') && bracket_depth(&line) > 0 {
+            let (_, next) = lines
+                .next()
+                .ok_or_else(|| format!("line {}: unterminated array", line_no))?;
+            line.push(' ');
+            line.push_str(strip_comment(next).trim());
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key = value", line_no))?;
+        let key = key.trim().trim_matches('"').to_string();
+        if key.is_empty() {
+            return Err(format!("line {}: missing key", line_no));
+        }
+        let value = parse_value(value.trim()).map_err(|e| format!("line {}: {}", line_no, e))?;
+        tables
+            .entry(section.clone())
+            .or_default()
+            .insert(key, value);
+    }
+
+    Ok(tables)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match in_string {
+            Some(_) if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => in_string = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => in_string = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn bracket_depth(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut in_string: Option<char> = None;
+    for c in line.chars() {
+        match in_string {
+            Some(q) if c == q => in_string = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => in_string = Some(c),
+            None if c == '[' => depth += 1,
+            None if c == ']' => depth -= 1,
+            None => {}
+        }
+    }
+    depth
+}
+
+fn parse_value(input: &str) -> Result<Value, String> {
+    let (value, rest) = parse_value_prefix(input)?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected trailing characters '{}'", rest.trim()));
+    }
+    Ok(value)
+}
+
+fn parse_value_prefix(input: &str) -> Result<(Value, &str), String> {
+    let input = input.trim_start();
+
+    if let Some(rest) = input.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(out), &rest[i + 1..])),
+                '\\' => match chars.next().map(|(_, e)| e) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some(other) => return Err(format!("unsupported escape '\\{}'", other)),
+                    None => break,
+                },
+                _ => out.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+
+    if let Some(rest) = input.strip_prefix('\'') {
+        let end = rest
+            .find('\'')
+            .ok_or_else(|| "unterminated string".to_string())?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+
+    if let Some(mut rest) = input.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::Array(items), after));
+            }
+            let (item, after) = parse_value_prefix(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after;
+            } else if !rest.starts_with(']') {
+                return Err("expected ',' or ']' in array".to_string());
+            }
+        }
+    }
+
+    let end = input
+        .find(|c: char| c == ',' || c == ']' || c.is_whitespace())
+        .unwrap_or(input.len());
+    let (token, rest) = input.split_at(end);
+    match token {
+        "true" => Ok((Value::Bool(true), rest)),
+        "false" => Ok((Value::Bool(false), rest)),
+        _ => token
+            .replace('_', "")
+            .parse::<i64>()
+            .map(|i| (Value::Integer(i), rest))
+            .map_err(|_| format!("unsupported value '{}'", token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+data_dir = "/var/lib/nparrot" # trailing comment
+progress_expire_after = "12h"
+
+[identity]
+nsec = "nsec1secret"
+target_pubkey = 'npub1target'
+
+[relays]
+urls = [
+    "wss://relay.damus.io",  # primary
+    "wss://nos.lol#bulk",
+]
+routes = "debug=bulk"
+
+[goose]
+binary = "/opt/goose#1/goose"
+
+[profiles.main]
+display_name = "Parrot \"Bot\""
+"#;
+
+    fn setting<'a>(config: &'a Config, arg: &str) -> Option<&'a str> {
+        config
+            .settings
+            .iter()
+            .find(|(a, _)| *a == arg)
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_parse_sample_config() {
+        let config = Config::parse(SAMPLE).unwrap();
+
+        assert_eq!(setting(&config, "data_dir"), Some("/var/lib/nparrot"));
+        assert_eq!(setting(&config, "progress_expire_after"), Some("12h"));
+        assert_eq!(setting(&config, "nsec"), Some("nsec1secret"));
+        assert_eq!(setting(&config, "target_pubkey"), Some("npub1target"));
+        assert_eq!(
+            setting(&config, "relay"),
+            Some("wss://relay.damus.io,wss://nos.lol#bulk")
+        );
+        assert_eq!(setting(&config, "relay_routes"), Some("debug=bulk"));
+        assert_eq!(setting(&config, "goose_bin"), Some("/opt/goose#1/goose"));
+        assert_eq!(setting(&config, "searxng_url"), None);
+        assert_eq!(
+            config.profile("main").unwrap().display_name.as_deref(),
+            Some("Parrot \"Bot\"")
+        );
+        let config = Config::parse("[multi_agent]\norchestrator_memory = \"allow\"").unwrap();
+        assert_eq!(setting(&config, "orchestrator_memory"), Some("allow"));
+    }
+
+    #[test]
+    fn test_parse_retry_tables() {
+        let config = Config::parse(
+            "[retry]\njitter_percent = 10\n\n[retry.goose]\nmax_attempts = 5\nbase_delay = \"500ms\"\nmultiplier = \"1.5\"\nmax_delay = \"2m\"",
+        )
+        .unwrap();
+        assert_eq!(config.retry[""].jitter_percent, Some(10));
+        assert_eq!(
+            config.retry["goose"],
+            RetryConfig {
+                max_attempts: Some(5),
+                base_delay: Some(Duration::from_millis(500)),
+                multiplier: Some(1.5),
+                max_delay: Some(Duration::from_secs(120)),
+                jitter_percent: None,
+            }
+        );
+
+        assert!(Config::parse("[retry.gose]\nmax_attempts = 2").is_err());
+        assert!(Config::parse("[retry]\nmultiplier = \"0.5\"").is_err());
+        assert!(Config::parse("[retry]\nbase_delay = \"soon\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_lines() {
+        assert!(Config::parse("[identity\nnsec = \"x\"").is_err());
+        assert!(Config::parse("nsec \"x\"").is_err());
+        assert!(Config::parse("urls = [\"a\"").is_err());
+        assert!(Config::parse("nsec = \"unterminated").is_err());
+    }
+
+    #[test]
+    fn test_settings_of_missing_args_are_ignored() {
+        let config = Config::parse(SAMPLE).unwrap();
+        // A build without Goose has no --goose-bin to take the [goose] binary
+        let command = config.apply_defaults(
+            Command::new("nparrot").arg(clap::Arg::new("data_dir").long("data-dir")),
+        );
+        let matches = command.get_matches_from(["nparrot"]);
+        assert_eq!(
+            matches.get_one::<String>("data_dir").map(String::as_str),
+            Some("/var/lib/nparrot")
+        );
+
+        let rendered = config.render_effective(&matches);
+        assert!(rendered.contains("data_dir = \"/var/lib/nparrot\"  # config file"));
+        assert!(!rendered.contains("[goose]"));
+    }
+
+    #[test]
+    fn test_config_path_from_args() {
+        let args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            config_path_from_args(args(&["nparrot", "--config", "a.toml", "send"])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            config_path_from_args(args(&["nparrot", "--config=b.toml"])),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(config_path_from_args(args(&["nparrot", "send"])), None);
+    }
+}
@@ -0,0 +1,212 @@
+//! Durations and sizes as users write them, for every option, env variable and config key
+//!
+//! Durations are a whole number with one of `ms`, `s`, `m`, `h`, `d` or `w` (`90s`, `15m`, `2h`,
+//! `1d`); a bare number is seconds, which is what every timeout took before units existed. Sizes
+//! are a whole number with `B`, `K`, `M` or `G` (`4096B`, `256kb`, `4MiB`), case-insensitive and
+//! binary, so `1K` is 1024 bytes. A size must carry its unit: whether `512` meant bytes or
+//! kilobytes is a guess nobody should have to make for a limit. Only `0` needs no unit.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::time::Duration;
+
+/// Parses a duration such as `500ms`, `90`, `30s`, `15m`, `12h`, `1d` or `2w`
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration '{}': expected e.g. 30m, 12h, 1d", input))?;
+    let millis: u64 = match unit.trim() {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        "w" => 7 * 24 * 60 * 60 * 1000,
+        other => {
+            return Err(format!(
+                "Invalid duration unit '{}' in '{}': use ms, s, m, h, d or w",
+                other, input
+            ))
+        }
+    };
+
+    value
+        .checked_mul(millis)
+        .map(Duration::from_millis)
+        .ok_or_else(|| format!("Duration '{}' is too large", input))
+}
+
+/// Parses a duration of whole seconds, e.g. `90`, `30s`, `15m`, `12h`, `1d` or `2w`
+pub fn parse_duration_secs(input: &str) -> Result<u64, String> {
+    let duration = parse_duration(input)?;
+    if duration.subsec_millis() != 0 {
+        return Err(format!(
+            "Invalid duration '{}': this takes whole seconds",
+            input.trim()
+        ));
+    }
+    Ok(duration.as_secs())
+}
+
+/// Parses a size such as `4096B`, `64K`, `256kb`, `512M` or `2GiB` into bytes
+pub fn parse_size_bytes(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let value: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size '{}': expected e.g. 512M, 2G", input))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    let prefix = match unit.strip_suffix("IB") {
+        Some(prefix) if !prefix.is_empty() => prefix,
+        _ => unit.strip_suffix('B').unwrap_or(&unit),
+    };
+    let multiplier: u64 = match prefix {
+        "" if unit.is_empty() && value == 0 => 1,
+        "" if unit.is_empty() => {
+            return Err(format!(
+                "Size '{}' needs a unit: {}B for bytes, or K, M or G",
+                input, input
+            ))
+        }
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => {
+            return Err(format!(
+                "Invalid size unit '{}' in '{}': use B, K, M or G",
+                unit, input
+            ))
+        }
+    };
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size '{}' is too large", input))
+}
+
+/// When something given as `input` expires: a duration from `now` (`7d`) or an ISO 8601 time
+#[cfg_attr(not(feature = "memory"), allow(dead_code))]
+pub fn parse_expiry(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+    if let Ok(duration) = parse_duration(input) {
+        return TimeDelta::from_std(duration)
+            .ok()
+            .and_then(|delta| now.checked_add_signed(delta))
+            .ok_or_else(|| format!("Duration '{}' is too large", input));
+    }
+    DateTime::parse_from_rfc3339(input)
+        .map(|time| time.to_utc())
+        .map_err(|_| {
+            format!(
+                "Invalid expiry '{}': use a duration like \"7d\" or an ISO 8601 time",
+                input
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_durations() {
+        let cases = [
+            ("90", 90_000),
+            ("0", 0),
+            ("30s", 30_000),
+            (" 15m ", 900_000),
+            ("12h", 43_200_000),
+            ("1d", 86_400_000),
+            ("2w", 1_209_600_000),
+            ("500ms", 500),
+            ("1 h", 3_600_000),
+        ];
+        for (input, millis) in cases {
+            assert_eq!(
+                parse_duration(input),
+                Ok(Duration::from_millis(millis)),
+                "{}",
+                input
+            );
+        }
+        for input in ["", "h", "1y", "1.5h", "-5s", "1h30m", "12 hours", "1M"] {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+        assert!(parse_duration("1y")
+            .unwrap_err()
+            .contains("use ms, s, m, h, d or w"));
+        assert!(parse_duration(&format!("{}w", u64::MAX))
+            .unwrap_err()
+            .contains("too large"));
+    }
+
+    #[test]
+    fn test_duration_secs() {
+        assert_eq!(parse_duration_secs("90"), Ok(90));
+        assert_eq!(parse_duration_secs("15m"), Ok(900));
+        assert_eq!(parse_duration_secs("2000ms"), Ok(2));
+        assert!(parse_duration_secs("1500ms")
+            .unwrap_err()
+            .contains("whole seconds"));
+        assert!(parse_duration_secs("soon").is_err());
+    }
+
+    #[test]
+    fn test_sizes() {
+        let cases = [
+            ("4096B", 4096),
+            ("4096b", 4096),
+            ("0", 0),
+            ("64K", 64 << 10),
+            ("256kb", 256 << 10),
+            ("256KiB", 256 << 10),
+            ("4mb", 4 << 20),
+            ("512M", 512 << 20),
+            ("2GiB", 2 << 30),
+            (" 10 M ", 10 << 20),
+        ];
+        for (input, bytes) in cases {
+            assert_eq!(parse_size_bytes(input), Ok(bytes), "{}", input);
+        }
+        // The unit matters too much for a limit to guess it
+        assert!(parse_size_bytes("4096")
+            .unwrap_err()
+            .contains("needs a unit: 4096B for bytes"));
+        for input in ["", "M", "2T", "1.5M", "-1K", "10MBB", "KB"] {
+            assert!(parse_size_bytes(input).is_err(), "{}", input);
+        }
+        assert!(parse_size_bytes(&format!("{}G", u64::MAX))
+            .unwrap_err()
+            .contains("too large"));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let now = DateTime::parse_from_rfc3339("2025-07-01T09:00:00Z")
+            .unwrap()
+            .to_utc();
+        assert_eq!(
+            parse_expiry("7d", now).unwrap().to_rfc3339(),
+            "2025-07-08T09:00:00+00:00"
+        );
+        assert_eq!(
+            parse_expiry("2025-08-01T00:00:00+02:00", now)
+                .unwrap()
+                .to_rfc3339(),
+            "2025-07-31T22:00:00+00:00"
+        );
+        assert!(parse_expiry("next week", now)
+            .unwrap_err()
+            .contains("Invalid expiry"));
+    }
+}
@@ -0,0 +1,179 @@
+//! What kind of content an incoming message is, so agents don't read JSON or code as prose
+//!
+//! Every message gets a `content_kind`: `json` for a message that is a JSON object or array,
+//! `code` for one with a fenced code block, `nostr_uri` for one that mentions `nostr:` entities,
+//! `url_only` for a bare link, and `text` otherwise. The `nostr:` entities are decoded locally
+//! into one line each in `nostr_entities` (what it is, its hex ids, kind and relay hints), so the
+//! agent can look them up without first decoding the bech32 itself. Nothing is fetched and the
+//! content is never changed; checks go from cheapest to dearest and JSON is only parsed when the
+//! message starts like JSON.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// At most this many `nostr:` entities are described per message
+const MAX_ENTITIES: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    #[default]
+    Text,
+    Json,
+    Code,
+    NostrUri,
+    UrlOnly,
+}
+
+impl ContentKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentKind::Text => "text",
+            ContentKind::Json => "json",
+            ContentKind::Code => "code",
+            ContentKind::NostrUri => "nostr_uri",
+            ContentKind::UrlOnly => "url_only",
+        }
+    }
+}
+
+/// The kind of `content` and the `nostr:` entities it mentions
+pub fn detect(content: &str) -> (ContentKind, Vec<String>) {
+    let trimmed = content.trim();
+    let json_like = (trimmed.starts_with('{') && trimmed.ends_with('}'))
+        || (trimmed.starts_with('[') && trimmed.ends_with(']'));
+    if json_like && serde_json::from_str::<serde_json::Value>(trimmed).is_ok() {
+        return (ContentKind::Json, Vec::new());
+    }
+    if trimmed.contains("```") {
+        return (ContentKind::Code, Vec::new());
+    }
+    let entities = nostr_entities(trimmed);
+    if !entities.is_empty() {
+        return (ContentKind::NostrUri, entities);
+    }
+    if !trimmed.contains(char::is_whitespace)
+        && (trimmed.starts_with("https://") || trimmed.starts_with("http://"))
+    {
+        return (ContentKind::UrlOnly, Vec::new());
+    }
+    (ContentKind::Text, Vec::new())
+}
+
+/// One line for each valid `nostr:` URI in `content`
+fn nostr_entities(content: &str) -> Vec<String> {
+    if !content.contains("nostr:") {
+        return Vec::new();
+    }
+    content
+        .split(|c: char| c.is_whitespace() || "()<>[]\"',;".contains(c))
+        .filter_map(|word| {
+            let start = word.find("nostr:")?;
+            let uri = word[start..].trim_end_matches(['.', '!', '?', ':']);
+            Nip21::parse(uri).ok().map(|entity| describe(uri, &entity))
+        })
+        .take(MAX_ENTITIES)
+        .collect()
+}
+
+fn relay_hint(relays: &[RelayUrl]) -> String {
+    if relays.is_empty() {
+        String::new()
+    } else {
+        let relays: Vec<&str> = relays.iter().map(|relay| relay.as_str()).collect();
+        format!(", relays {}", relays.join(" "))
+    }
+}
+
+fn describe(uri: &str, entity: &Nip21) -> String {
+    let summary = match entity {
+        Nip21::Pubkey(pubkey) => format!("profile of pubkey {}", pubkey.to_hex()),
+        Nip21::Profile(profile) => format!(
+            "profile of pubkey {}{}",
+            profile.public_key.to_hex(),
+            relay_hint(&profile.relays)
+        ),
+        Nip21::EventId(id) => format!("event {}", id.to_hex()),
+        Nip21::Event(event) => format!(
+            "event {}{}{}{}",
+            event.event_id.to_hex(),
+            event
+                .kind
+                .map(|kind| format!(", kind {}", kind.as_u16()))
+                .unwrap_or_default(),
+            event
+                .author
+                .map(|author| format!(", by {}", author.to_hex()))
+                .unwrap_or_default(),
+            relay_hint(&event.relays)
+        ),
+        Nip21::Coordinate(coordinate) => format!(
+            "addressable event of kind {} by {}, identifier \"{}\"{}",
+            coordinate.kind.as_u16(),
+            coordinate.public_key.to_hex(),
+            coordinate.identifier,
+            relay_hint(&coordinate.relays)
+        ),
+    };
+    format!("{}: {}", uri, summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_kinds() {
+        assert_eq!(detect("Can you deploy it?").0, ContentKind::Text);
+        assert_eq!(detect(" {\"env\": \"staging\"}\n").0, ContentKind::Json);
+        assert_eq!(detect("[1, 2, 3]").0, ContentKind::Json);
+        assert_eq!(detect("{not json}").0, ContentKind::Text);
+        assert_eq!(
+            detect("This fails:\n```rust\nfn main() {}\n```").0,
+            ContentKind::Code
+        );
+        assert_eq!(
+            detect("https://github.com/rust-lang/rust/issues/1").0,
+            ContentKind::UrlOnly
+        );
+        assert_eq!(
+            detect("see https://example.com for details").0,
+            ContentKind::Text
+        );
+    }
+
+    #[test]
+    fn test_nostr_uris_are_described() {
+        let keys = Keys::generate();
+        let npub = keys.public_key().to_bech32().unwrap();
+        let message = format!("Follow nostr:{}, they know.", npub);
+        let (kind, entities) = detect(&message);
+        assert_eq!(kind, ContentKind::NostrUri);
+        assert_eq!(
+            entities,
+            vec![format!(
+                "nostr:{}: profile of pubkey {}",
+                npub,
+                keys.public_key().to_hex()
+            )]
+        );
+
+        let coordinate = Nip19Coordinate::new(
+            Coordinate::new(Kind::LongFormTextNote, keys.public_key()).identifier("roadmap"),
+            ["wss://relay.example"],
+        )
+        .unwrap();
+        let naddr = coordinate.to_bech32().unwrap();
+        let (_, entities) = detect(&format!("Read (nostr:{})", naddr));
+        assert_eq!(
+            entities,
+            vec![format!(
+                "nostr:{}: addressable event of kind 30023 by {}, identifier \"roadmap\", relays wss://relay.example",
+                naddr,
+                keys.public_key().to_hex()
+            )]
+        );
+
+        assert_eq!(detect("nostr:npub1invalid").0, ContentKind::Text);
+    }
+}
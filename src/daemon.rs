@@ -0,0 +1,620 @@
+//! `nparrot daemon`: one long-lived process that keeps the relay connections open, and a Unix
+//! socket through which `send`, `send-progress` and `wait` hand their work to it
+//!
+//! The protocol is one JSON object per line. A client writes a request (`{"op":"send",...}` or
+//! `{"op":"wait",...}`) and reads responses until the final one: `sent` or `error` for a send,
+//! and for a wait one `message` per DM followed by `done` or `error`. A connection may issue any
+//! number of requests in turn, and every connection is served concurrently. Each waiting client
+//! sees every DM that arrives while it waits.
+
+use crate::progress_channels::ProgressChannels;
+use crate::redelivery;
+use crate::response_tracker::{DeliveryState, DeliveryTracker};
+use crate::shutdown::{Shutdown, GRACE_PERIOD};
+use crate::transport::SharedTransport;
+use crate::utils::{listen_for_messages, reply_tags, IncomingMessage};
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinSet;
+
+type DaemonResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Incoming DMs buffered per waiting client before the slowest one starts missing some
+const MESSAGE_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    Send {
+        /// Sent as is; the client has already applied `--envelope`
+        message: String,
+        #[serde(default)]
+        expire_after: Option<u64>,
+        #[serde(default)]
+        reply_to: Option<EventId>,
+        /// Send with a progress identity
+        #[serde(default)]
+        progress: bool,
+        /// Progress channel, `status` if omitted
+        #[serde(default)]
+        channel: Option<String>,
+        /// The main identity and target the client was configured with, which must match the
+        /// daemon's so a client never talks as someone else
+        identity: PublicKey,
+        target: PublicKey,
+    },
+    Wait {
+        count: usize,
+        /// Seconds, after which `done` reports however many messages arrived
+        #[serde(default)]
+        timeout: Option<u64>,
+        identity: PublicKey,
+        target: PublicKey,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    /// `queued` means no relay took the event yet and the daemon's resend queue has it
+    Sent {
+        event_id: EventId,
+        queued: bool,
+    },
+    Message {
+        message: IncomingMessage,
+    },
+    Done {
+        received: usize,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// The default socket, next to the rest of the local state
+pub fn default_socket(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join("nparrot.sock")
+}
+
+#[derive(Debug)]
+pub struct Daemon {
+    main: SharedTransport,
+    progress: ProgressChannels<SharedTransport>,
+    our_pubkey: PublicKey,
+    target_pubkey: PublicKey,
+    messages: broadcast::Sender<IncomingMessage>,
+}
+
+impl Daemon {
+    pub fn new(
+        main: SharedTransport,
+        progress: ProgressChannels<SharedTransport>,
+        our_pubkey: PublicKey,
+        target_pubkey: PublicKey,
+    ) -> Arc<Self> {
+        let (messages, _) = broadcast::channel(MESSAGE_BUFFER);
+        Arc::new(Self {
+            main,
+            progress,
+            our_pubkey,
+            target_pubkey,
+            messages,
+        })
+    }
+
+    /// Accepts clients on `socket` until shutdown, then lets running requests finish within the
+    /// grace period and removes the socket
+    pub async fn serve(self: Arc<Self>, socket: &Path, shutdown: Shutdown) -> DaemonResult<()> {
+        let listener = bind(socket).await?;
+        log::info!("Daemon listening on {}", socket.display());
+
+        let mut listening = {
+            let daemon = Arc::clone(&self);
+            tokio::spawn(async move {
+                let messages = daemon.messages.clone();
+                let callback = move |message: IncomingMessage| {
+                    // Nobody waiting is fine; the message is simply not handed to anyone
+                    let _ = messages.send(message);
+                    async { false }
+                };
+                listen_for_messages(
+                    &*daemon.main,
+                    &daemon.our_pubkey,
+                    &daemon.target_pubkey,
+                    Arc::new(Mutex::new(callback)),
+                )
+                .await
+            })
+        };
+
+        let mut connections = JoinSet::new();
+        let result = loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        connections.spawn(Arc::clone(&self).handle(stream, shutdown.clone()));
+                    }
+                    Err(e) => log::warn!("Could not accept daemon client: {}", e),
+                },
+                // Finished connections are reaped here so the set does not grow without bound
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                ended = &mut listening => {
+                    break match ended {
+                        Ok(Ok(())) => Err("DM subscription ended".into()),
+                        Ok(Err(e)) => Err(e),
+                        Err(e) => Err(e.into()),
+                    };
+                }
+                _ = shutdown.requested() => break Ok(()),
+            }
+        };
+
+        drop(listener);
+        if let Err(e) = std::fs::remove_file(socket) {
+            log::warn!("Could not remove {}: {}", socket.display(), e);
+        }
+        listening.abort();
+        let finished = tokio::time::timeout(GRACE_PERIOD, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if finished.is_err() {
+            log::warn!("Daemon clients did not finish within the grace period");
+        }
+        result
+    }
+
+    async fn handle(self: Arc<Self>, stream: UnixStream, shutdown: Shutdown) {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => line,
+                _ = shutdown.requested() => return,
+            };
+            let line = match line {
+                Ok(Some(line)) => line,
+                Ok(None) => return,
+                Err(e) => {
+                    log::debug!("Daemon client went away: {}", e);
+                    return;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let result = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => self.respond(request, &mut writer, &shutdown).await,
+                Err(e) => {
+                    let error = format!("Invalid request: {}", e);
+                    write_response(&mut writer, &Response::Error { error }).await
+                }
+            };
+            if let Err(e) = result {
+                log::debug!("Could not answer daemon client: {}", e);
+                return;
+            }
+        }
+    }
+
+    async fn respond(
+        &self,
+        request: Request,
+        writer: &mut OwnedWriteHalf,
+        shutdown: &Shutdown,
+    ) -> std::io::Result<()> {
+        let (identity, target) = match &request {
+            Request::Send {
+                identity, target, ..
+            }
+            | Request::Wait {
+                identity, target, ..
+            } => (*identity, *target),
+        };
+        if identity != self.our_pubkey || target != self.target_pubkey {
+            let error = format!(
+                "This daemon runs as {} talking to {}, not as {} talking to {}",
+                self.our_pubkey, self.target_pubkey, identity, target
+            );
+            return write_response(writer, &Response::Error { error }).await;
+        }
+
+        match request {
+            Request::Send {
+                message,
+                expire_after,
+                reply_to,
+                progress,
+                channel,
+                ..
+            } => {
+                let channel = progress.then_some(channel);
+                let response = self
+                    .send(message, expire_after, reply_to, channel)
+                    .await
+                    .unwrap_or_else(|e| Response::Error {
+                        error: e.to_string(),
+                    });
+                write_response(writer, &response).await
+            }
+            Request::Wait { count, timeout, .. } => {
+                self.wait(count, timeout, writer, shutdown).await
+            }
+        }
+    }
+
+    /// Publishes once; if every relay rejects the event it goes to the resend queue like a
+    /// failed `send` tool call does
+    async fn send(
+        &self,
+        message: String,
+        expire_after: Option<u64>,
+        reply_to: Option<EventId>,
+        progress_channel: Option<Option<String>>,
+    ) -> DaemonResult<Response> {
+        let (transport, channel) = if let Some(name) = progress_channel {
+            let (_, progress) = self
+                .progress
+                .resolve(name.as_deref())
+                .ok_or("the daemon has no progress identity (set --progress-nsec)")?;
+            (progress, "progress")
+        } else {
+            (&self.main, "main")
+        };
+
+        let rumor_tags = reply_to.map(reply_tags).unwrap_or_default();
+        let event = transport
+            .prepare_private_msg(self.target_pubkey, message, expire_after, rumor_tags)
+            .await?;
+        let tracker = DeliveryTracker::global();
+        tracker.track(event.id, self.target_pubkey, channel);
+
+        let error = match transport.send_event(&event).await {
+            Ok(output) if tracker.record_output(&output) != Some(DeliveryState::Failed) => {
+                return Ok(Response::Sent {
+                    event_id: event.id,
+                    queued: false,
+                });
+            }
+            Ok(output) => format!("rejected by all relays: {:?}", output.failed),
+            Err(e) => {
+                tracker.record_error(&event.id);
+                e.to_string()
+            }
+        };
+
+        let event_id = event.id;
+        if redelivery::enqueue(event, self.target_pubkey, channel) {
+            Ok(Response::Sent {
+                event_id,
+                queued: true,
+            })
+        } else {
+            Err(error.into())
+        }
+    }
+
+    async fn wait(
+        &self,
+        count: usize,
+        timeout: Option<u64>,
+        writer: &mut OwnedWriteHalf,
+        shutdown: &Shutdown,
+    ) -> std::io::Result<()> {
+        let mut messages = self.messages.subscribe();
+        let deadline = timeout.map(|secs| tokio::time::Instant::now() + Duration::from_secs(secs));
+        let mut received = 0;
+
+        while received < count {
+            tokio::select! {
+                message = messages.recv() => match message {
+                    Ok(message) => {
+                        write_response(writer, &Response::Message { message }).await?;
+                        received += 1;
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        log::warn!("A waiting client fell behind and missed {} messages", missed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = sleep_until(deadline) => break,
+                _ = shutdown.requested() => {
+                    let error = "The daemon is shutting down".to_string();
+                    return write_response(writer, &Response::Error { error }).await;
+                }
+            }
+        }
+        write_response(writer, &Response::Done { received }).await
+    }
+}
+
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Binds `socket`, replacing a file left behind by a daemon that did not exit cleanly. Only our
+/// own user may connect, since a client can send as our identity.
+async fn bind(socket: &Path) -> DaemonResult<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).await.is_ok() {
+            return Err(format!(
+                "another daemon is already listening on {}",
+                socket.display()
+            )
+            .into());
+        }
+        std::fs::remove_file(socket)?;
+    }
+    if let Some(parent) = socket.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+async fn write_response(writer: &mut OwnedWriteHalf, response: &Response) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+/// A connection to a running daemon
+pub struct DaemonClient {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl DaemonClient {
+    /// None when no daemon is listening on `socket`, in which case callers work directly
+    pub async fn connect(socket: &Path) -> Option<Self> {
+        match UnixStream::connect(socket).await {
+            Ok(stream) => {
+                let (reader, writer) = stream.into_split();
+                Some(Self {
+                    lines: BufReader::new(reader).lines(),
+                    writer,
+                })
+            }
+            Err(e) => {
+                log::debug!("No daemon on {}: {}", socket.display(), e);
+                None
+            }
+        }
+    }
+
+    async fn request(&mut self, request: &Request) -> DaemonResult<()> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn response(&mut self) -> DaemonResult<Response> {
+        let line = self
+            .lines
+            .next_line()
+            .await?
+            .ok_or("the daemon closed the connection")?;
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Returns the event id and whether the event is waiting in the daemon's resend queue
+    pub async fn send(&mut self, request: Request) -> DaemonResult<(EventId, bool)> {
+        self.request(&request).await?;
+        match self.response().await? {
+            Response::Sent { event_id, queued } => Ok((event_id, queued)),
+            Response::Error { error } => Err(error.into()),
+            other => Err(format!("unexpected daemon response: {:?}", other).into()),
+        }
+    }
+
+    /// Hands each message to `on_message` and returns how many arrived before the timeout
+    pub async fn wait<F>(&mut self, request: Request, on_message: F) -> DaemonResult<usize>
+    where
+        F: Fn(IncomingMessage),
+    {
+        self.request(&request).await?;
+        loop {
+            match self.response().await? {
+                Response::Message { message } => on_message(message),
+                Response::Done { received } => return Ok(received),
+                Response::Error { error } => return Err(error.into()),
+                other => return Err(format!("unexpected daemon response: {:?}", other).into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+
+    struct Running {
+        daemon: Arc<Daemon>,
+        main: FakeTransport,
+        target: Keys,
+        socket: PathBuf,
+        stop: tokio::sync::watch::Sender<bool>,
+        task: tokio::task::JoinHandle<DaemonResult<()>>,
+    }
+
+    async fn start() -> Running {
+        let our = Keys::generate();
+        let target = Keys::generate();
+        let main = FakeTransport::new(our.clone());
+        let daemon = Daemon::new(
+            Arc::new(main.clone()),
+            ProgressChannels::default(),
+            our.public_key(),
+            target.public_key(),
+        );
+        let socket = std::env::temp_dir().join(format!(
+            "nparrot-test-{}.sock",
+            Keys::generate().public_key().to_hex()
+        ));
+        let (shutdown, stop) = Shutdown::manual();
+        let task = {
+            let (daemon, socket) = (Arc::clone(&daemon), socket.clone());
+            tokio::spawn(async move { daemon.serve(&socket, shutdown).await })
+        };
+        // Serving starts asynchronously
+        while !socket.exists() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        Running {
+            daemon,
+            main,
+            target,
+            socket,
+            stop,
+            task,
+        }
+    }
+
+    fn send_request(running: &Running, message: &str, progress: bool) -> Request {
+        Request::Send {
+            message: message.to_string(),
+            expire_after: Some(60),
+            reply_to: None,
+            progress,
+            channel: None,
+            identity: running.daemon.our_pubkey,
+            target: running.daemon.target_pubkey,
+        }
+    }
+
+    fn wait_request(running: &Running, count: usize, timeout: Option<u64>) -> Request {
+        Request::Wait {
+            count,
+            timeout,
+            identity: running.daemon.our_pubkey,
+            target: running.daemon.target_pubkey,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_through_daemon() {
+        let running = start().await;
+        let mut client = DaemonClient::connect(&running.socket).await.unwrap();
+
+        let (event_id, queued) = client
+            .send(send_request(&running, "hello", false))
+            .await
+            .unwrap();
+        assert!(!queued);
+        assert_eq!(running.main.published()[0].id, event_id);
+        assert_eq!(running.main.sent()[0].content, "hello");
+        assert_eq!(running.main.sent()[0].expire_after_secs, Some(60));
+
+        // Same connection, next request
+        let err = client
+            .send(send_request(&running, "hi", true))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("no progress identity"));
+
+        let mut wrong = send_request(&running, "hi", false);
+        if let Request::Send { target, .. } = &mut wrong {
+            *target = Keys::generate().public_key();
+        }
+        let err = client.send(wrong).await.unwrap_err();
+        assert!(err.to_string().contains("This daemon runs as"));
+        assert_eq!(running.main.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_waits_each_get_messages() {
+        let running = start().await;
+
+        let mut waits = JoinSet::new();
+        for count in [1, 2] {
+            let mut client = DaemonClient::connect(&running.socket).await.unwrap();
+            let request = wait_request(&running, count, Some(5));
+            waits.spawn(async move {
+                let seen = std::sync::Mutex::new(Vec::new());
+                let received = client
+                    .wait(request, |m| seen.lock().unwrap().push(m.content))
+                    .await
+                    .unwrap();
+                (received, seen.into_inner().unwrap())
+            });
+        }
+        while running.daemon.messages.receiver_count() < 2 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // A send on another connection is not held up by the waits
+        let mut sender = DaemonClient::connect(&running.socket).await.unwrap();
+        sender
+            .send(send_request(&running, "while waiting", false))
+            .await
+            .unwrap();
+
+        running.main.inject(&running.target, "first");
+        // Only the configured target is listened to
+        running.main.inject(&Keys::generate(), "intruder");
+        running.main.inject(&running.target, "second");
+
+        let mut results = waits.join_all().await;
+        results.sort();
+        assert_eq!(results[0], (1, vec!["first".to_string()]));
+        assert_eq!(
+            results[1],
+            (2, vec!["first".to_string(), "second".to_string()])
+        );
+
+        // A timed out wait reports how many arrived
+        let mut client = DaemonClient::connect(&running.socket).await.unwrap();
+        let received = client
+            .wait(wait_request(&running, 3, Some(0)), |_| {})
+            .await
+            .unwrap();
+        assert_eq!(received, 0);
+
+        let _ = running.stop.send(true);
+        running.task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_removes_socket_and_replaces_stale_one() {
+        let running = start().await;
+        assert!(
+            bind(&running.socket).await.is_err(),
+            "a second daemon must not take over a live socket"
+        );
+        let mut client = DaemonClient::connect(&running.socket).await.unwrap();
+        let request = wait_request(&running, 1, None);
+        let waiting = tokio::spawn(async move { client.wait(request, |_| {}).await });
+        while running.daemon.messages.receiver_count() < 1 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        let _ = running.stop.send(true);
+        running.task.await.unwrap().unwrap();
+        let err = waiting.await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("shutting down"));
+        assert!(!running.socket.exists());
+        assert!(DaemonClient::connect(&running.socket).await.is_none());
+
+        // A socket file without a daemon behind it is replaced
+        std::fs::write(&running.socket, b"").unwrap();
+        drop(bind(&running.socket).await.unwrap());
+        std::fs::remove_file(&running.socket).unwrap();
+    }
+}
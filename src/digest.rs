@@ -0,0 +1,229 @@
+//! Status digest on the progress channel for long unattended runs (`NPARROT_DIGEST_INTERVAL`)
+//!
+//! Overnight Goose jobs and multi-agent pipelines either flood the progress channel or go
+//! quiet. With an interval set, the combined and multi-agent servers send one digest per
+//! interval instead: agents by status, tasks completed and failures since the last digest,
+//! scheduled messages still pending and resends waiting in the redelivery queue. The numbers come
+//! from the same counters as the metrics endpoint; when none of them moved since the last digest
+//! nothing is sent. `set_digest_interval` changes the interval, or turns digests off, at runtime.
+
+use crate::config::units::parse_duration_secs;
+use crate::error::NparrotError;
+use crate::mcp::chat::{Chat, ProgressMessageRequest};
+use crate::metrics::{self, Snapshot};
+use crate::progress_channels;
+use crate::{redelivery, schedule};
+use rmcp::model::{CallToolResult, Content};
+use rmcp::{schemars, Error as RmcpError};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// How often the scheduler checks whether a digest is due
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static::lazy_static! {
+    static ref INTERVAL: RwLock<Option<Duration>> = RwLock::new(None);
+}
+
+/// Sets how often a digest goes out; `None` or zero turns digests off
+pub fn set_interval(interval: Option<Duration>) {
+    if let Ok(mut guard) = INTERVAL.write() {
+        *guard = interval.filter(|interval| !interval.is_zero());
+    }
+}
+
+pub fn interval() -> Option<Duration> {
+    INTERVAL.read().ok().and_then(|guard| *guard)
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetDigestIntervalRequest {
+    #[schemars(
+        description = "How often to send the status digest, e.g. \"1h\" or \"30m\"; \"off\" or \"0\" stops it"
+    )]
+    pub interval: String,
+}
+
+/// What a digest reports, as of when it was collected
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Digest {
+    counters: Snapshot,
+    /// `send_later` messages not sent yet
+    scheduled: usize,
+    /// Events waiting to be resent
+    backlog: usize,
+}
+
+impl Digest {
+    fn collect() -> Self {
+        Self {
+            counters: metrics::snapshot(),
+            scheduled: schedule::global().map_or(0, |schedule| schedule.pending().len()),
+            backlog: redelivery::backlog(),
+        }
+    }
+
+    /// The digest message, `None` if nothing changed since `previous`
+    fn message(&self, previous: &Digest, interval: Duration) -> Option<String> {
+        if self == previous {
+            return None;
+        }
+        let agents = if self.counters.agents.is_empty() {
+            "none".to_string()
+        } else {
+            self.counters
+                .agents
+                .iter()
+                .map(|(status, count)| format!("{} {}", count, status))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        Some(format!(
+            "📊 Status digest (every {})\n\
+            Agents: {}\n\
+            Tasks completed: {}\n\
+            Failures: {}\n\
+            Scheduled messages pending: {}\n\
+            Resends queued: {}",
+            format_interval(interval),
+            agents,
+            self.counters
+                .tasks_completed
+                .saturating_sub(previous.counters.tasks_completed),
+            self.counters
+                .failures
+                .saturating_sub(previous.counters.failures),
+            self.scheduled,
+            self.backlog,
+        ))
+    }
+}
+
+fn format_interval(interval: Duration) -> String {
+    let secs = interval.as_secs();
+    match secs {
+        s if s % 3600 == 0 => format!("{}h", s / 3600),
+        s if s % 60 == 0 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// Sends a digest on the status channel whenever one is due, until the process exits
+pub async fn run(chat: Chat) {
+    let mut previous = Digest::collect();
+    let mut last = Instant::now();
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let Some(interval) = interval() else {
+            last = Instant::now();
+            continue;
+        };
+        if last.elapsed() < interval {
+            continue;
+        }
+        last = Instant::now();
+
+        let current = Digest::collect();
+        match current.message(&previous, interval) {
+            Some(message) => {
+                let _ = chat
+                    .progress(ProgressMessageRequest {
+                        message,
+                        expire_after_secs: None,
+                        channel: Some(progress_channels::STATUS.to_string()),
+                    })
+                    .await;
+            }
+            None => log::debug!("Nothing changed since the last digest, skipping it"),
+        }
+        previous = current;
+    }
+}
+
+/// What `set_digest_interval` returns
+pub fn tool_result(
+    SetDigestIntervalRequest { interval }: SetDigestIntervalRequest,
+) -> Result<CallToolResult, RmcpError> {
+    let secs = match interval.trim() {
+        "off" => 0,
+        other => {
+            parse_duration_secs(other).map_err(|e| NparrotError::invalid_params("interval", e))?
+        }
+    };
+    set_interval(Some(Duration::from_secs(secs)));
+    let text = match self::interval() {
+        Some(interval) => format!(
+            "Status digest every {}, skipped when nothing changed",
+            format_interval(interval)
+        ),
+        None => "Status digest turned off".to_string(),
+    };
+    Ok(CallToolResult::success(vec![Content::text(text)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(agents: &[(&str, usize)], completed: u64, failures: u64) -> Digest {
+        Digest {
+            counters: Snapshot {
+                agents: agents
+                    .iter()
+                    .map(|(status, count)| (status.to_string(), *count))
+                    .collect(),
+                tasks_completed: completed,
+                failures,
+            },
+            scheduled: 1,
+            backlog: 0,
+        }
+    }
+
+    #[test]
+    fn test_digest_reports_the_changes() {
+        let hour = Duration::from_secs(3600);
+        let previous = digest(&[("running", 2)], 5, 1);
+        let current = digest(&[("busy", 1), ("running", 1)], 8, 2);
+        assert_eq!(
+            current.message(&previous, hour).unwrap(),
+            "📊 Status digest (every 1h)\n\
+            Agents: 1 busy, 1 running\n\
+            Tasks completed: 3\n\
+            Failures: 1\n\
+            Scheduled messages pending: 1\n\
+            Resends queued: 0"
+        );
+        // Nothing moved: no digest at all
+        assert_eq!(current.message(&current.clone(), hour), None);
+        assert!(digest(&[], 0, 0)
+            .message(&previous, hour)
+            .unwrap()
+            .contains("Agents: none"));
+    }
+
+    #[test]
+    fn test_interval_tool() {
+        let result = tool_result(SetDigestIntervalRequest {
+            interval: "30m".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            result.content[0].as_text().unwrap().text,
+            "Status digest every 30m, skipped when nothing changed"
+        );
+        assert_eq!(interval(), Some(Duration::from_secs(1800)));
+
+        tool_result(SetDigestIntervalRequest {
+            interval: "off".to_string(),
+        })
+        .unwrap();
+        assert_eq!(interval(), None);
+
+        let error = tool_result(SetDigestIntervalRequest {
+            interval: "hourly".to_string(),
+        })
+        .unwrap_err();
+        assert!(error.message.contains("Invalid duration"));
+    }
+}
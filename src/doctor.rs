@@ -0,0 +1,249 @@
+//! Environment validation for the `doctor` subcommand
+//!
+//! Runs every check independently so a single misconfiguration doesn't hide the others,
+//! then prints a pass/fail table (or JSON for CI).
+
+#[cfg(feature = "goose")]
+use crate::goose_mcp::commands::goose_binary;
+use crate::relays;
+#[cfg(feature = "searxng")]
+use crate::searxng_mcp::{client::SearXNGClient, SearXNGWebSearchRequest};
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+#[cfg(feature = "goose")]
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Settings the doctor validates, taken from the CLI/environment
+pub struct DoctorConfig {
+    pub nsec: Option<String>,
+    pub target_pubkey: Option<String>,
+    pub progress_nsec: Option<String>,
+    pub relays: Vec<String>,
+    /// `PROGRESS_RELAY_URL`, if the progress identities have relays of their own
+    pub progress_relays: Vec<String>,
+    #[cfg(feature = "searxng")]
+    pub searxng_url: String,
+    pub data_dir: String,
+    pub timeout: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub required: bool,
+    pub passed: bool,
+    pub detail: String,
+    pub duration_ms: u128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DoctorReport {
+    pub ok: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    /// Prints the report as an aligned table
+    pub fn print_table(&self) {
+        let width = self
+            .checks
+            .iter()
+            .map(|c| c.name.len())
+            .max()
+            .unwrap_or(5)
+            .max(5);
+
+        println!(
+            "{:<width$}  {:<6}  DETAIL",
+            "CHECK",
+            "STATUS",
+            width = width
+        );
+        for check in &self.checks {
+            let status = match (check.passed, check.required) {
+                (true, _) => "PASS",
+                (false, true) => "FAIL",
+                (false, false) => "WARN",
+            };
+            println!(
+                "{:<width$}  {:<6}  {}",
+                check.name,
+                status,
+                check.detail,
+                width = width
+            );
+        }
+        println!();
+        if self.ok {
+            println!("All required checks passed.");
+        } else {
+            println!("One or more required checks failed.");
+        }
+    }
+}
+
+/// Runs all checks and returns the report
+pub async fn run(config: &DoctorConfig) -> DoctorReport {
+    let mut checks = Vec::new();
+
+    checks.push(timed("nsec", true, || match &config.nsec {
+        Some(nsec) => check_nsec(nsec),
+        None => Err("Not set (--nsec or NSEC)".to_string()),
+    }));
+    if let Some(progress_nsec) = &config.progress_nsec {
+        checks.push(timed("progress nsec", true, || check_nsec(progress_nsec)));
+    }
+    checks.push(timed("target pubkey", true, || {
+        let target_pubkey = config
+            .target_pubkey
+            .as_deref()
+            .ok_or("Not set (--target-pubkey or TARGET_PUBKEY)")?;
+        PublicKey::parse(target_pubkey)
+            .map(|pk| pk.to_bech32().unwrap_or_else(|_| pk.to_hex()))
+            .map_err(|e| format!("Invalid target pubkey: {}", e))
+    }));
+
+    if config.relays.is_empty() {
+        checks.push(CheckResult {
+            name: "relays".to_string(),
+            required: true,
+            passed: false,
+            detail: "No relay URLs configured".to_string(),
+            duration_ms: 0,
+        });
+    }
+    for url in &config.relays {
+        let started = Instant::now();
+        let result = check_relay(url, config.timeout).await;
+        checks.push(to_check(format!("relay {}", url), true, started, result));
+    }
+    // Progress is best-effort, so its relays being down doesn't fail the run
+    for url in &config.progress_relays {
+        let started = Instant::now();
+        let result = check_relay(url, config.timeout).await;
+        checks.push(to_check(
+            format!("progress relay {}", url),
+            false,
+            started,
+            result,
+        ));
+    }
+
+    #[cfg(feature = "goose")]
+    checks.push(timed("goose binary", false, check_goose));
+
+    #[cfg(feature = "searxng")]
+    {
+        let started = Instant::now();
+        let result = check_searxng(&config.searxng_url, config.timeout).await;
+        checks.push(to_check("searxng".to_string(), false, started, result));
+    }
+
+    checks.push(timed("data dir", true, || check_data_dir(&config.data_dir)));
+
+    let ok = checks.iter().all(|c| c.passed || !c.required);
+    DoctorReport { ok, checks }
+}
+
+fn timed<F>(name: &str, required: bool, check: F) -> CheckResult
+where
+    F: FnOnce() -> Result<String, String>,
+{
+    let started = Instant::now();
+    let result = check();
+    to_check(name.to_string(), required, started, result)
+}
+
+fn to_check(
+    name: String,
+    required: bool,
+    started: Instant,
+    result: Result<String, String>,
+) -> CheckResult {
+    let (passed, detail) = match result {
+        Ok(detail) => (true, detail),
+        Err(detail) => (false, detail),
+    };
+    CheckResult {
+        name,
+        required,
+        passed,
+        detail,
+        duration_ms: started.elapsed().as_millis(),
+    }
+}
+
+fn check_nsec(nsec: &str) -> Result<String, String> {
+    let keys = Keys::parse(nsec).map_err(|e| format!("Invalid secret key: {}", e))?;
+    let pubkey = keys.public_key();
+    Ok(pubkey.to_bech32().unwrap_or_else(|_| pubkey.to_hex()))
+}
+
+async fn check_relay(url: &str, timeout: Duration) -> Result<String, String> {
+    let (probe, info) = tokio::join!(
+        relays::probe_relay(url, timeout),
+        relays::fetch_relay_info(url, timeout)
+    );
+    let probe = probe?;
+    let mut detail = format!(
+        "connected in {}ms, REQ answered in {}ms",
+        probe.connect_time.as_millis(),
+        probe.rtt.as_millis()
+    );
+    let summary = info
+        .map(|info| relays::RelayInfo::from_document(url, &info).summary())
+        .unwrap_or_default();
+    if !summary.is_empty() {
+        detail.push_str("; ");
+        detail.push_str(&summary);
+    }
+    Ok(detail)
+}
+
+#[cfg(feature = "goose")]
+pub fn check_goose() -> Result<String, String> {
+    let output = Command::new(goose_binary())
+        .arg("--version")
+        .output()
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => "goose not found on PATH".to_string(),
+            _ => format!("Could not run goose: {}", e),
+        })?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "goose --version exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(feature = "searxng")]
+pub async fn check_searxng(url: &str, timeout: Duration) -> Result<String, String> {
+    let client = SearXNGClient::new(url.to_string());
+    let request = SearXNGWebSearchRequest {
+        query: "nostr".to_string(),
+        count: Some(1),
+        offset: None,
+    };
+
+    match tokio::time::timeout(timeout, client.search(request)).await {
+        Ok(Ok(response)) => Ok(format!(
+            "{} answered with {} result(s)",
+            url,
+            response.results.len()
+        )),
+        Ok(Err(e)) => Err(format!("{}: {}", url, e)),
+        Err(_) => Err(format!("{}: no answer within {}s", url, timeout.as_secs())),
+    }
+}
+
+pub fn check_data_dir(data_dir: &str) -> Result<String, String> {
+    std::fs::create_dir_all(data_dir).map_err(|e| format!("Cannot create {}: {}", data_dir, e))?;
+    tempfile::NamedTempFile::new_in(data_dir)
+        .map_err(|e| format!("{} is not writable: {}", data_dir, e))?;
+    Ok(format!("{} is writable", data_dir))
+}
@@ -0,0 +1,33 @@
+//! Dry runs of the destructive tools: `deletenote`, `deleteevent`, `delete_memory`,
+//! `removesession` and `stop_agent`
+//!
+//! Each takes `dry_run: true` and then reports what it would affect without changing anything.
+//! The managers behind them find the affected items the same way either way and only stop before
+//! the change, so a preview can't promise something the real call does differently.
+//! `NPARROT_FORCE_DRY_RUN` turns every such call into a dry run, e.g. for demos.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Starts every message and result of a dry run
+pub const LABEL: &str = "🧪 DRY RUN";
+
+static FORCED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the process-wide `NPARROT_FORCE_DRY_RUN` switch
+pub fn set_forced(forced: bool) {
+    FORCED.store(forced, Ordering::Relaxed);
+}
+
+pub fn forced() -> bool {
+    FORCED.load(Ordering::Relaxed)
+}
+
+/// Whether a call with this `dry_run` argument only previews
+pub fn requested(dry_run: Option<bool>) -> bool {
+    forced() || dry_run.unwrap_or(false)
+}
+
+/// `message` marked as a dry run that changed nothing
+pub fn label(message: &str) -> String {
+    format!("{}, nothing was changed: {}", LABEL, message)
+}
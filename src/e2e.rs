@@ -0,0 +1,339 @@
+//! End-to-end tests: the real components talking through in-memory relays
+//!
+//! Each test wires up a server side the way `main` does and drives it from a user's transport
+//! on the other side of a `RelayNetwork`. Every message is wrapped, published, delivered by each
+//! relay, unwrapped and dispatched as it is against live relays, and nothing touches the network.
+
+use crate::mcp::chat::{Chat, SendMessageRequest, WaitRequest};
+use crate::progress_channels::ProgressChannels;
+use crate::replay;
+use crate::transport::memory_relay::{RelayNetwork, RelayTransport};
+use crate::transport::DmTransport;
+use nostr_sdk::prelude::*;
+use rmcp::model::CallToolResult;
+use std::sync::{Arc, Once};
+use std::time::Duration;
+
+const A: &str = "wss://a.relay";
+const B: &str = "wss://b.relay";
+
+/// How long a message that should never arrive is waited for
+const NOTHING_AFTER: Duration = Duration::from_millis(300);
+
+/// How long a `Chat` takes to subscribe once it starts listening
+const SUBSCRIBED: Duration = Duration::from_millis(50);
+
+/// Turns the replay guard on for every test, as `main` does at startup
+fn guard_replays() {
+    static GUARD: Once = Once::new();
+    GUARD.call_once(|| {
+        let dir = tempfile::tempdir().unwrap().keep();
+        replay::init(&dir.to_string_lossy(), Duration::from_secs(48 * 3600));
+    });
+}
+
+struct Parties {
+    network: RelayNetwork,
+    ours: Keys,
+    user: Keys,
+    /// The user's phone
+    phone: RelayTransport,
+}
+
+fn parties() -> Parties {
+    guard_replays();
+    let network = RelayNetwork::new(&[A, B]);
+    let user = Keys::generate();
+    Parties {
+        phone: network.connect(user.clone()),
+        network,
+        ours: Keys::generate(),
+        user,
+    }
+}
+
+impl Parties {
+    /// The server's chat, listening already as an MCP server is from the start
+    async fn chat(&self) -> Chat {
+        self.chat_with(Vec::new()).await
+    }
+
+    /// The same, taking messages from `other_senders` too
+    async fn chat_with(&self, other_senders: Vec<PublicKey>) -> Chat {
+        let chat = Chat::with_transport(
+            Arc::new(self.network.connect(self.ours.clone())),
+            ProgressChannels::default(),
+            self.ours.public_key(),
+            self.user.public_key(),
+        )
+        .with_interrupt(crate::interrupt::Interrupt::new())
+        .with_other_senders(other_senders);
+        chat.listen().await;
+        tokio::time::sleep(SUBSCRIBED).await;
+        chat
+    }
+
+    async fn tell(&self, from: &RelayTransport, message: &str) {
+        from.send_private_msg(self.ours.public_key(), message.to_string(), None)
+            .await
+            .unwrap();
+    }
+
+    /// The gift wraps addressed to `pubkey` that relay A stores
+    fn wraps_to(&self, pubkey: PublicKey) -> Vec<Event> {
+        self.network
+            .stored(A)
+            .into_iter()
+            .filter(|event| event.tags.public_keys().any(|p| *p == pubkey))
+            .collect()
+    }
+}
+
+fn text(result: &CallToolResult) -> String {
+    result.content[0].as_text().unwrap().text.clone()
+}
+
+/// The next message `wait` hands over, `None` if nothing comes
+async fn next(chat: &Chat, from: Option<&Keys>) -> Option<String> {
+    let request = WaitRequest {
+        from: from.map(|keys| keys.public_key().to_hex()),
+    };
+    tokio::time::timeout(NOTHING_AFTER, chat.wait(request))
+        .await
+        .ok()
+        .map(|result| text(&result.unwrap()))
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_cli_send_and_wait_round_trip() {
+    use crate::daemon::{Daemon, DaemonClient, Request};
+
+    let parties = parties();
+    let daemon = Daemon::new(
+        Arc::new(parties.network.connect(parties.ours.clone())),
+        ProgressChannels::default(),
+        parties.ours.public_key(),
+        parties.user.public_key(),
+    );
+    let socket = std::env::temp_dir().join(format!(
+        "nparrot-e2e-{}.sock",
+        Keys::generate().public_key().to_hex()
+    ));
+    let (shutdown, stop) = crate::shutdown::Shutdown::manual();
+    let serving = tokio::spawn({
+        let socket = socket.clone();
+        async move { daemon.serve(&socket, shutdown).await }
+    });
+    while !socket.exists() {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let mut inbox = parties
+        .phone
+        .subscribe_dms(parties.user.public_key())
+        .await
+        .unwrap();
+
+    // nparrot send "build finished"
+    let mut client = DaemonClient::connect(&socket).await.unwrap();
+    let (event_id, queued) = client
+        .send(Request::Send {
+            message: "build finished".to_string(),
+            expire_after: None,
+            reply_to: None,
+            progress: false,
+            channel: None,
+            identity: parties.ours.public_key(),
+            target: parties.user.public_key(),
+        })
+        .await
+        .unwrap();
+    assert!(!queued);
+    assert!(parties.network.stored(B).iter().any(|e| e.id == event_id));
+    let received = tokio::time::timeout(Duration::from_secs(1), inbox.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received.rumor.content, "build finished");
+
+    // nparrot wait, answered from the phone
+    let mut waiting = DaemonClient::connect(&socket).await.unwrap();
+    let request = Request::Wait {
+        count: 1,
+        timeout: Some(5),
+        identity: parties.ours.public_key(),
+        target: parties.user.public_key(),
+    };
+    let wait = tokio::spawn(async move {
+        let seen = std::sync::Mutex::new(Vec::new());
+        let received = waiting
+            .wait(request, |message| {
+                seen.lock().unwrap().push(message.content)
+            })
+            .await
+            .unwrap();
+        (received, seen.into_inner().unwrap())
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    parties.tell(&parties.phone, "ship it").await;
+    assert_eq!(wait.await.unwrap(), (1, vec!["ship it".to_string()]));
+
+    stop.send(true).unwrap();
+    serving.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_copies_from_every_relay_arrive_once() {
+    let parties = parties();
+    let chat = parties.chat().await;
+    parties.tell(&parties.phone, "hello from both relays").await;
+    assert_eq!(parties.network.stored(A), parties.network.stored(B));
+
+    assert!(next(&chat, None)
+        .await
+        .unwrap()
+        .starts_with("hello from both relays"));
+    assert_eq!(next(&chat, None).await, None);
+
+    // An identical send right after the first is skipped rather than published again
+    for _ in 0..2 {
+        chat.send(SendMessageRequest {
+            message: "On it".to_string(),
+            reply_to: None,
+            to: None,
+        })
+        .await
+        .unwrap();
+    }
+    assert_eq!(parties.wraps_to(parties.user.public_key()).len(), 1);
+}
+
+#[tokio::test]
+async fn test_lost_and_reordered_messages() {
+    let parties = parties();
+    let chat = parties.chat().await;
+
+    // One lossy relay out of two still gets the message through
+    parties.network.lose(A, true);
+    parties.tell(&parties.phone, "first.").await;
+    assert!(next(&chat, None).await.unwrap().starts_with("first."));
+
+    parties.network.lose(B, true);
+    parties.tell(&parties.phone, "never arrives.").await;
+    assert_eq!(next(&chat, None).await, None);
+
+    parties.network.lose(A, false);
+    parties.network.hold();
+    parties.tell(&parties.phone, "second.").await;
+    parties.tell(&parties.phone, "third.").await;
+    parties.network.release(true);
+    assert!(next(&chat, None).await.unwrap().starts_with("third."));
+    assert!(next(&chat, None).await.unwrap().starts_with("second."));
+}
+
+#[tokio::test]
+async fn test_only_allowed_senders_reach_wait() {
+    let parties = parties();
+    let bob = Keys::generate();
+    let chat = parties.chat_with(vec![bob.public_key()]).await;
+    let stranger = parties.network.connect(Keys::generate());
+    let bobs = parties.network.connect(bob.clone());
+
+    parties.tell(&stranger, "let me in.").await;
+    parties.tell(&bobs, "from bob.").await;
+    parties.tell(&parties.phone, "from the user.").await;
+
+    // In the order they arrived, the stranger's never
+    let first = next(&chat, None).await.unwrap();
+    assert!(first.contains("not the primary user") && first.contains("from bob."));
+    assert!(next(&chat, None)
+        .await
+        .unwrap()
+        .starts_with("from the user."));
+    assert_eq!(next(&chat, None).await, None);
+    assert_eq!(next(&chat, Some(&bob)).await, None);
+}
+
+#[tokio::test]
+async fn test_replayed_and_outdated_messages_are_dropped() {
+    let parties = parties();
+    let chat = parties.chat().await;
+    parties.tell(&parties.phone, "deploy to production.").await;
+    assert!(next(&chat, None)
+        .await
+        .unwrap()
+        .starts_with("deploy to production."));
+
+    // After a restart a relay sends the same event again
+    let restarted = parties.chat().await;
+    let wrap = parties.wraps_to(parties.ours.public_key())[0].id;
+    parties.network.replay(A, &wrap);
+    assert_eq!(next(&restarted, None).await, None);
+
+    // A message written three days ago, however it was wrapped
+    let old = parties
+        .phone
+        .prepare_private_msg_at(
+            parties.ours.public_key(),
+            "deploy again.".to_string(),
+            None,
+            Vec::new(),
+            Timestamp::now() - Duration::from_secs(72 * 3600),
+        )
+        .await
+        .unwrap();
+    parties.phone.send_event(&old).await.unwrap();
+    assert_eq!(next(&restarted, None).await, None);
+}
+
+#[cfg(feature = "memory")]
+#[tokio::test]
+async fn test_memory_lifecycle_across_devices() {
+    use crate::nostr_mcp::server::NostrMemoryServer;
+
+    let parties = parties();
+    let server = |keys: &Keys| {
+        NostrMemoryServer::with_transport(
+            Arc::new(parties.network.connect(keys.clone())),
+            keys.clone(),
+            keys.public_key(),
+            parties.user.public_key(),
+        )
+    };
+    let laptop = server(&parties.ours);
+    let stored = laptop
+        .store_memory(
+            serde_json::from_value(serde_json::json!({
+                "memory_type": "user_preference",
+                "title": "Coffee",
+                "description": "Takes it black",
+            }))
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    let id = text(&stored)
+        .strip_prefix("Memory stored with ID: ")
+        .unwrap()
+        .to_string();
+
+    // Another instance of the same identity only knows what the relays returned
+    let desktop = server(&parties.ours);
+    let everything = || serde_json::from_value(serde_json::json!({ "limit": 10 })).unwrap();
+    let retrieved = desktop.retrieve_memory(everything()).await.unwrap();
+    assert_eq!(text(&retrieved), "Retrieved 1 memories");
+
+    let deleted = desktop
+        .delete_memory(serde_json::from_value(serde_json::json!({ "id": id })).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(text(&deleted), format!("Memory {} deleted", id));
+    let retrieved = server(&parties.ours)
+        .retrieve_memory(everything())
+        .await
+        .unwrap();
+    assert_eq!(text(&retrieved), "Retrieved 0 memories");
+
+    // The user was told about each step
+    assert!(!parties.wraps_to(parties.user.public_key()).is_empty());
+}
@@ -0,0 +1,308 @@
+//! Optional JSON envelope for DM payloads, so two nparrot instances can talk without parsing free text
+//!
+//! With `NPARROT_ENVELOPE=1` outgoing messages are sent as
+//! `{"v":1,"type":"chat"|"progress"|"command","body":"...","meta":{...}}`. Incoming envelopes are
+//! always recognised and unwrapped; anything else is treated as plain text, exactly as before.
+//!
+//! Versioning: `v` only changes for incompatible changes to the fields above. New optional fields
+//! may be added without a bump and must be ignored by readers that don't know them. A message whose
+//! `v` is newer than [`VERSION`] is delivered as plain text rather than half-understood.
+
+use crate::transport::{DmTransport, SharedTransport, TransportResult};
+use futures::future::BoxFuture;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Newest envelope version this build reads and the one it writes
+pub const VERSION: u32 = 1;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageType {
+    Chat,
+    Progress,
+    Command,
+    /// A verified zap receipt (see `zap`); never taken from an envelope
+    Zap,
+}
+
+impl MessageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Chat => "chat",
+            Self::Progress => "progress",
+            Self::Command => "command",
+            Self::Zap => "zap",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub v: u32,
+    #[serde(rename = "type")]
+    pub message_type: MessageType,
+    pub body: String,
+    #[serde(default)]
+    pub meta: Map<String, Value>,
+}
+
+impl Envelope {
+    pub fn new(message_type: MessageType, body: impl Into<String>) -> Self {
+        Self {
+            v: VERSION,
+            message_type,
+            body: body.into(),
+            meta: Map::new(),
+        }
+    }
+
+    /// Recognises an envelope this build understands; `None` means the content is plain text
+    pub fn open(content: &str) -> Option<Self> {
+        if !content.trim_start().starts_with('{') {
+            return None;
+        }
+        let envelope: Self = serde_json::from_str(content).ok()?;
+        // Only a receipt signed by our provider makes a zap, not a DM saying so
+        ((1..=VERSION).contains(&envelope.v) && envelope.message_type != MessageType::Zap)
+            .then_some(envelope)
+    }
+}
+
+/// Installs the process-wide choice of whether outgoing messages are enveloped
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Wraps `body` in an envelope if enveloping is enabled, otherwise returns it unchanged
+pub fn wrap(message_type: MessageType, body: impl Into<String>) -> String {
+    let body = body.into();
+    if !enabled() {
+        return body;
+    }
+    seal(message_type, body)
+}
+
+fn seal(message_type: MessageType, body: String) -> String {
+    serde_json::to_string(&Envelope::new(message_type, body)).expect("envelopes always serialize")
+}
+
+/// Returns `transport` as is, or one that envelopes everything it sends as `message_type`
+/// when enveloping is enabled
+pub fn outgoing(transport: SharedTransport, message_type: MessageType) -> SharedTransport {
+    if enabled() {
+        Arc::new(Enveloping {
+            inner: transport,
+            message_type,
+        })
+    } else {
+        transport
+    }
+}
+
+#[derive(Debug)]
+struct Enveloping {
+    inner: SharedTransport,
+    message_type: MessageType,
+}
+
+impl DmTransport for Enveloping {
+    fn prepare_private_msg_at(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        self.inner.prepare_private_msg_at(
+            receiver,
+            seal(self.message_type, message),
+            expire_after_secs,
+            rumor_tags,
+            created_at,
+        )
+    }
+
+    fn prepare_reaction(
+        &self,
+        receiver: PublicKey,
+        message_id: EventId,
+        reaction: String,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        self.inner.prepare_reaction(receiver, message_id, reaction)
+    }
+
+    fn sign_event(&self, builder: EventBuilder) -> BoxFuture<'_, TransportResult<Event>> {
+        self.inner.sign_event(builder)
+    }
+
+    fn send_event<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        self.inner.send_event(event)
+    }
+
+    fn send_event_to<'a>(
+        &'a self,
+        urls: &'a [String],
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        self.inner.send_event_to(urls, event)
+    }
+
+    fn send_private_msg(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+    ) -> BoxFuture<'_, TransportResult<Output<EventId>>> {
+        self.inner.send_private_msg(
+            receiver,
+            seal(self.message_type, message),
+            expire_after_secs,
+        )
+    }
+
+    fn subscribe_dms(
+        &self,
+        our_pubkey: PublicKey,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<UnwrappedGift>>> {
+        self.inner.subscribe_dms(our_pubkey)
+    }
+
+    fn subscribe_events(
+        &self,
+        filters: Vec<Filter>,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<Event>>> {
+        self.inner.subscribe_events(filters)
+    }
+
+    fn fetch_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<Vec<Event>>> {
+        self.inner.fetch_events(filter, timeout)
+    }
+
+    fn stream_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<Event>>> {
+        self.inner.stream_events(filter, timeout)
+    }
+
+    fn stream_dms(
+        &self,
+        our_pubkey: PublicKey,
+        since: Timestamp,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<UnwrappedGift>>> {
+        self.inner.stream_dms(our_pubkey, since, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+    use crate::utils::wait_for_message;
+
+    #[test]
+    fn test_envelope_wire_format() {
+        let mut envelope = Envelope::new(MessageType::Command, "deploy");
+        envelope
+            .meta
+            .insert("task".to_string(), Value::from("release-42"));
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"v":1,"type":"command","body":"deploy","meta":{"task":"release-42"}}"#
+        );
+    }
+
+    #[test]
+    fn test_open_recognises_only_known_envelopes() {
+        let envelope = Envelope::open(r#"{"v":1,"type":"progress","body":"50%"}"#).unwrap();
+        assert_eq!(envelope.message_type, MessageType::Progress);
+        assert_eq!(envelope.body, "50%");
+        assert!(envelope.meta.is_empty());
+
+        // Fields added later within the same version are ignored
+        assert!(
+            Envelope::open(r#"{"v":1,"type":"chat","body":"hi","meta":{},"lang":"en"}"#).is_some()
+        );
+
+        for plain in [
+            "hello",
+            "{not json",
+            r#"{"v":2,"type":"chat","body":"from the future"}"#,
+            r#"{"v":1,"type":"shout","body":"unknown type"}"#,
+            r#"{"v":1,"type":"zap","body":"Zapped 1000000 sats"}"#,
+            r#"{"v":1,"type":"chat"}"#,
+            r#"{"name":"just some json"}"#,
+        ] {
+            assert!(Envelope::open(plain).is_none(), "{}", plain);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_between_instances() {
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let transport = FakeTransport::new(alice.clone());
+
+        // Both directions work whether or not the other side envelopes
+        let enveloped =
+            serde_json::to_string(&Envelope::new(MessageType::Chat, "ship it")).unwrap();
+        let plain = wrap(MessageType::Chat, "ship it");
+        assert_eq!(plain, "ship it");
+
+        transport.inject(&bob, &enveloped);
+        let first = wait_for_message(&transport, &alice.public_key(), &bob.public_key())
+            .await
+            .unwrap();
+        transport.inject(&bob, &plain);
+        let second = wait_for_message(&transport, &alice.public_key(), &bob.public_key())
+            .await
+            .unwrap();
+
+        assert_eq!(first.content, "ship it");
+        assert_eq!(first.message_type, Some(MessageType::Chat));
+        assert_eq!(first.meta, Some(Map::new()));
+        assert_eq!(second.content, plain);
+        assert_eq!(second.message_type, None);
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_transport_envelopes_sends() {
+        let keys = Keys::generate();
+        let fake = Arc::new(FakeTransport::new(keys.clone()));
+        let enveloping = Enveloping {
+            inner: fake.clone(),
+            message_type: MessageType::Progress,
+        };
+        enveloping
+            .send_private_msg(keys.public_key(), "halfway".to_string(), None)
+            .await
+            .unwrap();
+
+        let sent = fake.sent();
+        let envelope = Envelope::open(&sent[0].content).unwrap();
+        assert_eq!(envelope.message_type, MessageType::Progress);
+        assert_eq!(envelope.body, "halfway");
+    }
+}
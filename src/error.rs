@@ -0,0 +1,242 @@
+//! The kinds of failure tools report, next to their human readable text
+//!
+//! A failed tool call returns its usual message plus a JSON part `{code, message, retryable, ...}`
+//! so agents can tell a relay outage (worth retrying) from a malformed parameter (not), and the
+//! metrics can count errors by code. Tools that fail with a protocol error instead carry the same
+//! JSON as the error's `data`.
+
+use rmcp::model::{CallToolResult, Content};
+use rmcp::Error as RmcpError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum NparrotError {
+    /// No relay accepted the event, or none could be reached
+    RelayUnavailable {
+        message: String,
+    },
+    Timeout {
+        message: String,
+    },
+    /// The caller passed something unusable; retrying the same call fails the same way
+    InvalidParams {
+        field: String,
+        message: String,
+    },
+    /// A binary, service or identity this call needs is not installed or configured
+    BackendMissing {
+        what: String,
+        message: String,
+    },
+    RateLimited {
+        message: String,
+    },
+    Internal {
+        message: String,
+    },
+}
+
+impl NparrotError {
+    pub fn relay_unavailable(message: impl Into<String>) -> Self {
+        Self::RelayUnavailable {
+            message: message.into(),
+        }
+    }
+
+    pub fn timeout(message: impl Into<String>) -> Self {
+        Self::Timeout {
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_params(field: &str, message: impl Into<String>) -> Self {
+        Self::InvalidParams {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn backend_missing(what: &str, message: impl Into<String>) -> Self {
+        Self::BackendMissing {
+            what: what.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn rate_limited(message: impl Into<String>) -> Self {
+        Self::RateLimited {
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal {
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::RelayUnavailable { .. } => "relay_unavailable",
+            Self::Timeout { .. } => "timeout",
+            Self::InvalidParams { .. } => "invalid_params",
+            Self::BackendMissing { .. } => "backend_missing",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::Internal { .. } => "internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::RelayUnavailable { message }
+            | Self::Timeout { message }
+            | Self::InvalidParams { message, .. }
+            | Self::BackendMissing { message, .. }
+            | Self::RateLimited { message }
+            | Self::Internal { message } => message,
+        }
+    }
+
+    /// The same error with `context` in front of its message, e.g. "Failed to store memory"
+    pub fn context(mut self, context: &str) -> Self {
+        let message = match &mut self {
+            Self::RelayUnavailable { message }
+            | Self::Timeout { message }
+            | Self::InvalidParams { message, .. }
+            | Self::BackendMissing { message, .. }
+            | Self::RateLimited { message }
+            | Self::Internal { message } => message,
+        };
+        *message = format!("{}: {}", context, message);
+        self
+    }
+
+    /// Whether the same call may succeed later without changes
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::RelayUnavailable { .. } | Self::Timeout { .. } | Self::RateLimited { .. }
+        )
+    }
+
+    /// The JSON part of a tool error result
+    pub fn payload(&self) -> serde_json::Value {
+        let mut payload = serde_json::to_value(self).unwrap_or_default();
+        if let Some(fields) = payload.as_object_mut() {
+            fields.insert("retryable".to_string(), self.retryable().into());
+        }
+        payload
+    }
+
+    /// A tool error result with `text` for humans and the JSON payload for agents
+    pub fn to_result(&self, text: impl Into<String>) -> CallToolResult {
+        CallToolResult::error(vec![
+            Content::text(text.into()),
+            Content::text(self.payload().to_string()),
+        ])
+    }
+
+    /// The code of a tool error result built by `to_result`, if it is one
+    pub fn code_of(result: &CallToolResult) -> Option<String> {
+        if result.is_error != Some(true) {
+            return None;
+        }
+        result.content.iter().find_map(|content| {
+            let payload: serde_json::Value = serde_json::from_str(&content.as_text()?.text).ok()?;
+            payload_code(&payload)
+        })
+    }
+
+    /// The code of a protocol error built from an `NparrotError`, if it is one
+    pub fn code_of_error(error: &RmcpError) -> Option<String> {
+        error.data.as_ref().and_then(payload_code)
+    }
+}
+
+impl std::fmt::Display for NparrotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for NparrotError {}
+
+/// For the many helpers that still report plain strings
+impl From<NparrotError> for String {
+    fn from(error: NparrotError) -> Self {
+        error.to_string()
+    }
+}
+
+impl From<NparrotError> for RmcpError {
+    fn from(error: NparrotError) -> Self {
+        let payload = Some(error.payload());
+        match error {
+            NparrotError::InvalidParams { message, .. } => {
+                RmcpError::invalid_params(message, payload)
+            }
+            error => RmcpError::internal_error(error.to_string(), payload),
+        }
+    }
+}
+
+impl From<NparrotError> for CallToolResult {
+    fn from(error: NparrotError) -> Self {
+        error.to_result(format!("❌ {}", error))
+    }
+}
+
+fn payload_code(payload: &serde_json::Value) -> Option<String> {
+    payload.get("retryable")?;
+    Some(payload.get("code")?.as_str()?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_result_carries_code_and_retryable() {
+        let error = NparrotError::invalid_params("id", "Note not found");
+        assert_eq!(
+            error.payload(),
+            serde_json::json!({
+                "code": "invalid_params",
+                "field": "id",
+                "message": "Note not found",
+                "retryable": false,
+            })
+        );
+        let result = error.to_result("Failed to delete note: Note not found");
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            result.content[0].as_text().unwrap().text,
+            "Failed to delete note: Note not found"
+        );
+        assert_eq!(
+            NparrotError::code_of(&result).as_deref(),
+            Some("invalid_params")
+        );
+
+        let outage = CallToolResult::from(NparrotError::relay_unavailable("no relay"));
+        assert_eq!(
+            NparrotError::code_of(&outage).as_deref(),
+            Some("relay_unavailable")
+        );
+        let outage = NparrotError::relay_unavailable("no relay").context("Failed to store memory");
+        assert!(outage.retryable());
+        assert_eq!(outage.to_string(), "Failed to store memory: no relay");
+        let protocol_error = RmcpError::from(NparrotError::timeout("slow"));
+        assert_eq!(protocol_error.message, "slow");
+        assert_eq!(
+            NparrotError::code_of_error(&protocol_error).as_deref(),
+            Some("timeout")
+        );
+        // Plain error results have no code
+        assert_eq!(
+            NparrotError::code_of(&CallToolResult::error(vec![Content::text("{}")])),
+            None
+        );
+    }
+}
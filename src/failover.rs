@@ -0,0 +1,242 @@
+//! Minimum connected relays and the warm-standby fallback list
+//!
+//! `client.connect()` returns whether or not any relay answered, so after connecting we wait up
+//! to `CONNECT_TIMEOUT` for `NPARROT_MIN_RELAYS` of them. One-shot commands fail right away below
+//! that threshold; long-running servers keep going in degraded mode while `watch` connects the
+//! `RELAY_FALLBACK` relays, and drops them again once enough primaries are back.
+
+use crate::envelope::{self, MessageType};
+use crate::utils::send_private_msg;
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+/// How long startup waits for the minimum number of relays
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Healthy,
+    /// Enough relays only thanks to the standbys
+    OnStandby,
+    /// Fewer relays than required, standbys included
+    Degraded,
+}
+
+#[derive(Debug, Clone)]
+pub struct Failover {
+    primaries: Vec<RelayUrl>,
+    standbys: Vec<RelayUrl>,
+    min_relays: usize,
+}
+
+/// Connected relays among the primaries and the standbys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connected {
+    pub primaries: usize,
+    pub standbys: usize,
+}
+
+impl Failover {
+    pub fn new(primaries: &[String], standbys: &[String], min_relays: usize) -> Self {
+        let parse = |urls: &[String]| {
+            urls.iter()
+                .filter_map(|url| RelayUrl::parse(url).ok())
+                .collect::<Vec<_>>()
+        };
+        Self {
+            primaries: parse(primaries),
+            standbys: parse(standbys),
+            min_relays,
+        }
+    }
+
+    pub fn health(&self, connected: Connected) -> Health {
+        if connected.primaries >= self.min_relays {
+            Health::Healthy
+        } else if connected.primaries + connected.standbys >= self.min_relays {
+            Health::OnStandby
+        } else {
+            Health::Degraded
+        }
+    }
+
+    pub async fn connected(&self, client: &Client) -> Connected {
+        let relays = client.relays().await;
+        let count =
+            |urls: &[RelayUrl]| urls.iter().filter(|url| is_connected(&relays, url)).count();
+        Connected {
+            primaries: count(&self.primaries),
+            standbys: count(&self.standbys),
+        }
+    }
+
+    /// Waits up to `timeout` for enough relays, bringing in the standbys if the primaries alone
+    /// don't get there
+    pub async fn establish(&self, client: &Client, timeout: Duration) -> (Health, Connected) {
+        let deadline = Instant::now() + timeout;
+        let mut standbys_active = false;
+        loop {
+            let connected = self.connected(client).await;
+            let health = self.health(connected);
+            if health != Health::Degraded || Instant::now() >= deadline {
+                return (health, connected);
+            }
+            // Give the primaries half the time before falling back
+            if !standbys_active && Instant::now() + timeout / 2 >= deadline {
+                standbys_active = self.activate_standbys(client).await;
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Keeps the standbys connected exactly while the primaries are below the threshold,
+    /// logging (and DMing through `notifier`) each change of health once
+    pub async fn watch(
+        self,
+        label: String,
+        client: Client,
+        notifier: Option<(Client, PublicKey, Option<u64>)>,
+    ) {
+        // Startup already waited for the relays, so a first check that isn't healthy is news
+        let mut last = Health::Healthy;
+        loop {
+            let connected = self.connected(&client).await;
+            let standbys_active = self.standbys_in_pool(&client).await;
+            if connected.primaries < self.min_relays && !standbys_active {
+                self.activate_standbys(&client).await;
+            } else if connected.primaries >= self.min_relays && standbys_active {
+                self.deactivate_standbys(&client).await;
+            }
+
+            let health = self.health(connected);
+            if health == last {
+                sleep(CHECK_INTERVAL).await;
+                continue;
+            }
+            let notice = self.describe(&label, health, connected);
+            match health {
+                Health::Healthy => log::info!("{}", notice),
+                _ => log::warn!("{}", notice),
+            }
+            if let Some((notifier, target, expire_after_secs)) = &notifier {
+                // Only the transitions into and out of degraded mode are worth a DM
+                if health == Health::Degraded || last == Health::Degraded {
+                    let notice = envelope::wrap(MessageType::Progress, format!("📡 {}", notice));
+                    if let Err(e) =
+                        send_private_msg(notifier, *target, notice, *expire_after_secs).await
+                    {
+                        log::debug!("Could not report relay health: {}", e);
+                    }
+                }
+            }
+            last = health;
+            sleep(CHECK_INTERVAL).await;
+        }
+    }
+
+    pub fn describe(&self, label: &str, health: Health, connected: Connected) -> String {
+        match health {
+            Health::Healthy => format!(
+                "{}: {} of {} relays connected",
+                label,
+                connected.primaries,
+                self.primaries.len()
+            ),
+            Health::OnStandby => format!(
+                "{}: only {} primary relay(s) connected, using {} standby relay(s)",
+                label, connected.primaries, connected.standbys
+            ),
+            Health::Degraded => format!(
+                "{}: degraded, {} relay(s) connected but {} required",
+                label,
+                connected.primaries + connected.standbys,
+                self.min_relays
+            ),
+        }
+    }
+
+    async fn standbys_in_pool(&self, client: &Client) -> bool {
+        let relays = client.relays().await;
+        self.standbys.iter().any(|url| relays.contains_key(url))
+    }
+
+    /// Returns whether there were any standbys to connect
+    async fn activate_standbys(&self, client: &Client) -> bool {
+        if self.standbys.is_empty() {
+            return false;
+        }
+        log::warn!(
+            "Fewer than {} primary relay(s) connected, connecting {} standby relay(s)",
+            self.min_relays,
+            self.standbys.len()
+        );
+        for url in &self.standbys {
+            if let Err(e) = client.add_relay(url).await {
+                log::warn!("Could not add standby relay {}: {}", url, e);
+                continue;
+            }
+            if let Err(e) = client.connect_relay(url).await {
+                log::warn!("Could not connect standby relay {}: {}", url, e);
+            }
+        }
+        true
+    }
+
+    async fn deactivate_standbys(&self, client: &Client) {
+        log::info!("Primary relays recovered, disconnecting the standby relays");
+        for url in &self.standbys {
+            // Standbys also listed as primaries stay
+            if self.primaries.contains(url) {
+                continue;
+            }
+            if let Err(e) = client.force_remove_relay(url).await {
+                log::debug!("Could not remove standby relay {}: {}", url, e);
+            }
+        }
+    }
+}
+
+fn is_connected(relays: &HashMap<RelayUrl, Relay>, url: &RelayUrl) -> bool {
+    relays
+        .get(url)
+        .is_some_and(|relay| relay.status() == RelayStatus::Connected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_thresholds() {
+        let failover = Failover::new(
+            &["wss://a.example".to_string(), "wss://b.example".to_string()],
+            &["wss://standby.example".to_string()],
+            2,
+        );
+        let connected = |primaries, standbys| Connected {
+            primaries,
+            standbys,
+        };
+        assert_eq!(failover.health(connected(2, 0)), Health::Healthy);
+        assert_eq!(failover.health(connected(1, 1)), Health::OnStandby);
+        assert_eq!(failover.health(connected(1, 0)), Health::Degraded);
+        assert!(failover
+            .describe("main", Health::Degraded, connected(1, 0))
+            .contains("1 relay(s) connected but 2 required"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_establish_reports_unreachable_relays() {
+        let failover = Failover::new(&["wss://a.example".to_string()], &[], 1);
+        // Never added to the client, so never connected
+        let (health, connected) = failover
+            .establish(&Client::default(), Duration::from_secs(1))
+            .await;
+        assert_eq!(health, Health::Degraded);
+        assert_eq!(connected.primaries, 0);
+    }
+}
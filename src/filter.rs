@@ -0,0 +1,69 @@
+//! `--filter` for `listen` and `onmessage`: only messages matching a regex get printed or run
+
+use regex::Regex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug)]
+pub struct MessageFilter {
+    pattern: Regex,
+    invert: bool,
+    skipped: AtomicUsize,
+}
+
+impl MessageFilter {
+    pub fn new(pattern: Regex, invert: bool) -> Self {
+        Self {
+            pattern,
+            invert,
+            skipped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether `content` should be handled; anything rejected is counted as skipped
+    pub fn accepts(&self, content: &str) -> bool {
+        if self.pattern.is_match(content) != self.invert {
+            return true;
+        }
+        let skipped = self.skipped.fetch_add(1, Ordering::Relaxed) + 1;
+        log::info!(
+            "Skipping message not matching filter '{}' ({} skipped so far)",
+            self.pattern,
+            skipped
+        );
+        false
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.skipped.load(Ordering::Relaxed)
+    }
+}
+
+/// Compiles a `--filter` pattern, failing with the position of the problem
+pub fn parse_pattern(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_and_skip_count() {
+        let filter = MessageFilter::new(parse_pattern(r"^!cmd\b").unwrap(), false);
+        assert!(filter.accepts("!cmd deploy"));
+        assert!(!filter.accepts("good morning"));
+        assert!(!filter.accepts("run !cmd later"));
+        assert_eq!(filter.skipped(), 2);
+
+        let inverted = MessageFilter::new(parse_pattern(r"^!cmd\b").unwrap(), true);
+        assert!(!inverted.accepts("!cmd deploy"));
+        assert!(inverted.accepts("good morning"));
+        assert_eq!(inverted.skipped(), 1);
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let err = parse_pattern("(unclosed").unwrap_err();
+        assert!(err.contains("unclosed group"), "{}", err);
+    }
+}
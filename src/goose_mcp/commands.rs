@@ -1,25 +1,150 @@
+use crate::dry_run;
+use crate::error::NparrotError;
+use crate::goose_mcp::guard::SessionGuard;
 use crate::goose_mcp::types::*;
+use crate::interrupt::Interrupt;
+use crate::metrics;
+use crate::process_management::ProcessManager;
+use crate::retry;
 use log;
-use std::collections::HashMap;
+use std::ffi::OsString;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
-use tokio::time::timeout;
 
-// Global execution tracking to prevent duplicate commands
 lazy_static::lazy_static! {
-    static ref EXECUTION_TRACKER: Arc<Mutex<HashMap<String, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
-    static ref ACTIVE_SESSIONS: Arc<Mutex<HashMap<String, bool>>> = Arc::new(Mutex::new(HashMap::new()));
+    static ref GOOSE_BINARY: std::sync::RwLock<String> = std::sync::RwLock::new("goose".to_string());
+    static ref COMMAND_TIMEOUT: std::sync::RwLock<Duration> = std::sync::RwLock::new(DEFAULT_COMMAND_TIMEOUT);
+}
+
+/// How long a Goose command may run unless configured otherwise
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Sets how long a Goose command may run before it is terminated (from `--goose-timeout`)
+pub fn set_command_timeout(timeout: Duration) {
+    if let Ok(mut guard) = COMMAND_TIMEOUT.write() {
+        *guard = timeout;
+    }
+}
+
+fn command_timeout() -> Duration {
+    COMMAND_TIMEOUT
+        .read()
+        .map(|guard| *guard)
+        .unwrap_or(DEFAULT_COMMAND_TIMEOUT)
+}
+
+/// Sets the Goose executable used for all commands (from `--goose-bin` / config)
+pub fn set_goose_binary(binary: &str) {
+    if let Ok(mut guard) = GOOSE_BINARY.write() {
+        *guard = binary.to_string();
+    }
+}
+
+/// The Goose executable to run
+pub fn goose_binary() -> String {
+    GOOSE_BINARY
+        .read()
+        .map(|guard| guard.clone())
+        .unwrap_or_else(|_| "goose".to_string())
+}
+
+/// `cmd` as a shell-like command line, arguments with whitespace quoted
+fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                // Backslashes are left alone, so Windows paths stay readable
+                format!("\"{}\"", arg.replace('"', "\\\""))
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A saved session as `goose session list --format json` describes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SessionSummary {
+    id: String,
+    description: String,
+}
+
+/// The sessions in `goose session list --format json` output; `None` if it isn't that
+fn parse_session_list(json: &str) -> Option<Vec<SessionSummary>> {
+    let sessions: Vec<serde_json::Value> = serde_json::from_str(json.trim()).ok()?;
+    sessions
+        .iter()
+        .map(|session| {
+            let description = session
+                .pointer("/metadata/description")
+                .or_else(|| session.get("description"))
+                .and_then(|description| description.as_str())
+                .unwrap_or_default();
+            Some(SessionSummary {
+                id: session.get("id")?.as_str()?.to_string(),
+                description: description.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The sessions `goose session remove` removes for `request`: the one with the id or name
+/// (Goose names sessions by their id), or those whose id matches the regex
+fn selected_sessions<'a>(
+    request: &SessionRemoveRequest,
+    sessions: &'a [SessionSummary],
+) -> Vec<&'a SessionSummary> {
+    let regex = request
+        .regex
+        .as_deref()
+        .and_then(|regex| regex::Regex::new(regex).ok());
+    sessions
+        .iter()
+        .filter(|session| match (&request.id, &request.name, &regex) {
+            (Some(id), _, _) | (None, Some(id), _) => session.id == *id,
+            (None, None, Some(regex)) => regex.is_match(&session.id),
+            (None, None, None) => false,
+        })
+        .collect()
 }
 
 pub struct GooseCommands;
 
 impl GooseCommands {
     pub async fn run_task(request: RunTaskRequest) -> CommandResult {
-        // Create unique execution key for deduplication
-        let execution_key = format!(
+        let started = Instant::now();
+        let execution_key = Self::execution_key(&request);
+        let result = match Interrupt::global()
+            .interruptible(Self::run_task_inner(request, execution_key.clone()))
+            .await
+        {
+            Some(result) => result,
+            None => {
+                // Dropping the run leaves Goose running, and skipped the tracker cleanup
+                let terminated = ProcessManager::global()
+                    .terminate_label("goose", Duration::from_secs(5))
+                    .await;
+                log::info!(
+                    "Task interrupted by the user, terminated {} process(es)",
+                    terminated
+                );
+                SessionGuard::global().end_task(&execution_key);
+                CommandResult::interrupted()
+            }
+        };
+        metrics::goose_task("run", started.elapsed(), result.success);
+        result
+    }
+
+    /// Unique execution key for deduplication
+    fn execution_key(request: &RunTaskRequest) -> String {
+        format!(
             "runtask_{}",
             request
                 .instructions
@@ -27,87 +152,135 @@ impl GooseCommands {
                 .take(50)
                 .collect::<String>()
                 .replace(" ", "_")
-        );
+        )
+    }
 
+    async fn run_task_inner(request: RunTaskRequest, execution_key: String) -> CommandResult {
         // Check if this exact command is already being executed
-        if let Ok(mut tracker) = EXECUTION_TRACKER.lock() {
-            if let Some(last_execution) = tracker.get(&execution_key) {
-                if last_execution.elapsed() < Duration::from_secs(10) {
-                    return CommandResult::error(
-                        "Same task is already being executed. Please wait.".to_string(),
-                        -1,
-                    );
-                }
-            }
-            tracker.insert(execution_key.clone(), Instant::now());
+        if let Err(e) = SessionGuard::global().begin_task(&execution_key) {
+            return CommandResult::failed(e, -1);
         }
 
-        let mut cmd = Command::new("goose");
-        cmd.arg("run");
-
-        if let Some(file_path) = &request.instruction_file {
-            cmd.arg("-i").arg(file_path);
-        } else {
-            if request.instructions.trim().is_empty() {
-                // Clean up tracker
-                if let Ok(mut tracker) = EXECUTION_TRACKER.lock() {
-                    tracker.remove(&execution_key);
-                }
-                return CommandResult::error("Instructions cannot be empty".to_string(), 1);
-            }
-
-            match Self::create_temp_file(&request.instructions) {
-                Ok(temp_file) => {
-                    cmd.arg("-i").arg(temp_file.path());
-                    let result = Self::execute_command_with_cleanup(cmd, execution_key).await;
-                    return result;
-                }
-                Err(e) => {
+        // Inlined instructions go through a temp file, which must outlive the command
+        let mut temp_file = None;
+        let instruction_path = match &request.instruction_file {
+            Some(file_path) => PathBuf::from(file_path),
+            None => {
+                if request.instructions.trim().is_empty() {
                     // Clean up tracker
-                    if let Ok(mut tracker) = EXECUTION_TRACKER.lock() {
-                        tracker.remove(&execution_key);
+                    SessionGuard::global().end_task(&execution_key);
+                    return CommandResult::failed(
+                        NparrotError::invalid_params(
+                            "instructions",
+                            "Instructions cannot be empty",
+                        ),
+                        1,
+                    );
+                }
+                match Self::create_temp_file(&request.instructions) {
+                    Ok(file) => temp_file.insert(file).path().to_path_buf(),
+                    Err(e) => {
+                        // Clean up tracker
+                        SessionGuard::global().end_task(&execution_key);
+                        return CommandResult::error(
+                            format!("Failed to create temp file: {}", e),
+                            1,
+                        );
                     }
-                    return CommandResult::error(format!("Failed to create temp file: {}", e), 1);
                 }
             }
-        }
+        };
+
+        let mut cmd = Command::new(goose_binary());
+        cmd.args(Self::run_task_args(&request, &instruction_path));
+        let result = Self::execute_command_with_cleanup(cmd, execution_key).await;
+        drop(temp_file);
+        result
+    }
 
+    /// The `goose run` arguments for `request`, reading the instructions from `instruction_path`
+    fn run_task_args(request: &RunTaskRequest, instruction_path: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["run".into(), "-i".into(), instruction_path.into()];
         if let Some(max_turns) = request.max_turns {
-            cmd.arg("--max-turns").arg(max_turns.to_string());
+            args.push("--max-turns".into());
+            args.push(max_turns.to_string().into());
         }
-
         if request.debug.unwrap_or(false) {
-            cmd.arg("--debug");
+            args.push("--debug".into());
         }
-
-        Self::execute_command_with_cleanup(cmd, execution_key).await
+        args
     }
 
     pub async fn start_session(request: SessionRequest) -> CommandResult {
-        let session_id = request
-            .id
-            .clone()
-            .unwrap_or_else(|| format!("session_{}", chrono::Utc::now().timestamp()));
+        let started = Instant::now();
+        let result = Self::start_session_inner(request).await;
+        metrics::goose_task("session", started.elapsed(), result.success);
+        result
+    }
+
+    async fn start_session_inner(request: SessionRequest) -> CommandResult {
+        let (cmd, session) = match Self::session_command(&request) {
+            Ok(built) => built,
+            Err(e) => return CommandResult::failed(e, 1),
+        };
+        if request.resume.unwrap_or(false) && !Self::session_exists(&session).await {
+            return CommandResult::failed(
+                NparrotError::invalid_params(
+                    "id",
+                    format!(
+                        "No Goose session {} to resume; listsessions shows the saved ones",
+                        session
+                    ),
+                ),
+                1,
+            );
+        }
 
         // Check if session is already active
-        if let Ok(mut sessions) = ACTIVE_SESSIONS.lock() {
-            if sessions.get(&session_id).unwrap_or(&false) == &true {
-                return CommandResult::error(
-                    format!("Session {} is already active", session_id),
-                    -1,
-                );
-            }
-            sessions.insert(session_id.clone(), true);
+        if let Err(e) = SessionGuard::global().begin_session(&session) {
+            return CommandResult::failed(e, -1);
         }
 
-        let mut cmd = Command::new("goose");
+        let result = Self::execute_command(cmd).await;
+
+        // Mark session as inactive after completion
+        SessionGuard::global().end_session(&session);
+
+        result.with_session(session)
+    }
+
+    /// The `goose session` command for `request`, with the session it starts or resumes: the
+    /// name or id given, or a new name for a new session so it can be resumed later
+    fn session_command(request: &SessionRequest) -> Result<(Command, String), NparrotError> {
+        let resume = request.resume.unwrap_or(false);
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("session");
 
-        if let Some(name) = &request.name {
-            cmd.arg("--name").arg(name);
-        }
+        let session = match (&request.name, &request.id) {
+            (Some(name), _) => {
+                cmd.arg("--name").arg(name);
+                name.clone()
+            }
+            (None, Some(id)) if resume => id.clone(),
+            // Goose names sessions by their id
+            (None, Some(id)) => {
+                cmd.arg("--name").arg(id);
+                id.clone()
+            }
+            (None, None) if resume => {
+                return Err(NparrotError::invalid_params(
+                    "id",
+                    "Resuming needs the id of the session",
+                ))
+            }
+            (None, None) => {
+                let name = format!("session_{}", chrono::Utc::now().timestamp());
+                cmd.arg("--name").arg(&name);
+                name
+            }
+        };
 
-        if request.resume.unwrap_or(false) {
+        if resume {
             cmd.arg("--resume");
             if let Some(id) = &request.id {
                 cmd.arg("--id").arg(id);
@@ -130,18 +303,23 @@ impl GooseCommands {
             cmd.arg("--max-turns").arg(max_turns.to_string());
         }
 
-        let result = Self::execute_command(cmd).await;
+        Ok((cmd, session))
+    }
 
-        // Mark session as inactive after completion
-        if let Ok(mut sessions) = ACTIVE_SESSIONS.lock() {
-            sessions.insert(session_id, false);
+    /// Whether `session` exists: one started through us, or one `goose session list` shows
+    async fn session_exists(session: &str) -> bool {
+        if SessionGuard::global().knows(session) {
+            return true;
         }
-
-        result
+        let mut list = Command::new(goose_binary());
+        list.args(["session", "list", "--format", "json"]);
+        let listed = Self::execute_command(list).await;
+        parse_session_list(&listed.stdout)
+            .is_some_and(|sessions| sessions.iter().any(|listed| listed.id == session))
     }
 
     pub async fn list_sessions(request: SessionListRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("session").arg("list");
 
         if request.verbose.unwrap_or(false) {
@@ -159,42 +337,88 @@ impl GooseCommands {
         Self::execute_command(cmd).await
     }
 
-    pub async fn remove_session(request: SessionRemoveRequest) -> CommandResult {
+    /// Removes the sessions `request` selects, or with `dry_run` lists them and the command
+    /// that would remove them
+    pub async fn remove_session(request: SessionRemoveRequest, dry_run: bool) -> CommandResult {
+        let cmd = match Self::remove_session_command(&request) {
+            Ok(cmd) => cmd,
+            Err(e) => return CommandResult::failed(e, 1),
+        };
+        if dry_run {
+            return Self::preview_remove_session(&request, &cmd).await;
+        }
+
         let session_key = request
             .id
             .clone()
             .or_else(|| request.name.clone())
             .unwrap_or_else(|| "unknown".to_string());
+        if let Some(id) = &request.id {
+            // Force terminate the session if it's active
+            SessionGuard::global().end_session(id);
+        }
 
-        let mut cmd = Command::new("goose");
+        let result = Self::execute_command(cmd).await;
+
+        // Ensure session is marked as terminated
+        SessionGuard::global().end_session(&session_key);
+
+        result
+    }
+
+    /// The `goose session remove` command for `request`, the same for real and dry runs
+    fn remove_session_command(request: &SessionRemoveRequest) -> Result<Command, NparrotError> {
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("session").arg("remove");
 
         if let Some(id) = &request.id {
             cmd.arg("-i").arg(id);
-            // Force terminate the session if it's active
-            if let Ok(mut sessions) = ACTIVE_SESSIONS.lock() {
-                sessions.insert(id.clone(), false);
-            }
         } else if let Some(name) = &request.name {
             cmd.arg("-n").arg(name);
         } else if let Some(regex) = &request.regex {
             cmd.arg("-r").arg(regex);
         } else {
-            return CommandResult::error("Must specify id, name, or regex pattern".to_string(), 1);
+            return Err(NparrotError::invalid_params(
+                "id",
+                "Must specify id, name, or regex pattern",
+            ));
         }
+        Ok(cmd)
+    }
 
-        let result = Self::execute_command(cmd).await;
-
-        // Ensure session is marked as terminated
-        if let Ok(mut sessions) = ACTIVE_SESSIONS.lock() {
-            sessions.insert(session_key, false);
+    async fn preview_remove_session(
+        request: &SessionRemoveRequest,
+        cmd: &Command,
+    ) -> CommandResult {
+        let mut list = Command::new(goose_binary());
+        list.args(["session", "list", "--format", "json"]);
+        let listed = Self::execute_command(list).await;
+        let sessions = match parse_session_list(&listed.stdout) {
+            Some(sessions) if listed.success => sessions,
+            _ => {
+                return CommandResult::success(dry_run::label(&format!(
+                "would run `{}`; the saved sessions could not be listed to show which match: {}",
+                command_line(cmd),
+                listed
+                    .error
+                    .unwrap_or_else(|| "unexpected output".to_string())
+            )))
+            }
+        };
+        let selected = selected_sessions(request, &sessions);
+        let mut report = format!(
+            "would run `{}`, removing {} session(s)",
+            command_line(cmd),
+            selected.len()
+        );
+        for session in selected {
+            report.push_str(&format!("\n- {}: {}", session.id, session.description));
         }
-
-        result
+        CommandResult::success(dry_run::label(&report))
     }
 
     pub async fn export_session(request: SessionExportRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("session").arg("export");
 
         if let Some(id) = &request.id {
@@ -213,7 +437,7 @@ impl GooseCommands {
     }
 
     pub async fn configure(request: ConfigureRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("configure");
 
         if request.reconfigure.unwrap_or(false) {
@@ -224,7 +448,7 @@ impl GooseCommands {
     }
 
     pub async fn update(request: UpdateRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("update");
 
         if request.canary.unwrap_or(false) {
@@ -239,7 +463,7 @@ impl GooseCommands {
     }
 
     pub async fn info(request: InfoRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("info");
 
         if request.verbose.unwrap_or(false) {
@@ -250,7 +474,7 @@ impl GooseCommands {
     }
 
     pub async fn version() -> CommandResult {
-        let cmd = Command::new("goose");
+        let cmd = Command::new(goose_binary());
         let mut cmd = cmd;
         cmd.arg("--version");
 
@@ -258,7 +482,7 @@ impl GooseCommands {
     }
 
     pub async fn help() -> CommandResult {
-        let cmd = Command::new("goose");
+        let cmd = Command::new(goose_binary());
         let mut cmd = cmd;
         cmd.arg("--help");
 
@@ -266,7 +490,7 @@ impl GooseCommands {
     }
 
     pub async fn mcp_list(request: McpListRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("mcp").arg("list");
 
         if request.available.unwrap_or(false) {
@@ -281,7 +505,7 @@ impl GooseCommands {
     }
 
     pub async fn mcp_install(request: McpInstallRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("mcp").arg("install").arg(&request.server);
 
         if request.force.unwrap_or(false) {
@@ -292,7 +516,7 @@ impl GooseCommands {
     }
 
     pub async fn project_management(request: ProjectRequest) -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
 
         if request.new.unwrap_or(false) {
             cmd.arg("projects");
@@ -308,7 +532,7 @@ impl GooseCommands {
     }
 
     pub async fn list_projects() -> CommandResult {
-        let mut cmd = Command::new("goose");
+        let mut cmd = Command::new(goose_binary());
         cmd.arg("projects");
 
         Self::execute_command(cmd).await
@@ -318,49 +542,23 @@ impl GooseCommands {
     pub async fn kill_all_sessions() -> CommandResult {
         log::info!("Killing all active Goose sessions...");
 
-        // Mark all sessions as inactive
-        if let Ok(mut sessions) = ACTIVE_SESSIONS.lock() {
-            for (_, active) in sessions.iter_mut() {
-                *active = false;
-            }
-            sessions.clear();
-        }
+        // Forget all sessions and running tasks
+        SessionGuard::global().clear();
 
-        // Clear execution tracker
-        if let Ok(mut tracker) = EXECUTION_TRACKER.lock() {
-            tracker.clear();
-        }
-
-        // Force kill any goose processes
-        let kill_result = tokio::process::Command::new("pkill")
-            .arg("-f")
-            .arg("goose")
-            .output()
+        // Terminate the Goose process groups we started
+        let terminated = ProcessManager::global()
+            .terminate_label("goose", Duration::from_secs(5))
             .await;
 
-        match kill_result {
-            Ok(output) => {
-                if output.status.success() {
-                    CommandResult::success("All Goose sessions terminated".to_string())
-                } else {
-                    CommandResult::success(
-                        "Session cleanup completed (no active processes found)".to_string(),
-                    )
-                }
-            }
-            Err(e) => {
-                log::warn!("Failed to kill processes: {}", e);
-                CommandResult::success("Session state cleared (process kill failed)".to_string())
-            }
-        }
-    }
-
-    // New method to check if any sessions are active
-    pub fn has_active_sessions() -> bool {
-        if let Ok(sessions) = ACTIVE_SESSIONS.lock() {
-            sessions.values().any(|&active| active)
+        if terminated > 0 {
+            CommandResult::success(format!(
+                "All Goose sessions terminated ({} process(es))",
+                terminated
+            ))
         } else {
-            false
+            CommandResult::success(
+                "Session cleanup completed (no active processes found)".to_string(),
+            )
         }
     }
 
@@ -368,17 +566,26 @@ impl GooseCommands {
         let result = Self::execute_command(cmd).await;
 
         // Clean up execution tracker regardless of success/failure
-        if let Ok(mut tracker) = EXECUTION_TRACKER.lock() {
-            tracker.remove(&execution_key);
-        }
+        SessionGuard::global().end_task(&execution_key);
 
         result
     }
 
     async fn execute_command(cmd: Command) -> CommandResult {
-        const MAX_RETRIES: u32 = 3;
-        const COMMAND_TIMEOUT: Duration = Duration::from_secs(300); // 5 minutes
-        const RETRY_DELAY: Duration = Duration::from_secs(5);
+        let started = Instant::now();
+        let argv = command_line(&cmd);
+        let result = Self::execute_with_retries(cmd).await;
+        log::debug!(
+            "{} finished in {} ms (exit code {})",
+            argv,
+            started.elapsed().as_millis(),
+            result.exit_code
+        );
+        result.with_run(argv, started.elapsed())
+    }
+
+    async fn execute_with_retries(cmd: Command) -> CommandResult {
+        let timeout = command_timeout();
 
         let program = cmd.get_program().to_os_string();
         let args: Vec<_> = cmd.get_args().map(|s| s.to_os_string()).collect();
@@ -389,101 +596,75 @@ impl GooseCommands {
 
         log::debug!("Executing command: {:?} with args: {:?}", program, args);
 
-        for attempt in 1..=MAX_RETRIES {
-            log::debug!("Command attempt {} of {}", attempt, MAX_RETRIES);
-
-            let cmd_future = tokio::task::spawn_blocking({
-                let program = program.clone();
-                let args = args.clone();
-                let envs = envs.clone();
-
-                move || {
-                    let mut cmd = Command::new(program);
-                    cmd.args(args);
-                    cmd.envs(envs);
-                    cmd.output()
-                }
-            });
+        let attempt = |attempt: u32| {
+            let (program, args, envs) = (&program, &args, &envs);
+            async move {
+                log::debug!("Command attempt {}", attempt);
 
-            match timeout(COMMAND_TIMEOUT, cmd_future).await {
-                Ok(Ok(Ok(output))) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    let exit_code = output.status.code().unwrap_or(-1);
+                let mut cmd = Command::new(program);
+                cmd.args(args);
+                cmd.envs(envs.clone());
 
-                    if output.status.success() {
-                        log::debug!("Command succeeded on attempt {}", attempt);
+                match ProcessManager::global().output(cmd, "goose", timeout).await {
+                    Ok(Some(output)) => {
+                        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+                        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+                        let exit_code = output.status.code().unwrap_or(-1);
 
-                        // Add session completion marker to output
-                        let enhanced_output = format!(
-                            "{}\n🔚 EXECUTION COMPLETED - SESSION READY FOR TERMINATION",
-                            stdout
-                        );
-                        return CommandResult::success(enhanced_output);
-                    } else {
-                        let error_msg = if stderr.is_empty() { stdout } else { stderr };
-
-                        // Check for specific errors that indicate hanging or timeout
-                        if Self::is_recoverable_error(&error_msg, exit_code)
-                            && attempt < MAX_RETRIES
-                        {
-                            log::warn!(
-                                "Recoverable error on attempt {}: {} (exit code: {})",
-                                attempt,
-                                error_msg,
-                                exit_code
-                            );
-                            log::info!("Retrying in {} seconds...", RETRY_DELAY.as_secs());
-                            tokio::time::sleep(RETRY_DELAY).await;
-                            continue;
+                        if output.status.success() {
+                            log::debug!("Command succeeded on attempt {}", attempt);
+                            return Ok(CommandResult::completed(stdout, stderr));
                         }
-
-                        return CommandResult::error(error_msg, exit_code);
-                    }
-                }
-                Ok(Ok(Err(e))) => {
-                    let error_msg = format!("Command execution failed: {}", e);
-                    log::error!("Attempt {} failed: {}", attempt, error_msg);
-
-                    if attempt < MAX_RETRIES {
-                        log::info!("Retrying in {} seconds...", RETRY_DELAY.as_secs());
-                        tokio::time::sleep(RETRY_DELAY).await;
-                        continue;
-                    }
-
-                    return CommandResult::error(error_msg, -1);
-                }
-                Ok(Err(e)) => {
-                    let error_msg = format!("Task execution failed: {}", e);
-                    log::error!("Attempt {} failed: {}", attempt, error_msg);
-
-                    if attempt < MAX_RETRIES {
-                        log::info!("Retrying in {} seconds...", RETRY_DELAY.as_secs());
-                        tokio::time::sleep(RETRY_DELAY).await;
-                        continue;
-                    }
-
-                    return CommandResult::error(error_msg, -1);
-                }
-                Err(_) => {
-                    let error_msg = format!(
-                        "Command timed out after {} seconds",
-                        COMMAND_TIMEOUT.as_secs()
-                    );
-                    log::error!("Attempt {} timed out", attempt);
-
-                    if attempt < MAX_RETRIES {
-                        log::info!("Retrying in {} seconds...", RETRY_DELAY.as_secs());
-                        tokio::time::sleep(RETRY_DELAY).await;
-                        continue;
+                        let error_msg = if stderr.is_empty() {
+                            stdout.clone()
+                        } else {
+                            stderr.clone()
+                        };
+                        Err(FailedAttempt {
+                            // Check for specific errors that indicate hanging or timeout
+                            retryable: Self::is_recoverable_error(&error_msg, exit_code),
+                            result: CommandResult::error(error_msg, exit_code)
+                                .with_streams(stdout, stderr),
+                        })
                     }
-
-                    return CommandResult::error(error_msg, -2);
+                    // Retrying won't install it
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(FailedAttempt {
+                        retryable: false,
+                        result: CommandResult::failed(
+                            NparrotError::backend_missing(
+                                "goose",
+                                format!("Goose binary '{}' not found", program.to_string_lossy()),
+                            ),
+                            -1,
+                        ),
+                    }),
+                    Err(e) => Err(FailedAttempt {
+                        retryable: true,
+                        result: CommandResult::error(
+                            format!("Command execution failed: {}", e),
+                            -1,
+                        ),
+                    }),
+                    Ok(None) => Err(FailedAttempt {
+                        retryable: true,
+                        result: CommandResult::failed(
+                            NparrotError::timeout(format!(
+                                "Command timed out after {} seconds",
+                                timeout.as_secs()
+                            )),
+                            -2,
+                        ),
+                    }),
                 }
             }
-        }
+        };
 
-        CommandResult::error(format!("Failed after {} attempts", MAX_RETRIES), -1)
+        match retry::policy(retry::GOOSE)
+            .run(retry::GOOSE, attempt, |failed| failed.retryable)
+            .await
+        {
+            Ok(result) | Err(FailedAttempt { result, .. }) => result,
+        }
     }
 
     fn is_recoverable_error(error_msg: &str, exit_code: i32) -> bool {
@@ -520,3 +701,199 @@ impl GooseCommands {
         Ok(temp_file)
     }
 }
+
+/// A Goose run that failed, with whether running it again may help
+struct FailedAttempt {
+    result: CommandResult,
+    retryable: bool,
+}
+
+impl std::fmt::Display for FailedAttempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let error = self.result.error.as_deref().unwrap_or_default();
+        write!(
+            f,
+            "exit code {}: {}",
+            self.result.exit_code,
+            error.lines().next().unwrap_or_default()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(
+        instruction_file: Option<&str>,
+        max_turns: Option<u32>,
+        debug: Option<bool>,
+    ) -> RunTaskRequest {
+        RunTaskRequest {
+            instructions: "fix the build".to_string(),
+            instruction_file: instruction_file.map(str::to_string),
+            max_turns,
+            debug,
+        }
+    }
+
+    fn args(request: &RunTaskRequest, path: &str) -> Vec<String> {
+        GooseCommands::run_task_args(request, Path::new(path))
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_dry_run_selects_sessions_like_goose_remove() {
+        let listed = r#"[
+            {"id": "20250101_1", "path": "/s/1.jsonl", "metadata": {"description": "Fix the build"}},
+            {"id": "20250101_2", "path": "/s/2.jsonl", "metadata": {"description": "Write docs"}},
+            {"id": "other", "path": "/s/3.jsonl", "metadata": {}}
+        ]"#;
+        let sessions = parse_session_list(listed).unwrap();
+        assert_eq!(sessions[0].description, "Fix the build");
+        assert!(parse_session_list("No sessions found").is_none());
+
+        let remove =
+            |id: Option<&str>, name: Option<&str>, regex: Option<&str>| SessionRemoveRequest {
+                id: id.map(str::to_string),
+                name: name.map(str::to_string),
+                regex: regex.map(str::to_string),
+                dry_run: Some(true),
+            };
+        let ids = |request: &SessionRemoveRequest| -> Vec<String> {
+            selected_sessions(request, &sessions)
+                .into_iter()
+                .map(|session| session.id.clone())
+                .collect()
+        };
+        assert_eq!(ids(&remove(Some("other"), None, None)), ["other"]);
+        assert_eq!(ids(&remove(None, Some("20250101_2"), None)), ["20250101_2"]);
+        assert_eq!(
+            ids(&remove(None, None, Some("^2025"))),
+            ["20250101_1", "20250101_2"]
+        );
+        assert!(ids(&remove(Some("missing"), None, Some("."))).is_empty());
+
+        let cmd =
+            GooseCommands::remove_session_command(&remove(None, None, Some("^2025"))).unwrap();
+        assert!(command_line(&cmd).ends_with("session remove -r ^2025"));
+        assert!(GooseCommands::remove_session_command(&remove(None, None, None)).is_err());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut cmd = Command::new("goose");
+        cmd.args(["run", "-i", "/tmp/my task.md", "--max-turns", "3"]);
+        assert_eq!(
+            command_line(&cmd),
+            "goose run -i \"/tmp/my task.md\" --max-turns 3"
+        );
+
+        let mut cmd = Command::new("goose");
+        cmd.args(["run", "-i", r"C:\Users\Jane Doe\AppData\Local\Temp\.tmpAbc"]);
+        assert_eq!(
+            command_line(&cmd),
+            r#"goose run -i "C:\Users\Jane Doe\AppData\Local\Temp\.tmpAbc""#
+        );
+    }
+
+    #[test]
+    fn test_session_command() {
+        let session = |name: Option<&str>, id: Option<&str>, resume: bool| {
+            let request = SessionRequest {
+                name: name.map(str::to_string),
+                id: id.map(str::to_string),
+                resume: Some(resume),
+                with_extension: None,
+                with_builtin: None,
+                debug: None,
+                max_turns: None,
+            };
+            GooseCommands::session_command(&request)
+                .map(|(cmd, session)| (command_line(&cmd), session))
+        };
+
+        // A new session gets a name it can be resumed by
+        let (line, name) = session(None, None, false).unwrap();
+        assert!(name.starts_with("session_"));
+        assert!(line.ends_with(&format!("session --name {}", name)));
+        assert_eq!(
+            session(None, Some("fix-login"), false).unwrap(),
+            (
+                "goose session --name fix-login".to_string(),
+                "fix-login".to_string()
+            )
+        );
+
+        assert_eq!(
+            session(None, Some("20250101_1"), true).unwrap(),
+            (
+                "goose session --resume --id 20250101_1".to_string(),
+                "20250101_1".to_string()
+            )
+        );
+        assert_eq!(
+            session(Some("fix-login"), None, true).unwrap().0,
+            "goose session --name fix-login --resume"
+        );
+        let missing = session(None, None, true).unwrap_err();
+        assert_eq!(missing.code(), "invalid_params");
+    }
+
+    #[tokio::test]
+    async fn test_resuming_an_unknown_session_is_refused() {
+        let request: SessionRequest = ResumeSessionRequest {
+            id: "never-started".to_string(),
+        }
+        .into();
+        let result = GooseCommands::start_session(request).await;
+        assert!(!result.success);
+        assert!(result
+            .error
+            .unwrap()
+            .contains("No Goose session never-started"));
+    }
+
+    #[test]
+    fn test_temp_instructions_path_is_one_argument() {
+        // Temp dirs like `C:\Users\Jane Doe\AppData\Local\Temp` have spaces (and backslashes)
+        let dir = tempfile::Builder::new()
+            .prefix("nparrot tasks ")
+            .tempdir()
+            .unwrap();
+        let file = NamedTempFile::new_in(dir.path()).unwrap();
+        let args = GooseCommands::run_task_args(&request(None, None, None), file.path());
+        // Passed to goose as is, no shell in between to split or unescape it
+        assert_eq!(args[2], file.path().as_os_str());
+
+        let windows = Path::new(r"C:\Users\Jane Doe\Temp\.tmpAbc");
+        let args = GooseCommands::run_task_args(&request(None, None, None), windows);
+        assert_eq!(args.len(), 3);
+        assert_eq!(args[2], windows.as_os_str());
+    }
+
+    #[test]
+    fn test_run_task_args() {
+        // Inlined instructions, read from their temp file
+        assert_eq!(
+            args(&request(None, None, None), "/tmp/.tmpAbc"),
+            ["run", "-i", "/tmp/.tmpAbc"]
+        );
+        assert_eq!(
+            args(&request(None, Some(7), Some(true)), "/tmp/.tmpAbc"),
+            ["run", "-i", "/tmp/.tmpAbc", "--max-turns", "7", "--debug"]
+        );
+
+        // An instruction file
+        assert_eq!(
+            args(&request(Some("task.md"), None, Some(false)), "task.md"),
+            ["run", "-i", "task.md"]
+        );
+        assert_eq!(
+            args(&request(Some("task.md"), Some(3), Some(true)), "task.md"),
+            ["run", "-i", "task.md", "--max-turns", "3", "--debug"]
+        );
+    }
+}
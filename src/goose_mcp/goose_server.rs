@@ -1,8 +1,12 @@
+use crate::dry_run;
+use crate::goose_mcp::guard::{self, SessionGuard};
 use crate::goose_mcp::{commands::GooseCommands, types::*};
+use crate::interrupt::{self, Interrupt};
+use crate::mcp::server_common::ServerInfoBuilder;
+use crate::process_management::stats::{format_history, format_stats, ProcessStatsRequest};
+use crate::process_management::{format_process_list, ProcessManager};
 use rmcp::{
-    model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
-    },
+    model::{CallToolResult, Content, ServerInfo},
     tool, Error as RmcpError, ServerHandler,
 };
 
@@ -22,12 +26,24 @@ impl GooseServer {
         &self,
         #[tool(aggr)] request: RunTaskRequest,
     ) -> Result<CallToolResult, RmcpError> {
+        // Same checks as the combined server; with no chat, the warning is the result
+        if let Err((warning, _)) = SessionGuard::global().preflight() {
+            return Ok(CallToolResult::error(vec![Content::text(warning)]));
+        }
+
+        log::info!("Starting Goose task execution...");
         let result = GooseCommands::run_task(request).await;
+        if result.is_interrupted() {
+            // Reported here, so the next tool call runs normally
+            Interrupt::global().take();
+            return Ok(interrupt::interrupted_result());
+        }
+        log::info!("{}", guard::task_report(&result));
         Self::convert_result(result)
     }
 
     #[tool(
-        description = "Start a new Goose session or resume an existing one with specified configuration."
+        description = "Start a new Goose session or resume an existing one with specified configuration. Returns the session id, for resumesession and removesession."
     )]
     async fn startsession(
         &self,
@@ -37,6 +53,16 @@ impl GooseServer {
         Self::convert_result(result)
     }
 
+    #[tool(
+        description = "Resume a saved Goose session by the id startsession returned or listsessions shows."
+    )]
+    async fn resumesession(
+        &self,
+        #[tool(aggr)] request: ResumeSessionRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.startsession(request.into()).await
+    }
+
     #[tool(description = "List all saved Goose sessions with optional filtering and formatting.")]
     async fn listsessions(
         &self,
@@ -46,12 +72,15 @@ impl GooseServer {
         Self::convert_result(result)
     }
 
-    #[tool(description = "Remove one or more Goose sessions by ID, name, or regex pattern.")]
+    #[tool(
+        description = "Remove one or more Goose sessions by ID, name, or regex pattern; dry_run only lists what would be removed."
+    )]
     async fn removesession(
         &self,
         #[tool(aggr)] request: SessionRemoveRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let result = GooseCommands::remove_session(request).await;
+        let dry_run = dry_run::requested(request.dry_run);
+        let result = GooseCommands::remove_session(request, dry_run).await;
         Self::convert_result(result)
     }
 
@@ -143,45 +172,58 @@ impl GooseServer {
 
     #[tool(description = "Force terminate all active Goose sessions and cleanup execution state.")]
     async fn killsessions(&self) -> Result<CallToolResult, RmcpError> {
+        log::info!("Terminating all active Goose sessions...");
         let result = GooseCommands::kill_all_sessions().await;
+        log::info!("{}", guard::kill_report(&result));
         Self::convert_result(result)
     }
 
     #[tool(description = "Check if any Goose sessions are currently active.")]
     async fn checksessions(&self) -> Result<CallToolResult, RmcpError> {
-        let has_active = GooseCommands::has_active_sessions();
-        let message = if has_active {
-            "Active Goose sessions detected".to_string()
-        } else {
-            "No active Goose sessions".to_string()
-        };
+        let (message, _) = SessionGuard::global().status();
         Ok(CallToolResult::success(vec![Content::text(message)]))
     }
 
+    #[tool(
+        description = "Show CPU and memory usage of spawned processes; pass a pid for its recent history."
+    )]
+    async fn process_stats(
+        &self,
+        #[tool(aggr)] request: ProcessStatsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let text = match request.pid {
+            Some(pid) => match ProcessManager::global().history(pid) {
+                Some((label, samples)) => format_history(pid, &label, &samples),
+                None => {
+                    return Ok(CallToolResult::error(vec![Content::text(format!(
+                        "No managed process with PID {}",
+                        pid
+                    ))]))
+                }
+            },
+            None => format_stats(&ProcessManager::global().list()),
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "List processes spawned by this server (pid, label, uptime, status).")]
+    async fn list_processes(&self) -> Result<CallToolResult, RmcpError> {
+        let processes = ProcessManager::global().list();
+        Ok(CallToolResult::success(vec![Content::text(
+            format_process_list(&processes),
+        )]))
+    }
+
     fn convert_result(result: CommandResult) -> Result<CallToolResult, RmcpError> {
-        if result.success {
-            Ok(CallToolResult::success(vec![Content::text(result.output)]))
-        } else {
-            let error_msg = result.error.unwrap_or_else(|| "Unknown error".to_string());
-            let formatted_error = format!(
-                "Command failed (exit code {}): {}",
-                result.exit_code, error_msg
-            );
-            Ok(CallToolResult::error(vec![Content::text(formatted_error)]))
-        }
+        Ok(result.tool_result())
     }
 }
 
 #[tool(tool_box)]
 impl ServerHandler for GooseServer {
     fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some("This server provides comprehensive tools for interacting with the Goose AI agent CLI. You can execute tasks, manage sessions, configure settings, handle projects, and perform all major Goose operations.\n\n🚨 CRITICAL SESSION MANAGEMENT & DUPLICATE PREVENTION:\n\n🔄 **EXECUTION CONTROL**:\n• Each request is tracked to prevent duplicate execution\n• If same task is already running, you'll get an error message\n• Use 'checksessions' to verify current execution state\n• Use 'killsessions' to force terminate all active sessions\n\n⚠️ **DUPLICATE RESPONSE PREVENTION**:\n• NEVER execute the same command multiple times for one request\n• If you get \"already being executed\" error, STOP and inform user\n• Wait for current execution to complete before new requests\n• Check execution status before starting new operations\n\n🔚 **MANDATORY SESSION TERMINATION**:\n• After completing ANY task, check for active sessions\n• Use 'killsessions' to cleanup when task is done\n• Look for \"🔚 EXECUTION COMPLETED\" marker in outputs\n• ALWAYS terminate sessions after successful completion\n\n📋 **REQUIRED WORKFLOW**:\n1. Check if sessions active (checksessions)\n2. Execute requested operation (runtask/startsession/etc)\n3. Wait for completion marker in output\n4. Terminate sessions (killsessions)\n5. Confirm cleanup completed\n\n🛡️ **ERROR HANDLING**:\n• If \"already being executed\" error: inform user to wait\n• If timeout errors: use killsessions then retry\n• If hanging: force terminate with killsessions\n• Always cleanup state after errors\n\n🚫 **STRICTLY FORBIDDEN**:\n• Multiple executions of same command\n• Starting new tasks without checking active sessions\n• Leaving sessions active after completion\n• Ignoring duplicate execution warnings\n\n⚡ **TOOLS AVAILABLE**:\n• 'runtask' - Execute instructions (with deduplication)\n• 'startsession' - Start interactive session (with tracking)\n• 'killsessions' - Force terminate all sessions\n• 'checksessions' - Check for active sessions\n• All standard Goose operations with session management\n\n💀 **FAILURE TO FOLLOW SESSION MANAGEMENT WILL CAUSE**:\n❌ Duplicate responses to users\n❌ Multiple agents responding to same request\n❌ System resource exhaustion\n❌ Hanging/zombie processes\n❌ Broken user experience\n\nUse 'run_task' for headless execution of instructions, 'start_session' for interactive sessions, and various management tools for sessions, projects, and configuration. All commands support the full range of Goose CLI options and return structured results with success/failure status and detailed output.".to_string()),
-        }
+        ServerInfoBuilder::new()
+            .instructions("This server provides comprehensive tools for interacting with the Goose AI agent CLI. You can execute tasks, manage sessions, configure settings, handle projects, and perform all major Goose operations.\n\n🚨 CRITICAL SESSION MANAGEMENT & DUPLICATE PREVENTION:\n\n🔄 **EXECUTION CONTROL**:\n• Each request is tracked to prevent duplicate execution\n• If same task is already running, you'll get an error message\n• Use 'checksessions' to verify current execution state\n• Use 'killsessions' to force terminate all active sessions\n\n⚠️ **DUPLICATE RESPONSE PREVENTION**:\n• NEVER execute the same command multiple times for one request\n• If you get \"already being executed\" error, STOP and inform user\n• Wait for current execution to complete before new requests\n• Check execution status before starting new operations\n\n🔚 **MANDATORY SESSION TERMINATION**:\n• After completing ANY task, check for active sessions\n• Use 'killsessions' to cleanup when task is done\n• Look for \"🔚 EXECUTION COMPLETED\" marker in outputs\n• ALWAYS terminate sessions after successful completion\n\n📋 **REQUIRED WORKFLOW**:\n1. Check if sessions active (checksessions)\n2. Execute requested operation (runtask/startsession/etc)\n3. Wait for completion marker in output\n4. Terminate sessions (killsessions)\n5. Confirm cleanup completed\n\n🛡️ **ERROR HANDLING**:\n• If \"already being executed\" error: inform user to wait\n• If timeout errors: use killsessions then retry\n• If hanging: force terminate with killsessions\n• Always cleanup state after errors\n\n🚫 **STRICTLY FORBIDDEN**:\n• Multiple executions of same command\n• Starting new tasks without checking active sessions\n• Leaving sessions active after completion\n• Ignoring duplicate execution warnings\n\n⚡ **TOOLS AVAILABLE**:\n• 'runtask' - Execute instructions (with deduplication)\n• 'startsession' - Start interactive session (with tracking)\n• 'killsessions' - Force terminate all sessions\n• 'checksessions' - Check for active sessions\n• All standard Goose operations with session management\n\n💀 **FAILURE TO FOLLOW SESSION MANAGEMENT WILL CAUSE**:\n❌ Duplicate responses to users\n❌ Multiple agents responding to same request\n❌ System resource exhaustion\n❌ Hanging/zombie processes\n❌ Broken user experience\n\nUse 'run_task' for headless execution of instructions, 'start_session' for interactive sessions, and various management tools for sessions, projects, and configuration. All commands support the full range of Goose CLI options and return structured results with success/failure status and detailed output.")
+            .build()
     }
 }
@@ -0,0 +1,226 @@
+//! The duplicate-execution guard shared by every server that runs Goose
+//!
+//! `GooseCommands` records running tasks and sessions here, and both the standalone Goose server
+//! and the combined server check it before `runtask` and report `checksessions` / `killsessions`
+//! with the same helpers, so switching between them doesn't change what is refused or said.
+
+use super::types::CommandResult;
+use crate::catalog::{self, Key};
+use crate::error::NparrotError;
+use rmcp::model::{CallToolResult, Content};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// A task started again within this long of the same task is refused as a duplicate
+pub const DUPLICATE_WINDOW: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref GUARD: SessionGuard = SessionGuard::default();
+}
+
+/// Running tasks by execution key and sessions by id
+#[derive(Debug, Default)]
+pub struct SessionGuard {
+    executions: Mutex<HashMap<String, Instant>>,
+    sessions: Mutex<HashMap<String, bool>>,
+}
+
+fn locked<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl SessionGuard {
+    pub fn global() -> &'static SessionGuard {
+        &GUARD
+    }
+
+    /// Records the task `key` as started, unless the same task started within
+    /// `DUPLICATE_WINDOW`; checking and recording happen at once, so of several identical tasks
+    /// started together only one goes through
+    pub fn begin_task(&self, key: &str) -> Result<(), NparrotError> {
+        let mut executions = locked(&self.executions);
+        if let Some(started) = executions.get(key) {
+            if started.elapsed() < DUPLICATE_WINDOW {
+                return Err(NparrotError::rate_limited(
+                    "Same task is already being executed. Please wait.",
+                ));
+            }
+        }
+        executions.insert(key.to_string(), Instant::now());
+        Ok(())
+    }
+
+    pub fn end_task(&self, key: &str) {
+        locked(&self.executions).remove(key);
+    }
+
+    /// Marks session `id` active, unless it already is
+    pub fn begin_session(&self, id: &str) -> Result<(), NparrotError> {
+        let mut sessions = locked(&self.sessions);
+        if sessions.get(id).copied().unwrap_or(false) {
+            return Err(NparrotError::rate_limited(format!(
+                "Session {} is already active",
+                id
+            )));
+        }
+        sessions.insert(id.to_string(), true);
+        Ok(())
+    }
+
+    pub fn end_session(&self, id: &str) {
+        locked(&self.sessions).insert(id.to_string(), false);
+    }
+
+    /// Whether session `id` was started since the sessions were last cleared
+    pub fn knows(&self, id: &str) -> bool {
+        locked(&self.sessions).contains_key(id)
+    }
+
+    pub fn has_active_sessions(&self) -> bool {
+        locked(&self.sessions).values().any(|active| *active)
+    }
+
+    /// Forgets every task and session, after their processes were terminated
+    pub fn clear(&self) {
+        locked(&self.sessions).clear();
+        locked(&self.executions).clear();
+    }
+
+    /// The refusal `runtask` returns while a session is active, with the warning for the user
+    pub fn preflight(&self) -> Result<(), (String, CallToolResult)> {
+        if !self.has_active_sessions() {
+            return Ok(());
+        }
+        Err((
+            catalog::text(Key::SessionsBlockTask, &[]),
+            CallToolResult::error(vec![Content::text(
+                "Active sessions must be terminated first",
+            )]),
+        ))
+    }
+
+    /// What `checksessions` tells the user, and the short form for the model
+    pub fn status(&self) -> (String, String) {
+        if self.has_active_sessions() {
+            (
+                catalog::text(Key::SessionsActive, &[]),
+                "Active sessions detected".to_string(),
+            )
+        } else {
+            (
+                catalog::text(Key::NoActiveSessions, &[]),
+                "No active sessions".to_string(),
+            )
+        }
+    }
+}
+
+/// What the user is told about a finished `runtask`
+pub fn task_report(result: &CommandResult) -> String {
+    let took = result.took();
+    if result.success {
+        let report = catalog::text(
+            Key::TaskSucceeded,
+            &[
+                ("took", &took),
+                (
+                    "output",
+                    &super::output::extract_task_results(&result.output),
+                ),
+            ],
+        );
+        if result.completed {
+            format!("{}\n\n{}", report, catalog::text(Key::TaskFinished, &[]))
+        } else {
+            report
+        }
+    } else {
+        let error = result
+            .error
+            .as_deref()
+            .map(super::output::extract_error_message)
+            .unwrap_or_else(|| "Unknown error".to_string());
+        catalog::text(
+            Key::TaskFailed,
+            &[
+                ("took", &took),
+                ("code", &result.exit_code.to_string()),
+                ("error", &error),
+            ],
+        )
+    }
+}
+
+/// What the user is told after `killsessions`
+pub fn kill_report(result: &CommandResult) -> String {
+    if result.success {
+        catalog::text(Key::SessionsKilled, &[("output", &result.output)])
+    } else {
+        catalog::text(
+            Key::KillFailed,
+            &[
+                ("code", &result.exit_code.to_string()),
+                ("error", result.error.as_deref().unwrap_or("Unknown error")),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_concurrent_runs_of_one_task_start_once() {
+        let guard = Arc::new(SessionGuard::default());
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+        let attempts: Vec<_> = (0..8)
+            .map(|_| {
+                let guard = guard.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    guard.begin_task("runtask_build_it").is_ok()
+                })
+            })
+            .collect();
+        let started = attempts
+            .into_iter()
+            .map(|attempt| attempt.join().unwrap())
+            .filter(|started| *started)
+            .count();
+        assert_eq!(started, 1);
+
+        // Another task isn't held up, and the same one runs again once the first finished
+        assert!(guard.begin_task("runtask_test_it").is_ok());
+        let refused = guard.begin_task("runtask_build_it").unwrap_err();
+        assert!(refused.to_string().contains("already being executed"));
+        guard.end_task("runtask_build_it");
+        assert!(guard.begin_task("runtask_build_it").is_ok());
+    }
+
+    #[test]
+    fn test_active_sessions_block_runtask_until_killed() {
+        let guard = SessionGuard::default();
+        assert!(guard.preflight().is_ok());
+        assert_eq!(guard.status().1, "No active sessions");
+
+        guard.begin_session("s1").unwrap();
+        assert!(guard.begin_session("s1").is_err());
+        let (warning, refusal) = guard.preflight().unwrap_err();
+        assert!(warning.contains("killsessions"));
+        assert_eq!(refusal.is_error, Some(true));
+        assert_eq!(guard.status().1, "Active sessions detected");
+
+        guard.end_session("s1");
+        assert!(guard.preflight().is_ok());
+        assert!(guard.knows("s1") && !guard.knows("s2"));
+        guard.begin_session("s2").unwrap();
+        guard.begin_task("runtask_x").unwrap();
+        guard.clear();
+        assert!(guard.preflight().is_ok());
+        assert!(guard.begin_task("runtask_x").is_ok());
+    }
+}
@@ -1,5 +1,7 @@
 pub mod commands;
 pub mod goose_server;
+pub mod guard;
+pub mod output;
 pub mod types;
 
 pub use goose_server::GooseServer;
@@ -0,0 +1,301 @@
+//! Turning raw Goose output into what the user should read
+//!
+//! Goose prints session banners, tool-call headers, context meters and terminal colours around
+//! its answer. The answer is found from the structure of the output where there is one: the part
+//! after the last `( O)>` prompt, and after the last tool call within it. Fenced code blocks are
+//! always kept whole. Keyword scanning is only the fallback for long output without structure.
+
+use super::types::COMPLETION_MARKER;
+use regex::Regex;
+
+pub const NO_RESULTS: &str =
+    "Task completed successfully. Check your working directory for results.";
+pub const NO_ERROR: &str = "An error occurred during task execution.";
+
+/// Lines of unstructured output kept before keyword scanning trims it
+const MAX_UNSTRUCTURED_LINES: usize = 40;
+/// Lines of an error kept
+const MAX_ERROR_LINES: usize = 20;
+
+const RESULT_KEYWORDS: &[&str] = &[
+    "created",
+    "implemented",
+    "added",
+    "modified",
+    "updated",
+    "fixed",
+    "here is",
+    "here's",
+    "summary",
+    "done",
+];
+
+lazy_static::lazy_static! {
+    /// CSI sequences (colours, cursor movement) and OSC sequences (titles, links)
+    static ref ANSI: Regex =
+        Regex::new(r"\x1b\[[0-9;?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[@-Z\\-_]")
+            .unwrap();
+    /// `─── shell | developer ──────` above each tool call
+    static ref TOOL_HEADER: Regex = Regex::new(r"^\s*─{2,}\s*\S.*\|.*─{2,}\s*$").unwrap();
+}
+
+pub fn strip_ansi(text: &str) -> String {
+    ANSI.replace_all(text, "").replace('\r', "")
+}
+
+fn is_prompt(line: &str) -> bool {
+    line.trim_start().starts_with("( O)>") || line.trim_start().starts_with("( o)>")
+}
+
+fn is_fence(line: &str) -> bool {
+    line.trim_start().starts_with("```")
+}
+
+/// Session chrome Goose prints around the conversation
+fn is_noise(line: &str) -> bool {
+    let trimmed = line.trim();
+    let lower = trimmed.to_lowercase();
+    lower.starts_with("starting session")
+        || lower.starts_with("resuming session")
+        || lower.starts_with("closing session")
+        || lower.starts_with("logging to")
+        || lower.starts_with("working directory")
+        || lower.starts_with("goose is running")
+        || lower.starts_with("enter your instructions")
+        || lower.starts_with("press enter to send")
+        || lower.starts_with("context:")
+        || trimmed.contains(COMPLETION_MARKER)
+        || (!trimmed.is_empty() && trimmed.chars().all(|c| "●○◐◓◑◒ ".contains(c)))
+}
+
+/// The non-noise lines, with fenced code blocks kept untouched
+fn meaningful_lines(text: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut in_fence = false;
+    for line in text.lines() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+            lines.push(line);
+        } else if in_fence || !is_noise(line) {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Indexes of the lines matching `matches` outside fenced code blocks
+fn positions_outside_fences(lines: &[&str], matches: impl Fn(&str) -> bool) -> Vec<usize> {
+    let mut in_fence = false;
+    let mut positions = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        } else if !in_fence && matches(line) {
+            positions.push(i);
+        }
+    }
+    positions
+}
+
+/// What Goose answered to the last instruction: the last non-empty stretch between prompts
+fn after_last_prompt<'a>(lines: &'a [&'a str]) -> &'a [&'a str] {
+    let prompts = positions_outside_fences(lines, is_prompt);
+    let mut end = lines.len();
+    for &prompt in prompts.iter().rev() {
+        let region = &lines[prompt + 1..end];
+        if region.iter().any(|line| !line.trim().is_empty()) {
+            return region;
+        }
+        end = prompt;
+    }
+    &lines[..end]
+}
+
+/// The text after the last tool call and its parameter lines, if there is any
+fn after_last_tool_call<'a>(lines: &'a [&'a str]) -> &'a [&'a str] {
+    let Some(&header) = positions_outside_fences(lines, |line| TOOL_HEADER.is_match(line)).last()
+    else {
+        return lines;
+    };
+    let mut start = header + 1;
+    // The parameters follow the header up to the first blank line
+    while start < lines.len() && !lines[start].trim().is_empty() {
+        start += 1;
+    }
+    let rest = &lines[start..];
+    if rest.iter().any(|line| !line.trim().is_empty()) {
+        rest
+    } else {
+        lines
+    }
+}
+
+/// Joins `lines`, without blank lines at either end or runs of blank lines outside fences
+fn join(lines: &[&str]) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in lines {
+        if is_fence(line) {
+            in_fence = !in_fence;
+        }
+        let blank = line.trim().is_empty();
+        if blank && !in_fence && out.last().is_none_or(|last| last.trim().is_empty()) {
+            continue;
+        }
+        out.push(line.trim_end());
+    }
+    out.join("\n").trim().to_string()
+}
+
+/// The user-facing result in raw Goose output
+pub fn extract_task_results(raw_output: &str) -> String {
+    let text = strip_ansi(raw_output);
+    let lines = meaningful_lines(&text);
+    let structured = lines
+        .iter()
+        .any(|line| is_prompt(line) || is_fence(line) || TOOL_HEADER.is_match(line));
+
+    let result = if structured {
+        join(after_last_tool_call(after_last_prompt(&lines)))
+    } else if lines.len() > MAX_UNSTRUCTURED_LINES {
+        // Long unstructured output: start at the first result-like line near the end
+        let tail = &lines[lines.len() - MAX_UNSTRUCTURED_LINES..];
+        let start = tail
+            .iter()
+            .position(|line| {
+                let lower = line.to_lowercase();
+                RESULT_KEYWORDS
+                    .iter()
+                    .any(|keyword| lower.contains(keyword))
+            })
+            .unwrap_or(0);
+        join(&tail[start..])
+    } else {
+        join(&lines)
+    };
+
+    if result.is_empty() {
+        NO_RESULTS.to_string()
+    } else {
+        result
+    }
+}
+
+/// The error in raw Goose error output, from the first line that looks like one
+pub fn extract_error_message(raw_error: &str) -> String {
+    let text = strip_ansi(raw_error);
+    let lines: Vec<&str> = meaningful_lines(&text)
+        .into_iter()
+        .filter(|line| {
+            let lower = line.trim().to_lowercase();
+            !(lower.starts_with("session:")
+                || lower.starts_with("provider:")
+                || lower.starts_with("model:"))
+        })
+        .collect();
+    let start = lines
+        .iter()
+        .position(|line| {
+            let lower = line.trim().to_lowercase();
+            lower.starts_with("error") || lower.contains("panicked") || lower.starts_with("failed")
+        })
+        .unwrap_or(0);
+    let end = lines.len().min(start + MAX_ERROR_LINES);
+    let message = join(&lines[start..end]);
+
+    if message.is_empty() {
+        NO_ERROR.to_string()
+    } else {
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Goose transcripts, with their terminal escapes
+    const RUN_WITH_TOOLS: &str = include_str!("fixtures/run_with_tools.txt");
+    const SESSION_PROMPTS: &str = include_str!("fixtures/session_prompts.txt");
+    const CODE_ANSWER: &str = include_str!("fixtures/code_answer.txt");
+    const PLAIN_ANSWER: &str = include_str!("fixtures/plain_answer.txt");
+    const LONG_BUILD_LOG: &str = include_str!("fixtures/long_build_log.txt");
+    const PROVIDER_ERROR: &str = include_str!("fixtures/provider_error.txt");
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(
+            strip_ansi("\x1b[1;32mok\x1b[0m \x1b]8;;https://x.dev\x07link\x1b]8;;\x07\r"),
+            "ok link"
+        );
+    }
+
+    #[test]
+    fn test_answer_after_the_last_tool_call() {
+        let result = extract_task_results(RUN_WITH_TOOLS);
+        assert!(
+            result.starts_with("I've added a `/health` endpoint"),
+            "{}",
+            result
+        );
+        assert!(result.contains("returns `200 OK` with `{\"status\":\"ok\"}`"));
+        assert!(!result.contains('\x1b'));
+        assert!(!result.contains("starting session"));
+        assert!(!result.contains("command: cargo test"));
+        assert!(!result.contains(COMPLETION_MARKER));
+    }
+
+    #[test]
+    fn test_answer_to_the_last_prompt() {
+        let result = extract_task_results(SESSION_PROMPTS);
+        assert!(result.contains("There are 3 failing tests"), "{}", result);
+        assert!(!result.contains("list the files"));
+        assert!(!result.contains("Cargo.toml  README.md"));
+        assert!(!result.contains("( O)>"));
+    }
+
+    #[test]
+    fn test_code_blocks_are_kept_whole() {
+        let result = extract_task_results(CODE_ANSWER);
+        let fence = result.find("```rust").expect("code block kept");
+        let block = &result[fence..];
+        // Lines that look like session chrome are left alone inside code
+        assert!(block.contains("println!(\"logging to {}\", path.display());"));
+        assert!(block.contains("\n\n    Ok(())"));
+        assert!(result.starts_with("Here's the helper"));
+    }
+
+    #[test]
+    fn test_answers_without_keywords_are_not_dropped() {
+        assert_eq!(
+            extract_task_results(PLAIN_ANSWER),
+            "The repository has two crates:\n- `core`, the parser and evaluator\n- `cli`, a thin wrapper around it\n\nMost of the logic, and all 48 tests, live in `core`."
+        );
+    }
+
+    #[test]
+    fn test_long_unstructured_output_falls_back_to_keywords() {
+        let result = extract_task_results(LONG_BUILD_LOG);
+        assert!(result.starts_with("Fixed the failing build"), "{}", result);
+        assert!(!result.contains("Compiling serde"));
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        assert_eq!(extract_task_results(""), NO_RESULTS);
+        assert_eq!(
+            extract_task_results("starting session | provider: openai model: gpt-4o\n"),
+            NO_RESULTS
+        );
+        assert_eq!(extract_error_message("\n  \n"), NO_ERROR);
+    }
+
+    #[test]
+    fn test_error_message() {
+        let error = extract_error_message(PROVIDER_ERROR);
+        assert!(error.starts_with("Error: Request failed"), "{}", error);
+        assert!(error.contains("401 Unauthorized"));
+        assert!(!error.contains("logging to"));
+        assert!(!error.contains('\x1b'));
+    }
+}
@@ -1,5 +1,8 @@
+use crate::error::NparrotError;
+use rmcp::model::{CallToolResult, Content};
 use rmcp::schemars::{self, JsonSchema};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct RunTaskRequest {
@@ -20,6 +23,26 @@ pub struct SessionRequest {
     pub max_turns: Option<u32>,
 }
 
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ResumeSessionRequest {
+    /// The session id `startsession` returned, or one `listsessions` shows
+    pub id: String,
+}
+
+impl From<ResumeSessionRequest> for SessionRequest {
+    fn from(request: ResumeSessionRequest) -> Self {
+        Self {
+            name: None,
+            id: Some(request.id),
+            resume: Some(true),
+            with_extension: None,
+            with_builtin: None,
+            debug: None,
+            max_turns: None,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct SessionListRequest {
     pub verbose: Option<bool>,
@@ -32,6 +55,8 @@ pub struct SessionRemoveRequest {
     pub id: Option<String>,
     pub name: Option<String>,
     pub regex: Option<String>,
+    /// Only report which sessions would be removed
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -76,12 +101,37 @@ pub struct ProjectRequest {
     pub new: Option<bool>,
 }
 
+/// Exit code of `CommandResult::interrupted`; -1 and -2 are other internal failures
+pub const INTERRUPTED_EXIT_CODE: i32 = -3;
+
+/// Shown to agents after the output of a command that ran to the end; kept out of `output`
+pub const COMPLETION_MARKER: &str = "🔚 EXECUTION COMPLETED";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CommandResult {
     pub success: bool,
+    /// What the command printed for the user, without internal markers
     pub output: String,
     pub error: Option<String>,
     pub exit_code: i32,
+    /// What kind of failure `error` is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<NparrotError>,
+    /// The command ran to the end, so its session can be cleaned up
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// The command line, for logs
+    #[serde(default)]
+    pub argv: String,
+    /// The Goose session the command started or resumed, to resume or remove it later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
 }
 
 impl CommandResult {
@@ -91,15 +141,195 @@ impl CommandResult {
             output,
             error: None,
             exit_code: 0,
+            failure: None,
+            completed: false,
+            stdout: String::new(),
+            stderr: String::new(),
+            duration_ms: 0,
+            argv: String::new(),
+            session: None,
+        }
+    }
+
+    /// A command that ran to the end and exited successfully
+    pub fn completed(stdout: String, stderr: String) -> Self {
+        Self {
+            completed: true,
+            stderr,
+            stdout: stdout.clone(),
+            ..Self::success(stdout)
         }
     }
 
     pub fn error(error: String, exit_code: i32) -> Self {
+        Self::failed(NparrotError::internal(error), exit_code)
+    }
+
+    pub fn failed(failure: NparrotError, exit_code: i32) -> Self {
         Self {
             success: false,
-            output: String::new(),
-            error: Some(error),
+            error: Some(failure.to_string()),
             exit_code,
+            failure: Some(failure),
+            ..Self::success(String::new())
+        }
+    }
+
+    /// Keeps what the command printed to each stream
+    pub fn with_streams(mut self, stdout: String, stderr: String) -> Self {
+        self.stdout = stdout;
+        self.stderr = stderr;
+        self
+    }
+
+    /// Records the command line and how long the command took, retries included
+    pub fn with_run(mut self, argv: String, duration: Duration) -> Self {
+        self.argv = argv;
+        self.duration_ms = duration.as_millis() as u64;
+        self
+    }
+
+    pub fn with_session(mut self, session: String) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+
+    /// How long the command took, e.g. "4.2s" or "3m 05s"
+    pub fn took(&self) -> String {
+        let secs = self.duration().as_secs();
+        if secs < 60 {
+            format!("{:.1}s", self.duration().as_secs_f64())
+        } else {
+            format!("{}m {:02}s", secs / 60, secs % 60)
+        }
+    }
+
+    /// The output for agents, then the session id if there is one, followed by
+    /// `COMPLETION_MARKER` once the command completed
+    pub fn tool_text(&self) -> String {
+        let mut text = self.output.clone();
+        if let Some(session) = &self.session {
+            text.push_str(&format!("\nSession id: {}", session));
         }
+        if self.completed {
+            format!(
+                "{}\n{} - SESSION READY FOR TERMINATION",
+                text, COMPLETION_MARKER
+            )
+        } else {
+            text
+        }
+    }
+
+    /// The tool result for the command, an error result if it failed
+    pub fn tool_result(&self) -> CallToolResult {
+        if self.success {
+            CallToolResult::success(vec![Content::text(self.tool_text())])
+        } else {
+            let formatted_error = format!(
+                "Command failed (exit code {}): {}",
+                self.exit_code,
+                self.error.as_deref().unwrap_or("Unknown error")
+            );
+            self.error_result(formatted_error)
+        }
+    }
+
+    /// The tool error result for a failed command, with `text` for humans
+    pub fn error_result(&self, text: String) -> CallToolResult {
+        self.failure
+            .clone()
+            .unwrap_or_else(|| NparrotError::internal(text.clone()))
+            .to_result(text)
+    }
+
+    /// The user interrupted the command and its process was killed
+    pub fn interrupted() -> Self {
+        Self::error("Interrupted by user".to_string(), INTERRUPTED_EXIT_CODE)
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        !self.success && self.exit_code == INTERRUPTED_EXIT_CODE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_marker_round_trip() {
+        let result = CommandResult::completed("Built the project".to_string(), String::new());
+        assert_eq!(result.output, "Built the project");
+        assert!(result.completed);
+        assert!(result.tool_text().starts_with("Built the project\n"));
+        assert!(result.tool_text().contains(COMPLETION_MARKER));
+
+        let partial = CommandResult::success("partial output".to_string());
+        assert!(!partial.completed);
+        assert!(!partial.tool_text().contains(COMPLETION_MARKER));
+        assert!(!CommandResult::error("failed".to_string(), 1).completed);
+
+        let session = CommandResult::completed("Session ended".to_string(), String::new())
+            .with_session("session_1700000000".to_string());
+        assert!(session
+            .tool_text()
+            .starts_with("Session ended\nSession id: session_1700000000\n"));
+        assert_eq!(
+            serde_json::to_value(&session).unwrap()["session"],
+            "session_1700000000"
+        );
+    }
+
+    #[test]
+    fn test_command_result_wire_shape() {
+        let result = CommandResult::completed("done".to_string(), "warning: x".to_string())
+            .with_run(
+                "goose run -i task.md".to_string(),
+                Duration::from_millis(1500),
+            );
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::json!({
+                "success": true,
+                "output": "done",
+                "error": null,
+                "exit_code": 0,
+                "completed": true,
+                "stdout": "done",
+                "stderr": "warning: x",
+                "duration_ms": 1500,
+                "argv": "goose run -i task.md",
+            })
+        );
+
+        let failed = serde_json::to_value(CommandResult::error("boom".to_string(), 2)).unwrap();
+        assert_eq!(failed["success"], false);
+        assert_eq!(failed["exit_code"], 2);
+        assert!(failed["failure"].is_object());
+
+        // Results saved before the structured fields existed still load
+        let old: CommandResult =
+            serde_json::from_str(r#"{"success":true,"output":"ok","error":null,"exit_code":0}"#)
+                .unwrap();
+        assert_eq!(old.output, "ok");
+        assert!(!old.completed);
+        assert_eq!(old.duration_ms, 0);
+        assert!(old.argv.is_empty());
+    }
+
+    #[test]
+    fn test_took() {
+        let took = |ms| {
+            CommandResult::success(String::new())
+                .with_run(String::new(), Duration::from_millis(ms))
+                .took()
+        };
+        assert_eq!(took(4_210), "4.2s");
+        assert_eq!(took(185_000), "3m 05s");
     }
 }
@@ -0,0 +1,365 @@
+//! NIP-29 relay-based groups as a conversation target (`--group <group-id>@<relay>`)
+//!
+//! In group mode `send` posts kind-9 chat messages tagged with the group id to the group's relay,
+//! and `wait`/`listen` take the group's kind 9/11 messages instead of DMs. Only members (and
+//! `TARGET_PUBKEY`) are heard, which extends the usual single-sender allowlist to the group.
+//! Moderation events (joins, leaves, removals) keep the member list current and show up in
+//! `--json` output, but never reach an agent as user messages.
+
+use crate::transport::{DmTransport, TransportResult};
+use crate::utils::{expiration_tag, IncomingMessage};
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::RwLock;
+use tokio::sync::mpsc;
+
+pub const CHAT_MESSAGE: Kind = Kind::Custom(9);
+pub const THREAD: Kind = Kind::Custom(11);
+const PUT_USER: Kind = Kind::Custom(9000);
+const REMOVE_USER: Kind = Kind::Custom(9001);
+const JOIN_REQUEST: Kind = Kind::Custom(9021);
+const LEAVE_REQUEST: Kind = Kind::Custom(9022);
+/// Relay-signed, addressable member list of a group
+const GROUP_MEMBERS: Kind = Kind::Custom(39002);
+
+lazy_static::lazy_static! {
+    static ref GROUP: RwLock<Option<GroupTarget>> = RwLock::new(None);
+}
+
+/// A group on the relay that hosts it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupTarget {
+    pub id: String,
+    pub relay: String,
+}
+
+impl GroupTarget {
+    /// Parses `<group-id>@<relay>`, e.g. `devs@groups.example.com` or `devs@wss://groups.example.com`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (id, relay) = spec
+            .trim()
+            .split_once('@')
+            .ok_or_else(|| format!("Expected <group-id>@<relay>, got '{}'", spec))?;
+        let id = id.trim();
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "Invalid group id '{}': use letters, digits, '-' and '_'",
+                id
+            ));
+        }
+        let relay = relay.trim();
+        let relay = if relay.contains("://") {
+            relay.to_string()
+        } else {
+            format!("wss://{}", relay)
+        };
+        RelayUrl::parse(&relay).map_err(|e| format!("Invalid group relay '{}': {}", relay, e))?;
+        Ok(Self {
+            id: id.to_string(),
+            relay,
+        })
+    }
+
+    /// A chat message to the group, dated `created_at`
+    pub fn message(
+        &self,
+        content: String,
+        expire_after_secs: Option<u64>,
+        tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> EventBuilder {
+        EventBuilder::new(CHAT_MESSAGE, content)
+            .tag(Tag::custom(TagKind::h(), [self.id.clone()]))
+            .tags(tags)
+            .tags(expire_after_secs.map(|secs| expiration_tag(created_at, secs)))
+            .custom_created_at(created_at)
+    }
+
+    /// Messages and moderation events of the group, plus its member list
+    pub fn filters(&self) -> Vec<Filter> {
+        vec![
+            Filter::new()
+                .kinds([
+                    CHAT_MESSAGE,
+                    THREAD,
+                    PUT_USER,
+                    REMOVE_USER,
+                    JOIN_REQUEST,
+                    LEAVE_REQUEST,
+                ])
+                .custom_tag(SingleLetterTag::lowercase(Alphabet::H), &self.id)
+                .since(Timestamp::now()),
+            Filter::new()
+                .kind(GROUP_MEMBERS)
+                .identifier(&self.id)
+                .limit(1),
+        ]
+    }
+}
+
+/// Installs the process-wide conversation target `Chat::new` picks up
+pub fn set_target(group: Option<GroupTarget>) {
+    if let Ok(mut guard) = GROUP.write() {
+        *guard = group;
+    }
+}
+
+pub fn target() -> Option<GroupTarget> {
+    GROUP.read().ok()?.clone()
+}
+
+/// Who a conversation is with: the target user over DMs, or a group
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conversation {
+    Direct(PublicKey),
+    Group(GroupTarget),
+}
+
+impl Conversation {
+    /// Builds the outgoing message without publishing it
+    pub async fn prepare(
+        &self,
+        client: &dyn DmTransport,
+        message: String,
+        expire_after_secs: Option<u64>,
+        tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> TransportResult<Event> {
+        match self {
+            Self::Direct(receiver) => {
+                client
+                    .prepare_private_msg_at(*receiver, message, expire_after_secs, tags, created_at)
+                    .await
+            }
+            Self::Group(group) => {
+                client
+                    .sign_event(group.message(message, expire_after_secs, tags, created_at))
+                    .await
+            }
+        }
+    }
+
+    /// The relays a message has to go to, `None` to leave it to the routing policy
+    pub fn relays(&self) -> Option<Vec<String>> {
+        match self {
+            Self::Direct(_) => None,
+            Self::Group(group) => Some(vec![group.relay.clone()]),
+        }
+    }
+}
+
+/// A change to the group, surfaced in `--json` output only
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "moderation", rename_all = "snake_case")]
+pub enum Moderation {
+    Added { pubkey: PublicKey, by: PublicKey },
+    Removed { pubkey: PublicKey, by: PublicKey },
+    JoinRequested { pubkey: PublicKey },
+    Left { pubkey: PublicKey },
+    Members { count: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupEvent {
+    Message(IncomingMessage),
+    Moderation(Moderation),
+}
+
+/// Tracks the member list and turns the group's events into messages and moderation notices
+#[derive(Debug)]
+pub struct Members {
+    group: GroupTarget,
+    our_pubkey: PublicKey,
+    /// Always heard, even before the member list arrives
+    target_pubkey: PublicKey,
+    members: HashSet<PublicKey>,
+}
+
+impl Members {
+    pub fn new(group: GroupTarget, our_pubkey: PublicKey, target_pubkey: PublicKey) -> Self {
+        Self {
+            group,
+            our_pubkey,
+            target_pubkey,
+            members: HashSet::new(),
+        }
+    }
+
+    pub fn is_allowed(&self, pubkey: &PublicKey) -> bool {
+        *pubkey == self.target_pubkey || self.members.contains(pubkey)
+    }
+
+    pub fn apply(&mut self, event: &Event) -> Option<GroupEvent> {
+        if event.kind == GROUP_MEMBERS {
+            if event.tags.identifier() != Some(self.group.id.as_str()) {
+                return None;
+            }
+            self.members = event.tags.public_keys().copied().collect();
+            return Some(GroupEvent::Moderation(Moderation::Members {
+                count: self.members.len(),
+            }));
+        }
+
+        let in_group = event
+            .tags
+            .find(TagKind::h())
+            .and_then(|tag| tag.content())
+            .is_some_and(|id| id == self.group.id);
+        if !in_group {
+            return None;
+        }
+
+        let by = event.pubkey;
+        let targets: Vec<PublicKey> = event.tags.public_keys().copied().collect();
+        let kind = event.kind;
+        let moderation = if kind == CHAT_MESSAGE || kind == THREAD {
+            if by == self.our_pubkey {
+                return None;
+            }
+            if !self.is_allowed(&by) {
+                log::warn!("Dropping group message from non-member {}", by);
+                return None;
+            }
+            return Some(GroupEvent::Message(IncomingMessage::from_rumor(
+                UnsignedEvent::from(event.clone()),
+            )));
+        } else if kind == PUT_USER {
+            self.members.extend(targets.iter().copied());
+            targets
+                .into_iter()
+                .map(|pubkey| Moderation::Added { pubkey, by })
+                .next()
+        } else if kind == REMOVE_USER {
+            for pubkey in &targets {
+                self.members.remove(pubkey);
+            }
+            targets
+                .into_iter()
+                .map(|pubkey| Moderation::Removed { pubkey, by })
+                .next()
+        } else if kind == JOIN_REQUEST {
+            Some(Moderation::JoinRequested { pubkey: by })
+        } else if kind == LEAVE_REQUEST {
+            self.members.remove(&by);
+            Some(Moderation::Left { pubkey: by })
+        } else {
+            None
+        };
+        moderation.map(GroupEvent::Moderation)
+    }
+}
+
+/// Subscribes to the group and streams its messages from members and its moderation events
+pub async fn subscribe(
+    client: &dyn DmTransport,
+    group: &GroupTarget,
+    our_pubkey: PublicKey,
+    target_pubkey: PublicKey,
+) -> TransportResult<mpsc::UnboundedReceiver<GroupEvent>> {
+    let mut events = client.subscribe_events(group.filters()).await?;
+    let mut members = Members::new(group.clone(), our_pubkey, target_pubkey);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let Some(group_event) = members.apply(&event) else {
+                continue;
+            };
+            if let GroupEvent::Moderation(moderation) = &group_event {
+                log::info!("Group event: {:?}", moderation);
+            }
+            if sender.send(group_event).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group() -> GroupTarget {
+        GroupTarget::parse("devs@groups.example.com").unwrap()
+    }
+
+    fn event(keys: &Keys, kind: Kind, tags: Vec<Tag>, content: &str) -> Event {
+        EventBuilder::new(kind, content)
+            .tags(tags)
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_group_target() {
+        assert_eq!(group().relay, "wss://groups.example.com");
+        assert_eq!(
+            GroupTarget::parse("devs@ws://localhost:7777")
+                .unwrap()
+                .relay,
+            "ws://localhost:7777"
+        );
+        assert!(GroupTarget::parse("devs").is_err());
+        assert!(GroupTarget::parse("bad id@relay.example.com").is_err());
+    }
+
+    #[test]
+    fn test_only_members_are_heard() {
+        let (ours, target, alice, admin) = (
+            Keys::generate(),
+            Keys::generate(),
+            Keys::generate(),
+            Keys::generate(),
+        );
+        let mut members = Members::new(group(), ours.public_key(), target.public_key());
+        let h = || Tag::custom(TagKind::h(), ["devs"]);
+        let said = |keys: &Keys| event(keys, CHAT_MESSAGE, vec![h()], "deploy please");
+
+        assert!(members.apply(&said(&alice)).is_none());
+        assert!(matches!(
+            members.apply(&said(&target)),
+            Some(GroupEvent::Message(_))
+        ));
+        // Our own messages come back from the relay too
+        assert!(members.apply(&said(&ours)).is_none());
+
+        let added = event(
+            &admin,
+            PUT_USER,
+            vec![h(), Tag::public_key(alice.public_key())],
+            "",
+        );
+        assert_eq!(
+            members.apply(&added),
+            Some(GroupEvent::Moderation(Moderation::Added {
+                pubkey: alice.public_key(),
+                by: admin.public_key(),
+            }))
+        );
+        match members.apply(&said(&alice)) {
+            Some(GroupEvent::Message(message)) => assert_eq!(message.content, "deploy please"),
+            other => panic!("expected a message, got {:?}", other),
+        }
+
+        let left = event(&alice, LEAVE_REQUEST, vec![h()], "");
+        assert!(matches!(
+            members.apply(&left),
+            Some(GroupEvent::Moderation(Moderation::Left { .. }))
+        ));
+        assert!(!members.is_allowed(&alice.public_key()));
+
+        // Other groups on the same relay are ignored
+        let elsewhere = event(
+            &target,
+            CHAT_MESSAGE,
+            vec![Tag::custom(TagKind::h(), ["ops"])],
+            "hi",
+        );
+        assert!(members.apply(&elsewhere).is_none());
+    }
+}
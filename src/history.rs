@@ -0,0 +1,214 @@
+//! Recent DM history with the target user, rendered as a transcript
+//!
+//! The user's side comes from the gift wraps relays stored for us. Ours can't be read back
+//! that way, since our gift wraps are only encrypted to the user, so every message `Chat`
+//! sends is also kept here in memory; messages sent before this process started are missing
+//! from the transcript.
+
+use crate::envelope::{Envelope, MessageType};
+use crate::error::NparrotError;
+use crate::selftest;
+use crate::transport::{DmTransport, STREAM_PROGRESS_EVERY};
+use crate::utils::is_authentic_dm;
+use nostr_sdk::prelude::*;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Sent messages kept for the transcript
+const SENT_CAPACITY: usize = 1000;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref SENT: Mutex<VecDeque<(PublicKey, HistoryEntry)>> = Mutex::new(VecDeque::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the user to us
+    Incoming,
+    /// From us to the user
+    Outgoing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub created_at: Timestamp,
+    pub direction: Direction,
+    /// The progress channel for progress messages, `None` for the conversation itself
+    pub progress_channel: Option<String>,
+    pub content: String,
+}
+
+/// Remembers a message we sent to `target`; `channel` is "main" or a progress channel
+pub fn record_sent(target: PublicKey, channel: &str, content: &str) {
+    let entry = HistoryEntry {
+        created_at: Timestamp::now(),
+        direction: Direction::Outgoing,
+        progress_channel: (channel != "main").then(|| channel.to_string()),
+        content: content.to_string(),
+    };
+    if let Ok(mut sent) = SENT.lock() {
+        if sent.len() == SENT_CAPACITY {
+            sent.pop_front();
+        }
+        sent.push_back((target, entry));
+    }
+}
+
+/// Both sides of the conversation with `target` since `since`, oldest first
+pub async fn fetch(
+    client: &dyn DmTransport,
+    our_pubkey: PublicKey,
+    target: PublicKey,
+    since: Timestamp,
+) -> Result<Vec<HistoryEntry>, NparrotError> {
+    let mut gifts = client
+        .stream_dms(our_pubkey, since, FETCH_TIMEOUT)
+        .await
+        .map_err(|e| {
+            NparrotError::relay_unavailable(format!("Could not fetch DM history: {}", e))
+        })?;
+    // Each gift is turned into an entry or dropped as it arrives, so months of DMs never sit
+    // in memory as raw gifts
+    let mut entries = Vec::new();
+    let mut processed = 0;
+    while let Some(gift) = gifts.recv().await {
+        processed += 1;
+        if processed % STREAM_PROGRESS_EVERY == 0 {
+            log::info!("DM history: {} messages processed", processed);
+        }
+        if selftest::is_probe(&gift.rumor) || !is_authentic_dm(&gift, &target) {
+            continue;
+        }
+        entries.push(incoming_entry(gift.rumor));
+    }
+    if let Ok(sent) = SENT.lock() {
+        entries.extend(
+            sent.iter()
+                .filter(|(to, entry)| *to == target && entry.created_at >= since)
+                .map(|(_, entry)| entry.clone()),
+        );
+    }
+    // Stable, so messages from the same second keep the order they were sent in
+    entries.sort_by_key(|entry| entry.created_at);
+    Ok(entries)
+}
+
+fn incoming_entry(rumor: UnsignedEvent) -> HistoryEntry {
+    let (content, progress_channel) = match Envelope::open(&rumor.content) {
+        Some(envelope) if envelope.message_type == MessageType::Progress => {
+            (envelope.body, Some("progress".to_string()))
+        }
+        Some(envelope) => (envelope.body, None),
+        None => (rumor.content, None),
+    };
+    HistoryEntry {
+        created_at: rumor.created_at,
+        direction: Direction::Incoming,
+        progress_channel,
+        content,
+    }
+}
+
+/// A transcript of the newest entries fitting in `max_bytes`, with how many older ones were
+/// left out; `include_progress` keeps progress messages in
+pub fn render(
+    entries: &[HistoryEntry],
+    include_progress: bool,
+    max_bytes: usize,
+) -> (String, usize) {
+    let lines: Vec<String> = entries
+        .iter()
+        .filter(|entry| include_progress || entry.progress_channel.is_none())
+        .map(render_line)
+        .collect();
+
+    // Fill the budget from the newest message backwards
+    let mut used = 0;
+    let mut kept = 0;
+    for line in lines.iter().rev() {
+        if used + line.len() + 1 > max_bytes {
+            break;
+        }
+        used += line.len() + 1;
+        kept += 1;
+    }
+    if kept == 0 {
+        // Even the newest message is too long: keep its end
+        return match lines.split_last() {
+            Some((line, older)) => (tail(line, max_bytes).to_string(), older.len()),
+            None => (String::new(), 0),
+        };
+    }
+    let omitted = lines.len() - kept;
+    (lines[omitted..].join("\n"), omitted)
+}
+
+fn render_line(entry: &HistoryEntry) -> String {
+    let who = match (entry.direction, &entry.progress_channel) {
+        (Direction::Incoming, None) => "← user".to_string(),
+        (Direction::Incoming, Some(channel)) => format!("← user ({})", channel),
+        (Direction::Outgoing, None) => "→ you".to_string(),
+        (Direction::Outgoing, Some(channel)) => format!("→ you ({})", channel),
+    };
+    format!(
+        "[{}] {}: {}",
+        entry.created_at.to_human_datetime(),
+        who,
+        entry.content
+    )
+}
+
+/// The last `max_bytes` bytes of `text`, cut at a character boundary
+fn tail(text: &str, max_bytes: usize) -> &str {
+    let mut start = text.len().saturating_sub(max_bytes);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(secs: u64, direction: Direction, progress: bool, content: &str) -> HistoryEntry {
+        HistoryEntry {
+            created_at: Timestamp::from(secs),
+            direction,
+            progress_channel: progress.then(|| "status".to_string()),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_render_truncates_from_the_oldest_side() {
+        let entries = vec![
+            entry(1, Direction::Incoming, false, "first question"),
+            entry(2, Direction::Outgoing, true, "working on it"),
+            entry(3, Direction::Outgoing, false, "first answer"),
+            entry(4, Direction::Incoming, false, "second question"),
+        ];
+
+        let (transcript, omitted) = render(&entries, false, usize::MAX);
+        assert_eq!(omitted, 0);
+        assert_eq!(transcript.lines().count(), 3);
+        assert!(transcript.contains("← user: first question"));
+        assert!(transcript.contains("→ you: first answer"));
+        assert!(!transcript.contains("working on it"));
+
+        let (transcript, _) = render(&entries, true, usize::MAX);
+        assert!(transcript.contains("→ you (status): working on it"));
+
+        let newest = render_line(&entries[3]);
+        let (transcript, omitted) = render(&entries, false, newest.len() + 1);
+        assert_eq!(transcript, newest);
+        assert_eq!(omitted, 2);
+
+        let (transcript, omitted) = render(&entries, false, 8);
+        assert_eq!(transcript, "question");
+        assert_eq!(omitted, 2);
+    }
+}
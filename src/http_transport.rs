@@ -0,0 +1,299 @@
+//! MCP over HTTP with server-sent events, as an alternative to stdio
+//!
+//! Clients open `GET /sse`, receive an `endpoint` event naming the URL to `POST` their
+//! JSON-RPC messages to, and get responses back as `message` events. Every SSE connection
+//! is its own MCP session on a clone of the server, so a dropped connection only ends that
+//! session; agents, Goose runs and other server state live on.
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use rmcp::model::ClientJsonRpcMessage;
+use rmcp::service::Service;
+use rmcp::service::TxJsonRpcMessage;
+use rmcp::{RoleServer, ServiceExt};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, RwLock};
+use tokio_util::sync::{CancellationToken, PollSender};
+
+use crate::shutdown::{Shutdown, GRACE_PERIOD};
+
+const SSE_PATH: &str = "/sse";
+const MESSAGE_PATH: &str = "/message";
+
+/// Largest JSON-RPC message accepted on the POST endpoint
+const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+type Body = BoxBody<Bytes, Infallible>;
+type Sessions = Arc<RwLock<HashMap<String, mpsc::Sender<ClientJsonRpcMessage>>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Transport {
+    Stdio,
+    Sse,
+}
+
+struct App<S> {
+    server: S,
+    bearer_token: Option<String>,
+    sessions: Sessions,
+    cancellation: CancellationToken,
+}
+
+/// Serves `server` over HTTP/SSE on `listen` until a shutdown signal arrives
+pub async fn serve<S>(
+    server: S,
+    listen: SocketAddr,
+    bearer_token: Option<String>,
+    shutdown: &Shutdown,
+    on_listening: impl FnOnce() -> std::io::Result<()>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: Service<RoleServer> + Clone,
+{
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    log::info!("MCP server listening on http://{}{}", listen, SSE_PATH);
+    on_listening()?;
+    if bearer_token.is_none() && !listen.ip().is_loopback() {
+        log::warn!(
+            "MCP server is reachable on {} without a bearer token",
+            listen
+        );
+    }
+
+    let app = Arc::new(App {
+        server,
+        bearer_token,
+        sessions: Arc::default(),
+        cancellation: CancellationToken::new(),
+    });
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = shutdown.requested() => break,
+        };
+
+        let app = app.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| handle(app.clone(), request));
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::debug!("Connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+
+    log::info!("Stopping MCP server");
+    app.cancellation.cancel();
+    let deadline = tokio::time::Instant::now() + GRACE_PERIOD;
+    while !app.sessions.read().await.is_empty() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+async fn handle<S>(
+    app: Arc<App<S>>,
+    request: Request<Incoming>,
+) -> Result<Response<Body>, Infallible>
+where
+    S: Service<RoleServer> + Clone,
+{
+    if !authorized(app.bearer_token.as_deref(), &request) {
+        return Ok(status(StatusCode::UNAUTHORIZED));
+    }
+
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, SSE_PATH) => open_session(&app).await,
+        (&Method::POST, MESSAGE_PATH) => post_message(&app, request).await,
+        _ => status(StatusCode::NOT_FOUND),
+    };
+    Ok(response)
+}
+
+fn authorized<B>(token: Option<&str>, request: &Request<B>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn open_session<S>(app: &App<S>) -> Response<Body>
+where
+    S: Service<RoleServer> + Clone,
+{
+    let session_id = format!("{:032x}", rand::random::<u128>());
+    let (from_client_tx, from_client_rx) = mpsc::channel::<ClientJsonRpcMessage>(64);
+    let (to_client_tx, to_client_rx) = mpsc::channel::<TxJsonRpcMessage<RoleServer>>(64);
+    app.sessions
+        .write()
+        .await
+        .insert(session_id.clone(), from_client_tx);
+
+    let sink = PollSender::new(to_client_tx).sink_map_err(std::io::Error::other);
+    let stream = tokio_stream_from(from_client_rx);
+    let server = app.server.clone();
+    let sessions = app.sessions.clone();
+    let session_cancellation = app.cancellation.child_token();
+    let cancellation = session_cancellation.clone();
+    let id = session_id.clone();
+    tokio::spawn(async move {
+        log::info!("MCP session {} opened", id);
+        match server.serve_with_ct((sink, stream), cancellation).await {
+            Ok(service) => {
+                let _ = service.waiting().await;
+            }
+            Err(e) => log::warn!("MCP session {} failed to start: {}", id, e),
+        }
+        sessions.write().await.remove(&id);
+        log::info!("MCP session {} closed", id);
+    });
+
+    let endpoint = sse_event(
+        "endpoint",
+        &format!("{}?sessionId={}", MESSAGE_PATH, session_id),
+    );
+    let messages = tokio_stream_from(to_client_rx).filter_map(|message| async move {
+        match serde_json::to_string(&message) {
+            Ok(json) => Some(sse_event("message", &json)),
+            Err(e) => {
+                log::error!("Could not serialize MCP message: {}", e);
+                None
+            }
+        }
+    });
+    // hyper drops the body when the client disconnects, which ends the session
+    let disconnect = session_cancellation.drop_guard();
+    let frames = futures::stream::once(async move { endpoint })
+        .chain(messages)
+        .map(move |event| {
+            let _ = &disconnect;
+            Ok::<_, Infallible>(Frame::data(event))
+        });
+
+    let mut response = Response::new(BodyExt::boxed(StreamBody::new(frames)));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, "text/event-stream".parse().unwrap());
+    headers.insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+    response
+}
+
+async fn post_message<S>(app: &App<S>, request: Request<Incoming>) -> Response<Body> {
+    let Some(session_id) = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("sessionId="))
+            .map(str::to_string)
+    }) else {
+        return status(StatusCode::BAD_REQUEST);
+    };
+
+    let sender = app.sessions.read().await.get(&session_id).cloned();
+    let Some(sender) = sender else {
+        return status(StatusCode::NOT_FOUND);
+    };
+
+    let body = match http_body_util::Limited::new(request.into_body(), MAX_MESSAGE_BYTES)
+        .collect()
+        .await
+    {
+        Ok(body) => body.to_bytes(),
+        Err(_) => return status(StatusCode::PAYLOAD_TOO_LARGE),
+    };
+    let message: ClientJsonRpcMessage = match serde_json::from_slice(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            log::debug!("Rejected malformed MCP message: {}", e);
+            return status(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    if sender.send(message).await.is_err() {
+        return status(StatusCode::GONE);
+    }
+    status(StatusCode::ACCEPTED)
+}
+
+fn sse_event(event: &str, data: &str) -> Bytes {
+    // Each line of the payload needs its own `data:` prefix
+    let mut out = format!("event: {}\n", event);
+    for line in data.lines() {
+        out.push_str("data: ");
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push('\n');
+    Bytes::from(out)
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(BodyExt::boxed(Full::new(Bytes::new())));
+    *response.status_mut() = code;
+    response
+}
+
+fn tokio_stream_from<T: Send + 'static>(
+    mut receiver: mpsc::Receiver<T>,
+) -> impl futures::Stream<Item = T> + Send + 'static {
+    futures::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bearer_token_check() {
+        let request = |header: Option<&str>| {
+            let mut builder = Request::builder().uri(SSE_PATH);
+            if let Some(header) = header {
+                builder = builder.header(AUTHORIZATION, header);
+            }
+            builder.body(()).unwrap()
+        };
+
+        assert!(authorized(None, &request(None)));
+        assert!(authorized(Some("s3cret"), &request(Some("Bearer s3cret"))));
+        assert!(!authorized(Some("s3cret"), &request(Some("Bearer wrong"))));
+        assert!(!authorized(Some("s3cret"), &request(Some("s3cret"))));
+        assert!(!authorized(Some("s3cret"), &request(None)));
+    }
+
+    #[test]
+    fn test_sse_event_framing() {
+        assert_eq!(
+            sse_event("message", "{\"a\":1}"),
+            Bytes::from("event: message\ndata: {\"a\":1}\n\n")
+        );
+        assert_eq!(
+            sse_event("message", "a\nb"),
+            Bytes::from("event: message\ndata: a\ndata: b\n\n")
+        );
+    }
+}
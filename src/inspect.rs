@@ -0,0 +1,160 @@
+//! The `inspect` subcommand: opens a raw gift wrap layer by layer and prints what is inside
+
+use crate::utils::{open_seal, open_wrap, UnwrapError};
+use nostr_sdk::prelude::*;
+use std::fmt::Write as _;
+
+/// Renders every layer of `gift_wrap` that `keys` can open, stopping at the first failure
+pub async fn run(keys: &Keys, gift_wrap: &Event) -> Result<String, (String, UnwrapError)> {
+    let mut report = String::new();
+    layer(&mut report, "Gift wrap", gift_wrap);
+    let wrap_signature = if gift_wrap.verify().is_ok() {
+        "valid"
+    } else {
+        "INVALID"
+    };
+    line(&mut report, "signature", wrap_signature);
+    line(
+        &mut report,
+        "note",
+        "author is a one-time key; created_at is randomized up to two days back",
+    );
+
+    let seal = match open_wrap(keys, gift_wrap).await {
+        Ok(seal) => seal,
+        Err(e) => return Err((report, e)),
+    };
+    report.push('\n');
+    layer(&mut report, "Seal", &seal);
+
+    let rumor = match open_seal(keys, &seal).await {
+        Ok(rumor) => rumor,
+        Err(e) => return Err((report, e)),
+    };
+    report.push('\n');
+    let id = rumor
+        .id
+        .map(|id| id.to_hex())
+        .unwrap_or_else(|| "(none)".to_string());
+    let _ = writeln!(report, "Rumor {}", id);
+    line(&mut report, "kind", &rumor.kind.to_string());
+    line(&mut report, "author", &author(&rumor.pubkey, keys));
+    line(&mut report, "created_at", &timestamp(rumor.created_at));
+    line(&mut report, "tags", &tags(&rumor.tags));
+    let sealed_by_author = if rumor.pubkey == seal.pubkey {
+        "yes"
+    } else {
+        "NO: the rumor claims a different author than the seal signer"
+    };
+    line(&mut report, "sealed by author", sealed_by_author);
+    let _ = writeln!(report, "\n{}", rumor.content);
+
+    Ok(report)
+}
+
+fn layer(report: &mut String, name: &str, event: &Event) {
+    let _ = writeln!(report, "{} {}", name, event.id);
+    line(report, "kind", &event.kind.to_string());
+    line(report, "author", &event.pubkey.to_hex());
+    line(report, "created_at", &timestamp(event.created_at));
+    line(report, "tags", &tags(&event.tags));
+}
+
+fn line(report: &mut String, label: &str, value: &str) {
+    let _ = writeln!(report, "  {:<18}{}", label, value);
+}
+
+fn author(pubkey: &PublicKey, keys: &Keys) -> String {
+    if *pubkey == keys.public_key() {
+        format!("{} (us)", pubkey.to_hex())
+    } else {
+        pubkey.to_hex()
+    }
+}
+
+fn timestamp(created_at: Timestamp) -> String {
+    format!(
+        "{} ({})",
+        created_at.as_u64(),
+        created_at.to_human_datetime()
+    )
+}
+
+fn tags(tags: &Tags) -> String {
+    if tags.is_empty() {
+        return "(none)".to_string();
+    }
+    serde_json::to_string(tags).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::build_private_msg;
+
+    #[tokio::test]
+    async fn test_reports_every_layer() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let wrap = build_private_msg(
+            &sender,
+            receiver.public_key(),
+            "hi there",
+            Some(60),
+            vec![],
+            0,
+        )
+        .await
+        .unwrap();
+
+        let report = run(&receiver, &wrap).await.unwrap();
+        assert!(report.starts_with(&format!("Gift wrap {}", wrap.id)));
+        assert!(report.contains("\nSeal "));
+        assert!(report.contains("\nRumor "));
+        assert!(report.contains(&sender.public_key().to_hex()));
+        assert!(report.contains("sealed by author  yes"));
+        assert!(report.contains("\"expiration\""));
+        assert!(report.ends_with("\nhi there\n"));
+    }
+
+    #[tokio::test]
+    async fn test_reports_failing_layer() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let wrap = build_private_msg(&sender, receiver.public_key(), "hi", None, vec![], 0)
+            .await
+            .unwrap();
+
+        let (report, err) = run(&Keys::generate(), &wrap).await.unwrap_err();
+        assert!(matches!(err, UnwrapError::Wrap(_)));
+        assert!(report.starts_with("Gift wrap"));
+        assert!(!report.contains("Seal"));
+
+        // A well-formed wrap around a seal whose content is not an encrypted rumor
+        let seal = EventBuilder::new(Kind::Seal, "garbage")
+            .sign_with_keys(&sender)
+            .unwrap();
+        let wrap_keys = Keys::generate();
+        let content = nip44::encrypt(
+            wrap_keys.secret_key(),
+            &receiver.public_key(),
+            seal.as_json(),
+            nip44::Version::default(),
+        )
+        .unwrap();
+        let wrap = EventBuilder::new(Kind::GiftWrap, content)
+            .tag(Tag::public_key(receiver.public_key()))
+            .sign_with_keys(&wrap_keys)
+            .unwrap();
+        let (report, err) = run(&receiver, &wrap).await.unwrap_err();
+        assert!(matches!(err, UnwrapError::Seal(_)));
+        assert!(report.contains("\nSeal "));
+        assert!(!report.contains("Rumor"));
+
+        let note = EventBuilder::text_note("hi")
+            .sign_with_keys(&sender)
+            .unwrap();
+        let (_, err) = run(&receiver, &note).await.unwrap_err();
+        assert!(matches!(err, UnwrapError::NotGiftWrap(Kind::TextNote)));
+    }
+}
@@ -0,0 +1,212 @@
+//! `/stop`: a message from the user that aborts whatever the agent is working on
+//!
+//! The `Chat` inbox checks every incoming message against the interrupt pattern. A match is never
+//! handed to `wait`; it raises the process-wide `Interrupt` instead. Running work reacts to it
+//! (`runtask` kills its Goose process, the multi-agent server stops its agents) and the next tool
+//! call answers with an "interrupted by user" result so the model can acknowledge it.
+
+use regex::Regex;
+use rmcp::model::{CallToolResult, ClientRequest, Content, ServerResult};
+use rmcp::service::{Peer, RequestContext, RoleServer, Service, ServiceRole};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
+
+/// What `--interrupt-pattern` defaults to
+pub const DEFAULT_PATTERN: &str = "/stop";
+
+lazy_static::lazy_static! {
+    static ref PATTERN: RwLock<Regex> =
+        RwLock::new(parse_pattern(DEFAULT_PATTERN).expect("default interrupt pattern is valid"));
+    static ref GLOBAL: Interrupt = Interrupt::new();
+}
+
+/// Compiles an `--interrupt-pattern`, which has to match the whole message (ignoring case)
+pub fn parse_pattern(value: &str) -> Result<Regex, String> {
+    Regex::new(&format!("(?i)^(?:{})$", value)).map_err(|e| e.to_string())
+}
+
+pub fn set_pattern(pattern: Regex) {
+    if let Ok(mut guard) = PATTERN.write() {
+        *guard = pattern;
+    }
+}
+
+/// Whether a message from the user is a request to stop
+pub fn is_interrupt(content: &str) -> bool {
+    PATTERN
+        .read()
+        .map(|pattern| pattern.is_match(content.trim()))
+        .unwrap_or(false)
+}
+
+/// The result a tool call gets instead of running (or finishing) once the user interrupted
+pub fn interrupted_result() -> CallToolResult {
+    let details = serde_json::json!({ "interrupted": true, "reason": "interrupted by user" });
+    CallToolResult::error(vec![
+        Content::text(
+            "Interrupted by user: the user asked to stop, so the current operation was cancelled. \
+             Tell the user with 'send' that you stopped, then 'wait' for new instructions.",
+        ),
+        Content::text(details.to_string()),
+    ])
+}
+
+/// An abort flag plus a signal for work that is running when it is raised
+#[derive(Debug, Clone)]
+pub struct Interrupt {
+    /// Set until a tool call has reported the interrupt to the model
+    pending: Arc<AtomicBool>,
+    /// Bumped on every interrupt, for the work that is in flight at that moment
+    signal: Arc<watch::Sender<u64>>,
+}
+
+impl Default for Interrupt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Self {
+            pending: Arc::new(AtomicBool::new(false)),
+            signal: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// The one the inbox raises and the servers consult
+    pub fn global() -> Self {
+        GLOBAL.clone()
+    }
+
+    pub fn request(&self) {
+        self.pending.store(true, Ordering::SeqCst);
+        self.signal.send_modify(|count| *count += 1);
+    }
+
+    /// Clears the flag, returning whether it was set
+    pub fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::SeqCst)
+    }
+
+    /// Notifies on every interrupt raised from now on
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.signal.subscribe()
+    }
+
+    /// Runs `work` until it finishes or an interrupt arrives, whichever comes first.
+    ///
+    /// Finished work wins a tie: its output is returned and the flag stays set, so an interrupt
+    /// landing just as the work completes is still reported by the next tool call.
+    pub async fn interruptible<F: Future>(&self, work: F) -> Option<F::Output> {
+        let mut signal = self.subscribe();
+        tokio::select! {
+            biased;
+            output = work => Some(output),
+            _ = signal.changed() => None,
+        }
+    }
+}
+
+/// Wraps an MCP server so the first tool call after an interrupt reports it instead of running
+#[derive(Debug, Clone)]
+pub struct Guarded<S> {
+    inner: S,
+}
+
+impl<S> Guarded<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S: Service<RoleServer>> Service<RoleServer> for Guarded<S> {
+    async fn handle_request(
+        &self,
+        request: ClientRequest,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ServerResult, rmcp::Error> {
+        if let ClientRequest::CallToolRequest(call) = &request {
+            if Interrupt::global().take() {
+                log::info!(
+                    "Reporting the user's interrupt instead of running {}",
+                    call.params.name
+                );
+                return Ok(ServerResult::CallToolResult(interrupted_result()));
+            }
+        }
+        self.inner.handle_request(request, context).await
+    }
+
+    async fn handle_notification(
+        &self,
+        notification: <RoleServer as ServiceRole>::PeerNot,
+    ) -> Result<(), rmcp::Error> {
+        self.inner.handle_notification(notification).await
+    }
+
+    fn get_peer(&self) -> Option<Peer<RoleServer>> {
+        self.inner.get_peer()
+    }
+
+    fn set_peer(&mut self, peer: Peer<RoleServer>) {
+        self.inner.set_peer(peer)
+    }
+
+    fn get_info(&self) -> <RoleServer as ServiceRole>::Info {
+        self.inner.get_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{sleep, Duration};
+
+    #[test]
+    fn test_pattern_matches_whole_message() {
+        let pattern = parse_pattern(DEFAULT_PATTERN).unwrap();
+        assert!(pattern.is_match("/stop"));
+        assert!(pattern.is_match("/STOP"));
+        assert!(!pattern.is_match("please don't /stop yet"));
+
+        let custom = parse_pattern("stop|halt!").unwrap();
+        assert!(custom.is_match("Halt!"));
+        assert!(!custom.is_match("stop it"));
+        assert!(parse_pattern("(unclosed").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_cancels_running_work() {
+        let interrupt = Interrupt::new();
+        let raiser = interrupt.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(20)).await;
+            raiser.request();
+        });
+
+        let outcome = interrupt
+            .interruptible(sleep(Duration::from_secs(30)))
+            .await;
+        assert!(outcome.is_none());
+        assert!(interrupt.take());
+        assert!(!interrupt.take());
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_racing_completion_keeps_result_and_flag() {
+        let interrupt = Interrupt::new();
+        let raiser = interrupt.clone();
+        // The interrupt lands in the same poll in which the work finishes
+        let work = async move {
+            raiser.request();
+            "done"
+        };
+
+        assert_eq!(interrupt.interruptible(work).await, Some("done"));
+        // Not lost: the next tool call still reports it
+        assert!(interrupt.take());
+    }
+}
@@ -0,0 +1,316 @@
+//! Guessing the language the user writes in, so agents can answer in it
+//!
+//! A small detector without a model: the script for non-Latin text, and common function words
+//! for the Latin-script languages below. It only answers when the evidence is clear and returns
+//! `unknown` for short or mixed messages rather than a confident wrong guess. The conversation
+//! language is the most frequent one among the user's recent messages.
+//!
+//! Supported: English, German, French, Spanish, Italian, Portuguese and Dutch by their words
+//! (`LATIN`), and Japanese, Chinese, Korean, Russian, Greek, Hebrew, Arabic, Hindi and Thai by
+//! their script (`detect_script`). Anything else comes out as `unknown`. This is not `whatlang`
+//! because that crate isn't available to our build; its trigram models would cover more
+//! languages, but the answers here only steer reply language, where `unknown` is harmless.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+pub const UNKNOWN: &str = "unknown";
+
+/// Latin-script messages need at least this many words
+const MIN_WORDS: usize = 3;
+/// Non-Latin messages need at least this many letters (a CJK character carries a whole word)
+const MIN_LETTERS: usize = 4;
+/// Detections the conversation language is taken from
+const RECENT: usize = 10;
+
+/// ISO 639-1 code, English name, and function words hardly used by the other languages here
+const LATIN: &[(&str, &str, &[&str])] = &[
+    (
+        "en",
+        "English",
+        &[
+            "the", "and", "you", "that", "this", "what", "with", "for", "have", "can", "how", "my",
+            "please", "it's", "i'm", "are", "was", "will", "of", "to", "is", "it", "not", "be",
+            "do", "your", "there", "about", "could", "would", "thanks", "hello",
+        ],
+    ),
+    (
+        "de",
+        "German",
+        &[
+            "und", "ist", "nicht", "ich", "das", "der", "den", "dem", "ein", "eine", "du", "wir",
+            "mit", "auf", "für", "bitte", "kannst", "wie", "zu", "noch", "auch", "mir", "mich",
+            "bei", "aber", "oder", "wenn", "jetzt", "schon", "habe", "hast", "sind", "danke",
+            "hallo", "bin", "dass", "nach", "sie", "können", "kann",
+        ],
+    ),
+    (
+        "fr",
+        "French",
+        &[
+            "le", "la", "les", "et", "est", "je", "tu", "vous", "nous", "une", "des", "du", "pas",
+            "qui", "pour", "avec", "dans", "ce", "sur", "mais", "merci", "bonjour", "peux", "suis",
+            "très", "c'est", "j'ai", "au", "aux", "ça",
+        ],
+    ),
+    (
+        "es",
+        "Spanish",
+        &[
+            "el", "los", "las", "y", "por", "para", "con", "yo", "tú", "qué", "cómo", "pero",
+            "gracias", "hola", "puedes", "muy", "también", "esto", "del", "al", "es", "lo", "está",
+            "hay", "mi", "favor",
+        ],
+    ),
+    (
+        "it",
+        "Italian",
+        &[
+            "il", "gli", "è", "di", "che", "non", "sono", "per", "mi", "ti", "ciao", "grazie",
+            "puoi", "questo", "anche", "come", "perché", "della", "molto", "ho", "hai", "nel",
+        ],
+    ),
+    (
+        "pt",
+        "Portuguese",
+        &[
+            "o", "os", "não", "um", "uma", "do", "da", "você", "obrigado", "obrigada", "olá",
+            "pode", "isso", "muito", "mas", "em", "eu", "com", "por", "favor", "está", "ao", "é",
+            "no", "na",
+        ],
+    ),
+    (
+        "nl",
+        "Dutch",
+        &[
+            "het",
+            "een",
+            "en",
+            "niet",
+            "ik",
+            "je",
+            "jij",
+            "wij",
+            "van",
+            "op",
+            "met",
+            "voor",
+            "dat",
+            "wat",
+            "kun",
+            "kunt",
+            "alsjeblieft",
+            "bedankt",
+            "hallo",
+            "ook",
+            "maar",
+            "zijn",
+            "naar",
+            "mijn",
+            "deze",
+            "is",
+        ],
+    ),
+];
+
+lazy_static::lazy_static! {
+    static ref CONVERSATION: Mutex<Tracker> = Mutex::new(Tracker::default());
+}
+
+/// The ISO 639-1 code of the language `text` is written in, or `unknown`
+pub fn detect(text: &str) -> &'static str {
+    if let Some(language) = detect_script(text) {
+        return language;
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| !(c.is_alphabetic() || c == '\''))
+        .map(|word| word.trim_matches('\'').to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.len() < MIN_WORDS {
+        return UNKNOWN;
+    }
+    let mut scores: Vec<(&'static str, usize)> = LATIN
+        .iter()
+        .map(|(code, _, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|word| stopwords.contains(&word.as_str()))
+                .count();
+            (*code, hits)
+        })
+        .collect();
+    scores.sort_by_key(|(_, hits)| std::cmp::Reverse(*hits));
+    let (best, hits) = scores[0];
+    let runner_up = scores[1].1;
+    // At least two function words, and clearly more than any other language
+    if hits >= 2 && hits > runner_up * 3 / 2 {
+        best
+    } else {
+        UNKNOWN
+    }
+}
+
+/// The language of text mostly in a script only one of the supported languages uses
+fn detect_script(text: &str) -> Option<&'static str> {
+    let mut letters = 0;
+    let mut counts: Vec<(&'static str, usize)> = Vec::new();
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        let language = match c as u32 {
+            0x3040..=0x30FF => "ja",
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF => "zh",
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => "ko",
+            0x0400..=0x04FF => "ru",
+            0x0370..=0x03FF => "el",
+            0x0590..=0x05FF => "he",
+            0x0600..=0x06FF => "ar",
+            0x0900..=0x097F => "hi",
+            0x0E00..=0x0E7F => "th",
+            _ => continue,
+        };
+        match counts.iter_mut().find(|(code, _)| *code == language) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((language, 1)),
+        }
+    }
+    // Japanese mixes kana with kanji, so any kana makes it Japanese
+    if let Some(kana) = counts
+        .iter()
+        .find(|(code, _)| *code == "ja")
+        .map(|(_, n)| *n)
+    {
+        let kanji = counts
+            .iter()
+            .find(|(code, _)| *code == "zh")
+            .map_or(0, |(_, n)| *n);
+        counts.retain(|(code, _)| *code != "zh" && *code != "ja");
+        counts.push(("ja", kana + kanji));
+    }
+    let (language, count) = counts.into_iter().max_by_key(|(_, count)| *count)?;
+    // Mostly non-Latin; otherwise the words decide
+    let language = if count >= MIN_LETTERS {
+        language
+    } else {
+        UNKNOWN
+    };
+    (count * 2 > letters).then_some(language)
+}
+
+/// The English name of a language code, for hints to agents
+pub fn name(code: &str) -> Option<&'static str> {
+    const OTHERS: &[(&str, &str)] = &[
+        ("ja", "Japanese"),
+        ("zh", "Chinese"),
+        ("ko", "Korean"),
+        ("ru", "Russian"),
+        ("el", "Greek"),
+        ("he", "Hebrew"),
+        ("ar", "Arabic"),
+        ("hi", "Hindi"),
+        ("th", "Thai"),
+    ];
+    LATIN
+        .iter()
+        .map(|(code, name, _)| (*code, *name))
+        .chain(OTHERS.iter().copied())
+        .find(|(known, _)| *known == code)
+        .map(|(_, name)| name)
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    recent: VecDeque<String>,
+}
+
+impl Tracker {
+    fn observe(&mut self, language: &str) {
+        if language == UNKNOWN {
+            return;
+        }
+        if self.recent.len() == RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(language.to_string());
+    }
+
+    /// The most frequent recent language, the latest one on a tie
+    fn language(&self) -> String {
+        let mut best = (UNKNOWN, 0);
+        for language in &self.recent {
+            let count = self
+                .recent
+                .iter()
+                .filter(|other| *other == language)
+                .count();
+            if count >= best.1 {
+                best = (language.as_str(), count);
+            }
+        }
+        best.0.to_string()
+    }
+}
+
+/// Counts a message from the user towards the conversation language
+pub fn observe(language: &str) {
+    if let Ok(mut tracker) = CONVERSATION.lock() {
+        tracker.observe(language);
+    }
+}
+
+/// The language the user has mostly been writing in this session, or `unknown`
+pub fn conversation() -> String {
+    CONVERSATION
+        .lock()
+        .map(|tracker| tracker.language())
+        .unwrap_or_else(|_| UNKNOWN.to_string())
+}
+
+/// `task` with a note to answer in the conversation language, unless that is English or unknown
+pub fn with_hint(task: &str) -> String {
+    let language = conversation();
+    match name(&language) {
+        Some(name) if language != "en" => format!(
+            "{}\n\nThe user writes in {}; reply to them in {}.",
+            task, name, name
+        ),
+        _ => task.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("Kannst du mir bitte mit dem Build helfen?"), "de");
+        assert_eq!(detect("Can you please check why the build failed?"), "en");
+        assert_eq!(detect("Bonjour, peux-tu vérifier le déploiement ?"), "fr");
+        assert_eq!(detect("¿Puedes revisar el despliegue por favor?"), "es");
+        assert_eq!(detect("Kun je de build voor mij controleren?"), "nl");
+        assert_eq!(detect("ビルドが失敗した理由を確認してください"), "ja");
+        assert_eq!(detect("Почему сборка упала?"), "ru");
+
+        // Too short or ambiguous to tell
+        assert_eq!(detect("ok"), UNKNOWN);
+        assert_eq!(detect("danke"), UNKNOWN);
+        assert_eq!(detect("deploy staging now"), UNKNOWN);
+        assert_eq!(detect("да"), UNKNOWN);
+        assert_eq!(detect("👍"), UNKNOWN);
+    }
+
+    #[test]
+    fn test_conversation_language() {
+        let mut tracker = Tracker::default();
+        assert_eq!(tracker.language(), UNKNOWN);
+        for language in ["de", UNKNOWN, "en", "de"] {
+            tracker.observe(language);
+        }
+        assert_eq!(tracker.language(), "de");
+        tracker.observe("en");
+        // A tie goes to the latest
+        assert_eq!(tracker.language(), "en");
+    }
+}
@@ -0,0 +1,199 @@
+//! The log file of the MCP servers and `onmessage`, which must keep stdio free
+//!
+//! The file rotates by size: once a record would push it past the limit it is renamed to
+//! `<file>.1`, older ones shift up to `<file>.<keep>`, and the oldest is dropped. env_logger hands
+//! each record over in a single write while holding its own lock, so rotation only ever happens
+//! between whole lines no matter how many tasks are logging.
+
+use crate::redact;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// env_logger's usual human readable lines
+    Text,
+    /// One JSON object per line with timestamp, level, module and message
+    Json,
+}
+
+/// The default log file, next to the rest of the local state
+pub fn default_log_file(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join("nparrot.log")
+}
+
+/// Sets `builder`'s line format, with secrets masked in every message
+pub fn apply_format(builder: &mut env_logger::Builder, format: LogFormat) {
+    match format {
+        LogFormat::Json => builder.format(|buf, record| {
+            let line = json_line(
+                &buf.timestamp_millis().to_string(),
+                record.level(),
+                record.module_path().unwrap_or(record.target()),
+                &redact::redact(&record.args().to_string()),
+            );
+            writeln!(buf, "{}", line)
+        }),
+        // env_logger's default layout, without colours
+        LogFormat::Text => builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "[{} {:<5} {}] {}",
+                buf.timestamp(),
+                record.level(),
+                record.module_path().unwrap_or(record.target()),
+                redact::redact(&record.args().to_string())
+            )
+        }),
+    };
+}
+
+fn json_line(timestamp: &str, level: log::Level, module: &str, message: &str) -> String {
+    serde_json::json!({
+        "timestamp": timestamp,
+        "level": level.as_str(),
+        "module": module,
+        "message": message,
+    })
+    .to_string()
+}
+
+/// Logs `RUST_LOG` records to `path`, rotating it every `max_bytes`
+pub fn init_file(path: &Path, max_bytes: u64, keep: usize, format: LogFormat) -> io::Result<()> {
+    let file = RotatingFile::open(path.to_path_buf(), max_bytes, keep)?;
+    let mut builder = env_logger::Builder::from_env("RUST_LOG");
+    apply_format(&mut builder, format);
+    builder
+        .target(env_logger::Target::Pipe(Box::new(file)))
+        .init();
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Appends to `path`, creating it and its directory if needed
+    pub fn open(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep > 0 {
+            let _ = std::fs::remove_file(self.rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        // With nothing to keep the file simply starts over
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A single record larger than the limit still goes into one file
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            if let Err(e) = self.rotate() {
+                // Keep logging to the current file rather than losing records
+                eprintln!("Could not rotate {}: {}", self.path.display(), e);
+            }
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_and_keeps_limited_files() {
+        let dir = std::env::temp_dir().join(format!("nparrot-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("nested").join("nparrot.log");
+
+        let mut file = RotatingFile::open(path.clone(), 20, 2).unwrap();
+        for line in [
+            "line 1 ........\n",
+            "line 2 ........\n",
+            "line 3 ........\n",
+        ] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        drop(file);
+        // Reopening continues from the current size
+        let mut file = RotatingFile::open(path.clone(), 20, 2).unwrap();
+        file.write_all(b"line 4 ........\n").unwrap();
+
+        let read = |p: &Path| std::fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "line 4 ........\n");
+        assert_eq!(read(&dir.join("nested/nparrot.log.1")), "line 3 ........\n");
+        assert_eq!(read(&dir.join("nested/nparrot.log.2")), "line 2 ........\n");
+        assert!(!dir.join("nested/nparrot.log.3").exists());
+
+        let mut file = RotatingFile::open(path.clone(), 20, 0).unwrap();
+        file.write_all(b"line 5 ........\n").unwrap();
+        assert_eq!(read(&path), "line 5 ........\n");
+        assert_eq!(read(&dir.join("nested/nparrot.log.1")), "line 3 ........\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_line() {
+        let line = json_line(
+            "2026-01-02T03:04:05.678Z",
+            log::Level::Warn,
+            "nparrot::mcp::chat",
+            "said \"hi\"\nthen left",
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["timestamp"], "2026-01-02T03:04:05.678Z");
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["module"], "nparrot::mcp::chat");
+        assert_eq!(value["message"], "said \"hi\"\nthen left");
+        assert!(!line.contains('\n'));
+    }
+}
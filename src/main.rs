@@ -1,25 +1,109 @@
 //! CLI utility tool for one-on-one private messaging on Nostr for CLI and agent use
 //!
 //! It uses the `nostr_sdk` crate to interact with the Nostr network. It sends and receives direct messages that are encrypted with NIP-17 by default.
+
+// Leaving a subsystem out also leaves unused the shared tooling only its servers call
+#![cfg_attr(
+    not(all(
+        feature = "goose",
+        feature = "searxng",
+        feature = "multi-agent",
+        feature = "memory"
+    )),
+    allow(dead_code, unused_imports)
+)]
+
+mod ack;
+mod announce;
+mod at_rest;
+mod audit;
+mod catalog;
+#[cfg(all(feature = "goose", feature = "searxng"))]
 mod combined_mcp;
+mod command_template;
+mod config;
+mod content_kind;
+#[cfg(unix)]
+mod daemon;
+#[cfg(all(feature = "goose", feature = "searxng"))]
+mod digest;
+mod doctor;
+mod dry_run;
+#[cfg(test)]
+mod e2e;
+mod envelope;
+mod error;
+mod failover;
+mod filter;
+#[cfg(feature = "goose")]
 mod goose_mcp;
+mod group;
+mod history;
+mod http_transport;
+mod inspect;
+mod interrupt;
+mod language;
+mod logging;
 mod mcp;
+mod media;
+mod message_size;
+mod message_template;
+mod metrics;
+#[cfg(feature = "multi-agent")]
 mod multi_agent;
+#[cfg(feature = "memory")]
 mod nostr_mcp;
+mod outbox;
+mod output;
+mod ping;
+mod pow;
+mod presence;
 mod process_management;
 mod profile;
+mod progress_channels;
+mod progress_history;
+mod readiness;
+mod redact;
+mod redelivery;
+mod relays;
+mod replay;
+#[cfg(all(feature = "goose", feature = "searxng"))]
+mod research;
 mod response_tracker;
+mod retry;
+mod rotate_key;
+mod schedule;
+#[cfg(feature = "searxng")]
 mod searxng_mcp;
+mod selftest;
+mod senders;
+mod shutdown;
+mod spool;
+mod timezone;
+mod transcript;
+mod transport;
 mod utils;
+mod wallet;
+mod zap;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+#[cfg(all(feature = "goose", feature = "searxng"))]
 use combined_mcp::CombinedServer;
+use config::units::parse_duration_secs;
+use config::units::parse_size_bytes;
 use dotenv::dotenv;
+use envelope::MessageType;
+#[cfg(feature = "goose")]
 use goose_mcp::GooseServer;
-use mcp::{chat::Chat, EnhancedMcpServer};
+use mcp::chat::Chat;
+#[cfg(feature = "memory")]
+use mcp::EnhancedMcpServer;
+#[cfg(feature = "multi-agent")]
 use multi_agent::MultiAgentMcp;
+#[cfg(feature = "memory")]
 use nostr_mcp::NostrMemoryServer;
 use nostr_sdk::prelude::*;
+use output::{detail, status};
 use rmcp::{transport::stdio, ServiceExt};
 use std::sync::Arc;
 use std::{
@@ -27,29 +111,494 @@ use std::{
     process::exit,
 };
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use utils::listen_for_messages;
+use utils::parse_event_id;
+use utils::reply_tags;
 use utils::run_command_on_message;
-use utils::wait_for_message;
+use utils::send_private_msg;
+use utils::wait_for_messages;
+use utils::IncomingMessage;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Pubkey of the target user to talk to via DMs (in bech32 format)
+    /// Pubkey of the target user to talk to via DMs (in bech32 format); required by the
+    /// commands that talk to the user
     #[arg(long, env = "TARGET_PUBKEY")]
-    target_pubkey: String,
+    target_pubkey: Option<String>,
 
-    /// The private key (nsec) identity to use on the DMs
-    #[arg(long, env = "NSEC")]
-    nsec: String,
+    /// The private key (nsec) identity to use on the DMs; required by every command that uses
+    /// Nostr
+    #[arg(long, env = "NSEC", hide_env_values = true)]
+    nsec: Option<String>,
 
     /// Optional private key (nsec) identity to use for progress/debug DMs
-    #[arg(long, env = "PROGRESS_NSEC")]
+    #[arg(long, env = "PROGRESS_NSEC", hide_env_values = true)]
     progress_nsec: Option<String>,
 
-    /// Relay URL to use for sending/receiving messages
+    /// Further progress identities by channel, e.g. `--progress debug=nsec1…` (repeatable);
+    /// PROGRESS_NSEC is the `status` channel unless one is given here
+    #[arg(
+        long = "progress",
+        env = "NPARROT_PROGRESS",
+        value_name = "CHANNEL=NSEC",
+        value_delimiter = ',',
+        value_parser = progress_channels::parse_channel_arg,
+        hide_env_values = true
+    )]
+    progress_channels: Vec<(String, String)>,
+
+    /// Relay URL to use for sending/receiving messages; several are comma-separated and may
+    /// carry tags (e.g. `wss://paid#primary,wss://free1#bulk`)
     #[arg(long, env = "RELAY_URL", default_value = "wss://relay.damus.io")]
     relay: String,
 
+    /// Relays for the progress identities instead of `RELAY_URL`, e.g. a private relay that
+    /// only the user reads; same format, tags included
+    #[arg(long, env = "PROGRESS_RELAY_URL")]
+    progress_relay: Option<String>,
+
+    /// Which tagged relays each channel publishes to (e.g. `main=primary,debug=bulk,status=all`);
+    /// by default `main` uses all relays and progress channels the `bulk` ones, if any
+    #[arg(long, env = "NPARROT_RELAY_ROUTES", value_parser = relays::RelayRoutes::parse)]
+    relay_routes: Option<relays::RelayRoutes>,
+
+    /// Talk in a NIP-29 group instead of DMs: `send`, `wait`, `listen` and the MCP servers' `send`
+    /// and `wait` use `<group-id>@<relay>`; progress still goes to TARGET_PUBKEY as DMs
+    #[arg(long, env = "NPARROT_GROUP", value_parser = group::GroupTarget::parse)]
+    group: Option<group::GroupTarget>,
+
+    /// Standby relays (comma-separated), only connected while fewer than `--min-relays` of the
+    /// main relays are
+    #[arg(long, env = "RELAY_FALLBACK")]
+    relay_fallback: Option<String>,
+
+    /// How many relays must be connected; below that `send` and friends fail and servers run
+    /// degraded
+    #[arg(long, env = "NPARROT_MIN_RELAYS", default_value_t = 1)]
+    min_relays: usize,
+
+    /// Default expiration for progress messages sent by the enhanced and multi-agent servers
+    /// (e.g. 30m, 12h, 1d; 0 keeps them forever)
+    #[arg(
+        long,
+        env = "NPARROT_PROGRESS_EXPIRE_AFTER",
+        default_value = "1d",
+        value_parser = parse_duration_secs
+    )]
+    progress_expire_after: u64,
+
+    /// NIP-13 proof-of-work difficulty for outgoing events, either a number for all relays or
+    /// per relay (e.g. `wss://nos.lol=24,wss://relay.damus.io=0`)
+    #[arg(long, env = "NPARROT_POW", value_parser = pow::PowPolicy::parse)]
+    pow: Option<pow::PowPolicy>,
+
+    #[cfg(feature = "searxng")]
+    /// SearXNG instance used for web search
+    #[arg(long, env = "SEARXNG_URL", default_value = "https://searx.stream")]
+    searxng_url: String,
+
+    #[cfg(feature = "searxng")]
+    /// How long each SearXNG request may take (e.g. 30s, 2m)
+    #[arg(long, env = "NPARROT_SEARXNG_TIMEOUT", default_value = "30s", value_parser = parse_duration_secs)]
+    searxng_timeout: u64,
+
+    /// Directory for notes, events and other local state
+    #[arg(long, env = "NPARROT_DATA_DIR", default_value = "data")]
+    data_dir: String,
+
+    #[cfg(feature = "goose")]
+    /// Goose binary to run for agent tasks
+    #[arg(long, env = "GOOSE_BIN", default_value = "goose")]
+    goose_bin: String,
+
+    #[cfg(feature = "goose")]
+    /// How long a Goose command may run before it is terminated (e.g. 5m, 1h)
+    #[arg(long, env = "NPARROT_GOOSE_TIMEOUT", default_value = "5m", value_parser = parse_duration_secs)]
+    goose_timeout: u64,
+
+    /// How long settled deliveries stay visible to the `delivery_status` tool (e.g. 30m, 1h)
+    #[arg(
+        long,
+        env = "NPARROT_DELIVERY_RETENTION",
+        default_value = "1h",
+        value_parser = parse_duration_secs
+    )]
+    delivery_retention: u64,
+
+    /// What happens to a second `send` answering the same user message: `strict` reroutes it to
+    /// the progress channel, `warn` sends it and logs a warning, `off` sends it
+    #[arg(
+        long,
+        env = "NPARROT_RESPONSE_POLICY",
+        value_enum,
+        default_value = "warn"
+    )]
+    response_policy: response_tracker::ResponsePolicy,
+
+    /// How long an event with only a start or end time is assumed to last when checking
+    /// `addevent` for overlaps (e.g. 30m, 1h)
+    #[arg(
+        long,
+        env = "NPARROT_EVENT_DURATION",
+        default_value = "1h",
+        value_parser = parse_duration_secs
+    )]
+    event_duration: u64,
+
+    /// How many times to resend a message every relay rejected before giving up (0 disables)
+    #[arg(long, env = "NPARROT_RESEND_ATTEMPTS", default_value_t = 3)]
+    resend_attempts: u32,
+
+    /// How often to sample CPU and memory of spawned processes (e.g. 5s, 1m)
+    #[arg(
+        long,
+        env = "NPARROT_SAMPLE_INTERVAL",
+        default_value = "5s",
+        value_parser = parse_duration_secs
+    )]
+    sample_interval: u64,
+
+    /// Terminate any spawned process whose resident memory exceeds this (e.g. 2G, 512M)
+    #[arg(long, env = "NPARROT_MAX_CHILD_RSS", value_parser = parse_size_bytes)]
+    max_child_rss: Option<u64>,
+
+    /// Also mask keys starting with these prefixes (comma-separated, e.g. `acme_live_`) in logs
+    /// and outgoing messages
+    #[arg(
+        long = "redact-prefix",
+        env = "NPARROT_REDACT_PREFIXES",
+        value_delimiter = ','
+    )]
+    redact_prefixes: Vec<String>,
+
+    /// Only pass these environment variables (names or `*` patterns, comma-separated) to spawned
+    /// processes; keys, wallet URI and tokens are withheld either way
+    #[arg(long, env = "NPARROT_ENV_ALLOWLIST", value_delimiter = ',')]
+    env_allowlist: Vec<String>,
+
+    /// Write this file once an MCP server is ready, rewrite it every 30s while it runs and
+    /// remove it on shutdown (e.g. /run/nparrot/ready)
+    #[arg(long, env = "NPARROT_HEALTH_FILE")]
+    health_file: Option<std::path::PathBuf>,
+
+    /// How the MCP server subcommands talk to their client
+    #[arg(
+        long,
+        env = "NPARROT_MCP_TRANSPORT",
+        value_enum,
+        default_value = "stdio"
+    )]
+    transport: http_transport::Transport,
+
+    /// Address the MCP server listens on with `--transport sse`
+    #[arg(long, env = "NPARROT_MCP_LISTEN", default_value = "127.0.0.1:8977")]
+    listen: std::net::SocketAddr,
+
+    /// Bearer token MCP clients must send with `--transport sse`
+    #[arg(long, env = "NPARROT_MCP_TOKEN", hide_env_values = true)]
+    mcp_token: Option<String>,
+
+    /// Serve Prometheus metrics on this address (e.g. 127.0.0.1:9187)
+    #[arg(long, env = "NPARROT_METRICS_LISTEN")]
+    metrics_listen: Option<std::net::SocketAddr>,
+
+    /// Wrap outgoing messages in a JSON envelope for other nparrot instances to parse
+    /// (incoming envelopes are always understood)
+    #[arg(long, env = "NPARROT_ENVELOPE")]
+    envelope: bool,
+
+    /// Lightning address (name@domain) or LNURL-pay URL: zap receipts from its provider reach
+    /// the listeners as `zap` messages, and `request_zap` asks it for invoices
+    #[arg(long, env = "NPARROT_LUD16")]
+    lud16: Option<String>,
+
+    /// Nostr Wallet Connect URI (`nostr+walletconnect://...`); gives `combined-mcp` the
+    /// `pay_invoice` and `get_balance` tools
+    #[arg(long, env = "NWC_URI", hide_env_values = true)]
+    nwc_uri: Option<String>,
+
+    /// Largest single payment `pay_invoice` makes, in sats
+    #[arg(long, env = "NPARROT_WALLET_MAX_PAYMENT", default_value_t = 1000)]
+    wallet_max_payment: u64,
+
+    /// Most `pay_invoice` spends in any 24 hours, in sats
+    #[arg(long, env = "NPARROT_WALLET_DAILY_BUDGET", default_value_t = 5000)]
+    wallet_daily_budget: u64,
+
+    /// Blossom or NIP-96 server `upload_and_send_file` uploads to
+    #[arg(long, env = "NPARROT_MEDIA_SERVER")]
+    media_server: Option<String>,
+
+    /// Upload protocol the media server speaks
+    #[arg(
+        long,
+        env = "NPARROT_MEDIA_PROTOCOL",
+        value_enum,
+        default_value = "blossom"
+    )]
+    media_protocol: media::MediaProtocol,
+
+    /// Largest file `upload_and_send_file` uploads (e.g. 10M)
+    #[arg(
+        long,
+        env = "NPARROT_MAX_UPLOAD_SIZE",
+        default_value = "10M",
+        value_parser = parse_size_bytes
+    )]
+    max_upload_size: u64,
+
+    /// Largest event the MCP servers send to relays that don't publish a limit in NIP-11 (e.g.
+    /// 64K); longer messages are truncated with a note
+    #[arg(
+        long,
+        env = "NPARROT_MAX_MESSAGE_SIZE",
+        default_value = "64K",
+        value_parser = parse_size_bytes
+    )]
+    max_message_size: u64,
+
+    /// Most subscriptions held open at once on relays that don't publish a limit in NIP-11;
+    /// further REQs wait for one to close (0 for no limit)
+    #[arg(
+        long,
+        env = "NPARROT_MAX_SUBSCRIPTIONS",
+        default_value_t = transport::subscriptions::DEFAULT_MAX_SUBSCRIPTIONS
+    )]
+    max_subscriptions: usize,
+
+    /// Don't publish the kind-0 profiles on startup (one-shot sends never do)
+    #[arg(long, env = "NPARROT_NO_PROFILE")]
+    no_profile: bool,
+
+    /// Turn every `deletenote`, `deleteevent`, `delete_memory`, `removesession` and `stop_agent`
+    /// call (and `deletenotes`, tag renames and expired-memory cleanup) into a dry run that only
+    /// reports what it would change
+    #[arg(long, env = "NPARROT_FORCE_DRY_RUN")]
+    force_dry_run: bool,
+
+    /// Send a NIP-25 reaction to every accepted message so the sender sees it arrived
+    #[arg(long, env = "NPARROT_ACK_REACTIONS")]
+    ack_reactions: bool,
+
+    /// Send a progress DM when an MCP server starts (version, tools, relays, data dir) and
+    /// when it stops
+    #[arg(long, env = "NPARROT_ANNOUNCE_START")]
+    announce_start: bool,
+
+    /// Log every message sent and received to daily JSON-lines files under the data dir, with
+    /// the content encrypted with the data key
+    #[arg(long, env = "NPARROT_TRANSCRIPT")]
+    transcript: bool,
+
+    /// Most progress text kept for the progress_history tool (e.g. 256K); the oldest messages
+    /// are dropped first
+    #[arg(
+        long,
+        env = "NPARROT_PROGRESS_HISTORY_SIZE",
+        default_value = "256K",
+        value_parser = parse_size_bytes
+    )]
+    progress_history_size: u64,
+
+    /// Reject incoming messages written longer ago than this, besides the ones already processed;
+    /// 0 only rejects those
+    #[arg(long, env = "NPARROT_REPLAY_MAX_AGE", default_value = "48h", value_parser = parse_duration_secs)]
+    replay_max_age: u64,
+
+    /// What a server or onmessage does when another instance already answers for this key
+    /// (its presence event is fresh, or it holds the data dir's lock): `refuse` to start,
+    /// start `passive` (receive and log, publish nothing) or `ignore` it. Two instances starting
+    /// within a few seconds of each other can both miss the other
+    #[arg(
+        long,
+        env = "NPARROT_MULTI_INSTANCE",
+        value_enum,
+        default_value = "refuse"
+    )]
+    multi_instance: presence::MultiInstance,
+
+    /// Expose the get_audit_log tool, listing recent tool calls, in the MCP servers; the calls
+    /// are logged to audit.jsonl under the data dir either way
+    #[arg(long, env = "NPARROT_AUDIT_TOOL")]
+    audit_tool: bool,
+
+    /// Save the values of the context tools (set_context) to the data dir so they survive a
+    /// restart; otherwise they only last as long as the process
+    #[arg(long, env = "NPARROT_PERSIST_CONTEXT")]
+    persist_context: bool,
+
+    /// Frames every message the MCP tools send, e.g. "{body}\n\n— {bot_name}"; placeholders are
+    /// {body}, {bot_name}, {channel} and {timestamp}
+    #[arg(long, env = "NPARROT_MESSAGE_TEMPLATE")]
+    message_template: Option<String>,
+
+    /// TOML file replacing the texts nparrot sends the user ("task_completed = \"Done\"", ...);
+    /// keys it leaves out keep their English default
+    #[arg(long, env = "NPARROT_MESSAGES")]
+    messages: Option<std::path::PathBuf>,
+
+    /// Milliseconds to wait for follow-ups to a message that doesn't end a sentence, handing
+    /// them to `wait` as one message (0 delivers each message at once)
+    #[arg(long, env = "NPARROT_COALESCE_MS", default_value_t = 0)]
+    coalesce_ms: u64,
+
+    /// How long the enhanced and combined servers wait for any tool call after `wait` returns
+    /// a message before telling the user the assistant seems delayed, and again before
+    /// apologizing on the main channel (0 turns it off)
+    #[arg(long, env = "NPARROT_STALL_AFTER", default_value = "5m", value_parser = parse_duration_secs)]
+    stall_after: u64,
+
+    /// How long an identical `send` to the same recipient is skipped as a duplicate (e.g. 30s;
+    /// 0 sends every copy)
+    #[arg(long, env = "NPARROT_DEDUP_WINDOW", default_value = "30s", value_parser = parse_duration_secs)]
+    dedup_window: u64,
+
+    /// The same for identical progress messages on the same channel
+    #[arg(
+        long,
+        env = "NPARROT_PROGRESS_DEDUP_WINDOW",
+        default_value = "10s",
+        value_parser = parse_duration_secs
+    )]
+    progress_dedup_window: u64,
+
+    /// How much `get_info` of the enhanced and combined servers tells the model: `compact` keeps
+    /// only the mandatory workflow and JSON rules, `minimal` is a few lines for clients that bring
+    /// their own system prompt
+    #[arg(long, env = "NPARROT_INSTRUCTIONS", value_enum, default_value = "full")]
+    instructions: mcp::instructions::InstructionProfile,
+
+    /// The name {bot_name} stands for in --message-template
+    #[arg(long, env = "NPARROT_BOT_NAME")]
+    bot_name: Option<String>,
+
+    /// Further senders (npub or hex, comma-separated) the MCP servers take messages from besides
+    /// the target user; each gets their own queue, and `send` answers them with `to`
+    #[arg(
+        long = "allow-sender",
+        env = "NPARROT_ALLOW_SENDERS",
+        value_delimiter = ',',
+        value_parser = senders::parse
+    )]
+    allow_senders: Vec<PublicKey>,
+
+    /// Send a `send` without `to` that answers one of the --allow-sender senders to the target
+    /// user instead of refusing it
+    #[arg(long, env = "NPARROT_UNADDRESSED_TO_TARGET")]
+    unaddressed_to_target: bool,
+
+    /// Let the MCP `send` tool address anyone with `to`, not only the target and the
+    /// --allow-sender senders
+    #[arg(long, env = "ALLOW_ARBITRARY_RECIPIENTS")]
+    allow_arbitrary_recipients: bool,
+
+    /// Answer DMs starting with this prefix (e.g. `!`) as admin commands without the model;
+    /// off unless set
+    #[arg(long, env = "NPARROT_COMMAND_PREFIX")]
+    command_prefix: Option<String>,
+
+    /// Further commands as name=action (status, agents, stop, help or reply:<text>,
+    /// comma-separated), added to !status, !agents, !stop and !help
+    #[arg(
+        long = "command",
+        env = "NPARROT_COMMANDS",
+        value_delimiter = ',',
+        value_parser = mcp::palette::parse_mapping
+    )]
+    commands: Vec<mcp::palette::Mapping>,
+
+    /// A message matching this (the whole message, ignoring case) stops the running task
+    /// instead of being handed to `wait`
+    #[arg(
+        long,
+        env = "NPARROT_INTERRUPT_PATTERN",
+        default_value = interrupt::DEFAULT_PATTERN,
+        value_parser = interrupt::parse_pattern
+    )]
+    interrupt_pattern: regex::Regex,
+
+    #[cfg(feature = "multi-agent")]
+    /// Whether `multi-agent-mcp` lets the orchestrator use the memory tools itself instead of
+    /// telling it to create an agent
+    #[arg(long, env = "ORCHESTRATOR_MEMORY", value_enum, default_value = "deny")]
+    orchestrator_memory: multi_agent::OrchestratorMemory,
+
+    #[cfg(feature = "multi-agent")]
+    /// How `multi-agent-mcp` names new agents; clashes with live agents get a numeric suffix
+    #[arg(
+        long,
+        env = "NPARROT_AGENT_NAMING",
+        value_enum,
+        default_value = "random"
+    )]
+    agent_naming: multi_agent::naming::AgentNaming,
+
+    #[cfg(feature = "multi-agent")]
+    /// How long `multi-agent-mcp` reuses the result of probing the agents' backends (Goose,
+    /// SearXNG, relays, data dir) before probing again
+    #[arg(long, env = "NPARROT_CAPABILITY_TTL", default_value = "5m", value_parser = parse_duration_secs)]
+    capability_ttl: u64,
+
+    #[cfg(all(feature = "goose", feature = "searxng"))]
+    /// How often `combined-mcp` and `multi-agent-mcp` send a status digest on the progress
+    /// channel, skipped when nothing changed (e.g. 1h; 0 sends none)
+    #[arg(long, env = "NPARROT_DIGEST_INTERVAL", default_value = "0", value_parser = parse_duration_secs)]
+    digest_interval: u64,
+
+    /// Passphrase the notes and events files are encrypted with (default: derived from the nsec)
+    #[arg(long, env = "NPARROT_DATA_KEY", hide_env_values = true, value_parser = at_rest::DataKey::parse)]
+    data_key: Option<at_rest::DataKey>,
+
+    /// Passphrase the enhanced server's `rotate_data_key` re-encrypts the files with; move it to
+    /// NPARROT_DATA_KEY after the rotation
+    #[arg(long, env = "NPARROT_NEW_DATA_KEY", hide_env_values = true, value_parser = at_rest::DataKey::parse)]
+    new_data_key: Option<at_rest::DataKey>,
+
+    /// IANA time zone (e.g. Europe/Berlin) for times shown to the user; stored times stay UTC
+    #[arg(long, env = "NPARROT_TZ", value_parser = timezone::parse_zone)]
+    tz: Option<timezone::Zone>,
+
+    /// Only print errors and essential output (messages, event ids)
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print per-relay publish results, subscription details and timings
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Log file of the MCP servers and `onmessage` when RUST_LOG is set (defaults to
+    /// nparrot.log in the data dir)
+    #[arg(long, env = "NPARROT_LOG_FILE")]
+    log_file: Option<std::path::PathBuf>,
+
+    /// Rotate the log file once it reaches this size (e.g. 10M)
+    #[arg(
+        long,
+        env = "NPARROT_LOG_MAX_SIZE",
+        default_value = "10M",
+        value_parser = parse_size_bytes
+    )]
+    log_max_size: u64,
+
+    /// Rotated log files to keep next to the current one (0 just truncates it)
+    #[arg(long, env = "NPARROT_LOG_KEEP", default_value_t = 5)]
+    log_keep: usize,
+
+    /// Format of log lines, in the log file and on stderr
+    #[arg(long, env = "NPARROT_LOG_FORMAT", value_enum, default_value = "text")]
+    log_format: logging::LogFormat,
+
+    /// Control socket of `nparrot daemon`; `send`, `send-progress` and `wait` go through the
+    /// daemon when one is listening there (defaults to nparrot.sock in the data dir)
+    #[arg(long, env = "NPARROT_SOCKET", global = true)]
+    socket: Option<std::path::PathBuf>,
+
+    /// Config file (defaults to ~/.config/nparrot/config.toml)
+    #[arg(long, env = "NPARROT_CONFIG")]
+    config: Option<std::path::PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -60,280 +609,1940 @@ enum Commands {
     Send {
         /// The message to send
         message: Option<String>,
+        /// Ask relays to delete the message after this long (NIP-40), e.g. 30m, 12h, 1d
+        #[arg(long, value_parser = parse_duration_secs)]
+        expire_after: Option<u64>,
+        /// Thread the message as a reply to this event id (hex or note1), as printed by `wait --json`
+        #[arg(long, value_parser = parse_event_id)]
+        reply_to: Option<EventId>,
+        /// Send to this npub or nprofile instead of the target, for this message only; the
+        /// relays an nprofile lists are used for it as well
+        #[arg(long, value_parser = senders::parse_recipient)]
+        to: Option<senders::Recipient>,
     },
     /// Sends a private message via NIP-17 using the progress identity. If the message is omitted, reads it from stdin.
     SendProgress {
         /// The message to send
         message: Option<String>,
+        /// Ask relays to delete the message after this long (NIP-40), e.g. 30m, 12h, 1d
+        #[arg(long, value_parser = parse_duration_secs)]
+        expire_after: Option<u64>,
+        /// Progress channel to send on (defaults to `status`)
+        #[arg(long)]
+        channel: Option<String>,
     },
     /// Waits for a private NIP-17 message to be received and prints the decrypted contents to stdout once received.
-    Wait,
+    Wait {
+        /// Print the message as a JSON object, including envelope type and meta
+        #[arg(long)]
+        json: bool,
+        /// Number of messages to wait for before exiting
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        count: u64,
+        /// Give up after this long (e.g. 30s, 5m), printing whatever arrived and exiting with
+        /// status 124
+        #[arg(long, value_parser = parse_duration_secs)]
+        timeout: Option<u64>,
+    },
+    /// Keeps the relay connections open and serves `send`, `send-progress` and `wait` over the
+    /// --socket, so they skip connecting to relays themselves
+    Daemon,
     /// Listens for private NIP-17 messages to be received and prints the decrypted contents to stdout after each one is received.
-    Listen,
+    Listen {
+        /// Print one JSON object per line, including envelope type and meta
+        #[arg(long)]
+        json: bool,
+        /// Write each message to this directory as `<received>-<event id>.json` instead of
+        /// printing it
+        #[arg(long)]
+        exec_dir: Option<std::path::PathBuf>,
+        /// Command run with the path of each spooled file; the file then moves to `done/`, or to
+        /// `failed/` with the command's stderr
+        #[arg(long, requires = "exec_dir")]
+        handler: Option<String>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
     /// Starts an MCP server to allow an AI agent to manage the conversation
     Mcp,
     /// Starts an MCP server to provide Goose AI agent command execution capabilities
+    #[cfg(feature = "goose")]
     GooseMcp,
     /// Starts a combined MCP server with both chat and Goose command capabilities
+    #[cfg(all(feature = "goose", feature = "searxng"))]
     CombinedMcp,
     /// Starts an enhanced MCP server with chat, notes, and events management
+    #[cfg(feature = "memory")]
     EnhancedMcp,
     /// Starts a multi-agent MCP server that can run multiple agents in parallel
+    #[cfg(feature = "multi-agent")]
     MultiAgentMcp,
     /// Starts a Nostr Memory MCP server for agent memory storage using encrypted DMs
+    #[cfg(feature = "memory")]
     NostrMemoryMcp,
+    /// Checks the configuration and environment (keys, relays, goose, SearXNG, data dir) and reports problems
+    Doctor {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+        /// How long to wait for each network check (e.g. 10s, 1m; a bare number is seconds)
+        #[arg(long, default_value = "10s", value_parser = parse_duration_secs)]
+        timeout: u64,
+    },
+    /// Publishes or updates the kind-0 profile of the main (or progress) identity
+    SetProfile {
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long)]
+        display_name: Option<String>,
+        #[arg(long)]
+        about: Option<String>,
+        /// Profile picture URL
+        #[arg(long)]
+        picture: Option<String>,
+        /// Banner image URL
+        #[arg(long)]
+        banner: Option<String>,
+        #[arg(long)]
+        nip05: Option<String>,
+        #[arg(long)]
+        lud16: Option<String>,
+        /// Read fields from a TOML file (flags take precedence)
+        #[arg(long)]
+        from_file: Option<std::path::PathBuf>,
+        /// Update the progress identity instead of the main one
+        #[arg(long)]
+        progress: bool,
+        /// Publish without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Inspects the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Connects to each configured relay and reports connect time, round-trip time and NIP-11 info
+    Ping {
+        /// Repeat at this interval until interrupted (e.g. 30s, 5m)
+        #[arg(long, value_name = "INTERVAL", value_parser = parse_duration_secs)]
+        watch: Option<u64>,
+        /// How long to wait for each relay (e.g. 10s)
+        #[arg(long, default_value = "10s", value_parser = parse_duration_secs)]
+        timeout: u64,
+    },
+    /// Sends a DM to ourselves through the relays and reports each stage of its round trip
+    Selftest {
+        /// How long to wait for the message to come back (e.g. 30s)
+        #[arg(long, default_value_t = selftest::DEFAULT_TIMEOUT_SECS, value_parser = parse_duration_secs)]
+        timeout: u64,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Moves to a new identity: tells the user, migrates memories, data files and profile
+    RotateKey {
+        /// The nsec to move to
+        #[arg(long, value_name = "NSEC", hide_env_values = true)]
+        new_nsec: String,
+        /// List what each remaining step would migrate without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Also replace the nsec in the config file and .env
+        #[arg(long)]
+        update_config: bool,
+    },
+    /// Decrypts a raw gift wrap event (kind 1059, as JSON) and prints its seal and rumor
+    Inspect {
+        /// File holding the event JSON; reads stdin if omitted
+        file: Option<std::path::PathBuf>,
+    },
+    /// Searches the message transcript written with NPARROT_TRANSCRIPT=1
+    Transcript {
+        /// Only this day (UTC), e.g. 2024-06-01; every day if omitted
+        #[arg(long)]
+        date: Option<chrono::NaiveDate>,
+        /// Only messages whose content matches this regex (case-insensitive)
+        #[arg(long)]
+        grep: Option<String>,
+        /// Print the matching lines as JSON, with the decrypted content
+        #[arg(long)]
+        json: bool,
+    },
+    /// Shows, resends or discards the messages waiting in the resend queue
+    Outbox {
+        #[command(subcommand)]
+        action: OutboxAction,
+    },
+    /// Shows CPU and memory usage of processes spawned by running nparrot instances
+    Ps {
+        /// Print the snapshots as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Runs a specified shell command each time it receives a NIP-17 direct message, passing the decrypted message contents to it via stdin.
     Onmessage {
+        /// May contain {content}, {sender}, {event_id} and {created_at}, each replaced by a
+        /// shell-quoted value
         #[clap(required = true)]
         shell_command: String,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+}
+
+#[derive(Args, Debug)]
+struct FilterArgs {
+    /// Only handle messages whose content matches this regex; others are skipped and counted
+    #[arg(long, value_parser = filter::parse_pattern)]
+    filter: Option<regex::Regex>,
+    /// Handle only the messages that do not match --filter
+    #[arg(long, requires = "filter")]
+    invert_filter: bool,
+}
+
+impl FilterArgs {
+    fn build(self) -> Option<Arc<filter::MessageFilter>> {
+        self.filter
+            .map(|pattern| Arc::new(filter::MessageFilter::new(pattern, self.invert_filter)))
+    }
+}
+
+/// Tells the user how many messages `--filter` skipped
+fn report_skipped(filter: Option<&filter::MessageFilter>) {
+    if let Some(filter) = filter.filter(|filter| filter.skipped() > 0) {
+        status!(
+            "Skipped {} messages not matching --filter",
+            filter.skipped()
+        );
+    }
+}
+
+/// The store for the context tools, saved to the data dir with `--persist-context`
+fn context_store(args: &Cli) -> Arc<mcp::context::ContextStore> {
+    Arc::new(if args.persist_context {
+        mcp::context::ContextStore::persistent(
+            std::path::Path::new(&args.data_dir).join(mcp::context::FILE),
+        )
+    } else {
+        mcp::context::ContextStore::in_memory()
+    })
+}
+
+/// The Nostr arguments a command can't run without
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NostrNeeds {
+    /// Runs without any Nostr configuration, e.g. `goose-mcp`
+    Nothing,
+    /// Needs our identity (--nsec) but no conversation
+    Identity,
+    /// Talks to the target user: --nsec and --target-pubkey
+    Conversation,
+}
+
+impl Commands {
+    fn nostr_needs(&self) -> NostrNeeds {
+        match self {
+            #[cfg(feature = "goose")]
+            Commands::GooseMcp => NostrNeeds::Nothing,
+            // Doctor reports missing keys instead of refusing to run
+            Commands::Doctor { .. }
+            | Commands::Config { .. }
+            | Commands::Ping { .. }
+            | Commands::Outbox { .. }
+            | Commands::Ps { .. } => NostrNeeds::Nothing,
+            Commands::Inspect { .. } | Commands::Transcript { .. } | Commands::Selftest { .. } => {
+                NostrNeeds::Identity
+            }
+            // The recipient is given, so the target isn't needed
+            Commands::Send { to: Some(_), .. } => NostrNeeds::Identity,
+            _ => NostrNeeds::Conversation,
+        }
+    }
+
+    /// Whether stdout belongs to the protocol, so logs must go to a file
+    fn logs_to_file(&self) -> bool {
+        match self {
+            #[cfg(all(feature = "goose", feature = "searxng"))]
+            Commands::CombinedMcp => true,
+            #[cfg(feature = "goose")]
+            Commands::GooseMcp => true,
+            #[cfg(feature = "memory")]
+            Commands::EnhancedMcp | Commands::NostrMemoryMcp => true,
+            #[cfg(feature = "multi-agent")]
+            Commands::MultiAgentMcp => true,
+            Commands::Onmessage { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl Cli {
+    /// The Nostr arguments the command needs that were not given
+    fn missing_nostr_args(&self) -> Vec<&'static str> {
+        let needs = self.command.nostr_needs();
+        let mut missing = Vec::new();
+        if needs != NostrNeeds::Nothing && self.nsec.is_none() {
+            missing.push("--nsec <NSEC>");
+        }
+        if needs == NostrNeeds::Conversation && self.target_pubkey.is_none() {
+            missing.push("--target-pubkey <TARGET_PUBKEY>");
+        }
+        missing
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Prints the merged effective configuration with secrets redacted
+    Show,
+}
+
+#[derive(Subcommand, Debug)]
+enum OutboxAction {
+    /// Lists the queued messages with their age, recipient and attempts so far
+    List {
+        /// Print the messages as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Tries every queued message once now and reports which got through
+    Flush {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Discards a queued message that will never get through
+    Drop {
+        /// Its event id: hex, a unique prefix of it, or note1…
+        id: String,
+        /// Discard without asking for confirmation
+        #[arg(long, short)]
+        yes: bool,
+        /// Print the discarded message as JSON
+        #[arg(long)]
+        json: bool,
     },
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenv().ok();
-    let args = Cli::parse();
+    let config = config::Config::load(std::env::args()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(2);
+    });
+    let matches = config.apply_defaults(Cli::command()).get_matches();
+    let args = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    redact::set_extra_prefixes(&args.redact_prefixes);
+    retry::configure(&config.retry);
+    #[cfg(feature = "multi-agent")]
+    multi_agent::naming::set_strategy(args.agent_naming);
+    let missing = args.missing_nostr_args();
+    if !missing.is_empty() {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                format!(
+                    "the following required arguments were not provided:\n  {}",
+                    missing.join("\n  ")
+                ),
+            )
+            .exit();
+    }
+
+    output::set_verbosity(output::Verbosity::from_flags(args.quiet, args.verbose));
+    output::timing("Parsed arguments");
 
     // Initialize logging based on the command
-    match &args.command {
-        Commands::CombinedMcp
-        | Commands::GooseMcp
-        | Commands::EnhancedMcp
-        | Commands::MultiAgentMcp
-        | Commands::NostrMemoryMcp
-        | Commands::Onmessage { .. } => {
-            // For MCP servers and onmessage, use file-based logging to avoid interfering with stdio
-            use std::fs::OpenOptions;
-
-            if let Ok(_log_level) = std::env::var("RUST_LOG") {
-                let log_file = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open("nparrot.log");
-
-                if log_file.is_ok() {
-                    env_logger::Builder::from_env("RUST_LOG")
-                        .target(env_logger::Target::Pipe(Box::new(log_file.unwrap())))
-                        .init();
+    if args.command.logs_to_file() {
+        // For MCP servers and onmessage, use file-based logging to avoid interfering with stdio
+        if std::env::var("RUST_LOG").is_ok() {
+            let log_file = args
+                .log_file
+                .clone()
+                .unwrap_or_else(|| logging::default_log_file(&args.data_dir));
+            if let Err(e) =
+                logging::init_file(&log_file, args.log_max_size, args.log_keep, args.log_format)
+            {
+                eprintln!("Could not open log file {}: {}", log_file.display(), e);
+            }
+        }
+    } else {
+        // For non-MCP commands, use normal stdout logging
+        output::init_logger(args.log_format);
+    }
+
+    #[cfg(feature = "goose")]
+    {
+        goose_mcp::commands::set_goose_binary(&args.goose_bin);
+        goose_mcp::commands::set_command_timeout(std::time::Duration::from_secs(
+            args.goose_timeout,
+        ));
+    }
+    #[cfg(feature = "searxng")]
+    searxng_mcp::client::set_request_timeout(std::time::Duration::from_secs(args.searxng_timeout));
+    readiness::clear(args.health_file.as_deref());
+    process_management::env::set_policy(process_management::env::EnvPolicy {
+        allowlist: (!args.env_allowlist.is_empty()).then(|| args.env_allowlist.clone()),
+    });
+
+    response_tracker::DeliveryTracker::global()
+        .set_retention(std::time::Duration::from_secs(args.delivery_retention));
+    response_tracker::AnswerLedger::global().set_policy(args.response_policy);
+
+    mcp::events::set_default_duration(chrono::Duration::seconds(args.event_duration as i64));
+
+    if let Some(policy) = args.pow.clone() {
+        pow::set_policy(policy);
+    }
+
+    envelope::set_enabled(args.envelope);
+    dry_run::set_forced(args.force_dry_run);
+    interrupt::set_pattern(args.interrupt_pattern.clone());
+    senders::set_allowed(args.allow_senders.clone());
+    mcp::palette::set_palette(
+        args.command_prefix
+            .clone()
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| mcp::palette::Palette::new(prefix, args.commands.clone())),
+    );
+    senders::set_unaddressed_to_target(args.unaddressed_to_target);
+    senders::set_arbitrary_recipients(args.allow_arbitrary_recipients);
+    if let Some(zone) = args.tz.clone() {
+        log::debug!("Showing times in {}", zone.name());
+        timezone::set_default(zone);
+    }
+
+    // The doctor reports bad keys/relays instead of failing on them, so it runs before any setup
+    if let Commands::Config {
+        action: ConfigAction::Show,
+    } = &args.command
+    {
+        print!("{}", config.render_effective(&matches));
+        exit(0);
+    }
+
+    if let Commands::Doctor { json, timeout } = &args.command {
+        let config = doctor::DoctorConfig {
+            nsec: args.nsec.clone(),
+            target_pubkey: args.target_pubkey.clone(),
+            progress_nsec: args.progress_nsec.clone(),
+            relays: relays::parse_relay_urls(&args.relay),
+            progress_relays: args
+                .progress_relay
+                .as_deref()
+                .map(relays::parse_relay_urls)
+                .unwrap_or_default(),
+            #[cfg(feature = "searxng")]
+            searxng_url: args.searxng_url.clone(),
+            data_dir: args.data_dir.clone(),
+            timeout: std::time::Duration::from_secs(*timeout),
+        };
+        let report = doctor::run(&config).await;
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            report.print_table();
+        }
+        exit(if report.ok { 0 } else { 1 });
+    }
+
+    if let Commands::Ping { watch, timeout } = &args.command {
+        let relay_urls = relays::parse_relay_urls(&args.relay);
+        ping::run(
+            &relay_urls,
+            std::time::Duration::from_secs(*timeout),
+            *watch,
+        )
+        .await;
+        exit(0);
+    }
+
+    // Queued events are already signed, so the outbox needs no keys
+    if let Commands::Outbox { action } = &args.command {
+        let queue = redelivery::init(&args.data_dir, redelivery::ResendPolicy::default());
+        match action {
+            OutboxAction::List { json } => {
+                let pending = queue.pending();
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&pending)?);
+                } else {
+                    print!("{}", outbox::format_list(&pending, Timestamp::now()));
+                }
+            }
+            OutboxAction::Flush { json } => {
+                let client = Client::default();
+                let urls: Vec<String> = relays::parse_relay_urls(&args.relay)
+                    .into_iter()
+                    .chain(relays::parse_relay_urls(
+                        args.progress_relay.as_deref().unwrap_or(""),
+                    ))
+                    .collect();
+                relays::connect_client(&client, &urls).await?;
+                client.wait_for_connection(failover::CONNECT_TIMEOUT).await;
+                let report = redelivery::flush(&client, None).await;
+                client.disconnect().await;
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    for id in &report.delivered {
+                        println!("✅ {} delivered", id);
+                    }
+                    for id in &report.pending {
+                        println!("❌ {} still pending", id);
+                    }
+                    println!(
+                        "{} delivered, {} still queued",
+                        report.delivered.len(),
+                        report.pending.len()
+                    );
+                }
+                exit(if report.pending.is_empty() { 0 } else { 1 });
+            }
+            OutboxAction::Drop { id, yes, json } => {
+                let pending = queue.pending();
+                let id = outbox::resolve(&pending, id).unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    exit(1);
+                });
+                let message = pending
+                    .iter()
+                    .find(|message| message.id == id)
+                    .expect("resolved from the list");
+                if !*yes {
+                    eprint!(
+                        "{}",
+                        outbox::format_list(std::slice::from_ref(message), Timestamp::now())
+                    );
+                    eprint!("Discard this message? [y/N] ");
+                    let mut answer = String::new();
+                    io::stdin().read_line(&mut answer)?;
+                    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                        eprintln!("Aborted, message kept");
+                        exit(1);
+                    }
+                }
+                if !queue.remove(&id) {
+                    return Err(io::Error::other(format!(
+                        "{} was resent or dropped meanwhile",
+                        id
+                    ))
+                    .into());
+                }
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(message)?);
+                } else {
+                    println!("Dropped {}", id);
                 }
             }
         }
-        _ => {
-            // For non-MCP commands, use normal stdout logging
-            env_logger::init();
+        exit(0);
+    }
+
+    if let Commands::Ps { json } = &args.command {
+        let snapshots = process_management::stats::read_snapshots(
+            &process_management::stats::snapshot_dir(&args.data_dir),
+        );
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&snapshots)?);
+        } else if snapshots.is_empty() {
+            println!("No running nparrot instances with managed processes");
+        } else {
+            for snapshot in snapshots {
+                println!("nparrot PID {}", snapshot.owner_pid);
+                print!(
+                    "{}",
+                    process_management::stats::format_stats(&snapshot.processes)
+                );
+            }
         }
+        exit(0);
+    }
+
+    // Goose only runs local commands: no identity, relays or profiles
+    #[cfg(feature = "goose")]
+    if let Commands::GooseMcp = &args.command {
+        require_goose(&args)?;
+        let shutdown = shutdown::Shutdown::install();
+        let snapshot_dir = process_management::stats::snapshot_dir(&args.data_dir);
+        let mut violations =
+            process_management::stats::start_sampler(process_management::stats::SamplerConfig {
+                interval: std::time::Duration::from_secs(args.sample_interval),
+                limits: process_management::stats::ResourceLimits {
+                    max_rss_bytes: args.max_child_rss,
+                },
+                snapshot_dir: Some(snapshot_dir.clone()),
+            });
+        tokio::spawn(async move {
+            while let Some(violation) = violations.recv().await {
+                log::warn!("{}", violation.notice());
+            }
+        });
+        serve_until_shutdown(GooseServer::new(), &args, &shutdown).await?;
+        process_management::ProcessManager::global()
+            .terminate_all(shutdown::GRACE_PERIOD)
+            .await;
+        process_management::stats::remove_snapshot(&snapshot_dir);
+        exit(0);
     }
 
     // Parse our keys from the provided identity (nsec)
-    let keys = Keys::parse(&args.nsec)?;
+    let keys = Keys::parse(args.nsec.as_deref().expect("checked by missing_nostr_args"))?;
     let our_pubkey = keys.public_key();
+    at_rest::Vault::global().set_key(Some(
+        args.data_key
+            .clone()
+            .unwrap_or_else(|| at_rest::DataKey::derive(keys.secret_key().as_secret_bytes())),
+    ));
+    at_rest::set_next_key(args.new_data_key.clone());
+    if args.transcript {
+        transcript::set_enabled(Some(&args.data_dir));
+    }
+    progress_history::init(
+        &args.data_dir,
+        args.progress_history_size as usize,
+        args.transcript,
+    );
+    audit::init(&args.data_dir, args.audit_tool);
+    replay::init(
+        &args.data_dir,
+        std::time::Duration::from_secs(args.replay_max_age),
+    );
+    mcp::inbox::set_coalesce_window(Some(std::time::Duration::from_millis(args.coalesce_ms)));
+    mcp::watchdog::set_stall_after(Some(std::time::Duration::from_secs(args.stall_after)));
+    mcp::dedup::Suppressor::global().set_windows(
+        std::time::Duration::from_secs(args.dedup_window),
+        std::time::Duration::from_secs(args.progress_dedup_window),
+    );
+    mcp::instructions::set_profile(args.instructions);
+    #[cfg(all(feature = "goose", feature = "searxng"))]
+    digest::set_interval(Some(std::time::Duration::from_secs(args.digest_interval)));
+    if let Some(template) = &args.message_template {
+        let template = message_template::MessageTemplate::parse(template, args.bot_name.as_deref())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        message_template::set(Some(template));
+    }
+    if let Some(path) = &args.messages {
+        let text = std::fs::read_to_string(path)?;
+        let catalog = catalog::Catalog::parse(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+        catalog::set(catalog);
+    }
 
-    // Parse the target public key
-    let target_pk: PublicKey = args.target_pubkey.parse()?;
+    if let Commands::Transcript { date, grep, json } = &args.command {
+        let pattern = grep
+            .as_deref()
+            .map(|grep| {
+                regex::RegexBuilder::new(grep)
+                    .case_insensitive(true)
+                    .build()
+            })
+            .transpose()?;
+        let found = transcript::search(&args.data_dir, *date, pattern.as_ref())?;
+        let mut unreadable = 0;
+        for (line, content) in &found {
+            if content.is_err() {
+                unreadable += 1;
+            }
+            if *json {
+                let mut value = serde_json::to_value(line)?;
+                value["content"] = match content {
+                    Ok(content) => serde_json::Value::String(content.clone()),
+                    Err(_) => serde_json::Value::Null,
+                };
+                if let Some(object) = value.as_object_mut() {
+                    object.remove("sealed_content");
+                }
+                println!("{}", value);
+            } else {
+                println!(
+                    "[{}] {} {} {}: {}",
+                    timezone::format(line.timestamp),
+                    match line.direction {
+                        transcript::Direction::Sent => "→",
+                        transcript::Direction::Received => "←",
+                    },
+                    line.channel,
+                    line.event_id,
+                    content
+                        .as_deref()
+                        .unwrap_or("<cannot decrypt with this data key>")
+                );
+            }
+        }
+        if unreadable > 0 {
+            eprintln!(
+                "{} line(s) could not be decrypted with the current data key",
+                unreadable
+            );
+        }
+        exit(0);
+    }
+
+    if let Commands::Inspect { file } = &args.command {
+        let json = match file {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => {
+                let mut buffer = String::new();
+                io::stdin().read_to_string(&mut buffer)?;
+                buffer
+            }
+        };
+        let event = Event::from_json(json.trim())?;
+        // Wraps sent to a progress identity need its key instead
+        let progress_keys = args
+            .progress_nsec
+            .iter()
+            .chain(args.progress_channels.iter().map(|(_, nsec)| nsec))
+            .map(|nsec| Keys::parse(nsec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let keys = progress_keys
+            .into_iter()
+            .find(|progress| {
+                event
+                    .tags
+                    .public_keys()
+                    .any(|p| *p == progress.public_key())
+            })
+            .unwrap_or_else(|| keys.clone());
+        match inspect::run(&keys, &event).await {
+            Ok(report) => print!("{}", report),
+            Err((report, e)) => {
+                print!("{}", report);
+                eprintln!("\nError: {}", e);
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+
+    if let Commands::Selftest { timeout, json } = &args.command {
+        let client = Client::builder().signer(keys.clone()).build();
+        relays::connect_client(&client, &relays::parse_relay_urls(&args.relay)).await?;
+        client.wait_for_connection(failover::CONNECT_TIMEOUT).await;
+        let report = selftest::run(
+            &client,
+            our_pubkey,
+            std::time::Duration::from_secs(*timeout),
+        )
+        .await;
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!("{}", report);
+        }
+        client.disconnect().await;
+        exit(if report.ok { 0 } else { 1 });
+    }
+
+    // Parse the target public key; `send --to` talks to someone else this once
+    let target_pk: PublicKey = match &args.command {
+        Commands::Send { to: Some(to), .. } => to.pubkey,
+        _ => args
+            .target_pubkey
+            .as_deref()
+            .expect("checked by missing_nostr_args")
+            .parse()?,
+    };
+
+    if let Commands::RotateKey {
+        new_nsec,
+        dry_run,
+        update_config,
+    } = &args.command
+    {
+        let new_keys = Keys::parse(new_nsec)?;
+        let relay_urls = relays::parse_relay_urls(&args.relay);
+        let old_client = Client::builder().signer(keys.clone()).build();
+        let new_client = Client::builder().signer(new_keys.clone()).build();
+        for client in [&old_client, &new_client] {
+            relays::connect_client(client, &relay_urls).await?;
+            client.wait_for_connection(failover::CONNECT_TIMEOUT).await;
+        }
+        let rotation = rotate_key::Rotation {
+            old: Arc::new(old_client.clone()),
+            new: Arc::new(new_client.clone()),
+            old_keys: keys.clone(),
+            new_keys,
+            target: target_pk,
+            data_dir: std::path::PathBuf::from(&args.data_dir),
+            explicit_data_key: args.data_key.is_some(),
+            profile_overrides: config.profile("main").cloned(),
+            config_files: config
+                .path
+                .iter()
+                .cloned()
+                .chain([std::path::PathBuf::from(".env")])
+                .collect(),
+            update_config: *update_config,
+        };
+        let result = rotation.run(*dry_run).await;
+        old_client.disconnect().await;
+        new_client.disconnect().await;
+        if let Err(e) = result {
+            eprintln!("Error: {}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    #[cfg(unix)]
+    let socket = args
+        .socket
+        .clone()
+        .unwrap_or_else(|| daemon::default_socket(&args.data_dir));
+
+    // A running daemon already has warm relay connections, but only talks to its own target
+    #[cfg(unix)]
+    if matches!(
+        args.command,
+        Commands::Send { to: None, .. } | Commands::SendProgress { .. } | Commands::Wait { .. }
+    ) {
+        if let Some(client) = daemon::DaemonClient::connect(&socket).await {
+            detail!("Using the daemon on {}", socket.display());
+            exit(run_via_daemon(client, args.command, our_pubkey, target_pk).await?);
+        }
+    }
 
     // Create a client with our keys
     let client = Client::builder().signer(keys.clone()).build();
 
-    // Optional progress client
-    let progress_client = if let Some(progress_nsec) = &args.progress_nsec {
+    // Optional progress clients by channel; `progress_client` is the default one
+    let mut progress_clients = progress_channels::ProgressChannels::default();
+    let progress_nsecs = args
+        .progress_nsec
+        .iter()
+        .map(|nsec| (progress_channels::STATUS, nsec))
+        .chain(
+            args.progress_channels
+                .iter()
+                .map(|(name, nsec)| (name.as_str(), nsec)),
+        );
+    for (name, progress_nsec) in progress_nsecs {
         let progress_keys = Keys::parse(progress_nsec)?;
         let c = Client::builder().signer(progress_keys).build();
-        Some(c)
-    } else {
-        None
-    };
+        progress_clients.insert(name, c);
+    }
+    let progress_client = progress_clients.default_sender().cloned();
 
-    let relay_urls: Vec<&str> = args
-        .relay
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .collect();
+    let relay_specs = relays::parse_relay_specs(&args.relay);
+    let relay_urls: Vec<String> = relay_specs.iter().map(|r| r.url.clone()).collect();
+    let progress_specs = args
+        .progress_relay
+        .as_deref()
+        .map(relays::parse_relay_specs)
+        .filter(|specs| !specs.is_empty());
+    let progress_relay_urls: Vec<String> = progress_specs
+        .as_ref()
+        .map(|specs| specs.iter().map(|r| r.url.clone()).collect())
+        .unwrap_or_else(|| relay_urls.clone());
+    let routing = relays::RelayRouting::new(
+        relay_specs,
+        progress_specs,
+        args.relay_routes.clone().unwrap_or_default(),
+    )?;
+    // All identities connect at once; each only waits for its relays to be added
+    let connections = std::iter::once(relays::connect_client(&client, &relay_urls)).chain(
+        progress_clients
+            .iter()
+            .map(|(_, c)| relays::connect_client(c, &progress_relay_urls)),
+    );
+    futures::future::try_join_all(connections).await?;
+    detail!("Using relays {}", args.relay);
+    if let Some(progress_relay) = &args.progress_relay {
+        detail!("Progress identities use relays {}", progress_relay);
+    }
+    if routing.is_routed() {
+        detail!(
+            "Progress publishes to {}",
+            routing
+                .targets(progress_channels::STATUS)
+                .map(|urls| urls.join(", "))
+                .unwrap_or_else(|| "all relays".to_string())
+        );
+    }
+    relays::set_routing(routing);
+    message_size::set_default_limit(args.max_message_size as usize);
+    transport::subscriptions::set_default_limit(args.max_subscriptions);
+    relays::learn_info(
+        relay_urls
+            .iter()
+            .chain(
+                progress_relay_urls
+                    .iter()
+                    .filter(|url| !relay_urls.contains(url)),
+            )
+            .cloned()
+            .collect(),
+    );
+    relays::watch_auth(&client).await;
 
-    for url in &relay_urls {
-        client.add_relay(*url).await?;
+    // `send --to` goes straight to its recipient, through the relays an nprofile lists too
+    let one_off = match &args.command {
+        Commands::Send { to: Some(to), .. } => Some(to),
+        _ => None,
+    };
+    for relay in one_off.map(|to| to.relays.as_slice()).unwrap_or_default() {
+        client.add_relay(relay.as_str()).await?;
+        if let Err(e) = client
+            .try_connect_relay(relay.as_str(), failover::CONNECT_TIMEOUT)
+            .await
+        {
+            log::warn!("Could not connect to recipient relay {}: {}", relay, e);
+        }
     }
-    client.connect().await;
 
-    if let Some(ref c) = progress_client {
-        for url in &relay_urls {
-            c.add_relay(*url).await?;
+    let conversation = match &args.group {
+        Some(group) if one_off.is_none() => {
+            let timeout = failover::CONNECT_TIMEOUT;
+            client.add_relay(group.relay.as_str()).await?;
+            if let Err(e) = client
+                .try_connect_relay(group.relay.as_str(), timeout)
+                .await
+            {
+                log::warn!("Could not connect to group relay {}: {}", group.relay, e);
+            }
+            detail!("Talking in group {} on {}", group.id, group.relay);
+            group::set_target(Some(group.clone()));
+            group::Conversation::Group(group.clone())
         }
-        c.connect().await;
+        _ => group::Conversation::Direct(target_pk),
+    };
+
+    if let Some(server) = &args.media_server {
+        media::set_uploads(Some(media::Uploads {
+            server: server.clone(),
+            protocol: args.media_protocol,
+            max_size: args.max_upload_size,
+            roots: vec![
+                std::path::PathBuf::from(&args.data_dir),
+                std::env::current_dir()?,
+            ],
+        }));
     }
 
-    // Setup profiles for The Fux Family agents
-    log::info!("🔥 Setting up The Fux Family profiles...");
-    if let Err(e) = profile::setup_main_client_profile(&client).await {
-        log::warn!("Could not setup main profile: {}", e);
+    let long_running = !matches!(
+        args.command,
+        Commands::Send { .. } | Commands::SendProgress { .. }
+    );
+
+    // One-shot sends wrap (and mine) their message while the relays are still connecting
+    let outgoing = match &args.command {
+        Commands::Send {
+            message,
+            expire_after,
+            reply_to,
+            ..
+        } => {
+            let content = envelope::wrap(MessageType::Chat, read_message(message.clone())?);
+            let rumor_tags = reply_to.map(reply_tags).unwrap_or_default();
+            Some(prepare_in_background(
+                &client,
+                conversation.clone(),
+                content,
+                *expire_after,
+                rumor_tags,
+            ))
+        }
+        Commands::SendProgress {
+            message,
+            expire_after,
+            channel,
+        } => {
+            let (_, progress_client) =
+                progress_clients
+                    .resolve(channel.as_deref())
+                    .ok_or_else(|| {
+                        io::Error::other("progress identity not configured (set --progress-nsec)")
+                    })?;
+            let content = envelope::wrap(MessageType::Progress, read_message(message.clone())?);
+            Some(prepare_in_background(
+                progress_client,
+                group::Conversation::Direct(target_pk),
+                content,
+                *expire_after,
+                Vec::new(),
+            ))
+        }
+        _ => None,
+    };
+    let standby_urls = relays::parse_relay_urls(args.relay_fallback.as_deref().unwrap_or(""));
+    let failover = failover::Failover::new(&relay_urls, &standby_urls, args.min_relays);
+    let (health, connected) = failover.establish(&client, failover::CONNECT_TIMEOUT).await;
+    output::timing(&format!(
+        "{} relay(s) connected",
+        connected.primaries + connected.standbys
+    ));
+    // Servers keep running and `failover.watch` below reports the degraded state
+    // Under supervision, a server that can't reach enough relays is not ready
+    if health == failover::Health::Degraded
+        && (!long_running || readiness::requested(args.health_file.as_deref()))
+    {
+        return Err(io::Error::other(format!(
+            "Only {} relay(s) connected, {} required (--min-relays)",
+            connected.primaries + connected.standbys,
+            args.min_relays
+        ))
+        .into());
     }
 
-    if let Some(ref progress_client) = progress_client {
-        if let Err(e) = profile::setup_progress_client_profile(progress_client).await {
-            log::warn!("Could not setup progress profile: {}", e);
+    // Manual edits run before the automatic publication below, which would overwrite them
+    if let Commands::SetProfile {
+        name,
+        display_name,
+        about,
+        picture,
+        banner,
+        nip05,
+        lud16,
+        from_file,
+        progress,
+        yes,
+    } = &args.command
+    {
+        let mut fields = config::ProfileConfig {
+            name: name.clone(),
+            display_name: display_name.clone(),
+            about: about.clone(),
+            picture: picture.clone(),
+            banner: banner.clone(),
+            nip05: nip05.clone(),
+            lud16: lud16.clone(),
+        };
+        if let Some(path) = from_file {
+            let text = std::fs::read_to_string(path)?;
+            fields = fields.or(config::ProfileConfig::parse(&text)?);
         }
+
+        let target_client = if *progress {
+            progress_client.as_ref().ok_or_else(|| {
+                io::Error::other("progress identity not configured (set --progress-nsec)")
+            })?
+        } else {
+            &client
+        };
+        profile::set_profile_interactive(target_client, &fields, *yes).await?;
+        exit(0);
     }
 
-    // The Fux Family is ready for action
-    log::info!("💎 The Fux Family ready for action!");
+    // Servers and onmessage answer DMs; a second one answering for the same key doubles every
+    // reply
+    let answers = match args.command {
+        Commands::Mcp | Commands::Onmessage { .. } => true,
+        #[cfg(all(feature = "goose", feature = "searxng"))]
+        Commands::CombinedMcp => true,
+        #[cfg(feature = "memory")]
+        Commands::EnhancedMcp | Commands::NostrMemoryMcp => true,
+        #[cfg(feature = "multi-agent")]
+        Commands::MultiAgentMcp => true,
+        _ => false,
+    };
+    let presence = match args.multi_instance {
+        presence::MultiInstance::Ignore => None,
+        _ if !answers => None,
+        mode => match presence::claim(&client, &args.data_dir).await {
+            Ok(claim) => {
+                let claim = std::sync::Arc::new(claim);
+                tokio::spawn(claim.clone().run(client.clone()));
+                Some(claim)
+            }
+            Err(conflict) if mode == presence::MultiInstance::Passive => {
+                log::warn!("{}; running passive: receiving and logging only", conflict);
+                presence::set_passive(true);
+                None
+            }
+            Err(conflict) => {
+                return Err(io::Error::other(format!(
+                    "{}; stop it, or set NPARROT_MULTI_INSTANCE=passive to only listen",
+                    conflict
+                ))
+                .into())
+            }
+        },
+    };
 
-    match args.command {
-        Commands::Send { message } => {
-            // Obtain the message from argument or via stdin
-            let content = match message {
-                Some(msg) => msg,
-                None => {
-                    let mut buffer = String::new();
-                    io::stdin().read_to_string(&mut buffer)?;
-                    buffer
-                }
+    // One-shot sends leave the profiles to the next long-running command; the others publish
+    // them in the background instead of holding up startup
+    if long_running && !args.no_profile && !presence::is_passive() {
+        let main_overrides = config.profile("main").cloned();
+        let progress = progress_clients.iter().map(|(name, progress_client)| {
+            let overrides = match name {
+                progress_channels::STATUS => config.profile("progress"),
+                name => config.profile(name),
             };
+            (
+                name.to_string(),
+                progress_client.clone(),
+                overrides.cloned(),
+            )
+        });
+        let progress: Vec<_> = progress.collect();
+        let client = client.clone();
+        tokio::spawn(async move {
+            // Setup profiles for The Fux Family agents
+            log::info!("🔥 Setting up The Fux Family profiles...");
+            if let Err(e) =
+                profile::setup_main_client_profile(&client, main_overrides.as_ref()).await
+            {
+                log::warn!("Could not setup main profile: {}", e);
+            }
 
-            eprintln!("Sending direct message to {}...", args.target_pubkey);
-            client.send_private_msg(target_pk, content, []).await?;
-            eprintln!("Message sent!");
-            exit(0);
+            for (name, progress_client, overrides) in progress {
+                if let Err(e) =
+                    profile::setup_progress_client_profile(&progress_client, overrides.as_ref())
+                        .await
+                {
+                    log::warn!("Could not setup {} progress profile: {}", name, e);
+                }
+            }
+
+            // The Fux Family is ready for action
+            log::info!("💎 The Fux Family ready for action!");
+        });
+    }
+
+    let progress_expiration = Some(args.progress_expire_after).filter(|secs| *secs > 0);
+    let shutdown = shutdown::Shutdown::install();
+
+    let snapshot_dir = process_management::stats::snapshot_dir(&args.data_dir);
+    if long_running {
+        if let Some(address) = &args.lud16 {
+            match zap::Provider::resolve(address).await {
+                Ok(provider) => {
+                    if provider.receipt_signer().is_none() {
+                        log::warn!("{} does not support zaps, only invoices", address);
+                    }
+                    zap::set_provider(Some(provider));
+                }
+                Err(e) => log::warn!("Could not resolve lightning address {}: {}", address, e),
+            }
         }
-        Commands::SendProgress { message } => {
-            let progress_client = progress_client.ok_or_else(|| {
-                io::Error::other("progress identity not configured (set --progress-nsec)")
-            })?;
-            let content = match message {
-                Some(msg) => msg,
-                None => {
-                    let mut buffer = String::new();
-                    io::stdin().read_to_string(&mut buffer)?;
-                    buffer
+        tokio::spawn(
+            failover.clone().watch(
+                "main".to_string(),
+                client.clone(),
+                progress_client
+                    .clone()
+                    .map(|c| (c, target_pk, progress_expiration)),
+            ),
+        );
+        for (name, progress_client) in progress_clients.iter() {
+            tokio::spawn(failover.clone().watch(
+                format!("progress {}", name),
+                progress_client.clone(),
+                None,
+            ));
+        }
+        if let Some(listen) = args.metrics_listen {
+            metrics::watch_relays("main", &client);
+            for (name, progress_client) in progress_clients.iter() {
+                match name {
+                    progress_channels::STATUS => metrics::watch_relays("progress", progress_client),
+                    name => metrics::watch_relays(&format!("progress-{}", name), progress_client),
                 }
-            };
+            }
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                if let Err(e) = metrics::serve(listen, shutdown).await {
+                    log::error!("Metrics endpoint failed: {}", e);
+                }
+            });
+        }
 
-            eprintln!(
+        let mut violations =
+            process_management::stats::start_sampler(process_management::stats::SamplerConfig {
+                interval: std::time::Duration::from_secs(args.sample_interval),
+                limits: process_management::stats::ResourceLimits {
+                    max_rss_bytes: args.max_child_rss,
+                },
+                snapshot_dir: Some(snapshot_dir.clone()),
+            });
+        if args.ack_reactions {
+            ack::init(Arc::new(client.clone()));
+        }
+
+        let queue = redelivery::init(
+            &args.data_dir,
+            redelivery::ResendPolicy {
+                retry: retry::RetryPolicy {
+                    max_attempts: args.resend_attempts,
+                    ..retry::policy(retry::RESEND)
+                },
+                ..Default::default()
+            },
+        );
+        tokio::spawn(queue.run(client.clone(), progress_client.clone(), progress_expiration));
+
+        let notifier = progress_client.clone();
+        tokio::spawn(async move {
+            while let Some(violation) = violations.recv().await {
+                let Some(notifier) = &notifier else {
+                    continue;
+                };
+                let notice = envelope::wrap(MessageType::Progress, violation.notice());
+                if let Err(e) =
+                    send_private_msg(notifier, target_pk, notice, progress_expiration).await
+                {
+                    log::warn!("Could not report resource limit violation: {}", e);
+                }
+            }
+        });
+    }
+
+    let announcer = args.announce_start.then(|| announce::Announcer {
+        client: client.clone(),
+        sender: progress_client.clone().unwrap_or_else(|| client.clone()),
+        target: target_pk,
+        mode: matches.subcommand_name().unwrap_or_default().to_string(),
+        data_dir: args.data_dir.clone(),
+        expire_after_secs: progress_expiration,
+    });
+
+    match args.command {
+        Commands::Send { .. } => {
+            match &conversation {
+                group::Conversation::Group(group) => {
+                    status!("Sending message to group {}...", group.id)
+                }
+                group::Conversation::Direct(pubkey) => {
+                    status!("Sending direct message to {}...", pubkey)
+                }
+            }
+            let prepared = outgoing.expect("prepared before connecting");
+            publish_one_shot(&client, prepared, &conversation, "main", target_pk).await?;
+            status!("{}", catalog::text(catalog::Key::MessageSent, &[]));
+            exit(0);
+        }
+        Commands::SendProgress { channel, .. } => {
+            let (_, progress_client) = progress_clients
+                .resolve(channel.as_deref())
+                .expect("resolved before connecting");
+            status!(
                 "Sending PROGRESS direct message to {}...",
-                args.target_pubkey
+                args.target_pubkey.as_deref().unwrap_or_default()
             );
-            progress_client
-                .send_private_msg(target_pk, content, [])
-                .await?;
-            eprintln!("Progress message sent!");
+            let prepared = outgoing.expect("prepared before connecting");
+            publish_one_shot(
+                progress_client,
+                prepared,
+                &group::Conversation::Direct(target_pk),
+                "progress",
+                target_pk,
+            )
+            .await?;
+            status!("Progress message sent!");
             exit(0);
         }
-        Commands::Wait => {
-            let message = wait_for_message(&client, &our_pubkey, &target_pk).await?;
-            println!("{}", message);
+        Commands::Wait {
+            json,
+            count,
+            timeout,
+        } => {
+            let count = count as usize;
+            let started = std::time::Instant::now();
+            let timeout = timeout.map(std::time::Duration::from_secs);
+            let waiting = async {
+                match &args.group {
+                    Some(group) => {
+                        follow_group(
+                            &client,
+                            group,
+                            (our_pubkey, target_pk),
+                            json,
+                            Some(count),
+                            timeout,
+                        )
+                        .await
+                    }
+                    None => {
+                        wait_for_messages(
+                            &client,
+                            &our_pubkey,
+                            &target_pk,
+                            count,
+                            timeout,
+                            move |message| print_message(&message, json),
+                        )
+                        .await
+                    }
+                }
+            };
+            tokio::select! {
+                received = waiting => {
+                    let received = received?;
+                    detail!(
+                        "Received {} of {} messages in {} ms",
+                        received,
+                        count,
+                        started.elapsed().as_millis()
+                    );
+                    if received < count {
+                        eprintln!("Timed out after {} of {} messages", received, count);
+                        client.disconnect().await;
+                        exit(WAIT_TIMED_OUT);
+                    }
+                }
+                _ = shutdown.requested() => status!("Shutting down..."),
+            }
         }
-        Commands::Listen => {
+        Commands::Listen {
+            json,
+            exec_dir,
+            handler,
+            filter,
+        } => {
+            let filter = filter.build();
+            let spool = match &exec_dir {
+                Some(_) if args.group.is_some() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--exec-dir only works with direct messages, not --group",
+                    )
+                    .into());
+                }
+                Some(dir) => {
+                    status!("Spooling messages to {}", dir.display());
+                    Some(Arc::new(spool::Spool::start(dir, handler)?))
+                }
+                None => None,
+            };
             let message_callback = {
-                async move |message: String| {
-                    println!("{}", message);
-                    false // Never returns
+                let filter = filter.clone();
+                move |message: IncomingMessage| {
+                    let filter = filter.clone();
+                    let spool = spool.clone();
+                    async move {
+                        if filter.is_none_or(|filter| filter.accepts(&message.content)) {
+                            match spool {
+                                Some(spool) => spool.accept(&message),
+                                None => print_message(&message, json),
+                            }
+                        }
+                        false // Never returns
+                    }
                 }
             };
 
-            listen_for_messages(
-                &client,
-                &our_pubkey,
-                &target_pk,
-                Arc::new(Mutex::new(message_callback)),
-            )
-            .await?;
+            let listening = async {
+                match &args.group {
+                    Some(group) => {
+                        let filter = filter.clone();
+                        follow_group_filtered(
+                            &client,
+                            group,
+                            (our_pubkey, target_pk),
+                            json,
+                            move |message| {
+                                filter
+                                    .as_ref()
+                                    .is_none_or(|filter| filter.accepts(&message.content))
+                            },
+                        )
+                        .await
+                    }
+                    None => {
+                        listen_for_messages(
+                            &client,
+                            &our_pubkey,
+                            &target_pk,
+                            Arc::new(Mutex::new(message_callback)),
+                        )
+                        .await
+                    }
+                }
+            };
+            tokio::select! {
+                result = listening => result?,
+                _ = shutdown.requested() => status!("Shutting down..."),
+            }
+            report_skipped(filter.as_deref());
+        }
+        Commands::Daemon => {
+            #[cfg(unix)]
+            {
+                let progress =
+                    progress_clients.map(|c| Arc::new(c.clone()) as transport::SharedTransport);
+                let server =
+                    daemon::Daemon::new(Arc::new(client.clone()), progress, our_pubkey, target_pk);
+                status!("Daemon listening on {}", socket.display());
+                server.serve(&socket, shutdown.clone()).await?;
+                status!("Shutting down...");
+                redelivery::flush(&client, progress_client.as_ref()).await;
+            }
+            #[cfg(not(unix))]
+            return Err(io::Error::other("the daemon needs Unix domain sockets").into());
         }
         Commands::Mcp => {
             // Create and serve our chat service
-            let service = Chat::new(
+            let server = Chat::new(
                 client.clone(),
-                progress_client.clone(),
+                progress_clients.clone(),
                 our_pubkey,
                 target_pk,
-            )
-            .serve(stdio())
-            .await
-            .inspect_err(|e| {
-                log::error!("{e}");
-            })?;
-            service.waiting().await?;
-            progress_client.unwrap()
-                .send_private_msg(target_pk, "Task completed", [])
+            );
+            server.listen().await;
+            tokio::spawn(schedule::init(&args.data_dir).run(server.clone()));
+            serve_announced(server.tools(), server, announcer.as_ref(), &args, &shutdown).await?;
+            if let Some(progress_client) = &progress_client {
+                send_private_msg(
+                    progress_client,
+                    target_pk,
+                    envelope::wrap(
+                        MessageType::Progress,
+                        catalog::text(catalog::Key::TaskCompleted, &[]),
+                    ),
+                    progress_expiration,
+                )
                 .await?;
+            }
         }
-        Commands::GooseMcp => {
-            // Create and serve the Goose MCP server
-            let service = GooseServer::new().serve(stdio()).await.inspect_err(|e| {
-                log::error!("{e}");
-            })?;
-            service.waiting().await?;
-        }
+        #[cfg(all(feature = "goose", feature = "searxng"))]
         Commands::CombinedMcp => {
             // Create and serve the combined MCP server with both chat, Goose, and SearXNG capabilities
-            let searxng_url =
-                std::env::var("SEARXNG_URL").unwrap_or_else(|_| "https://searx.stream".to_string());
-
+            require_goose(&args)?;
+            let wallet = match &args.nwc_uri {
+                Some(uri) => {
+                    let budget = wallet::Budget {
+                        max_payment_sats: args.wallet_max_payment,
+                        daily_sats: args.wallet_daily_budget,
+                    };
+                    let wallet = wallet::Wallet::connect(uri, &args.data_dir, budget).await?;
+                    detail!(
+                        "Wallet connected, paying up to {} sats at a time and {} sats a day",
+                        budget.max_payment_sats,
+                        budget.daily_sats
+                    );
+                    Some(Arc::new(wallet))
+                }
+                None => None,
+            };
             let server = CombinedServer::new(
                 client.clone(),
-                progress_client.clone(),
+                progress_clients.clone(),
                 our_pubkey,
                 target_pk,
-                searxng_url,
-            );
-
-            let service = server.serve(stdio()).await.inspect_err(|e| {
-                log::error!("Failed to start MCP server: {}", e);
-            })?;
-
-            service.waiting().await?;
+                args.searxng_url.clone(),
+                Some(args.data_dir.clone()),
+            )
+            .with_wallet(wallet)
+            .with_context(context_store(&args));
+            server.chat().listen().await;
+            tokio::spawn(schedule::init(&args.data_dir).run(server.chat().clone()));
+            tokio::spawn(digest::run(server.chat().clone()));
+            serve_announced(server.tools(), server, announcer.as_ref(), &args, &shutdown).await?;
         }
+        #[cfg(feature = "memory")]
         Commands::EnhancedMcp => {
             // Create and serve the enhanced MCP server with chat, notes, and events capabilities
-            let service = EnhancedMcpServer::new(
+            let server = EnhancedMcpServer::new(
                 client.clone(),
-                progress_client.clone(),
+                progress_clients.clone(),
+                keys.clone(),
                 our_pubkey,
                 target_pk,
-                None,
+                Some(args.data_dir.clone()),
             )
-            .serve(stdio())
-            .await
-            .inspect_err(|e| {
-                log::error!("{e}");
-            })?;
-            service.waiting().await?;
+            .with_progress_expiration(progress_expiration)
+            .with_context(context_store(&args));
+            server.chat().listen().await;
+            tokio::spawn(schedule::init(&args.data_dir).run(server.chat().clone()));
+            serve_announced(server.tools(), server, announcer.as_ref(), &args, &shutdown).await?;
         }
+        #[cfg(feature = "multi-agent")]
         Commands::MultiAgentMcp => {
             // Create and serve the multi-agent MCP server
-            let service = MultiAgentMcp::new(
+            require_goose(&args)?;
+            let server = MultiAgentMcp::new(
                 client.clone(),
-                progress_client.clone(),
+                progress_clients.clone(),
                 keys.clone(),
                 our_pubkey,
                 target_pk,
             )
-            .serve(stdio())
-            .await
-            .inspect_err(|e| {
-                log::error!("{e}");
-            })?;
-            service.waiting().await?;
+            .with_progress_expiration(progress_expiration)
+            .with_memory_access(args.orchestrator_memory)
+            .with_backends(
+                args.searxng_url.clone(),
+                args.data_dir.clone(),
+                std::time::Duration::from_secs(args.capability_ttl),
+            );
+            let digests = tokio::spawn(digest::run(server.chat().clone()));
+            let stopper = tokio::spawn({
+                let server = server.clone();
+                async move { server.stop_agents_on_interrupt().await }
+            });
+            serve_announced(
+                server.tools(),
+                server.clone(),
+                announcer.as_ref(),
+                &args,
+                &shutdown,
+            )
+            .await?;
+            stopper.abort();
+            digests.abort();
+            server.shutdown().await;
         }
+        #[cfg(feature = "memory")]
         Commands::NostrMemoryMcp => {
             // Create and serve the Nostr Memory MCP server
-            let service = NostrMemoryServer::new(
+            let server = NostrMemoryServer::new(
                 client.clone(),
-                progress_client.clone(),
+                progress_clients.clone(),
                 keys.clone(),
                 our_pubkey,
                 target_pk,
-            )
-            .serve(stdio())
-            .await
-            .inspect_err(|e| {
-                log::error!("{e}");
-            })?;
-            service.waiting().await?;
+            );
+            serve_announced(server.tools(), server, announcer.as_ref(), &args, &shutdown).await?;
         }
-        Commands::Onmessage { shell_command } => {
+        #[cfg(feature = "goose")]
+        Commands::GooseMcp => unreachable!("handled before profile setup"),
+        Commands::Doctor { .. }
+        | Commands::Inspect { .. }
+        | Commands::Transcript { .. }
+        | Commands::Ps { .. }
+        | Commands::Outbox { .. }
+        | Commands::Ping { .. }
+        | Commands::Selftest { .. }
+        | Commands::RotateKey { .. }
+        | Commands::Config { .. }
+        | Commands::SetProfile { .. } => {
+            unreachable!("handled before profile setup")
+        }
+        Commands::Onmessage {
+            shell_command,
+            filter,
+        } => {
             log::info!("Listening for messages");
-            run_command_on_message(&client, &our_pubkey, &target_pk, &shell_command).await?;
+            let filter = filter.build();
+            run_command_on_message(
+                &client,
+                &our_pubkey,
+                &target_pk,
+                &shell_command,
+                filter.clone(),
+                &shutdown,
+            )
+            .await?;
+            report_skipped(filter.as_deref());
+        }
+    }
+
+    // Nothing we spawned should outlive us
+    process_management::ProcessManager::global()
+        .terminate_all(shutdown::GRACE_PERIOD)
+        .await;
+    process_management::stats::remove_snapshot(&snapshot_dir);
+
+    if let Some(claim) = &presence {
+        claim.release(&client).await;
+    }
+    client.disconnect().await;
+    for (_, progress_client) in progress_clients.iter() {
+        progress_client.disconnect().await;
+    }
+
+    Ok(())
+}
+
+/// Exit status of `wait` when `--timeout` expires before `--count` messages arrived
+const WAIT_TIMED_OUT: i32 = 124;
+
+/// Runs `send`, `send-progress` or `wait` through a running daemon and returns the exit status
+#[cfg(unix)]
+async fn run_via_daemon(
+    mut client: daemon::DaemonClient,
+    command: Commands,
+    identity: PublicKey,
+    target: PublicKey,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
+    let (message, expire_after, reply_to, progress) = match command {
+        Commands::Send {
+            message,
+            expire_after,
+            reply_to,
+            ..
+        } => (message, expire_after, reply_to, None),
+        Commands::SendProgress {
+            message,
+            expire_after,
+            channel,
+        } => (message, expire_after, None, Some(channel)),
+        Commands::Wait {
+            json,
+            count,
+            timeout,
+        } => {
+            let count = count as usize;
+            let request = daemon::Request::Wait {
+                count,
+                timeout,
+                identity,
+                target,
+            };
+            let received = client
+                .wait(request, |message| print_message(&message, json))
+                .await?;
+            if received < count {
+                eprintln!("Timed out after {} of {} messages", received, count);
+                return Ok(WAIT_TIMED_OUT);
+            }
+            return Ok(0);
+        }
+        _ => unreachable!("only send, send-progress and wait go through the daemon"),
+    };
+
+    let content = read_message(message)?;
+    let message_type = if progress.is_some() {
+        MessageType::Progress
+    } else {
+        MessageType::Chat
+    };
+    let request = daemon::Request::Send {
+        message: envelope::wrap(message_type, content),
+        expire_after,
+        reply_to,
+        progress: progress.is_some(),
+        channel: progress.flatten(),
+        identity,
+        target,
+    };
+    let (event_id, queued) = client.send(request).await?;
+    if queued {
+        status!("No relay accepted the message yet; the daemon will keep resending it");
+    } else {
+        status!("{}", catalog::text(catalog::Key::MessageSent, &[]));
+    }
+    println!("{}", event_id);
+    Ok(0)
+}
+
+/// Prints a received message for `Wait`/`Listen`, as plain text or one JSON object per line
+/// The message given on the command line, or stdin if there is none
+fn read_message(message: Option<String>) -> io::Result<String> {
+    match message {
+        Some(msg) => Ok(msg),
+        None => {
+            let mut buffer = String::new();
+            io::stdin().read_to_string(&mut buffer)?;
+            Ok(buffer)
         }
     }
+}
+
+type PreparedEvent =
+    tokio::task::JoinHandle<Result<Event, Box<dyn std::error::Error + Send + Sync>>>;
 
+/// Gift-wraps (and mines, with PoW) a message on its own task so it overlaps relay connection
+fn prepare_in_background(
+    client: &Client,
+    conversation: group::Conversation,
+    content: String,
+    expire_after_secs: Option<u64>,
+    rumor_tags: Vec<Tag>,
+) -> PreparedEvent {
+    let client = client.clone();
+    tokio::spawn(async move {
+        let event = conversation
+            .prepare(
+                &client,
+                content,
+                expire_after_secs,
+                rumor_tags,
+                Timestamp::now(),
+            )
+            .await;
+        output::timing("Message wrapped");
+        event
+    })
+}
+
+/// Publishes a prepared one-shot message as soon as a relay is ready and prints its id, then
+/// gives the relays that were still connecting a chance to take it before returning
+async fn publish_one_shot(
+    client: &Client,
+    prepared: PreparedEvent,
+    conversation: &group::Conversation,
+    channel: &str,
+    receiver: PublicKey,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let event = prepared.await??;
+    let tracker = response_tracker::DeliveryTracker::global();
+    tracker.track(event.id, receiver, channel);
+
+    let started = std::time::Instant::now();
+    // Group messages only go to the group's relay
+    if let Some(urls) = conversation.relays() {
+        let published = client.send_event_to(urls, &event).await?;
+        tracker.record_output(&published);
+        output::publish_results(&published, started.elapsed());
+        println!("{}", published.val);
+        return Ok(());
+    }
+    let (published, later) =
+        relays::publish_early(client, &event, failover::CONNECT_TIMEOUT).await?;
+    tracker.record_output(&published);
+    output::timing("Published to the first relays");
+    output::publish_results(&published, started.elapsed());
+    println!("{}", published.val);
+
+    for published in later.await.unwrap_or_default() {
+        tracker.record_output(&published);
+        output::publish_results(&published, started.elapsed());
+    }
+    output::timing("Done");
     Ok(())
 }
+
+/// `wait` in group mode: prints members' messages and, with `--json`, moderation events until
+/// `count` messages arrived or `timeout` passed; returns how many messages arrived
+async fn follow_group(
+    client: &Client,
+    group: &group::GroupTarget,
+    (our_pubkey, target_pk): (PublicKey, PublicKey),
+    json: bool,
+    count: Option<usize>,
+    timeout: Option<std::time::Duration>,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let mut events = group::subscribe(client, group, our_pubkey, target_pk).await?;
+    let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+    let mut received = 0;
+    loop {
+        let next = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, events.recv()).await {
+                Ok(next) => next,
+                Err(_) => break,
+            },
+            None => events.recv().await,
+        };
+        let Some(event) = next else {
+            break;
+        };
+        if print_group_event(event, json, |_| true) {
+            received += 1;
+            if count.is_some_and(|count| received >= count) {
+                break;
+            }
+        }
+    }
+    Ok(received)
+}
+
+/// `listen` in group mode, printing the messages `accept` lets through
+async fn follow_group_filtered(
+    client: &Client,
+    group: &group::GroupTarget,
+    (our_pubkey, target_pk): (PublicKey, PublicKey),
+    json: bool,
+    accept: impl Fn(&IncomingMessage) -> bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut events = group::subscribe(client, group, our_pubkey, target_pk).await?;
+    while let Some(event) = events.recv().await {
+        print_group_event(event, json, &accept);
+    }
+    Ok(())
+}
+
+/// Prints a group message (if `accept`ed) or, as a JSON line with `--json`, a moderation event;
+/// true for a printed message
+fn print_group_event(
+    event: group::GroupEvent,
+    json: bool,
+    accept: impl Fn(&IncomingMessage) -> bool,
+) -> bool {
+    match event {
+        group::GroupEvent::Message(message) => {
+            if !accept(&message) {
+                return false;
+            }
+            print_message(&message, json);
+            true
+        }
+        group::GroupEvent::Moderation(moderation) => {
+            if json {
+                match serde_json::to_string(&moderation) {
+                    Ok(line) => println!("{}", line),
+                    Err(e) => log::error!("Could not serialize group event: {}", e),
+                }
+            } else {
+                detail!("Group: {:?}", moderation);
+            }
+            false
+        }
+    }
+}
+
+fn print_message(message: &IncomingMessage, json: bool) {
+    if json {
+        match serde_json::to_string(message) {
+            Ok(line) => println!("{}", line),
+            Err(e) => log::error!("Could not serialize message: {}", e),
+        }
+    } else {
+        println!("{}", message.content);
+    }
+}
+
+/// `serve_until_shutdown` between the `--announce-start` notices, for servers offering `tools`
+async fn serve_announced<S>(
+    tools: Vec<rmcp::model::Tool>,
+    server: S,
+    announcer: Option<&announce::Announcer>,
+    args: &Cli,
+    shutdown: &shutdown::Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: rmcp::ServerHandler + Clone,
+{
+    if let Some(announcer) = announcer {
+        announcer.started(&tools).await;
+    }
+    serve_until_shutdown(server, args, shutdown).await?;
+    if let Some(announcer) = announcer {
+        announcer.stopped().await;
+    }
+    Ok(())
+}
+
+/// Serves an MCP server on the configured transport until the client goes away or a shutdown
+/// signal arrives, giving in-flight tool calls a grace period to finish
+async fn serve_until_shutdown<S>(
+    server: S,
+    args: &Cli,
+    shutdown: &shutdown::Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: rmcp::ServerHandler + Clone,
+{
+    let server = metrics::Instrumented::new(interrupt::Guarded::new(server));
+    let mut readiness = readiness::Readiness::new(args.health_file.clone());
+    if args.transport == http_transport::Transport::Sse {
+        return http_transport::serve(
+            server,
+            args.listen,
+            args.mcp_token.clone(),
+            shutdown,
+            || readiness.ready(),
+        )
+        .await;
+    }
+
+    let cancellation = CancellationToken::new();
+    let service = server
+        .serve_with_ct(stdio(), cancellation.clone())
+        .await
+        .inspect_err(|e| {
+            log::error!("Failed to start MCP server: {}", e);
+        })?;
+    readiness.ready()?;
+
+    let waiting = service.waiting();
+    tokio::pin!(waiting);
+
+    tokio::select! {
+        result = &mut waiting => {
+            result?;
+        }
+        _ = shutdown.requested() => {
+            log::info!("Stopping MCP server");
+            cancellation.cancel();
+            readiness.stopping();
+            if tokio::time::timeout(shutdown::GRACE_PERIOD, waiting).await.is_err() {
+                log::warn!("MCP server did not stop within the grace period");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// With readiness requested, a server that runs Goose is only ready once Goose is found
+#[cfg(feature = "goose")]
+fn require_goose(args: &Cli) -> io::Result<()> {
+    if readiness::requested(args.health_file.as_deref()) {
+        let version = doctor::check_goose().map_err(io::Error::other)?;
+        log::info!("Found Goose: {}", version);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `args` with the Nostr arguments set only as given, whatever the environment holds
+    fn cli(args: &[&str], nsec: bool, target: bool) -> Cli {
+        let mut cli = Cli::try_parse_from(std::iter::once("nparrot").chain(args.iter().copied()))
+            .expect("valid arguments");
+        cli.nsec = nsec.then(|| Keys::generate().secret_key().to_bech32().unwrap());
+        cli.target_pubkey = target.then(|| Keys::generate().public_key().to_bech32().unwrap());
+        cli
+    }
+
+    #[test]
+    fn test_nostr_requirements_per_command() {
+        let nsec = "--nsec <NSEC>";
+        let target = "--target-pubkey <TARGET_PUBKEY>";
+        for (args, needs) in [
+            (&["goose-mcp"][..], NostrNeeds::Nothing),
+            (&["doctor"], NostrNeeds::Nothing),
+            (&["config", "show"], NostrNeeds::Nothing),
+            (&["ping"], NostrNeeds::Nothing),
+            (&["ps"], NostrNeeds::Nothing),
+            (&["inspect"], NostrNeeds::Identity),
+            (&["transcript"], NostrNeeds::Identity),
+            (&["selftest"], NostrNeeds::Identity),
+            (&["send", "hi"], NostrNeeds::Conversation),
+            (&["wait"], NostrNeeds::Conversation),
+            (&["listen"], NostrNeeds::Conversation),
+            (&["mcp"], NostrNeeds::Conversation),
+            (&["enhanced-mcp"], NostrNeeds::Conversation),
+            (&["combined-mcp"], NostrNeeds::Conversation),
+            (&["multi-agent-mcp"], NostrNeeds::Conversation),
+            (&["nostr-memory-mcp"], NostrNeeds::Conversation),
+            (&["daemon"], NostrNeeds::Conversation),
+            (
+                &["rotate-key", "--new-nsec", "nsec1x"],
+                NostrNeeds::Conversation,
+            ),
+        ] {
+            // Servers left out by cargo features
+            if Cli::command().find_subcommand(args[0]).is_none() {
+                continue;
+            }
+            assert_eq!(
+                cli(args, false, false).command.nostr_needs(),
+                needs,
+                "{:?}",
+                args
+            );
+
+            let expected: Vec<&str> = match needs {
+                NostrNeeds::Nothing => vec![],
+                NostrNeeds::Identity => vec![nsec],
+                NostrNeeds::Conversation => vec![nsec, target],
+            };
+            assert_eq!(
+                cli(args, false, false).missing_nostr_args(),
+                expected,
+                "{:?}",
+                args
+            );
+            // Supplying them anyway is harmless
+            assert!(
+                cli(args, true, true).missing_nostr_args().is_empty(),
+                "{:?}",
+                args
+            );
+        }
+        assert_eq!(
+            cli(&["send", "hi"], true, false).missing_nostr_args(),
+            vec![target]
+        );
+    }
+}
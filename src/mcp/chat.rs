@@ -1,155 +1,2334 @@
-use crate::response_tracker::{create_response_reminder, ResponseTracker};
-use crate::utils::wait_for_message;
+use crate::catalog::{self, Key};
+use crate::envelope::{self, MessageType};
+use crate::error::NparrotError;
+use crate::group::{self, Conversation};
+use crate::history;
+use crate::interrupt::Interrupt;
+use crate::language;
+use crate::mcp::context::{
+    ClearContextRequest, ContextStore, GetContextRequest, SetContextRequest,
+};
+use crate::mcp::dedup::{Suppressor, Traffic};
+use crate::mcp::inbox::{self, Buffered, Inbox};
+use crate::mcp::palette::{self, Action, AgentRoster, Invocation, Palette};
+use crate::mcp::server_common::ServerInfoBuilder;
+use crate::mcp::watchdog::TurnWatchdog;
+use crate::media::{self, Uploads};
+use crate::message_size;
+use crate::message_template;
+use crate::metrics;
+use crate::progress_channels::ProgressChannels;
+use crate::progress_history::{self, ProgressHistoryRequest};
+use crate::redact;
+use crate::redelivery;
+use crate::relays::{self, RelayInfoRequest};
+use crate::response_tracker::{
+    create_response_reminder, Answer, AnswerLedger, DeliveryState, DeliveryStatusRequest,
+    DeliveryTracker, ResponseTracker,
+};
+use crate::retry;
+use crate::schedule::{self, parse_when};
+use crate::selftest::{self, SelftestRequest};
+use crate::senders;
+use crate::timezone;
+use crate::transcript;
+use crate::transport::{DmTransport, SharedTransport};
+use crate::utils::{parse_event_id, reply_tags, IncomingMessage};
+use crate::zap;
+use futures::future::BoxFuture;
+use futures::StreamExt;
 use nostr_sdk::prelude::*;
 use rmcp::{
-    model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
-    },
+    model::{CallToolResult, Content, ServerInfo},
     schemars, tool, Error as RmcpError, ServerHandler,
 };
-use tokio::time::{sleep, Duration};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Duration;
+
+/// Transcript size `summarize_conversation` returns unless told otherwise
+const DEFAULT_TRANSCRIPT_BYTES: usize = 16 * 1024;
+/// How many messages of a batch are published at the same time
+const BATCH_CONCURRENCY: usize = 4;
+/// How long a message may have waited in the inbox before `wait` says it is not fresh
+const BUFFERED_NOTE_AFTER: Duration = Duration::from_secs(30);
+
+/// How long a `send` waits for the relays of a recipient's nprofile to connect
+const RELAY_HINT_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct SendMessageRequest {
     #[schemars(description = "The message to send to the user")]
     pub message: String,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional id (hex or note1) of the user's message this replies to, as returned by wait"
+    )]
+    pub reply_to: Option<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional npub, nprofile or hex pubkey of who to answer, the `sender` wait returned; required when answering anyone but the primary user"
+    )]
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize, schemars::JsonSchema)]
+pub struct WaitRequest {
+    #[serde(default)]
+    #[schemars(
+        description = "Optional npub or hex pubkey to only take the next message of this sender, leaving the others' messages queued"
+    )]
+    pub from: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SendBatchRequest {
+    #[schemars(description = "The messages to send to the user, in the order they should appear")]
+    pub messages: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct RequestZapRequest {
+    #[schemars(description = "How many sats to ask for")]
+    pub amount_sats: u64,
+    #[serde(default)]
+    #[schemars(description = "Optional note on what the payment is for, shown to the user")]
+    pub memo: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct UploadFileRequest {
+    #[schemars(
+        description = "Path of the file to send, relative to (or inside) the data dir or the working directory"
+    )]
+    pub path: String,
+    #[serde(default)]
+    #[schemars(description = "Optional text to send along with the file")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SendLaterRequest {
+    #[schemars(description = "The message to send to the user")]
+    pub message: String,
+    #[schemars(
+        description = "When to send it: a delay (\"in 2h\", \"30m\"), a local time (\"tomorrow 9:00\", \"17:30\", \"2025-07-01 09:00\") or ISO 8601 with an offset"
+    )]
+    pub when: String,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct CancelScheduledRequest {
+    #[schemars(description = "Id of the scheduled message, as returned by send_later")]
+    pub id: String,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SummarizeConversationRequest {
+    #[serde(default)]
+    #[schemars(description = "How many hours of history to fetch (default 24)")]
+    pub hours: Option<u64>,
+    #[serde(default)]
+    #[schemars(description = "Include progress messages in the transcript (default false)")]
+    pub include_progress: Option<bool>,
+    #[serde(default)]
+    #[schemars(
+        description = "Maximum transcript size in bytes (default 16384); the oldest messages are dropped first"
+    )]
+    pub max_bytes: Option<usize>,
+    #[serde(default)]
+    #[schemars(
+        description = "A summary you wrote of an earlier transcript, to store as a memory tagged conversation-summary"
+    )]
+    pub summary: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct ProgressMessageRequest {
     #[schemars(description = "The progress/debug message to send to the user")]
     pub message: String,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional number of seconds after which relays may delete this progress message (NIP-40)"
+    )]
+    pub expire_after_secs: Option<u64>,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional progress channel, e.g. \"status\" (the default) for milestones or \"debug\" for internal detail"
+    )]
+    pub channel: Option<String>,
+}
+
+/// Where `summarize_conversation` keeps the summaries the model writes (the memory store)
+pub trait SummaryStore: std::fmt::Debug + Send + Sync {
+    /// Stores `summary` and returns the id it was stored under
+    fn store_summary(&self, summary: String) -> BoxFuture<'_, Result<String, NparrotError>>;
 }
 
 #[derive(Debug, Clone)]
 pub struct Chat {
-    client: Client,
-    progress_client: Option<Client>,
+    client: SharedTransport,
+    progress_clients: ProgressChannels<SharedTransport>,
     our_pubkey: PublicKey,
     target_pubkey: PublicKey,
+    /// Who else `wait` takes messages from (see `senders`)
+    other_senders: Vec<PublicKey>,
+    /// Whether `send` may address anyone, not just the target and `other_senders`
+    arbitrary_recipients: bool,
+    /// Where `send` and `wait` talk; progress always goes to `target_pubkey` as DMs
+    conversation: Conversation,
+    /// Media server for `upload_and_send_file`, if one is configured
+    uploads: Option<Uploads>,
+    /// Where `summarize_conversation` stores summaries, if this server has a memory store
+    summaries: Option<Arc<dyn SummaryStore>>,
+    /// Session state for the context tools, reminded of in every `wait` result
+    context: Option<Arc<ContextStore>>,
     response_tracker: ResponseTracker,
+    progress_expire_after_secs: Option<u64>,
+    /// Started by `listen` or the first `wait` and shared by every clone, so it outlives the
+    /// MCP sessions
+    inbox: Arc<Mutex<Option<Arc<Inbox>>>>,
+    /// How long the inbox waits for follow-up fragments of a message
+    coalesce: Option<Duration>,
+    interrupt: Interrupt,
+    /// The user message `send` answers; the one `wait` returned last if `None`
+    correlation_id: Option<EventId>,
+    /// Nudges the user when the model goes quiet after `wait`, if this server has one
+    watchdog: Option<Arc<TurnWatchdog>>,
+    /// Admin commands the inbox answers without the model, if enabled
+    palette: Option<Palette>,
+    /// The agents `!agents` lists, if this server runs any
+    roster: Option<Arc<dyn AgentRoster>>,
+    /// Who the progress history says sent our progress: the orchestrator or an agent id
+    source: String,
 }
 
 #[tool(tool_box)]
 impl Chat {
     pub fn new(
         client: Client,
-        progress_client: Option<Client>,
+        progress_clients: ProgressChannels<Client>,
+        our_pubkey: PublicKey,
+        target_pubkey: PublicKey,
+    ) -> Self {
+        let chat = Self::with_transport(
+            envelope::outgoing(Arc::new(client), MessageType::Chat),
+            progress_clients
+                .map(|c| envelope::outgoing(Arc::new(c.clone()), MessageType::Progress)),
+            our_pubkey,
+            target_pubkey,
+        );
+        let chat = chat.with_uploads(media::uploads());
+        match group::target() {
+            Some(group) => chat.with_conversation(Conversation::Group(group)),
+            None => chat,
+        }
+    }
+
+    /// Same as `new`, for any transport (e.g. the in-memory fake in tests)
+    pub fn with_transport(
+        client: SharedTransport,
+        progress_clients: ProgressChannels<SharedTransport>,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
     ) -> Self {
         Self {
             client,
-            progress_client,
+            progress_clients,
             our_pubkey,
             target_pubkey,
+            other_senders: senders::allowed(),
+            arbitrary_recipients: senders::arbitrary_recipients(),
+            conversation: Conversation::Direct(target_pubkey),
+            uploads: None,
+            summaries: None,
+            context: None,
             response_tracker: ResponseTracker::new(),
+            progress_expire_after_secs: None,
+            inbox: Arc::new(Mutex::new(None)),
+            coalesce: inbox::coalesce_window(),
+            interrupt: Interrupt::global(),
+            correlation_id: None,
+            watchdog: None,
+            palette: palette::palette(),
+            roster: None,
+            source: progress_history::ORCHESTRATOR.to_string(),
         }
     }
 
+    /// Talks to `conversation` (e.g. a NIP-29 group) instead of DMing the target user
+    pub fn with_conversation(mut self, conversation: Conversation) -> Self {
+        self.conversation = conversation;
+        self
+    }
+
+    pub fn with_uploads(mut self, uploads: Option<Uploads>) -> Self {
+        self.uploads = uploads;
+        self
+    }
+
+    pub fn with_summaries(mut self, summaries: Arc<dyn SummaryStore>) -> Self {
+        self.summaries = Some(summaries);
+        self
+    }
+
+    pub fn with_context(mut self, context: Arc<ContextStore>) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Uses `window` instead of the process-wide coalescing window
+    #[cfg(test)]
+    pub fn with_coalesce(mut self, window: Option<Duration>) -> Self {
+        self.coalesce = window;
+        self
+    }
+
+    /// Takes messages from `other_senders` too, instead of the process-wide allowed senders
+    #[cfg(test)]
+    pub fn with_other_senders(mut self, other_senders: Vec<PublicKey>) -> Self {
+        self.other_senders = other_senders;
+        self
+    }
+
+    /// Lets `send` address anyone, or not, regardless of `ALLOW_ARBITRARY_RECIPIENTS`
+    #[cfg(test)]
+    pub fn with_arbitrary_recipients(mut self, allowed: bool) -> Self {
+        self.arbitrary_recipients = allowed;
+        self
+    }
+
+    /// Uses `palette` instead of the process-wide one
+    #[cfg(test)]
+    pub fn with_palette(mut self, palette: Option<Palette>) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Lists `roster` for `!agents`
+    pub fn with_roster(mut self, roster: Arc<dyn AgentRoster>) -> Self {
+        self.roster = Some(roster);
+        self
+    }
+
+    /// Uses `interrupt` instead of the process-wide one
+    #[cfg(test)]
+    pub fn with_interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Ties every `send` to `correlation_id` (the user message being answered), e.g. for an agent
+    /// working on a message after `wait` has moved on to the next one
+    pub fn with_correlation(mut self, correlation_id: Option<EventId>) -> Self {
+        self.correlation_id = correlation_id;
+        self
+    }
+
+    /// Records the progress this chat sends as coming from agent `agent_id`
+    pub fn with_source(mut self, agent_id: &str) -> Self {
+        self.source = agent_id.to_string();
+        self
+    }
+
+    /// Watches every turn `wait` starts for a model that goes quiet for `stall_after`
+    pub fn with_watchdog(mut self, stall_after: Option<Duration>) -> Self {
+        self.watchdog = stall_after.map(|interval| Arc::new(TurnWatchdog::new(interval)));
+        self
+    }
+
+    /// Tells the watchdog the model is at work; the servers call it for every tool call
+    pub fn tool_called(&self) {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.activity();
+        }
+    }
+
+    /// The tools this server offers
+    pub fn tools(&self) -> Vec<rmcp::model::Tool> {
+        Self::tool_box().list()
+    }
+
+    /// Sets the default NIP-40 expiration applied to progress messages that don't specify one
+    pub fn with_progress_expiration(mut self, expire_after_secs: Option<u64>) -> Self {
+        self.progress_expire_after_secs = expire_after_secs;
+        self
+    }
+
     #[tool(description = "Send a message to the user")]
     pub async fn send(
         &self,
-        #[tool(aggr)] SendMessageRequest { message }: SendMessageRequest,
+        #[tool(aggr)] SendMessageRequest {
+            message,
+            reply_to,
+            to,
+        }: SendMessageRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let result = self.send_with_retry(&self.client, message).await;
+        let rumor_tags = match reply_to {
+            Some(id) => {
+                reply_tags(parse_event_id(&id).map_err(|e| RmcpError::invalid_params(e, None))?)
+            }
+            None => Vec::new(),
+        };
+        let to = to.map(|to| self.addressee(&to)).transpose()?;
+        let hints = to.as_ref().map(|to| to.relays.clone()).unwrap_or_default();
+        let to = to.map(|to| to.pubkey);
+        let answers = AnswerLedger::global();
+        let correlation_id = self.correlation_id.or_else(|| match &to {
+            Some(to) => answers.current_from(to),
+            None => answers.current(),
+        });
+        let recipient = match to {
+            Some(to) => to,
+            None => self.unaddressed_recipient(correlation_id)?,
+        };
+        let conversation = self.conversation_with(recipient);
+        let duplicates = Suppressor::global();
+        let dedup_key = recipient.to_hex();
+        if let Some(ago) = duplicates.claim(Traffic::Send, &dedup_key, &message) {
+            return deduplicated(ago);
+        }
+        let claimed = message.clone();
+        match answers.claim(correlation_id) {
+            Answer::First => {}
+            Answer::Again(earlier) => log::warn!(
+                "Answer number {} to message {}; the user may get duplicates",
+                earlier + 1,
+                correlation_id.map(|id| id.to_hex()).unwrap_or_default()
+            ),
+            Answer::Reroute(_) => return self.reroute_answer(message).await,
+        }
+        let result = if hints.is_empty() {
+            self.send_with_retry(
+                self.client.as_ref(),
+                ("main", relays::MAIN),
+                &conversation,
+                message,
+                None,
+                rumor_tags,
+            )
+            .await
+        } else {
+            self.send_with_hints(&conversation, message, rumor_tags, &hints)
+                .await
+        };
         if result.is_ok() {
-            self.response_tracker.mark_response_sent();
+            self.response_tracker.mark_response_sent(recipient);
+        } else {
+            answers.release(correlation_id);
+            duplicates.release(Traffic::Send, &dedup_key, &claimed);
         }
         result
     }
 
-    #[tool(description = "Send a progress/debug message to the user via the progress identity")]
-    pub async fn progress(
+    /// Who `to` names: the target or another allowed sender, or with `ALLOW_ARBITRARY_RECIPIENTS`
+    /// anyone, along with the relays their nprofile lists
+    fn addressee(&self, to: &str) -> Result<senders::Recipient, NparrotError> {
+        let recipient =
+            senders::parse_recipient(to).map_err(|e| NparrotError::invalid_params("to", e))?;
+        let pubkey = recipient.pubkey;
+        if pubkey == self.target_pubkey
+            || self.other_senders.contains(&pubkey)
+            || self.arbitrary_recipients
+        {
+            Ok(recipient)
+        } else {
+            Err(NparrotError::invalid_params(
+                "to",
+                format!(
+                    "{} is neither the user nor an allowed sender (ALLOW_ARBITRARY_RECIPIENTS=1 allows anyone)",
+                    to
+                ),
+            ))
+        }
+    }
+
+    /// A `send` to someone whose nprofile lists relays: published like any other message, and
+    /// to those relays as well, without adding them to our pool
+    async fn send_with_hints(
         &self,
-        #[tool(aggr)] ProgressMessageRequest { message }: ProgressMessageRequest,
+        conversation: &Conversation,
+        message: String,
+        rumor_tags: Vec<Tag>,
+        hints: &[RelayUrl],
     ) -> Result<CallToolResult, RmcpError> {
-        let result = match &self.progress_client {
-            Some(c) => self.send_with_retry(c, message).await,
-            None => Err(RmcpError::internal_error(
-                "Progress identity not configured",
+        let route = ("main", relays::MAIN);
+        let (content, event) = self
+            .prepare_outgoing(
+                self.client.as_ref(),
+                route,
+                conversation,
+                message,
                 None,
-            )),
-        };
-        if result.is_ok() {
-            self.response_tracker.mark_progress_sent();
+                rumor_tags,
+            )
+            .await?;
+        let result = self
+            .publish_prepared(
+                self.client.as_ref(),
+                route,
+                conversation,
+                &content,
+                event.clone(),
+            )
+            .await;
+        match relays::publish_via(hints, &event, RELAY_HINT_TIMEOUT).await {
+            Ok(output) => log::info!(
+                "Sent {} to {} of {} hinted relay(s)",
+                event.id,
+                output.success.len(),
+                hints.len()
+            ),
+            Err(e) => log::warn!("Could not send {} to the hinted relays: {}", event.id, e),
         }
         result
     }
 
-    #[tool(description = "Listen and wait for the user's next message")]
-    pub async fn wait(&self) -> Result<CallToolResult, RmcpError> {
-        let message = wait_for_message(&self.client, &self.our_pubkey, &self.target_pubkey)
-            .await
-            .map_err(|e| RmcpError::internal_error(e.to_string(), None))?;
+    /// `value` as the pubkey of the target or another allowed sender
+    fn known_sender(&self, field: &str, value: &str) -> Result<PublicKey, NparrotError> {
+        let pubkey = senders::parse(value).map_err(|e| NparrotError::invalid_params(field, e))?;
+        if pubkey == self.target_pubkey || self.other_senders.contains(&pubkey) {
+            Ok(pubkey)
+        } else {
+            Err(NparrotError::invalid_params(
+                field,
+                format!("{} is neither the user nor an allowed sender", value),
+            ))
+        }
+    }
 
-        self.response_tracker.start_conversation();
+    /// Who a `send` without `to` goes to: the target, unless it answers another sender's
+    /// message, which needs `to` (or `NPARROT_UNADDRESSED_TO_TARGET`) rather than a guess. An
+    /// agent's explicit correlation addresses its sender.
+    fn unaddressed_recipient(
+        &self,
+        correlation_id: Option<EventId>,
+    ) -> Result<PublicKey, NparrotError> {
+        let sender = correlation_id.and_then(|id| AnswerLedger::global().sender_of(id));
+        match sender {
+            Some(sender) if self.other_senders.contains(&sender) => {
+                if self.correlation_id.is_some() {
+                    Ok(sender)
+                } else if senders::unaddressed_to_target() {
+                    log::info!(
+                        "Sending an answer to a message from {} to the target user",
+                        sender
+                    );
+                    Ok(self.target_pubkey)
+                } else {
+                    Err(NparrotError::invalid_params(
+                        "to",
+                        format!(
+                            "This answers a message from {}, not from the primary user; set `to` to the sender wait returned",
+                            sender.to_bech32().unwrap_or_else(|_| sender.to_hex())
+                        ),
+                    ))
+                }
+            }
+            _ => Ok(self.target_pubkey),
+        }
+    }
 
-        let reminder = create_response_reminder();
-        let enhanced_message = format!("{}\n\n{}", message, reminder);
+    /// Where messages to `recipient` go: the configured conversation for the target, a DM for
+    /// anyone else
+    fn conversation_with(&self, recipient: PublicKey) -> Conversation {
+        if recipient == self.target_pubkey {
+            self.conversation.clone()
+        } else {
+            Conversation::Direct(recipient)
+        }
+    }
+
+    /// Who receives what is sent to `conversation`
+    fn recipient(&self, conversation: &Conversation) -> PublicKey {
+        match conversation {
+            Conversation::Direct(receiver) => *receiver,
+            Conversation::Group(_) => self.target_pubkey,
+        }
+    }
 
+    /// Sends a second answer to the same user message to the progress channel instead
+    async fn reroute_answer(&self, message: String) -> Result<CallToolResult, RmcpError> {
+        log::warn!("The user message was already answered; rerouting the answer to progress");
+        if self.progress_clients.resolve(None).is_none() {
+            return Err(NparrotError::invalid_params(
+                "message",
+                "The user already got an answer to this message and there is no progress channel to put another one",
+            )
+            .into());
+        }
+        self.progress(ProgressMessageRequest {
+            message: format!(
+                "↪️ Another answer to a message that was already answered:\n\n{}",
+                message
+            ),
+            expire_after_secs: None,
+            channel: None,
+        })
+        .await?;
         Ok(CallToolResult::success(vec![Content::text(
-            enhanced_message,
+            "The user already got an answer to this message, so this one went to the progress channel",
         )]))
     }
 
-    async fn send_with_retry(
+    #[tool(
+        description = "Send the user a file (e.g. a diagram or a log) that doesn't fit in a message: uploads it to the media server and sends its link, with an optional message"
+    )]
+    pub async fn upload_and_send_file(
         &self,
-        client: &Client,
-        message: String,
+        #[tool(aggr)] UploadFileRequest { path, message }: UploadFileRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        const MAX_RETRIES: u32 = 3;
-        const BASE_DELAY_MS: u64 = 1000;
-        let mut last_error = String::new();
+        let Some(uploads) = &self.uploads else {
+            return Err(NparrotError::backend_missing(
+                "media_server",
+                "No media server configured (--media-server or NPARROT_MEDIA_SERVER)",
+            )
+            .into());
+        };
+        let file = uploads.check_path(&path)?;
+        let message = message
+            .map(|message| message.trim().to_string())
+            .filter(|message| !message.is_empty());
 
-        for attempt in 0..MAX_RETRIES {
-            let result = client
-                .send_private_msg(self.target_pubkey, message.clone(), [])
-                .await;
-            match result {
-                Ok(_) => {
-                    let msg = if attempt == 0 {
-                        "Sent message"
-                    } else {
-                        "Sent message after retry"
-                    };
-                    return Ok(CallToolResult::success(vec![Content::text(
-                        msg.to_string(),
-                    )]));
+        let uploaded = uploads.upload(self.client.as_ref(), &file).await;
+        // The text goes out whether or not the file made it
+        let (text, tags) = match &uploaded {
+            Ok(file) => (
+                format!(
+                    "{}📎 {} ({} bytes): {}\nsha256: {}",
+                    message
+                        .as_ref()
+                        .map(|message| format!("{}\n\n", message))
+                        .unwrap_or_default(),
+                    file.name,
+                    file.size,
+                    file.url,
+                    file.sha256
+                ),
+                vec![file.imeta_tag()],
+            ),
+            Err(e) => (
+                format!(
+                    "{}⚠️ Could not attach {}: {}",
+                    message
+                        .as_ref()
+                        .map(|message| format!("{}\n\n", message))
+                        .unwrap_or_default(),
+                    path,
+                    e
+                ),
+                Vec::new(),
+            ),
+        };
+        self.send_with_retry(
+            self.client.as_ref(),
+            ("main", relays::MAIN),
+            &self.conversation,
+            text,
+            None,
+            tags,
+        )
+        .await?;
+        self.response_tracker.mark_response_sent(self.target_pubkey);
+
+        match uploaded {
+            Ok(file) => Ok(CallToolResult::success(vec![
+                Content::text(format!("Sent {}", file.url)),
+                Content::json(serde_json::json!({
+                    "url": file.url,
+                    "sha256": file.sha256,
+                    "size": file.size,
+                    "mime": file.mime,
+                }))?,
+            ])),
+            Err(e) => Ok(e.to_result(format!(
+                "The upload failed, sent the message with an error note instead: {}",
+                e
+            ))),
+        }
+    }
+
+    #[tool(
+        description = "Ask the user for a payment: gets a lightning invoice for the amount from the configured lightning address and sends it to the user"
+    )]
+    pub async fn request_zap(
+        &self,
+        #[tool(aggr)] RequestZapRequest { amount_sats, memo }: RequestZapRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let Some(provider) = zap::provider() else {
+            return Err(NparrotError::backend_missing(
+                "lud16",
+                "No lightning address configured (--lud16 or NPARROT_LUD16)",
+            )
+            .into());
+        };
+        let amount_msats = amount_sats
+            .checked_mul(1000)
+            .filter(|msats| *msats > 0)
+            .ok_or_else(|| NparrotError::invalid_params("amount_sats", "Invalid amount"))?;
+        let memo = memo
+            .map(|memo| memo.trim().to_string())
+            .filter(|memo| !memo.is_empty());
+
+        let invoice = provider.invoice(amount_msats, memo.as_deref()).await?;
+        let message = format!(
+            "⚡ Payment request: {} sats{}\n\nlightning:{}",
+            amount_sats,
+            memo.as_ref()
+                .map(|memo| format!(" for {}", memo))
+                .unwrap_or_default(),
+            invoice
+        );
+        self.send_with_retry(
+            self.client.as_ref(),
+            ("main", relays::MAIN),
+            &self.conversation,
+            message,
+            None,
+            Vec::new(),
+        )
+        .await?;
+        self.response_tracker.mark_response_sent(self.target_pubkey);
+
+        Ok(CallToolResult::success(vec![
+            Content::text(format!("Sent a payment request for {} sats", amount_sats)),
+            Content::json(serde_json::json!({
+                "invoice": invoice,
+                "amount_msats": amount_msats,
+                "memo": memo,
+            }))?,
+        ]))
+    }
+
+    #[tool(
+        description = "Send several messages to the user in one call, e.g. a summary followed by code blocks; they arrive in the given order"
+    )]
+    pub async fn send_batch(
+        &self,
+        #[tool(aggr)] SendBatchRequest { messages }: SendBatchRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        if messages.is_empty() {
+            return Err(NparrotError::invalid_params("messages", "No messages to send").into());
+        }
+
+        // Wrapped up front, so a message that can't be built stops the batch before any is
+        // published; consecutive dates keep the order however the publishes interleave
+        let client = self.client.as_ref();
+        let first_created_at = Timestamp::now();
+        let mut events = Vec::with_capacity(messages.len());
+        let encrypted = matches!(self.conversation, Conversation::Direct(_));
+        let limit = message_size::limit_for(
+            self.conversation
+                .relays()
+                .or_else(|| relays::targets_for(relays::MAIN))
+                .as_deref(),
+        );
+        let messages: Vec<String> = messages
+            .into_iter()
+            .map(|message| message_template::apply(redact::redact(&message).into_owned(), "main"))
+            .map(|message| message_size::bounded(message, encrypted, limit))
+            .collect();
+        for (index, message) in messages.iter().enumerate() {
+            let created_at = first_created_at + Duration::from_secs(index as u64);
+            let event = self
+                .conversation
+                .prepare(client, message.clone(), None, Vec::new(), created_at)
+                .await
+                .map_err(|e| NparrotError::internal(format!("Message {}: {}", index, e)))?;
+            events.push(event);
+        }
+        let tracker = DeliveryTracker::global();
+        for event in &events {
+            tracker.track(event.id, self.target_pubkey, "main");
+        }
+
+        let targets = self
+            .conversation
+            .relays()
+            .or_else(|| relays::targets_for(relays::MAIN));
+        let publishes: Vec<_> = events
+            .iter()
+            .map(|event| self.publish_with_retry(client, "main", targets.as_deref(), event))
+            .collect();
+        let results: Vec<Result<bool, String>> = futures::stream::iter(publishes)
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        let failed: Vec<Event> = events
+            .iter()
+            .zip(&results)
+            .filter(|(event, result)| {
+                result.is_err()
+                    && tracker
+                        .get(&event.id)
+                        .is_some_and(|record| record.state == DeliveryState::Failed)
+            })
+            .map(|(event, _)| event.clone())
+            .collect();
+        let queued =
+            !failed.is_empty() && redelivery::enqueue_batch(failed, self.target_pubkey, "main");
+
+        let statuses: Vec<serde_json::Value> = events
+            .iter()
+            .zip(&results)
+            .enumerate()
+            .map(|(index, (event, result))| match result {
+                Ok(_) => serde_json::json!({
+                    "index": index,
+                    "event_id": event.id.to_hex(),
+                    "status": "sent",
+                }),
+                Err(error) => serde_json::json!({
+                    "index": index,
+                    "event_id": event.id.to_hex(),
+                    "status": if queued { "queued" } else { "failed" },
+                    "error": error,
+                }),
+            })
+            .collect();
+
+        for ((event, message), _) in events
+            .iter()
+            .zip(&messages)
+            .zip(&results)
+            .filter(|(_, result)| result.is_ok())
+        {
+            self.record_sent(
+                ("main", relays::MAIN),
+                self.target_pubkey,
+                event.id,
+                message,
+            );
+        }
+        let sent = results.iter().filter(|result| result.is_ok()).count();
+        if sent > 0 {
+            self.response_tracker.mark_response_sent(self.target_pubkey);
+        }
+        let summary = format!("Sent {} of {} messages", sent, results.len());
+        if sent == results.len() {
+            return Ok(CallToolResult::success(vec![
+                Content::text(summary),
+                Content::json(statuses)?,
+            ]));
+        }
+        let error = NparrotError::relay_unavailable(format!(
+            "{}{}",
+            summary,
+            if queued {
+                ", the rest is queued for automatic resend"
+            } else {
+                ""
+            }
+        ));
+        Ok(CallToolResult::error(vec![
+            Content::text(error.message().to_string()),
+            Content::text(error.payload().to_string()),
+            Content::json(statuses)?,
+        ]))
+    }
+
+    #[tool(
+        description = "Send a progress/debug message to the user via the progress identity of the given channel"
+    )]
+    pub async fn progress(
+        &self,
+        #[tool(aggr)] ProgressMessageRequest {
+            message,
+            expire_after_secs,
+            channel,
+        }: ProgressMessageRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let expire_after_secs = expire_after_secs.or(self.progress_expire_after_secs);
+        let result = match self.progress_clients.resolve(channel.as_deref()) {
+            Some((name, c)) => {
+                let duplicates = Suppressor::global();
+                if let Some(ago) = duplicates.claim(Traffic::Progress, name, &message) {
+                    return deduplicated(ago);
                 }
-                Err(e) => {
-                    last_error = e.to_string();
-                    log::warn!("Attempt {} failed: {}", attempt + 1, last_error);
+                let claimed = message.clone();
+                let result = self
+                    .send_with_retry(
+                        c.as_ref(),
+                        ("progress", name),
+                        &Conversation::Direct(self.target_pubkey),
+                        message,
+                        expire_after_secs,
+                        Vec::new(),
+                    )
+                    .await;
+                if result.is_err() {
+                    duplicates.release(Traffic::Progress, name, &claimed);
                 }
+                result
+            }
+            None => Err(NparrotError::backend_missing(
+                "progress identity",
+                "Progress identity not configured",
+            )
+            .into()),
+        };
+        if result.is_ok() {
+            self.response_tracker.mark_progress_sent();
+        }
+        result
+    }
+
+    #[tool(
+        description = "Listen and wait for the user's next message, or for the next one of the sender given in `from`"
+    )]
+    pub async fn wait(
+        &self,
+        #[tool(aggr)] WaitRequest { from }: WaitRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let from = from
+            .map(|from| self.known_sender("from", &from))
+            .transpose()?;
+        let inbox = self.inbox().await;
+        let Some(Buffered {
+            message,
+            received_at,
+            sequence,
+        }) = inbox.next(from).await
+        else {
+            // Resubscribe on the next call
+            self.inbox.lock().await.take();
+            return Err(NparrotError::relay_unavailable(
+                "The subscription for incoming messages ended",
+            )
+            .into());
+        };
+        // Nothing was running while we waited, so there is nothing left to interrupt
+        if self.interrupt.take() {
+            log::info!("Discarding an interrupt that arrived while idle");
+        }
+
+        metrics::message_received("main");
+        self.response_tracker.start_conversation(message.sender);
+        AnswerLedger::global().begin(message.event_id, message.sender);
+        let primary = message.sender == self.target_pubkey;
+        let sender_note = if primary {
+            String::new()
+        } else {
+            format!(
+                "[Message {} from {}, not the primary user; answer with `to` set to this sender]\n\n",
+                sequence,
+                message
+                    .sender
+                    .to_bech32()
+                    .unwrap_or_else(|_| message.sender.to_hex())
+            )
+        };
+
+        let reminder = create_response_reminder();
+        let buffered = received_at.elapsed();
+        let enhanced_message = if buffered >= BUFFERED_NOTE_AFTER {
+            format!(
+                "{}[Sent {} and buffered for {}s while no session was waiting; it is not a fresh message]\n\n{}\n\n{}",
+                sender_note,
+                timezone::format(
+                    chrono::DateTime::from_timestamp(message.created_at.as_u64() as i64, 0)
+                        .unwrap_or_default()
+                ),
+                buffered.as_secs(),
+                message.content,
+                reminder
+            )
+        } else {
+            format!("{}{}\n\n{}", sender_note, message.content, reminder)
+        };
+
+        // Event id (for `send`'s reply_to) and any envelope type/meta, without repeating the text
+        let mut details = serde_json::to_value(&message)
+            .map_err(|e| RmcpError::internal_error(e.to_string(), None))?;
+        if let Some(details) = details.as_object_mut() {
+            details.remove("content");
+            details.insert(
+                "correlation_id".to_string(),
+                serde_json::Value::String(message.event_id.to_hex()),
+            );
+            details.insert("sequence".to_string(), sequence.into());
+            details.insert("primary".to_string(), primary.into());
+            details.insert(
+                "conversation_language".to_string(),
+                serde_json::Value::String(language::conversation()),
+            );
+            if buffered >= BUFFERED_NOTE_AFTER {
+                details.insert("buffered_secs".to_string(), buffered.as_secs().into());
+            }
+        }
+
+        let mut content = vec![Content::text(enhanced_message), Content::json(details)?];
+        if let Some(reminder) = self.context.as_ref().and_then(|context| context.reminder()) {
+            content.push(Content::text(reminder));
+        }
+        self.watch_turn();
+        Ok(CallToolResult::success(content))
+    }
+
+    /// Starts the watchdog on the turn `wait` just started: a progress notice after one stall
+    /// interval without any tool call, an apology on the main channel after the second
+    fn watch_turn(&self) {
+        let Some(watchdog) = self.watchdog.clone() else {
+            return;
+        };
+        let generation = watchdog.turn_started();
+        let chat = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(watchdog.interval()).await;
+            if !watchdog.stalled(generation) {
+                return;
+            }
+            log::warn!(
+                "Stalled turn: no tool call {}s after the user's message",
+                watchdog.interval().as_secs()
+            );
+            let notice = ProgressMessageRequest {
+                message: catalog::text(Key::StallNotice, &[]),
+                expire_after_secs: None,
+                channel: None,
+            };
+            if let Err(e) = chat.progress(notice).await {
+                log::warn!("Could not send the stall notice: {}", e.message);
             }
 
-            if attempt < MAX_RETRIES - 1 {
-                let delay = Duration::from_millis(BASE_DELAY_MS * (1 << attempt));
-                log::info!("Retrying in {}ms...", delay.as_millis());
-                sleep(delay).await;
+            tokio::time::sleep(watchdog.interval()).await;
+            if !watchdog.stalled(generation) {
+                return;
             }
+            log::warn!(
+                "Stalled turn: still no tool call after {}s, apologizing to the user",
+                2 * watchdog.interval().as_secs()
+            );
+            // Not an answer: the model's own `send` still counts as the first one
+            let apology = chat
+                .send_with_retry(
+                    chat.client.as_ref(),
+                    ("main", relays::MAIN),
+                    &chat.conversation,
+                    catalog::text(Key::StallApology, &[]),
+                    None,
+                    Vec::new(),
+                )
+                .await;
+            if let Err(e) = apology {
+                log::warn!("Could not send the stall apology: {}", e.message);
+            }
+        });
+    }
+
+    fn context(&self) -> Result<&ContextStore, NparrotError> {
+        self.context
+            .as_deref()
+            .ok_or_else(|| NparrotError::backend_missing("context", "This server keeps no context"))
+    }
+
+    /// `set_context` for the servers that offer it
+    pub async fn set_context(
+        &self,
+        SetContextRequest {
+            key,
+            value,
+            ttl_secs,
+        }: SetContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let entry = self.context()?.set(&key, value, ttl_secs)?;
+        let text = match entry.expires_at {
+            Some(expires_at) => format!(
+                "Set context '{}' until {}",
+                key.trim(),
+                timezone::format(expires_at)
+            ),
+            None => format!("Set context '{}'", key.trim()),
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    /// `get_context` for the servers that offer it
+    pub async fn get_context(
+        &self,
+        GetContextRequest { key }: GetContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let values = self.context()?.get(key.as_deref());
+        let text = match (&key, values.len()) {
+            (Some(key), 0) => format!("No context value '{}'", key.trim()),
+            (None, 0) => "The context is empty".to_string(),
+            (_, count) => format!("{} context value(s)", count),
+        };
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(values)?,
+        ]))
+    }
+
+    /// `clear_context` for the servers that offer it
+    pub async fn clear_context(
+        &self,
+        ClearContextRequest { key }: ClearContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let removed = self.context()?.clear(key.as_deref());
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Removed {} context value(s)",
+            removed
+        ))]))
+    }
+
+    #[tool(
+        description = "Schedule a message to the user for later, e.g. a reminder; it is sent even if you are busy or the server restarts in between"
+    )]
+    pub async fn send_later(
+        &self,
+        #[tool(aggr)] SendLaterRequest { message, when }: SendLaterRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let schedule = Self::schedule()?;
+        if message.trim().is_empty() {
+            return Err(NparrotError::invalid_params("message", "The message is empty").into());
+        }
+        let deliver_at = parse_when(&when, chrono::Utc::now(), &timezone::default_zone())
+            .map_err(|e| NparrotError::invalid_params("when", e))?;
+        let entry = schedule.add(message, deliver_at)?;
+        Ok(CallToolResult::success(vec![
+            Content::text(format!(
+                "Scheduled message {} for {}",
+                entry.id,
+                timezone::format(entry.deliver_at)
+            )),
+            Content::json(serde_json::json!({
+                "id": entry.id,
+                "deliver_at": entry.deliver_at,
+            }))?,
+        ]))
+    }
+
+    #[tool(description = "List the messages scheduled with send_later that are not sent yet")]
+    pub async fn list_scheduled(&self) -> Result<CallToolResult, RmcpError> {
+        let pending = Self::schedule()?.pending();
+        if pending.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text(
+                "No scheduled messages",
+            )]));
         }
+        let mut text = format!("{} scheduled message(s):", pending.len());
+        for entry in &pending {
+            text.push_str(&format!(
+                "\n- {} at {}: {}",
+                entry.id,
+                timezone::format(entry.deliver_at),
+                entry.message
+            ));
+        }
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "Cancel a message scheduled with send_later before it is sent")]
+    pub async fn cancel_scheduled(
+        &self,
+        #[tool(aggr)] CancelScheduledRequest { id }: CancelScheduledRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let entry = Self::schedule()?.cancel(&id)?;
+        Ok(CallToolResult::success(vec![Content::text(format!(
+            "Cancelled message {} scheduled for {}",
+            entry.id,
+            timezone::format(entry.deliver_at)
+        ))]))
+    }
 
-        Err(RmcpError::internal_error(
+    /// Builds the message a scheduled entry goes out as, without publishing it
+    pub async fn prepare_scheduled(&self, message: &str) -> Result<Event, NparrotError> {
+        self.conversation
+            .prepare(
+                self.client.as_ref(),
+                message_template::apply(redact::redact(message).into_owned(), "main"),
+                None,
+                Vec::new(),
+                Timestamp::now(),
+            )
+            .await
+            .map_err(|e| NparrotError::internal(e.to_string()))
+    }
+
+    /// Sends a scheduled message built by `prepare_scheduled` the way `send` would
+    pub async fn deliver_scheduled(&self, message: &str, event: Event) -> Result<(), RmcpError> {
+        self.publish_prepared(
+            self.client.as_ref(),
+            ("main", relays::MAIN),
+            &self.conversation,
+            message,
+            event,
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Keeps a sent message for `summarize_conversation` and the transcript
+    fn record_sent(
+        &self,
+        (channel, route): (&str, &str),
+        recipient: PublicKey,
+        event_id: EventId,
+        content: &str,
+    ) {
+        history::record_sent(
+            recipient,
+            if channel == "main" { channel } else { route },
+            content,
+        );
+        if channel == "progress" {
+            transcript::sent(channel, Some(&self.source), event_id, content);
+            progress_history::record(&self.source, content);
+        } else {
+            transcript::sent(channel, None, event_id, content);
+        }
+    }
+
+    fn schedule() -> Result<Arc<schedule::Schedule>, NparrotError> {
+        schedule::global().ok_or_else(|| {
+            NparrotError::backend_missing(
+                "schedule",
+                "Scheduled messages are only available in the long-running MCP servers",
+            )
+        })
+    }
+
+    #[tool(
+        description = "Fetch the recent conversation with the user as a transcript (← user, → you), to summarize when earlier decisions have scrolled out of context; pass a summary you wrote to store it as a memory"
+    )]
+    pub async fn summarize_conversation(
+        &self,
+        #[tool(aggr)] SummarizeConversationRequest {
+            hours,
+            include_progress,
+            max_bytes,
+            summary,
+        }: SummarizeConversationRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let hours = hours.unwrap_or(24);
+        if hours == 0 {
+            return Err(NparrotError::invalid_params("hours", "Must be at least 1").into());
+        }
+        let summary = summary
+            .map(|summary| summary.trim().to_string())
+            .filter(|summary| !summary.is_empty());
+        // Checked first, so a summary is never silently dropped
+        if summary.is_some() && self.summaries.is_none() {
+            return Err(NparrotError::backend_missing(
+                "memory",
+                "This server has no memory store to keep the summary in",
+            )
+            .into());
+        }
+
+        let since = Timestamp::now() - Duration::from_secs(hours.saturating_mul(3600));
+        let entries = history::fetch(
+            self.client.as_ref(),
+            self.our_pubkey,
+            self.target_pubkey,
+            since,
+        )
+        .await?;
+        let (transcript, omitted) = history::render(
+            &entries,
+            include_progress.unwrap_or(false),
+            max_bytes.unwrap_or(DEFAULT_TRANSCRIPT_BYTES),
+        );
+
+        let mut stored = None;
+        if let (Some(summary), Some(summaries)) = (summary, &self.summaries) {
+            stored = Some(summaries.store_summary(summary).await?);
+        }
+
+        let text = if transcript.is_empty() {
+            format!("No messages in the last {} hours", hours)
+        } else if omitted > 0 {
+            format!("[{} older messages left out]\n{}", omitted, transcript)
+        } else {
+            transcript
+        };
+        Ok(CallToolResult::success(vec![
+            Content::text(text),
+            Content::json(serde_json::json!({
+                "hours": hours,
+                "messages": entries.len(),
+                "omitted": omitted,
+                "stored_summary": stored,
+            }))?,
+        ]))
+    }
+
+    #[tool(
+        description = "Show how many sent messages relays have acknowledged, which are still pending and which failed, and how often each recent user message was answered"
+    )]
+    pub async fn delivery_status(
+        &self,
+        #[tool(aggr)] DeliveryStatusRequest { list_pending }: DeliveryStatusRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let mut summary = DeliveryTracker::global().summary(list_pending);
+        summary.answers = AnswerLedger::global().counts();
+        Ok(CallToolResult::success(vec![Content::json(summary)?]))
+    }
+
+    #[tool(
+        description = "Search the progress messages sent earlier, e.g. what was being done an hour ago: filter by since (\"2h\" or an ISO 8601 time) and contains, newest `limit` entries oldest first"
+    )]
+    pub async fn progress_history(
+        &self,
+        #[tool(aggr)] request: ProgressHistoryRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        progress_history::tool_result(request)
+    }
+
+    #[tool(
+        description = "Show what each configured relay published about itself (NIP-11 size and subscription limits, auth and payment requirements) whether NIP-42 authentication with it succeeded and how many subscriptions are open or queued on it"
+    )]
+    pub async fn relay_info(
+        &self,
+        #[tool(aggr)] RelayInfoRequest { relay }: RelayInfoRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let relays: Vec<_> = relays::known_info()
+            .into_iter()
+            .filter(|info| {
+                relay
+                    .as_deref()
+                    .is_none_or(|url| info.url == url.trim_end_matches('/'))
+            })
+            .collect();
+        if relays.is_empty() {
+            return Ok(CallToolResult::error(vec![Content::text(match relay {
+                Some(url) => format!("{} is not a configured relay", url),
+                None => "No relays configured".to_string(),
+            })]));
+        }
+        Ok(CallToolResult::success(vec![Content::json(
+            serde_json::json!({ "relays": relays }),
+        )?]))
+    }
+
+    #[tool(
+        description = "Check the DM path end to end: send a tagged message to ourselves through the relays and report how each stage went. The user never sees it"
+    )]
+    pub async fn selftest(
+        &self,
+        #[tool(aggr)] SelftestRequest { timeout_secs }: SelftestRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let timeout = Duration::from_secs(timeout_secs.unwrap_or(selftest::DEFAULT_TIMEOUT_SECS));
+        let report = selftest::run(self.client.as_ref(), self.our_pubkey, timeout).await;
+        let content = vec![Content::json(&report)?];
+        Ok(if report.ok {
+            CallToolResult::success(content)
+        } else {
+            CallToolResult::error(content)
+        })
+    }
+
+    /// Starts receiving now rather than at the first `wait`, so messages sent before a client
+    /// connects are buffered too
+    pub async fn listen(&self) {
+        self.inbox().await;
+    }
+
+    async fn inbox(&self) -> Arc<Inbox> {
+        let mut inbox = self.inbox.lock().await;
+        inbox
+            .get_or_insert_with(|| {
+                let commands = self.palette.clone().map(|palette| {
+                    let (commands, received) = mpsc::unbounded_channel();
+                    tokio::spawn(self.clone().answer_commands(palette.clone(), received));
+                    (palette, commands)
+                });
+                Arc::new(Inbox::start(
+                    self.client.clone(),
+                    self.our_pubkey,
+                    std::iter::once(self.target_pubkey)
+                        .chain(self.other_senders.iter().copied())
+                        .collect(),
+                    self.conversation.clone(),
+                    self.interrupt.clone(),
+                    self.coalesce,
+                    commands,
+                ))
+            })
+            .clone()
+    }
+
+    /// Answers each command the inbox hands over with a reply to its sender, until the inbox
+    /// is gone
+    async fn answer_commands(
+        self,
+        palette: Palette,
+        mut commands: mpsc::UnboundedReceiver<IncomingMessage>,
+    ) {
+        while let Some(command) = commands.recv().await {
+            let Some(invocation) = palette.parse(&command.content) else {
+                continue;
+            };
+            let reply = match invocation {
+                Invocation::Run(Action::Status) => self.status_summary().await,
+                Invocation::Run(Action::Agents) => match &self.roster {
+                    Some(roster) => {
+                        let agents = roster.agent_lines().await;
+                        if agents.is_empty() {
+                            "No agents running".to_string()
+                        } else {
+                            format!("Agents:\n{}", agents.join("\n"))
+                        }
+                    }
+                    None => "This server runs no agents".to_string(),
+                },
+                Invocation::Run(Action::Stop) => {
+                    self.interrupt.request();
+                    "⏹️ Stopping whatever is running".to_string()
+                }
+                Invocation::Run(Action::Help) => palette.help(),
+                Invocation::Run(Action::Reply(text)) => text,
+                Invocation::Unknown(name) => palette.unknown(&name),
+            };
+            // Its own correlation, so the reply doesn't count as an answer to anything the
+            // model is working on
+            let reply = self
+                .clone()
+                .with_correlation(Some(command.event_id))
+                .send(SendMessageRequest {
+                    message: reply,
+                    reply_to: Some(command.event_id.to_hex()),
+                    to: Some(command.sender.to_hex()),
+                })
+                .await;
+            if let Err(e) = reply {
+                log::warn!(
+                    "Could not answer command {}: {}",
+                    command.event_id,
+                    e.message
+                );
+            }
+        }
+    }
+
+    /// What `!status` answers
+    async fn status_summary(&self) -> String {
+        let deliveries = DeliveryTracker::global().summary(false);
+        let waiting = match self.inbox.lock().await.as_ref() {
+            Some(inbox) => inbox.waiting(),
+            None => 0,
+        };
+        let mut lines = vec![
+            format!("nparrot {}", env!("CARGO_PKG_VERSION")),
+            format!("Inbox: {} message(s) waiting", waiting),
             format!(
-                "Failed to send message after {} attempts: {}",
-                MAX_RETRIES, last_error
+                "Deliveries: {} acknowledged, {} pending, {} failed",
+                deliveries.acknowledged, deliveries.pending, deliveries.failed
             ),
-            None,
+        ];
+        if let Some(roster) = &self.roster {
+            lines.push(format!(
+                "Agents: {} running",
+                roster.agent_lines().await.len()
+            ));
+        }
+        lines.join("\n")
+    }
+
+    async fn send_with_retry(
+        &self,
+        client: &dyn DmTransport,
+        (channel, route): (&str, &str),
+        conversation: &Conversation,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+    ) -> Result<CallToolResult, RmcpError> {
+        let (content, event) = self
+            .prepare_outgoing(
+                client,
+                (channel, route),
+                conversation,
+                message,
+                expire_after_secs,
+                rumor_tags,
+            )
+            .await?;
+        self.publish_prepared(client, (channel, route), conversation, &content, event)
+            .await
+    }
+
+    /// Templates, redacts and bounds `message` and builds its event for `conversation`
+    async fn prepare_outgoing(
+        &self,
+        client: &dyn DmTransport,
+        (channel, route): (&str, &str),
+        conversation: &Conversation,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+    ) -> Result<(String, Event), RmcpError> {
+        let message = message_template::apply(
+            redact::redact(&message).into_owned(),
+            if channel == "main" { channel } else { route },
+        );
+        let message = message_size::bounded(
+            message,
+            matches!(conversation, Conversation::Direct(_)),
+            message_size::limit_for(
+                conversation
+                    .relays()
+                    .or_else(|| relays::targets_for(route))
+                    .as_deref(),
+            ),
+        );
+        let content = message.clone();
+        // Built once so every attempt republishes the same event id
+        let event = conversation
+            .prepare(
+                client,
+                message,
+                expire_after_secs,
+                rumor_tags,
+                Timestamp::now(),
+            )
+            .await
+            .map_err(|e| NparrotError::internal(e.to_string()))?;
+        Ok((content, event))
+    }
+
+    /// Publishes an event built by `send_with_retry` with retries, queueing it for automatic
+    /// resend if every attempt fails
+    async fn publish_prepared(
+        &self,
+        client: &dyn DmTransport,
+        (channel, route): (&str, &str),
+        conversation: &Conversation,
+        content: &str,
+        event: Event,
+    ) -> Result<CallToolResult, RmcpError> {
+        let recipient = self.recipient(conversation);
+        let tracker = DeliveryTracker::global();
+        tracker.track(event.id, recipient, channel);
+
+        let targets = conversation.relays().or_else(|| relays::targets_for(route));
+        let last_error = match self
+            .publish_with_retry(client, channel, targets.as_deref(), &event)
+            .await
+        {
+            Ok(retried) => {
+                self.record_sent((channel, route), recipient, event.id, content);
+                let msg = if retried {
+                    "Sent message after retry"
+                } else {
+                    "Sent message"
+                };
+                return Ok(CallToolResult::success(vec![Content::text(
+                    msg.to_string(),
+                )]));
+            }
+            Err(last_error) => last_error,
+        };
+
+        let queued = tracker
+            .get(&event.id)
+            .is_some_and(|record| record.state == DeliveryState::Failed)
+            && redelivery::enqueue(event, recipient, channel);
+        // Progress is best-effort: with its relays down it waits in the resend queue instead of
+        // failing the tool call
+        if queued && channel == "progress" {
+            log::warn!(
+                "Progress relays ({}) unreachable, queued the message for resend: {}",
+                route,
+                last_error
+            );
+            return Ok(CallToolResult::success(vec![
+                Content::text("Progress relays are unreachable; the message is queued for resend"),
+                Content::json(serde_json::json!({ "queued": true }))?,
+            ]));
+        }
+        Err(NparrotError::relay_unavailable(format!(
+            "Failed to send message after {} attempts: {}{}",
+            retry::policy(retry::RELAY).max_attempts,
+            last_error,
+            if queued {
+                " (queued for automatic resend)"
+            } else {
+                ""
+            }
         ))
+        .into())
+    }
+
+    /// Publishes an already tracked event to `targets` (all relays if `None`), retrying with
+    /// the relay policy; `Ok(true)` if it took a retry, the last error if every attempt failed
+    async fn publish_with_retry(
+        &self,
+        client: &dyn DmTransport,
+        channel: &str,
+        targets: Option<&[String]>,
+        event: &Event,
+    ) -> Result<bool, String> {
+        let tracker = DeliveryTracker::global();
+        let result = retry::policy(retry::RELAY)
+            .run(
+                retry::RELAY,
+                |attempt| async move {
+                    let sent = match targets {
+                        Some(urls) => client.send_event_to(urls, event).await,
+                        None => client.send_event(event).await,
+                    };
+                    match sent {
+                        Ok(output)
+                            if tracker.record_output(&output) != Some(DeliveryState::Failed) =>
+                        {
+                            Ok(attempt > 1)
+                        }
+                        Ok(output) => Err(format!("rejected by all relays: {:?}", output.failed)),
+                        Err(e) => {
+                            tracker.record_error(&event.id);
+                            Err(e.to_string())
+                        }
+                    }
+                },
+                |_| true,
+            )
+            .await;
+
+        match &result {
+            Ok(_) => metrics::message_sent(channel),
+            Err(_) => metrics::send_failed(channel),
+        }
+        result
     }
 }
 
 #[tool(tool_box)]
 impl ServerHandler for Chat {
     fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some("This server provides tools for talking to a specific user over the Nostr protocol via encrypted DMs.\n\nABSOLUTELY MANDATORY FOR EVERY USER MESSAGE:\n\n1. IMMEDIATE PROGRESS RESPONSE: The INSTANT you receive a user message, you MUST send a progress update\n   Example: {\"tool\": \"progress\", \"arguments\": {\"message\": \"I'm working on your request...\"}}\n\n2. PERFORM OPERATIONS: Execute the requested tasks\n\n3. MANDATORY FINAL SEND: You MUST ALWAYS end with a 'send' tool call - NO EXCEPTIONS\n   Example: {\"tool\": \"send\", \"arguments\": {\"message\": \"Here are the results...\"}}\n\nCRITICAL: EVERY conversation turn MUST follow this pattern:\n   wait -> progress -> [operations] -> send\n\nUSER VISIBILITY RULES:\n- Users can ONLY see messages sent via 'send' and 'progress' tools\n- Users CANNOT see your thinking, reasoning, or stdout output\n- If you don't use 'send', the user sees NOTHING\n- If you don't use 'progress', users think you're not working\n\nFORBIDDEN BEHAVIORS:\n- Never end a turn without 'send'\n- Never start work without 'progress'\n- Never assume the user knows what you're doing\n- Never output to stdout/terminal\n\nCRITICAL JSON PARAMETER RULES:\n- Parameters MUST be a SINGLE, complete JSON object: {\"message\": \"text\"}\n- Use ONLY double quotes, never single quotes\n- ABSOLUTELY NO text, characters, or content after the closing brace }\n- NO comments, explanations, or additional text outside the JSON\n- Properly escape quotes and backslashes inside strings\n- Example of CORRECT format: {\"message\": \"Hello world\"}\n- Example of WRONG format: {\"message\": \"Hello world\"}\\nI'm working on this\n- Example of WRONG format: {\"message\": \"Hello world\"} // sending message\n\nTRAILING CHARACTERS ERROR: If you see \"trailing characters\" errors, you have text after the JSON.\n\nPARAMETER PARSING FAILURES WILL BREAK THE ENTIRE SYSTEM".to_string()),
+        ServerInfoBuilder::new()
+            .instructions("This server provides tools for talking to a specific user over the Nostr protocol via encrypted DMs.\n\nABSOLUTELY MANDATORY FOR EVERY USER MESSAGE:\n\n1. IMMEDIATE PROGRESS RESPONSE: The INSTANT you receive a user message, you MUST send a progress update\n   Example: {\"tool\": \"progress\", \"arguments\": {\"message\": \"I'm working on your request...\"}}\n\n2. PERFORM OPERATIONS: Execute the requested tasks\n\n3. MANDATORY FINAL SEND: You MUST ALWAYS end with a 'send' tool call - NO EXCEPTIONS\n   Example: {\"tool\": \"send\", \"arguments\": {\"message\": \"Here are the results...\"}}\n\nCRITICAL: EVERY conversation turn MUST follow this pattern:\n   wait -> progress -> [operations] -> send\n\nUSER VISIBILITY RULES:\n- Users can ONLY see messages sent via 'send' and 'progress' tools\n- Users CANNOT see your thinking, reasoning, or stdout output\n- If you don't use 'send', the user sees NOTHING\n- If you don't use 'progress', users think you're not working\n\nFORBIDDEN BEHAVIORS:\n- Never end a turn without 'send'\n- Never start work without 'progress'\n- Never assume the user knows what you're doing\n- Never output to stdout/terminal\n\nCRITICAL JSON PARAMETER RULES:\n- Parameters MUST be a SINGLE, complete JSON object: {\"message\": \"text\"}\n- Use ONLY double quotes, never single quotes\n- ABSOLUTELY NO text, characters, or content after the closing brace }\n- NO comments, explanations, or additional text outside the JSON\n- Properly escape quotes and backslashes inside strings\n- Example of CORRECT format: {\"message\": \"Hello world\"}\n- Example of WRONG format: {\"message\": \"Hello world\"}\\nI'm working on this\n- Example of WRONG format: {\"message\": \"Hello world\"} // sending message\n\nTRAILING CHARACTERS ERROR: If you see \"trailing characters\" errors, you have text after the JSON.\n\nPARAMETER PARSING FAILURES WILL BREAK THE ENTIRE SYSTEM")
+            .build()
+    }
+}
+
+/// What a `send` or `progress` identical to one sent `ago` returns instead of sending it again
+fn deduplicated(ago: Duration) -> Result<CallToolResult, RmcpError> {
+    log::info!(
+        "Skipping a message identical to one sent {}s ago",
+        ago.as_secs()
+    );
+    Ok(CallToolResult::success(vec![
+        Content::text(format!(
+            "Not sent again: the same message went out {}s ago",
+            ago.as_secs()
+        )),
+        Content::json(serde_json::json!({
+            "deduplicated": true,
+            "sent_secs_ago": ago.as_secs(),
+        }))?,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress_channels;
+    use crate::transport::fake::FakeTransport;
+    use tokio::time::sleep;
+
+    fn chat() -> (Chat, FakeTransport, Keys) {
+        let ours = Keys::generate();
+        let user = Keys::generate();
+        let transport = FakeTransport::new(ours.clone());
+        let chat = Chat::with_transport(
+            Arc::new(transport.clone()),
+            ProgressChannels::default(),
+            ours.public_key(),
+            user.public_key(),
+        )
+        .with_interrupt(Interrupt::new());
+        (chat, transport, user)
+    }
+
+    fn text(result: &CallToolResult) -> String {
+        result.content[0].as_text().unwrap().text.clone()
+    }
+
+    fn details(result: &CallToolResult) -> serde_json::Value {
+        serde_json::from_str(&result.content[1].as_text().unwrap().text).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_wait_returns_injected_message() {
+        let (chat, transport, user) = chat();
+        transport.inject(&Keys::generate(), "from a stranger");
+        transport.inject(&user, "hello agent");
+
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("hello agent\n\n"));
+    }
+
+    #[tokio::test]
+    async fn test_conversations_are_kept_apart_per_sender() {
+        let (chat, transport, user) = chat();
+        let bob = Keys::generate();
+        let chat = chat.with_other_senders(vec![bob.public_key()]);
+        transport.inject(&user, "first from the user.");
+        transport.inject(&bob, "first from bob.");
+        transport.inject(&bob, "second from bob.");
+
+        let from_bob = WaitRequest {
+            from: Some(bob.public_key().to_bech32().unwrap()),
+        };
+        let result = chat.wait(from_bob).await.unwrap();
+        assert!(text(&result).contains("first from bob."));
+        assert!(text(&result).contains("answer with `to`"));
+        let bobs = details(&result);
+        assert_eq!(bobs["sender"], bob.public_key().to_hex());
+        assert_eq!(
+            (bobs["sequence"].as_u64(), bobs["primary"].as_bool()),
+            (Some(1), Some(false))
+        );
+
+        // The user's message waited in their own queue
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("first from the user.\n\n"));
+        assert_eq!(details(&result)["sequence"], 1);
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert_eq!(details(&result)["sequence"], 2);
+
+        // Answering bob without saying so is refused rather than sent to the user
+        let bobs_message = EventId::from_hex(bobs["event_id"].as_str().unwrap()).unwrap();
+        assert!(chat.unaddressed_recipient(Some(bobs_message)).is_err());
+
+        chat.send(SendMessageRequest {
+            message: "hi bob".to_string(),
+            reply_to: None,
+            to: Some(bob.public_key().to_hex()),
+        })
+        .await
+        .unwrap();
+        assert_eq!(transport.sent().pop().unwrap().receiver, bob.public_key());
+
+        let stranger = WaitRequest {
+            from: Some(Keys::generate().public_key().to_hex()),
+        };
+        assert!(chat.wait(stranger).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_arbitrary_recipients_need_the_flag() {
+        let (chat, transport, _) = chat();
+        let colleague = Keys::generate().public_key();
+        let forward = || SendMessageRequest {
+            message: "Forwarding the build result".to_string(),
+            reply_to: None,
+            to: Some(format!("nostr:{}", colleague.to_bech32().unwrap())),
+        };
+
+        let refused = chat
+            .clone()
+            .with_arbitrary_recipients(false)
+            .send(forward())
+            .await
+            .unwrap_err();
+        assert!(refused.message.contains("ALLOW_ARBITRARY_RECIPIENTS"));
+        assert!(transport.sent().is_empty());
+
+        chat.with_arbitrary_recipients(true)
+            .send(forward())
+            .await
+            .unwrap();
+        assert_eq!(transport.sent().pop().unwrap().receiver, colleague);
+    }
+
+    #[tokio::test]
+    async fn test_commands_are_answered_without_the_model() {
+        let (chat, transport, user) = chat();
+        let palette = Palette::new(
+            "!".to_string(),
+            vec![palette::parse_mapping("docs=reply:See the README").unwrap()],
+        );
+        let chat = chat.with_palette(Some(palette));
+        transport.inject(&user, "!docs");
+        transport.inject(&user, "!deploy");
+        transport.inject(&user, "hello agent");
+
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("hello agent\n\n"));
+
+        let mut replies = Vec::new();
+        for _ in 0..50 {
+            replies = transport.sent();
+            if replies.len() == 2 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        let mut replies: Vec<String> = replies.into_iter().map(|sent| sent.content).collect();
+        replies.sort();
+        assert_eq!(
+            replies,
+            [
+                "See the README",
+                "Unknown command !deploy; !help lists the commands"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_reminds_of_the_context() {
+        let (chat, transport, user) = chat();
+        let chat = chat.with_context(Arc::new(ContextStore::in_memory()));
+        chat.set_context(SetContextRequest {
+            key: "current_repo".to_string(),
+            value: "nmcpparrot".to_string(),
+            ttl_secs: None,
+        })
+        .await
+        .unwrap();
+        transport.inject(&user, "how is it going?");
+
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert_eq!(result.content.len(), 3);
+        assert_eq!(
+            result.content[2].as_text().unwrap().text,
+            "Current context: current_repo=nmcpparrot"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_coalesces_fragments() {
+        let (chat, transport, user) = chat();
+        let chat = chat.with_coalesce(Some(Duration::from_millis(200)));
+        transport.inject(&user, "can you check");
+        transport.inject(&user, "the staging deploy");
+        transport.inject(&user, "and the logs.");
+        transport.inject(&user, "thanks");
+
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("can you check\nthe staging deploy\nand the logs.\n\n"));
+        assert_eq!(
+            details(&result)["fragment_ids"].as_array().unwrap().len(),
+            3
+        );
+
+        // The terminator ended the window, so the next message stands alone
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("thanks\n\n"));
+        assert!(details(&result).get("fragment_ids").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_messages_survive_a_reconnect() {
+        let (chat, transport, user) = chat();
+        let chat = chat.with_coalesce(Some(Duration::from_secs(2)));
+        chat.listen().await;
+
+        // A session drops while its wait is still coalescing the first fragment
+        let session = chat.clone();
+        transport.inject(&user, "can you check");
+        let dropped =
+            tokio::time::timeout(Duration::from_secs(1), session.wait(WaitRequest::default()))
+                .await;
+        assert!(dropped.is_err());
+        drop(session);
+
+        transport.inject(&user, "the staging deploy?");
+        sleep(Duration::from_secs(60)).await;
+
+        // The next session's first wait gets both, marked as not fresh
+        let reconnected = chat.clone();
+        let result = reconnected.wait(WaitRequest::default()).await.unwrap();
+        let body = text(&result);
+        assert!(body.starts_with("[Sent "));
+        assert!(body.contains("can you check\nthe staging deploy?\n\n"));
+        assert!(details(&result)["buffered_secs"].as_u64().unwrap() >= 60);
+
+        // A message taken right away is not flagged
+        transport.inject(&user, "thanks.");
+        let result = reconnected.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("thanks."));
+        assert!(details(&result).get("buffered_secs").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_is_not_a_wait_result() {
+        let ours = Keys::generate();
+        let user = Keys::generate();
+        let transport = FakeTransport::new(ours.clone());
+        let interrupt = Interrupt::new();
+        let chat = Chat::with_transport(
+            Arc::new(transport.clone()),
+            ProgressChannels::default(),
+            ours.public_key(),
+            user.public_key(),
+        )
+        .with_interrupt(interrupt.clone());
+        let mut signal = interrupt.subscribe();
+
+        transport.inject(&user, "first");
+        assert!(text(&chat.wait(WaitRequest::default()).await.unwrap()).starts_with("first\n\n"));
+
+        // Arrives while the agent is busy, before the user's next real message
+        transport.inject(&user, "/stop");
+        signal.changed().await.unwrap();
+        assert!(interrupt.take());
+
+        transport.inject(&user, "/stop");
+        transport.inject(&user, "next task");
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("next task\n\n"));
+        // The one that came in during `wait` had nothing to stop
+        assert!(!interrupt.take());
+    }
+
+    #[tokio::test]
+    async fn test_wait_unwraps_envelope() {
+        let (chat, transport, user) = chat();
+        transport.inject(
+            &user,
+            r#"{"v":1,"type":"command","body":"deploy","meta":{"env":"prod"}}"#,
+        );
+
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("deploy\n\n"));
+        let details = details(&result);
+        assert_eq!(details["type"], "command");
+        assert_eq!(details["meta"], serde_json::json!({"env": "prod"}));
+    }
+
+    #[tokio::test]
+    async fn test_send_reply_to_tags_the_rumor() {
+        let (chat, transport, user) = chat();
+        transport.inject(&user, "what's the status?");
+        let waited = chat.wait(WaitRequest::default()).await.unwrap();
+        let parent = details(&waited)["event_id"].as_str().unwrap().to_string();
+
+        chat.send(SendMessageRequest {
+            message: "all green".to_string(),
+            reply_to: Some(parent.clone()),
+            to: None,
+        })
+        .await
+        .unwrap();
+
+        let wrap = transport.published().pop().unwrap();
+        let gift = UnwrappedGift::from_gift_wrap(&user, &wrap).await.unwrap();
+        assert_eq!(gift.rumor.content, "all green");
+        assert_eq!(
+            gift.rumor.tags.event_ids().collect::<Vec<_>>(),
+            vec![&EventId::from_hex(&parent).unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_rejects_invalid_reply_to() {
+        let (chat, transport, _) = chat();
+        let err = chat
+            .send(SendMessageRequest {
+                message: "hi".to_string(),
+                reply_to: Some("not-an-id".to_string()),
+                to: None,
+            })
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("Invalid event id"));
+        assert!(transport.published().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wait_drops_forged_rumor() {
+        let (chat, transport, user) = chat();
+        let attacker = Keys::generate();
+        transport.inject_gift(UnwrappedGift {
+            sender: attacker.public_key(),
+            rumor: EventBuilder::private_msg_rumor(Keys::generate().public_key(), "forged")
+                .build(user.public_key()),
+        });
+        transport.inject(&user, "genuine");
+
+        let result = chat.wait(WaitRequest::default()).await.unwrap();
+        assert!(text(&result).starts_with("genuine\n\n"));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_send_is_truncated_not_lost() {
+        let (chat, transport, user) = chat();
+        let output = "line of goose output\n".repeat(15_000);
+        chat.send(SendMessageRequest {
+            message: output.clone(),
+            reply_to: None,
+            to: None,
+        })
+        .await
+        .unwrap();
+
+        let wrap = transport.published().pop().unwrap();
+        assert!(wrap.as_json().len() <= message_size::limit_for(None));
+        let gift = UnwrappedGift::from_gift_wrap(&user, &wrap).await.unwrap();
+        assert!(gift.rumor.content.starts_with("line of goose output\n"));
+        assert!(gift
+            .rumor
+            .content
+            .ends_with("KB omitted — ask for the full output)"));
+        assert!(gift.rumor.content.len() < output.len());
+    }
+
+    #[tokio::test]
+    async fn test_identical_send_is_deduplicated() {
+        let (chat, transport, _user) = chat();
+        let send = || {
+            chat.send(SendMessageRequest {
+                message: "Deployed to staging.".to_string(),
+                reply_to: None,
+                to: None,
+            })
+        };
+        send().await.unwrap();
+        let repeated = send().await.unwrap();
+
+        assert_eq!(transport.sent().len(), 1);
+        let details = repeated.content[1].as_text().unwrap();
+        assert!(details.text.contains("\"deduplicated\":true"));
+    }
+
+    #[tokio::test]
+    async fn test_send_records_message() {
+        let (chat, transport, user) = chat();
+        chat.send(SendMessageRequest {
+            message: "done".to_string(),
+            reply_to: None,
+            to: None,
+        })
+        .await
+        .unwrap();
+
+        let sent = transport.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].receiver, user.public_key());
+        assert_eq!(sent[0].content, "done");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_failure_is_a_tool_error() {
+        let (chat, transport, _) = chat();
+        transport.fail_sends(true);
+
+        let error = chat
+            .send(SendMessageRequest {
+                message: "lost".to_string(),
+                reply_to: None,
+                to: None,
+            })
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("after 3 attempts"));
+        assert_eq!(
+            NparrotError::code_of_error(&error).as_deref(),
+            Some("relay_unavailable")
+        );
+        assert!(transport.sent().is_empty());
+
+        let error = chat
+            .progress(ProgressMessageRequest {
+                message: "working".to_string(),
+                expire_after_secs: None,
+                channel: None,
+            })
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("Progress identity not configured"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_nudges_a_stalled_turn() {
+        let ours = Keys::generate();
+        let user = Keys::generate();
+        let main = FakeTransport::new(ours.clone());
+        let status = FakeTransport::new(Keys::generate());
+        let mut progress_clients: ProgressChannels<SharedTransport> = ProgressChannels::default();
+        progress_clients.insert(progress_channels::STATUS, Arc::new(status.clone()));
+        let chat = Chat::with_transport(
+            Arc::new(main.clone()),
+            progress_clients,
+            ours.public_key(),
+            user.public_key(),
+        )
+        .with_interrupt(Interrupt::new())
+        .with_watchdog(Some(Duration::from_secs(60)));
+
+        main.inject(&user, "first");
+        chat.tool_called();
+        chat.wait(WaitRequest::default()).await.unwrap();
+        sleep(Duration::from_secs(61)).await;
+        assert_eq!(status.sent().len(), 1);
+        assert!(status.sent()[0]
+            .content
+            .contains(&catalog::text(Key::StallNotice, &[])));
+        assert!(main.sent().is_empty());
+        sleep(Duration::from_secs(60)).await;
+        assert_eq!(main.sent().len(), 1);
+        assert!(main.sent()[0]
+            .content
+            .contains(&catalog::text(Key::StallApology, &[])));
+
+        // Any tool call ends the next turn's watch
+        main.inject(&user, "second");
+        chat.tool_called();
+        chat.wait(WaitRequest::default()).await.unwrap();
+        chat.tool_called();
+        sleep(Duration::from_secs(300)).await;
+        assert_eq!(status.sent().len(), 1);
+        assert_eq!(main.sent().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_progress_routes_by_channel() {
+        let ours = Keys::generate();
+        let user = Keys::generate();
+        let status = FakeTransport::new(Keys::generate());
+        let debug = FakeTransport::new(Keys::generate());
+        let mut progress_clients: ProgressChannels<SharedTransport> = ProgressChannels::default();
+        progress_clients.insert(progress_channels::STATUS, Arc::new(status.clone()));
+        progress_clients.insert(progress_channels::DEBUG, Arc::new(debug.clone()));
+        let chat = Chat::with_transport(
+            Arc::new(FakeTransport::new(ours.clone())),
+            progress_clients,
+            ours.public_key(),
+            user.public_key(),
+        );
+
+        for (message, channel) in [
+            ("milestone", None),
+            ("detail", Some(progress_channels::DEBUG)),
+            ("misrouted", Some("nonexistent")),
+        ] {
+            chat.progress(ProgressMessageRequest {
+                message: message.to_string(),
+                expire_after_secs: None,
+                channel: channel.map(str::to_string),
+            })
+            .await
+            .unwrap();
+        }
+
+        let contents = |transport: &FakeTransport| -> Vec<String> {
+            transport.sent().into_iter().map(|m| m.content).collect()
+        };
+        // Unknown channels fall back to the default instead of being dropped
+        assert_eq!(contents(&status), ["milestone", "misrouted"]);
+        assert_eq!(contents(&debug), ["detail"]);
+    }
+
+    #[tokio::test]
+    async fn test_progress_publishes_to_bulk_relays_only() {
+        relays::set_routing(
+            relays::RelayRouting::new(
+                relays::parse_relay_specs("wss://paid.example#primary,wss://free.example#bulk"),
+                None,
+                relays::RelayRoutes::default(),
+            )
+            .unwrap(),
+        );
+        let ours = Keys::generate();
+        let main = FakeTransport::new(ours.clone());
+        let status = FakeTransport::new(Keys::generate());
+        let mut progress_clients: ProgressChannels<SharedTransport> = ProgressChannels::default();
+        progress_clients.insert(progress_channels::STATUS, Arc::new(status.clone()));
+        let chat = Chat::with_transport(
+            Arc::new(main.clone()),
+            progress_clients,
+            ours.public_key(),
+            Keys::generate().public_key(),
+        );
+
+        chat.progress(ProgressMessageRequest {
+            message: "working".to_string(),
+            expire_after_secs: None,
+            channel: None,
+        })
+        .await
+        .unwrap();
+        chat.send(SendMessageRequest {
+            message: "done".to_string(),
+            reply_to: None,
+            to: None,
+        })
+        .await
+        .unwrap();
+
+        let progress = status.published().pop().unwrap();
+        assert_eq!(
+            status.targets(&progress.id),
+            Some(vec!["wss://free.example".to_string()])
+        );
+        // User-facing messages go to every relay
+        let reply = main.published().pop().unwrap();
+        assert_eq!(main.targets(&reply.id), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_batch_keeps_order() {
+        let (chat, transport, user) = chat();
+        let result = chat
+            .send_batch(SendBatchRequest {
+                messages: vec![
+                    "summary".to_string(),
+                    "code".to_string(),
+                    "tests".to_string(),
+                ],
+            })
+            .await
+            .unwrap();
+        assert_eq!(text(&result), "Sent 3 of 3 messages");
+        let statuses = details(&result);
+        assert_eq!(statuses[2]["status"], "sent");
+
+        let mut rumors = Vec::new();
+        for wrap in transport.published() {
+            let gift = UnwrappedGift::from_gift_wrap(&user, &wrap).await.unwrap();
+            rumors.push(gift.rumor);
         }
+        rumors.sort_by_key(|rumor| rumor.created_at);
+        let contents: Vec<&str> = rumors.iter().map(|rumor| rumor.content.as_str()).collect();
+        assert_eq!(contents, ["summary", "code", "tests"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_batch_reports_failures() {
+        let (chat, transport, _) = chat();
+        transport.fail_sends(true);
+        let result = chat
+            .send_batch(SendBatchRequest {
+                messages: vec!["one".to_string(), "two".to_string()],
+            })
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            NparrotError::code_of(&result).as_deref(),
+            Some("relay_unavailable")
+        );
+        let statuses: serde_json::Value =
+            serde_json::from_str(&result.content[2].as_text().unwrap().text).unwrap();
+        assert_eq!(statuses[1]["status"], "failed");
+
+        let error = chat
+            .send_batch(SendBatchRequest { messages: vec![] })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            NparrotError::code_of_error(&error).as_deref(),
+            Some("invalid_params")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_group_conversation() {
+        let (chat, transport, user) = chat();
+        let group = group::GroupTarget::parse("devs@groups.example.com").unwrap();
+        let chat = chat.with_conversation(Conversation::Group(group.clone()));
+
+        chat.send(SendMessageRequest {
+            message: "deployed".to_string(),
+            reply_to: None,
+            to: None,
+        })
+        .await
+        .unwrap();
+        let posted = transport.published().pop().unwrap();
+        assert_eq!(posted.kind, group::CHAT_MESSAGE);
+        assert_eq!(posted.content, "deployed");
+        assert_eq!(
+            transport.targets(&posted.id),
+            Some(vec![group.relay.clone()])
+        );
+
+        let waiting = tokio::spawn({
+            let chat = chat.clone();
+            async move { chat.wait(WaitRequest::default()).await }
+        });
+        // Let `wait` subscribe before the group message arrives
+        sleep(Duration::from_millis(50)).await;
+        let said = |keys: &Keys, content: &str| {
+            EventBuilder::new(group::CHAT_MESSAGE, content)
+                .tag(Tag::custom(TagKind::h(), ["devs"]))
+                .sign_with_keys(keys)
+                .unwrap()
+        };
+        transport.inject_event(said(&Keys::generate(), "not a member"));
+        transport.inject_event(said(&user, "thanks"));
+        let result = waiting.await.unwrap().unwrap();
+        assert!(text(&result).starts_with("thanks\n\n"));
+    }
+
+    #[tokio::test]
+    async fn test_summarize_conversation_transcript() {
+        let (chat, transport, user) = chat();
+        transport.inject(&user, "which port should it use?");
+        transport.inject(&Keys::generate(), "not the user");
+        chat.send(SendMessageRequest {
+            message: "8080, as agreed".to_string(),
+            reply_to: None,
+            to: None,
+        })
+        .await
+        .unwrap();
+        let request = |summary: Option<&str>| SummarizeConversationRequest {
+            hours: None,
+            include_progress: None,
+            max_bytes: None,
+            summary: summary.map(str::to_string),
+        };
+        let result = chat.summarize_conversation(request(None)).await.unwrap();
+        let transcript = text(&result);
+        assert!(transcript.contains("← user: which port should it use?"));
+        assert!(transcript.contains("→ you: 8080, as agreed"));
+        assert!(transcript.find("← user").unwrap() < transcript.find("→ you").unwrap());
+        assert!(!transcript.contains("not the user"));
+
+        // Without a memory store the summary is refused rather than lost
+        let error = chat
+            .summarize_conversation(request(Some("Port 8080")))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            NparrotError::code_of_error(&error).as_deref(),
+            Some("backend_missing")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_still_sends_the_message() {
+        let (chat, transport, _) = chat();
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("build.log"), "error: linker failed").unwrap();
+        let chat = chat.with_uploads(Some(Uploads {
+            // Nothing listens there
+            server: "http://127.0.0.1:1".to_string(),
+            protocol: media::MediaProtocol::Blossom,
+            max_size: 1024,
+            roots: vec![root.path().to_path_buf()],
+        }));
+
+        let result = chat
+            .upload_and_send_file(UploadFileRequest {
+                path: "build.log".to_string(),
+                message: Some("Here is the build log".to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let sent = transport.sent().pop().unwrap();
+        assert!(sent
+            .content
+            .starts_with("Here is the build log\n\n⚠️ Could not attach build.log"));
+
+        let outside = chat
+            .upload_and_send_file(UploadFileRequest {
+                path: "/etc/hostname".to_string(),
+                message: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            NparrotError::code_of_error(&outside).as_deref(),
+            Some("invalid_params")
+        );
+        assert_eq!(transport.sent().len(), 1);
     }
 }
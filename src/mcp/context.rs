@@ -0,0 +1,268 @@
+//! Short-lived key/value state for the agent (`set_context` / `get_context` / `clear_context`)
+//!
+//! Meant for things like "currently working on repo X" that only matter for the session. It is
+//! separate from the Nostr-backed memory: nothing is published, values are small, and keys can
+//! expire. Kept in memory, and in `context.json` in the data dir with `--persist-context`.
+
+use crate::error::NparrotError;
+use chrono::{DateTime, TimeDelta, Utc};
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const MAX_KEYS: usize = 50;
+pub const MAX_KEY_CHARS: usize = 64;
+pub const MAX_VALUE_BYTES: usize = 2048;
+/// Characters of each value shown in the reminder `wait` adds
+const REMINDER_VALUE_CHARS: usize = 60;
+
+pub const FILE: &str = "context.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextValue {
+    pub value: String,
+    pub updated_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ContextValue {
+    fn expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SetContextRequest {
+    #[schemars(description = "Name of the value, e.g. \"current_repo\" (up to 64 characters)")]
+    pub key: String,
+    #[schemars(description = "The value, up to 2048 bytes")]
+    pub value: String,
+    #[serde(default)]
+    #[schemars(
+        description = "Forget the value after this many seconds (kept for the session if omitted)"
+    )]
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GetContextRequest {
+    #[serde(default)]
+    #[schemars(description = "The key to read; every key if omitted")]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ClearContextRequest {
+    #[serde(default)]
+    #[schemars(description = "The key to remove; every key if omitted")]
+    pub key: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct ContextStore {
+    /// Where the values are saved, `None` to keep them in memory only
+    path: Option<PathBuf>,
+    values: Mutex<BTreeMap<String, ContextValue>>,
+}
+
+impl ContextStore {
+    /// A store that is lost when the process exits
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// A store saved to `path`, starting with what it already holds
+    pub fn persistent(path: PathBuf) -> Self {
+        let values = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable context {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path: Some(path),
+            values: Mutex::new(values),
+        }
+    }
+
+    pub fn set(
+        &self,
+        key: &str,
+        value: String,
+        ttl_secs: Option<u64>,
+    ) -> Result<ContextValue, NparrotError> {
+        let key = key.trim();
+        if key.is_empty() || key.chars().count() > MAX_KEY_CHARS {
+            return Err(NparrotError::invalid_params(
+                "key",
+                format!("The key must have 1 to {} characters", MAX_KEY_CHARS),
+            ));
+        }
+        if value.len() > MAX_VALUE_BYTES {
+            return Err(NparrotError::invalid_params(
+                "value",
+                format!(
+                    "The value has {} bytes, over the {} byte limit; store longer content in memory",
+                    value.len(),
+                    MAX_VALUE_BYTES
+                ),
+            ));
+        }
+        let now = Utc::now();
+        let expires_at = match ttl_secs {
+            Some(0) => {
+                return Err(NparrotError::invalid_params(
+                    "ttl_secs",
+                    "The TTL must be at least one second",
+                ))
+            }
+            Some(secs) => Some(
+                now + TimeDelta::try_seconds(secs as i64).ok_or_else(|| {
+                    NparrotError::invalid_params("ttl_secs", "The TTL is too long")
+                })?,
+            ),
+            None => None,
+        };
+
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.retain(|_, value| !value.expired(now));
+        if !values.contains_key(key) && values.len() >= MAX_KEYS {
+            return Err(NparrotError::invalid_params(
+                "key",
+                format!(
+                    "The context already holds {} keys; clear some first",
+                    MAX_KEYS
+                ),
+            ));
+        }
+        let entry = ContextValue {
+            value,
+            updated_at: now,
+            expires_at,
+        };
+        values.insert(key.to_string(), entry.clone());
+        self.save(&values);
+        Ok(entry)
+    }
+
+    /// The live values, or only `key`'s
+    pub fn get(&self, key: Option<&str>) -> BTreeMap<String, ContextValue> {
+        let now = Utc::now();
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values
+            .iter()
+            .filter(|(name, value)| {
+                key.is_none_or(|key| key.trim() == *name) && !value.expired(now)
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Removes `key`, or every key; returns how many live values were removed
+    pub fn clear(&self, key: Option<&str>) -> usize {
+        let now = Utc::now();
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        let before = values.values().filter(|value| !value.expired(now)).count();
+        match key {
+            Some(key) => {
+                values.remove(key.trim());
+            }
+            None => values.clear(),
+        }
+        values.retain(|_, value| !value.expired(now));
+        let removed = before - values.len();
+        self.save(&values);
+        removed
+    }
+
+    /// One line listing the keys with the start of their values, `None` when empty
+    pub fn reminder(&self) -> Option<String> {
+        let values = self.get(None);
+        if values.is_empty() {
+            return None;
+        }
+        let items: Vec<String> = values
+            .iter()
+            .map(|(key, value)| {
+                let mut shown: String = value.value.chars().take(REMINDER_VALUE_CHARS).collect();
+                if value.value.chars().count() > REMINDER_VALUE_CHARS {
+                    shown.push('…');
+                }
+                format!("{}={}", key, shown)
+            })
+            .collect();
+        Some(format!("Current context: {}", items.join("; ")))
+    }
+
+    fn save(&self, values: &BTreeMap<String, ContextValue>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(values)?)?;
+            std::fs::rename(tmp, path)
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to save context: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_limits_ttl_and_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let store = ContextStore::persistent(path.clone());
+
+        store
+            .set("current_repo", "nmcpparrot".to_string(), None)
+            .unwrap();
+        store
+            .set("scratch", "gone soon".to_string(), Some(60))
+            .unwrap();
+        assert_eq!(store.get(None).len(), 2);
+        assert_eq!(
+            store.get(Some("current_repo"))["current_repo"].value,
+            "nmcpparrot"
+        );
+        assert_eq!(
+            store.reminder().unwrap(),
+            "Current context: current_repo=nmcpparrot; scratch=gone soon"
+        );
+
+        let err = store
+            .set("big", "x".repeat(MAX_VALUE_BYTES + 1), None)
+            .unwrap_err();
+        assert!(matches!(err, NparrotError::InvalidParams { ref field, .. } if field == "value"));
+        assert!(store.set(" ", "x".to_string(), None).is_err());
+        assert!(store.set("ttl", "x".to_string(), Some(0)).is_err());
+
+        // Expired values are neither returned nor counted
+        store
+            .values
+            .lock()
+            .unwrap()
+            .get_mut("scratch")
+            .unwrap()
+            .expires_at = Some(Utc::now() - TimeDelta::seconds(1));
+        assert_eq!(store.get(None).len(), 1);
+        assert_eq!(store.clear(Some("scratch")), 0);
+
+        let reloaded = ContextStore::persistent(path);
+        assert_eq!(reloaded.get(None).len(), 1);
+        assert_eq!(reloaded.clear(None), 1);
+        assert!(reloaded.reminder().is_none());
+    }
+}
@@ -0,0 +1,190 @@
+//! Drops a `send` or `progress` identical to one that just went out (`NPARROT_DEDUP_WINDOW`)
+//!
+//! A retried MCP call, or a model calling `send` twice with the same text, used to give the user
+//! the same message twice within seconds. `Chat` hashes each message per recipient (per channel
+//! for progress); the same hash again within the window is answered as sent, marked
+//! `deduplicated`, without publishing anything. Progress has a shorter window of its own
+//! (`NPARROT_PROGRESS_DEDUP_WINDOW`). Once the window has passed the same text goes out again, so
+//! a repeated short confirmation later on still arrives. A window of 0 turns suppression off.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(30);
+pub const DEFAULT_PROGRESS_WINDOW: Duration = Duration::from_secs(10);
+
+/// Which window a message falls under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Traffic {
+    Send,
+    Progress,
+}
+
+lazy_static::lazy_static! {
+    static ref SUPPRESSOR: Suppressor = Suppressor::default();
+}
+
+/// A message: its traffic, recipient and content hash
+type Key = (Traffic, String, u64);
+
+#[derive(Debug)]
+pub struct Suppressor {
+    windows: RwLock<(Duration, Duration)>,
+    /// When each (traffic, recipient, content hash) was last sent, and the window it counts for
+    recent: Mutex<HashMap<Key, (Instant, Duration)>>,
+}
+
+impl Default for Suppressor {
+    fn default() -> Self {
+        Self {
+            windows: RwLock::new((DEFAULT_WINDOW, DEFAULT_PROGRESS_WINDOW)),
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+fn hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Suppressor {
+    /// The suppressor shared by every `Chat`
+    pub fn global() -> &'static Suppressor {
+        &SUPPRESSOR
+    }
+
+    pub fn set_windows(&self, send: Duration, progress: Duration) {
+        if let Ok(mut guard) = self.windows.write() {
+            *guard = (send, progress);
+        }
+    }
+
+    fn window(&self, traffic: Traffic) -> Duration {
+        let (send, progress) = self.windows.read().map(|guard| *guard).unwrap_or_default();
+        match traffic {
+            Traffic::Send => send,
+            Traffic::Progress => progress,
+        }
+    }
+
+    /// Claims `content` for `recipient`: `None` if it may go out, or how long ago the identical
+    /// message went out if it is a duplicate
+    pub fn claim(&self, traffic: Traffic, recipient: &str, content: &str) -> Option<Duration> {
+        self.claim_at(traffic, recipient, content, Instant::now())
+    }
+
+    fn claim_at(
+        &self,
+        traffic: Traffic,
+        recipient: &str,
+        content: &str,
+        now: Instant,
+    ) -> Option<Duration> {
+        let window = self.window(traffic);
+        if window.is_zero() {
+            return None;
+        }
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.retain(|_, (sent_at, window)| now.saturating_duration_since(*sent_at) < *window);
+        let key = (traffic, recipient.to_string(), hash(content));
+        if let Some((sent_at, _)) = recent.get(&key) {
+            return Some(now.saturating_duration_since(*sent_at));
+        }
+        recent.insert(key, (now, window));
+        None
+    }
+
+    /// Forgets the claim of a message that couldn't be sent, so a retry of it goes out
+    pub fn release(&self, traffic: Traffic, recipient: &str, content: &str) {
+        if let Ok(mut recent) = self.recent.lock() {
+            recent.remove(&(traffic, recipient.to_string(), hash(content)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_within_the_window_are_suppressed() {
+        let suppressor = Suppressor::default();
+        let start = Instant::now();
+        let at = |secs: u64, millis: u64| {
+            start + Duration::from_secs(secs) + Duration::from_millis(millis)
+        };
+        assert_eq!(
+            suppressor.claim_at(Traffic::Send, "alice", "Done.", start),
+            None
+        );
+        assert_eq!(
+            suppressor.claim_at(Traffic::Send, "alice", "Done.", at(29, 999)),
+            Some(Duration::from_millis(29_999))
+        );
+        // Other recipients and other text are unaffected
+        assert_eq!(
+            suppressor.claim_at(Traffic::Send, "bob", "Done.", at(1, 0)),
+            None
+        );
+        assert_eq!(
+            suppressor.claim_at(Traffic::Send, "alice", "Done!", at(1, 0)),
+            None
+        );
+        // The same confirmation goes out again once the window has passed
+        assert_eq!(
+            suppressor.claim_at(Traffic::Send, "alice", "Done.", at(30, 0)),
+            None
+        );
+        assert!(suppressor
+            .claim_at(Traffic::Send, "alice", "Done.", at(31, 0))
+            .is_some());
+    }
+
+    #[test]
+    fn test_progress_has_its_own_shorter_window() {
+        let suppressor = Suppressor::default();
+        let start = Instant::now();
+        assert_eq!(
+            suppressor.claim_at(Traffic::Progress, "status", "Building", start),
+            None
+        );
+        assert!(suppressor
+            .claim_at(
+                Traffic::Progress,
+                "status",
+                "Building",
+                start + Duration::from_secs(9)
+            )
+            .is_some());
+        assert_eq!(
+            suppressor.claim_at(
+                Traffic::Progress,
+                "status",
+                "Building",
+                start + Duration::from_secs(19)
+            ),
+            None
+        );
+        // A send of the same text is a different message
+        assert_eq!(
+            suppressor.claim_at(Traffic::Send, "status", "Building", start),
+            None
+        );
+    }
+
+    #[test]
+    fn test_released_and_disabled_claims() {
+        let suppressor = Suppressor::default();
+        assert_eq!(suppressor.claim(Traffic::Send, "alice", "retry me"), None);
+        suppressor.release(Traffic::Send, "alice", "retry me");
+        assert_eq!(suppressor.claim(Traffic::Send, "alice", "retry me"), None);
+
+        suppressor.set_windows(Duration::ZERO, Duration::ZERO);
+        assert_eq!(suppressor.claim(Traffic::Send, "alice", "retry me"), None);
+    }
+}
@@ -1,28 +1,129 @@
+use super::ics::{self, ImportedEvent};
+use super::tags::{PreviousTags, Retag};
 use super::types::*;
+use crate::at_rest::Vault;
+use chrono::{DateTime, Utc};
 use serde_json;
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{BTreeSet, HashMap};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// Assumed length of an event that only has a start (or only an end) time, in seconds
+static DEFAULT_DURATION_SECS: AtomicU64 = AtomicU64::new(3600);
+
+/// Sets the duration used for overlap checks on events without both times (from `--event-duration`)
+pub fn set_default_duration(duration: chrono::Duration) {
+    DEFAULT_DURATION_SECS.store(duration.num_seconds().max(0) as u64, Ordering::Relaxed);
+}
+
+fn default_duration() -> chrono::Duration {
+    chrono::Duration::seconds(DEFAULT_DURATION_SECS.load(Ordering::Relaxed) as i64)
+}
+
+/// The window an event occupies, if it has any time at all
+fn window(event: &Event) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    match (event.start_time, event.end_time) {
+        (Some(start), Some(end)) => Some((start, end.max(start))),
+        (Some(start), None) => Some((start, start + default_duration())),
+        (None, Some(end)) => Some((end - default_duration(), end)),
+        (None, None) => None,
+    }
+}
+
+/// Cancelled events are tagged "cancelled" or have `status: cancelled` in their metadata
+fn is_cancelled(event: &Event) -> bool {
+    let cancelled = |value: &str| {
+        value.eq_ignore_ascii_case("cancelled") || value.eq_ignore_ascii_case("canceled")
+    };
+    event.tags.iter().any(|tag| cancelled(tag))
+        || event
+            .metadata
+            .get("status")
+            .is_some_and(|status| cancelled(status))
+}
+
+/// All events by id, plus an index by start time for overlap queries
+#[derive(Debug, Default)]
+struct EventStore {
+    by_id: HashMap<String, Event>,
+    by_start: BTreeSet<(DateTime<Utc>, String)>,
+    /// The longest explicit start-to-end span, bounding how far back an overlap can start
+    longest: chrono::Duration,
+}
+
+impl EventStore {
+    fn new(by_id: HashMap<String, Event>) -> Self {
+        let mut store = Self::default();
+        for (_, event) in by_id {
+            store.insert(event);
+        }
+        store
+    }
+
+    fn insert(&mut self, event: Event) {
+        self.remove(&event.id);
+        if let Some((start, end)) = window(&event) {
+            self.by_start.insert((start, event.id.clone()));
+            if event.start_time.is_some() && event.end_time.is_some() {
+                self.longest = self.longest.max(end - start);
+            }
+        }
+        self.by_id.insert(event.id.clone(), event);
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Event> {
+        let event = self.by_id.remove(id)?;
+        self.unindex(&event);
+        Some(event)
+    }
+
+    fn unindex(&mut self, event: &Event) {
+        if let Some((start, _)) = window(event) {
+            self.by_start.remove(&(start, event.id.clone()));
+        }
+    }
+
+    /// Non-cancelled events whose window intersects `[start, end)`, by start time
+    fn overlapping(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<Event> {
+        let earliest = start - self.longest.max(default_duration());
+        self.by_start
+            .range((earliest, String::new())..)
+            .take_while(|(event_start, _)| *event_start < end)
+            .filter_map(|(_, id)| self.by_id.get(id))
+            .filter(|event| !is_cancelled(event))
+            .filter(|event| window(event).is_some_and(|(_, event_end)| event_end > start))
+            .cloned()
+            .collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct EventsManager {
-    events: RwLock<HashMap<String, Event>>,
+    events: RwLock<EventStore>,
     storage_path: String,
+    /// Why the events file could not be loaded; saving is refused so it is not overwritten
+    unreadable: Option<String>,
 }
 
 impl EventsManager {
     pub fn new(storage_path: String) -> Self {
         let mut manager = Self {
-            events: RwLock::new(HashMap::new()),
+            events: RwLock::new(EventStore::default()),
             storage_path,
+            unreadable: None,
         };
-        let _ = manager.load_from_disk();
+        if let Err(e) = manager.load_from_disk() {
+            log::error!("{}", e);
+            manager.unreadable = Some(e);
+        }
         manager
     }
 
-    pub async fn add_event(&self, request: AddEventRequest) -> Result<Event, String> {
+    /// Adds an event, returning it with the existing events it overlaps (unless
+    /// `allow_conflict` is set)
+    pub async fn add_event(&self, request: AddEventRequest) -> Result<(Event, Vec<Event>), String> {
         let now = chrono::Utc::now();
 
         let start_time = if let Some(start_str) = request.start_time {
@@ -57,18 +158,26 @@ impl EventsManager {
             metadata: request.metadata.unwrap_or_default(),
         };
 
-        {
+        let conflicts = {
             let mut events = self.events.write().await;
-            events.insert(event.id.clone(), event.clone());
-        }
+            let conflicts = match window(&event) {
+                Some((start, end)) if !request.allow_conflict.unwrap_or(false) => {
+                    events.overlapping(start, end)
+                }
+                _ => Vec::new(),
+            };
+            events.insert(event.clone());
+            conflicts
+        };
 
         self.save_to_disk().await?;
-        Ok(event)
+        Ok((event, conflicts))
     }
 
     pub async fn list_events(&self, request: ListEventsRequest) -> Result<Vec<Event>, String> {
         let events = self.events.read().await;
         let mut filtered_events: Vec<Event> = events
+            .by_id
             .values()
             .filter(|event| {
                 let type_match = if let Some(event_type) = &request.event_type {
@@ -90,14 +199,14 @@ impl EventsManager {
 
         let sort_order = request.sort.as_deref().unwrap_or("newest");
         match sort_order {
-            "oldest" => filtered_events.sort_by(|a, b| a.created_at.cmp(&b.created_at)),
+            "oldest" => filtered_events.sort_by_key(|e| e.created_at),
             "start_time" => filtered_events.sort_by(|a, b| match (a.start_time, b.start_time) {
                 (Some(a_time), Some(b_time)) => a_time.cmp(&b_time),
                 (Some(_), None) => std::cmp::Ordering::Less,
                 (None, Some(_)) => std::cmp::Ordering::Greater,
                 (None, None) => a.created_at.cmp(&b.created_at),
             }),
-            _ => filtered_events.sort_by(|a, b| b.created_at.cmp(&a.created_at)),
+            _ => filtered_events.sort_by_key(|e| std::cmp::Reverse(e.created_at)),
         }
 
         if let Some(limit) = request.limit {
@@ -112,6 +221,7 @@ impl EventsManager {
         let query_lower = request.query.to_lowercase();
 
         let mut matching_events: Vec<Event> = events
+            .by_id
             .values()
             .filter(|event| {
                 let title_match = event.title.to_lowercase().contains(&query_lower);
@@ -140,7 +250,7 @@ impl EventsManager {
             .cloned()
             .collect();
 
-        matching_events.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        matching_events.sort_by_key(|e| std::cmp::Reverse(e.created_at));
 
         if let Some(limit) = request.limit {
             matching_events.truncate(limit as usize);
@@ -149,50 +259,325 @@ impl EventsManager {
         Ok(matching_events)
     }
 
-    pub async fn delete_event(&self, request: DeleteEventRequest) -> Result<bool, String> {
+    /// Deletes event `id`, or with `dry_run` only looks it up; returns the event either way,
+    /// `None` if there is none
+    pub async fn delete_event(&self, id: &str, dry_run: bool) -> Result<Option<Event>, String> {
         let mut events = self.events.write().await;
-        let existed = events.remove(&request.id).is_some();
+        let Some(event) = events.by_id.get(id).cloned() else {
+            return Ok(None);
+        };
+        if dry_run {
+            return Ok(Some(event));
+        }
+        events.remove(id);
         drop(events);
 
-        if existed {
+        self.save_to_disk().await?;
+        Ok(Some(event))
+    }
+
+    /// Non-cancelled events whose window intersects `[from, to)`, with that window, by start
+    pub async fn in_window(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Vec<(Event, DateTime<Utc>, DateTime<Utc>)> {
+        let events = self.events.read().await;
+        events
+            .by_start
+            .iter()
+            .take_while(|(start, _)| to.is_none_or(|to| *start < to))
+            .filter_map(|(_, id)| events.by_id.get(id))
+            .filter(|event| !is_cancelled(event))
+            .filter_map(|event| window(event).map(|(start, end)| (event.clone(), start, end)))
+            .filter(|(_, start, end)| from.is_none_or(|from| *end > from || *start >= from))
+            .collect()
+    }
+
+    /// Adds imported calendar entries, updating the events earlier imports created from the
+    /// same UID; returns how many were added and how many updated
+    pub async fn import_ics(&self, imported: Vec<ImportedEvent>) -> Result<(usize, usize), String> {
+        let now = chrono::Utc::now();
+        let (mut created, mut updated) = (0, 0);
+        {
+            let mut events = self.events.write().await;
+            let by_uid: HashMap<String, String> = events
+                .by_id
+                .values()
+                .filter_map(|e| {
+                    e.metadata
+                        .get(ics::UID_KEY)
+                        .map(|uid| (uid.clone(), e.id.clone()))
+                })
+                .collect();
+            for item in imported {
+                let existing = item
+                    .uid
+                    .as_ref()
+                    .and_then(|uid| by_uid.get(uid))
+                    .and_then(|id| events.by_id.get(id))
+                    .cloned();
+                let mut event = match existing {
+                    Some(event) => {
+                        updated += 1;
+                        event
+                    }
+                    None => {
+                        created += 1;
+                        Event {
+                            id: Uuid::new_v4().to_string(),
+                            title: String::new(),
+                            description: None,
+                            event_type: ics::EVENT_TYPE.to_string(),
+                            tags: Vec::new(),
+                            created_at: now,
+                            start_time: None,
+                            end_time: None,
+                            metadata: HashMap::new(),
+                        }
+                    }
+                };
+                event.title = item.title;
+                event.description = item.description;
+                event.start_time = item.start;
+                event.end_time = item.end;
+                if !item.tags.is_empty() {
+                    event.tags = item.tags;
+                }
+                if let Some(uid) = item.uid {
+                    event.metadata.insert(ics::UID_KEY.to_string(), uid);
+                }
+                if item.cancelled {
+                    event
+                        .metadata
+                        .insert("status".to_string(), "cancelled".to_string());
+                } else if event
+                    .metadata
+                    .get("status")
+                    .is_some_and(|status| status.eq_ignore_ascii_case("cancelled"))
+                {
+                    event.metadata.remove("status");
+                }
+                events.insert(event);
+            }
+        }
+        if created + updated > 0 {
             self.save_to_disk().await?;
         }
+        Ok((created, updated))
+    }
 
-        Ok(existed)
+    /// Every event, oldest first
+    pub async fn all_events(&self) -> Vec<Event> {
+        let mut events: Vec<Event> = self.events.read().await.by_id.values().cloned().collect();
+        events.sort_by_key(|e| e.created_at);
+        events
     }
 
-    fn load_from_disk(&mut self) -> Result<(), String> {
-        if !Path::new(&self.storage_path).exists() {
+    /// How many events `retag` would change
+    pub async fn retag_count(&self, retag: &Retag) -> usize {
+        let events = self.events.read().await;
+        events
+            .by_id
+            .values()
+            .filter(|event| retag.apply(&event.tags).is_some())
+            .count()
+    }
+
+    /// Retags every event and saves, returning the changed events' previous tags; on a failed
+    /// save the events are left untouched
+    pub async fn retag(&self, retag: &Retag) -> Result<PreviousTags, String> {
+        let mut previous = PreviousTags::new();
+        {
+            let mut events = self.events.write().await;
+            for event in events.by_id.values_mut() {
+                if let Some(tags) = retag.apply(&event.tags) {
+                    previous.insert(event.id.clone(), std::mem::replace(&mut event.tags, tags));
+                }
+            }
+        }
+        if previous.is_empty() {
+            return Ok(previous);
+        }
+        if let Err(e) = self.save_to_disk().await {
+            self.put_back_tags(previous).await;
+            return Err(e);
+        }
+        Ok(previous)
+    }
+
+    /// Undoes `retag`
+    pub async fn restore_tags(&self, previous: PreviousTags) -> Result<(), String> {
+        if previous.is_empty() {
             return Ok(());
         }
+        self.put_back_tags(previous).await;
+        self.save_to_disk().await
+    }
 
-        let content = fs::read_to_string(&self.storage_path)
-            .map_err(|e| format!("Failed to read events file: {}", e))?;
+    async fn put_back_tags(&self, previous: PreviousTags) {
+        let mut events = self.events.write().await;
+        for (id, tags) in previous {
+            if let Some(event) = events.by_id.get_mut(&id) {
+                event.tags = tags;
+            }
+        }
+    }
 
-        if content.trim().is_empty() {
+    fn load_from_disk(&mut self) -> Result<(), String> {
+        let Some(content) = Vault::global().read(Path::new(&self.storage_path))? else {
+            return Ok(());
+        };
+
+        if content.trim_ascii().is_empty() {
             return Ok(());
         }
 
-        let events: HashMap<String, Event> = serde_json::from_str(&content)
+        let events: HashMap<String, Event> = serde_json::from_slice(&content)
             .map_err(|e| format!("Failed to parse events file: {}", e))?;
 
-        *self.events.get_mut() = events;
+        *self.events.get_mut() = EventStore::new(events);
         Ok(())
     }
 
     async fn save_to_disk(&self) -> Result<(), String> {
+        if let Some(e) = &self.unreadable {
+            return Err(format!(
+                "Not overwriting the events file that failed to load: {}",
+                e
+            ));
+        }
         let events = self.events.read().await;
-        let content = serde_json::to_string_pretty(&*events)
+        let content = serde_json::to_string_pretty(&events.by_id)
             .map_err(|e| format!("Failed to serialize events: {}", e))?;
 
-        if let Some(parent) = Path::new(&self.storage_path).parent() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create storage directory: {}", e))?;
+        Vault::global().write(Path::new(&self.storage_path), content.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(title: &str, start: Option<&str>, end: Option<&str>) -> AddEventRequest {
+        AddEventRequest {
+            title: title.to_string(),
+            description: None,
+            event_type: "meeting".to_string(),
+            tags: None,
+            start_time: start.map(str::to_string),
+            end_time: end.map(str::to_string),
+            metadata: None,
+            timezone: None,
+            allow_conflict: None,
         }
+    }
+
+    fn titles(events: &[Event]) -> Vec<&str> {
+        events.iter().map(|event| event.title.as_str()).collect()
+    }
 
-        fs::write(&self.storage_path, content)
-            .map_err(|e| format!("Failed to write events file: {}", e))?;
+    #[tokio::test]
+    async fn test_add_event_reports_overlaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("events.json")
+            .to_string_lossy()
+            .into_owned();
+        let manager = EventsManager::new(path.clone());
+
+        let (_, conflicts) = manager
+            .add_event(request(
+                "standup",
+                Some("2025-06-02T09:00:00Z"),
+                Some("2025-06-02T09:30:00Z"),
+            ))
+            .await
+            .unwrap();
+        assert!(conflicts.is_empty());
+        // Start only: assumed to last the default hour
+        manager
+            .add_event(request("review", Some("2025-06-02T10:00:00Z"), None))
+            .await
+            .unwrap();
+        let mut cancelled = request(
+            "retro",
+            Some("2025-06-02T09:00:00Z"),
+            Some("2025-06-02T12:00:00Z"),
+        );
+        cancelled.tags = Some(vec!["cancelled".to_string()]);
+        manager.add_event(cancelled).await.unwrap();
+
+        let (_, conflicts) = manager
+            .add_event(request(
+                "lunch",
+                Some("2025-06-02T09:15:00Z"),
+                Some("2025-06-02T10:30:00Z"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(titles(&conflicts), ["standup", "review"]);
+
+        // Back to back is not a conflict
+        let (_, conflicts) = manager
+            .add_event(request(
+                "walk",
+                Some("2025-06-02T08:30:00Z"),
+                Some("2025-06-02T09:00:00Z"),
+            ))
+            .await
+            .unwrap();
+        assert!(conflicts.is_empty());
+
+        let mut allowed = request("call", Some("2025-06-02T09:10:00Z"), None);
+        allowed.allow_conflict = Some(true);
+        assert!(manager.add_event(allowed).await.unwrap().1.is_empty());
+
+        // The index is rebuilt from disk
+        let reloaded = EventsManager::new(path);
+        let (_, conflicts) = reloaded
+            .add_event(request("late", Some("2025-06-02T10:45:00Z"), None))
+            .await
+            .unwrap();
+        assert_eq!(titles(&conflicts), ["review"]);
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn test_reimporting_a_calendar_updates_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = EventsManager::new(
+            dir.path()
+                .join("events.json")
+                .to_string_lossy()
+                .into_owned(),
+        );
+        let calendar = |summary: &str| {
+            format!(
+                "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nBEGIN:VEVENT\r\nUID:dentist@example.com\r\n\
+                 SUMMARY:{}\r\nDTSTART:20250602T090000Z\r\nDTEND:20250602T100000Z\r\n\
+                 END:VEVENT\r\nEND:VCALENDAR\r\n",
+                summary
+            )
+        };
+
+        let (imported, _) = ics::parse(&calendar("Dentist")).unwrap();
+        assert_eq!(manager.import_ics(imported).await.unwrap(), (1, 0));
+        let (imported, _) = ics::parse(&calendar("Dentist (moved room)")).unwrap();
+        assert_eq!(manager.import_ics(imported).await.unwrap(), (0, 1));
+
+        let events = manager.all_events().await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].title, "Dentist (moved room)");
+        assert_eq!(events[0].event_type, ics::EVENT_TYPE);
+
+        let window = |from: &str, to: &str| {
+            let at = |value: &str| DateTime::parse_from_rfc3339(value).unwrap().to_utc();
+            (Some(at(from)), Some(at(to)))
+        };
+        let (from, to) = window("2025-06-02T09:30:00Z", "2025-06-03T00:00:00Z");
+        assert_eq!(manager.in_window(from, to).await.len(), 1);
+        let (from, to) = window("2025-06-02T10:00:00Z", "2025-06-03T00:00:00Z");
+        assert!(manager.in_window(from, to).await.is_empty());
     }
 }
@@ -0,0 +1,494 @@
+//! iCalendar (RFC 5545) rendering and parsing for `exportevents_ics` and `importevents_ics`
+//!
+//! Only what calendars exchange for plain events is supported: a VEVENT's UID, summary,
+//! description, start, end (or duration), categories and status. Recurrence is not expanded.
+
+use super::types::Event;
+use crate::timezone::{self, Zone};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+
+/// Event metadata holding the UID of the calendar entry an event was imported from
+pub const UID_KEY: &str = "_ics_uid";
+
+/// Type of events created by an import
+pub const EVENT_TYPE: &str = "calendar";
+
+const PRODID: &str = "-//nparrot//events//EN";
+
+/// Longest content line before folding, in octets, without the line break
+const LINE_OCTETS: usize = 75;
+
+const UTC_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Escapes a TEXT value: backslashes, semicolons, commas and line breaks
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Folds a content line into lines of at most 75 octets, never splitting a character
+pub fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + line.len() / LINE_OCTETS * 3);
+    // Continuation lines start with a space, which counts towards their length
+    let mut room = LINE_OCTETS;
+    for c in line.chars() {
+        if c.len_utf8() > room {
+            folded.push_str("\r\n ");
+            room = LINE_OCTETS - 1;
+        }
+        folded.push(c);
+        room -= c.len_utf8();
+    }
+    folded
+}
+
+/// Content lines with folding undone
+fn unfold(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// A calendar file holding `events`
+pub fn export(events: &[(Event, DateTime<Utc>, DateTime<Utc>)], stamp: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODID),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+    for (event, start, end) in events {
+        let uid = event
+            .metadata
+            .get(UID_KEY)
+            .cloned()
+            .unwrap_or_else(|| format!("{}@nparrot", event.id));
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:{}", escape_text(&uid)));
+        lines.push(format!("DTSTAMP:{}", stamp.format(UTC_FORMAT)));
+        lines.push(format!("DTSTART:{}", start.format(UTC_FORMAT)));
+        lines.push(format!("DTEND:{}", end.format(UTC_FORMAT)));
+        lines.push(format!("SUMMARY:{}", escape_text(&event.title)));
+        if let Some(description) = event.description.as_deref().filter(|d| !d.is_empty()) {
+            lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+        }
+        if !event.tags.is_empty() {
+            let categories: Vec<String> = event.tags.iter().map(|t| escape_text(t)).collect();
+            lines.push(format!("CATEGORIES:{}", categories.join(",")));
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut ics: String = lines
+        .iter()
+        .map(|line| fold_line(line))
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    ics.push_str("\r\n");
+    ics
+}
+
+/// One VEVENT read from a calendar file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportedEvent {
+    pub uid: Option<String>,
+    pub title: String,
+    pub description: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub cancelled: bool,
+    /// What could not be imported from this entry
+    pub warnings: Vec<String>,
+}
+
+/// A content line split into name, parameters and value
+#[derive(Debug)]
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Self> {
+        // The value starts at the first colon outside a quoted parameter value
+        let mut quoted = false;
+        let colon = line.char_indices().find_map(|(i, c)| match c {
+            '"' => {
+                quoted = !quoted;
+                None
+            }
+            ':' if !quoted => Some(i),
+            _ => None,
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let mut parts = head.split(';');
+        let name = parts.next()?.trim().to_ascii_uppercase();
+        let params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| {
+                (
+                    key.to_ascii_uppercase(),
+                    value.trim_matches('"').to_string(),
+                )
+            })
+            .collect();
+        Some(Self {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    fn param(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Reads every VEVENT in `ics`; entries that cannot be read at all are skipped with a warning
+pub fn parse(ics: &str) -> Result<(Vec<ImportedEvent>, Vec<String>), String> {
+    let lines = unfold(ics);
+    if !lines
+        .first()
+        .is_some_and(|line| line.eq_ignore_ascii_case("BEGIN:VCALENDAR"))
+    {
+        return Err("Not an iCalendar file: it has to start with BEGIN:VCALENDAR".to_string());
+    }
+
+    let mut events = Vec::new();
+    let mut skipped = Vec::new();
+    let mut current: Option<Vec<Property>> = None;
+    let mut nested = 0;
+    for line in &lines {
+        let Some(property) = Property::parse(line) else {
+            continue;
+        };
+        match (
+            property.name.as_str(),
+            property.value.to_ascii_uppercase().as_str(),
+        ) {
+            ("BEGIN", "VEVENT") => current = Some(Vec::new()),
+            ("END", "VEVENT") => {
+                if let Some(properties) = current.take() {
+                    match read_event(properties) {
+                        Ok(event) => events.push(event),
+                        Err(e) => skipped.push(e),
+                    }
+                }
+            }
+            // Alarms and other components inside an event are not ours to read
+            ("BEGIN", _) if current.is_some() => nested += 1,
+            ("END", _) if current.is_some() && nested > 0 => nested -= 1,
+            _ if nested > 0 => {}
+            _ => {
+                if let Some(properties) = current.as_mut() {
+                    properties.push(property);
+                }
+            }
+        }
+    }
+    Ok((events, skipped))
+}
+
+fn read_event(properties: Vec<Property>) -> Result<ImportedEvent, String> {
+    let mut event = ImportedEvent::default();
+    let mut duration = None;
+    for property in &properties {
+        match property.name.as_str() {
+            "UID" => event.uid = Some(unescape_text(&property.value)),
+            "SUMMARY" => event.title = unescape_text(&property.value),
+            "DESCRIPTION" => event.description = Some(unescape_text(&property.value)),
+            "DTSTART" => event.start = Some(read_time(property)?),
+            "DTEND" => event.end = Some(read_time(property)?),
+            "DURATION" => duration = Some(read_duration(&property.value)?),
+            "CATEGORIES" => event.tags.extend(
+                split_list(&property.value)
+                    .into_iter()
+                    .filter(|tag| !tag.is_empty()),
+            ),
+            "STATUS" => event.cancelled = property.value.eq_ignore_ascii_case("CANCELLED"),
+            "RRULE" | "RDATE" | "EXRULE" => {
+                let warning =
+                    "its recurrence rule is not supported; only the first occurrence was imported";
+                if !event.warnings.iter().any(|w| w == warning) {
+                    event.warnings.push(warning.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let label = if event.title.is_empty() {
+        event
+            .uid
+            .clone()
+            .unwrap_or_else(|| "untitled event".to_string())
+    } else {
+        event.title.clone()
+    };
+    if event.title.is_empty() {
+        event.title = "Untitled event".to_string();
+    }
+    if event.uid.is_none() {
+        event
+            .warnings
+            .push("it has no UID, so importing it again will add it again".to_string());
+    }
+    if let (None, Some(start), Some(duration)) = (event.end, event.start, duration) {
+        event.end = Some(start + duration);
+    }
+    if let (Some(start), Some(end)) = (event.start, event.end) {
+        if end < start {
+            return Err(format!("'{}' skipped: it ends before it starts", label));
+        }
+    }
+    for warning in &mut event.warnings {
+        *warning = format!("'{}': {}", label, warning);
+    }
+    Ok(event)
+}
+
+/// A DATE-TIME (UTC, floating or with TZID) or an all-day DATE, which starts at midnight in the
+/// default time zone
+fn read_time(property: &Property) -> Result<DateTime<Utc>, String> {
+    let value = property.value.trim();
+    let invalid = |e: chrono::ParseError| format!("Invalid {} '{}': {}", property.name, value, e);
+    if let Some(utc) = value.strip_suffix(['Z', 'z']) {
+        return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+            .map(|time| time.and_utc())
+            .map_err(invalid);
+    }
+
+    let zone = match property.param("TZID") {
+        Some(tzid) => std::sync::Arc::new(Zone::load(tzid).map_err(|e| {
+            format!(
+                "Invalid {} '{}': unknown time zone: {}",
+                property.name, value, e
+            )
+        })?),
+        None => timezone::default_zone(),
+    };
+    let local = if property
+        .param("VALUE")
+        .is_some_and(|v| v.eq_ignore_ascii_case("DATE"))
+        || value.len() == 8
+    {
+        NaiveDate::parse_from_str(value, "%Y%m%d")
+            .map_err(invalid)?
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight exists")
+    } else {
+        NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(invalid)?
+    };
+    Ok(zone.to_utc(local))
+}
+
+/// A DURATION value such as `PT1H30M`, `P1D` or `P2W`
+fn read_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid DURATION '{}'", value);
+    let (negative, rest) = match value.trim().strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.trim().trim_start_matches('+')),
+    };
+    let rest = rest.strip_prefix(['P', 'p']).ok_or_else(invalid)?;
+
+    let mut seconds = 0i64;
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in rest.chars() {
+        match c.to_ascii_uppercase() {
+            'T' => in_time = true,
+            digit if digit.is_ascii_digit() => number.push(digit),
+            unit => {
+                let n: i64 = number.parse().map_err(|_| invalid())?;
+                number.clear();
+                seconds += n * match (unit, in_time) {
+                    ('W', false) => 7 * 86_400,
+                    ('D', false) => 86_400,
+                    ('H', true) => 3_600,
+                    ('M', true) => 60,
+                    ('S', true) => 1,
+                    _ => return Err(invalid()),
+                };
+            }
+        }
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::seconds(if negative { -seconds } else { seconds }))
+}
+
+/// Splits a comma-separated list of TEXT values, honouring escaped commas
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                item.push(c);
+                if let Some(next) = chars.next() {
+                    item.push(next);
+                }
+            }
+            ',' => items.push(unescape_text(std::mem::take(&mut item).trim())),
+            c => item.push(c),
+        }
+    }
+    items.push(unescape_text(item.trim()));
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_escaping_round_trips() {
+        let text = "Lunch; then review, maybe\\not\r\nBring: slides";
+        let escaped = escape_text(text);
+        assert_eq!(
+            escaped,
+            "Lunch\\; then review\\, maybe\\\\not\\nBring: slides"
+        );
+        assert_eq!(unescape_text(&escaped), text.replace("\r\n", "\n"));
+        assert_eq!(split_list("a\\,b,c , d"), ["a,b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_folding_keeps_lines_short_and_characters_whole() {
+        let line = format!("DESCRIPTION:{}", "ä".repeat(100));
+        let folded = fold_line(&line);
+        for (i, part) in folded.split("\r\n").enumerate() {
+            assert!(
+                part.len() <= LINE_OCTETS,
+                "line {} is {} octets",
+                i,
+                part.len()
+            );
+            assert_eq!(i > 0, part.starts_with(' '));
+        }
+        assert_eq!(unfold(&folded), [line]);
+        assert_eq!(fold_line("SUMMARY:short"), "SUMMARY:short");
+        assert_eq!(fold_line(&"x".repeat(75)), "x".repeat(75));
+        assert_eq!(
+            fold_line(&"x".repeat(76)),
+            format!("{}\r\n x", "x".repeat(75))
+        );
+    }
+
+    #[test]
+    fn test_export_then_parse() {
+        let event = Event {
+            id: "abc".to_string(),
+            title: "Standup, daily".to_string(),
+            description: Some("Line one\nLine two; more".to_string()),
+            event_type: "meeting".to_string(),
+            tags: vec!["work".to_string(), "team,core".to_string()],
+            created_at: at("2025-01-01T00:00:00Z"),
+            start_time: Some(at("2025-06-02T09:00:00Z")),
+            end_time: Some(at("2025-06-02T09:15:00Z")),
+            metadata: HashMap::new(),
+        };
+        let ics = export(
+            &[(
+                event.clone(),
+                event.start_time.unwrap(),
+                event.end_time.unwrap(),
+            )],
+            at("2025-06-01T00:00:00Z"),
+        );
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.contains("\r\nUID:abc@nparrot\r\n"));
+        assert!(ics.contains("\r\nDTSTART:20250602T090000Z\r\n"));
+
+        let (events, skipped) = parse(&ics).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(
+            events,
+            [ImportedEvent {
+                uid: Some("abc@nparrot".to_string()),
+                title: event.title,
+                description: event.description,
+                start: event.start_time,
+                end: event.end_time,
+                tags: event.tags,
+                cancelled: false,
+                warnings: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_handles_zones_dates_durations_and_recurrence() {
+        let ics = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n\
+            BEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Holiday\r\nDTSTART;VALUE=DATE:20250704\r\n\
+            DTEND;VALUE=DATE:20250705\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nUID:2\r\nSUMMARY:Gym\r\nDTSTART;TZID=UTC:20250602T180000\r\n\
+            DURATION:PT1H30M\r\nRRULE:FREQ=WEEKLY\r\nBEGIN:VALARM\r\nACTION:DISPLAY\r\n\
+            SUMMARY:Alarm\r\nEND:VALARM\r\nSTATUS:CANCELLED\r\nEND:VEVENT\r\n\
+            BEGIN:VEVENT\r\nSUMMARY:Broken\r\nDTSTART:20250602T100000Z\r\n\
+            DTEND:20250602T090000Z\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        let (events, skipped) = parse(ics).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(skipped.len(), 1, "{:?}", skipped);
+
+        let gym = &events[1];
+        assert_eq!(gym.title, "Gym");
+        assert_eq!(gym.start, Some(at("2025-06-02T18:00:00Z")));
+        assert_eq!(gym.end, Some(at("2025-06-02T19:30:00Z")));
+        assert!(gym.cancelled);
+        assert_eq!(gym.warnings.len(), 1);
+        assert!(gym.warnings[0].contains("recurrence"));
+
+        assert!(parse("BEGIN:VEVENT\r\nEND:VEVENT").is_err());
+        assert_eq!(read_duration("P1W").unwrap(), Duration::days(7));
+        assert!(read_duration("PT1X").is_err());
+    }
+}
@@ -0,0 +1,406 @@
+//! `Chat`'s background receive path: one subscription that keeps running between `wait` calls
+//!
+//! Messages that arrive while the agent is busy, or while no MCP client is connected, are buffered
+//! for the next `wait` instead of being missed (at most `MAX_BUFFERED`, the oldest dropped first),
+//! and interrupt messages (see `interrupt`) are acted on immediately rather than queued.
+//!
+//! With a coalescing window (`NPARROT_COALESCE_MS`), a plain message that doesn't end a sentence
+//! waits up to the window for a follow-up from the same sender, and the fragments are handed to
+//! `wait` as one message. Each follow-up restarts the window; a fragment ending in `.`, `!` or
+//! `?`, or a gap longer than the window, ends it.
+//!
+//! Each sender (see `senders`) has a queue of their own, so `wait` can take the next message of
+//! one sender while the others' wait, and the messages of each are numbered separately.
+
+use crate::content_kind;
+use crate::group::{self, Conversation, GroupEvent};
+use crate::interrupt::{self, Interrupt};
+use crate::language;
+use crate::mcp::palette::Palette;
+use crate::transport::SharedTransport;
+use crate::utils::{listen_for_messages_from, IncomingMessage};
+use nostr_sdk::prelude::*;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Fragments merged into one message at most
+const MAX_FRAGMENTS: usize = 10;
+/// Messages buffered at most while nobody waits; beyond that the oldest are dropped
+pub const MAX_BUFFERED: usize = 500;
+
+lazy_static::lazy_static! {
+    static ref COALESCE_WINDOW: RwLock<Option<Duration>> = RwLock::new(None);
+}
+
+/// Sets the coalescing window for inboxes started from now on; `None` delivers every message
+/// on its own, without waiting
+pub fn set_coalesce_window(window: Option<Duration>) {
+    if let Ok(mut guard) = COALESCE_WINDOW.write() {
+        *guard = window.filter(|window| !window.is_zero());
+    }
+}
+
+pub fn coalesce_window() -> Option<Duration> {
+    COALESCE_WINDOW.read().ok().and_then(|guard| *guard)
+}
+
+/// A message and when the inbox received it, which may be long before a `wait` takes it
+#[derive(Debug, Clone)]
+pub struct Buffered {
+    pub message: IncomingMessage,
+    pub received_at: Instant,
+    /// Which message of its sender this is, from 1, counting the ones `next` handed out; 0
+    /// until then
+    pub sequence: u64,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    /// One queue per sender, each oldest first
+    queues: HashMap<PublicKey, VecDeque<Buffered>>,
+    /// The subscription ended; nothing more will arrive
+    closed: bool,
+}
+
+impl Buffer {
+    /// The oldest message of `from`, or of anyone if `None`
+    fn pop(&mut self, from: Option<PublicKey>) -> Option<Buffered> {
+        let sender = match from {
+            Some(sender) => sender,
+            None => {
+                self.queues
+                    .iter()
+                    .filter_map(|(sender, queue)| Some((*sender, queue.front()?.received_at)))
+                    .min_by_key(|(_, received_at)| *received_at)?
+                    .0
+            }
+        };
+        self.queues.get_mut(&sender)?.pop_front()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    buffer: std::sync::Mutex<Buffer>,
+    arrived: Notify,
+}
+
+impl Shared {
+    fn push(&self, message: IncomingMessage) {
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        let queue = buffer.queues.entry(message.sender).or_default();
+        if queue.len() >= MAX_BUFFERED {
+            if let Some(dropped) = queue.pop_front() {
+                log::warn!(
+                    "Inbox full, dropping the oldest buffered message {} from {}",
+                    dropped.message.event_id,
+                    dropped.message.sender
+                );
+            }
+        }
+        queue.push_back(Buffered {
+            message,
+            received_at: Instant::now(),
+            sequence: 0,
+        });
+        drop(buffer);
+        self.arrived.notify_waiters();
+    }
+
+    fn close(&self) {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        self.arrived.notify_waiters();
+    }
+
+    /// The oldest buffered message of `from` (of anyone if `None`), waiting for one; `None` once
+    /// the subscription has ended. Taking it is a single step, so dropping the future never loses
+    /// a message.
+    async fn recv(&self, from: Option<PublicKey>) -> Option<Buffered> {
+        loop {
+            // Registered before looking, so a message pushed in between still wakes us; every
+            // waiter is woken, since each may be waiting for a different sender
+            let arrived = self.arrived.notified();
+            tokio::pin!(arrived);
+            arrived.as_mut().enable();
+            {
+                let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(message) = buffer.pop(from) {
+                    return Some(message);
+                }
+                if buffer.closed {
+                    return None;
+                }
+            }
+            arrived.await;
+        }
+    }
+
+    fn len(&self) -> usize {
+        let buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        buffer.queues.values().map(VecDeque::len).sum()
+    }
+
+    /// Puts a message taken too early back in front of its sender's queue
+    fn unread(&self, message: Buffered) {
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        buffer
+            .queues
+            .entry(message.message.sender)
+            .or_default()
+            .push_front(message);
+        drop(buffer);
+        self.arrived.notify_waiters();
+    }
+}
+
+/// Outlives MCP sessions: the server owns it, so a client that reconnects gets what arrived in
+/// between from its first `wait`
+#[derive(Debug)]
+pub struct Inbox {
+    shared: Arc<Shared>,
+    /// Fragments taken for the messages being coalesced, per sender; kept here rather than in
+    /// the `next` future, so a `wait` cancelled by a dropped session leaves them for the next one
+    pending: Mutex<HashMap<PublicKey, Vec<Buffered>>>,
+    /// How many messages `next` handed out per sender
+    sequences: std::sync::Mutex<HashMap<PublicKey, u64>>,
+    coalesce: Option<Duration>,
+    listener: JoinHandle<()>,
+}
+
+impl Inbox {
+    /// Subscribes to DMs from `senders` (or, for a group, to the messages of the first) and
+    /// starts buffering them; messages `commands` recognizes go to its channel instead
+    pub fn start(
+        client: SharedTransport,
+        our_pubkey: PublicKey,
+        senders: Vec<PublicKey>,
+        conversation: Conversation,
+        interrupt: Interrupt,
+        coalesce: Option<Duration>,
+        commands: Option<(Palette, mpsc::UnboundedSender<IncomingMessage>)>,
+    ) -> Self {
+        let shared = Arc::new(Shared::default());
+        let queue = shared.clone();
+        let callback = move |message: IncomingMessage| {
+            if interrupt::is_interrupt(&message.content) {
+                log::info!("The user asked to stop ({})", message.event_id);
+                interrupt.request();
+            } else if let Some((_, commands)) = commands
+                .as_ref()
+                .filter(|(palette, _)| palette.parse(&message.content).is_some())
+            {
+                log::info!("Answering command {} without the model", message.event_id);
+                let _ = commands.send(message);
+            } else {
+                queue.push(message);
+            }
+            async { false }
+        };
+
+        let closing = shared.clone();
+        let listener = tokio::spawn(async move {
+            let result = match conversation {
+                Conversation::Direct(_) => {
+                    listen_for_messages_from(
+                        client.as_ref(),
+                        &our_pubkey,
+                        &senders,
+                        Arc::new(Mutex::new(callback)),
+                    )
+                    .await
+                }
+                Conversation::Group(group) => {
+                    match group::subscribe(client.as_ref(), &group, our_pubkey, senders[0]).await {
+                        Ok(mut events) => {
+                            // Moderation events are logged by the subscription, never queued
+                            while let Some(event) = events.recv().await {
+                                if let GroupEvent::Message(message) = event {
+                                    callback(message).await;
+                                }
+                            }
+                            Ok(())
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            if let Err(e) = result {
+                log::warn!("Inbox subscription ended: {}", e);
+            }
+            closing.close();
+        });
+
+        Self {
+            shared,
+            pending: Mutex::new(HashMap::new()),
+            sequences: std::sync::Mutex::new(HashMap::new()),
+            coalesce,
+            listener,
+        }
+    }
+
+    /// How many messages are waiting for a `wait`
+    pub fn waiting(&self) -> usize {
+        self.shared.len()
+    }
+
+    /// The oldest buffered message of `from` (of anyone if `None`), waiting for one if there is
+    /// none, merged with its sender's follow-ups when coalescing; `None` once the subscription
+    /// has ended
+    pub async fn next(&self, from: Option<PublicKey>) -> Option<Buffered> {
+        let mut pending = self.pending.lock().await;
+        // Fragments a cancelled `wait` left behind go first
+        let left_behind = pending
+            .iter()
+            .filter(|(sender, fragments)| {
+                !fragments.is_empty() && from.is_none_or(|from| from == **sender)
+            })
+            .min_by_key(|(_, fragments)| fragments[0].received_at)
+            .map(|(sender, _)| *sender);
+        let sender = match left_behind {
+            Some(sender) => sender,
+            None => {
+                let first = self.shared.recv(from).await?;
+                let sender = first.message.sender;
+                pending.insert(sender, vec![first]);
+                sender
+            }
+        };
+        let fragments = pending.entry(sender).or_default();
+        if let Some(window) = self.coalesce {
+            while fragments.len() < MAX_FRAGMENTS
+                && continues(&fragments[fragments.len() - 1].message)
+            {
+                match tokio::time::timeout(window, self.shared.recv(Some(sender))).await {
+                    Ok(Some(next)) if next.message.message_type.is_none() => fragments.push(next),
+                    Ok(Some(next)) => {
+                        self.shared.unread(next);
+                        break;
+                    }
+                    // The gap was too long, or the subscription ended after these fragments
+                    Ok(None) | Err(_) => break,
+                }
+            }
+        }
+        let fragments = pending.remove(&sender).unwrap_or_default();
+        let received_at = fragments[0].received_at;
+        let sequence = {
+            let mut sequences = self.sequences.lock().unwrap_or_else(|e| e.into_inner());
+            let sequence = sequences.entry(sender).or_default();
+            *sequence += 1;
+            *sequence
+        };
+        Some(Buffered {
+            message: merge(
+                fragments
+                    .into_iter()
+                    .map(|fragment| fragment.message)
+                    .collect(),
+            ),
+            received_at,
+            sequence,
+        })
+    }
+}
+
+/// Whether a follow-up may still belong to `message`: plain text not ending a sentence
+fn continues(message: &IncomingMessage) -> bool {
+    message.message_type.is_none() && !message.content.trim_end().ends_with(['.', '!', '?', '…'])
+}
+
+/// One message with the fragments' text on separate lines; the first fragment's id and date,
+/// and every fragment's id in `fragment_ids`
+fn merge(mut fragments: Vec<IncomingMessage>) -> IncomingMessage {
+    if fragments.len() == 1 {
+        return fragments.remove(0);
+    }
+    let content = fragments
+        .iter()
+        .map(|fragment| fragment.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let fragment_ids = fragments.iter().map(|fragment| fragment.event_id).collect();
+    let first = fragments.remove(0);
+    let (content_kind, nostr_entities) = content_kind::detect(&content);
+    IncomingMessage {
+        detected_language: language::detect(&content).to_string(),
+        content,
+        fragment_ids,
+        content_kind,
+        nostr_entities,
+        ..first
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: &Keys, content: &str) -> IncomingMessage {
+        IncomingMessage::from_rumor(
+            EventBuilder::private_msg_rumor(from.public_key(), content).build(from.public_key()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_buffer_drops_the_oldest_when_full() {
+        let alice = Keys::generate();
+        let shared = Shared::default();
+        for n in 0..=MAX_BUFFERED {
+            shared.push(message(&alice, &n.to_string()));
+        }
+        assert_eq!(shared.recv(None).await.unwrap().message.content, "1");
+
+        // A message put back is the next one out
+        let second = shared.recv(None).await.unwrap();
+        shared.unread(second);
+        assert_eq!(shared.recv(None).await.unwrap().message.content, "2");
+
+        shared.buffer.lock().unwrap().queues.clear();
+        shared.close();
+        assert!(shared.recv(None).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_buffer_keeps_a_queue_per_sender() {
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let shared = Arc::new(Shared::default());
+        shared.push(message(&alice, "a1"));
+        shared.push(message(&bob, "b1"));
+        shared.push(message(&alice, "a2"));
+
+        assert_eq!(
+            shared
+                .recv(Some(bob.public_key()))
+                .await
+                .unwrap()
+                .message
+                .content,
+            "b1"
+        );
+        assert_eq!(shared.recv(None).await.unwrap().message.content, "a1");
+
+        // A wait for bob isn't woken by alice's messages, and takes bob's once it arrives
+        let waiting = tokio::spawn({
+            let shared = shared.clone();
+            let bob = bob.public_key();
+            async move { shared.recv(Some(bob)).await.unwrap().message.content }
+        });
+        tokio::task::yield_now().await;
+        shared.push(message(&alice, "a3"));
+        shared.push(message(&bob, "b2"));
+        assert_eq!(waiting.await.unwrap(), "b2");
+        assert_eq!(shared.recv(None).await.unwrap().message.content, "a2");
+        assert_eq!(shared.recv(None).await.unwrap().message.content, "a3");
+    }
+}
@@ -0,0 +1,148 @@
+//! `get_info` instructions of the enhanced and combined servers (`NPARROT_INSTRUCTIONS`)
+//!
+//! Each server lists its instructions as rules, and the profile picks which ones are rendered:
+//! some MCP clients truncate long instructions, so `compact` keeps only the mandatory workflow
+//! and JSON rules, and `minimal` is a few lines for clients that inject their own system prompt.
+//! Rules about tools the server isn't listing are left out whatever the profile.
+
+use std::sync::RwLock;
+
+/// How much of the server instructions `get_info` returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum InstructionProfile {
+    /// What the server is and the turn workflow
+    Minimal,
+    /// Also the JSON argument and error rules
+    Compact,
+    /// Everything, including the prompts, tool families and examples
+    #[default]
+    Full,
+}
+
+/// One paragraph of the instructions
+#[derive(Debug, Clone, Copy)]
+pub struct Rule {
+    /// The sparsest profile that still includes it
+    pub profile: InstructionProfile,
+    /// The tools it is about; it is dropped when none of them is listed, and kept always if empty
+    pub tools: &'static [&'static str],
+    pub text: &'static str,
+}
+
+impl Rule {
+    pub const fn new(profile: InstructionProfile, text: &'static str) -> Self {
+        Self {
+            profile,
+            tools: &[],
+            text,
+        }
+    }
+
+    pub const fn about(
+        profile: InstructionProfile,
+        tools: &'static [&'static str],
+        text: &'static str,
+    ) -> Self {
+        Self {
+            profile,
+            tools,
+            text,
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref PROFILE: RwLock<InstructionProfile> = RwLock::new(InstructionProfile::default());
+}
+
+/// Sets the profile of the servers started from now on
+pub fn set_profile(profile: InstructionProfile) {
+    if let Ok(mut guard) = PROFILE.write() {
+        *guard = profile;
+    }
+}
+
+pub fn profile() -> InstructionProfile {
+    PROFILE.read().map(|guard| *guard).unwrap_or_default()
+}
+
+/// The rules `profile` includes whose tools are `listed`, as paragraphs
+pub fn render(rules: &[Rule], profile: InstructionProfile, listed: &[&str]) -> String {
+    rules
+        .iter()
+        .filter(|rule| rule.profile <= profile)
+        .filter(|rule| rule.tools.is_empty() || rule.tools.iter().any(|t| listed.contains(t)))
+        .map(|rule| rule.text)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &[Rule] = &[
+        Rule::new(InstructionProfile::Minimal, "intro"),
+        Rule::new(InstructionProfile::Compact, "json"),
+        Rule::about(InstructionProfile::Full, &["pay_invoice"], "wallet"),
+        Rule::new(InstructionProfile::Full, "prompts"),
+    ];
+
+    #[test]
+    fn test_render_keeps_the_rules_of_the_profile_and_listed_tools() {
+        let listed = ["send", "pay_invoice"];
+        assert_eq!(render(RULES, InstructionProfile::Minimal, &listed), "intro");
+        assert_eq!(
+            render(RULES, InstructionProfile::Compact, &listed),
+            "intro\n\njson"
+        );
+        assert_eq!(
+            render(RULES, InstructionProfile::Full, &listed),
+            "intro\n\njson\n\nwallet\n\nprompts"
+        );
+        assert_eq!(
+            render(RULES, InstructionProfile::Full, &["send"]),
+            "intro\n\njson\n\nprompts"
+        );
+    }
+
+    /// Each server's rules rendered with every tool listed, against `fixtures/`, so that
+    /// wording changes show up in review
+    fn assert_snapshots(rules: &[Rule], snapshots: [&str; 3]) {
+        let listed: Vec<&str> = rules.iter().flat_map(|rule| rule.tools).copied().collect();
+        let profiles = [
+            InstructionProfile::Full,
+            InstructionProfile::Compact,
+            InstructionProfile::Minimal,
+        ];
+        for (profile, snapshot) in profiles.into_iter().zip(snapshots) {
+            assert_eq!(render(rules, profile, &listed), snapshot, "{:?}", profile);
+        }
+    }
+
+    #[cfg(all(feature = "goose", feature = "searxng"))]
+    #[test]
+    fn test_combined_instruction_snapshots() {
+        assert_snapshots(
+            crate::combined_mcp::INSTRUCTIONS,
+            [
+                include_str!("fixtures/instructions_combined_full.txt"),
+                include_str!("fixtures/instructions_combined_compact.txt"),
+                include_str!("fixtures/instructions_combined_minimal.txt"),
+            ],
+        );
+    }
+
+    #[cfg(feature = "memory")]
+    #[test]
+    fn test_enhanced_instruction_snapshots() {
+        assert_snapshots(
+            crate::mcp::server::INSTRUCTIONS,
+            [
+                include_str!("fixtures/instructions_enhanced_full.txt"),
+                include_str!("fixtures/instructions_enhanced_compact.txt"),
+                include_str!("fixtures/instructions_enhanced_minimal.txt"),
+            ],
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Mirrors notes into the relay-backed memory store and imports memories back as notes
+//!
+//! A note and its memory are linked by the note's reserved `_memory_id` metadata and the memory's
+//! `source_note`. Both directions only create or update; an item deleted on one side is reported,
+//! never deleted on the other.
+
+use super::notes::NotesManager;
+use super::types::Note;
+use crate::error::NparrotError;
+use crate::nostr_mcp::client::NostrMemoryClient;
+use crate::nostr_mcp::types::{MemoryEntry, RetrieveMemoryRequest};
+use std::collections::{HashMap, HashSet};
+
+/// Note metadata holding the id of the memory that mirrors it
+pub const MEMORY_ID_KEY: &str = "_memory_id";
+
+/// Memory type of notes mirrored into memory
+pub const NOTE_MEMORY_TYPE: &str = "note";
+
+/// Longest memory title taken from a note's first line
+const TITLE_CHARS: usize = 80;
+
+/// What `syncnotes_to_memory` did
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Notes whose memory was deleted; their link is kept so they are not mirrored again
+    pub memory_deleted: Vec<Note>,
+    /// Memories whose note was deleted
+    pub note_deleted: Vec<MemoryEntry>,
+}
+
+/// What `import_memories_as_notes` did
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: Vec<Note>,
+    pub already_linked: usize,
+    /// Memories mirrored from notes that have since been deleted; not re-imported
+    pub note_deleted: Vec<MemoryEntry>,
+    /// Notes linked to memories that have since been deleted
+    pub memory_deleted: Vec<Note>,
+}
+
+/// Stores every note (with `tag`, if given) as a memory, updating memories whose note changed
+pub async fn sync_notes_to_memory(
+    notes: &NotesManager,
+    memory: &NostrMemoryClient,
+    tag: Option<&str>,
+) -> Result<SyncReport, NparrotError> {
+    let memories = all_memories(memory).await?;
+    let all_notes = notes.all_notes().await;
+    let mut report = SyncReport::default();
+    let mut links = Vec::new();
+
+    for note in all_notes.iter().filter(|note| has_tag(note, tag)) {
+        let linked = note.metadata.get(MEMORY_ID_KEY);
+        let existing = linked.and_then(|id| memories.get(id));
+        match (linked, existing) {
+            (Some(_), None) => report.memory_deleted.push(note.clone()),
+            (Some(_), Some(existing)) => {
+                // Notes are only edited after their memory was last written if they changed
+                if note.updated_at <= existing.timestamp {
+                    report.unchanged += 1;
+                } else {
+                    store(memory, &mirror(note, Some(existing))).await?;
+                    report.updated += 1;
+                }
+            }
+            (None, _) => {
+                let mirrored = mirror(note, None);
+                store(memory, &mirrored).await?;
+                links.push((note.id.clone(), MEMORY_ID_KEY, mirrored.id.to_string()));
+                report.created += 1;
+            }
+        }
+    }
+    notes.set_reserved_metadata(links).await?;
+
+    let note_ids: HashSet<&str> = all_notes.iter().map(|n| n.id.as_str()).collect();
+    report.note_deleted = memories
+        .into_values()
+        .filter(|m| {
+            m.source_note
+                .as_deref()
+                .is_some_and(|id| !note_ids.contains(id))
+        })
+        .collect();
+    Ok(report)
+}
+
+/// Adds a note for every unexpired memory (of `memory_type`, if given) not linked to one yet
+pub async fn import_memories_as_notes(
+    notes: &NotesManager,
+    memory: &NostrMemoryClient,
+    memory_type: Option<&str>,
+) -> Result<ImportReport, NparrotError> {
+    let memories = all_memories(memory).await?;
+    let all_notes = notes.all_notes().await;
+    let note_ids: HashSet<&str> = all_notes.iter().map(|n| n.id.as_str()).collect();
+    let linked: HashSet<&str> = all_notes
+        .iter()
+        .filter_map(|n| n.metadata.get(MEMORY_ID_KEY).map(String::as_str))
+        .collect();
+    let mut report = ImportReport {
+        memory_deleted: all_notes
+            .iter()
+            .filter(|n| {
+                n.metadata
+                    .get(MEMORY_ID_KEY)
+                    .is_some_and(|id| !memories.contains_key(id))
+            })
+            .cloned()
+            .collect(),
+        ..ImportReport::default()
+    };
+
+    let mut candidates: Vec<&MemoryEntry> = memories
+        .values()
+        .filter(|m| !m.is_expired())
+        .filter(|m| memory_type.is_none_or(|t| m.memory_type.eq_ignore_ascii_case(t)))
+        .collect();
+    candidates.sort_by_key(|m| m.timestamp);
+    for entry in candidates {
+        if linked.contains(entry.id.to_string().as_str()) {
+            report.already_linked += 1;
+        } else if entry
+            .source_note
+            .as_deref()
+            .is_some_and(|id| !note_ids.contains(id))
+        {
+            report.note_deleted.push(entry.clone());
+        } else {
+            report.imported.push(import(entry));
+        }
+    }
+    notes.import_notes(report.imported.clone()).await?;
+    Ok(report)
+}
+
+/// The memory mirroring `note`; an existing memory keeps its id, type, priority and expiry
+fn mirror(note: &Note, existing: Option<&MemoryEntry>) -> MemoryEntry {
+    let title = note
+        .content
+        .lines()
+        .map(|line| line.trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or("Untitled note");
+    let title = match title.char_indices().nth(TITLE_CHARS) {
+        Some((end, _)) => format!("{}…", &title[..end]),
+        None => title.to_string(),
+    };
+    let mut entry = MemoryEntry::new(
+        NOTE_MEMORY_TYPE.to_string(),
+        note.tags.first().cloned(),
+        title,
+        note.content.clone(),
+        note.tags.clone(),
+        None,
+        None,
+    );
+    if let Some(existing) = existing {
+        entry.id = existing.id;
+        entry.memory_type = existing.memory_type.clone();
+        entry.content.metadata.priority = existing.content.metadata.priority.clone();
+        entry.content.metadata.expiry = existing.content.metadata.expiry;
+    }
+    entry.source_note = Some(note.id.clone());
+    entry
+}
+
+/// A new note holding `entry`, linked to it
+fn import(entry: &MemoryEntry) -> Note {
+    let title = entry.content.title.trim();
+    let description = entry.content.description.trim();
+    let content = if description.is_empty() {
+        title.to_string()
+    } else if title.is_empty() || description.starts_with(title) {
+        description.to_string()
+    } else {
+        format!("{}\n\n{}", title, description)
+    };
+
+    let mut tags = entry.content.metadata.tags.clone();
+    if let Some(category) = &entry.category {
+        if !tags.iter().any(|t| t.eq_ignore_ascii_case(category)) {
+            tags.insert(0, category.clone());
+        }
+    }
+    Note {
+        id: uuid::Uuid::new_v4().to_string(),
+        content,
+        tags,
+        created_at: entry.timestamp,
+        updated_at: entry.timestamp,
+        metadata: HashMap::from([(MEMORY_ID_KEY.to_string(), entry.id.to_string())]),
+    }
+}
+
+fn has_tag(note: &Note, tag: Option<&str>) -> bool {
+    tag.is_none_or(|tag| note.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+}
+
+async fn all_memories(
+    memory: &NostrMemoryClient,
+) -> Result<HashMap<String, MemoryEntry>, NparrotError> {
+    let filter = RetrieveMemoryRequest {
+        query: None,
+        memory_type: None,
+        category: None,
+        tags: None,
+        limit: Some(10000),
+        since: None,
+        until: None,
+    };
+    let memories = memory
+        .retrieve_memories(&filter)
+        .await
+        .map_err(|e| NparrotError::from(e).context("Failed to read memories"))?;
+    Ok(memories
+        .into_iter()
+        .map(|m| (m.id.to_string(), m))
+        .collect())
+}
+
+async fn store(memory: &NostrMemoryClient, entry: &MemoryEntry) -> Result<(), NparrotError> {
+    memory
+        .store_memory(entry)
+        .await
+        .map(|_| ())
+        .map_err(|e| NparrotError::from(e).context("Failed to store memory"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::types::AddNoteRequest;
+    use crate::transport::fake::FakeTransport;
+    use nostr_sdk::prelude::Keys;
+    use std::sync::Arc;
+
+    fn setup() -> (NotesManager, NostrMemoryClient, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = NotesManager::new(dir.path().join("notes.json").to_string_lossy().into_owned());
+        let keys = Keys::generate();
+        let transport = Arc::new(FakeTransport::new(keys.clone()));
+        let memory = NostrMemoryClient::new(transport, keys.clone(), keys.public_key());
+        (notes, memory, dir)
+    }
+
+    async fn add(notes: &NotesManager, content: &str, tags: &[&str]) -> Note {
+        notes
+            .add_note(AddNoteRequest {
+                content: content.to_string(),
+                tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                metadata: None,
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sync_is_idempotent_and_reports_deletions() {
+        let (notes, memory, _dir) = setup();
+        let kept = add(&notes, "# Standup\nMove it to 10am", &["work", "team"]).await;
+        let gone = add(&notes, "Buy milk", &[]).await;
+
+        let report = sync_notes_to_memory(&notes, &memory, None).await.unwrap();
+        assert_eq!(
+            (report.created, report.updated, report.unchanged),
+            (2, 0, 0)
+        );
+        let report = sync_notes_to_memory(&notes, &memory, None).await.unwrap();
+        assert_eq!(
+            (report.created, report.updated, report.unchanged),
+            (0, 0, 2)
+        );
+
+        let memories = all_memories(&memory).await.unwrap();
+        assert_eq!(memories.len(), 2);
+        let linked = notes.all_notes().await[0].metadata[MEMORY_ID_KEY].clone();
+        let mirrored = &memories[&linked];
+        assert_eq!(mirrored.memory_type, NOTE_MEMORY_TYPE);
+        assert_eq!(mirrored.category.as_deref(), Some("work"));
+        assert_eq!(mirrored.content.title, "Standup");
+        assert_eq!(mirrored.source_note.as_deref(), Some(kept.id.as_str()));
+
+        // Nothing is propagated: both deletions only show up in the reports
+        notes.delete_note(&gone.id, false).await.unwrap();
+        memory.delete_memory(&linked, false).await.unwrap();
+        let report = sync_notes_to_memory(&notes, &memory, None).await.unwrap();
+        assert_eq!(report.created, 0);
+        assert_eq!(report.memory_deleted.len(), 1);
+        assert_eq!(report.note_deleted.len(), 1);
+        assert_eq!(all_memories(&memory).await.unwrap().len(), 1);
+
+        let report = import_memories_as_notes(&notes, &memory, None)
+            .await
+            .unwrap();
+        assert!(report.imported.is_empty());
+        assert_eq!(report.note_deleted.len(), 1);
+        assert_eq!(report.memory_deleted.len(), 1);
+        assert_eq!(notes.all_notes().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_import_creates_linked_notes_once() {
+        let (notes, memory, _dir) = setup();
+        let entry = MemoryEntry::new(
+            "fact".to_string(),
+            Some("personal".to_string()),
+            "Coffee".to_string(),
+            "Prefers oat milk".to_string(),
+            vec!["food".to_string()],
+            None,
+            None,
+        );
+        memory.store_memory(&entry).await.unwrap();
+
+        let report = import_memories_as_notes(&notes, &memory, Some("FACT"))
+            .await
+            .unwrap();
+        assert_eq!(report.imported.len(), 1);
+        let note = &report.imported[0];
+        assert_eq!(note.content, "Coffee\n\nPrefers oat milk");
+        assert_eq!(note.tags, ["personal", "food"]);
+        assert_eq!(note.metadata[MEMORY_ID_KEY], entry.id.to_string());
+
+        let report = import_memories_as_notes(&notes, &memory, None)
+            .await
+            .unwrap();
+        assert!(report.imported.is_empty());
+        assert_eq!(report.already_linked, 1);
+        // The imported note is already linked, so syncing it back creates nothing new
+        let report = sync_notes_to_memory(&notes, &memory, None).await.unwrap();
+        assert_eq!(report.created, 0);
+        assert_eq!(notes.all_notes().await.len(), 1);
+    }
+}
@@ -1,9 +1,26 @@
 pub mod chat;
+pub mod context;
+pub mod dedup;
 pub mod events;
+pub mod ics;
+pub mod inbox;
+pub mod instructions;
+#[cfg(feature = "memory")]
+pub mod memory_sync;
+pub mod notebook;
 pub mod notes;
+pub mod palette;
+#[cfg(feature = "memory")]
 pub mod progress_enforcer;
+pub mod prompts;
+pub mod search;
+#[cfg(feature = "memory")]
 pub mod server;
+pub mod server_common;
+pub mod tags;
 pub mod types;
 pub mod validation;
+pub mod watchdog;
 
+#[cfg(feature = "memory")]
 pub use server::EnhancedMcpServer;
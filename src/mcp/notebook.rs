@@ -0,0 +1,463 @@
+//! The note and event tools, shared by the enhanced and the combined server
+//!
+//! Each tool reports its result to the user through the server's `Chat` as well as returning it,
+//! so the servers pass theirs in.
+
+use super::chat::Chat;
+use super::events::EventsManager;
+use super::notes::{first_line, NotesManager};
+use super::server_common::{reply, reply_and_result, reply_error, with_progress};
+use super::types::*;
+use crate::dry_run;
+use crate::timezone::{self, DISPLAY_FORMAT};
+use rmcp::model::{CallToolResult, Content};
+use rmcp::Error as RmcpError;
+use std::sync::Arc;
+
+pub const NOTES_FILE: &str = "notes.json";
+pub const EVENTS_FILE: &str = "events.json";
+
+#[derive(Debug, Clone)]
+pub struct Notebook {
+    notes: Arc<NotesManager>,
+    events: Arc<EventsManager>,
+}
+
+impl Notebook {
+    /// Opens the notes and events files in `data_dir`
+    pub fn open(data_dir: &str) -> Self {
+        Self {
+            notes: Arc::new(NotesManager::new(format!("{}/{}", data_dir, NOTES_FILE))),
+            events: Arc::new(EventsManager::new(format!("{}/{}", data_dir, EVENTS_FILE))),
+        }
+    }
+
+    pub fn notes(&self) -> &Arc<NotesManager> {
+        &self.notes
+    }
+
+    pub fn events(&self) -> &Arc<EventsManager> {
+        &self.events
+    }
+
+    pub async fn addnote(
+        &self,
+        chat: &Chat,
+        request: AddNoteRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        match with_progress(chat, "Adding new note...", self.notes.add_note(request)).await {
+            Ok(note) => {
+                let message = format!(
+                    "Note added successfully!\n\nID: {}\nContent: {}\nTags: {}\nCreated: {}",
+                    note.id,
+                    note.content,
+                    note.tags.join(", "),
+                    timezone::format(note.created_at)
+                );
+
+                reply_and_result(chat, message, format!("Note added with ID: {}", note.id)).await
+            }
+            Err(e) => reply_error(chat, format!("Failed to add note: {}", e), e).await,
+        }
+    }
+
+    pub async fn listnotes(
+        &self,
+        chat: &Chat,
+        request: ListNotesRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let include_metadata = request.include_metadata.unwrap_or(false);
+        match with_progress(chat, "Retrieving notes...", self.notes.list_notes(request)).await {
+            Ok(notes) => {
+                let message = if notes.is_empty() {
+                    "📝 No notes found.".to_string()
+                } else {
+                    let notes_text = notes
+                        .iter()
+                        .map(|note| {
+                            let metadata = if include_metadata && !note.metadata.is_empty() {
+                                let mut pairs: Vec<String> = note
+                                    .metadata
+                                    .iter()
+                                    .map(|(key, value)| format!("{}={}", key, value))
+                                    .collect();
+                                pairs.sort();
+                                format!("  Metadata: {}\n", pairs.join(", "))
+                            } else {
+                                String::new()
+                            };
+                            format!(
+                                "• **{}** ({})\n  Tags: {}\n  Created: {}\n{}",
+                                &note.id[..8],
+                                note.content.chars().take(50).collect::<String>()
+                                    + if note.content.len() > 50 { "..." } else { "" },
+                                if note.tags.is_empty() {
+                                    "none".to_string()
+                                } else {
+                                    note.tags.join(", ")
+                                },
+                                timezone::format(note.created_at),
+                                metadata
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    format!("📝 Found {} note(s):\n\n{}", notes.len(), notes_text)
+                };
+
+                reply_and_result(chat, message, format!("Listed {} notes", notes.len())).await
+            }
+            Err(e) => reply_error(chat, format!("❌ Failed to list notes: {}", e), e).await,
+        }
+    }
+
+    pub async fn searchnotes(
+        &self,
+        chat: &Chat,
+        request: SearchNotesRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        match with_progress(
+            chat,
+            format!("Searching notes for: '{}'...", request.query),
+            self.notes.search_notes(request),
+        )
+        .await
+        {
+            Ok(notes) => {
+                let message = if notes.is_empty() {
+                    "🔍 No matching notes found.".to_string()
+                } else {
+                    let notes_text = notes
+                        .iter()
+                        .map(|note| {
+                            format!(
+                                "• **{}**\n  {}\n  Tags: {}\n  Created: {}\n",
+                                &note.id[..8],
+                                note.content,
+                                if note.tags.is_empty() {
+                                    "none".to_string()
+                                } else {
+                                    note.tags.join(", ")
+                                },
+                                timezone::format(note.created_at)
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    format!(
+                        "🔍 Found {} matching note(s):\n\n{}",
+                        notes.len(),
+                        notes_text
+                    )
+                };
+
+                reply_and_result(
+                    chat,
+                    message,
+                    format!("Found {} matching notes", notes.len()),
+                )
+                .await
+            }
+            Err(e) => reply_error(chat, format!("❌ Failed to search notes: {}", e), e).await,
+        }
+    }
+
+    pub async fn deletenote(
+        &self,
+        chat: &Chat,
+        request: DeleteNoteRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let dry_run = dry_run::requested(request.dry_run);
+        let label = if dry_run {
+            format!("Previewing deletion of note {}...", request.id)
+        } else {
+            format!("Deleting note {}...", request.id)
+        };
+        match with_progress(chat, label, self.notes.delete_note(&request.id, dry_run)).await {
+            Ok(note) => {
+                let (message, result) = match note {
+                    Some(note) if dry_run => {
+                        let result = format!(
+                            "Would delete 1 note: {}: {}",
+                            note.id,
+                            first_line(&note.content)
+                        );
+                        (dry_run::label(&result), dry_run::label(&result))
+                    }
+                    Some(_) => (
+                        "🗑️ Note deleted successfully!".to_string(),
+                        "Note deleted".to_string(),
+                    ),
+                    None if dry_run => (
+                        dry_run::label("no note with that ID, nothing would be deleted"),
+                        dry_run::label("Note not found"),
+                    ),
+                    None => (
+                        "❌ Note not found.".to_string(),
+                        "Note not found".to_string(),
+                    ),
+                };
+
+                reply_and_result(chat, message, result).await
+            }
+            Err(e) => reply_error(chat, format!("❌ Failed to delete note: {}", e), e).await,
+        }
+    }
+
+    pub async fn addevent(
+        &self,
+        chat: &Chat,
+        request: AddEventRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let zone = timezone::display_zone(request.timezone.as_deref())
+            .map_err(|e| RmcpError::invalid_params(e, None))?;
+        match with_progress(chat, "Adding new event...", self.events.add_event(request)).await {
+            Ok((event, conflicts)) => {
+                let time_info = match (event.start_time, event.end_time) {
+                    (Some(start), Some(end)) => format!(
+                        "\nStart: {}\nEnd: {}",
+                        zone.format(start, DISPLAY_FORMAT),
+                        zone.format(end, DISPLAY_FORMAT)
+                    ),
+                    (Some(start), None) => {
+                        format!("\nStart: {}", zone.format(start, DISPLAY_FORMAT))
+                    }
+                    (None, Some(end)) => format!("\nEnd: {}", zone.format(end, DISPLAY_FORMAT)),
+                    (None, None) => "".to_string(),
+                };
+
+                let message = format!(
+                    "📅 Event added successfully!\n\nID: {}\nTitle: {}\nType: {}\nTags: {}\nCreated: {}{}",
+                    event.id,
+                    event.title,
+                    event.event_type,
+                    if event.tags.is_empty() { "none".to_string() } else { event.tags.join(", ") },
+                    zone.format(event.created_at, DISPLAY_FORMAT),
+                    time_info
+                );
+                let message = if conflicts.is_empty() {
+                    message
+                } else {
+                    let listed = conflicts
+                        .iter()
+                        .map(|conflict| {
+                            let when = match (conflict.start_time, conflict.end_time) {
+                                (Some(start), Some(end)) => format!(
+                                    "{} - {}",
+                                    zone.format(start, DISPLAY_FORMAT),
+                                    zone.format(end, DISPLAY_FORMAT)
+                                ),
+                                (Some(time), None) | (None, Some(time)) => {
+                                    zone.format(time, DISPLAY_FORMAT)
+                                }
+                                (None, None) => String::new(),
+                            };
+                            format!(
+                                "• **{}** - {} ({})",
+                                &conflict.id[..8],
+                                conflict.title,
+                                when
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    format!(
+                        "{}\n\n⚠️ Overlaps with {} existing event(s):\n{}",
+                        message,
+                        conflicts.len(),
+                        listed
+                    )
+                };
+
+                let mut result = format!("Event added with ID: {}", event.id);
+                if !conflicts.is_empty() {
+                    let ids: Vec<&str> = conflicts.iter().map(|c| c.id.as_str()).collect();
+                    result.push_str(&format!(
+                        " (overlaps {} existing event(s): {})",
+                        conflicts.len(),
+                        ids.join(", ")
+                    ));
+                }
+                reply_and_result(chat, message, result).await
+            }
+            Err(e) => {
+                let error_msg = format!("❌ Failed to add event: {}", e);
+                reply(chat, error_msg.clone()).await;
+                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+            }
+        }
+    }
+
+    pub async fn listevents(
+        &self,
+        chat: &Chat,
+        request: ListEventsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let zone = timezone::display_zone(request.timezone.as_deref())
+            .map_err(|e| RmcpError::invalid_params(e, None))?;
+        match with_progress(
+            chat,
+            "Retrieving events...",
+            self.events.list_events(request),
+        )
+        .await
+        {
+            Ok(events) => {
+                let message = if events.is_empty() {
+                    "📅 No events found.".to_string()
+                } else {
+                    let events_text = events
+                        .iter()
+                        .map(|event| {
+                            let time_info = match event.start_time {
+                                Some(start) => format!(" | {}", zone.format(start, "%m/%d %H:%M")),
+                                None => "".to_string(),
+                            };
+
+                            format!(
+                                "• **{}** - {} ({}){}\n  Tags: {}\n",
+                                &event.id[..8],
+                                event.title,
+                                event.event_type,
+                                time_info,
+                                if event.tags.is_empty() {
+                                    "none".to_string()
+                                } else {
+                                    event.tags.join(", ")
+                                }
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+
+                    format!("📅 Found {} event(s):\n\n{}", events.len(), events_text)
+                };
+
+                reply_and_result(chat, message, format!("Listed {} events", events.len())).await
+            }
+            Err(e) => {
+                let error_msg = format!("❌ Failed to list events: {}", e);
+                reply(chat, error_msg.clone()).await;
+                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+            }
+        }
+    }
+
+    pub async fn searchevents(
+        &self,
+        chat: &Chat,
+        request: SearchEventsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let zone = timezone::display_zone(request.timezone.as_deref())
+            .map_err(|e| RmcpError::invalid_params(e, None))?;
+        match with_progress(
+            chat,
+            format!("Searching events for: '{}'...", request.query),
+            self.events.search_events(request),
+        )
+        .await
+        {
+            Ok(events) => {
+                let message = if events.is_empty() {
+                    "🔍 No matching events found.".to_string()
+                } else {
+                    let events_text = events
+                        .iter()
+                        .map(|event| {
+                            let time_info = match (event.start_time, event.end_time) {
+                                (Some(start), Some(end)) => format!(
+                                    "\n  Time: {} - {}",
+                                    zone.format(start, DISPLAY_FORMAT),
+                                    zone.format(end, DISPLAY_FORMAT)
+                                ),
+                                (Some(start), None) => {
+                                    format!("\n  Start: {}", zone.format(start, DISPLAY_FORMAT))
+                                }
+                                (None, Some(end)) => {
+                                    format!("\n  End: {}", zone.format(end, DISPLAY_FORMAT))
+                                }
+                                (None, None) => "".to_string(),
+                            };
+
+                            format!(
+                                "• **{}** - {} ({})\n  Tags: {}{}",
+                                &event.id[..8],
+                                event.title,
+                                event.event_type,
+                                if event.tags.is_empty() {
+                                    "none".to_string()
+                                } else {
+                                    event.tags.join(", ")
+                                },
+                                time_info
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n\n");
+
+                    format!(
+                        "🔍 Found {} matching event(s):\n\n{}",
+                        events.len(),
+                        events_text
+                    )
+                };
+
+                reply_and_result(
+                    chat,
+                    message,
+                    format!("Found {} matching events", events.len()),
+                )
+                .await
+            }
+            Err(e) => {
+                let error_msg = format!("❌ Failed to search events: {}", e);
+                reply(chat, error_msg.clone()).await;
+                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+            }
+        }
+    }
+
+    pub async fn deleteevent(
+        &self,
+        chat: &Chat,
+        request: DeleteEventRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let dry_run = dry_run::requested(request.dry_run);
+        let label = if dry_run {
+            format!("Previewing deletion of event {}...", request.id)
+        } else {
+            format!("Deleting event {}...", request.id)
+        };
+        match with_progress(chat, label, self.events.delete_event(&request.id, dry_run)).await {
+            Ok(event) => {
+                let (message, result) = match event {
+                    Some(event) if dry_run => {
+                        let result = format!("Would delete 1 event: {}: {}", event.id, event.title);
+                        (dry_run::label(&result), dry_run::label(&result))
+                    }
+                    Some(_) => (
+                        "🗑️ Event deleted successfully!".to_string(),
+                        "Event deleted".to_string(),
+                    ),
+                    None if dry_run => (
+                        dry_run::label("no event with that ID, nothing would be deleted"),
+                        dry_run::label("Event not found"),
+                    ),
+                    None => (
+                        "❌ Event not found.".to_string(),
+                        "Event not found".to_string(),
+                    ),
+                };
+
+                reply_and_result(chat, message, result).await
+            }
+            Err(e) => {
+                let error_msg = format!("❌ Failed to delete event: {}", e);
+                reply(chat, error_msg.clone()).await;
+                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+            }
+        }
+    }
+}
@@ -1,15 +1,52 @@
+use super::search::{self, Query, FUZZY_THRESHOLD};
+use super::tags::{PreviousTags, Retag};
 use super::types::*;
+use crate::at_rest::Vault;
+use crate::error::NparrotError;
+use crate::timezone;
+use crate::transport::DmTransport;
+use nostr_sdk::prelude::{
+    Coordinate, Event as NostrEvent, EventBuilder, Kind, Nip19Coordinate, RelayUrl, Tag,
+    TagStandard, Timestamp, ToBech32,
+};
+use regex::Regex;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// How long backups of bulk-deleted notes are kept, in days
+const BACKUP_GRACE_DAYS: i64 = 7;
+
+/// Directory next to the notes file holding those backups
+const BACKUP_DIR: &str = "deleted-notes";
+
+/// Metadata keys starting with this are set by nparrot itself, never by `addnote`
+pub const RESERVED_PREFIX: &str = "_";
+
+/// Where `publishnote` records the article's address and first publication time
+pub const NADDR_KEY: &str = "_naddr";
+pub const PUBLISHED_AT_KEY: &str = "_published_at";
+
+/// How many of the relays that accepted an article go into its naddr
+const NADDR_RELAYS: usize = 3;
+
+/// A note rendered as a NIP-23 article, and whether it actually went out
+#[derive(Debug)]
+pub struct PublishedNote {
+    pub event: NostrEvent,
+    pub naddr: String,
+    pub published: bool,
+}
+
 #[derive(Debug)]
 pub struct NotesManager {
     notes: RwLock<HashMap<String, Note>>,
     storage_path: String,
+    /// Why the notes file could not be loaded; saving is refused so it is not overwritten
+    unreadable: Option<String>,
 }
 
 impl NotesManager {
@@ -17,12 +54,31 @@ impl NotesManager {
         let mut manager = Self {
             notes: RwLock::new(HashMap::new()),
             storage_path,
+            unreadable: None,
         };
-        let _ = manager.load_from_disk();
+        if let Err(e) = manager.load_from_disk() {
+            log::error!("{}", e);
+            manager.unreadable = Some(e);
+        }
         manager
     }
 
-    pub async fn add_note(&self, request: AddNoteRequest) -> Result<Note, String> {
+    pub async fn add_note(&self, request: AddNoteRequest) -> Result<Note, NparrotError> {
+        if let Some(key) = request
+            .metadata
+            .iter()
+            .flat_map(|metadata| metadata.keys())
+            .find(|key| key.starts_with(RESERVED_PREFIX))
+        {
+            return Err(NparrotError::invalid_params(
+                "metadata",
+                format!(
+                    "Metadata key '{}' is reserved: keys starting with '{}' are for internal use",
+                    key, RESERVED_PREFIX
+                ),
+            ));
+        }
+
         let now = chrono::Utc::now();
         let note = Note {
             id: Uuid::new_v4().to_string(),
@@ -42,25 +98,20 @@ impl NotesManager {
         Ok(note)
     }
 
-    pub async fn list_notes(&self, request: ListNotesRequest) -> Result<Vec<Note>, String> {
+    pub async fn list_notes(&self, request: ListNotesRequest) -> Result<Vec<Note>, NparrotError> {
         let notes = self.notes.read().await;
         let mut filtered_notes: Vec<Note> = notes
             .values()
-            .filter(|note| {
-                if let Some(tag) = &request.tag {
-                    note.tags.contains(tag)
-                } else {
-                    true
-                }
-            })
+            .filter(|note| has_tag(note, request.tag.as_deref()))
+            .filter(|note| has_metadata(note, request.metadata_filters.as_ref()))
             .cloned()
             .collect();
 
         let sort_order = request.sort.as_deref().unwrap_or("newest");
         match sort_order {
-            "oldest" => filtered_notes.sort_by(|a, b| a.created_at.cmp(&b.created_at)),
-            "updated" => filtered_notes.sort_by(|a, b| b.updated_at.cmp(&a.updated_at)),
-            _ => filtered_notes.sort_by(|a, b| b.created_at.cmp(&a.created_at)),
+            "oldest" => filtered_notes.sort_by_key(|n| n.created_at),
+            "updated" => filtered_notes.sort_by_key(|n| std::cmp::Reverse(n.updated_at)),
+            _ => filtered_notes.sort_by_key(|n| std::cmp::Reverse(n.created_at)),
         }
 
         if let Some(limit) = request.limit {
@@ -70,25 +121,37 @@ impl NotesManager {
         Ok(filtered_notes)
     }
 
-    pub async fn search_notes(&self, request: SearchNotesRequest) -> Result<Vec<Note>, String> {
+    pub async fn search_notes(
+        &self,
+        request: SearchNotesRequest,
+    ) -> Result<Vec<Note>, NparrotError> {
         let notes = self.notes.read().await;
-        let query_lower = request.query.to_lowercase();
-
-        let mut matching_notes: Vec<Note> = notes
+        let query = Query::new(&request.query);
+        let candidates = notes
             .values()
-            .filter(|note| {
-                let content_match = note.content.to_lowercase().contains(&query_lower);
-                let tag_match = if let Some(tag) = &request.tag {
-                    note.tags.contains(tag)
-                } else {
-                    true
-                };
-                content_match && tag_match
-            })
-            .cloned()
-            .collect();
+            .filter(|note| has_tag(note, request.tag.as_deref()))
+            .filter(|note| has_metadata(note, request.metadata_filters.as_ref()));
 
-        matching_notes.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        let mut matching_notes: Vec<Note> = if request.fuzzy.unwrap_or(false) {
+            let mut scored: Vec<(f64, &Note)> = candidates
+                .map(|note| (query.score(&note.content), note))
+                .filter(|(score, _)| *score >= FUZZY_THRESHOLD)
+                .collect();
+            // Best match first, newest first among equals
+            scored.sort_by(|(a_score, a), (b_score, b)| {
+                b_score
+                    .total_cmp(a_score)
+                    .then(b.created_at.cmp(&a.created_at))
+            });
+            scored.into_iter().map(|(_, note)| note.clone()).collect()
+        } else {
+            let mut matching: Vec<Note> = candidates
+                .filter(|note| query.matches(&note.content))
+                .cloned()
+                .collect();
+            matching.sort_by_key(|n| std::cmp::Reverse(n.created_at));
+            matching
+        };
 
         if let Some(limit) = request.limit {
             matching_notes.truncate(limit as usize);
@@ -97,50 +160,700 @@ impl NotesManager {
         Ok(matching_notes)
     }
 
-    pub async fn delete_note(&self, request: DeleteNoteRequest) -> Result<bool, String> {
+    /// Deletes note `id`, or with `dry_run` only looks it up; returns the note either way, `None`
+    /// if there is none
+    pub async fn delete_note(&self, id: &str, dry_run: bool) -> Result<Option<Note>, NparrotError> {
         let mut notes = self.notes.write().await;
-        let existed = notes.remove(&request.id).is_some();
+        let Some(note) = notes.get(id).cloned() else {
+            return Ok(None);
+        };
+        if dry_run {
+            return Ok(Some(note));
+        }
+        notes.remove(id);
         drop(notes);
 
-        if existed {
-            self.save_to_disk().await?;
-        }
+        self.save_to_disk().await?;
+        Ok(Some(note))
+    }
 
-        Ok(existed)
+    /// Every note, oldest first
+    pub async fn all_notes(&self) -> Vec<Note> {
+        let mut notes: Vec<Note> = self.notes.read().await.values().cloned().collect();
+        notes.sort_by_key(|n| n.created_at);
+        notes
     }
 
-    fn load_from_disk(&mut self) -> Result<(), String> {
-        if !Path::new(&self.storage_path).exists() {
+    /// Sets reserved metadata on several notes and saves once; unknown ids are skipped
+    pub async fn set_reserved_metadata(
+        &self,
+        updates: Vec<(String, &str, String)>,
+    ) -> Result<(), NparrotError> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+        {
+            let mut notes = self.notes.write().await;
+            for (id, key, value) in updates {
+                debug_assert!(key.starts_with(RESERVED_PREFIX));
+                if let Some(note) = notes.get_mut(&id) {
+                    note.metadata.insert(key.to_string(), value);
+                }
+            }
+        }
+        self.save_to_disk().await
+    }
+
+    /// Adds notes built elsewhere (e.g. imported memories), reserved metadata included
+    pub async fn import_notes(&self, imported: Vec<Note>) -> Result<(), NparrotError> {
+        if imported.is_empty() {
             return Ok(());
         }
+        {
+            let mut notes = self.notes.write().await;
+            for note in imported {
+                notes.insert(note.id.clone(), note);
+            }
+        }
+        self.save_to_disk().await
+    }
+
+    /// How many notes `retag` would change
+    pub async fn retag_count(&self, retag: &Retag) -> usize {
+        let notes = self.notes.read().await;
+        notes
+            .values()
+            .filter(|note| retag.apply(&note.tags).is_some())
+            .count()
+    }
+
+    /// Retags every note and saves, returning the changed notes' previous tags; on a failed
+    /// save the notes are left untouched
+    pub async fn retag(&self, retag: &Retag) -> Result<PreviousTags, NparrotError> {
+        let mut previous = PreviousTags::new();
+        {
+            let mut notes = self.notes.write().await;
+            for note in notes.values_mut() {
+                if let Some(tags) = retag.apply(&note.tags) {
+                    previous.insert(note.id.clone(), std::mem::replace(&mut note.tags, tags));
+                    note.updated_at = chrono::Utc::now();
+                }
+            }
+        }
+        if previous.is_empty() {
+            return Ok(previous);
+        }
+        if let Err(e) = self.save_to_disk().await {
+            self.put_back_tags(previous).await;
+            return Err(e);
+        }
+        Ok(previous)
+    }
+
+    async fn put_back_tags(&self, previous: PreviousTags) {
+        let mut notes = self.notes.write().await;
+        for (id, tags) in previous {
+            if let Some(note) = notes.get_mut(&id) {
+                note.tags = tags;
+            }
+        }
+    }
+
+    /// Publishes a note as a kind 30023 long-form article signed by `transport`'s identity.
+    ///
+    /// The note id is the article's `d` tag, so publishing the same note again replaces the
+    /// earlier version instead of adding another article. Unless `dry_run` is set the naddr and
+    /// publication time are stored in the note's reserved metadata.
+    pub async fn publish(
+        &self,
+        request: PublishNoteRequest,
+        transport: &dyn DmTransport,
+    ) -> Result<PublishedNote, NparrotError> {
+        let note = self
+            .notes
+            .read()
+            .await
+            .get(&request.id)
+            .cloned()
+            .ok_or_else(|| {
+                NparrotError::invalid_params("id", format!("Note {} not found", request.id))
+            })?;
+
+        let (title, content) = match request.title {
+            Some(title) => (title, note.content.clone()),
+            None => split_title(&note.content),
+        };
+        if title.trim().is_empty() {
+            return Err(NparrotError::invalid_params(
+                "title",
+                "The note has no title: pass one or start the note with it",
+            ));
+        }
+        // Keep the original date on updates, as NIP-23 asks
+        let published_at = note
+            .metadata
+            .get(PUBLISHED_AT_KEY)
+            .and_then(|at| at.parse::<u64>().ok())
+            .map(Timestamp::from)
+            .unwrap_or_else(Timestamp::now);
+
+        let mut tags = vec![
+            Tag::identifier(&note.id),
+            Tag::title(title.trim()),
+            Tag::from_standardized(TagStandard::PublishedAt(published_at)),
+        ];
+        tags.extend(note.tags.iter().map(Tag::hashtag));
+        let builder = EventBuilder::long_form_text_note(content).tags(tags);
+        let event = transport
+            .sign_event(builder)
+            .await
+            .map_err(|e| NparrotError::internal(format!("Failed to sign article: {}", e)))?;
+        let coordinate = Coordinate::new(Kind::LongFormTextNote, event.pubkey).identifier(&note.id);
+
+        if request.dry_run.unwrap_or(false) {
+            let naddr = Nip19Coordinate::new(coordinate, Vec::<RelayUrl>::new())
+                .and_then(|c| c.to_bech32())
+                .map_err(|e| NparrotError::internal(e.to_string()))?;
+            return Ok(PublishedNote {
+                event,
+                naddr,
+                published: false,
+            });
+        }
+
+        let output = transport.send_event(&event).await.map_err(|e| {
+            NparrotError::relay_unavailable(format!("Failed to publish article: {}", e))
+        })?;
+        if output.success.is_empty() {
+            return Err(NparrotError::relay_unavailable(
+                "No relay accepted the article",
+            ));
+        }
+        let mut relays: Vec<RelayUrl> = output.success.into_iter().collect();
+        relays.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        relays.truncate(NADDR_RELAYS);
+        let naddr = Nip19Coordinate::new(coordinate, relays)
+            .and_then(|c| c.to_bech32())
+            .map_err(|e| NparrotError::internal(e.to_string()))?;
+
+        {
+            let mut notes = self.notes.write().await;
+            if let Some(note) = notes.get_mut(&note.id) {
+                note.metadata.insert(NADDR_KEY.to_string(), naddr.clone());
+                note.metadata.insert(
+                    PUBLISHED_AT_KEY.to_string(),
+                    published_at.as_u64().to_string(),
+                );
+            }
+        }
+        self.save_to_disk().await?;
+
+        Ok(PublishedNote {
+            event,
+            naddr,
+            published: true,
+        })
+    }
+
+    /// Deletes every note matching `filter` in a single write, after saving them to a backup
+    /// file; with `confirm` unset nothing is deleted and the matching notes are only returned
+    pub async fn delete_notes(
+        &self,
+        filter: &NoteFilter,
+        confirm: bool,
+    ) -> Result<(Vec<Note>, Option<PathBuf>), NparrotError> {
+        let mut notes = self.notes.write().await;
+        let mut matching: Vec<Note> = notes
+            .values()
+            .filter(|note| filter.matches(note))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|n| n.created_at);
+        if !confirm || matching.is_empty() {
+            return Ok((matching, None));
+        }
 
-        let content = fs::read_to_string(&self.storage_path)
-            .map_err(|e| format!("Failed to read notes file: {}", e))?;
+        // Back up first, so a failed backup deletes nothing
+        let backup = self
+            .write_backup(&matching)
+            .map_err(NparrotError::internal)?;
+        let deleted: HashSet<&str> = matching.iter().map(|note| note.id.as_str()).collect();
+        let remaining: HashMap<String, Note> = notes
+            .iter()
+            .filter(|(id, _)| !deleted.contains(id.as_str()))
+            .map(|(id, note)| (id.clone(), note.clone()))
+            .collect();
+        self.write_notes(&remaining)?;
+        *notes = remaining;
+        Ok((matching, Some(backup)))
+    }
 
-        if content.trim().is_empty() {
+    fn write_backup(&self, deleted: &[Note]) -> Result<PathBuf, String> {
+        let dir = Path::new(&self.storage_path)
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(BACKUP_DIR);
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create backup directory: {}", e))?;
+
+        let now = chrono::Utc::now();
+        let cutoff = std::time::SystemTime::now()
+            - std::time::Duration::from_secs(BACKUP_GRACE_DAYS as u64 * 86_400);
+        if let Ok(entries) = fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let expired = entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .is_ok_and(|modified| modified < cutoff);
+                if expired {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        let path = dir.join(format!(
+            "{}-{}.json",
+            now.format("%Y%m%dT%H%M%S"),
+            &Uuid::new_v4().to_string()[..8]
+        ));
+        let content = serde_json::to_string_pretty(deleted)
+            .map_err(|e| format!("Failed to serialize deleted notes: {}", e))?;
+        fs::write(&path, content).map_err(|e| format!("Failed to write backup: {}", e))?;
+        Ok(path)
+    }
+
+    fn load_from_disk(&mut self) -> Result<(), String> {
+        let Some(content) = Vault::global().read(Path::new(&self.storage_path))? else {
+            return Ok(());
+        };
+
+        if content.trim_ascii().is_empty() {
             return Ok(());
         }
 
-        let notes: HashMap<String, Note> = serde_json::from_str(&content)
+        let notes: HashMap<String, Note> = serde_json::from_slice(&content)
             .map_err(|e| format!("Failed to parse notes file: {}", e))?;
 
         *self.notes.get_mut() = notes;
         Ok(())
     }
 
-    async fn save_to_disk(&self) -> Result<(), String> {
+    async fn save_to_disk(&self) -> Result<(), NparrotError> {
         let notes = self.notes.read().await;
-        let content = serde_json::to_string_pretty(&*notes)
-            .map_err(|e| format!("Failed to serialize notes: {}", e))?;
+        self.write_notes(&notes)
+    }
 
-        if let Some(parent) = Path::new(&self.storage_path).parent() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create storage directory: {}", e))?;
+    /// Replaces the notes file in one step, so readers never see a half-written set
+    fn write_notes(&self, notes: &HashMap<String, Note>) -> Result<(), NparrotError> {
+        if let Some(e) = &self.unreadable {
+            return Err(NparrotError::internal(format!(
+                "Not overwriting the notes file that failed to load: {}",
+                e
+            )));
         }
+        let content = serde_json::to_string_pretty(notes)
+            .map_err(|e| NparrotError::internal(format!("Failed to serialize notes: {}", e)))?;
 
-        fs::write(&self.storage_path, content)
-            .map_err(|e| format!("Failed to write notes file: {}", e))?;
+        Vault::global()
+            .write(Path::new(&self.storage_path), content.as_bytes())
+            .map_err(NparrotError::internal)
+    }
+}
 
-        Ok(())
+/// The first line (minus any Markdown heading marks) as title, the rest as the article body
+fn split_title(content: &str) -> (String, String) {
+    let content = content.trim_start();
+    let (first, rest) = content.split_once('\n').unwrap_or((content, ""));
+    (
+        first.trim_start_matches('#').trim().to_string(),
+        rest.trim_start_matches(['\r', '\n']).to_string(),
+    )
+}
+
+/// Which notes `deletenotes` removes; every given filter has to match
+#[derive(Debug, Default)]
+pub struct NoteFilter {
+    pub tag: Option<String>,
+    pub created_before: Option<chrono::DateTime<chrono::Utc>>,
+    pub content: Option<Regex>,
+}
+
+impl NoteFilter {
+    pub fn from_request(request: &DeleteNotesRequest) -> Result<Self, NparrotError> {
+        let created_before = request
+            .created_before
+            .as_deref()
+            .map(|value| {
+                chrono::DateTime::parse_from_rfc3339(value)
+                    .map(|time| time.to_utc())
+                    .or_else(|_| {
+                        // A plain date means midnight in the user's time zone
+                        chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| {
+                            timezone::default_zone()
+                                .to_utc(date.and_hms_opt(0, 0, 0).expect("midnight exists"))
+                        })
+                    })
+                    .map_err(|_| {
+                        NparrotError::invalid_params(
+                            "created_before",
+                            format!(
+                                "Invalid created_before '{}': use YYYY-MM-DD or ISO 8601",
+                                value
+                            ),
+                        )
+                    })
+            })
+            .transpose()?;
+        let content = request
+            .content_regex
+            .as_deref()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    NparrotError::invalid_params(
+                        "content_regex",
+                        format!("Invalid content_regex: {}", e),
+                    )
+                })
+            })
+            .transpose()?;
+        let filter = Self {
+            tag: request.tag.clone(),
+            created_before,
+            content,
+        };
+        if filter.tag.is_none() && filter.created_before.is_none() && filter.content.is_none() {
+            return Err(NparrotError::invalid_params(
+                "tag",
+                "Give at least one filter (tag, created_before or content_regex)",
+            ));
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, note: &Note) -> bool {
+        has_tag(note, self.tag.as_deref())
+            && self
+                .created_before
+                .is_none_or(|before| note.created_at < before)
+            && self
+                .content
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(&note.content))
+    }
+}
+
+/// Tags compare like search text: ignoring case and accents
+fn has_tag(note: &Note, tag: Option<&str>) -> bool {
+    let Some(tag) = tag else {
+        return true;
+    };
+    let tag = search::normalize(tag);
+    note.tags.iter().any(|t| search::normalize(t) == tag)
+}
+
+/// Every filter has to match a metadata value exactly
+fn has_metadata(note: &Note, filters: Option<&HashMap<String, String>>) -> bool {
+    filters
+        .into_iter()
+        .flatten()
+        .all(|(key, value)| note.metadata.get(key) == Some(value))
+}
+
+/// A note's first line, shortened the way `listnotes` shortens content
+pub fn first_line(content: &str) -> String {
+    let line = content.lines().next().unwrap_or_default();
+    if line.chars().count() > 50 {
+        line.chars().take(50).collect::<String>() + "..."
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn search(query: &str, tag: Option<&str>, fuzzy: bool) -> SearchNotesRequest {
+        SearchNotesRequest {
+            query: query.to_string(),
+            tag: tag.map(str::to_string),
+            limit: None,
+            fuzzy: Some(fuzzy),
+            metadata_filters: None,
+        }
+    }
+
+    async fn manager_with(contents: &[(&str, &[&str])]) -> (NotesManager, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.json").to_string_lossy().into_owned();
+        let manager = NotesManager::new(path);
+        for (content, tags) in contents {
+            manager
+                .add_note(AddNoteRequest {
+                    content: content.to_string(),
+                    tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+                    metadata: None,
+                })
+                .await
+                .unwrap();
+        }
+        (manager, dir)
+    }
+
+    #[tokio::test]
+    async fn test_search_normalizes_and_ranks_fuzzy_matches() {
+        let (manager, _dir) = manager_with(&[
+            ("Deployment checklist", &["Työ"]),
+            ("Deploy the hotfix", &["work"]),
+            ("Grocery list", &[]),
+        ])
+        .await;
+
+        let found = manager
+            .search_notes(search("DEPLOY", Some("tyo"), false))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content, "Deployment checklist");
+
+        assert!(manager
+            .search_notes(search("deplyo", None, false))
+            .await
+            .unwrap()
+            .is_empty());
+        let found = manager
+            .search_notes(search("deplyo", None, true))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        let found = manager
+            .search_notes(search("checklst", None, true))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content, "Deployment checklist");
+        let found = manager
+            .search_notes(search("deploy hotfx", None, true))
+            .await
+            .unwrap();
+        assert_eq!(found[0].content, "Deploy the hotfix");
+    }
+
+    #[tokio::test]
+    async fn test_metadata_filters_apply_before_limit() {
+        let (manager, _dir) =
+            manager_with(&[("alpha one", &[]), ("beta", &[]), ("alpha two", &[])]).await;
+        {
+            let mut notes = manager.notes.write().await;
+            for note in notes.values_mut() {
+                let project = note.content.split(' ').next().unwrap().to_string();
+                note.metadata.insert("project".to_string(), project);
+            }
+        }
+        let filters = HashMap::from([("project".to_string(), "alpha".to_string())]);
+
+        let listed = manager
+            .list_notes(ListNotesRequest {
+                tag: None,
+                limit: Some(2),
+                sort: Some("oldest".to_string()),
+                metadata_filters: Some(filters.clone()),
+                include_metadata: None,
+            })
+            .await
+            .unwrap();
+        let contents: Vec<&str> = listed.iter().map(|n| n.content.as_str()).collect();
+        assert_eq!(contents, ["alpha one", "alpha two"]);
+
+        let mut request = search("a", None, false);
+        request.metadata_filters = Some(filters);
+        let found = manager.search_notes(request).await.unwrap();
+        assert_eq!(found.len(), 2);
+
+        let error = manager
+            .add_note(AddNoteRequest {
+                content: "sneaky".to_string(),
+                tags: None,
+                metadata: Some(HashMap::from([("_naddr".to_string(), "x".to_string())])),
+            })
+            .await
+            .unwrap_err();
+        assert!(error.message().contains("reserved"), "{}", error);
+        assert_eq!(error.code(), "invalid_params");
+    }
+
+    #[tokio::test]
+    async fn test_republishing_updates_the_same_article() {
+        use crate::transport::fake::FakeTransport;
+        use nostr_sdk::prelude::{FromBech32, Keys, TagKind};
+
+        let (manager, _dir) =
+            manager_with(&[("# Release notes\n\nShipped the hotfix.", &["Work"])]).await;
+        let id = manager.notes.read().await.keys().next().unwrap().clone();
+        let transport = FakeTransport::new(Keys::generate());
+        let request = |dry_run| PublishNoteRequest {
+            id: id.clone(),
+            title: None,
+            dry_run: Some(dry_run),
+        };
+
+        let preview = manager.publish(request(true), &transport).await.unwrap();
+        assert!(!preview.published);
+        assert!(transport.published().is_empty());
+        assert!(!manager.notes.read().await[&id]
+            .metadata
+            .contains_key(NADDR_KEY));
+
+        let first = manager.publish(request(false), &transport).await.unwrap();
+        assert_eq!(first.event.kind, Kind::LongFormTextNote);
+        assert_eq!(first.event.content, "Shipped the hotfix.");
+        assert_eq!(first.event.tags.identifier(), Some(id.as_str()));
+        let title = first
+            .event
+            .tags
+            .find(TagKind::Title)
+            .and_then(|t| t.content());
+        assert_eq!(title, Some("Release notes"));
+        assert_eq!(first.event.tags.hashtags().collect::<Vec<_>>(), ["work"]);
+        let stored = manager.notes.read().await[&id].metadata.clone();
+        assert_eq!(stored[NADDR_KEY], first.naddr);
+        let coordinate = Nip19Coordinate::from_bech32(&first.naddr).unwrap();
+        assert_eq!(coordinate.identifier, id);
+
+        let second = manager.publish(request(false), &transport).await.unwrap();
+        assert_eq!(second.event.tags.identifier(), Some(id.as_str()));
+        assert_eq!(second.naddr, first.naddr);
+        let published_at = |event: &NostrEvent| {
+            event
+                .tags
+                .find(TagKind::PublishedAt)
+                .and_then(|t| t.content())
+                .map(str::to_string)
+        };
+        assert_eq!(published_at(&second.event), published_at(&first.event));
+        assert_eq!(transport.published().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_delete_previews_then_deletes_with_backup() {
+        let (manager, dir) = manager_with(&[
+            ("scratch: try this", &["scratch"]),
+            ("Scratch idea", &["Scratch"]),
+            ("keep me", &["scratch"]),
+            ("unrelated", &[]),
+        ])
+        .await;
+        let request = DeleteNotesRequest {
+            tag: Some("scratch".to_string()),
+            created_before: None,
+            content_regex: Some("(?i)^scratch".to_string()),
+            confirm: None,
+        };
+        let filter = NoteFilter::from_request(&request).unwrap();
+
+        let (preview, backup) = manager.delete_notes(&filter, false).await.unwrap();
+        assert_eq!(preview.len(), 2);
+        assert!(backup.is_none());
+        assert_eq!(manager.all_notes().await.len(), 4);
+
+        let (deleted, backup) = manager.delete_notes(&filter, true).await.unwrap();
+        assert_eq!(deleted.len(), 2);
+        let backed_up: Vec<Note> =
+            serde_json::from_str(&fs::read_to_string(backup.unwrap()).unwrap()).unwrap();
+        assert_eq!(backed_up.len(), 2);
+        let reloaded = NotesManager::new(manager.storage_path.clone());
+        let mut left: Vec<String> = reloaded
+            .all_notes()
+            .await
+            .into_iter()
+            .map(|n| n.content)
+            .collect();
+        left.sort();
+        assert_eq!(left, ["keep me", "unrelated"]);
+        assert!(dir.path().join(BACKUP_DIR).is_dir());
+
+        let no_filters = DeleteNotesRequest {
+            tag: None,
+            created_before: None,
+            content_regex: None,
+            confirm: Some(true),
+        };
+        assert!(NoteFilter::from_request(&no_filters).is_err());
+        let bad_date = DeleteNotesRequest {
+            created_before: Some("last week".to_string()),
+            ..no_filters
+        };
+        assert!(NoteFilter::from_request(&bad_date).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_delete_reports_the_note_it_keeps() {
+        let (manager, _dir) = manager_with(&[("Groceries\nmilk, eggs", &[])]).await;
+        let id = manager.all_notes().await[0].id.clone();
+
+        let preview = manager.delete_note(&id, true).await.unwrap().unwrap();
+        assert_eq!(first_line(&preview.content), "Groceries");
+        assert_eq!(manager.all_notes().await.len(), 1);
+
+        let deleted = manager.delete_note(&id, false).await.unwrap().unwrap();
+        assert_eq!(deleted.id, preview.id);
+        assert!(manager.all_notes().await.is_empty());
+        assert!(manager.delete_note(&id, true).await.unwrap().is_none());
+    }
+
+    /// Run with `cargo test --release -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_fuzzy_search_on_thousands_of_notes() {
+        let words = [
+            "deploy", "release", "meeting", "grocery", "invoice", "travel", "budget",
+        ];
+        let contents: Vec<String> = (0..5000)
+            .map(|i| {
+                format!(
+                    "Note {} about the {} and the {} plan for week {}",
+                    i,
+                    words[i % words.len()],
+                    words[(i / 7) % words.len()],
+                    i % 52
+                )
+            })
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let manager =
+            NotesManager::new(dir.path().join("notes.json").to_string_lossy().into_owned());
+        {
+            let mut notes = manager.notes.write().await;
+            for content in contents {
+                let now = chrono::Utc::now();
+                let id = Uuid::new_v4().to_string();
+                notes.insert(
+                    id.clone(),
+                    Note {
+                        id,
+                        content,
+                        tags: Vec::new(),
+                        created_at: now,
+                        updated_at: now,
+                        metadata: HashMap::new(),
+                    },
+                );
+            }
+        }
+
+        let started = Instant::now();
+        let found = manager
+            .search_notes(search("budgte meting", None, true))
+            .await
+            .unwrap();
+        let elapsed = started.elapsed();
+        println!(
+            "fuzzy search over 5000 notes: {:?}, {} hits",
+            elapsed,
+            found.len()
+        );
+        assert!(!found.is_empty());
+        assert!(elapsed.as_millis() < 100, "took {:?}", elapsed);
     }
 }
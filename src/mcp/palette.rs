@@ -0,0 +1,219 @@
+//! Admin commands in DMs (`!status`, `!agents`, `!stop`, `!help`) that the server answers itself,
+//! without spending model tokens (`NPARROT_COMMAND_PREFIX`)
+//!
+//! Off unless a prefix is configured. The `Chat` inbox checks each incoming message after the
+//! interrupt pattern: a message starting with the prefix is answered with a reply to the sender
+//! and never handed to `wait`. `NPARROT_COMMANDS` adds mappings (`name=action`) on top of the four
+//! defaults, replacing a default of the same name; an action is `status`, `agents`, `stop`,
+//! `help` or `reply:<text>` for a fixed answer.
+
+use futures::future::BoxFuture;
+use std::sync::RwLock;
+
+/// What a command does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// A short summary of the server: inbox, deliveries, agents
+    Status,
+    /// The running agents, in the servers that have any
+    Agents,
+    /// Raises the interrupt, stopping the running task and agents
+    Stop,
+    /// Lists the commands
+    Help,
+    /// Answers with fixed text
+    Reply(String),
+}
+
+impl Action {
+    fn describe(&self) -> &str {
+        match self {
+            Action::Status => "status summary",
+            Action::Agents => "running agents",
+            Action::Stop => "stop everything that is running",
+            Action::Help => "this list",
+            Action::Reply(_) => "fixed answer",
+        }
+    }
+}
+
+/// A command name and its action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub name: String,
+    pub action: Action,
+}
+
+/// Parses a `name=action` mapping, for the command line
+pub fn parse_mapping(value: &str) -> Result<Mapping, String> {
+    let (name, action) = value
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not name=action", value))?;
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("invalid command name in '{}'", value));
+    }
+    let action = match action.trim() {
+        "status" => Action::Status,
+        "agents" => Action::Agents,
+        "stop" => Action::Stop,
+        "help" => Action::Help,
+        other => match other.strip_prefix("reply:") {
+            Some(text) if !text.trim().is_empty() => Action::Reply(text.trim().to_string()),
+            _ => {
+                return Err(format!(
+                    "unknown action '{}' (status, agents, stop, help or reply:<text>)",
+                    other
+                ))
+            }
+        },
+    };
+    Ok(Mapping { name, action })
+}
+
+/// What an incoming command asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invocation {
+    Run(Action),
+    /// A prefixed name nothing is mapped to
+    Unknown(String),
+}
+
+/// The configured prefix and commands
+#[derive(Debug, Clone)]
+pub struct Palette {
+    prefix: String,
+    mappings: Vec<Mapping>,
+}
+
+impl Palette {
+    /// The default commands plus `mappings`, which replace defaults of the same name
+    pub fn new(prefix: String, mappings: Vec<Mapping>) -> Self {
+        let mut all: Vec<Mapping> = [
+            ("status", Action::Status),
+            ("agents", Action::Agents),
+            ("stop", Action::Stop),
+            ("help", Action::Help),
+        ]
+        .into_iter()
+        .map(|(name, action)| Mapping {
+            name: name.to_string(),
+            action,
+        })
+        .collect();
+        for mapping in mappings {
+            all.retain(|existing| existing.name != mapping.name);
+            all.push(mapping);
+        }
+        Self {
+            prefix,
+            mappings: all,
+        }
+    }
+
+    /// The command `content` invokes, `None` if it isn't one (the prefix followed by a name)
+    pub fn parse(&self, content: &str) -> Option<Invocation> {
+        let rest = content.trim().strip_prefix(&self.prefix)?;
+        let name = rest.split_whitespace().next()?;
+        if !rest.starts_with(name) {
+            return None;
+        }
+        let name = name.to_lowercase();
+        Some(
+            match self.mappings.iter().find(|mapping| mapping.name == name) {
+                Some(mapping) => Invocation::Run(mapping.action.clone()),
+                None => Invocation::Unknown(name),
+            },
+        )
+    }
+
+    pub fn unknown(&self, name: &str) -> String {
+        format!(
+            "Unknown command {}{}; {}help lists the commands",
+            self.prefix, name, self.prefix
+        )
+    }
+
+    pub fn help(&self) -> String {
+        let mut help = String::from("Commands:");
+        for mapping in &self.mappings {
+            help.push_str(&format!(
+                "\n{}{} - {}",
+                self.prefix,
+                mapping.name,
+                mapping.action.describe()
+            ));
+        }
+        help
+    }
+}
+
+/// The agents `!agents` lists, for the servers that run agents
+pub trait AgentRoster: std::fmt::Debug + Send + Sync {
+    /// One line per agent
+    fn agent_lines(&self) -> BoxFuture<'_, Vec<String>>;
+}
+
+lazy_static::lazy_static! {
+    static ref PALETTE: RwLock<Option<Palette>> = RwLock::new(None);
+}
+
+/// Sets the palette of the servers started from now on; `None` turns commands off
+pub fn set_palette(palette: Option<Palette>) {
+    if let Ok(mut guard) = PALETTE.write() {
+        *guard = palette;
+    }
+}
+
+pub fn palette() -> Option<Palette> {
+    PALETTE.read().ok().and_then(|guard| guard.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!(
+            parse_mapping("Ping=status").unwrap(),
+            Mapping {
+                name: "ping".to_string(),
+                action: Action::Status
+            }
+        );
+        assert_eq!(
+            parse_mapping("docs=reply: https://example.com/docs")
+                .unwrap()
+                .action,
+            Action::Reply("https://example.com/docs".to_string())
+        );
+        assert!(parse_mapping("docs").is_err());
+        assert!(parse_mapping("docs=reply:").is_err());
+        assert!(parse_mapping("docs=deploy").is_err());
+    }
+
+    #[test]
+    fn test_palette_parses_commands() {
+        let palette = Palette::new(
+            "!".to_string(),
+            vec![parse_mapping("help=reply:Ask away").unwrap()],
+        );
+        assert_eq!(
+            palette.parse("!STATUS please"),
+            Some(Invocation::Run(Action::Status))
+        );
+        assert_eq!(
+            palette.parse(" !help"),
+            Some(Invocation::Run(Action::Reply("Ask away".to_string())))
+        );
+        assert_eq!(
+            palette.parse("!deploy"),
+            Some(Invocation::Unknown("deploy".to_string()))
+        );
+        assert_eq!(palette.parse("hello!"), None);
+        assert_eq!(palette.parse("! status"), None);
+        assert_eq!(palette.parse("!"), None);
+        assert!(palette.help().contains("\n!agents - running agents"));
+    }
+}
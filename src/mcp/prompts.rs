@@ -0,0 +1,356 @@
+//! Canned workflows exposed through the MCP prompts capability
+//!
+//! Clients fetch a prompt such as `handle_user_message` with the user's text as an argument
+//! and get back the wait → progress → work → send loop spelled out with the real tool names,
+//! instead of relying on the model to honour the server instructions.
+
+use rmcp::model::{
+    GetPromptRequestParam, GetPromptResult, JsonObject, ListPromptsResult, Prompt, PromptArgument,
+    PromptMessage, PromptMessageRole,
+};
+use rmcp::Error as RmcpError;
+
+pub struct PromptArg {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub required: bool,
+}
+
+pub struct WorkflowPrompt {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub arguments: &'static [PromptArg],
+    render: fn(&JsonObject) -> String,
+}
+
+const MESSAGE_ARG: PromptArg = PromptArg {
+    name: "message",
+    description: "The user's message, as returned by the 'wait' tool",
+    required: true,
+};
+
+/// Prompts offered by `CombinedServer`
+pub const COMBINED: &[WorkflowPrompt] = &[
+    WorkflowPrompt {
+        name: "handle_user_message",
+        description: "Answer one user message: progress, do the work, send the result",
+        arguments: &[MESSAGE_ARG],
+        render: handle_user_message_combined,
+    },
+    WorkflowPrompt {
+        name: "run_dev_task",
+        description: "Run a development task through Goose and report back to the user",
+        arguments: &[
+            PromptArg {
+                name: "task",
+                description: "What Goose should do",
+                required: true,
+            },
+            PromptArg {
+                name: "max_turns",
+                description: "Optional cap on Goose turns",
+                required: false,
+            },
+        ],
+        render: run_dev_task,
+    },
+    WorkflowPrompt {
+        name: "daily_summary",
+        description: "Send the user a summary of Goose sessions, processes and message delivery",
+        arguments: &[PromptArg {
+            name: "focus",
+            description: "Optional topic the summary should concentrate on",
+            required: false,
+        }],
+        render: daily_summary_combined,
+    },
+];
+
+/// Prompts offered by `EnhancedMcpServer`
+pub const ENHANCED: &[WorkflowPrompt] = &[
+    WorkflowPrompt {
+        name: "handle_user_message",
+        description: "Answer one user message: progress, do the work, send the result",
+        arguments: &[MESSAGE_ARG],
+        render: handle_user_message_enhanced,
+    },
+    WorkflowPrompt {
+        name: "daily_summary",
+        description: "Send the user a digest of upcoming events and recently updated notes",
+        arguments: &[PromptArg {
+            name: "focus",
+            description: "Optional tag or topic the summary should concentrate on",
+            required: false,
+        }],
+        render: daily_summary_enhanced,
+    },
+];
+
+pub fn list(prompts: &[WorkflowPrompt]) -> ListPromptsResult {
+    ListPromptsResult {
+        prompts: prompts
+            .iter()
+            .map(|prompt| {
+                Prompt::new(
+                    prompt.name,
+                    Some(prompt.description),
+                    Some(
+                        prompt
+                            .arguments
+                            .iter()
+                            .map(|arg| PromptArgument {
+                                name: arg.name.to_string(),
+                                description: Some(arg.description.to_string()),
+                                required: Some(arg.required),
+                            })
+                            .collect(),
+                    ),
+                )
+            })
+            .collect(),
+        next_cursor: None,
+    }
+}
+
+pub fn get(
+    prompts: &[WorkflowPrompt],
+    GetPromptRequestParam { name, arguments }: GetPromptRequestParam,
+) -> Result<GetPromptResult, RmcpError> {
+    let prompt = prompts
+        .iter()
+        .find(|prompt| prompt.name == name)
+        .ok_or_else(|| RmcpError::invalid_params(format!("Unknown prompt '{}'", name), None))?;
+    let arguments = arguments.unwrap_or_default();
+    for arg in prompt.arguments.iter().filter(|arg| arg.required) {
+        if argument(&arguments, arg.name).is_none() {
+            return Err(RmcpError::invalid_params(
+                format!("Prompt '{}' requires the '{}' argument", name, arg.name),
+                None,
+            ));
+        }
+    }
+
+    Ok(GetPromptResult {
+        description: Some(prompt.description.to_string()),
+        messages: vec![PromptMessage::new_text(
+            PromptMessageRole::User,
+            (prompt.render)(&arguments),
+        )],
+    })
+}
+
+/// Argument value as text; clients may send numbers or booleans unquoted
+fn argument(arguments: &JsonObject, name: &str) -> Option<String> {
+    match arguments.get(name)? {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) if s.trim().is_empty() => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+/// Tool call example with the arguments escaped as JSON
+fn call(tool: &str, arguments: serde_json::Value) -> String {
+    format!(
+        "{{\"tool\": \"{}\", \"arguments\": {}}}",
+        tool,
+        serde_json::to_string(&arguments).unwrap_or_default()
+    )
+}
+
+fn quoted(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("> {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn user_message_loop(message: &str, work: &str) -> String {
+    format!(
+        "The user sent this message over Nostr:\n\n{}\n\n\
+         Handle it with exactly this sequence of tool calls:\n\
+         1. Acknowledge right away: {}\n\
+         2. {}\n\
+         3. Reply with the result: {}\n\
+         4. Call `wait` for the next message and start again from step 1.\n\n\
+         The user only sees `progress` and `send` messages, so never finish without `send`.",
+        quoted(message),
+        call(
+            "progress",
+            serde_json::json!({"message": "On it, working on your request..."})
+        ),
+        work,
+        call("send", serde_json::json!({"message": "<your answer>"})),
+    )
+}
+
+fn handle_user_message_combined(arguments: &JsonObject) -> String {
+    let message = argument(arguments, "message").unwrap_or_default();
+    user_message_loop(
+        &message,
+        "Do the work. For coding or system tasks call `checksessions`, then `runtask` with \
+         clear instructions, then `killsessions`; use `searxng_web_search` for lookups. Send \
+         short `progress` updates for anything that takes longer than a minute.",
+    )
+}
+
+fn handle_user_message_enhanced(arguments: &JsonObject) -> String {
+    let message = argument(arguments, "message").unwrap_or_default();
+    user_message_loop(
+        &message,
+        "Do the work with the note tools (`addnote`, `listnotes`, `searchnotes`, `deletenote`) \
+         and event tools (`addevent`, `listevents`, `searchevents`, `deleteevent`) as needed.",
+    )
+}
+
+fn run_dev_task(arguments: &JsonObject) -> String {
+    let task = argument(arguments, "task").unwrap_or_default();
+    let mut runtask = serde_json::json!({ "instructions": task });
+    if let Some(max_turns) = argument(arguments, "max_turns").and_then(|v| v.parse::<u32>().ok()) {
+        runtask["max_turns"] = max_turns.into();
+    }
+
+    format!(
+        "Run this development task through Goose:\n\n{}\n\n\
+         Use exactly this sequence of tool calls:\n\
+         1. Tell the user you started: {}\n\
+         2. Call `checksessions`; if a session is active, call `killsessions` first.\n\
+         3. Run the task once: {}\n\
+         4. Call `killsessions` when `runtask` returns, even if it failed.\n\
+         5. Send a short summary of what changed or why it failed: {}\n\
+         6. Call `wait` for the next message.\n\n\
+         Never call `runtask` twice for the same task.",
+        quoted(&task),
+        call(
+            "progress",
+            serde_json::json!({"message": "Starting the task in Goose..."})
+        ),
+        call("runtask", runtask),
+        call("send", serde_json::json!({"message": "<summary>"})),
+    )
+}
+
+fn focus_line(arguments: &JsonObject) -> String {
+    match argument(arguments, "focus") {
+        Some(focus) => format!("Concentrate on: {}\n\n", focus),
+        None => String::new(),
+    }
+}
+
+fn daily_summary_combined(arguments: &JsonObject) -> String {
+    format!(
+        "Prepare a daily status summary for the user.\n\n{}\
+         Use exactly this sequence of tool calls:\n\
+         1. {}\n\
+         2. Gather state with `listsessions`, `list_processes` and `delivery_status`.\n\
+         3. Send one concise summary covering sessions, running processes and any failed \
+         deliveries: {}\n\
+         4. Call `wait` for the next message.",
+        focus_line(arguments),
+        call(
+            "progress",
+            serde_json::json!({"message": "Putting together today's summary..."})
+        ),
+        call("send", serde_json::json!({"message": "<summary>"})),
+    )
+}
+
+fn daily_summary_enhanced(arguments: &JsonObject) -> String {
+    format!(
+        "Prepare a daily digest for the user.\n\n{}\
+         Use exactly this sequence of tool calls:\n\
+         1. {}\n\
+         2. {}\n\
+         3. {}\n\
+         4. Send one concise digest of today's and tomorrow's events and the notes that \
+         changed recently: {}\n\
+         5. Call `wait` for the next message.",
+        focus_line(arguments),
+        call(
+            "progress",
+            serde_json::json!({"message": "Putting together today's digest..."})
+        ),
+        call("listevents", serde_json::json!({"sort": "start_time"})),
+        call(
+            "listnotes",
+            serde_json::json!({"sort": "updated", "limit": 20})
+        ),
+        call("send", serde_json::json!({"message": "<digest>"})),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(name: &str, arguments: serde_json::Value) -> GetPromptRequestParam {
+        GetPromptRequestParam {
+            name: name.to_string(),
+            arguments: Some(rmcp::model::object(arguments)),
+        }
+    }
+
+    fn text(result: &GetPromptResult) -> &str {
+        match &result.messages[0].content {
+            rmcp::model::PromptMessageContent::Text { text } => text,
+            other => panic!("unexpected content {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_listing_advertises_arguments() {
+        let listed = list(COMBINED);
+        let names: Vec<_> = listed.prompts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["handle_user_message", "run_dev_task", "daily_summary"]
+        );
+        let args = listed.prompts[0].arguments.as_ref().unwrap();
+        assert_eq!(args[0].name, "message");
+        assert_eq!(args[0].required, Some(true));
+    }
+
+    #[test]
+    fn test_render_includes_message_and_tool_names() {
+        let result = get(
+            COMBINED,
+            request(
+                "run_dev_task",
+                serde_json::json!({"task": "fix \"quoted\" bug", "max_turns": 5}),
+            ),
+        )
+        .unwrap();
+        let rendered = text(&result);
+        assert!(rendered.contains("> fix \"quoted\" bug"));
+        assert!(rendered.contains(
+            r#"{"tool": "runtask", "arguments": {"instructions":"fix \"quoted\" bug","max_turns":5}}"#
+        ));
+        assert!(rendered.contains("`killsessions`"));
+
+        let result = get(
+            ENHANCED,
+            request("handle_user_message", serde_json::json!({"message": "hi"})),
+        )
+        .unwrap();
+        assert!(text(&result).contains("`listnotes`"));
+    }
+
+    #[test]
+    fn test_missing_or_unknown() {
+        assert!(get(
+            COMBINED,
+            request("handle_user_message", serde_json::json!({}))
+        )
+        .is_err());
+        assert!(get(
+            COMBINED,
+            request("handle_user_message", serde_json::json!({"message": "  "}))
+        )
+        .is_err());
+        assert!(get(
+            ENHANCED,
+            request("run_dev_task", serde_json::json!({"task": "x"}))
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Text matching for `searchnotes`: case- and accent-insensitive, optionally fuzzy
+
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Fuzzy matches scoring below this are dropped
+pub const FUZZY_THRESHOLD: f64 = 0.7;
+
+/// Lowercases `text` and strips accents, so "Déploy" and "deploy" compare equal
+pub fn normalize(text: &str) -> String {
+    text.nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// A normalized query, ready to be matched against many texts
+#[derive(Debug)]
+pub struct Query {
+    text: String,
+    words: Vec<String>,
+}
+
+impl Query {
+    pub fn new(query: &str) -> Self {
+        let text = normalize(query);
+        let words = words(&text).map(str::to_string).collect();
+        Self { text, words }
+    }
+
+    /// Whether the normalized `text` contains the query
+    pub fn matches(&self, text: &str) -> bool {
+        normalize(text).contains(&self.text)
+    }
+
+    /// How well `text` matches, from 0 to 1; an exact (normalized) substring scores 1.
+    ///
+    /// Otherwise every query word is compared with each word of `text`, and with that word's
+    /// prefix of the same length so "deplo" finds "deployment"; the score is the average of the
+    /// best similarity for each query word.
+    pub fn score(&self, text: &str) -> f64 {
+        let text = normalize(text);
+        if text.contains(&self.text) {
+            return 1.0;
+        }
+        if self.words.is_empty() {
+            return 0.0;
+        }
+
+        let candidates: Vec<&str> = words(&text).collect();
+        let total: f64 = self
+            .words
+            .iter()
+            .map(|word| {
+                candidates
+                    .iter()
+                    .map(|candidate| similarity(word, candidate))
+                    .fold(0.0, f64::max)
+            })
+            .sum();
+        total / self.words.len() as f64
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+fn similarity(word: &str, candidate: &str) -> f64 {
+    let whole = strsim::normalized_damerau_levenshtein(word, candidate);
+    let prefix_len = word.chars().count();
+    if candidate.chars().count() <= prefix_len {
+        return whole;
+    }
+    let prefix: String = candidate.chars().take(prefix_len).collect();
+    whole.max(strsim::normalized_damerau_levenshtein(word, &prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_ignores_case_and_accents() {
+        assert_eq!(normalize("Déploymént Checklist"), "deployment checklist");
+        assert!(Query::new("deploy").matches("Deployment checklist"));
+        assert!(Query::new("CAFE").matches("Meet at the café"));
+        assert!(!Query::new("deploy").matches("Release notes"));
+    }
+
+    #[test]
+    fn test_fuzzy_score_tolerates_typos() {
+        let query = Query::new("deplyo checklst");
+        assert!(query.score("Deployment checklist for Friday") >= FUZZY_THRESHOLD);
+        assert!(query.score("Grocery list") < FUZZY_THRESHOLD);
+        assert_eq!(Query::new("checklist").score("Deployment checklist"), 1.0);
+        assert_eq!(Query::new("").score("anything"), 1.0);
+    }
+}
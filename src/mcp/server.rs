@@ -1,45 +1,154 @@
 use super::chat::Chat;
-use super::events::EventsManager;
-use super::notes::NotesManager;
-use super::progress_enforcer::ProgressTracker;
+use super::context::ContextStore;
+use super::ics;
+use super::instructions::{
+    self,
+    InstructionProfile::{Compact, Full, Minimal},
+    Rule,
+};
+use super::memory_sync;
+use super::notebook::{self, Notebook};
+use super::notes::{first_line, NoteFilter};
+use super::progress_enforcer::WORKFLOW_ENFORCEMENT;
+use super::prompts;
+use super::server_common::{
+    reply, reply_and_result, reply_error, with_progress, ServerInfoBuilder,
+};
+use super::tags::{self, Retag};
 use super::types::*;
 use super::validation::{extract_error_context, sanitize_json_parameters};
+use super::watchdog;
+use crate::at_rest::{self, Vault};
+use crate::audit::{self, AuditLogRequest};
+use crate::catalog::{self, Key};
+use crate::dry_run;
+use crate::nostr_mcp::client::NostrMemoryClient;
+use crate::progress_channels::ProgressChannels;
+use crate::progress_history::{self, ProgressHistoryRequest};
+use crate::relays::RelayInfoRequest;
+use crate::response_tracker::DeliveryStatusRequest;
+use crate::selftest::SelftestRequest;
+use crate::timezone::{self, DISPLAY_FORMAT};
+use crate::transcript;
+use crate::transport::SharedTransport;
 use nostr_sdk::prelude::*;
 use rmcp::{
+    handler::server::tool::ToolCallContext,
     model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+        CallToolRequestParam, CallToolResult, Content, GetPromptRequestParam, GetPromptResult,
+        ListPromptsResult, ListToolsResult, PaginatedRequestParam, ServerInfo,
     },
-    tool, Error as RmcpError, ServerHandler,
+    service::RequestContext,
+    tool, Error as RmcpError, RoleServer, ServerHandler,
 };
 use std::sync::Arc;
 
+/// What `get_info` tells the model, by instruction profile
+pub const INSTRUCTIONS: &[Rule] = &[
+    Rule::new(
+        Minimal,
+        "This enhanced server provides Nostr chat with the user plus note and event management.",
+    ),
+    Rule::new(
+        Minimal,
+        "Every user message is handled as: wait -> progress -> [note/event operations] -> send. The user only sees 'progress' and 'send' messages, so never end a turn without 'send'.",
+    ),
+    Rule::new(
+        Full,
+        "The 'handle_user_message' and 'daily_summary' prompts spell out these workflows step by step with the exact tool calls.",
+    ),
+    Rule::new(Full, WORKFLOW_ENFORCEMENT),
+    Rule::new(
+        Compact,
+        "Tool arguments must be a single JSON object with double quotes and nothing after the closing brace, e.g. {\"message\": \"hello\"}. On parameter errors, retry with simpler JSON. A failed tool call also returns JSON with 'code' and 'retryable': only retry when 'retryable' is true.",
+    ),
+    Rule::new(
+        Full,
+        "Available capabilities: Chat (send, progress, wait), Notes (addnote, listnotes, searchnotes, deletenote, deletenotes, publishnote, syncnotes_to_memory, import_memories_as_notes), Tags (managetags), Events (addevent, listevents, searchevents, deleteevent, exportevents_ics, importevents_ics), Storage (rotate_data_key).",
+    ),
+];
+
 #[derive(Debug, Clone)]
 pub struct EnhancedMcpServer {
     chat: Chat,
-    notes: Arc<NotesManager>,
-    events: Arc<EventsManager>,
-    progress_tracker: Arc<ProgressTracker>,
+    notebook: Notebook,
+    /// Signs and publishes articles with the main identity
+    publisher: SharedTransport,
+    /// The same relay-backed store the memory server uses, for mirroring notes
+    memory: NostrMemoryClient,
+    /// Where exported calendar files are written
+    data_dir: String,
 }
 
 #[tool(tool_box)]
 impl EnhancedMcpServer {
     pub fn new(
         client: Client,
-        progress_client: Option<Client>,
+        progress_clients: ProgressChannels<Client>,
+        keys: Keys,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
         data_dir: Option<String>,
     ) -> Self {
         let data_dir = data_dir.unwrap_or_else(|| "data".to_string());
+        let memory = NostrMemoryClient::new(Arc::new(client.clone()), keys, our_pubkey);
+
+        Self {
+            publisher: Arc::new(client.clone()),
+            chat: Chat::new(client, progress_clients, our_pubkey, target_pubkey)
+                .with_summaries(Arc::new(memory.clone()))
+                .with_context(Arc::new(ContextStore::in_memory()))
+                .with_watchdog(watchdog::stall_after()),
+            memory,
+            notebook: Notebook::open(&data_dir),
+            data_dir,
+        }
+    }
 
+    /// Same as `new`, for any transport (e.g. the in-memory relays in tests)
+    #[cfg(test)]
+    pub fn with_transport(
+        transport: SharedTransport,
+        keys: Keys,
+        our_pubkey: PublicKey,
+        target_pubkey: PublicKey,
+        data_dir: String,
+    ) -> Self {
+        let memory = NostrMemoryClient::new(transport.clone(), keys, our_pubkey);
         Self {
-            chat: Chat::new(client, progress_client, our_pubkey, target_pubkey),
-            notes: Arc::new(NotesManager::new(format!("{}/notes.json", data_dir))),
-            events: Arc::new(EventsManager::new(format!("{}/events.json", data_dir))),
-            progress_tracker: Arc::new(ProgressTracker::new()),
+            publisher: transport.clone(),
+            chat: Chat::with_transport(
+                transport,
+                ProgressChannels::default(),
+                our_pubkey,
+                target_pubkey,
+            )
+            .with_summaries(Arc::new(memory.clone()))
+            .with_context(Arc::new(ContextStore::in_memory()))
+            .with_interrupt(crate::interrupt::Interrupt::new()),
+            memory,
+            notebook: Notebook::open(&data_dir),
+            data_dir,
         }
     }
 
+    /// Sets the default NIP-40 expiration for progress messages; main-channel messages stay permanent
+    pub fn with_progress_expiration(mut self, expire_after_secs: Option<u64>) -> Self {
+        self.chat = self.chat.with_progress_expiration(expire_after_secs);
+        self
+    }
+
+    /// Keeps the context tools' values in `context` instead of in memory only
+    pub fn with_context(mut self, context: Arc<ContextStore>) -> Self {
+        self.chat = self.chat.with_context(context);
+        self
+    }
+
+    /// The chat the schedule delivers through
+    pub fn chat(&self) -> &Chat {
+        &self.chat
+    }
+
     /// Helper function to safely parse JSON parameters with error recovery
     #[allow(dead_code)] // Future use for JSON parameter recovery
     fn safe_parse_params<T>(&self, params_str: &str) -> Result<T, RmcpError>
@@ -88,6 +197,36 @@ impl EnhancedMcpServer {
         self.chat.send(request).await
     }
 
+    #[tool(
+        description = "Send several messages to the user in one call, e.g. a summary followed by code blocks; they arrive in the given order"
+    )]
+    async fn send_batch(
+        &self,
+        #[tool(aggr)] request: SendBatchRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.send_batch(request).await
+    }
+
+    #[tool(
+        description = "Send the user a file (e.g. a diagram or a log) that doesn't fit in a message: uploads it to the media server and sends its link, with an optional message"
+    )]
+    async fn upload_and_send_file(
+        &self,
+        #[tool(aggr)] request: UploadFileRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.upload_and_send_file(request).await
+    }
+
+    #[tool(
+        description = "Ask the user for a payment: gets a lightning invoice for the amount from the configured lightning address and sends it to the user"
+    )]
+    async fn request_zap(
+        &self,
+        #[tool(aggr)] request: RequestZapRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.request_zap(request).await
+    }
+
     #[tool(description = "Send a progress/debug message to the user via the progress identity")]
     async fn progress(
         &self,
@@ -96,9 +235,124 @@ impl EnhancedMcpServer {
         self.chat.progress(request).await
     }
 
-    #[tool(description = "Listen and wait for the user's next message")]
-    async fn wait(&self) -> Result<CallToolResult, RmcpError> {
-        self.chat.wait().await
+    #[tool(
+        description = "Listen and wait for the user's next message, or for the next one of the sender given in `from`"
+    )]
+    async fn wait(&self, #[tool(aggr)] request: WaitRequest) -> Result<CallToolResult, RmcpError> {
+        self.chat.wait(request).await
+    }
+
+    #[tool(
+        description = "Park a small piece of session state, e.g. current_repo=nmcpparrot, optionally expiring after ttl_secs; listed in every wait result. Not the Nostr-backed memory: nothing is published and values are limited to 2 KB"
+    )]
+    async fn set_context(
+        &self,
+        #[tool(aggr)] request: SetContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.set_context(request).await
+    }
+
+    #[tool(
+        description = "Read the session state stored with set_context (one key or all); separate from the Nostr-backed memory"
+    )]
+    async fn get_context(
+        &self,
+        #[tool(aggr)] request: GetContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.get_context(request).await
+    }
+
+    #[tool(
+        description = "Remove one key, or all of them, from the session state stored with set_context; does not touch the Nostr-backed memory"
+    )]
+    async fn clear_context(
+        &self,
+        #[tool(aggr)] request: ClearContextRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.clear_context(request).await
+    }
+
+    #[tool(
+        description = "Schedule a message to the user for later, e.g. a reminder; it is sent even if you are busy or the server restarts in between"
+    )]
+    async fn send_later(
+        &self,
+        #[tool(aggr)] request: SendLaterRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.send_later(request).await
+    }
+
+    #[tool(description = "List the messages scheduled with send_later that are not sent yet")]
+    async fn list_scheduled(&self) -> Result<CallToolResult, RmcpError> {
+        self.chat.list_scheduled().await
+    }
+
+    #[tool(description = "Cancel a message scheduled with send_later before it is sent")]
+    async fn cancel_scheduled(
+        &self,
+        #[tool(aggr)] request: CancelScheduledRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.cancel_scheduled(request).await
+    }
+
+    #[tool(
+        description = "Fetch the recent conversation with the user as a transcript (← user, → you), to summarize when earlier decisions have scrolled out of context; pass a summary you wrote to store it as a memory"
+    )]
+    async fn summarize_conversation(
+        &self,
+        #[tool(aggr)] request: SummarizeConversationRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.summarize_conversation(request).await
+    }
+
+    #[tool(
+        description = "Show the latest tool calls with the shape and a keyed hash of their arguments, duration and outcome, for debugging"
+    )]
+    async fn get_audit_log(
+        &self,
+        #[tool(aggr)] AuditLogRequest { limit }: AuditLogRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        audit::tool_result(limit)
+    }
+
+    #[tool(
+        description = "Show how many sent messages relays have acknowledged, which are still pending and which failed"
+    )]
+    async fn delivery_status(
+        &self,
+        #[tool(aggr)] request: DeliveryStatusRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.delivery_status(request).await
+    }
+
+    #[tool(
+        description = "Search the progress messages sent earlier, e.g. what was being done an hour ago: filter by since (\"2h\" or an ISO 8601 time) and contains, newest `limit` entries oldest first"
+    )]
+    async fn progress_history(
+        &self,
+        #[tool(aggr)] request: ProgressHistoryRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.progress_history(request).await
+    }
+
+    #[tool(
+        description = "Show what each configured relay published about itself (NIP-11 size and subscription limits, auth and payment requirements) whether NIP-42 authentication with it succeeded and how many subscriptions are open or queued on it"
+    )]
+    async fn relay_info(
+        &self,
+        #[tool(aggr)] request: RelayInfoRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.relay_info(request).await
+    }
+
+    #[tool(
+        description = "Check the DM path end to end: send a tagged message to ourselves through the relays and report how each stage went. The user never sees it"
+    )]
+    async fn selftest(
+        &self,
+        #[tool(aggr)] request: SelftestRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.selftest(request).await
     }
 
     #[tool(description = "Add a new note with content, optional tags, and metadata")]
@@ -106,41 +360,7 @@ impl EnhancedMcpServer {
         &self,
         #[tool(aggr)] request: AddNoteRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Adding new note...".to_string(),
-            })
-            .await;
-
-        match self.notes.add_note(request).await {
-            Ok(note) => {
-                let message = format!(
-                    "Note added successfully!\n\nID: {}\nContent: {}\nTags: {}\nCreated: {}",
-                    note.id,
-                    note.content,
-                    note.tags.join(", "),
-                    note.created_at.format("%Y-%m-%d %H:%M UTC")
-                );
-
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Note added with ID: {}",
-                    note.id
-                ))]))
-            }
-            Err(e) => {
-                let error_msg = format!("Failed to add note: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
-            }
-        }
+        self.notebook.addnote(&self.chat, request).await
     }
 
     #[tool(description = "List notes with optional filtering by tag, limit, and sort order")]
@@ -148,57 +368,7 @@ impl EnhancedMcpServer {
         &self,
         #[tool(aggr)] request: ListNotesRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Retrieving notes...".to_string(),
-            })
-            .await;
-
-        match self.notes.list_notes(request).await {
-            Ok(notes) => {
-                let message = if notes.is_empty() {
-                    "📝 No notes found.".to_string()
-                } else {
-                    let notes_text = notes
-                        .iter()
-                        .map(|note| {
-                            format!(
-                                "• **{}** ({})\n  Tags: {}\n  Created: {}\n",
-                                &note.id[..8],
-                                note.content.chars().take(50).collect::<String>()
-                                    + if note.content.len() > 50 { "..." } else { "" },
-                                if note.tags.is_empty() {
-                                    "none".to_string()
-                                } else {
-                                    note.tags.join(", ")
-                                },
-                                note.created_at.format("%Y-%m-%d %H:%M UTC")
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
-
-                    format!("📝 Found {} note(s):\n\n{}", notes.len(), notes_text)
-                };
-
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Listed {} notes",
-                    notes.len()
-                ))]))
-            }
-            Err(e) => {
-                let error_msg = format!("❌ Failed to list notes: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
-            }
-        }
+        self.notebook.listnotes(&self.chat, request).await
     }
 
     #[tool(description = "Search notes by content with optional tag filtering and result limit")]
@@ -206,225 +376,339 @@ impl EnhancedMcpServer {
         &self,
         #[tool(aggr)] request: SearchNotesRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Searching notes for: '{}'...", request.query),
-            })
-            .await;
-
-        match self.notes.search_notes(request).await {
-            Ok(notes) => {
-                let message = if notes.is_empty() {
-                    "🔍 No matching notes found.".to_string()
-                } else {
-                    let notes_text = notes
-                        .iter()
-                        .map(|note| {
-                            format!(
-                                "• **{}**\n  {}\n  Tags: {}\n  Created: {}\n",
-                                &note.id[..8],
-                                note.content,
-                                if note.tags.is_empty() {
-                                    "none".to_string()
-                                } else {
-                                    note.tags.join(", ")
-                                },
-                                note.created_at.format("%Y-%m-%d %H:%M UTC")
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
+        self.notebook.searchnotes(&self.chat, request).await
+    }
 
-                    format!(
-                        "🔍 Found {} matching note(s):\n\n{}",
+    #[tool(description = "Delete a note by its ID")]
+    async fn deletenote(
+        &self,
+        #[tool(aggr)] request: DeleteNoteRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.deletenote(&self.chat, request).await
+    }
+
+    #[tool(
+        description = "Delete every note matching filters (tag, created_before, content_regex) at once; previews unless confirm is true"
+    )]
+    async fn deletenotes(
+        &self,
+        #[tool(aggr)] request: DeleteNotesRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let filter = NoteFilter::from_request(&request)?;
+        // Forced dry runs only ever preview
+        let confirm = request.confirm.unwrap_or(false) && !dry_run::forced();
+        let label = if confirm {
+            "Deleting matching notes..."
+        } else {
+            "Previewing notes to delete..."
+        };
+        match with_progress(
+            &self.chat,
+            label,
+            self.notebook.notes().delete_notes(&filter, confirm),
+        )
+        .await
+        {
+            Ok((notes, backup)) => {
+                let listed: Vec<String> = notes
+                    .iter()
+                    .map(|note| format!("- {}: {}", note.id, first_line(&note.content)))
+                    .collect();
+                let text = match backup {
+                    Some(backup) => {
+                        let deleted = catalog::text(
+                            Key::NotesDeleted,
+                            &[("count", &notes.len().to_string())],
+                        );
+                        reply(&self.chat, deleted).await;
+                        format!(
+                            "Deleted {} note(s), backed up to {}:\n{}",
+                            notes.len(),
+                            backup.display(),
+                            listed.join("\n")
+                        )
+                    }
+                    None if notes.is_empty() => "No notes match these filters".to_string(),
+                    None if dry_run::forced() => dry_run::label(&format!(
+                        "{} note(s) would be deleted\n{}",
+                        notes.len(),
+                        listed.join("\n")
+                    )),
+                    None => format!(
+                        "Preview: {} note(s) would be deleted. Call again with confirm: true to delete them.\n{}",
                         notes.len(),
-                        notes_text
-                    )
+                        listed.join("\n")
+                    ),
                 };
+                Ok(CallToolResult::success(vec![Content::text(text)]))
+            }
+            Err(e) => {
+                reply_error(
+                    &self.chat,
+                    catalog::text(Key::NotesDeleteFailed, &[("error", &e.to_string())]),
+                    e,
+                )
+                .await
+            }
+        }
+    }
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
+    #[tool(
+        description = "Publish a note publicly as a long-form article (NIP-23); publishing it again updates the same article"
+    )]
+    async fn publishnote(
+        &self,
+        #[tool(aggr)] request: PublishNoteRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let dry_run = request.dry_run.unwrap_or(false);
+        let label = if dry_run {
+            format!("Previewing note {} as an article...", request.id)
+        } else {
+            format!("Publishing note {} as an article...", request.id)
+        };
+        match with_progress(
+            &self.chat,
+            label,
+            self.notebook
+                .notes()
+                .publish(request, self.publisher.as_ref()),
+        )
+        .await
+        {
+            Ok(published) if !published.published => {
+                let event = serde_json::to_string_pretty(&published.event)
+                    .map_err(|e| RmcpError::internal_error(e.to_string(), None))?;
                 Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Found {} matching notes",
-                    notes.len()
+                    "Dry run, nothing was published. The article would be:\n{}\nnaddr: {}",
+                    event, published.naddr
                 ))]))
             }
+            Ok(published) => {
+                reply_and_result(
+                    &self.chat,
+                    catalog::text(Key::NotePublished, &[("naddr", &published.naddr)]),
+                    format!(
+                        "Published article {} as {}",
+                        published.event.id, published.naddr
+                    ),
+                )
+                .await
+            }
             Err(e) => {
-                let error_msg = format!("❌ Failed to search notes: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+                let message = catalog::text(Key::NotePublishFailed, &[("error", &e.to_string())]);
+                reply_error(&self.chat, message, e).await
             }
         }
     }
 
-    #[tool(description = "Delete a note by its ID")]
-    async fn deletenote(
+    #[tool(
+        description = "List tags with usage counts across notes and events, or rename/merge tags everywhere at once (supports dry_run)"
+    )]
+    async fn managetags(
         &self,
-        #[tool(aggr)] request: DeleteNoteRequest,
+        #[tool(aggr)] request: ManageTagsRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Deleting note {}...", request.id),
-            })
-            .await;
-
-        match self.notes.delete_note(request).await {
-            Ok(existed) => {
-                let message = if existed {
-                    "🗑️ Note deleted successfully!".to_string()
-                } else {
-                    "❌ Note not found.".to_string()
-                };
-
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-                Ok(CallToolResult::success(vec![Content::text(
-                    if existed {
-                        "Note deleted"
-                    } else {
-                        "Note not found"
-                    }
-                    .to_string(),
-                )]))
-            }
-            Err(e) => {
-                let error_msg = format!("❌ Failed to delete note: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
+        let sources = request.tags.unwrap_or_default();
+        let target = request.target.unwrap_or_default();
+        let dry_run = dry_run::requested(request.dry_run);
+        match request.operation.as_str() {
+            "list" => {
+                let usage = tags::usage(self.notebook.notes(), self.notebook.events()).await;
+                if usage.is_empty() {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        "No tags in use",
+                    )]));
+                }
+                let lines: Vec<String> = usage
+                    .iter()
+                    .map(|tag| {
+                        let mut line = format!(
+                            "• {}: {} ({} notes, {} events)",
+                            tag.name(),
+                            tag.total(),
+                            tag.notes,
+                            tag.events
+                        );
+                        if tag.spellings.len() > 1 {
+                            let spellings: Vec<&str> =
+                                tag.spellings.keys().map(String::as_str).collect();
+                            line.push_str(&format!(" — spelled {}", spellings.join(", ")));
+                        }
+                        line
                     })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+                    .collect();
+                Ok(CallToolResult::success(vec![Content::text(format!(
+                    "🏷️ {} tag(s):\n{}",
+                    usage.len(),
+                    lines.join("\n")
+                ))]))
             }
+            operation @ ("rename" | "merge") => {
+                if operation == "rename" && sources.len() != 1 {
+                    return Err(RmcpError::invalid_params(
+                        "rename takes exactly one tag in 'tags'; use merge for several",
+                        None,
+                    ));
+                }
+                let retag = Retag::new(&sources, &target)
+                    .map_err(|e| RmcpError::invalid_params(e, None))?;
+                let label = format!(
+                    "{} tags {} into '{}'...",
+                    if dry_run { "Previewing" } else { "Rewriting" },
+                    sources.join(", "),
+                    target.trim()
+                );
+                let retagged = tags::retag(
+                    self.notebook.notes(),
+                    self.notebook.events(),
+                    &retag,
+                    dry_run,
+                );
+                match with_progress(&self.chat, label, retagged).await {
+                    Ok(summary) => Ok(CallToolResult::success(vec![Content::text(format!(
+                        "{} {} into '{}': {} note(s) and {} event(s){}",
+                        if operation == "rename" {
+                            "Rename"
+                        } else {
+                            "Merge"
+                        },
+                        sources.join(", "),
+                        target.trim(),
+                        summary.notes,
+                        summary.events,
+                        if dry_run {
+                            " would change (dry run, nothing was changed)"
+                        } else {
+                            " changed"
+                        }
+                    ))])),
+                    Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
+                        "❌ Failed to update tags, nothing was changed: {}",
+                        e
+                    ))])),
+                }
+            }
+            other => Err(RmcpError::invalid_params(
+                format!("Unknown operation '{}': use list, rename or merge", other),
+                None,
+            )),
         }
     }
 
     #[tool(
-        description = "Add a new event with title, description, type, optional times, tags, and metadata"
+        description = "Mirror notes into Nostr memory storage (type 'note'); safe to run repeatedly, deletions are only reported"
     )]
-    async fn addevent(
+    async fn syncnotes_to_memory(
         &self,
-        #[tool(aggr)] request: AddEventRequest,
+        #[tool(aggr)] request: SyncNotesToMemoryRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Adding new event...".to_string(),
-            })
-            .await;
-
-        match self.events.add_event(request).await {
-            Ok(event) => {
-                let time_info = match (event.start_time, event.end_time) {
-                    (Some(start), Some(end)) => format!(
-                        "\nStart: {}\nEnd: {}",
-                        start.format("%Y-%m-%d %H:%M UTC"),
-                        end.format("%Y-%m-%d %H:%M UTC")
-                    ),
-                    (Some(start), None) => {
-                        format!("\nStart: {}", start.format("%Y-%m-%d %H:%M UTC"))
+        match with_progress(
+            &self.chat,
+            "Syncing notes to memory...",
+            memory_sync::sync_notes_to_memory(
+                self.notebook.notes(),
+                &self.memory,
+                request.tag.as_deref(),
+            ),
+        )
+        .await
+        {
+            Ok(report) => {
+                let mut text = format!(
+                    "Synced notes to memory: {} created, {} updated, {} unchanged",
+                    report.created, report.updated, report.unchanged
+                );
+                if !report.memory_deleted.is_empty() {
+                    text.push_str(&format!(
+                        "\n\n⚠️ {} note(s) whose memory was deleted (not recreated):",
+                        report.memory_deleted.len()
+                    ));
+                    for note in &report.memory_deleted {
+                        text.push_str(&format!("\n- {}: {}", note.id, first_line(&note.content)));
                     }
-                    (None, Some(end)) => format!("\nEnd: {}", end.format("%Y-%m-%d %H:%M UTC")),
-                    (None, None) => "".to_string(),
-                };
+                }
+                if !report.note_deleted.is_empty() {
+                    text.push_str(&format!(
+                        "\n\n⚠️ {} memory(ies) whose note was deleted (kept in memory):",
+                        report.note_deleted.len()
+                    ));
+                    for memory in &report.note_deleted {
+                        text.push_str(&format!("\n- {}: {}", memory.id, memory.content.title));
+                    }
+                }
+                Ok(CallToolResult::success(vec![Content::text(text)]))
+            }
+            Err(e) => Ok(e.to_result(format!("❌ Failed to sync notes: {}", e))),
+        }
+    }
 
-                let message = format!(
-                    "📅 Event added successfully!\n\nID: {}\nTitle: {}\nType: {}\nTags: {}\nCreated: {}{}",
-                    event.id,
-                    event.title,
-                    event.event_type,
-                    if event.tags.is_empty() { "none".to_string() } else { event.tags.join(", ") },
-                    event.created_at.format("%Y-%m-%d %H:%M UTC"),
-                    time_info
+    #[tool(
+        description = "Import Nostr memories that have no note yet as notes; safe to run repeatedly, deletions are only reported"
+    )]
+    async fn import_memories_as_notes(
+        &self,
+        #[tool(aggr)] request: ImportMemoriesRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        match with_progress(
+            &self.chat,
+            "Importing memories as notes...",
+            memory_sync::import_memories_as_notes(
+                self.notebook.notes(),
+                &self.memory,
+                request.memory_type.as_deref(),
+            ),
+        )
+        .await
+        {
+            Ok(report) => {
+                let mut text = format!(
+                    "Imported {} memory(ies) as notes, {} already had one",
+                    report.imported.len(),
+                    report.already_linked
                 );
-
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Event added with ID: {}",
-                    event.id
-                ))]))
-            }
-            Err(e) => {
-                let error_msg = format!("❌ Failed to add event: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+                for note in &report.imported {
+                    text.push_str(&format!("\n- {}: {}", note.id, first_line(&note.content)));
+                }
+                if !report.note_deleted.is_empty() {
+                    text.push_str(&format!(
+                        "\n\n⚠️ {} memory(ies) mirrored from notes that were deleted (not re-imported):",
+                        report.note_deleted.len()
+                    ));
+                    for memory in &report.note_deleted {
+                        text.push_str(&format!("\n- {}: {}", memory.id, memory.content.title));
+                    }
+                }
+                if !report.memory_deleted.is_empty() {
+                    text.push_str(&format!(
+                        "\n\n⚠️ {} note(s) whose memory was deleted (notes kept):",
+                        report.memory_deleted.len()
+                    ));
+                    for note in &report.memory_deleted {
+                        text.push_str(&format!("\n- {}: {}", note.id, first_line(&note.content)));
+                    }
+                }
+                Ok(CallToolResult::success(vec![Content::text(text)]))
             }
+            Err(e) => Ok(e.to_result(format!("❌ Failed to import memories: {}", e))),
         }
     }
 
+    #[tool(
+        description = "Add a new event with title, description, type, optional times, tags, and metadata"
+    )]
+    async fn addevent(
+        &self,
+        #[tool(aggr)] request: AddEventRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.notebook.addevent(&self.chat, request).await
+    }
+
     #[tool(description = "List events with optional filtering by type, tag, limit, and sort order")]
     async fn listevents(
         &self,
         #[tool(aggr)] request: ListEventsRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Retrieving events...".to_string(),
-            })
-            .await;
-
-        match self.events.list_events(request).await {
-            Ok(events) => {
-                let message = if events.is_empty() {
-                    "📅 No events found.".to_string()
-                } else {
-                    let events_text = events
-                        .iter()
-                        .map(|event| {
-                            let time_info = match event.start_time {
-                                Some(start) => format!(" | {}", start.format("%m/%d %H:%M")),
-                                None => "".to_string(),
-                            };
-
-                            format!(
-                                "• **{}** - {} ({}){}\n  Tags: {}\n",
-                                &event.id[..8],
-                                event.title,
-                                event.event_type,
-                                time_info,
-                                if event.tags.is_empty() {
-                                    "none".to_string()
-                                } else {
-                                    event.tags.join(", ")
-                                }
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n");
-
-                    format!("📅 Found {} event(s):\n\n{}", events.len(), events_text)
-                };
-
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Listed {} events",
-                    events.len()
-                ))]))
-            }
-            Err(e) => {
-                let error_msg = format!("❌ Failed to list events: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
-            }
-        }
+        self.notebook.listevents(&self.chat, request).await
     }
 
     #[tool(
@@ -434,76 +718,7 @@ impl EnhancedMcpServer {
         &self,
         #[tool(aggr)] request: SearchEventsRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Searching events for: '{}'...", request.query),
-            })
-            .await;
-
-        match self.events.search_events(request).await {
-            Ok(events) => {
-                let message = if events.is_empty() {
-                    "🔍 No matching events found.".to_string()
-                } else {
-                    let events_text = events
-                        .iter()
-                        .map(|event| {
-                            let time_info = match (event.start_time, event.end_time) {
-                                (Some(start), Some(end)) => format!(
-                                    "\n  Time: {} - {}",
-                                    start.format("%Y-%m-%d %H:%M"),
-                                    end.format("%Y-%m-%d %H:%M")
-                                ),
-                                (Some(start), None) => {
-                                    format!("\n  Start: {}", start.format("%Y-%m-%d %H:%M"))
-                                }
-                                (None, Some(end)) => {
-                                    format!("\n  End: {}", end.format("%Y-%m-%d %H:%M"))
-                                }
-                                (None, None) => "".to_string(),
-                            };
-
-                            format!(
-                                "• **{}** - {} ({})\n  Tags: {}{}",
-                                &event.id[..8],
-                                event.title,
-                                event.event_type,
-                                if event.tags.is_empty() {
-                                    "none".to_string()
-                                } else {
-                                    event.tags.join(", ")
-                                },
-                                time_info
-                            )
-                        })
-                        .collect::<Vec<_>>()
-                        .join("\n\n");
-
-                    format!(
-                        "🔍 Found {} matching event(s):\n\n{}",
-                        events.len(),
-                        events_text
-                    )
-                };
-
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Found {} matching events",
-                    events.len()
-                ))]))
-            }
-            Err(e) => {
-                let error_msg = format!("❌ Failed to search events: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
-            }
-        }
+        self.notebook.searchevents(&self.chat, request).await
     }
 
     #[tool(description = "Delete an event by its ID")]
@@ -511,56 +726,253 @@ impl EnhancedMcpServer {
         &self,
         #[tool(aggr)] request: DeleteEventRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Deleting event {}...", request.id),
-            })
-            .await;
-
-        match self.events.delete_event(request).await {
-            Ok(existed) => {
-                let message = if existed {
-                    "🗑️ Event deleted successfully!".to_string()
-                } else {
-                    "❌ Event not found.".to_string()
-                };
+        self.notebook.deleteevent(&self.chat, request).await
+    }
+
+    #[tool(
+        description = "Re-encrypt the notes, events and transcript files with the new data key the operator configured (NPARROT_NEW_DATA_KEY); the old files are only removed once the new ones read back correctly"
+    )]
+    async fn rotate_data_key(&self) -> Result<CallToolResult, RmcpError> {
+        let Some(new_key) = at_rest::next_key() else {
+            return Ok(CallToolResult::error(vec![Content::text(
+                "❌ No new data key is configured. The operator sets NPARROT_NEW_DATA_KEY (or --new-data-key) and restarts nparrot.",
+            )]));
+        };
+        let data_dir = std::path::Path::new(&self.data_dir);
+        let paths = [
+            data_dir.join(notebook::NOTES_FILE),
+            data_dir.join(notebook::EVENTS_FILE),
+            data_dir.join(progress_history::FILE),
+        ];
+        match transcript::rotate_with_stores(&self.data_dir, Vault::global(), &paths, new_key) {
+            Ok((rotated, resealed)) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "🔑 Re-encrypted {} file(s) and {} transcript line(s) with the new data key. Set NPARROT_DATA_KEY to the value of NPARROT_NEW_DATA_KEY before the next start, or the files cannot be read.",
+                rotated, resealed
+            ))])),
+            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
+                "❌ Key rotation failed, the files still use the old key: {}",
+                e
+            ))])),
+        }
+    }
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-                Ok(CallToolResult::success(vec![Content::text(
-                    if existed {
-                        "Event deleted"
-                    } else {
-                        "Event not found"
+    #[tool(
+        description = "Export non-cancelled events in a time window as an iCalendar (.ics) file in the data directory"
+    )]
+    async fn exportevents_ics(
+        &self,
+        #[tool(aggr)] request: ExportEventsIcsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let parse_time = |name: &str, value: Option<&String>| {
+            value
+                .map(|value| {
+                    chrono::DateTime::parse_from_rfc3339(value)
+                        .map(|time| time.to_utc())
+                        .map_err(|e| {
+                            RmcpError::invalid_params(format!("Invalid {}: {}", name, e), None)
+                        })
+                })
+                .transpose()
+        };
+        let from = parse_time("from", request.from.as_ref())?;
+        let to = parse_time("to", request.to.as_ref())?;
+        let filename = request.filename.unwrap_or_else(|| "events.ics".to_string());
+        if filename.is_empty() || filename.starts_with('.') || filename.contains(['/', '\\']) {
+            return Err(RmcpError::invalid_params(
+                "filename must be a plain file name inside the data directory",
+                None,
+            ));
+        }
+
+        let events = self.notebook.events().in_window(from, to).await;
+        let path = std::path::Path::new(&self.data_dir).join(&filename);
+        let written = std::fs::create_dir_all(&self.data_dir)
+            .and_then(|_| std::fs::write(&path, ics::export(&events, chrono::Utc::now())));
+        if let Err(e) = written {
+            let error_msg = format!("❌ Failed to write {}: {}", path.display(), e);
+            return Ok(CallToolResult::error(vec![Content::text(error_msg)]));
+        }
+
+        let zone = timezone::default_zone();
+        let mut summary = format!(
+            "📅 Exported {} event(s) to {}",
+            events.len(),
+            path.display()
+        );
+        for (event, start, _) in events.iter().take(10) {
+            summary.push_str(&format!(
+                "\n• {} — {}",
+                event.title,
+                zone.format(*start, DISPLAY_FORMAT)
+            ));
+        }
+        if events.len() > 10 {
+            summary.push_str(&format!("\n… and {} more", events.len() - 10));
+        }
+        reply_and_result(&self.chat, summary.clone(), summary).await
+    }
+
+    #[tool(
+        description = "Import events from an iCalendar (.ics) payload; entries imported before (same UID) are updated, recurrence rules are not expanded"
+    )]
+    async fn importevents_ics(
+        &self,
+        #[tool(aggr)] request: ImportEventsIcsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let (imported, skipped) =
+            ics::parse(&request.ics).map_err(|e| RmcpError::invalid_params(e, None))?;
+        let warnings: Vec<String> = imported
+            .iter()
+            .flat_map(|event| event.warnings.iter().cloned())
+            .chain(skipped.iter().cloned())
+            .collect();
+
+        match self.notebook.events().import_ics(imported).await {
+            Ok((created, updated)) => {
+                let mut text = format!(
+                    "📅 Imported calendar: {} event(s) added, {} updated",
+                    created, updated
+                );
+                if !warnings.is_empty() {
+                    text.push_str(&format!("\n\n⚠️ {} warning(s):", warnings.len()));
+                    for warning in &warnings {
+                        text.push_str(&format!("\n- {}", warning));
                     }
-                    .to_string(),
-                )]))
-            }
-            Err(e) => {
-                let error_msg = format!("❌ Failed to delete event: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_msg.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_msg)]))
+                }
+                Ok(CallToolResult::success(vec![Content::text(text)]))
             }
+            Err(e) => Ok(CallToolResult::error(vec![Content::text(format!(
+                "❌ Failed to import events: {}",
+                e
+            ))])),
         }
     }
 }
 
-#[tool(tool_box)]
+impl EnhancedMcpServer {
+    /// The tools this server offers, `get_audit_log` only if the operator exposed it
+    pub fn tools(&self) -> Vec<rmcp::model::Tool> {
+        Self::tool_box()
+            .list()
+            .into_iter()
+            .filter(|tool| audit::tool_enabled() || tool.name != audit::TOOL)
+            .collect()
+    }
+
+    /// The `get_info` instructions in `profile`, without rules about tools that aren't listed
+    fn instructions(&self, profile: instructions::InstructionProfile) -> String {
+        let tools = self.tools();
+        let listed: Vec<&str> = tools.iter().map(|tool| tool.name.as_ref()).collect();
+        instructions::render(INSTRUCTIONS, profile, &listed)
+    }
+}
+
 impl ServerHandler for EnhancedMcpServer {
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some(format!("This enhanced server provides comprehensive tools for Nostr chat, note management, and event tracking.\n\nABSOLUTELY MANDATORY FOR EVERY USER MESSAGE:\n\n1. IMMEDIATE PROGRESS RESPONSE: The INSTANT you receive a user message, you MUST send a progress update\n   Example: {{\"tool\": \"progress\", \"arguments\": {{\"message\": \"I'm processing your request...\"}}}}\n\n2. PERFORM OPERATIONS: Execute the requested note/event operations\n\n3. MANDATORY FINAL SEND: You MUST ALWAYS end with a 'send' tool call - NO EXCEPTIONS\n   Example: {{\"tool\": \"send\", \"arguments\": {{\"message\": \"Operation completed successfully\"}}}}\n\nCRITICAL: EVERY conversation turn MUST follow this pattern:\n   wait -> progress -> [note/event operations] -> send\n\nUSER VISIBILITY RULES:\n- Users can ONLY see messages sent via 'send' and 'progress' tools\n- Users CANNOT see your thinking, reasoning, or stdout output\n- If you don't use 'send', the user sees NOTHING\n- If you don't use 'progress', users think you're not working\n\nFORBIDDEN BEHAVIORS:\n- Never end a turn without 'send'\n- Never start work without 'progress'\n- Never perform note/event operations without progress updates\n- Never assume the user knows what you're doing\n\n{}\n\nCRITICAL PARAMETER RULES:\n1) ALL tool parameters MUST be valid JSON objects\n2) String values MUST be properly quoted\n3) Use double quotes, not single quotes\n4) Ensure proper escaping of special characters\n5) NO trailing commas or extra characters\n\nCOMMON PARAMETER ERRORS TO AVOID:\n- Unquoted strings: {{message: hello}} WRONG -> {{\"message\": \"hello\"}} CORRECT\n- Single quotes: {{'message': 'hello'}} WRONG -> {{\"message\": \"hello\"}} CORRECT\n- Trailing chars: {{\"message\": \"hello\"}}extra WRONG -> {{\"message\": \"hello\"}} CORRECT\n- Missing commas: {{\"a\": \"1\" \"b\": \"2\"}} WRONG -> {{\"a\": \"1\", \"b\": \"2\"}} CORRECT\n\nERROR RECOVERY: If you receive parameter errors, retry with simpler, properly formatted JSON.\n\nFAILURE TO FOLLOW THIS PATTERN WILL BREAK THE SYSTEM\n\nAvailable capabilities: Chat (send, progress, wait), Notes (addnote, listnotes, searchnotes, deletenote), Events (addevent, listevents, searchevents, deleteevent).", 
-                self.progress_tracker.create_comprehensive_instructions())),
+    // Listed and called by hand to leave out `get_audit_log` unless the operator exposed it,
+    // and to audit every call
+    async fn list_tools(
+        &self,
+        _request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, RmcpError> {
+        Ok(ListToolsResult {
+            next_cursor: None,
+            tools: self.tools(),
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, RmcpError> {
+        if !audit::tool_enabled() && request.name == audit::TOOL {
+            return Err(RmcpError::invalid_params("tool not found", None));
         }
+        self.chat.tool_called();
+        let audited = request.clone();
+        audit::record(
+            "enhanced",
+            &audited,
+            Self::tool_box().call(ToolCallContext::new(self, request, context)),
+        )
+        .await
+    }
+
+    fn get_info(&self) -> ServerInfo {
+        ServerInfoBuilder::new()
+            .with_prompts()
+            .instructions(self.instructions(instructions::profile()))
+            .build()
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, RmcpError> {
+        Ok(prompts::list(prompts::ENHANCED))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, RmcpError> {
+        prompts::get(prompts::ENHANCED, request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::memory_relay::RelayNetwork;
+    use crate::transport::DmTransport;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_send_and_wait_through_relays() {
+        let network = RelayNetwork::new(&["wss://a.relay", "wss://b.relay"]);
+        let (ours, user) = (Keys::generate(), Keys::generate());
+        let dir = tempfile::tempdir().unwrap();
+        let server = EnhancedMcpServer::with_transport(
+            Arc::new(network.connect(ours.clone())),
+            ours.clone(),
+            ours.public_key(),
+            user.public_key(),
+            dir.path().to_string_lossy().to_string(),
+        );
+        let phone = network.connect(user.clone());
+        let mut inbox = phone.subscribe_dms(user.public_key()).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let server = server.clone();
+            async move { server.wait(WaitRequest::default()).await }
+        });
+        // The server subscribes once `wait` starts
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        phone
+            .send_private_msg(ours.public_key(), "what's on today?".to_string(), None)
+            .await
+            .unwrap();
+        let received = waiting.await.unwrap().unwrap();
+        let text = &received.content[0].as_text().unwrap().text;
+        assert!(text.starts_with("what's on today?"));
+
+        let sent = server
+            .send(SendMessageRequest {
+                message: "Nothing, enjoy the day".to_string(),
+                reply_to: None,
+                to: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(sent.is_error, Some(false));
+        let reply = tokio::time::timeout(Duration::from_secs(1), inbox.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply.sender, ours.public_key());
+        assert_eq!(reply.rumor.content, "Nothing, enjoy the day");
     }
 }
@@ -0,0 +1,249 @@
+//! What the MCP servers built on `Chat` do around their tools
+//!
+//! The combined, enhanced, memory and multi-agent servers all announce an operation on the debug
+//! progress channel, run it, tell the user the outcome with `send` and return a short summary to
+//! the model, or the error with its code when it failed. The helpers here do each step the same
+//! way for all of them, and `ServerInfoBuilder` assembles their `get_info`.
+
+use crate::error::NparrotError;
+use crate::mcp::chat::{Chat, ProgressMessageRequest, SendMessageRequest};
+use crate::progress_channels;
+use rmcp::model::{
+    CallToolResult, Content, Implementation, PromptsCapability, ProtocolVersion,
+    ResourcesCapability, ServerCapabilities, ServerInfo,
+};
+use rmcp::Error as RmcpError;
+use std::future::Future;
+
+/// Posts `label` on the debug progress channel, then runs `operation`. Progress is
+/// best-effort: failing to post it never stops the operation.
+pub async fn with_progress<F: Future>(
+    chat: &Chat,
+    label: impl Into<String>,
+    operation: F,
+) -> F::Output {
+    let _ = chat
+        .progress(ProgressMessageRequest {
+            message: label.into(),
+            expire_after_secs: None,
+            channel: Some(progress_channels::DEBUG.to_string()),
+        })
+        .await;
+    operation.await
+}
+
+/// Sends `message` to the user and returns `summary` to the model. The result doesn't depend on
+/// the send: the operation itself succeeded either way.
+pub async fn reply_and_result(
+    chat: &Chat,
+    message: String,
+    summary: impl Into<String>,
+) -> Result<CallToolResult, RmcpError> {
+    reply(chat, message).await;
+    Ok(CallToolResult::success(vec![Content::text(summary.into())]))
+}
+
+/// Tells the user `message` about a failed operation and returns it as an error result carrying
+/// the code of `error`
+pub async fn reply_error(
+    chat: &Chat,
+    message: String,
+    error: impl Into<NparrotError>,
+) -> Result<CallToolResult, RmcpError> {
+    reply(chat, message.clone()).await;
+    Ok(error.into().to_result(message))
+}
+
+/// Sends `message` to the user, best-effort
+pub async fn reply(chat: &Chat, message: String) {
+    let _ = chat
+        .send(SendMessageRequest {
+            message,
+            reply_to: None,
+            to: None,
+        })
+        .await;
+}
+
+/// Assembles a server's `ServerInfo`: tools always, prompts and resources for the servers that
+/// have them, and its instructions
+#[derive(Debug, Default)]
+pub struct ServerInfoBuilder {
+    prompts: bool,
+    resources: bool,
+    instructions: Option<String>,
+}
+
+impl ServerInfoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_prompts(mut self) -> Self {
+        self.prompts = true;
+        self
+    }
+
+    pub fn with_resources(mut self) -> Self {
+        self.resources = true;
+        self
+    }
+
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    pub fn build(self) -> ServerInfo {
+        let mut capabilities = ServerCapabilities::builder().enable_tools().build();
+        if self.prompts {
+            capabilities.prompts = Some(PromptsCapability::default());
+        }
+        if self.resources {
+            capabilities.resources = Some(ResourcesCapability::default());
+        }
+        ServerInfo {
+            protocol_version: ProtocolVersion::V_2024_11_05,
+            capabilities,
+            server_info: Implementation::from_build_env(),
+            instructions: self.instructions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress_channels::ProgressChannels;
+    use crate::transport::fake::FakeTransport;
+    use crate::transport::SharedTransport;
+    use nostr_sdk::prelude::*;
+    use std::sync::Arc;
+
+    fn chat() -> (Chat, Arc<FakeTransport>, Arc<FakeTransport>) {
+        let main = Arc::new(FakeTransport::new(Keys::generate()));
+        let debug = Arc::new(FakeTransport::new(Keys::generate()));
+        let mut progress = ProgressChannels::<SharedTransport>::default();
+        progress.insert(progress_channels::DEBUG, debug.clone());
+        let chat = Chat::with_transport(
+            main.clone(),
+            progress,
+            Keys::generate().public_key(),
+            Keys::generate().public_key(),
+        );
+        (chat, main, debug)
+    }
+
+    #[tokio::test]
+    async fn test_with_progress_announces_then_runs() {
+        let (chat, main, debug) = chat();
+        let result = with_progress(&chat, "Storing memory: plan", async { 42 }).await;
+        assert_eq!(result, 42);
+        assert_eq!(debug.sent()[0].content, "Storing memory: plan");
+        assert!(main.sent().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_reply_and_result() {
+        let (chat, main, _) = chat();
+        let result = reply_and_result(&chat, "📝 Found 2 note(s)".to_string(), "Listed 2 notes")
+            .await
+            .unwrap();
+        assert_eq!(main.sent()[0].content, "📝 Found 2 note(s)");
+        assert_eq!(result.is_error, Some(false));
+        assert_eq!(result.content[0].as_text().unwrap().text, "Listed 2 notes");
+    }
+
+    #[tokio::test]
+    async fn test_reply_error_keeps_the_code() {
+        let (chat, main, _) = chat();
+        let result = reply_error(
+            &chat,
+            "❌ Failed to delete note: No note abc".to_string(),
+            NparrotError::invalid_params("id", "No note abc"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            main.sent()[0].content,
+            "❌ Failed to delete note: No note abc"
+        );
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            result.content[0].as_text().unwrap().text,
+            "❌ Failed to delete note: No note abc"
+        );
+        assert_eq!(
+            NparrotError::code_of(&result).as_deref(),
+            Some("invalid_params")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_notebook_outputs_are_unchanged() {
+        use crate::mcp::notebook::Notebook;
+        use crate::mcp::types::{AddNoteRequest, DeleteNoteRequest};
+
+        let (chat, main, debug) = chat();
+        let dir = tempfile::tempdir().unwrap();
+        let notebook = Notebook::open(&dir.path().to_string_lossy());
+
+        let added = notebook
+            .addnote(
+                &chat,
+                AddNoteRequest {
+                    content: "Buy milk".to_string(),
+                    tags: Some(vec!["errands".to_string()]),
+                    metadata: None,
+                },
+            )
+            .await
+            .unwrap();
+        let summary = &added.content[0].as_text().unwrap().text;
+        let id = summary.strip_prefix("Note added with ID: ").unwrap();
+        assert!(main.sent()[0].content.starts_with(&format!(
+            "Note added successfully!\n\nID: {}\nContent: Buy milk\nTags: errands\nCreated: ",
+            id
+        )));
+        assert_eq!(debug.sent()[0].content, "Adding new note...");
+
+        let missing = notebook
+            .deletenote(
+                &chat,
+                DeleteNoteRequest {
+                    id: "nope".to_string(),
+                    dry_run: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(missing.is_error, Some(false));
+        assert_eq!(missing.content[0].as_text().unwrap().text, "Note not found");
+        assert_eq!(main.sent()[1].content, "❌ Note not found.");
+        assert_eq!(debug.sent()[1].content, "Deleting note nope...");
+    }
+
+    #[test]
+    fn test_server_info() {
+        let info = ServerInfoBuilder::new()
+            .with_prompts()
+            .instructions("Talk to the user")
+            .build();
+        assert!(info.capabilities.tools.is_some());
+        assert!(info.capabilities.prompts.is_some());
+        assert!(info.capabilities.resources.is_none());
+        assert_eq!(info.instructions.as_deref(), Some("Talk to the user"));
+        // The same capabilities rmcp's own builder gives
+        assert_eq!(
+            info.capabilities,
+            ServerCapabilities::builder()
+                .enable_tools()
+                .enable_prompts()
+                .build()
+        );
+
+        let info = ServerInfoBuilder::new().build();
+        assert!(info.capabilities.prompts.is_none());
+        assert_eq!(info.instructions, None);
+    }
+}
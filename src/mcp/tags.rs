@@ -0,0 +1,237 @@
+//! `managetags`: tag usage across notes and events, and renaming or merging tags everywhere
+//!
+//! Tags are matched like `has_tag` matches them, ignoring case and accents, so a rename of
+//! "todo" also catches "TODO"; the target keeps exactly the spelling it was given.
+
+use super::events::EventsManager;
+use super::notes::NotesManager;
+use super::search;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Tags before a retag, by item id, for putting them back
+pub type PreviousTags = HashMap<String, Vec<String>>;
+
+/// Replaces every tag matching one of `sources` with `target`
+#[derive(Debug)]
+pub struct Retag {
+    sources: HashSet<String>,
+    target: String,
+}
+
+impl Retag {
+    pub fn new(sources: &[String], target: &str) -> Result<Self, String> {
+        let target = target.trim();
+        if target.is_empty() {
+            return Err("The target tag must not be empty".to_string());
+        }
+        let sources: HashSet<String> = sources
+            .iter()
+            .map(|s| search::normalize(s.trim()))
+            .collect();
+        if sources.is_empty() || sources.contains("") {
+            return Err("Give at least one non-empty tag to replace".to_string());
+        }
+        Ok(Self {
+            sources,
+            target: target.to_string(),
+        })
+    }
+
+    /// The retagged list, without duplicates, or `None` if nothing changes
+    pub fn apply(&self, tags: &[String]) -> Option<Vec<String>> {
+        let target = search::normalize(&self.target);
+        let mut seen = HashSet::new();
+        let retagged: Vec<String> = tags
+            .iter()
+            .map(|tag| {
+                let normalized = search::normalize(tag);
+                if self.sources.contains(&normalized) {
+                    (target.clone(), self.target.clone())
+                } else {
+                    (normalized, tag.clone())
+                }
+            })
+            .filter(|(normalized, _)| seen.insert(normalized.clone()))
+            .map(|(_, tag)| tag)
+            .collect();
+        (retagged != tags).then_some(retagged)
+    }
+}
+
+/// How often one tag (in all its spellings) is used
+#[derive(Debug, Default, PartialEq)]
+pub struct TagUsage {
+    pub notes: usize,
+    pub events: usize,
+    /// Every spelling in use, with its count
+    pub spellings: BTreeMap<String, usize>,
+}
+
+impl TagUsage {
+    pub fn total(&self) -> usize {
+        self.notes + self.events
+    }
+
+    /// The most used spelling
+    pub fn name(&self) -> &str {
+        self.spellings
+            .iter()
+            .max_by(|(a_tag, a), (b_tag, b)| a.cmp(b).then(b_tag.cmp(a_tag)))
+            .map(|(tag, _)| tag.as_str())
+            .unwrap_or_default()
+    }
+}
+
+/// Tag usage across notes and events, most used first
+pub async fn usage(notes: &NotesManager, events: &EventsManager) -> Vec<TagUsage> {
+    let mut usage: HashMap<String, TagUsage> = HashMap::new();
+    let mut count = |tag: &String, is_note: bool| {
+        let entry = usage.entry(search::normalize(tag)).or_default();
+        if is_note {
+            entry.notes += 1;
+        } else {
+            entry.events += 1;
+        }
+        *entry.spellings.entry(tag.clone()).or_default() += 1;
+    };
+    for note in notes.all_notes().await {
+        note.tags.iter().for_each(|tag| count(tag, true));
+    }
+    for event in events.all_events().await {
+        event.tags.iter().for_each(|tag| count(tag, false));
+    }
+
+    let mut usage: Vec<TagUsage> = usage.into_values().collect();
+    usage.sort_by(|a, b| b.total().cmp(&a.total()).then(a.name().cmp(b.name())));
+    usage
+}
+
+/// How many items a rename or merge touched (or would touch)
+#[derive(Debug, Default, PartialEq)]
+pub struct RetagSummary {
+    pub notes: usize,
+    pub events: usize,
+}
+
+/// Applies `retag` to notes and events as one change: if saving either store fails, both are
+/// left as they were
+pub async fn retag(
+    notes: &NotesManager,
+    events: &EventsManager,
+    retag: &Retag,
+    dry_run: bool,
+) -> Result<RetagSummary, String> {
+    if dry_run {
+        return Ok(RetagSummary {
+            notes: notes.retag_count(retag).await,
+            events: events.retag_count(retag).await,
+        });
+    }
+
+    let previous_events = events.retag(retag).await?;
+    let previous_notes = match notes.retag(retag).await {
+        Ok(previous) => previous,
+        Err(e) => {
+            if let Err(restore_error) = events.restore_tags(previous_events).await {
+                log::error!("Could not restore event tags: {}", restore_error);
+            }
+            return Err(e.into());
+        }
+    };
+    Ok(RetagSummary {
+        notes: previous_notes.len(),
+        events: previous_events.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::types::{AddEventRequest, AddNoteRequest};
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn test_retag_ignores_case_and_keeps_target_spelling() {
+        let merge = Retag::new(&tags(&["todo", "to-do"]), "ToDo").unwrap();
+        assert_eq!(
+            merge.apply(&tags(&["work", "TODO", "to-do"])),
+            Some(tags(&["work", "ToDo"]))
+        );
+        assert_eq!(merge.apply(&tags(&["ToDo"])), None);
+        assert_eq!(merge.apply(&tags(&["work"])), None);
+        assert!(Retag::new(&tags(&["todo"]), " ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_merge_touches_notes_and_events_unless_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let notes = NotesManager::new(path("notes.json"));
+        let events = EventsManager::new(path("events.json"));
+        for note_tags in [&["todo"][..], &["TODO", "home"], &["home"]] {
+            notes
+                .add_note(AddNoteRequest {
+                    content: "note".to_string(),
+                    tags: Some(tags(note_tags)),
+                    metadata: None,
+                })
+                .await
+                .unwrap();
+        }
+        events
+            .add_event(AddEventRequest {
+                title: "Review".to_string(),
+                description: None,
+                event_type: "task".to_string(),
+                start_time: None,
+                end_time: None,
+                tags: Some(tags(&["to-do"])),
+                metadata: None,
+                timezone: None,
+                allow_conflict: None,
+            })
+            .await
+            .unwrap();
+
+        let listed = usage(&notes, &events).await;
+        assert_eq!(listed.len(), 3);
+        let todo = listed
+            .iter()
+            .find(|u| u.spellings.contains_key("TODO"))
+            .unwrap();
+        assert_eq!((todo.notes, todo.spellings.len()), (2, 2));
+
+        let merge = Retag::new(&tags(&["todo", "to-do"]), "todo").unwrap();
+        // The note already tagged "todo" needs no change
+        let expected = RetagSummary {
+            notes: 1,
+            events: 1,
+        };
+        assert_eq!(
+            retag(&notes, &events, &merge, true).await.unwrap(),
+            expected
+        );
+        assert_eq!(usage(&notes, &events).await.len(), 3);
+
+        assert_eq!(
+            retag(&notes, &events, &merge, false).await.unwrap(),
+            expected
+        );
+        let listed = usage(&notes, &events).await;
+        assert_eq!(listed.len(), 2);
+        let todo = listed.iter().find(|u| u.name() == "todo").unwrap();
+        assert_eq!((todo.notes, todo.events), (2, 1));
+        assert_eq!(todo.spellings.len(), 1);
+
+        // Reloaded from disk, the change is there too
+        let reloaded = NotesManager::new(path("notes.json"));
+        assert!(reloaded
+            .all_notes()
+            .await
+            .iter()
+            .all(|n| !n.tags.contains(&"TODO".to_string())));
+    }
+}
@@ -2,7 +2,12 @@ use rmcp::schemars::{self, JsonSchema};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-pub use super::chat::{ProgressMessageRequest, SendMessageRequest};
+pub use super::chat::{
+    CancelScheduledRequest, ProgressMessageRequest, RequestZapRequest, SendBatchRequest,
+    SendLaterRequest, SendMessageRequest, SummarizeConversationRequest, UploadFileRequest,
+    WaitRequest,
+};
+pub use super::context::{ClearContextRequest, GetContextRequest, SetContextRequest};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Note {
@@ -33,7 +38,9 @@ pub struct AddNoteRequest {
     pub content: String,
     #[schemars(description = "Optional tags for categorizing the note")]
     pub tags: Option<Vec<String>>,
-    #[schemars(description = "Optional metadata key-value pairs")]
+    #[schemars(
+        description = "Optional metadata key-value pairs; keys starting with '_' are reserved"
+    )]
     pub metadata: Option<HashMap<String, String>>,
 }
 
@@ -53,6 +60,16 @@ pub struct AddEventRequest {
     pub end_time: Option<String>,
     #[schemars(description = "Optional metadata key-value pairs")]
     pub metadata: Option<HashMap<String, String>>,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional IANA time zone to show times in, e.g. \"Europe/Berlin\" (defaults to NPARROT_TZ, else UTC)"
+    )]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Set to true to add the event without checking for overlapping events"
+    )]
+    pub allow_conflict: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -63,6 +80,14 @@ pub struct ListNotesRequest {
     pub limit: Option<u32>,
     #[schemars(description = "Sort order: 'newest', 'oldest', or 'updated'")]
     pub sort: Option<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional metadata key-value pairs a note must all have exactly, e.g. {\"project\": \"alpha\"}"
+    )]
+    pub metadata_filters: Option<HashMap<String, String>>,
+    #[serde(default)]
+    #[schemars(description = "Set to true to show each note's metadata")]
+    pub include_metadata: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -75,16 +100,29 @@ pub struct ListEventsRequest {
     pub limit: Option<u32>,
     #[schemars(description = "Sort order: 'newest', 'oldest', or 'start_time'")]
     pub sort: Option<String>,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional IANA time zone to show times in, e.g. \"Europe/Berlin\" (defaults to NPARROT_TZ, else UTC)"
+    )]
+    pub timezone: Option<String>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct SearchNotesRequest {
-    #[schemars(description = "Search query - searches in note content")]
+    #[schemars(description = "Search query - searches in note content, ignoring case and accents")]
     pub query: String,
     #[schemars(description = "Optional tag filter")]
     pub tag: Option<String>,
     #[schemars(description = "Optional limit on number of results")]
     pub limit: Option<u32>,
+    #[serde(default)]
+    #[schemars(description = "Set to true to also find near misses and typos, best matches first")]
+    pub fuzzy: Option<bool>,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional metadata key-value pairs a note must all have exactly, e.g. {\"project\": \"alpha\"}"
+    )]
+    pub metadata_filters: Option<HashMap<String, String>>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -97,16 +135,107 @@ pub struct SearchEventsRequest {
     pub tag: Option<String>,
     #[schemars(description = "Optional limit on number of results")]
     pub limit: Option<u32>,
+    #[serde(default)]
+    #[schemars(
+        description = "Optional IANA time zone to show times in, e.g. \"Europe/Berlin\" (defaults to NPARROT_TZ, else UTC)"
+    )]
+    pub timezone: Option<String>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct DeleteNoteRequest {
     #[schemars(description = "The ID of the note to delete")]
     pub id: String,
+    #[schemars(
+        description = "Only report what would be deleted, without deleting it (optional, defaults to false)"
+    )]
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ExportEventsIcsRequest {
+    #[schemars(description = "Only events ending after this time (optional, ISO 8601)")]
+    pub from: Option<String>,
+    #[schemars(description = "Only events starting before this time (optional, ISO 8601)")]
+    pub to: Option<String>,
+    #[schemars(description = "File name in the data directory (optional, defaults to events.ics)")]
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ImportEventsIcsRequest {
+    #[schemars(description = "The iCalendar (.ics) file content")]
+    pub ics: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ManageTagsRequest {
+    #[schemars(
+        description = "list (tags with usage counts), rename (one tag to target) or merge (several tags into target)"
+    )]
+    pub operation: String,
+    #[schemars(
+        description = "Tags to rename or merge, matched ignoring case (one for rename, several for merge)"
+    )]
+    pub tags: Option<Vec<String>>,
+    #[schemars(description = "The tag to rename or merge into, spelled exactly as it should be")]
+    pub target: Option<String>,
+    #[schemars(
+        description = "Only report how many notes and events would change (optional, defaults to false)"
+    )]
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct SyncNotesToMemoryRequest {
+    #[schemars(description = "Only sync notes with this tag (optional)")]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ImportMemoriesRequest {
+    #[schemars(
+        description = "Only import memories of this type, e.g. fact or user_preference (optional)"
+    )]
+    pub memory_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeleteNotesRequest {
+    #[schemars(description = "Only notes with this tag (optional)")]
+    pub tag: Option<String>,
+    #[schemars(
+        description = "Only notes created before this date or time (optional, YYYY-MM-DD or ISO 8601)"
+    )]
+    pub created_before: Option<String>,
+    #[schemars(
+        description = "Only notes whose content matches this regular expression (optional)"
+    )]
+    pub content_regex: Option<String>,
+    #[schemars(
+        description = "Must be true to actually delete; otherwise only lists what would be deleted"
+    )]
+    pub confirm: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PublishNoteRequest {
+    #[schemars(description = "The ID of the note to publish")]
+    pub id: String,
+    #[schemars(description = "Title of the article (optional, defaults to the note's first line)")]
+    pub title: Option<String>,
+    #[schemars(
+        description = "Return the event that would be published without publishing it (optional, defaults to false)"
+    )]
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct DeleteEventRequest {
     #[schemars(description = "The ID of the event to delete")]
     pub id: String,
+    #[schemars(
+        description = "Only report what would be deleted, without deleting it (optional, defaults to false)"
+    )]
+    pub dry_run: Option<bool>,
 }
@@ -0,0 +1,90 @@
+//! Stalled-turn watchdog of the enhanced and combined servers (`NPARROT_STALL_AFTER`)
+//!
+//! A turn starts when `wait` hands the model a message. If no tool is called at all for the
+//! stall interval, the user gets a progress DM saying the assistant seems delayed and the stall is
+//! logged; after the same interval again, an apology goes to the main channel. Any tool call ends
+//! the watch. Every `wait` starts a new turn with its own generation, so the timers of an earlier
+//! turn that overlapped it never fire.
+
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+lazy_static::lazy_static! {
+    static ref STALL_AFTER: RwLock<Option<Duration>> = RwLock::new(None);
+}
+
+/// Sets the stall interval of the servers started from now on; `None` or zero turns it off
+pub fn set_stall_after(interval: Option<Duration>) {
+    if let Ok(mut guard) = STALL_AFTER.write() {
+        *guard = interval.filter(|interval| !interval.is_zero());
+    }
+}
+
+pub fn stall_after() -> Option<Duration> {
+    STALL_AFTER.read().ok().and_then(|guard| *guard)
+}
+
+#[derive(Debug, Default)]
+struct Watch {
+    generation: u64,
+    armed: bool,
+}
+
+/// Whether the turn that started last is still waiting for the model
+#[derive(Debug)]
+pub struct TurnWatchdog {
+    interval: Duration,
+    watch: Mutex<Watch>,
+}
+
+impl TurnWatchdog {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            watch: Mutex::default(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Starts watching a new turn, replacing any earlier one; returns its generation
+    pub fn turn_started(&self) -> u64 {
+        let mut watch = self.watch.lock().unwrap_or_else(|e| e.into_inner());
+        watch.generation += 1;
+        watch.armed = true;
+        watch.generation
+    }
+
+    /// The model called a tool: the current turn is not stalled
+    pub fn activity(&self) {
+        self.watch.lock().unwrap_or_else(|e| e.into_inner()).armed = false;
+    }
+
+    /// Whether turn `generation` is still the current one and nothing happened in it
+    pub fn stalled(&self, generation: u64) -> bool {
+        let watch = self.watch.lock().unwrap_or_else(|e| e.into_inner());
+        watch.armed && watch.generation == generation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_current_idle_turn_is_stalled() {
+        let watchdog = TurnWatchdog::new(Duration::from_secs(60));
+        let first = watchdog.turn_started();
+        assert!(watchdog.stalled(first));
+        watchdog.activity();
+        assert!(!watchdog.stalled(first));
+
+        // An overlapping turn replaces the first one, whose timers then stay quiet
+        let first = watchdog.turn_started();
+        let second = watchdog.turn_started();
+        assert!(!watchdog.stalled(first));
+        assert!(watchdog.stalled(second));
+    }
+}
@@ -0,0 +1,375 @@
+//! File uploads to a Blossom or NIP-96 media server, for the `upload_and_send_file` tool
+//!
+//! Only files under the data dir or the working directory can be uploaded, up to
+//! `--max-upload-size`. The DM carries the URL in its text and a NIP-92 `imeta` tag with the
+//! NIP-94 fields (`url`, `m`, `x`, `size`), so clients can show the file inline and check its
+//! sha256.
+
+use crate::error::NparrotError;
+use crate::transport::DmTransport;
+use base64::Engine;
+use nostr_sdk::hashes::sha256::Hash as Sha256Hash;
+use nostr_sdk::hashes::Hash;
+use nostr_sdk::prelude::*;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::Duration;
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+/// Blossom authorization event (BUD-01)
+const BLOSSOM_AUTH: Kind = Kind::Custom(24242);
+/// How long an upload authorization stays valid
+const AUTH_VALIDITY: Duration = Duration::from_secs(300);
+
+lazy_static::lazy_static! {
+    static ref UPLOADS: RwLock<Option<Uploads>> = RwLock::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MediaProtocol {
+    Blossom,
+    Nip96,
+}
+
+/// Where uploads go and what may be uploaded
+#[derive(Debug, Clone)]
+pub struct Uploads {
+    pub server: String,
+    pub protocol: MediaProtocol,
+    pub max_size: u64,
+    /// Files must live under one of these
+    pub roots: Vec<PathBuf>,
+}
+
+/// A file that made it to the media server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uploaded {
+    pub name: String,
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+    pub mime: String,
+}
+
+impl Uploaded {
+    /// NIP-92 media attachment tag with the file's NIP-94 fields
+    pub fn imeta_tag(&self) -> Tag {
+        Tag::custom(
+            TagKind::custom("imeta"),
+            [
+                format!("url {}", self.url),
+                format!("m {}", self.mime),
+                format!("x {}", self.sha256),
+                format!("size {}", self.size),
+                format!("alt {}", self.name),
+            ],
+        )
+    }
+}
+
+/// Installs the process-wide upload settings
+pub fn set_uploads(uploads: Option<Uploads>) {
+    if let Ok(mut guard) = UPLOADS.write() {
+        *guard = uploads;
+    }
+}
+
+pub fn uploads() -> Option<Uploads> {
+    UPLOADS.read().ok()?.clone()
+}
+
+impl Uploads {
+    /// Resolves `path` to a file under one of the roots, without following links out of them
+    pub fn check_path(&self, path: &str) -> Result<PathBuf, NparrotError> {
+        let invalid = |message: String| NparrotError::invalid_params("path", message);
+        let path = Path::new(path);
+        let candidates: Vec<PathBuf> = if path.is_absolute() {
+            vec![path.to_path_buf()]
+        } else {
+            self.roots.iter().map(|root| root.join(path)).collect()
+        };
+        for candidate in candidates {
+            let Ok(resolved) = candidate.canonicalize() else {
+                continue;
+            };
+            let inside = self.roots.iter().any(|root| {
+                root.canonicalize()
+                    .is_ok_and(|root| resolved.starts_with(root))
+            });
+            if !inside {
+                return Err(invalid(format!(
+                    "{} is outside the data dir and the working directory",
+                    path.display()
+                )));
+            }
+            if !resolved.is_file() {
+                return Err(invalid(format!("{} is not a file", path.display())));
+            }
+            return Ok(resolved);
+        }
+        Err(invalid(format!("{} does not exist", path.display())))
+    }
+
+    /// Uploads the file at `path` (already checked), signing the authorization with `client`
+    pub async fn upload(
+        &self,
+        client: &dyn DmTransport,
+        path: &Path,
+    ) -> Result<Uploaded, NparrotError> {
+        let size = std::fs::metadata(path)
+            .map_err(|e| NparrotError::invalid_params("path", e.to_string()))?
+            .len();
+        if size > self.max_size {
+            return Err(NparrotError::invalid_params(
+                "path",
+                format!(
+                    "{} is {} bytes, more than the {} allowed",
+                    path.display(),
+                    size,
+                    self.max_size
+                ),
+            ));
+        }
+        // Read into memory, so nothing is left behind on disk whatever happens to the upload
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| NparrotError::internal(format!("Could not read file: {}", e)))?;
+        let sha256 = Sha256Hash::hash(&data).to_string();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mime = content_type(path).to_string();
+
+        let url = match self.protocol {
+            MediaProtocol::Blossom => self.upload_blossom(client, data, &sha256, &mime).await?,
+            MediaProtocol::Nip96 => self.upload_nip96(client, data, &name, &mime).await?,
+        };
+        log::info!("Uploaded {} ({} bytes) to {}", name, size, url);
+        Ok(Uploaded {
+            name,
+            url,
+            sha256,
+            size,
+            mime,
+        })
+    }
+
+    /// BUD-02 `PUT /upload` with a kind-24242 authorization
+    async fn upload_blossom(
+        &self,
+        client: &dyn DmTransport,
+        data: Vec<u8>,
+        sha256: &str,
+        mime: &str,
+    ) -> Result<String, NparrotError> {
+        let expiration = Timestamp::now() + AUTH_VALIDITY;
+        let auth = EventBuilder::new(BLOSSOM_AUTH, "Upload file").tags([
+            Tag::hashtag("upload"),
+            Tag::custom(TagKind::x(), [sha256]),
+            Tag::expiration(expiration),
+        ]);
+        let auth = authorization(client, auth).await?;
+
+        #[derive(Deserialize)]
+        struct BlobDescriptor {
+            url: String,
+            sha256: String,
+        }
+        let url = format!("{}/upload", self.server.trim_end_matches('/'));
+        let response = http_client()?
+            .put(&url)
+            .header("Authorization", auth)
+            .header("Content-Type", mime)
+            .body(data)
+            .send()
+            .await
+            .map_err(request_error)?;
+        let response = check_status(response).await?;
+        let blob: BlobDescriptor = response.json().await.map_err(|e| {
+            NparrotError::internal(format!("Unexpected answer from {}: {}", url, e))
+        })?;
+        if blob.sha256 != sha256 {
+            return Err(NparrotError::internal(format!(
+                "The media server stored {} instead of {}",
+                blob.sha256, sha256
+            )));
+        }
+        Ok(blob.url)
+    }
+
+    /// NIP-96 multipart upload with a NIP-98 authorization
+    async fn upload_nip96(
+        &self,
+        client: &dyn DmTransport,
+        data: Vec<u8>,
+        name: &str,
+        mime: &str,
+    ) -> Result<String, NparrotError> {
+        let server = Url::parse(&self.server)
+            .map_err(|e| NparrotError::invalid_params("media_server", e.to_string()))?;
+        let config_url = server
+            .join("/.well-known/nostr/nip96.json")
+            .map_err(|e| NparrotError::invalid_params("media_server", e.to_string()))?;
+        let http = http_client()?;
+        let config: nip96::ServerConfig = check_status(
+            http.get(config_url.as_str())
+                .send()
+                .await
+                .map_err(request_error)?,
+        )
+        .await?
+        .json()
+        .await
+        .map_err(|e| NparrotError::internal(format!("Invalid NIP-96 server config: {}", e)))?;
+
+        let payload = Sha256Hash::hash(&data);
+        let auth = EventBuilder::http_auth(
+            HttpData::new(config.api_url.clone(), HttpMethod::POST).payload(payload),
+        );
+        let auth = authorization(client, auth).await?;
+        let part = reqwest::multipart::Part::bytes(data)
+            .file_name(name.to_string())
+            .mime_str(mime)
+            .map_err(|e| NparrotError::internal(e.to_string()))?;
+        let response = http
+            .post(config.api_url.as_str())
+            .header("Authorization", auth)
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .map_err(request_error)?;
+        let response: nip96::UploadResponse = check_status(response)
+            .await?
+            .json()
+            .await
+            .map_err(|e| NparrotError::internal(format!("Invalid NIP-96 answer: {}", e)))?;
+        if response.status == nip96::UploadResponseStatus::Error {
+            return Err(NparrotError::internal(format!(
+                "The media server refused the file: {}",
+                response.message
+            )));
+        }
+        response
+            .nip94_event
+            .and_then(|event| match event.tags.find_standardized(TagKind::Url) {
+                Some(TagStandard::Url(url)) => Some(url.to_string()),
+                _ => None,
+            })
+            .ok_or_else(|| NparrotError::internal("The media server returned no URL"))
+    }
+}
+
+/// `Nostr <base64 event>`, the authorization header both protocols use
+async fn authorization(
+    client: &dyn DmTransport,
+    builder: EventBuilder,
+) -> Result<String, NparrotError> {
+    let event = client.sign_event(builder).await.map_err(|e| {
+        NparrotError::internal(format!("Could not sign upload authorization: {}", e))
+    })?;
+    Ok(format!(
+        "Nostr {}",
+        base64::engine::general_purpose::STANDARD.encode(event.as_json())
+    ))
+}
+
+fn http_client() -> Result<reqwest::Client, NparrotError> {
+    reqwest::Client::builder()
+        .timeout(UPLOAD_TIMEOUT)
+        .build()
+        .map_err(|e| NparrotError::internal(e.to_string()))
+}
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, NparrotError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    // Blossom servers explain refusals in this header
+    let reason = response
+        .headers()
+        .get("X-Reason")
+        .and_then(|reason| reason.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_default();
+    let message = format!("The media server answered {} {}", status, reason);
+    Err(match status {
+        reqwest::StatusCode::TOO_MANY_REQUESTS => NparrotError::rate_limited(message),
+        reqwest::StatusCode::PAYLOAD_TOO_LARGE => NparrotError::invalid_params("path", message),
+        status if status.is_server_error() => NparrotError::relay_unavailable(message),
+        _ => NparrotError::internal(message),
+    })
+}
+
+fn request_error(e: reqwest::Error) -> NparrotError {
+    if e.is_timeout() {
+        NparrotError::timeout(format!("The media server did not answer in time: {}", e))
+    } else {
+        NparrotError::relay_unavailable(format!("The media server is unreachable: {}", e))
+    }
+}
+
+/// MIME type from the file extension, enough for the files agents produce
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "md" => "text/markdown",
+        "txt" | "log" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uploads_stay_inside_the_roots() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("diagram.png"), b"png").unwrap();
+        std::fs::write(outside.path().join("secret.txt"), b"secret").unwrap();
+        let uploads = Uploads {
+            server: "http://127.0.0.1:1".to_string(),
+            protocol: MediaProtocol::Blossom,
+            max_size: 1024,
+            roots: vec![root.path().to_path_buf()],
+        };
+
+        let file = uploads.check_path("diagram.png").unwrap();
+        assert_eq!(content_type(&file), "image/png");
+        let escape = format!(
+            "../{}/secret.txt",
+            outside.path().file_name().unwrap().to_string_lossy()
+        );
+        for path in [
+            outside.path().join("secret.txt").display().to_string(),
+            escape,
+            "missing.png".to_string(),
+            ".".to_string(),
+        ] {
+            assert_eq!(
+                uploads.check_path(&path).unwrap_err().code(),
+                "invalid_params",
+                "{}",
+                path
+            );
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Keeps outgoing messages within what relays accept (`NPARROT_MAX_MESSAGE_SIZE`)
+//!
+//! Relays silently drop events over their size limit, so a long `listnotes` or Goose output
+//! used to vanish without the user seeing anything. `Chat` estimates the size of the event a
+//! message becomes (for DMs the gift wrap, two NIP-44 encryptions deep) and cuts a message that
+//! wouldn't fit, ending it with a note of how much was left out. The limit is the smallest
+//! `max_message_length`/`max_content_length` the target relays publish in their NIP-11
+//! document (kept by `relays`), or the configured default for relays that publish none. Nothing here splits
+//! messages: there is no chunking for DMs, so truncation is the only way to deliver something.
+
+use crate::relays;
+use std::sync::RwLock;
+
+/// What relays are assumed to accept unless they say otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// The longest plaintext NIP-44 encrypts
+const NIP44_MAX_PLAINTEXT: usize = 65535;
+/// The JSON of an event around its content: id, pubkey, sig, kind, dates and a few tags
+const EVENT_OVERHEAD: usize = 600;
+/// Never cut a message below this, so there is always something to deliver
+const MIN_KEPT: usize = 256;
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_LIMIT: RwLock<usize> = RwLock::new(DEFAULT_MAX_MESSAGE_SIZE);
+}
+
+/// Sets the limit for relays without a NIP-11 limit
+pub fn set_default_limit(bytes: usize) {
+    if let Ok(mut guard) = DEFAULT_LIMIT.write() {
+        *guard = bytes;
+    }
+}
+
+/// The size limit for a message to `targets` (all configured relays if `None`): the smallest of
+/// their NIP-11 limits, counting the default for the relays that published none
+pub fn limit_for(targets: Option<&[String]>) -> usize {
+    let default = DEFAULT_LIMIT.read().map(|guard| *guard).unwrap_or_default();
+    let limit_of = |url: &str| {
+        relays::relay_info(url)
+            .and_then(|info| info.message_limit())
+            .unwrap_or(default)
+    };
+    match targets {
+        Some(targets) => targets.iter().map(|url| limit_of(url)).min(),
+        None => relays::known_info()
+            .iter()
+            .map(|info| info.message_limit().unwrap_or(default))
+            .min(),
+    }
+    .unwrap_or(default)
+}
+
+/// How long NIP-44 makes a plaintext of `len` bytes, base64 encoded
+fn nip44_len(len: usize) -> usize {
+    let padded = if len <= 32 {
+        32
+    } else {
+        let next_power = (len - 1).next_power_of_two();
+        let chunk = if next_power <= 256 {
+            32
+        } else {
+            next_power / 8
+        };
+        chunk * ((len - 1) / chunk + 1)
+    };
+    // Version, nonce, length prefix and MAC around the padded text
+    let payload = 1 + 32 + 2 + padded + 32;
+    payload.div_ceil(3) * 4
+}
+
+/// Roughly how big the event for `content` is on the wire; `encrypted` for a gift-wrapped DM.
+/// `None` if it can't be encrypted at all.
+pub fn estimated_size(content: &str, encrypted: bool) -> Option<usize> {
+    let rumor =
+        serde_json::to_string(content).map_or(content.len(), |json| json.len()) + EVENT_OVERHEAD;
+    if !encrypted {
+        return Some(rumor);
+    }
+    if rumor > NIP44_MAX_PLAINTEXT {
+        return None;
+    }
+    let seal = nip44_len(rumor) + EVENT_OVERHEAD;
+    if seal > NIP44_MAX_PLAINTEXT {
+        return None;
+    }
+    Some(nip44_len(seal) + EVENT_OVERHEAD)
+}
+
+fn fits(content: &str, encrypted: bool, limit: usize) -> bool {
+    estimated_size(content, encrypted).is_some_and(|size| size <= limit)
+}
+
+/// `content`, cut to fit `limit` with a note of how much was left out if it doesn't
+pub fn bounded(content: String, encrypted: bool, limit: usize) -> String {
+    if fits(&content, encrypted, limit) {
+        return content;
+    }
+    log::warn!(
+        "Outgoing message of {} bytes is over the {} byte limit, truncating it",
+        content.len(),
+        limit
+    );
+    let suffix = |kept: usize| {
+        format!(
+            "\n\n(truncated, {} KB omitted — ask for the full output)",
+            (content.len() - kept).div_ceil(1024)
+        )
+    };
+    // The longest prefix that still fits with its note, found by bisection over char boundaries
+    let (mut low, mut high) = (0, content.len());
+    while low < high {
+        let mid = floor_char_boundary(&content, (low + high).div_ceil(2));
+        if mid <= low {
+            break;
+        }
+        let candidate = format!("{}{}", &content[..mid], suffix(mid));
+        if fits(&candidate, encrypted, limit) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    let kept = floor_char_boundary(&content, low.max(MIN_KEPT.min(content.len())));
+    format!("{}{}", &content[..kept], suffix(kept))
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_small_messages_are_untouched() {
+        let message = "all green".to_string();
+        assert_eq!(bounded(message.clone(), true, 16 * 1024), message);
+    }
+
+    #[test]
+    fn test_oversized_messages_are_truncated_with_a_note() {
+        let message = "é".repeat(100_000);
+        let bounded = bounded(message.clone(), true, 16 * 1024);
+        assert!(bounded.ends_with("KB omitted — ask for the full output)"));
+        assert!(bounded.starts_with("éé"));
+        assert!(estimated_size(&bounded, true).unwrap() <= 16 * 1024);
+
+        // Past what NIP-44 can encrypt, whatever the relays allow
+        let bounded = super::bounded(message, true, usize::MAX);
+        assert!(estimated_size(&bounded, true).is_some());
+    }
+
+    #[test]
+    fn test_limit_is_the_smallest_known_for_the_targets() {
+        for (url, limit) in [
+            ("wss://small.example/", 20_000),
+            ("wss://large.example", 500_000),
+        ] {
+            relays::remember_info(relays::RelayInfo {
+                url: url.trim_end_matches('/').to_string(),
+                fetched: true,
+                max_message_length: Some(limit),
+                ..Default::default()
+            });
+        }
+        let large = ["wss://large.example".to_string()];
+        assert_eq!(limit_for(Some(&large)), 500_000);
+        let unknown = [
+            "wss://unknown.example".to_string(),
+            "wss://large.example".to_string(),
+        ];
+        assert_eq!(limit_for(Some(&unknown)), DEFAULT_MAX_MESSAGE_SIZE);
+        let both = [
+            "wss://large.example".to_string(),
+            "wss://small.example".to_string(),
+        ];
+        assert_eq!(limit_for(Some(&both)), 20_000);
+    }
+}
@@ -0,0 +1,150 @@
+//! Framing around every message `Chat` sends (`message_template`)
+//!
+//! `{body}` is the message as the agent wrote it; `{bot_name}`, `{channel}` ("main" or the
+//! progress channel) and `{timestamp}` (in the configured time zone) are filled in per message.
+//! `{{` and `}}` stand for literal braces. The template is checked when nparrot starts, so a
+//! typo stops it there instead of failing every send; without one, messages go out unchanged.
+
+use crate::timezone;
+use chrono::{DateTime, Utc};
+use std::sync::RwLock;
+
+const PLACEHOLDERS: &[&str] = &["body", "bot_name", "channel", "timestamp"];
+
+lazy_static::lazy_static! {
+    static ref TEMPLATE: RwLock<Option<MessageTemplate>> = RwLock::new(None);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageTemplate {
+    parts: Vec<Part>,
+    bot_name: String,
+}
+
+impl MessageTemplate {
+    /// Parses `template`; `bot_name` is required if it uses `{bot_name}`
+    pub fn parse(template: &str, bot_name: Option<&str>) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("{{") {
+                literal.push('{');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("}}") {
+                literal.push('}');
+                rest = after;
+            } else if c == '{' {
+                let end = rest.find('}').ok_or_else(|| {
+                    "Unclosed '{' in message_template; write '{{' for a literal brace".to_string()
+                })?;
+                let name = &rest[1..end];
+                let placeholder = PLACEHOLDERS
+                    .iter()
+                    .copied()
+                    .find(|placeholder| *placeholder == name)
+                    .ok_or_else(|| {
+                        format!(
+                            "Unknown placeholder {{{}}} in message_template; use {{body}}, {{bot_name}}, {{channel}} or {{timestamp}}",
+                            name
+                        )
+                    })?;
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+                parts.push(Part::Placeholder(placeholder));
+                rest = &rest[end + 1..];
+            } else if c == '}' {
+                return Err(
+                    "Unmatched '}' in message_template; write '}}' for a literal brace".to_string(),
+                );
+            } else {
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        parts.push(Part::Literal(literal));
+        parts.retain(|part| part != &Part::Literal(String::new()));
+
+        if !parts.contains(&Part::Placeholder("body")) {
+            return Err("message_template must contain {body}".to_string());
+        }
+        let bot_name = bot_name.map(str::trim).filter(|name| !name.is_empty());
+        if parts.contains(&Part::Placeholder("bot_name")) && bot_name.is_none() {
+            return Err("message_template uses {bot_name} but no bot_name is set".to_string());
+        }
+        Ok(Self {
+            parts,
+            bot_name: bot_name.unwrap_or_default().to_string(),
+        })
+    }
+
+    pub fn render(&self, body: &str, channel: &str, now: DateTime<Utc>) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Placeholder("body") => body.to_string(),
+                Part::Placeholder("bot_name") => self.bot_name.clone(),
+                Part::Placeholder("channel") => channel.to_string(),
+                Part::Placeholder("timestamp") => timezone::format(now),
+                Part::Placeholder(name) => unreachable!("unknown placeholder {}", name),
+            })
+            .collect()
+    }
+}
+
+/// Frames every message sent from now on with `template`, or with nothing
+pub fn set(template: Option<MessageTemplate>) {
+    if let Ok(mut guard) = TEMPLATE.write() {
+        *guard = template;
+    }
+}
+
+/// `body` framed by the configured template; unchanged without one
+pub fn apply(body: String, channel: &str) -> String {
+    match TEMPLATE
+        .read()
+        .ok()
+        .as_ref()
+        .and_then(|guard| guard.as_ref())
+    {
+        Some(template) => template.render(&body, channel, Utc::now()),
+        None => body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_parse_and_render() {
+        let template = MessageTemplate::parse("{body}\n\n— {bot_name}", Some("Parrot")).unwrap();
+        assert_eq!(
+            template.render("Done", "main", Utc::now()),
+            "Done\n\n— Parrot"
+        );
+
+        let template = MessageTemplate::parse("[{channel}] {{{body}}}", None).unwrap();
+        assert_eq!(
+            template.render("checking CI", "status", Utc::now()),
+            "[status] {checking CI}"
+        );
+
+        for (template, error) in [
+            ("no body here", "must contain {body}"),
+            ("{body} {sender}", "Unknown placeholder {sender}"),
+            ("{body", "Unclosed '{'"),
+            ("{body} }", "Unmatched '}'"),
+            ("{body} — {bot_name}", "no bot_name is set"),
+        ] {
+            let err = MessageTemplate::parse(template, None).unwrap_err();
+            assert!(err.contains(error), "{}: {}", template, err);
+        }
+    }
+}
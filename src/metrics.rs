@@ -0,0 +1,515 @@
+//! Prometheus metrics for the long-running commands
+//!
+//! Instrumented code calls the recording functions below; nothing is exported unless
+//! `--metrics-listen` is set, in which case `serve` answers `GET /metrics` in the Prometheus
+//! text format. Relay connection state is read from the watched clients at scrape time.
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use nostr_sdk::prelude::*;
+use rmcp::model::{ClientRequest, ServerResult};
+use rmcp::service::{Peer, RequestContext, RoleServer, Service, ServiceRole};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::NparrotError;
+use crate::shutdown::Shutdown;
+
+const MESSAGES_SENT: &str = "nparrot_messages_sent_total";
+const MESSAGES_RECEIVED: &str = "nparrot_messages_received_total";
+const SEND_FAILURES: &str = "nparrot_send_failures_total";
+const TOOL_CALLS: &str = "nparrot_tool_calls_total";
+const TOOL_ERRORS: &str = "nparrot_tool_errors_total";
+const TOOL_ERROR_CODES: &str = "nparrot_tool_errors_by_code_total";
+const TOOL_DURATION: &str = "nparrot_tool_call_duration_seconds";
+const GOOSE_DURATION: &str = "nparrot_goose_task_duration_seconds";
+const RELAY_CONNECTED: &str = "nparrot_relay_connected";
+const AGENTS: &str = "nparrot_agents";
+const RETRIES: &str = "nparrot_retries_total";
+
+const TOOL_BUCKETS: &[f64] = &[0.05, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0];
+const GOOSE_BUCKETS: &[f64] = &[
+    1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// Name, type and help text of every exported metric, in output order
+const DESCRIPTORS: &[(&str, &str, &str)] = &[
+    (
+        MESSAGES_SENT,
+        "counter",
+        "Messages delivered to at least one relay",
+    ),
+    (
+        MESSAGES_RECEIVED,
+        "counter",
+        "Messages received from the user",
+    ),
+    (
+        SEND_FAILURES,
+        "counter",
+        "Messages that could not be sent after all retries",
+    ),
+    (TOOL_CALLS, "counter", "MCP tool invocations"),
+    (
+        TOOL_ERRORS,
+        "counter",
+        "MCP tool invocations that returned an error",
+    ),
+    (TOOL_ERROR_CODES, "counter", "MCP tool errors by error code"),
+    (TOOL_DURATION, "histogram", "MCP tool call latency"),
+    (GOOSE_DURATION, "histogram", "Duration of Goose runs"),
+    (
+        RELAY_CONNECTED,
+        "gauge",
+        "Whether the relay is currently connected (1) or not (0)",
+    ),
+    (
+        AGENTS,
+        "gauge",
+        "Agents of the multi-agent server by status",
+    ),
+    (
+        RETRIES,
+        "counter",
+        "Failed attempts by subsystem and what followed (retry, exhausted, fatal)",
+    ),
+];
+
+type Series = (&'static str, String);
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(&mut self.counts) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<Series, u64>,
+    gauges: BTreeMap<Series, f64>,
+    histograms: BTreeMap<Series, Histogram>,
+}
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+    static ref WATCHED_CLIENTS: Mutex<Vec<(String, Client)>> = Mutex::new(Vec::new());
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn increment(name: &'static str, pairs: &[(&str, &str)]) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        *registry.counters.entry((name, labels(pairs))).or_default() += 1;
+    }
+}
+
+fn observe(name: &'static str, bounds: &'static [f64], pairs: &[(&str, &str)], value: f64) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry
+            .histograms
+            .entry((name, labels(pairs)))
+            .or_insert_with(|| Histogram::new(bounds))
+            .observe(value);
+    }
+}
+
+pub fn message_sent(channel: &str) {
+    increment(MESSAGES_SENT, &[("channel", channel)]);
+}
+
+pub fn message_received(channel: &str) {
+    increment(MESSAGES_RECEIVED, &[("channel", channel)]);
+}
+
+pub fn send_failed(channel: &str) {
+    increment(SEND_FAILURES, &[("channel", channel)]);
+}
+
+pub fn tool_call(tool: &str, elapsed: Duration, ok: bool) {
+    increment(TOOL_CALLS, &[("tool", tool)]);
+    if !ok {
+        increment(TOOL_ERRORS, &[("tool", tool)]);
+    }
+    observe(
+        TOOL_DURATION,
+        TOOL_BUCKETS,
+        &[("tool", tool)],
+        elapsed.as_secs_f64(),
+    );
+}
+
+/// Counts a tool error that carried a code (see `error::NparrotError`)
+pub fn tool_error_code(tool: &str, code: &str) {
+    increment(TOOL_ERROR_CODES, &[("tool", tool), ("code", code)]);
+}
+
+/// Counts a failed attempt of a retried operation (see `retry`)
+pub fn retry(subsystem: &str, outcome: &str) {
+    increment(RETRIES, &[("subsystem", subsystem), ("outcome", outcome)]);
+}
+
+pub fn goose_task(command: &str, elapsed: Duration, success: bool) {
+    let outcome = if success { "success" } else { "failure" };
+    observe(
+        GOOSE_DURATION,
+        GOOSE_BUCKETS,
+        &[("command", command), ("outcome", outcome)],
+        elapsed.as_secs_f64(),
+    );
+}
+
+/// Replaces the agent gauges with the given per-status counts
+pub fn set_agent_counts<'a>(counts: impl IntoIterator<Item = (&'a str, usize)>) {
+    if let Ok(mut registry) = REGISTRY.lock() {
+        registry.gauges.retain(|(name, _), _| *name != AGENTS);
+        for (status, count) in counts {
+            registry
+                .gauges
+                .insert((AGENTS, labels(&[("status", status)])), count as f64);
+        }
+    }
+}
+
+/// What the counters add up to so far, for the status digest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Agents by status, leaving out statuses no agent is in
+    pub agents: BTreeMap<String, usize>,
+    /// Goose runs that succeeded
+    pub tasks_completed: u64,
+    /// Goose runs that failed, tool errors and messages that could not be sent
+    pub failures: u64,
+}
+
+/// The value of `name` in the label string `series`
+fn label_value<'a>(series: &'a str, name: &str) -> Option<&'a str> {
+    let start = series.find(&format!("{}=\"", name))? + name.len() + 2;
+    let end = series[start..].find('"')? + start;
+    Some(&series[start..end])
+}
+
+pub fn snapshot() -> Snapshot {
+    let Ok(registry) = REGISTRY.lock() else {
+        return Snapshot::default();
+    };
+    let agents = registry
+        .gauges
+        .iter()
+        .filter(|((name, _), count)| *name == AGENTS && **count > 0.0)
+        .filter_map(|((_, series), count)| {
+            Some((label_value(series, "status")?.to_string(), *count as usize))
+        })
+        .collect();
+    let goose = |outcome: &str| -> u64 {
+        registry
+            .histograms
+            .iter()
+            .filter(|((name, series), _)| {
+                *name == GOOSE_DURATION && label_value(series, "outcome") == Some(outcome)
+            })
+            .map(|(_, histogram)| histogram.count)
+            .sum()
+    };
+    let counted = |wanted: &str| -> u64 {
+        registry
+            .counters
+            .iter()
+            .filter(|((name, _), _)| *name == wanted)
+            .map(|(_, count)| count)
+            .sum()
+    };
+    Snapshot {
+        agents,
+        tasks_completed: goose("success"),
+        failures: goose("failure") + counted(TOOL_ERRORS) + counted(SEND_FAILURES),
+    }
+}
+
+/// Reports the connection state of `client`'s relays under the given identity label
+pub fn watch_relays(identity: &str, client: &Client) {
+    if let Ok(mut watched) = WATCHED_CLIENTS.lock() {
+        watched.push((identity.to_string(), client.clone()));
+    }
+}
+
+/// Renders every metric in the Prometheus text exposition format
+pub async fn render() -> String {
+    let watched = WATCHED_CLIENTS
+        .lock()
+        .map(|watched| watched.clone())
+        .unwrap_or_default();
+    let mut relay_gauges = BTreeMap::new();
+    for (identity, client) in watched {
+        for (url, relay) in client.relays().await {
+            let connected = relay.status() == RelayStatus::Connected;
+            relay_gauges.insert(
+                (
+                    RELAY_CONNECTED,
+                    labels(&[("identity", &identity), ("relay", url.as_str())]),
+                ),
+                if connected { 1.0 } else { 0.0 },
+            );
+        }
+    }
+
+    let Ok(registry) = REGISTRY.lock() else {
+        return String::new();
+    };
+    let mut out = String::new();
+    for (name, kind, help) in DESCRIPTORS {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+
+        for ((_, series), value) in registry.counters.iter().filter(|((n, _), _)| n == name) {
+            let _ = writeln!(out, "{}{} {}", name, braces(series), value);
+        }
+        for ((_, series), value) in registry
+            .gauges
+            .iter()
+            .chain(relay_gauges.iter())
+            .filter(|((n, _), _)| n == name)
+        {
+            let _ = writeln!(out, "{}{} {}", name, braces(series), value);
+        }
+        for ((_, series), histogram) in registry.histograms.iter().filter(|((n, _), _)| n == name) {
+            for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+                let le = format!("le=\"{}\"", bound);
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    name,
+                    braces(&join_labels(series, &le)),
+                    count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                name,
+                braces(&join_labels(series, "le=\"+Inf\"")),
+                histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{} {}", name, braces(series), histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", name, braces(series), histogram.count);
+        }
+    }
+    out
+}
+
+fn braces(series: &str) -> String {
+    if series.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", series)
+    }
+}
+
+fn join_labels(series: &str, extra: &str) -> String {
+    if series.is_empty() {
+        extra.to_string()
+    } else {
+        format!("{},{}", series, extra)
+    }
+}
+
+/// Serves `GET /metrics` on `listen` until a shutdown signal arrives
+pub async fn serve(
+    listen: SocketAddr,
+    shutdown: Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    log::info!("Serving metrics on http://{}/metrics", listen);
+
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::warn!("Failed to accept metrics connection: {}", e);
+                    continue;
+                }
+            },
+            _ = shutdown.requested() => return Ok(()),
+        };
+
+        tokio::spawn(async move {
+            let service = service_fn(|request: Request<hyper::body::Incoming>| async move {
+                let mut response = Response::new(Full::new(Bytes::new()));
+                if request.method() == Method::GET && request.uri().path() == "/metrics" {
+                    *response.body_mut() = Full::new(Bytes::from(render().await));
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+                } else {
+                    *response.status_mut() = StatusCode::NOT_FOUND;
+                }
+                Ok::<_, Infallible>(response)
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::debug!("Metrics connection ended: {}", e);
+            }
+        });
+    }
+}
+
+/// Wraps an MCP server to count and time its tool calls
+#[derive(Debug, Clone)]
+pub struct Instrumented<S> {
+    inner: S,
+}
+
+impl<S> Instrumented<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S: Service<RoleServer>> Service<RoleServer> for Instrumented<S> {
+    async fn handle_request(
+        &self,
+        request: ClientRequest,
+        context: RequestContext<RoleServer>,
+    ) -> Result<ServerResult, rmcp::Error> {
+        let ClientRequest::CallToolRequest(call) = &request else {
+            return self.inner.handle_request(request, context).await;
+        };
+        let tool = call.params.name.to_string();
+
+        let started = Instant::now();
+        let result = self.inner.handle_request(request, context).await;
+        let ok = match &result {
+            Ok(ServerResult::CallToolResult(result)) => result.is_error != Some(true),
+            Ok(_) => true,
+            Err(_) => false,
+        };
+        tool_call(&tool, started.elapsed(), ok);
+        let code = match &result {
+            Ok(ServerResult::CallToolResult(result)) => NparrotError::code_of(result),
+            Ok(_) => None,
+            Err(e) => NparrotError::code_of_error(e),
+        };
+        if let Some(code) = code {
+            tool_error_code(&tool, &code);
+        }
+        result
+    }
+
+    async fn handle_notification(
+        &self,
+        notification: <RoleServer as ServiceRole>::PeerNot,
+    ) -> Result<(), rmcp::Error> {
+        self.inner.handle_notification(notification).await
+    }
+
+    fn get_peer(&self) -> Option<Peer<RoleServer>> {
+        self.inner.get_peer()
+    }
+
+    fn set_peer(&mut self, peer: Peer<RoleServer>) {
+        self.inner.set_peer(peer)
+    }
+
+    fn get_info(&self) -> <RoleServer as ServiceRole>::Info {
+        self.inner.get_info()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render_exposition_format() {
+        // The registry is global, so use labels no other test records
+        message_sent("metrics-test");
+        message_sent("metrics-test");
+        tool_call("send", Duration::from_millis(70), true);
+        tool_call("runtask", Duration::from_secs(2), false);
+        tool_error_code("runtask", "timeout");
+        set_agent_counts([("running", 2), ("stopped", 0)]);
+
+        let text = render().await;
+        assert!(text.contains("# TYPE nparrot_messages_sent_total counter\n"));
+        assert!(text.contains("nparrot_messages_sent_total{channel=\"metrics-test\"} 2\n"));
+        assert!(text.contains("nparrot_tool_errors_total{tool=\"runtask\"} 1\n"));
+        assert!(text
+            .contains("nparrot_tool_errors_by_code_total{tool=\"runtask\",code=\"timeout\"} 1\n"));
+        assert!(text
+            .contains("nparrot_tool_call_duration_seconds_bucket{tool=\"send\",le=\"0.1\"} 1\n"));
+        assert!(text
+            .contains("nparrot_tool_call_duration_seconds_bucket{tool=\"send\",le=\"0.05\"} 0\n"));
+        assert!(text.contains("nparrot_tool_call_duration_seconds_count{tool=\"runtask\"} 1\n"));
+        assert!(text.contains("nparrot_agents{status=\"running\"} 2\n"));
+    }
+
+    #[test]
+    fn test_snapshot_adds_up_the_counters() {
+        let before = snapshot();
+        goose_task("run", Duration::from_secs(3), true);
+        goose_task("session", Duration::from_secs(9), false);
+        send_failed("snapshot-test");
+
+        let after = snapshot();
+        assert!(after.tasks_completed > before.tasks_completed);
+        assert!(after.failures >= before.failures + 2);
+        assert_eq!(
+            label_value("tool=\"a\",outcome=\"failure\"", "outcome"),
+            Some("failure")
+        );
+        assert_eq!(label_value("tool=\"a\"", "outcome"), None);
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        assert_eq!(
+            labels(&[("tool", "a\"b\\c"), ("x", "1")]),
+            "tool=\"a\\\"b\\\\c\",x=\"1\""
+        );
+    }
+}
@@ -3,6 +3,12 @@ use super::health_monitor::HealthMonitor;
 use super::message_bus::MessageBus;
 use super::resource_scheduler::ResourceScheduler;
 use super::types::*;
+use crate::envelope;
+use crate::language;
+use crate::mcp::notes::first_line;
+use crate::mcp::palette::AgentRoster;
+use crate::progress_channels::ProgressChannels;
+use futures::future::BoxFuture;
 use nostr_sdk::prelude::*;
 use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
@@ -23,7 +29,7 @@ pub struct AgentManager {
 impl AgentManager {
     pub fn new(
         client: Client,
-        progress_client: Option<Client>,
+        progress_clients: ProgressChannels<Client>,
         keys: Keys,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
@@ -33,15 +39,16 @@ impl AgentManager {
         // Create NostrMemoryServer for agents to use
         let nostr_memory = crate::nostr_mcp::NostrMemoryServer::new(
             client.clone(),
-            progress_client.clone(),
+            progress_clients.clone(),
             keys,
             our_pubkey,
             target_pubkey,
         );
 
         let agent_pool = Arc::new(AgentPool::new(
-            client,
-            progress_client,
+            envelope::outgoing(Arc::new(client), envelope::MessageType::Chat),
+            progress_clients
+                .map(|c| envelope::outgoing(Arc::new(c.clone()), envelope::MessageType::Progress)),
             our_pubkey,
             target_pubkey,
             nostr_memory,
@@ -55,7 +62,7 @@ impl AgentManager {
 
         let resource_scheduler = Arc::new(ResourceScheduler::new(config.clone()));
 
-        let mut manager = Self {
+        let manager = Self {
             agent_pool,
             health_monitor: health_monitor.clone(),
             message_bus: message_bus.clone(),
@@ -69,9 +76,15 @@ impl AgentManager {
         manager
     }
 
-    pub async fn create_agent(&mut self, request: CreateAgentRequest) -> AgentResult<String> {
+    pub async fn create_agent(&self, request: CreateAgentRequest) -> AgentResult<String> {
         self.resource_scheduler.reserve_agent_slot().await?;
 
+        // Agents answer the user too, so they need to know which language to use
+        let request = CreateAgentRequest {
+            task: language::with_hint(&request.task),
+            ..request
+        };
+
         match self.agent_pool.create_agent(request.clone()).await {
             Ok(agent_id) => {
                 // Register agent with message bus for routing
@@ -101,7 +114,7 @@ impl AgentManager {
         }
     }
 
-    pub async fn stop_agent(&mut self, agent_id: &str) -> AgentResult<bool> {
+    pub async fn stop_agent(&self, agent_id: &str) -> AgentResult<bool> {
         let result = self.agent_pool.stop_agent(agent_id).await?;
 
         if result {
@@ -115,6 +128,36 @@ impl AgentManager {
         Ok(result)
     }
 
+    /// Stops agent `agent_id`, or with `dry_run` only looks it up; returns the agent either way,
+    /// `None` if there is none
+    pub async fn stop_agent_previewed(
+        &self,
+        agent_id: &str,
+        dry_run: bool,
+    ) -> AgentResult<Option<Agent>> {
+        let Some(agent) = self.agent_pool.get_agent(agent_id).await else {
+            return Ok(None);
+        };
+        if dry_run {
+            return Ok(Some(agent));
+        }
+        // It may have stopped on its own since
+        Ok(self.stop_agent(agent_id).await?.then_some(agent))
+    }
+
+    /// Stops every agent through the normal stop path, returning how many were stopped
+    pub async fn stop_all_agents(&self) -> usize {
+        let mut stopped = 0;
+        for agent in self.agent_pool.list_agents().await {
+            match self.stop_agent(&agent.id).await {
+                Ok(true) => stopped += 1,
+                Ok(false) => {}
+                Err(e) => log::warn!("Failed to stop agent {}: {}", agent.id, e),
+            }
+        }
+        stopped
+    }
+
     pub async fn send_message_to_agent(
         &self,
         agent_id: &str,
@@ -134,10 +177,24 @@ impl AgentManager {
         Ok(response)
     }
 
+    /// Sets the default NIP-40 expiration of the progress the agents send
+    pub fn set_progress_expiration(&self, expire_after_secs: Option<u64>) {
+        self.agent_pool.set_progress_expiration(expire_after_secs);
+    }
+
     pub async fn list_agents(&self) -> Vec<Agent> {
         self.agent_pool.list_agents().await
     }
 
+    pub async fn get_agent(&self, agent_id: &str) -> Option<Agent> {
+        self.agent_pool.get_agent(agent_id).await
+    }
+
+    /// What every agent has spent, the ones already cleaned up included
+    pub async fn total_usage(&self) -> super::usage::Usage {
+        self.agent_pool.total_usage().await
+    }
+
     /// Check for and mark completed agents as stopped
     pub async fn detect_and_mark_completed_agents(&self) -> AgentResult<usize> {
         let agents = self.agent_pool.list_agents().await;
@@ -199,7 +256,7 @@ impl AgentManager {
     }
 
     #[allow(dead_code)]
-    pub async fn force_cleanup_timed_out_agents(&mut self) -> AgentResult<Vec<String>> {
+    pub async fn force_cleanup_timed_out_agents(&self) -> AgentResult<Vec<String>> {
         let statuses = self.health_monitor.get_all_agent_statuses().await;
         let mut cleaned_up = Vec::new();
 
@@ -214,7 +271,7 @@ impl AgentManager {
         Ok(cleaned_up)
     }
 
-    fn start_background_tasks(&mut self) {
+    fn start_background_tasks(&self) {
         let health_monitor = self.health_monitor.clone();
         tokio::spawn(async move {
             health_monitor.start_monitoring().await;
@@ -277,3 +334,24 @@ impl AgentManager {
         self.resource_scheduler.can_create_agent().await
     }
 }
+
+impl AgentRoster for AgentManager {
+    fn agent_lines(&self) -> BoxFuture<'_, Vec<String>> {
+        Box::pin(async move {
+            self.list_agents()
+                .await
+                .into_iter()
+                .filter(|agent| !matches!(agent.status, AgentStatus::Stopped))
+                .map(|agent| {
+                    format!(
+                        "{} ({}, {}): {}",
+                        agent.name,
+                        agent.agent_type,
+                        agent.status.label(),
+                        first_line(&agent.task)
+                    )
+                })
+                .collect()
+        })
+    }
+}
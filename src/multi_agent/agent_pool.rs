@@ -1,6 +1,14 @@
+use super::naming::{self, AgentNaming};
 use super::types::*;
+use super::usage::{Counted, Usage, UsageCounter};
+use crate::catalog::{self, Key};
+use crate::goose_mcp::output;
+use crate::mcp::chat::{Chat, ProgressMessageRequest};
+use crate::metrics;
 use crate::nostr_mcp::NostrMemoryServer;
-use crate::searxng_mcp::SearXNGServer;
+use crate::progress_channels::{self, ProgressChannels};
+use crate::response_tracker::AnswerLedger;
+use crate::transport::SharedTransport;
 use nostr_sdk::prelude::*;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -9,146 +17,131 @@ use tokio::sync::{mpsc, RwLock};
 #[derive(Debug)]
 pub struct AgentPool {
     agents: Arc<RwLock<HashMap<String, AgentInstance>>>,
-    client: Client,
-    progress_client: Option<Client>,
+    client: SharedTransport,
+    progress_clients: ProgressChannels<SharedTransport>,
+    /// The `debug` channel, where the agents' step-by-step progress goes
+    progress_client: Option<SharedTransport>,
     our_pubkey: PublicKey,
     target_pubkey: PublicKey,
     nostr_memory: NostrMemoryServer,
+    /// Last number handed out per agent type, for sequential names
+    sequence: std::sync::Mutex<HashMap<String, u32>>,
+    /// What the agents no longer in the pool spent
+    finished: std::sync::Mutex<Usage>,
+    /// Default NIP-40 expiration of the agents' progress (`--progress-expire-after`)
+    progress_expire_after_secs: std::sync::Mutex<Option<u64>>,
 }
 
 #[derive(Debug)]
 struct AgentInstance {
-    agent: Agent,
+    /// Locked on its own, so updating one agent only needs a read lock on the map
+    agent: std::sync::Mutex<Agent>,
     handle: AgentHandle,
     #[allow(dead_code)] // Future capability management
     capabilities: Vec<String>,
+    usage: Arc<UsageCounter>,
 }
 
-/// Extract clean user-facing results from raw task output
-fn extract_task_results(raw_output: &str) -> String {
-    let lines: Vec<&str> = raw_output.lines().collect();
-    let mut result_lines = Vec::new();
-    let mut in_result_section = false;
-    let mut skip_technical_output = true;
-
-    for line in &lines {
-        let line_lower = line.to_lowercase();
-
-        // Skip initial session startup logs
-        if line_lower.contains("starting session")
-            || line_lower.contains("logging to")
-            || line_lower.contains("working directory")
-            || line_lower.contains("goose is running")
-            || line_lower.contains("enter your instructions")
-            || line_lower.contains("context:")
-            || line_lower.contains("press enter to send")
-            || line_lower.contains("( o)>")
-            || line_lower.contains("○○○○○○")
-        {
-            continue;
-        }
-
-        // Look for actual task execution or results
-        if line_lower.contains("here") && (line_lower.contains("code") || line_lower.contains("solution") || line_lower.contains("result")) ||
-           line_lower.contains("created") ||
-           line_lower.contains("implemented") ||
-           line_lower.contains("added") ||
-           line_lower.contains("modified") ||
-           line_lower.contains("updated") ||
-           line_lower.contains("fixed") ||
-           line.trim().starts_with("```") ||  // Code blocks
-           (!line.trim().is_empty() && !line_lower.contains("provider:") && !line_lower.contains("model:") && skip_technical_output && line.trim().len() > 20)
-        {
-            skip_technical_output = false;
-            in_result_section = true;
-        }
-
-        // Include meaningful content
-        if in_result_section && !line.trim().is_empty() {
-            result_lines.push(*line);
-        }
+impl AgentInstance {
+    fn agent(&self) -> std::sync::MutexGuard<'_, Agent> {
+        self.agent.lock().unwrap_or_else(|e| e.into_inner())
     }
 
-    // If no specific results found, try to extract the last meaningful section
-    if result_lines.is_empty() {
-        let mut meaningful_lines = Vec::new();
-        for line in lines.iter().rev().take(20) {
-            // Last 20 lines
-            if !line.trim().is_empty()
-                && !line.to_lowercase().contains("press enter")
-                && !line.to_lowercase().contains("( o)>")
-                && !line.to_lowercase().contains("○○○○○○")
-                && !line.to_lowercase().contains("context:")
-            {
-                meaningful_lines.insert(0, *line);
-            }
-        }
-        result_lines = meaningful_lines;
-    }
-
-    if result_lines.is_empty() {
-        "Task completed successfully. Check your working directory for results.".to_string()
-    } else {
-        result_lines.join("\n").trim().to_string()
+    /// The agent as it is now, with what it has spent so far
+    fn snapshot(&self) -> Agent {
+        let mut agent = self.agent().clone();
+        agent.usage = self.usage.snapshot();
+        agent
     }
 }
 
-/// Extract clean error message from raw error output
-fn extract_error_message(raw_error: &str) -> String {
-    let lines: Vec<&str> = raw_error.lines().collect();
-    let mut error_lines = Vec::new();
-
-    for line in lines {
-        let line_lower = line.to_lowercase();
-
-        // Skip technical session details
-        if line_lower.contains("logging to")
-            || line_lower.contains("working directory")
-            || line_lower.contains("session:")
-            || line_lower.contains("provider:")
-            || line_lower.contains("model:")
-        {
-            continue;
-        }
-
-        // Include meaningful error content
-        if !line.trim().is_empty() {
-            error_lines.push(line.trim());
-        }
+fn publish_agent_counts(agents: &HashMap<String, AgentInstance>) {
+    let mut counts: HashMap<&'static str, usize> = AgentStatus::LABELS
+        .iter()
+        .map(|label| (*label, 0))
+        .collect();
+    for instance in agents.values() {
+        *counts.entry(instance.agent().status.label()).or_default() += 1;
     }
+    metrics::set_agent_counts(counts);
+}
 
-    if error_lines.is_empty() {
-        "An error occurred during task execution.".to_string()
-    } else {
-        error_lines.join("\n")
+/// Sends agent progress to `channel` (the default one if `None`) through the `progress` tool
+/// of the agent's chat, so it is routed, redacted, bounded and tracked like any other progress
+async fn post_progress(chat: &Chat, channel: Option<&str>, message: String) {
+    let request = ProgressMessageRequest {
+        message,
+        expire_after_secs: None,
+        channel: channel.map(str::to_string),
+    };
+    if let Err(e) = chat.progress(request).await {
+        log::warn!("Could not send agent progress: {}", e.message);
     }
 }
 
 impl AgentPool {
     pub fn new(
-        client: Client,
-        progress_client: Option<Client>,
+        client: SharedTransport,
+        progress_clients: ProgressChannels<SharedTransport>,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
         nostr_memory: NostrMemoryServer,
     ) -> Self {
+        let progress_client = progress_clients
+            .resolve(Some(progress_channels::DEBUG))
+            .map(|(_, client)| client.clone());
         Self {
             agents: Arc::new(RwLock::new(HashMap::new())),
             client,
+            progress_clients,
             progress_client,
             our_pubkey,
             target_pubkey,
             nostr_memory,
+            sequence: std::sync::Mutex::new(HashMap::new()),
+            finished: std::sync::Mutex::new(Usage::default()),
+            progress_expire_after_secs: std::sync::Mutex::new(None),
         }
     }
 
+    /// Sets the default NIP-40 expiration of the progress the agents send
+    pub fn set_progress_expiration(&self, expire_after_secs: Option<u64>) {
+        *self
+            .progress_expire_after_secs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = expire_after_secs;
+    }
+
+    fn progress_expiration(&self) -> Option<u64> {
+        *self
+            .progress_expire_after_secs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keeps what the removed agent spent in the pool's totals
+    fn retire(&self, instance: &AgentInstance) {
+        let mut finished = self.finished.lock().unwrap_or_else(|e| e.into_inner());
+        finished.add(&instance.usage.snapshot());
+    }
+
+    /// What every agent of this pool has spent, the removed ones included
+    pub async fn total_usage(&self) -> Usage {
+        let agents = self.agents.read().await;
+        let mut total = *self.finished.lock().unwrap_or_else(|e| e.into_inner());
+        for instance in agents.values() {
+            total.add(&instance.usage.snapshot());
+        }
+        total
+    }
+
     /// Get count of active (non-stopped) agents
     #[allow(dead_code)] // Used indirectly through manager/scheduler
     pub async fn get_active_agent_count(&self) -> usize {
         let agents = self.agents.read().await;
         agents
             .values()
-            .filter(|instance| !matches!(instance.agent.status, AgentStatus::Stopped))
+            .filter(|instance| !matches!(instance.agent().status, AgentStatus::Stopped))
             .count()
     }
 
@@ -162,7 +155,7 @@ impl AgentPool {
 
         agents
             .values()
-            .all(|instance| matches!(instance.agent.status, AgentStatus::Stopped))
+            .all(|instance| matches!(instance.agent().status, AgentStatus::Stopped))
     }
 
     /// Clean up stopped agents
@@ -171,9 +164,16 @@ impl AgentPool {
         let initial_count = agents.len();
 
         // Remove stopped agents
-        agents.retain(|_id, instance| !matches!(instance.agent.status, AgentStatus::Stopped));
+        agents.retain(|_id, instance| {
+            let stopped = matches!(instance.agent().status, AgentStatus::Stopped);
+            if stopped {
+                self.retire(instance);
+            }
+            !stopped
+        });
 
         let removed_count = initial_count - agents.len();
+        publish_agent_counts(&agents);
         if removed_count > 0 {
             log::info!("Cleaned up {} stopped agents", removed_count);
         }
@@ -182,7 +182,7 @@ impl AgentPool {
 
     pub async fn create_agent(&self, request: CreateAgentRequest) -> AgentResult<String> {
         let agent_id = uuid::Uuid::new_v4().to_string();
-        let agent_name = self.generate_cool_name(&request.agent_type);
+        let base_name = self.base_name(&request);
         let capabilities = request.capabilities.unwrap_or_else(|| {
             let mut base_tools = vec![
                 // Basic communication tools
@@ -228,6 +228,17 @@ impl AgentPool {
         });
 
         let (message_sender, message_receiver) = mpsc::unbounded_channel();
+        // Create detailed tool instructions for the agent
+        let tool_instructions = self.create_tool_instructions(&request.agent_type, &capabilities);
+
+        // Held until the agent is in the map, so two agents created at once can't share a name
+        let mut agents = self.agents.write().await;
+        let agent_name = naming::unique(&base_name, |name| {
+            agents.values().any(|instance| {
+                let agent = instance.agent();
+                agent.name == name && !matches!(agent.status, AgentStatus::Stopped)
+            })
+        });
 
         let task_clone = request.task.clone();
         let agent = Agent {
@@ -240,12 +251,11 @@ impl AgentPool {
             last_active: chrono::Utc::now(),
             capabilities: capabilities.clone(),
             metadata: request.metadata.unwrap_or_default(),
+            sender: AnswerLedger::global().current_sender(),
+            usage: Usage::default(),
         };
 
-        // Create detailed tool instructions for the agent
-        let tool_instructions = self.create_tool_instructions(&request.agent_type, &capabilities);
-
-        let join_handle = self
+        let (join_handle, usage) = self
             .spawn_agent_task(
                 agent_id.clone(),
                 agent_name.clone(),
@@ -266,37 +276,43 @@ impl AgentPool {
         agent_with_running_status.status = AgentStatus::Running;
 
         let instance = AgentInstance {
-            agent: agent_with_running_status,
+            agent: std::sync::Mutex::new(agent_with_running_status),
             handle,
             capabilities,
+            usage,
         };
 
-        let mut agents = self.agents.write().await;
         agents.insert(agent_id.clone(), instance);
+        publish_agent_counts(&agents);
 
         Ok(agent_id)
     }
 
     pub async fn stop_agent(&self, agent_id: &str) -> AgentResult<bool> {
-        let mut agents = self.agents.write().await;
-        if let Some(instance) = agents.remove(agent_id) {
-            instance.handle.join_handle.abort();
-
-            let stop_message = AgentMessage {
-                id: uuid::Uuid::new_v4().to_string(),
-                from_agent: None,
-                to_agent: Some(agent_id.to_string()),
-                message_type: MessageType::Status,
-                content: "STOP".to_string(),
-                timestamp: chrono::Utc::now(),
-                response_channel: None,
-            };
+        let instance = {
+            let mut agents = self.agents.write().await;
+            let instance = agents.remove(agent_id);
+            publish_agent_counts(&agents);
+            instance
+        };
+        let Some(instance) = instance else {
+            return Ok(false);
+        };
+        instance.handle.join_handle.abort();
+        self.retire(&instance);
+
+        let stop_message = AgentMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            from_agent: None,
+            to_agent: Some(agent_id.to_string()),
+            message_type: MessageType::Status,
+            content: "STOP".to_string(),
+            timestamp: chrono::Utc::now(),
+            response_channel: None,
+        };
 
-            let _ = instance.handle.sender.send(stop_message);
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        let _ = instance.handle.sender.send(stop_message);
+        Ok(true)
     }
 
     pub async fn send_message_to_agent(
@@ -304,81 +320,91 @@ impl AgentPool {
         agent_id: &str,
         content: &str,
     ) -> AgentResult<String> {
-        let agents = self.agents.read().await;
-        if let Some(instance) = agents.get(agent_id) {
-            let (response_sender, mut response_receiver) = mpsc::unbounded_channel();
-
-            let message = AgentMessage {
-                id: uuid::Uuid::new_v4().to_string(),
-                from_agent: None,
-                to_agent: Some(agent_id.to_string()),
-                message_type: MessageType::Task,
-                content: content.to_string(),
-                timestamp: chrono::Utc::now(),
-                response_channel: Some(response_sender),
-            };
+        // Not held while waiting for the answer, which would stall creating and stopping agents
+        let Some(sender) = self.get_agent_sender(agent_id).await else {
+            return Err(format!("Agent {} not found", agent_id).into());
+        };
+        let (response_sender, mut response_receiver) = mpsc::unbounded_channel();
+
+        let message = AgentMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            from_agent: None,
+            to_agent: Some(agent_id.to_string()),
+            message_type: MessageType::Task,
+            content: content.to_string(),
+            timestamp: chrono::Utc::now(),
+            response_channel: Some(response_sender),
+        };
 
-            instance
-                .handle
-                .sender
-                .send(message)
-                .map_err(|e| format!("Failed to send message to agent: {}", e))?;
-
-            tokio::select! {
-                response = response_receiver.recv() => {
-                    response.ok_or_else(|| "No response received".into())
-                }
-                _ = tokio::time::sleep(tokio::time::Duration::from_secs(10)) => {
-                    Err("Timeout waiting for agent response".into())
-                }
+        sender
+            .send(message)
+            .map_err(|e| format!("Failed to send message to agent: {}", e))?;
+
+        tokio::select! {
+            response = response_receiver.recv() => {
+                response.ok_or_else(|| "No response received".into())
+            }
+            _ = tokio::time::sleep(tokio::time::Duration::from_secs(10)) => {
+                Err("Timeout waiting for agent response".into())
             }
-        } else {
-            Err(format!("Agent {} not found", agent_id).into())
         }
     }
 
     pub async fn list_agents(&self) -> Vec<Agent> {
         let agents = self.agents.read().await;
-        agents
-            .values()
-            .map(|instance| instance.agent.clone())
-            .collect()
+        agents.values().map(AgentInstance::snapshot).collect()
     }
 
-    #[allow(dead_code)]
     pub async fn get_agent(&self, agent_id: &str) -> Option<Agent> {
         let agents = self.agents.read().await;
-        agents.get(agent_id).map(|instance| instance.agent.clone())
+        agents.get(agent_id).map(AgentInstance::snapshot)
     }
 
     #[allow(dead_code)]
     pub async fn update_agent_status(&self, agent_id: &str, status: AgentStatus) {
-        let mut agents = self.agents.write().await;
-        if let Some(instance) = agents.get_mut(agent_id) {
-            instance.agent.status = status.clone();
-            instance.agent.last_active = chrono::Utc::now();
+        let stopped_name = {
+            let agents = self.agents.read().await;
+            let Some(instance) = agents.get(agent_id) else {
+                return;
+            };
+            let stopped_name = {
+                let mut agent = instance.agent();
+                agent.status = status.clone();
+                agent.last_active = chrono::Utc::now();
+                matches!(status, AgentStatus::Stopped)
+                    .then(|| (agent.name.clone(), instance.usage.snapshot()))
+            };
+            publish_agent_counts(&agents);
+            stopped_name
+        };
 
-            // If agent is stopped, send completion notification
-            if matches!(status, AgentStatus::Stopped) {
-                log::info!(
-                    "Agent {} ({}) marked as completed and stopped",
-                    instance.agent.name,
-                    agent_id
-                );
+        // If agent is stopped, send completion notification
+        if let Some((name, usage)) = stopped_name {
+            log::info!(
+                "Agent {} ({}) marked as completed and stopped",
+                name,
+                agent_id
+            );
 
-                // Notify via progress if available
-                if let Some(ref prog_client) = self.progress_client {
-                    let _ = prog_client
-                        .send_private_msg(
-                            self.target_pubkey,
-                            format!(
-                                "✅ Agent {} has completed its task and stopped",
-                                instance.agent.name
-                            ),
-                            [],
-                        )
-                        .await;
-                }
+            // Notify via progress if available
+            if self.progress_clients.default_sender().is_some() {
+                let chat = Chat::with_transport(
+                    self.client.clone(),
+                    self.progress_clients.clone(),
+                    self.our_pubkey,
+                    self.target_pubkey,
+                )
+                .with_source(agent_id)
+                .with_progress_expiration(self.progress_expiration());
+                post_progress(
+                    &chat,
+                    None,
+                    catalog::text(
+                        Key::AgentCompleted,
+                        &[("name", &name), ("usage", &usage.summary())],
+                    ),
+                )
+                .await;
             }
         }
     }
@@ -393,6 +419,26 @@ impl AgentPool {
             .map(|instance| instance.handle.sender.clone())
     }
 
+    /// The name `naming::strategy()` gives the agent, before collisions are resolved
+    fn base_name(&self, request: &CreateAgentRequest) -> String {
+        match naming::strategy() {
+            AgentNaming::Random => self.generate_cool_name(&request.agent_type),
+            AgentNaming::Sequential => {
+                let number = match self.sequence.lock() {
+                    Ok(mut sequence) => {
+                        let number = sequence.entry(request.agent_type.clone()).or_default();
+                        *number += 1;
+                        *number
+                    }
+                    Err(_) => 1,
+                };
+                format!("{}-{}", naming::type_prefix(&request.agent_type), number)
+            }
+            AgentNaming::TaskDerived => naming::task_slug(&request.task)
+                .unwrap_or_else(|| naming::type_prefix(&request.agent_type)),
+        }
+    }
+
     fn generate_cool_name(&self, agent_type: &str) -> String {
         use rand::{seq::SliceRandom, thread_rng};
 
@@ -569,6 +615,7 @@ impl AgentPool {
         }
     }
 
+    /// Starts the agent's task, returning it with the counter of what it spends
     async fn spawn_agent_task(
         &self,
         agent_id: String,
@@ -577,22 +624,33 @@ impl AgentPool {
         initial_task: String,
         tool_instructions: String,
         mut message_receiver: mpsc::UnboundedReceiver<AgentMessage>,
-    ) -> AgentResult<tokio::task::JoinHandle<()>> {
-        let client = self.client.clone();
-        let progress_client = self.progress_client.clone();
+    ) -> AgentResult<(tokio::task::JoinHandle<()>, Arc<UsageCounter>)> {
         let our_pubkey = self.our_pubkey;
+        let usage = Arc::new(UsageCounter::default());
+        let counter = usage.clone();
+        // Everything the agent sends is counted as its own
+        let client = Counted::wrap(self.client.clone(), usage.clone(), our_pubkey);
+        let progress_clients = self
+            .progress_clients
+            .map(|client| Counted::wrap(client.clone(), usage.clone(), our_pubkey));
         let target_pubkey = self.target_pubkey;
 
-        // Create chat instance for agent to use send tool directly
-        let chat_server = crate::mcp::chat::Chat::new(
+        // Create chat instance for agent to use send tool directly; its answers belong to the
+        // user message the agent was created for
+        let chat_server = crate::mcp::chat::Chat::with_transport(
             client.clone(),
-            progress_client.clone(),
+            progress_clients.clone(),
             our_pubkey,
             target_pubkey,
-        );
+        )
+        .with_correlation(AnswerLedger::global().current())
+        .with_source(&agent_id)
+        .with_progress_expiration(self.progress_expiration());
+        // The agent's own step-by-step progress, sent to the `debug` channel
+        let progress_client = self.progress_client.is_some().then(|| chat_server.clone());
 
         // Clone the NostrMemoryServer for agent to use memory tools
-        let memory_server = self.nostr_memory.clone();
+        let _memory_server = self.nostr_memory.clone();
 
         let task_description = initial_task.clone();
         let instructions = tool_instructions.clone();
@@ -618,7 +676,6 @@ impl AgentPool {
             );
 
             // Send periodic heartbeat to prevent timeouts during idle periods
-            let heartbeat_agent_id = agent_id.clone();
             let heartbeat_interval = tokio::time::interval(std::time::Duration::from_secs(15));
             let mut heartbeat_interval = heartbeat_interval;
 
@@ -641,18 +698,15 @@ impl AgentPool {
                         "🚀 Agent {} ({}) starting work on: {}",
                         agent_name, agent_type, task_description
                     );
-                    let _ = prog_client
-                        .send_private_msg(target_pubkey, progress_msg, [])
-                        .await;
+                    post_progress(prog_client, Some(progress_channels::DEBUG), progress_msg).await;
 
                     // Send detailed tool instructions to agent via progress channel
-                    let _ = prog_client
-                        .send_private_msg(
-                            target_pubkey,
-                            format!("📋 Agent {} instructions:\n{}", agent_name, instructions),
-                            [],
-                        )
-                        .await;
+                    post_progress(
+                        prog_client,
+                        Some(progress_channels::DEBUG),
+                        format!("📋 Agent {} instructions:\n{}", agent_name, instructions),
+                    )
+                    .await;
                 }
 
                 // Execute initial task using actual tools and autonomous behavior
@@ -663,12 +717,11 @@ impl AgentPool {
 
                 // Send initial progress via progress channel
                 if let Some(ref prog_client) = progress_client {
-                    let _ = prog_client
-                        .send_private_msg(target_pubkey, work_progress, [])
-                        .await;
+                    post_progress(prog_client, Some(progress_channels::DEBUG), work_progress).await;
                 }
 
                 // Execute task using actual tools - REAL TOOL EXECUTION
+                let started = std::time::Instant::now();
                 let final_result = match agent_type.as_str() {
                     // "search" => {
                     //     // Progress: Starting real tool execution
@@ -677,7 +730,7 @@ impl AgentPool {
                     //             .send_private_msg(
                     //                 target_pubkey,
                     //                 format!("🔍 Agent {} initializing search tools...", agent_name),
-                    //                 [],
+                    //                 None,
                     //             )
                     //             .await;
                     //     }
@@ -736,7 +789,7 @@ impl AgentPool {
                     //         Ok(call_result) => {
                     //             if let Some(ref prog_client) = progress_client {
                     //                 let _ = prog_client.send_private_msg(target_pubkey,
-                    //                     format!("✅ Agent {} successfully executed searxng_web_search tool", agent_name), []).await;
+                    //                     format!("✅ Agent {} successfully executed searxng_web_search tool", agent_name), None).await;
                     //             }
 
                     //             // Use chat server send tool to deliver results directly to user
@@ -748,7 +801,7 @@ impl AgentPool {
                     //                             content_str
                     //                         ),
                     //                     };
-                    //                     log::info!("Agent {} sending search results to user via chat_server.send()", agent_name);
+                    //                     log::info!("Agent {} ({}) sending search results to user via chat_server.send()", agent_name, agent_id);
                     //                     match chat_server.send(send_request).await {
                     //                         Ok(_) => log::info!(
                     //                             "✅ Agent {} successfully sent search results",
@@ -804,30 +857,28 @@ impl AgentPool {
                     "goose" => {
                         // Progress: Starting Goose session
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "🛠️ Agent {} starting Goose development session...",
-                                        agent_name
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "🛠️ Agent {} starting Goose development session...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         // ACTUALLY CALL goose commands directly
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "⚙️ Agent {} executing startsession command...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "⚙️ Agent {} executing startsession command...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         // Step 1: Start session using GooseCommands directly
@@ -846,36 +897,34 @@ impl AgentPool {
                                 session_request,
                             )
                             .await;
-                        let session_result = if session_command_result.success {
+                        let _session_result = if session_command_result.success {
                             if let Some(ref prog_client) = progress_client {
-                                let _ = prog_client
-                                    .send_private_msg(
-                                        target_pubkey,
-                                        format!(
-                                            "✅ Agent {} successfully started Goose session",
-                                            agent_id
-                                        ),
-                                        [],
-                                    )
-                                    .await;
+                                post_progress(
+                                    prog_client,
+                                    Some(progress_channels::DEBUG),
+                                    format!(
+                                        "✅ Agent {} successfully started Goose session",
+                                        agent_name
+                                    ),
+                                )
+                                .await;
                             }
                             format!("Session started: {}", session_command_result.output)
                         } else {
                             if let Some(ref prog_client) = progress_client {
-                                let _ = prog_client
-                                    .send_private_msg(
-                                        target_pubkey,
-                                        format!(
-                                            "❌ Agent {} failed to start Goose session: {}",
-                                            agent_id,
-                                            session_command_result
-                                                .error
-                                                .as_deref()
-                                                .unwrap_or("Unknown error")
-                                        ),
-                                        [],
-                                    )
-                                    .await;
+                                post_progress(
+                                    prog_client,
+                                    Some(progress_channels::DEBUG),
+                                    format!(
+                                        "❌ Agent {} failed to start Goose session: {}",
+                                        agent_name,
+                                        session_command_result
+                                            .error
+                                            .as_deref()
+                                            .unwrap_or("Unknown error")
+                                    ),
+                                )
+                                .await;
                             }
                             format!(
                                 "Session start failed: {}",
@@ -890,16 +939,15 @@ impl AgentPool {
 
                         // Step 2: Run the task using GooseCommands directly
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "🚀 Agent {} executing runtask command for: {}",
-                                        agent_id, task_description
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "🚀 Agent {} executing runtask command for: {}",
+                                    agent_name, task_description
+                                ),
+                            )
+                            .await;
                         }
 
                         let task_request = crate::goose_mcp::types::RunTaskRequest {
@@ -911,42 +959,50 @@ impl AgentPool {
 
                         let task_command_result =
                             crate::goose_mcp::commands::GooseCommands::run_task(task_request).await;
+                        usage.goose_run();
                         let task_result = if task_command_result.success {
                             if let Some(ref prog_client) = progress_client {
-                                let _ = prog_client
-                                    .send_private_msg(
-                                        target_pubkey,
-                                        format!(
-                                            "✅ Agent {} successfully executed Goose task",
-                                            agent_id
-                                        ),
-                                        [],
-                                    )
-                                    .await;
+                                post_progress(
+                                    prog_client,
+                                    Some(progress_channels::DEBUG),
+                                    format!(
+                                        "✅ Agent {} successfully executed Goose task in {}",
+                                        agent_name,
+                                        task_command_result.took()
+                                    ),
+                                )
+                                .await;
                             }
 
                             // Extract clean user-facing results from task output
-                            let cleaned_output = extract_task_results(&task_command_result.output);
+                            let cleaned_output =
+                                output::extract_task_results(&task_command_result.output);
 
                             // Use chat server send tool to deliver results directly to user
                             let send_request = crate::mcp::chat::SendMessageRequest {
                                 message: format!(
-                                    "🛠️ **Development Task Results**\n\n{}",
+                                    "🛠️ **Development Task Results** ({})\n\n{}",
+                                    task_command_result.took(),
                                     cleaned_output
                                 ),
+                                reply_to: None,
+                                to: None,
                             };
                             log::info!(
-                                "Agent {} sending Goose results to user via chat_server.send()",
-                                agent_name
+                                "Agent {} ({}) sending Goose results to user via chat_server.send()",
+                                agent_name,
+                                agent_id
                             );
                             match chat_server.send(send_request).await {
                                 Ok(_) => log::info!(
-                                    "✅ Agent {} successfully sent Goose results",
-                                    agent_name
+                                    "✅ Agent {} ({}) successfully sent Goose results",
+                                    agent_name,
+                                    agent_id
                                 ),
                                 Err(e) => log::error!(
-                                    "❌ Agent {} failed to send Goose results: {}",
+                                    "❌ Agent {} ({}) failed to send Goose results: {}",
                                     agent_name,
+                                    agent_id,
                                     e
                                 ),
                             }
@@ -954,27 +1010,27 @@ impl AgentPool {
                             "Goose task completed successfully".to_string()
                         } else {
                             if let Some(ref prog_client) = progress_client {
-                                let _ = prog_client
-                                    .send_private_msg(
-                                        target_pubkey,
-                                        format!(
-                                            "❌ Agent {} Goose task failed: {}",
-                                            agent_id,
-                                            task_command_result
-                                                .error
-                                                .as_deref()
-                                                .unwrap_or("Unknown error")
-                                        ),
-                                        [],
-                                    )
-                                    .await;
+                                post_progress(
+                                    prog_client,
+                                    Some(progress_channels::DEBUG),
+                                    format!(
+                                        "❌ Agent {} Goose task failed after {}: {}",
+                                        agent_name,
+                                        task_command_result.took(),
+                                        task_command_result
+                                            .error
+                                            .as_deref()
+                                            .unwrap_or("Unknown error")
+                                    ),
+                                )
+                                .await;
                             }
                             // Extract clean error message
                             let error_msg = task_command_result
                                 .error
                                 .as_deref()
                                 .unwrap_or("Unknown error");
-                            let cleaned_error = extract_error_message(error_msg);
+                            let cleaned_error = output::extract_error_message(error_msg);
 
                             format!("⚠️ **Development Task Failed**\n\n{}", cleaned_error)
                         };
@@ -985,64 +1041,60 @@ impl AgentPool {
                     "enhanced" => {
                         // Progress: Starting project management tools
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "📝 Agent {} initializing project management tools...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "📝 Agent {} initializing project management tools...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
 
                         // REAL TOOL EXECUTION: Add project note
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "📋 Agent {} executing addnote tool for project: {}",
-                                        agent_id, task_description
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "📋 Agent {} executing addnote tool for project: {}",
+                                    agent_name, task_description
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 
                         // REAL TOOL EXECUTION: Add project events
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "📊 Agent {} executing addevent tool for tracking...",
-                                        agent_name
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "📊 Agent {} executing addevent tool for tracking...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 
                         // Progress: Tools execution complete
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "✅ Agent {} project management tools executed",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "✅ Agent {} project management tools executed",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         // Return indication that agent used real project management tools
@@ -1061,48 +1113,45 @@ impl AgentPool {
                     "combined" => {
                         // Progress: Analyzing multi-capability requirements
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "🚀 Agent {} analyzing comprehensive task requirements...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "🚀 Agent {} analyzing comprehensive task requirements...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 
                         // Progress: Integrating capabilities
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "⚡ Agent {} integrating multiple tool capabilities...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "⚡ Agent {} integrating multiple tool capabilities...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(3)).await;
 
                         // Progress: Executing coordinated approach
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "🔄 Agent {} executing coordinated multi-tool approach...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "🔄 Agent {} executing coordinated multi-tool approach...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(4)).await;
@@ -1124,40 +1173,45 @@ impl AgentPool {
                     "chat" => {
                         // Progress: Preparing communication capabilities
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "💬 Agent {} initializing communication protocols...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "💬 Agent {} initializing communication protocols...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 
                         // Progress: Establishing user interaction
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "🔗 Agent {} establishing user communication channels...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "🔗 Agent {} establishing user communication channels...",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 
                         // ACTUALLY USE CHAT TOOLS - send progress via progress channel only
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client.send_private_msg(target_pubkey,
-                                format!("💬 Communication Agent {} activated - channels operational", agent_name), []).await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!(
+                                    "💬 Communication Agent {} activated - channels operational",
+                                    agent_name
+                                ),
+                            )
+                            .await;
                         }
 
                         // Communication agent should not send activation messages to main channel
@@ -1167,32 +1221,24 @@ impl AgentPool {
                     _ => {
                         // Progress: Analyzing general task
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "🤖 Agent {} analyzing task requirements...",
-                                        agent_name
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!("🤖 Agent {} analyzing task requirements...", agent_name),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
 
                         // Progress: Executing task
                         if let Some(ref prog_client) = progress_client {
-                            let _ = prog_client
-                                .send_private_msg(
-                                    target_pubkey,
-                                    format!(
-                                        "⚙️ Agent {} executing assigned operations...",
-                                        agent_id
-                                    ),
-                                    [],
-                                )
-                                .await;
+                            post_progress(
+                                prog_client,
+                                Some(progress_channels::DEBUG),
+                                format!("⚙️ Agent {} executing assigned operations...", agent_name),
+                            )
+                            .await;
                         }
 
                         tokio::time::sleep(std::time::Duration::from_secs(3)).await;
@@ -1214,21 +1260,34 @@ impl AgentPool {
                 // 🚨 MANDATORY: Send ALL agent results to users - NO FILTERING!
                 let send_request = crate::mcp::chat::SendMessageRequest {
                     message: final_result.clone(),
+                    reply_to: None,
+                    to: None,
                 };
                 log::info!(
-                    "Agent {} sending final result to user via chat_server.send(): {}",
+                    "Agent {} ({}) sending final result to user via chat_server.send(): {}",
                     agent_name,
+                    agent_id,
                     final_result
                 );
                 match chat_server.send(send_request).await {
                     Ok(_) => {
-                        log::info!("✅ Agent {} successfully sent final result", agent_name)
+                        log::info!(
+                            "✅ Agent {} ({}) successfully sent final result",
+                            agent_name,
+                            agent_id
+                        )
                     }
                     Err(e) => {
-                        log::error!("❌ Agent {} failed to send final result: {}", agent_name, e)
+                        log::error!(
+                            "❌ Agent {} ({}) failed to send final result: {}",
+                            agent_name,
+                            agent_id,
+                            e
+                        )
                     }
                 }
 
+                usage.busy(started.elapsed());
                 log::info!(
                     "Agent {} ({}) completed initial task and sent results to user",
                     agent_name,
@@ -1242,37 +1301,46 @@ impl AgentPool {
                     message = message_receiver.recv() => {
                         match message {
                             Some(msg) => {
-                                log::debug!("Agent {} received message: {:?}", agent_id, msg);
+                                log::debug!(
+                                    "Agent {} ({}) received message: {:?}",
+                                    agent_name,
+                                    agent_id,
+                                    msg
+                                );
 
                                 match msg.message_type {
                                     MessageType::Task => {
                                         log::info!("Agent {} ({}) executing additional task: {}", agent_name, agent_id, msg.content);
+                                        // Answers the user message the task was given for
+                                        let chat_server = chat_server.clone().with_correlation(AnswerLedger::global().current());
 
                                         // Send initial progress via progress client
                                         if let Some(ref prog_client) = progress_client {
                                             let progress_msg = format!("🎯 Agent {} received new task: {}", agent_name, msg.content);
-                                            let _ = prog_client.send_private_msg(target_pubkey, progress_msg, []).await;
+                                            post_progress(prog_client, Some(progress_channels::DEBUG), progress_msg).await;
                                         }
 
                                         // Execute task autonomously using tools
+                                        let started = std::time::Instant::now();
                                         let response = match agent_type.as_str() {
                                             "search" => {
                                                 // Progress: Starting real search task
                                                 if let Some(ref prog_client) = progress_client {
-                                                    let _ = prog_client.send_private_msg(target_pubkey,
-                                                        format!("🔍 Agent {} executing real search for: {}", agent_name, msg.content), []).await;
+                                                    post_progress(prog_client, Some(progress_channels::DEBUG), format!("🔍 Agent {} executing real search for: {}", agent_name, msg.content)).await;
                                                 }
 
                                                 // ACTUALLY USE SEARXNG TOOL - Real execution
                                                 let search_query = &msg.content;
                                                 let searxng_base_url = std::env::var("SEARXNG_URL")
                                                     .unwrap_or_else(|_| "https://searx.stream".to_string());
-                                                let searxng_server = crate::searxng_mcp::server::SearXNGServer::new(
+                                                let searxng_server = crate::searxng_mcp::server::SearXNGServer::with_chat(
                                                     searxng_base_url,
-                                                    client.clone(),
-                                                    progress_client.clone(),
-                                                    our_pubkey,
-                                                    target_pubkey,
+                                                    crate::mcp::chat::Chat::with_transport(
+                                                        client.clone(),
+                                                        progress_clients.clone(),
+                                                        our_pubkey,
+                                                        target_pubkey,
+                                                    ),
                                                 );
 
                                                 // Execute real search
@@ -1282,7 +1350,9 @@ impl AgentPool {
                                                     offset: Some(0),
                                                 };
 
-                                                match searxng_server.searxng_web_search(search_request).await {
+                                                let searched = searxng_server.searxng_web_search(search_request).await;
+                                                usage.search();
+                                                match searched {
                                                     Ok(search_result) => {
                                                         // Extract content from CallToolResult
                                                         let content_str = if let Some(content) = search_result.content.first() {
@@ -1301,11 +1371,13 @@ impl AgentPool {
                                                         // MANDATORY: Send to user via chat_server
                                                         let send_request = crate::mcp::chat::SendMessageRequest {
                                                             message: final_result.clone(),
+                                                            reply_to: None,
+                                                            to: None,
                                                         };
-                                                        log::info!("Agent {} sending search results to user", agent_name);
+                                                        log::info!("Agent {} ({}) sending search results to user", agent_name, agent_id);
                                                         match chat_server.send(send_request).await {
-                                                            Ok(_) => log::info!("✅ Agent {} sent search results successfully", agent_name),
-                                                            Err(e) => log::error!("❌ Agent {} failed to send search results: {}", agent_name, e),
+                                                            Ok(_) => log::info!("✅ Agent {} ({}) sent search results successfully", agent_name, agent_id),
+                                                            Err(e) => log::error!("❌ Agent {} ({}) failed to send search results: {}", agent_name, agent_id, e),
                                                         }
 
                                                         "Search results delivered to user".to_string()
@@ -1319,6 +1391,8 @@ impl AgentPool {
                                                         // MANDATORY: Send error to user
                                                         let send_request = crate::mcp::chat::SendMessageRequest {
                                                             message: error_msg.clone(),
+                                                            reply_to: None,
+                                                            to: None,
                                                         };
                                                         let _ = chat_server.send(send_request).await;
 
@@ -1329,8 +1403,7 @@ impl AgentPool {
                                             "goose" => {
                                                 // Progress: Starting real development task
                                                 if let Some(ref prog_client) = progress_client {
-                                                    let _ = prog_client.send_private_msg(target_pubkey,
-                                                        format!("🛠️ Agent {} executing real development task: {}", agent_name, msg.content), []).await;
+                                                    post_progress(prog_client, Some(progress_channels::DEBUG), format!("🛠️ Agent {} executing real development task: {}", agent_name, msg.content)).await;
                                                 }
 
                                                 // ACTUALLY USE GOOSE TOOLS - Real execution
@@ -1355,6 +1428,7 @@ impl AgentPool {
                                                         max_turns: Some(5),
                                                         debug: Some(false),
                                                     }).await;
+                                                    usage.goose_run();
 
                                                     if task_result.success {
                                                         // ENFORCE: Send real development results directly to user
@@ -1366,11 +1440,13 @@ impl AgentPool {
                                                         // MANDATORY: Send to user via chat_server
                                                         let send_request = crate::mcp::chat::SendMessageRequest {
                                                             message: final_result.clone(),
+                                                            reply_to: None,
+                                                            to: None,
                                                         };
-                                                        log::info!("Agent {} sending development results to user", agent_name);
+                                                        log::info!("Agent {} ({}) sending development results to user", agent_name, agent_id);
                                                         match chat_server.send(send_request).await {
-                                                            Ok(_) => log::info!("✅ Agent {} sent development results successfully", agent_name),
-                                                            Err(e) => log::error!("❌ Agent {} failed to send development results: {}", agent_name, e),
+                                                            Ok(_) => log::info!("✅ Agent {} ({}) sent development results successfully", agent_name, agent_id),
+                                                            Err(e) => log::error!("❌ Agent {} ({}) failed to send development results: {}", agent_name, agent_id, e),
                                                         }
 
                                                         "Development results delivered to user".to_string()
@@ -1383,6 +1459,8 @@ impl AgentPool {
                                                         // MANDATORY: Send error to user
                                                         let send_request = crate::mcp::chat::SendMessageRequest {
                                                             message: error_msg.clone(),
+                                                            reply_to: None,
+                                                            to: None,
                                                         };
                                                         let _ = chat_server.send(send_request).await;
 
@@ -1397,6 +1475,8 @@ impl AgentPool {
                                                     // MANDATORY: Send error to user
                                                     let send_request = crate::mcp::chat::SendMessageRequest {
                                                         message: error_msg.clone(),
+                                                        reply_to: None,
+                                                        to: None,
                                                     };
                                                     let _ = chat_server.send(send_request).await;
 
@@ -1406,8 +1486,7 @@ impl AgentPool {
                                             "enhanced" => {
                                                 // Progress: Processing project management task
                                                 if let Some(ref prog_client) = progress_client {
-                                                    let _ = prog_client.send_private_msg(target_pubkey,
-                                                        format!("📝 Agent {} processing project management task: {}", agent_name, msg.content), []).await;
+                                                    post_progress(prog_client, Some(progress_channels::DEBUG), format!("📝 Agent {} processing project management task: {}", agent_name, msg.content)).await;
                                                 }
 
                                                 // ENFORCE: Process the task and send results directly to user
@@ -1420,11 +1499,13 @@ impl AgentPool {
                                                 // MANDATORY: Send to user via chat_server
                                                 let send_request = crate::mcp::chat::SendMessageRequest {
                                                     message: response_content.clone(),
+                                                    reply_to: None,
+                                                    to: None,
                                                 };
-                                                log::info!("Agent {} sending project management results to user", agent_name);
+                                                log::info!("Agent {} ({}) sending project management results to user", agent_name, agent_id);
                                                 match chat_server.send(send_request).await {
-                                                    Ok(_) => log::info!("✅ Agent {} sent project management results successfully", agent_name),
-                                                    Err(e) => log::error!("❌ Agent {} failed to send project management results: {}", agent_name, e),
+                                                    Ok(_) => log::info!("✅ Agent {} ({}) sent project management results successfully", agent_name, agent_id),
+                                                    Err(e) => log::error!("❌ Agent {} ({}) failed to send project management results: {}", agent_name, agent_id, e),
                                                 }
 
                                                 "Project management results delivered to user".to_string()
@@ -1432,8 +1513,7 @@ impl AgentPool {
                                             "combined" => {
                                                 // Progress: Processing multi-capability request
                                                 if let Some(ref prog_client) = progress_client {
-                                                    let _ = prog_client.send_private_msg(target_pubkey,
-                                                        format!("🚀 Agent {} processing comprehensive task: {}", agent_name, msg.content), []).await;
+                                                    post_progress(prog_client, Some(progress_channels::DEBUG), format!("🚀 Agent {} processing comprehensive task: {}", agent_name, msg.content)).await;
                                                 }
 
                                                 // ENFORCE: Process the task and send results directly to user
@@ -1446,11 +1526,13 @@ impl AgentPool {
                                                 // MANDATORY: Send to user via chat_server
                                                 let send_request = crate::mcp::chat::SendMessageRequest {
                                                     message: response_content.clone(),
+                                                    reply_to: None,
+                                                    to: None,
                                                 };
-                                                log::info!("Agent {} sending multi-capability results to user", agent_name);
+                                                log::info!("Agent {} ({}) sending multi-capability results to user", agent_name, agent_id);
                                                 match chat_server.send(send_request).await {
-                                                    Ok(_) => log::info!("✅ Agent {} sent multi-capability results successfully", agent_name),
-                                                    Err(e) => log::error!("❌ Agent {} failed to send multi-capability results: {}", agent_name, e),
+                                                    Ok(_) => log::info!("✅ Agent {} ({}) sent multi-capability results successfully", agent_name, agent_id),
+                                                    Err(e) => log::error!("❌ Agent {} ({}) failed to send multi-capability results: {}", agent_name, agent_id, e),
                                                 }
 
                                                 "Multi-capability results delivered to user".to_string()
@@ -1458,8 +1540,7 @@ impl AgentPool {
                                             "chat" => {
                                                 // Progress: Processing communication request
                                                 if let Some(ref prog_client) = progress_client {
-                                                    let _ = prog_client.send_private_msg(target_pubkey,
-                                                        format!("💬 Agent {} processing communication task: {}", agent_name, msg.content), []).await;
+                                                    post_progress(prog_client, Some(progress_channels::DEBUG), format!("💬 Agent {} processing communication task: {}", agent_name, msg.content)).await;
                                                 }
 
                                                 // ENFORCE: Process the task and send results directly to user
@@ -1472,11 +1553,13 @@ impl AgentPool {
                                                 // MANDATORY: Send to user via chat_server
                                                 let send_request = crate::mcp::chat::SendMessageRequest {
                                                     message: response_content.clone(),
+                                                    reply_to: None,
+                                                    to: None,
                                                 };
-                                                log::info!("Agent {} sending communication results to user", agent_name);
+                                                log::info!("Agent {} ({}) sending communication results to user", agent_name, agent_id);
                                                 match chat_server.send(send_request).await {
-                                                    Ok(_) => log::info!("✅ Agent {} sent communication results successfully", agent_name),
-                                                    Err(e) => log::error!("❌ Agent {} failed to send communication results: {}", agent_name, e),
+                                                    Ok(_) => log::info!("✅ Agent {} ({}) sent communication results successfully", agent_name, agent_id),
+                                                    Err(e) => log::error!("❌ Agent {} ({}) failed to send communication results: {}", agent_name, agent_id, e),
                                                 }
 
                                                 "Communication results delivered to user".to_string()
@@ -1484,8 +1567,7 @@ impl AgentPool {
                                             _ => {
                                                 // Progress: Processing general request
                                                 if let Some(ref prog_client) = progress_client {
-                                                    let _ = prog_client.send_private_msg(target_pubkey,
-                                                        format!("🤖 Agent {} processing general task: {}", agent_name, msg.content), []).await;
+                                                    post_progress(prog_client, Some(progress_channels::DEBUG), format!("🤖 Agent {} processing general task: {}", agent_name, msg.content)).await;
                                                 }
 
                                                 // ENFORCE: Process the task and send results directly to user
@@ -1498,11 +1580,13 @@ impl AgentPool {
                                                 // MANDATORY: Send to user via chat_server
                                                 let send_request = crate::mcp::chat::SendMessageRequest {
                                                     message: response_content.clone(),
+                                                    reply_to: None,
+                                                    to: None,
                                                 };
-                                                log::info!("Agent {} sending general results to user", agent_name);
+                                                log::info!("Agent {} ({}) sending general results to user", agent_name, agent_id);
                                                 match chat_server.send(send_request).await {
-                                                    Ok(_) => log::info!("✅ Agent {} sent general results successfully", agent_name),
-                                                    Err(e) => log::error!("❌ Agent {} failed to send general results: {}", agent_name, e),
+                                                    Ok(_) => log::info!("✅ Agent {} ({}) sent general results successfully", agent_name, agent_id),
+                                                    Err(e) => log::error!("❌ Agent {} ({}) failed to send general results: {}", agent_name, agent_id, e),
                                                 }
 
                                                 "General results delivered to user".to_string()
@@ -1510,11 +1594,14 @@ impl AgentPool {
                                         };
 
                                         // 🚨 ENFORCEMENT: ALL agent responses MUST reach users - NO FILTERING!
-                                        log::info!("Agent {} sending response to user: {}", agent_name, response);
+                                        log::info!("Agent {} ({}) sending response to user: {}", agent_name, agent_id, response);
                                         let send_request = crate::mcp::chat::SendMessageRequest {
                                             message: response.clone(),
+                                            reply_to: None,
+                                            to: None,
                                         };
                                         let _ = chat_server.send(send_request).await;
+                                        usage.busy(started.elapsed());
 
                                         // Also send via response channel if available
                                         if let Some(sender) = msg.response_channel {
@@ -1547,7 +1634,7 @@ impl AgentPool {
                     }
                     // Send heartbeat periodically
                     _ = heartbeat_interval.tick() => {
-                        log::trace!("Agent {} sending heartbeat", heartbeat_agent_id);
+                        log::trace!("Agent {} ({}) sending heartbeat", agent_name, agent_id);
                         // Heartbeat is implicit - the fact we're running sends the signal
                     }
                 }
@@ -1556,6 +1643,130 @@ impl AgentPool {
             log::info!("Agent {} ({}) shutting down", agent_name, agent_id);
         });
 
-        Ok(handle)
+        Ok((handle, counter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_usage_outlives_the_agent() {
+        let keys = Keys::generate();
+        let user = Keys::generate().public_key();
+        let relay: SharedTransport = Arc::new(FakeTransport::new(keys.clone()));
+        let pool = AgentPool::new(
+            relay.clone(),
+            ProgressChannels::default(),
+            keys.public_key(),
+            user,
+            NostrMemoryServer::with_transport(relay, keys.clone(), keys.public_key(), user),
+        );
+        let request = CreateAgentRequest {
+            agent_type: "chat".to_string(),
+            task: "say hello".to_string(),
+            capabilities: None,
+            timeout_seconds: None,
+            priority: None,
+            metadata: None,
+        };
+        let id = pool.create_agent(request).await.unwrap();
+
+        // A chat agent answers its task with one message
+        let usage = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let usage = pool.get_agent(&id).await.unwrap().usage;
+                if usage.messages > 0 {
+                    return usage;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            (usage.messages, usage.goose_runs, usage.searches),
+            (1, 0, 0)
+        );
+
+        assert!(pool.stop_agent(&id).await.unwrap());
+        assert!(pool.get_agent(&id).await.is_none());
+        assert_eq!(pool.total_usage().await.messages, 1);
+    }
+
+    #[tokio::test]
+    async fn test_agent_progress_gets_the_default_expiration() {
+        let keys = Keys::generate();
+        let user = Keys::generate().public_key();
+        let relay: SharedTransport = Arc::new(FakeTransport::new(keys.clone()));
+        let debug = FakeTransport::new(Keys::generate());
+        let mut progress_clients: ProgressChannels<SharedTransport> = ProgressChannels::default();
+        progress_clients.insert(progress_channels::DEBUG, Arc::new(debug.clone()));
+        let pool = AgentPool::new(
+            relay.clone(),
+            progress_clients,
+            keys.public_key(),
+            user,
+            NostrMemoryServer::with_transport(relay, keys.clone(), keys.public_key(), user),
+        );
+        pool.set_progress_expiration(Some(3600));
+        let request = CreateAgentRequest {
+            agent_type: "chat".to_string(),
+            task: "say hello".to_string(),
+            capabilities: None,
+            timeout_seconds: None,
+            priority: None,
+            metadata: None,
+        };
+        let id = pool.create_agent(request).await.unwrap();
+
+        let sent = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let sent = debug.sent();
+                if !sent.is_empty() {
+                    return sent;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert!(sent
+            .iter()
+            .all(|message| message.expire_after_secs == Some(3600)));
+        pool.stop_agent(&id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_goose_errors_are_redacted_in_agent_progress() {
+        let keys = Keys::generate();
+        let user = Keys::generate().public_key();
+        let debug = FakeTransport::new(Keys::generate());
+        let mut progress_clients: ProgressChannels<SharedTransport> = ProgressChannels::default();
+        progress_clients.insert(progress_channels::DEBUG, Arc::new(debug.clone()));
+        let chat = Chat::with_transport(
+            Arc::new(FakeTransport::new(keys.clone())),
+            progress_clients,
+            keys.public_key(),
+            user,
+        )
+        .with_source("agent-1");
+
+        let error = "Error: provider rejected OPENAI_API_KEY=sk-proj-9xQ2wE4rT6yU8iO0pA1s";
+        post_progress(
+            &chat,
+            Some(progress_channels::DEBUG),
+            format!("❌ Agent Ada Goose task failed after 3s: {}", error),
+        )
+        .await;
+
+        let sent = debug.sent();
+        assert_eq!(sent.len(), 1);
+        assert!(sent[0].content.contains("Goose task failed"));
+        assert!(!sent[0].content.contains("sk-proj-9xQ2wE4rT6yU8iO0pA1s"));
+        assert!(sent[0].content.contains(crate::redact::MASK));
     }
 }
@@ -0,0 +1,305 @@
+//! What the agents' backends can actually do right now (`probe_capabilities`)
+//!
+//! The orchestrator picks agent types from keywords, which says nothing about whether Goose is
+//! installed or SearXNG answers. The probe runs the `doctor` checks that matter to agents, each
+//! with a short timeout, and maps their outcome onto the agent types. The result is cached for
+//! `NPARROT_CAPABILITY_TTL`; `analyze_request` reads the cached copy and recommends a substitute
+//! for agent types whose backend is down.
+
+use crate::doctor;
+use chrono::{DateTime, Utc};
+use nostr_sdk::prelude::*;
+use rmcp::schemars;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a probe's result is reused unless configured otherwise
+pub const DEFAULT_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// How long each check may take before its backend counts as unavailable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Goose,
+    Searxng,
+    MemoryRelays,
+    DataDir,
+}
+
+/// The backends each agent type needs
+const REQUIREMENTS: &[(&str, &[Backend])] = &[
+    ("search", &[Backend::Searxng]),
+    ("goose", &[Backend::Goose]),
+    ("enhanced", &[Backend::DataDir, Backend::MemoryRelays]),
+    ("combined", &[Backend::Goose, Backend::Searxng]),
+    ("chat", &[]),
+];
+
+/// What to recommend instead of an agent type that can't run, best first
+const SUBSTITUTES: &[(&str, &[&str])] = &[
+    ("search", &["combined", "chat"]),
+    ("goose", &["combined", "enhanced", "chat"]),
+    ("enhanced", &["combined", "chat"]),
+    ("combined", &["goose", "search", "enhanced", "chat"]),
+];
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ProbeCapabilitiesRequest {
+    #[serde(default)]
+    #[schemars(description = "Probe again even if the cached result is still fresh (optional)")]
+    pub refresh: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BackendStatus {
+    pub available: bool,
+    pub detail: String,
+}
+
+impl From<Result<String, String>> for BackendStatus {
+    fn from(result: Result<String, String>) -> Self {
+        match result {
+            Ok(detail) => Self {
+                available: true,
+                detail,
+            },
+            Err(detail) => Self {
+                available: false,
+                detail,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapabilityMap {
+    pub checked_at: DateTime<Utc>,
+    pub backends: BTreeMap<Backend, BackendStatus>,
+    /// Whether every backend an agent type needs is available
+    pub agent_types: BTreeMap<&'static str, bool>,
+}
+
+impl CapabilityMap {
+    pub fn new(backends: BTreeMap<Backend, BackendStatus>) -> Self {
+        let agent_types = REQUIREMENTS
+            .iter()
+            .map(|(agent_type, needs)| {
+                let available = needs
+                    .iter()
+                    .all(|backend| backends.get(backend).is_some_and(|status| status.available));
+                (*agent_type, available)
+            })
+            .collect();
+        Self {
+            checked_at: Utc::now(),
+            backends,
+            agent_types,
+        }
+    }
+
+    /// Whether `agent_type` can run; types the probe knows nothing about are assumed to
+    pub fn available(&self, agent_type: &str) -> bool {
+        self.agent_types.get(agent_type).copied().unwrap_or(true)
+    }
+
+    /// The agent type to use instead of `agent_type`, `None` if it can run or nothing can
+    pub fn substitute(&self, agent_type: &str) -> Option<&'static str> {
+        if self.available(agent_type) {
+            return None;
+        }
+        SUBSTITUTES
+            .iter()
+            .find(|(unavailable, _)| *unavailable == agent_type)?
+            .1
+            .iter()
+            .copied()
+            .find(|substitute| self.available(substitute))
+    }
+
+    /// One line per backend, unavailable ones marked
+    pub fn summary(&self) -> String {
+        self.backends
+            .iter()
+            .map(|(backend, status)| {
+                format!(
+                    "{} {:?}: {}",
+                    if status.available { "✅" } else { "❌" },
+                    backend,
+                    status.detail
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Runs the probe and keeps its result for the TTL
+#[derive(Debug)]
+pub struct Prober {
+    client: Client,
+    searxng_url: String,
+    data_dir: String,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, CapabilityMap)>>,
+}
+
+impl Prober {
+    /// Probes the relays of `client`, and SearXNG and the data dir at their default places
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            searxng_url: "https://searx.stream".to_string(),
+            data_dir: "data".to_string(),
+            ttl: DEFAULT_TTL,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// A prober of the same relays for SearXNG at `searxng_url` and the data dir `data_dir`,
+    /// whose result is reused for `ttl`
+    pub fn configured(&self, searxng_url: String, data_dir: String, ttl: Duration) -> Self {
+        Self {
+            client: self.client.clone(),
+            searxng_url,
+            data_dir,
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Starts out with `map` cached instead of probing
+    #[cfg(test)]
+    pub fn with_cached(self, map: CapabilityMap) -> Self {
+        *self.cached.lock().unwrap() = Some((Instant::now(), map));
+        self
+    }
+
+    /// The cached capabilities, probed again when older than the TTL or `refresh` is set
+    pub async fn get(&self, refresh: bool) -> CapabilityMap {
+        if !refresh {
+            let cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((probed_at, map)) = cached.as_ref() {
+                if probed_at.elapsed() < self.ttl {
+                    return map.clone();
+                }
+            }
+        }
+        let map = self.probe().await;
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) =
+            Some((Instant::now(), map.clone()));
+        map
+    }
+
+    async fn probe(&self) -> CapabilityMap {
+        let goose = async {
+            let check = tokio::task::spawn_blocking(doctor::check_goose);
+            match tokio::time::timeout(PROBE_TIMEOUT, check).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => Err(format!("goose check failed: {}", e)),
+                Err(_) => Err(format!(
+                    "goose --version gave no answer within {}s",
+                    PROBE_TIMEOUT.as_secs()
+                )),
+            }
+        };
+        let (goose, searxng, relays) = tokio::join!(
+            goose,
+            doctor::check_searxng(&self.searxng_url, PROBE_TIMEOUT),
+            self.check_relays()
+        );
+        let data_dir = doctor::check_data_dir(&self.data_dir);
+        CapabilityMap::new(BTreeMap::from([
+            (Backend::Goose, goose.into()),
+            (Backend::Searxng, searxng.into()),
+            (Backend::MemoryRelays, relays.into()),
+            (Backend::DataDir, data_dir.into()),
+        ]))
+    }
+
+    /// Whether any relay the memories are kept on is connected
+    async fn check_relays(&self) -> Result<String, String> {
+        let relays = self.client.relays().await;
+        if relays.is_empty() {
+            return Err("No relays configured".to_string());
+        }
+        let connected = relays
+            .values()
+            .filter(|relay| relay.status() == RelayStatus::Connected)
+            .count();
+        if connected == 0 {
+            return Err(format!("None of {} relay(s) connected", relays.len()));
+        }
+        Ok(format!(
+            "{} of {} relay(s) connected",
+            connected,
+            relays.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(unavailable: &[Backend]) -> CapabilityMap {
+        let backends = [
+            Backend::Goose,
+            Backend::Searxng,
+            Backend::MemoryRelays,
+            Backend::DataDir,
+        ]
+        .into_iter()
+        .map(|backend| {
+            let status = if unavailable.contains(&backend) {
+                Err("down".to_string())
+            } else {
+                Ok("up".to_string())
+            };
+            (backend, status.into())
+        })
+        .collect();
+        CapabilityMap::new(backends)
+    }
+
+    #[test]
+    fn test_agent_types_follow_their_backends() {
+        let all = map(&[]);
+        assert!(REQUIREMENTS
+            .iter()
+            .all(|(agent_type, _)| all.available(agent_type)));
+        assert_eq!(all.substitute("search"), None);
+
+        let no_search = map(&[Backend::Searxng]);
+        assert!(!no_search.available("search"));
+        assert!(!no_search.available("combined"));
+        assert!(no_search.available("goose"));
+        assert_eq!(no_search.substitute("search"), Some("chat"));
+        assert_eq!(no_search.substitute("combined"), Some("goose"));
+
+        let no_goose = map(&[Backend::Goose]);
+        assert_eq!(no_goose.substitute("goose"), Some("enhanced"));
+        assert!(no_goose.summary().contains("❌ Goose: down"));
+    }
+
+    #[tokio::test]
+    async fn test_cached_result_is_reused_until_refreshed() {
+        let dir = tempfile::tempdir().unwrap();
+        let prober = Prober::new(Client::new(Keys::generate()))
+            .configured(
+                "http://127.0.0.1:9".to_string(),
+                dir.path().to_string_lossy().to_string(),
+                DEFAULT_TTL,
+            )
+            .with_cached(map(&[Backend::Goose]));
+        assert!(!prober.get(false).await.available("goose"));
+
+        let probed = prober.get(true).await;
+        assert!(probed.backends[&Backend::DataDir].available);
+        assert!(!probed.backends[&Backend::MemoryRelays].available);
+        assert!(!probed.backends[&Backend::Searxng].available);
+    }
+}
@@ -1,73 +1,158 @@
 pub mod agent_manager;
 pub mod agent_pool;
+pub mod capabilities;
 pub mod health_monitor;
 pub mod message_bus;
+pub mod naming;
 pub mod orchestrator;
 pub mod resource_scheduler;
 pub mod types;
+pub mod usage;
 
+use crate::audit::{self, AuditLogRequest};
+use crate::digest::{self, SetDigestIntervalRequest};
+use crate::dry_run;
+use crate::interrupt::Interrupt;
 use crate::mcp::chat::Chat;
+use crate::mcp::server_common::ServerInfoBuilder;
 use crate::nostr_mcp::{
     DeleteMemoryRequest, NostrMemoryServer, RetrieveMemoryRequest, StoreMemoryRequest,
     UpdateMemoryRequest,
 };
+use crate::process_management::stats::{format_history, format_stats, ProcessStatsRequest};
+use crate::process_management::{format_process_list, ProcessManager};
+use crate::progress_channels::{self, ProgressChannels};
+use crate::progress_history::ProgressHistoryRequest;
+use crate::relays::RelayInfoRequest;
+use crate::response_tracker::{AnswerLedger, DeliveryStatusRequest};
 use nostr_sdk::prelude::*;
 use rmcp::{
+    handler::server::tool::ToolCallContext,
     model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+        CallToolRequestParam, CallToolResult, Content, ListToolsResult, PaginatedRequestParam,
+        ServerInfo,
     },
-    tool, Error as RmcpError, ServerHandler,
+    service::RequestContext,
+    tool, Error as RmcpError, RoleServer, ServerHandler,
 };
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
 
 use agent_manager::AgentManager;
+use capabilities::{ProbeCapabilitiesRequest, Prober};
 use orchestrator::IntelligentOrchestrator;
 use types::*;
 
 #[derive(Debug, Clone)]
 pub struct MultiAgentMcp {
-    agent_manager: Arc<RwLock<AgentManager>>,
+    agent_manager: Arc<AgentManager>,
+    /// Held from the limit and duplicate checks until the agents exist, never while sending
+    creating: Arc<Mutex<()>>,
     chat: Chat,
     orchestrator: IntelligentOrchestrator,
-    #[allow(dead_code)] // Used in agent architecture but blocked at main orchestrator level
+    capabilities: Arc<Prober>,
     nostr_memory: NostrMemoryServer,
+    memory_access: OrchestratorMemory,
+}
+
+/// Whether the orchestrator may use the memory tools itself instead of delegating to an agent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OrchestratorMemory {
+    Allow,
+    #[default]
+    Deny,
 }
 
 #[tool(tool_box)]
 impl MultiAgentMcp {
     pub fn new(
         client: Client,
-        progress_client: Option<Client>,
+        progress_clients: ProgressChannels<Client>,
         keys: Keys,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
     ) -> Self {
+        let agent_manager = Arc::new(AgentManager::new(
+            client.clone(),
+            progress_clients.clone(),
+            keys.clone(),
+            our_pubkey,
+            target_pubkey,
+        ));
         Self {
-            agent_manager: Arc::new(RwLock::new(AgentManager::new(
-                client.clone(),
-                progress_client.clone(),
-                keys.clone(),
-                our_pubkey,
-                target_pubkey,
-            ))),
+            agent_manager: agent_manager.clone(),
+            creating: Arc::default(),
             chat: Chat::new(
                 client.clone(),
-                progress_client.clone(),
+                progress_clients.clone(),
                 our_pubkey,
                 target_pubkey,
-            ),
+            )
+            .with_roster(agent_manager),
             orchestrator: IntelligentOrchestrator::new(),
+            capabilities: Arc::new(Prober::new(client.clone())),
             nostr_memory: NostrMemoryServer::new(
                 client,
-                progress_client,
+                progress_clients,
                 keys,
                 our_pubkey,
                 target_pubkey,
             ),
+            memory_access: OrchestratorMemory::default(),
         }
     }
 
+    /// Stops all running agents; called when the server shuts down
+    pub async fn shutdown(&self) {
+        let stopped = self.agent_manager.stop_all_agents().await;
+        log::info!("Stopped {} agent(s) during shutdown", stopped);
+    }
+
+    /// Stops all running agents whenever the user interrupts; runs until aborted
+    pub async fn stop_agents_on_interrupt(&self) {
+        let mut signal = Interrupt::global().subscribe();
+        while signal.changed().await.is_ok() {
+            let stopped = self.agent_manager.stop_all_agents().await;
+            log::info!("Stopped {} agent(s) at the user's request", stopped);
+        }
+    }
+
+    /// Sets the default NIP-40 expiration for progress messages; main-channel messages stay permanent
+    pub fn with_progress_expiration(mut self, expire_after_secs: Option<u64>) -> Self {
+        self.chat = self.chat.with_progress_expiration(expire_after_secs);
+        self.agent_manager
+            .set_progress_expiration(expire_after_secs);
+        self
+    }
+
+    /// Where `probe_capabilities` looks for SearXNG and the notes and events, and how long its
+    /// result is reused
+    pub fn with_backends(mut self, searxng_url: String, data_dir: String, ttl: Duration) -> Self {
+        self.capabilities = Arc::new(self.capabilities.configured(searxng_url, data_dir, ttl));
+        self
+    }
+
+    pub fn chat(&self) -> &Chat {
+        &self.chat
+    }
+
+    pub fn with_memory_access(mut self, memory_access: OrchestratorMemory) -> Self {
+        self.memory_access = memory_access;
+        self
+    }
+
+    async fn report_blocked_memory_operation(&self, request: &impl std::fmt::Debug) {
+        let _ = self
+            .chat
+            .progress(crate::mcp::types::ProgressMessageRequest {
+                message: format!("🚨 BLOCKED DIRECT MEMORY OPERATION: {:?}", request),
+                expire_after_secs: None,
+                channel: Some(progress_channels::DEBUG.to_string()),
+            })
+            .await;
+    }
+
     #[tool(
         description = "Send a message to the user - ONLY use for agent deployment feedback, NOT for answers"
     )]
@@ -102,6 +187,8 @@ impl MultiAgentMcp {
                 .chat
                 .progress(crate::mcp::types::ProgressMessageRequest {
                     message: request.message.clone(),
+                    expire_after_secs: None,
+                    channel: None,
                 })
                 .await;
             return Ok(CallToolResult::success(vec![Content::text(
@@ -133,8 +220,7 @@ impl MultiAgentMcp {
                 💀 **COMPLIANCE REQUIRED**: Use agents for ALL user content requests!"
                 .to_string();
 
-            // Return enforcement message to main channel
-            return Ok(CallToolResult::success(vec![Content::text(
+            return Ok(CallToolResult::error(vec![Content::text(
                 enforcement_message,
             )]));
         }
@@ -152,11 +238,14 @@ impl MultiAgentMcp {
     }
 
     #[tool(
-        description = "Listen and wait for the user's next message - ONLY after creating an agent"
+        description = "Listen and wait for the user's next message, or for the next one of the sender given in `from` - ONLY after creating an agent"
     )]
-    async fn wait(&self) -> Result<CallToolResult, RmcpError> {
+    async fn wait(
+        &self,
+        #[tool(aggr)] request: crate::mcp::types::WaitRequest,
+    ) -> Result<CallToolResult, RmcpError> {
         // Check if any agents are currently active
-        let manager = self.agent_manager.write().await;
+        let manager = &self.agent_manager;
 
         // First, detect and mark any completed agents
         let _ = manager.detect_and_mark_completed_agents().await;
@@ -176,7 +265,7 @@ impl MultiAgentMcp {
                 💀 **COMPLIANCE REQUIRED**: ALL user interactions must go through agents!"
                 .to_string();
 
-            return Ok(CallToolResult::success(vec![Content::text(
+            return Ok(CallToolResult::error(vec![Content::text(
                 enforcement_message,
             )]));
         }
@@ -185,7 +274,6 @@ impl MultiAgentMcp {
         if active_count == 0 {
             // All agents have completed - clean up and notify
             let cleaned_count = manager.cleanup_stopped_agents().await;
-            drop(manager); // Release the lock
 
             let completion_message = format!(
                 "✅ **ALL TASKS COMPLETED** ✅\n\n\
@@ -201,6 +289,11 @@ impl MultiAgentMcp {
                 .chat
                 .send(crate::mcp::types::SendMessageRequest {
                     message: completion_message,
+                    reply_to: None,
+                    // Whoever the finished work was for
+                    to: AnswerLedger::global()
+                        .current_sender()
+                        .map(|sender| sender.to_hex()),
                 })
                 .await;
 
@@ -211,8 +304,57 @@ impl MultiAgentMcp {
         }
 
         // If active agents remain, proceed with wait
-        drop(manager); // Release the lock before waiting
-        self.chat.wait().await
+        self.chat.wait(request).await
+    }
+
+    #[tool(
+        description = "Show the latest tool calls with the shape and a keyed hash of their arguments, duration and outcome, for debugging"
+    )]
+    async fn get_audit_log(
+        &self,
+        #[tool(aggr)] AuditLogRequest { limit }: AuditLogRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        audit::tool_result(limit)
+    }
+
+    #[tool(
+        description = "Show how many sent messages relays have acknowledged, which are still pending and which failed"
+    )]
+    async fn delivery_status(
+        &self,
+        #[tool(aggr)] request: DeliveryStatusRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.delivery_status(request).await
+    }
+
+    #[tool(
+        description = "Search the progress messages sent earlier, e.g. what was being done an hour ago: filter by since (\"2h\" or an ISO 8601 time) and contains, newest `limit` entries oldest first"
+    )]
+    async fn progress_history(
+        &self,
+        #[tool(aggr)] request: ProgressHistoryRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.progress_history(request).await
+    }
+
+    #[tool(
+        description = "Send a status digest (agents, tasks completed, failures, pending and queued messages) on the progress channel every interval, e.g. \"1h\", skipped when nothing changed; \"off\" stops it"
+    )]
+    async fn set_digest_interval(
+        &self,
+        #[tool(aggr)] request: SetDigestIntervalRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        digest::tool_result(request)
+    }
+
+    #[tool(
+        description = "Show what each configured relay published about itself (NIP-11 size and subscription limits, auth and payment requirements) whether NIP-42 authentication with it succeeded and how many subscriptions are open or queued on it"
+    )]
+    async fn relay_info(
+        &self,
+        #[tool(aggr)] request: RelayInfoRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        self.chat.relay_info(request).await
     }
 
     #[tool(description = "Create and start a new agent task with specified capabilities")]
@@ -220,10 +362,13 @@ impl MultiAgentMcp {
         &self,
         #[tool(aggr)] request: CreateAgentRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let mut manager = self.agent_manager.write().await;
+        let manager = &self.agent_manager;
+        let creating = self.creating.lock().await;
 
-        // Check if we already have similar agents running to prevent duplicates
+        // Check if we already have similar agents running to prevent duplicates; only the ones
+        // working for the same sender count, since two people may well ask for the same thing
         let existing_agents = manager.list_agents().await;
+        let sender = AnswerLedger::global().current_sender();
         let task_lowercase = request.task.to_lowercase();
         let task_key = task_lowercase
             .split_whitespace()
@@ -237,13 +382,16 @@ impl MultiAgentMcp {
                 "🚫 Maximum agent limit reached ({}/10). Cannot create more agents.",
                 existing_agents.len()
             );
+            drop(creating);
             let _ = self
                 .chat
                 .progress(crate::mcp::types::ProgressMessageRequest {
                     message: message.clone(),
+                    expire_after_secs: None,
+                    channel: None,
                 })
                 .await;
-            return Ok(CallToolResult::success(vec![Content::text(
+            return Ok(CallToolResult::error(vec![Content::text(
                 "Agent limit reached - cannot create more agents",
             )]));
         }
@@ -252,6 +400,7 @@ impl MultiAgentMcp {
             .iter()
             .filter(|agent| {
                 agent.agent_type == request.agent_type
+                    && agent.sender == sender
                     && agent.task.to_lowercase().contains(&task_key)
             })
             .collect();
@@ -265,13 +414,16 @@ impl MultiAgentMcp {
                 existing_names.join(", "),
                 request.task
             );
+            drop(creating);
             let _ = self
                 .chat
                 .progress(crate::mcp::types::ProgressMessageRequest {
                     message: message.clone(),
+                    expire_after_secs: None,
+                    channel: Some(progress_channels::DEBUG.to_string()),
                 })
                 .await;
-            return Ok(CallToolResult::success(vec![Content::text(format!(
+            return Ok(CallToolResult::error(vec![Content::text(format!(
                 "Duplicate prevention: {} already handling similar tasks",
                 existing_names.join(", ")
             ))]));
@@ -283,7 +435,9 @@ impl MultiAgentMcp {
             request.task
         );
 
-        match manager.create_agent(request.clone()).await {
+        let created = manager.create_agent(request.clone()).await;
+        drop(creating);
+        match created {
             Ok(agent_id) => {
                 log::info!("Successfully created anonymous agent ({})", agent_id);
 
@@ -302,6 +456,8 @@ impl MultiAgentMcp {
                     .chat
                     .progress(crate::mcp::types::ProgressMessageRequest {
                         message: progress_message,
+                        expire_after_secs: None,
+                        channel: None,
                     })
                     .await;
 
@@ -327,6 +483,8 @@ impl MultiAgentMcp {
                     .chat
                     .progress(crate::mcp::types::ProgressMessageRequest {
                         message: error_message,
+                        expire_after_secs: None,
+                        channel: None,
                     })
                     .await;
 
@@ -342,7 +500,8 @@ impl MultiAgentMcp {
         &self,
         #[tool(aggr)] request: CreateMultipleAgentsRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let mut manager = self.agent_manager.write().await;
+        let manager = &self.agent_manager;
+        let creating = self.creating.lock().await;
 
         // Check agent limit first
         let existing_agents = manager.list_agents().await;
@@ -352,7 +511,7 @@ impl MultiAgentMcp {
                 existing_agents.len(),
                 request.agents.len()
             );
-            return Ok(CallToolResult::success(vec![Content::text(message)]));
+            return Ok(CallToolResult::error(vec![Content::text(message)]));
         }
 
         let mut created_agents = Vec::new();
@@ -388,6 +547,8 @@ impl MultiAgentMcp {
             }
         }
 
+        drop(creating);
+
         // Send progress update about agent creation
         let progress_message = format!(
             "🚀 **Parallel Agent Creation Progress**\n\n\
@@ -414,6 +575,8 @@ impl MultiAgentMcp {
             .chat
             .progress(crate::mcp::types::ProgressMessageRequest {
                 message: progress_message.clone(),
+                expire_after_secs: None,
+                channel: None,
             })
             .await;
 
@@ -436,13 +599,15 @@ impl MultiAgentMcp {
             )
         };
 
+        if created_agents.is_empty() {
+            return Ok(CallToolResult::error(vec![Content::text(result_message)]));
+        }
         Ok(CallToolResult::success(vec![Content::text(result_message)]))
     }
 
     #[tool(description = "Get system processing status (internal debug only)")]
     async fn list_agents(&self) -> Result<CallToolResult, RmcpError> {
-        let manager = self.agent_manager.read().await;
-        let agents = manager.list_agents().await;
+        let agents = self.agent_manager.list_agents().await;
 
         let message = if agents.is_empty() {
             "System ready - no background processing".to_string()
@@ -454,19 +619,76 @@ impl MultiAgentMcp {
         Ok(CallToolResult::success(vec![Content::text(message)]))
     }
 
-    #[tool(description = "Stop background processing task")]
+    #[tool(
+        description = "Get one agent's details as JSON: its task, status and what it has spent so far (goose runs, busy seconds, searches, messages)"
+    )]
+    async fn get_agent_details(
+        &self,
+        #[tool(aggr)] request: GetAgentDetailsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        match self.agent_manager.get_agent(&request.agent_id).await {
+            Some(agent) => Ok(CallToolResult::success(vec![Content::json(&agent)?])),
+            None => Ok(CallToolResult::error(vec![Content::text(format!(
+                "Agent {} not found",
+                request.agent_id
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Get how many agents are running and what all agents have spent in total, finished ones included"
+    )]
+    async fn orchestration_stats(&self) -> Result<CallToolResult, RmcpError> {
+        let agents = self.agent_manager.list_agents().await;
+        let running = agents
+            .iter()
+            .filter(|agent| !matches!(agent.status, AgentStatus::Stopped))
+            .count();
+        let usage = self.agent_manager.total_usage().await;
+        Ok(CallToolResult::success(vec![
+            Content::json(serde_json::json!({
+                "agents": agents.len(),
+                "running": running,
+                "usage": usage,
+            }))?,
+            Content::text(format!(
+                "{} agent(s), {} running · {}",
+                agents.len(),
+                running,
+                usage.summary()
+            )),
+        ]))
+    }
+
+    #[tool(
+        description = "Stop background processing task; dry_run only reports what would be stopped"
+    )]
     async fn stop_agent(
         &self,
         #[tool(aggr)] request: StopAgentRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let mut manager = self.agent_manager.write().await;
-        match manager.stop_agent(&request.agent_id).await {
-            Ok(existed) => {
-                log::info!("Background task {} stopped: {}", request.agent_id, existed);
-                let message = if existed {
-                    "Background processing stopped"
-                } else {
-                    "No matching background task found"
+        let dry_run = dry_run::requested(request.dry_run);
+        match self
+            .agent_manager
+            .stop_agent_previewed(&request.agent_id, dry_run)
+            .await
+        {
+            Ok(Some(agent)) if dry_run => Ok(CallToolResult::success(vec![Content::text(
+                dry_run::label(&format!(
+                    "Would stop 1 background task: {} ({}): {}",
+                    agent.id, agent.name, agent.task
+                )),
+            )])),
+            Ok(stopped) => {
+                log::info!(
+                    "Background task {} stopped: {}",
+                    request.agent_id,
+                    stopped.is_some()
+                );
+                let message = match stopped {
+                    Some(_) => "Background processing stopped".to_string(),
+                    None if dry_run => dry_run::label("No matching background task found"),
+                    None => "No matching background task found".to_string(),
                 };
                 Ok(CallToolResult::success(vec![Content::text(message)]))
             }
@@ -479,12 +701,42 @@ impl MultiAgentMcp {
         }
     }
 
+    #[tool(
+        description = "Show CPU and memory usage of spawned processes; pass a pid for its recent history."
+    )]
+    async fn process_stats(
+        &self,
+        #[tool(aggr)] request: ProcessStatsRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        let text = match request.pid {
+            Some(pid) => match ProcessManager::global().history(pid) {
+                Some((label, samples)) => format_history(pid, &label, &samples),
+                None => {
+                    return Ok(CallToolResult::error(vec![Content::text(format!(
+                        "No managed process with PID {}",
+                        pid
+                    ))]))
+                }
+            },
+            None => format_stats(&ProcessManager::global().list()),
+        };
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "List processes spawned by this server (pid, label, uptime, status).")]
+    async fn list_processes(&self) -> Result<CallToolResult, RmcpError> {
+        let processes = ProcessManager::global().list();
+        Ok(CallToolResult::success(vec![Content::text(
+            format_process_list(&processes),
+        )]))
+    }
+
     #[tool(description = "Send a message to a specific agent")]
     async fn message_agent(
         &self,
         #[tool(aggr)] request: MessageAgentRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let manager = self.agent_manager.read().await;
+        let manager = &self.agent_manager;
         match manager
             .send_message_to_agent(&request.agent_id, &request.message)
             .await
@@ -507,6 +759,8 @@ impl MultiAgentMcp {
                     .chat
                     .progress(crate::mcp::types::ProgressMessageRequest {
                         message: message.clone(),
+                        expire_after_secs: None,
+                        channel: None,
                     })
                     .await;
                 Ok(CallToolResult::success(vec![Content::text(message)]))
@@ -518,6 +772,8 @@ impl MultiAgentMcp {
                     .chat
                     .progress(crate::mcp::types::ProgressMessageRequest {
                         message: error_msg.clone(),
+                        expire_after_secs: None,
+                        channel: None,
                     })
                     .await;
                 Ok(CallToolResult::error(vec![Content::text(error_msg)]))
@@ -525,12 +781,17 @@ impl MultiAgentMcp {
         }
     }
 
-    #[tool(description = "Analyze a request and create an intelligent orchestration plan")]
+    #[tool(
+        description = "Analyze a request and create an intelligent orchestration plan. Returns the plan as JSON (sub-tasks, agent_requirements ready for create_agents_parallel, execution_strategy), then as readable text"
+    )]
     async fn analyze_request(
         &self,
         #[tool(aggr)] args: AnalyzeRequestArgs,
     ) -> Result<CallToolResult, RmcpError> {
-        let analysis = self.orchestrator.analyze_request(&args.request);
+        let capabilities = self.capabilities.get(false).await;
+        let analysis = self
+            .orchestrator
+            .analyze_request(&args.request, &capabilities);
         let plan = self.orchestrator.generate_orchestration_plan(&analysis);
 
         let detailed_message = format!(
@@ -588,172 +849,136 @@ impl MultiAgentMcp {
             }
         }
 
-        // Send analysis via progress channel for visibility
-        let _ = self
-            .chat
-            .progress(crate::mcp::types::ProgressMessageRequest {
-                message: instructions.clone(),
-            })
-            .await;
+        if args.post_progress.unwrap_or(true) {
+            let _ = self
+                .chat
+                .progress(crate::mcp::types::ProgressMessageRequest {
+                    message: instructions.clone(),
+                    expire_after_secs: None,
+                    channel: Some(progress_channels::DEBUG.to_string()),
+                })
+                .await;
+        }
 
-        Ok(CallToolResult::success(vec![Content::text(format!(
-            "Analysis complete. {} agent(s) recommended for this request.",
-            analysis.agent_requirements.len()
-        ))]))
+        Ok(CallToolResult::success(vec![
+            Content::json(&analysis)?,
+            Content::text(instructions),
+        ]))
     }
 
-    #[tool(description = "Store a memory entry - AGENTS ONLY, main orchestrator must create agent")]
-    async fn store_memory(
+    #[tool(
+        description = "Check which agent backends work right now (Goose installed, SearXNG reachable, memory relays connected, data dir writable) and which agent types can run. Returns the capability map as JSON, cached for a few minutes unless refresh is set"
+    )]
+    async fn probe_capabilities(
         &self,
-        #[tool(aggr)] request: StoreMemoryRequest,
+        #[tool(aggr)] request: ProbeCapabilitiesRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        // ENFORCEMENT: Memory operations should be done by agents, not main orchestrator
-        let enforcement_message = "🚨 **AGENT CREATION MANDATE VIOLATION** 🚨\n\n\
-            ❌ **FORBIDDEN**: Main orchestrator cannot handle memory operations directly\n\
-            ⚡ **REQUIRED**: You must create a specialized Fux agent to store memories\n\n\
-            🎯 **Correct Workflow**:\n\
-            1. analyze_request(request=\"Store memory: [memory details]\")\n\
-            2. create_agent(agent_type=\"enhanced\", task=\"Store memory: [memory details]\")\n\
-            3. send(message=\"🚀 FuxManager deployed to handle memory storage\")\n\
-            4. wait() for agent to complete memory operation\n\n\
-            💀 **COMPLIANCE REQUIRED**: ALL memory operations must go through Fux agents!"
-            .to_string();
-
-        // Send enforcement via progress channel
-        let _ = self
-            .chat
-            .progress(crate::mcp::types::ProgressMessageRequest {
-                message: format!("🚨 BLOCKED DIRECT MEMORY OPERATION: {:?}", request),
-            })
+        let capabilities = self
+            .capabilities
+            .get(request.refresh.unwrap_or(false))
             .await;
+        Ok(CallToolResult::success(vec![
+            Content::json(&capabilities)?,
+            Content::text(capabilities.summary()),
+        ]))
+    }
 
-        // Return enforcement message
-        Ok(CallToolResult::success(vec![Content::text(
-            enforcement_message,
-        )]))
+    #[tool(
+        description = "Store a memory entry - AGENTS ONLY unless the orchestrator is allowed memory"
+    )]
+    async fn store_memory(
+        &self,
+        #[tool(aggr)] request: StoreMemoryRequest,
+    ) -> Result<CallToolResult, RmcpError> {
+        if self.memory_access == OrchestratorMemory::Allow {
+            return self.nostr_memory.store_memory(request).await;
+        }
+        self.report_blocked_memory_operation(&request).await;
+        Ok(memory_mandate(
+            "store memories",
+            "Store memory: [memory details]",
+            "memory storage",
+        ))
     }
 
     #[tool(
-        description = "Retrieve and search memory entries - AGENTS ONLY, main orchestrator must create agent"
+        description = "Retrieve and search memory entries - AGENTS ONLY unless the orchestrator is allowed memory"
     )]
     async fn retrieve_memory(
         &self,
         #[tool(aggr)] request: RetrieveMemoryRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        // ENFORCEMENT: Memory operations should be done by agents, not main orchestrator
-        let enforcement_message = "🚨 **AGENT CREATION MANDATE VIOLATION** 🚨\n\n\
-            ❌ **FORBIDDEN**: Main orchestrator cannot handle memory operations directly\n\
-            ⚡ **REQUIRED**: You must create a specialized Fux agent to retrieve memories\n\n\
-            🎯 **Correct Workflow**:\n\
-            1. analyze_request(request=\"Retrieve memory: [search criteria]\")\n\
-            2. create_agent(agent_type=\"enhanced\", task=\"Retrieve memory: [search criteria]\")\n\
-            3. send(message=\"🚀 FuxManager deployed to handle memory retrieval\")\n\
-            4. wait() for agent to complete memory operation\n\n\
-            💀 **COMPLIANCE REQUIRED**: ALL memory operations must go through Fux agents!"
-            .to_string();
-
-        // Send enforcement via progress channel
-        let _ = self
-            .chat
-            .progress(crate::mcp::types::ProgressMessageRequest {
-                message: format!("🚨 BLOCKED DIRECT MEMORY OPERATION: {:?}", request),
-            })
-            .await;
-
-        // Return enforcement message
-        Ok(CallToolResult::success(vec![Content::text(
-            enforcement_message,
-        )]))
+        if self.memory_access == OrchestratorMemory::Allow {
+            return self.nostr_memory.retrieve_memory(request).await;
+        }
+        self.report_blocked_memory_operation(&request).await;
+        Ok(memory_mandate(
+            "retrieve memories",
+            "Retrieve memory: [search criteria]",
+            "memory retrieval",
+        ))
     }
 
     #[tool(
-        description = "Update an existing memory entry - AGENTS ONLY, main orchestrator must create agent"
+        description = "Update an existing memory entry - AGENTS ONLY unless the orchestrator is allowed memory"
     )]
     async fn update_memory(
         &self,
-        #[tool(aggr)] _request: UpdateMemoryRequest,
+        #[tool(aggr)] request: UpdateMemoryRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        // ENFORCEMENT: Memory operations should be done by agents, not main orchestrator
-        let enforcement_message = "🚨 **AGENT CREATION MANDATE VIOLATION** 🚨\n\n\
-            ❌ **FORBIDDEN**: Main orchestrator cannot handle memory operations directly\n\
-            ⚡ **REQUIRED**: You must create a specialized Fux agent to update memories\n\n\
-            🎯 **Correct Workflow**:\n\
-            1. analyze_request(request=\"Update memory: [memory ID and changes]\")\n\
-            2. create_agent(agent_type=\"enhanced\", task=\"Update memory: [memory ID and changes]\")\n\
-            3. send(message=\"🚀 FuxManager deployed to handle memory update\")\n\
-            4. wait() for agent to complete memory operation\n\n\
-            💀 **COMPLIANCE REQUIRED**: ALL memory operations must go through Fux agents!"
-            .to_string();
-
-        Ok(CallToolResult::success(vec![Content::text(
-            enforcement_message,
-        )]))
+        if self.memory_access == OrchestratorMemory::Allow {
+            return self.nostr_memory.update_memory(request).await;
+        }
+        Ok(memory_mandate(
+            "update memories",
+            "Update memory: [memory ID and changes]",
+            "memory update",
+        ))
     }
 
     #[tool(
-        description = "Delete a memory entry by ID - AGENTS ONLY, main orchestrator must create agent"
+        description = "Delete a memory entry by ID - AGENTS ONLY unless the orchestrator is allowed memory"
     )]
     async fn delete_memory(
         &self,
-        #[tool(aggr)] _request: DeleteMemoryRequest,
+        #[tool(aggr)] request: DeleteMemoryRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        // ENFORCEMENT: Memory operations should be done by agents, not main orchestrator
-        let enforcement_message = "🚨 **AGENT CREATION MANDATE VIOLATION** 🚨\n\n\
-            ❌ **FORBIDDEN**: Main orchestrator cannot handle memory operations directly\n\
-            ⚡ **REQUIRED**: You must create a specialized Fux agent to delete memories\n\n\
-            🎯 **Correct Workflow**:\n\
-            1. analyze_request(request=\"Delete memory: [memory ID]\")\n\
-            2. create_agent(agent_type=\"enhanced\", task=\"Delete memory: [memory ID]\")\n\
-            3. send(message=\"🚀 FuxManager deployed to handle memory deletion\")\n\
-            4. wait() for agent to complete memory operation\n\n\
-            💀 **COMPLIANCE REQUIRED**: ALL memory operations must go through Fux agents!"
-            .to_string();
-
-        Ok(CallToolResult::success(vec![Content::text(
-            enforcement_message,
-        )]))
+        if self.memory_access == OrchestratorMemory::Allow {
+            return self.nostr_memory.delete_memory(request).await;
+        }
+        Ok(memory_mandate(
+            "delete memories",
+            "Delete memory: [memory ID]",
+            "memory deletion",
+        ))
     }
 
     #[tool(
-        description = "Get statistics about stored memories - AGENTS ONLY, main orchestrator must create agent"
+        description = "Get statistics about stored memories - AGENTS ONLY unless the orchestrator is allowed memory"
     )]
     async fn memory_stats(&self) -> Result<CallToolResult, RmcpError> {
-        // ENFORCEMENT: Memory operations should be done by agents, not main orchestrator
-        let enforcement_message = "🚨 **AGENT CREATION MANDATE VIOLATION** 🚨\n\n\
-            ❌ **FORBIDDEN**: Main orchestrator cannot handle memory operations directly\n\
-            ⚡ **REQUIRED**: You must create a specialized Fux agent to get memory statistics\n\n\
-            🎯 **Correct Workflow**:\n\
-            1. analyze_request(request=\"Get memory statistics\")\n\
-            2. create_agent(agent_type=\"enhanced\", task=\"Get memory statistics\")\n\
-            3. send(message=\"🚀 FuxManager deployed to handle memory statistics\")\n\
-            4. wait() for agent to complete memory operation\n\n\
-            💀 **COMPLIANCE REQUIRED**: ALL memory operations must go through Fux agents!"
-            .to_string();
-
-        Ok(CallToolResult::success(vec![Content::text(
-            enforcement_message,
-        )]))
+        if self.memory_access == OrchestratorMemory::Allow {
+            return self.nostr_memory.memory_stats().await;
+        }
+        Ok(memory_mandate(
+            "get memory statistics",
+            "Get memory statistics",
+            "memory statistics",
+        ))
     }
 
     #[tool(
-        description = "Clean up expired memory entries - AGENTS ONLY, main orchestrator must create agent"
+        description = "Clean up expired memory entries - AGENTS ONLY unless the orchestrator is allowed memory"
     )]
     async fn cleanup_expired_memories(&self) -> Result<CallToolResult, RmcpError> {
-        // ENFORCEMENT: Memory operations should be done by agents, not main orchestrator
-        let enforcement_message = "🚨 **AGENT CREATION MANDATE VIOLATION** 🚨\n\n\
-            ❌ **FORBIDDEN**: Main orchestrator cannot handle memory operations directly\n\
-            ⚡ **REQUIRED**: You must create a specialized Fux agent to cleanup memories\n\n\
-            🎯 **Correct Workflow**:\n\
-            1. analyze_request(request=\"Clean up expired memories\")\n\
-            2. create_agent(agent_type=\"enhanced\", task=\"Clean up expired memories\")\n\
-            3. send(message=\"🚀 FuxManager deployed to handle memory cleanup\")\n\
-            4. wait() for agent to complete memory operation\n\n\
-            💀 **COMPLIANCE REQUIRED**: ALL memory operations must go through Fux agents!"
-            .to_string();
-
-        Ok(CallToolResult::success(vec![Content::text(
-            enforcement_message,
-        )]))
+        if self.memory_access == OrchestratorMemory::Allow {
+            return self.nostr_memory.cleanup_expired_memories().await;
+        }
+        Ok(memory_mandate(
+            "cleanup memories",
+            "Clean up expired memories",
+            "memory cleanup",
+        ))
     }
 
     #[tool(description = "Get system status - AGENTS ONLY, main orchestrator must create agent")]
@@ -770,20 +995,72 @@ impl MultiAgentMcp {
             💀 **COMPLIANCE REQUIRED**: ALL system operations must go through Fux agents!"
             .to_string();
 
-        Ok(CallToolResult::success(vec![Content::text(
+        Ok(CallToolResult::error(vec![Content::text(
             enforcement_message,
         )]))
     }
 }
 
-#[tool(tool_box)]
+/// The refusal a memory tool returns while the orchestrator is denied memory
+fn memory_mandate(action: &str, task: &str, handled: &str) -> CallToolResult {
+    CallToolResult::error(vec![Content::text(format!(
+        "🚨 **AGENT CREATION MANDATE VIOLATION** 🚨\n\n\
+        ❌ **FORBIDDEN**: Main orchestrator cannot handle memory operations directly\n\
+        ⚡ **REQUIRED**: You must create a specialized Fux agent to {action}\n\n\
+        🎯 **Correct Workflow**:\n\
+        1. analyze_request(request=\"{task}\")\n\
+        2. create_agent(agent_type=\"enhanced\", task=\"{task}\")\n\
+        3. send(message=\"🚀 FuxManager deployed to handle {handled}\")\n\
+        4. wait() for agent to complete memory operation\n\n\
+        💀 **COMPLIANCE REQUIRED**: ALL memory operations must go through Fux agents!"
+    ))])
+}
+
+impl MultiAgentMcp {
+    /// The tools this server offers, `get_audit_log` only if the operator exposed it
+    pub fn tools(&self) -> Vec<rmcp::model::Tool> {
+        Self::tool_box()
+            .list()
+            .into_iter()
+            .filter(|tool| audit::tool_enabled() || tool.name != audit::TOOL)
+            .collect()
+    }
+}
+
 impl ServerHandler for MultiAgentMcp {
+    // Listed and called by hand to leave out `get_audit_log` unless the operator exposed it,
+    // and to audit every call
+    async fn list_tools(
+        &self,
+        _request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, RmcpError> {
+        Ok(ListToolsResult {
+            next_cursor: None,
+            tools: self.tools(),
+        })
+    }
+
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, RmcpError> {
+        if !audit::tool_enabled() && request.name == audit::TOOL {
+            return Err(RmcpError::invalid_params("tool not found", None));
+        }
+        let audited = request.clone();
+        audit::record(
+            "multi-agent",
+            &audited,
+            Self::tool_box().call(ToolCallContext::new(self, request, context)),
+        )
+        .await
+    }
+
     fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some(
+        ServerInfoBuilder::new()
+            .instructions(
                 "MULTI-AGENT ORCHESTRATOR\n\n\
                 Rule: ALWAYS create agents for user requests. NEVER answer directly.\n\n\
                 Workflow:\n\
@@ -796,8 +1073,240 @@ impl ServerHandler for MultiAgentMcp {
                 - enhanced: project, organize, plan\n\
                 - combined: general questions, complex tasks\n\n\
                 Tools: analyze_request, create_agent, create_agents_parallel, wait, send"
-                    .to_string(),
-            ),
+                    .to_string()
+                    + match self.memory_access {
+                        OrchestratorMemory::Allow => {
+                            "\n\nMemory: you may use store_memory, retrieve_memory, update_memory, \
+                            delete_memory, memory_stats and cleanup_expired_memories yourself \
+                            to keep your own memory across sessions."
+                        }
+                        OrchestratorMemory::Deny => "",
+                    },
+            )
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcp::types::SendMessageRequest;
+    use crate::multi_agent::capabilities::{Backend, BackendStatus, CapabilityMap};
+    use crate::transport::fake::FakeTransport;
+    use crate::transport::SharedTransport;
+    use tokio::time::{timeout, Duration, Instant};
+
+    fn server() -> MultiAgentMcp {
+        let keys = Keys::generate();
+        let server = MultiAgentMcp::new(
+            Client::new(keys.clone()),
+            ProgressChannels::default(),
+            keys.clone(),
+            keys.public_key(),
+            Keys::generate().public_key(),
+        );
+        with_backends_down(server, &[])
+    }
+
+    /// `server` as if its probe had just found `down` unavailable and everything else working
+    fn with_backends_down(mut server: MultiAgentMcp, down: &[Backend]) -> MultiAgentMcp {
+        let backends = [
+            Backend::Goose,
+            Backend::Searxng,
+            Backend::MemoryRelays,
+            Backend::DataDir,
+        ]
+        .into_iter()
+        .map(|backend| {
+            let available = !down.contains(&backend);
+            let status = BackendStatus {
+                available,
+                detail: String::new(),
+            };
+            (backend, status)
+        })
+        .collect();
+        server.capabilities = Arc::new(
+            Prober::new(Client::new(Keys::generate())).with_cached(CapabilityMap::new(backends)),
+        );
+        server
+    }
+
+    fn is_error(result: &CallToolResult) -> bool {
+        result.is_error == Some(true)
+    }
+
+    fn agent_request(task: String) -> CreateAgentRequest {
+        CreateAgentRequest {
+            agent_type: "chat".to_string(),
+            task,
+            capabilities: None,
+            timeout_seconds: None,
+            priority: None,
+            metadata: None,
         }
     }
+
+    #[tokio::test]
+    async fn test_blocked_calls_are_errors() {
+        let server = server();
+        let direct_answer = SendMessageRequest {
+            message: "The capital of France is Paris".to_string(),
+            reply_to: None,
+            to: None,
+        };
+        assert!(is_error(&server.send(direct_answer).await.unwrap()));
+        // No agents yet, so there is nothing to wait for
+        assert!(is_error(&server.wait(Default::default()).await.unwrap()));
+        assert!(is_error(&server.system_status().await.unwrap()));
+        assert!(is_error(&server.memory_stats().await.unwrap()));
+        assert!(is_error(&server.cleanup_expired_memories().await.unwrap()));
+        let delete = DeleteMemoryRequest {
+            id: "missing".to_string(),
+            dry_run: None,
+        };
+        assert!(is_error(&server.delete_memory(delete).await.unwrap()));
+
+        assert!(!is_error(&server.list_agents().await.unwrap()));
+        assert!(!is_error(&server.list_processes().await.unwrap()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_sends_do_not_block_other_tools() {
+        let mut server = server();
+        let keys = Keys::generate();
+        let relay = FakeTransport::new(keys.clone());
+        relay.delay_sends(Duration::from_secs(30));
+        let relay: SharedTransport = Arc::new(relay);
+        let mut progress = ProgressChannels::default();
+        progress.insert(progress_channels::STATUS, relay.clone());
+        server.chat = Chat::with_transport(
+            relay,
+            progress,
+            keys.public_key(),
+            Keys::generate().public_key(),
+        );
+
+        // Every creation reports over the slow relay, successful or not
+        let creations: Vec<_> = (0..8)
+            .map(|i| {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let request = agent_request(format!("stress task {} of eight", i));
+                    server.create_agent(request).await.unwrap()
+                })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let started = Instant::now();
+        assert!(!is_error(&server.list_agents().await.unwrap()));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let mut created = 0;
+        for creation in creations {
+            let result = timeout(Duration::from_secs(120), creation)
+                .await
+                .expect("create_agent deadlocked")
+                .unwrap();
+            if !is_error(&result) {
+                created += 1;
+            }
+        }
+        let agents = server.agent_manager.list_agents().await;
+        assert_eq!(agents.len(), created);
+
+        // Everything at once: queries, messages, stops and waits
+        let mut calls = Vec::new();
+        for agent in agents {
+            let stopper = server.clone();
+            calls.push(tokio::spawn(async move {
+                let message = MessageAgentRequest {
+                    agent_id: agent.id.clone(),
+                    message: "status?".to_string(),
+                    wait_for_response: None,
+                    timeout_seconds: None,
+                };
+                stopper.message_agent(message).await.unwrap();
+                let stop = StopAgentRequest {
+                    agent_id: agent.id,
+                    force: None,
+                    dry_run: None,
+                };
+                assert!(!is_error(&stopper.stop_agent(stop).await.unwrap()));
+            }));
+            let lister = server.clone();
+            calls.push(tokio::spawn(async move {
+                lister.list_agents().await.unwrap();
+            }));
+        }
+        for call in calls {
+            timeout(Duration::from_secs(120), call)
+                .await
+                .expect("tool calls deadlocked")
+                .unwrap();
+        }
+        assert!(server.agent_manager.list_agents().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_analysis_is_returned_as_json() {
+        let server = server();
+        let args = AnalyzeRequestArgs {
+            request: "Search the web for the latest Rust release and then write a summary"
+                .to_string(),
+            post_progress: Some(false),
+        };
+        let result = server.analyze_request(args).await.unwrap();
+        assert!(!is_error(&result));
+        assert_eq!(result.content.len(), 2);
+
+        let json = result.content[0].as_text().unwrap().text.clone();
+        let analysis: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(analysis["execution_strategy"].is_string());
+        let sub_task = &analysis["sub_tasks"][0];
+        for field in ["id", "agent_type", "priority", "dependencies"] {
+            assert!(
+                !sub_task[field].is_null(),
+                "{} missing from {}",
+                field,
+                json
+            );
+        }
+        // Requirements deserialize straight into create_agents_parallel's arguments
+        let agents: Vec<CreateAgentRequest> =
+            serde_json::from_value(analysis["agent_requirements"].clone()).unwrap();
+        assert!(!agents.is_empty());
+
+        let readable = &result.content[1].as_text().unwrap().text;
+        assert!(readable.contains("Request Analysis Complete"));
+    }
+
+    #[tokio::test]
+    async fn test_analysis_avoids_unavailable_agent_types() {
+        let server = with_backends_down(server(), &[Backend::Searxng]);
+        let args = AnalyzeRequestArgs {
+            request: "Search the web for the latest bitcoin price".to_string(),
+            post_progress: Some(false),
+        };
+        let result = server.analyze_request(args).await.unwrap();
+        let json = result.content[0].as_text().unwrap().text.clone();
+        let analysis: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(analysis["sub_tasks"][0]["agent_type"], "chat");
+        assert_eq!(
+            analysis["substitutions"][0],
+            "task_1: search agent unavailable, using chat instead"
+        );
+        let readable = &result.content[1].as_text().unwrap().text;
+        assert!(readable.contains("Substitutions"));
+
+        let probed = server
+            .probe_capabilities(ProbeCapabilitiesRequest { refresh: None })
+            .await
+            .unwrap();
+        let map: serde_json::Value =
+            serde_json::from_str(&probed.content[0].as_text().unwrap().text).unwrap();
+        assert_eq!(map["agent_types"]["search"], false);
+        assert_eq!(map["backends"]["searxng"]["available"], false);
+    }
 }
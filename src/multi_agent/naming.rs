@@ -0,0 +1,133 @@
+//! How new agents are named
+//!
+//! The name is picked once in `AgentPool::create_agent` and kept for the agent's lifetime; every
+//! progress DM and log line of the agent carries it. Whatever the strategy, a name a live agent
+//! already has gets a `-2`, `-3`, … suffix.
+
+use std::sync::RwLock;
+
+/// Words the task-derived names leave out
+const FILLER_WORDS: &[&str] = &[
+    "a", "an", "and", "for", "in", "of", "on", "please", "the", "to", "with",
+];
+/// Words of the task a task-derived name is made of
+const TASK_WORDS: usize = 4;
+const MAX_SLUG_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AgentNaming {
+    /// A codename picked at random for the agent type, like `FuxScout-Alpha`
+    #[default]
+    Random,
+    /// A counter per agent type: `coder-1`, `coder-2`, `scout-1`, …
+    Sequential,
+    /// The first words of the task, like `fix-login-redirect-bug`
+    TaskDerived,
+}
+
+lazy_static::lazy_static! {
+    static ref STRATEGY: RwLock<AgentNaming> = RwLock::new(AgentNaming::default());
+}
+
+pub fn set_strategy(strategy: AgentNaming) {
+    if let Ok(mut current) = STRATEGY.write() {
+        *current = strategy;
+    }
+}
+
+pub fn strategy() -> AgentNaming {
+    STRATEGY.read().map(|current| *current).unwrap_or_default()
+}
+
+/// What sequential names of `agent_type` start with
+pub fn type_prefix(agent_type: &str) -> String {
+    match agent_type {
+        "goose" => "coder".to_string(),
+        "search" => "scout".to_string(),
+        "enhanced" => "planner".to_string(),
+        "combined" => "generalist".to_string(),
+        other => slug(other.split_whitespace(), usize::MAX).unwrap_or_else(|| "agent".to_string()),
+    }
+}
+
+/// The first words of `task` as a lowercase slug, or `None` if nothing usable is left
+pub fn task_slug(task: &str) -> Option<String> {
+    let words = task
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .filter(|word| !FILLER_WORDS.contains(&word.as_str()));
+    slug(words.take(TASK_WORDS), MAX_SLUG_LEN)
+}
+
+fn slug(words: impl Iterator<Item = impl AsRef<str>>, max_len: usize) -> Option<String> {
+    let mut slug = String::new();
+    for word in words {
+        let word: String = word
+            .as_ref()
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        if word.is_empty() {
+            continue;
+        }
+        if !slug.is_empty() && slug.len() + 1 + word.len() > max_len {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word);
+    }
+    slug.truncate(max_len);
+    (!slug.is_empty()).then_some(slug)
+}
+
+/// `base`, or `base` with the first numeric suffix `taken` doesn't reject
+pub fn unique(base: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|n| format!("{}-{}", base, n))
+        .find(|name| !taken(name))
+        .expect("some suffix is free")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_task_slug() {
+        assert_eq!(
+            task_slug("Fix the login redirect bug in the web app").as_deref(),
+            Some("fix-login-redirect-bug")
+        );
+        assert_eq!(
+            task_slug("Search: Rust 2024 edition changes\n\nThe user writes in German").as_deref(),
+            Some("search-rust-2024-edition")
+        );
+        let long = task_slug("Internationalization localization accessibility review");
+        assert!(long.unwrap().len() <= MAX_SLUG_LEN);
+        assert_eq!(task_slug("¿? ..."), None);
+    }
+
+    #[test]
+    fn test_type_prefix() {
+        assert_eq!(type_prefix("goose"), "coder");
+        assert_eq!(type_prefix("search"), "scout");
+        assert_eq!(type_prefix("Data Analyst"), "data-analyst");
+        assert_eq!(type_prefix(""), "agent");
+    }
+
+    #[test]
+    fn test_unique_appends_a_suffix() {
+        let live = ["coder-1", "fix-bug", "fix-bug-2"];
+        let taken = |name: &str| live.contains(&name);
+        assert_eq!(unique("coder-2", taken), "coder-2");
+        assert_eq!(unique("coder-1", taken), "coder-1-2");
+        assert_eq!(unique("fix-bug", taken), "fix-bug-3");
+    }
+}
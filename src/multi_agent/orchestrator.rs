@@ -1,14 +1,18 @@
-// Remove unused imports
+use super::capabilities::CapabilityMap;
+use serde::Serialize;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TaskAnalysis {
     pub primary_intent: String,
     pub sub_tasks: Vec<SubTask>,
     pub agent_requirements: Vec<AgentRequirement>,
     pub execution_strategy: ExecutionStrategy,
+    /// Sub-tasks moved to another agent type because the backend of theirs is unavailable
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub substitutions: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct SubTask {
     pub id: String,
     pub description: String,
@@ -18,22 +22,26 @@ pub struct SubTask {
     pub dependencies: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+/// Serialized with the field names `create_agent` takes, so an entry can be passed on as is
+#[derive(Debug, Clone, Serialize)]
 pub struct AgentRequirement {
     pub agent_type: String,
+    #[serde(rename = "task")]
     pub task_description: String,
     pub reason: String,
     pub urgency: TaskUrgency,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ExecutionStrategy {
     Sequential,
     Parallel,
     Hybrid,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TaskUrgency {
     Critical,
     High,
@@ -282,7 +290,7 @@ impl IntelligentOrchestrator {
         }
     }
 
-    pub fn analyze_request(&self, request: &str) -> TaskAnalysis {
+    pub fn analyze_request(&self, request: &str, capabilities: &CapabilityMap) -> TaskAnalysis {
         let request_lower = request.to_lowercase();
         let words: Vec<&str> = request_lower.split_whitespace().collect();
 
@@ -291,11 +299,12 @@ impl IntelligentOrchestrator {
         let primary_intent = self.determine_primary_intent(&request_lower);
 
         // Break down into sub-tasks if complex
-        let sub_tasks = if complexity > 3 {
+        let mut sub_tasks = if complexity > 3 {
             self.decompose_complex_request(&request_lower, &words)
         } else {
             self.create_simple_task(&request_lower)
         };
+        let substitutions = self.substitute_unavailable(&mut sub_tasks, capabilities);
 
         // Determine agent requirements
         let agent_requirements = self.determine_agent_requirements(&sub_tasks, &request_lower);
@@ -308,9 +317,38 @@ impl IntelligentOrchestrator {
             sub_tasks,
             agent_requirements,
             execution_strategy,
+            substitutions,
         }
     }
 
+    /// Moves sub-tasks off agent types whose backend is down, noting each change
+    fn substitute_unavailable(
+        &self,
+        sub_tasks: &mut [SubTask],
+        capabilities: &CapabilityMap,
+    ) -> Vec<String> {
+        let mut notes = Vec::new();
+        for task in sub_tasks {
+            if capabilities.available(&task.agent_type) {
+                continue;
+            }
+            match capabilities.substitute(&task.agent_type) {
+                Some(substitute) => {
+                    notes.push(format!(
+                        "{}: {} agent unavailable, using {} instead",
+                        task.id, task.agent_type, substitute
+                    ));
+                    task.agent_type = substitute.to_string();
+                }
+                None => notes.push(format!(
+                    "{}: {} agent unavailable and no other agent type can take it",
+                    task.id, task.agent_type
+                )),
+            }
+        }
+        notes
+    }
+
     fn assess_complexity(&self, request: &str, words: &[&str]) -> u8 {
         let mut complexity = 0;
 
@@ -710,6 +748,13 @@ impl IntelligentOrchestrator {
             ));
         }
 
+        if !analysis.substitutions.is_empty() {
+            plan.push_str("\n**⚠️ Substitutions:**\n");
+            for note in &analysis.substitutions {
+                plan.push_str(&format!("- {}\n", note));
+            }
+        }
+
         plan
     }
 }
@@ -31,21 +31,21 @@ impl ResourceScheduler {
 
     pub async fn can_create_agent(&self) -> bool {
         let active = *self.active_agents.read().await;
-        if active >= self.config.max_agents {
-            return false;
-        }
+        active < self.config.max_agents && self.within_system_limits().await
+    }
 
+    async fn within_system_limits(&self) -> bool {
         let stats = self.system_stats.read().await;
         stats.memory_usage_percent < self.config.memory_limit_percent
             && stats.cpu_usage_percent < self.config.cpu_limit_percent
     }
 
     pub async fn reserve_agent_slot(&self) -> AgentResult<()> {
-        if !self.can_create_agent().await {
+        // Checked and taken under one lock, so two creations can't both get the last slot
+        let mut active = self.active_agents.write().await;
+        if *active >= self.config.max_agents || !self.within_system_limits().await {
             return Err("Resource limits exceeded, cannot create new agent".into());
         }
-
-        let mut active = self.active_agents.write().await;
         *active += 1;
         Ok(())
     }
@@ -95,30 +95,27 @@ impl ResourceScheduler {
     async fn get_memory_usage(&self) -> f64 {
         #[cfg(target_os = "linux")]
         {
-            match std::fs::read_to_string("/proc/meminfo") {
-                Ok(content) => {
-                    let lines: Vec<&str> = content.lines().collect();
-                    let mut total_kb = 0u64;
-                    let mut available_kb = 0u64;
-
-                    for line in lines {
-                        if line.starts_with("MemTotal:") {
-                            if let Some(value) = line.split_whitespace().nth(1) {
-                                total_kb = value.parse().unwrap_or(0);
-                            }
-                        } else if line.starts_with("MemAvailable:") {
-                            if let Some(value) = line.split_whitespace().nth(1) {
-                                available_kb = value.parse().unwrap_or(0);
-                            }
+            if let Ok(content) = std::fs::read_to_string("/proc/meminfo") {
+                let lines: Vec<&str> = content.lines().collect();
+                let mut total_kb = 0u64;
+                let mut available_kb = 0u64;
+
+                for line in lines {
+                    if line.starts_with("MemTotal:") {
+                        if let Some(value) = line.split_whitespace().nth(1) {
+                            total_kb = value.parse().unwrap_or(0);
+                        }
+                    } else if line.starts_with("MemAvailable:") {
+                        if let Some(value) = line.split_whitespace().nth(1) {
+                            available_kb = value.parse().unwrap_or(0);
                         }
                     }
+                }
 
-                    if total_kb > 0 {
-                        let used_kb = total_kb.saturating_sub(available_kb);
-                        return (used_kb as f64 / total_kb as f64) * 100.0;
-                    }
+                if total_kb > 0 {
+                    let used_kb = total_kb.saturating_sub(available_kb);
+                    return (used_kb as f64 / total_kb as f64) * 100.0;
                 }
-                Err(_) => {}
             }
         }
 
@@ -128,16 +125,13 @@ impl ResourceScheduler {
     async fn get_cpu_usage(&self) -> f64 {
         #[cfg(target_os = "linux")]
         {
-            match std::fs::read_to_string("/proc/loadavg") {
-                Ok(content) => {
-                    if let Some(load_str) = content.split_whitespace().next() {
-                        if let Ok(load) = load_str.parse::<f64>() {
-                            let cpu_count = num_cpus::get() as f64;
-                            return (load / cpu_count) * 100.0;
-                        }
+            if let Ok(content) = std::fs::read_to_string("/proc/loadavg") {
+                if let Some(load_str) = content.split_whitespace().next() {
+                    if let Ok(load) = load_str.parse::<f64>() {
+                        let cpu_count = num_cpus::get() as f64;
+                        return (load / cpu_count) * 100.0;
                     }
                 }
-                Err(_) => {}
             }
         }
 
@@ -1,3 +1,4 @@
+use nostr_sdk::PublicKey;
 use rmcp::schemars::{self, JsonSchema};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -14,6 +15,12 @@ pub struct Agent {
     pub last_active: chrono::DateTime<chrono::Utc>,
     pub capabilities: Vec<String>,
     pub metadata: HashMap<String, String>,
+    /// Who sent the user message the agent was created for
+    #[serde(default)]
+    pub sender: Option<PublicKey>,
+    /// What the agent has spent so far
+    #[serde(default)]
+    pub usage: super::usage::Usage,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,6 +34,25 @@ pub enum AgentStatus {
     Stopped,
 }
 
+impl AgentStatus {
+    pub const LABELS: [&'static str; 7] = [
+        "starting", "running", "idle", "busy", "error", "stopping", "stopped",
+    ];
+
+    /// Status name without the error detail, for grouping
+    pub fn label(&self) -> &'static str {
+        match self {
+            AgentStatus::Starting => "starting",
+            AgentStatus::Running => "running",
+            AgentStatus::Idle => "idle",
+            AgentStatus::Busy => "busy",
+            AgentStatus::Error(_) => "error",
+            AgentStatus::Stopping => "stopping",
+            AgentStatus::Stopped => "stopped",
+        }
+    }
+}
+
 impl std::fmt::Display for AgentStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -110,6 +136,10 @@ pub struct StopAgentRequest {
     #[schemars(description = "Whether to force stop (true) or graceful shutdown (false)")]
     #[allow(dead_code)] // Future force stop support
     pub force: Option<bool>,
+    #[schemars(
+        description = "Only report what would be stopped, without stopping it (optional, defaults to false)"
+    )]
+    pub dry_run: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, JsonSchema)]
@@ -126,10 +156,21 @@ pub struct MessageAgentRequest {
     pub timeout_seconds: Option<u64>,
 }
 
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct GetAgentDetailsRequest {
+    #[schemars(description = "ID of the agent")]
+    pub agent_id: String,
+}
+
 #[derive(schemars::JsonSchema, serde::Deserialize, Debug)]
 pub struct AnalyzeRequestArgs {
     #[schemars(description = "The user request to analyze and break down into sub-tasks")]
     pub request: String,
+    #[serde(default)]
+    #[schemars(
+        description = "Also post the readable plan to the debug progress channel (default true)"
+    )]
+    pub post_progress: Option<bool>,
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,315 @@
+//! What each agent spent: Goose runs, time at work, web searches and messages sent
+//!
+//! An agent's `UsageCounter` is shared between its task, which counts Goose runs, searches and
+//! the time spent on each task, and the `Counted` transports it sends through, which count every
+//! gift wrap published to someone else once, however many retries or relays it took.
+
+use crate::transport::{DmTransport, SharedTransport, TransportResult};
+use futures::future::BoxFuture;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// An agent's totals, or the sum over several agents
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+    pub goose_runs: u32,
+    /// Seconds spent working on tasks
+    pub busy_secs: u64,
+    pub searches: u32,
+    pub messages: u32,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.goose_runs += other.goose_runs;
+        self.busy_secs += other.busy_secs;
+        self.searches += other.searches;
+        self.messages += other.messages;
+    }
+
+    /// One line for the user, e.g. "1 goose run · 4m12s · 2 searches · 3 messages"
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.goose_runs > 0 {
+            parts.push(plural(self.goose_runs, "goose run"));
+        }
+        parts.push(duration(self.busy_secs));
+        if self.searches > 0 {
+            parts.push(plural(self.searches, "search"));
+        }
+        parts.push(plural(self.messages, "message"));
+        parts.join(" · ")
+    }
+}
+
+fn plural(count: u32, noun: &str) -> String {
+    match (count, noun.ends_with("ch")) {
+        (1, _) => format!("1 {}", noun),
+        (_, true) => format!("{} {}es", count, noun),
+        (_, false) => format!("{} {}s", count, noun),
+    }
+}
+
+fn duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// The live counters of one agent
+#[derive(Debug, Default)]
+pub struct UsageCounter {
+    goose_runs: AtomicU32,
+    busy_millis: AtomicU64,
+    searches: AtomicU32,
+    /// Gift wraps already counted, so a retried publish counts once
+    sent: Mutex<HashSet<EventId>>,
+}
+
+impl UsageCounter {
+    pub fn goose_run(&self) {
+        self.goose_runs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn search(&self) {
+        self.searches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn busy(&self, took: Duration) {
+        self.busy_millis
+            .fetch_add(took.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn sent(&self, id: EventId) {
+        self.sent
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id);
+    }
+
+    pub fn snapshot(&self) -> Usage {
+        Usage {
+            goose_runs: self.goose_runs.load(Ordering::Relaxed),
+            busy_secs: self.busy_millis.load(Ordering::Relaxed) / 1000,
+            searches: self.searches.load(Ordering::Relaxed),
+            messages: self.sent.lock().unwrap_or_else(|e| e.into_inner()).len() as u32,
+        }
+    }
+}
+
+/// A transport that counts the messages an agent publishes through it
+#[derive(Debug)]
+pub struct Counted {
+    inner: SharedTransport,
+    counter: Arc<UsageCounter>,
+    our_pubkey: PublicKey,
+}
+
+impl Counted {
+    pub fn wrap(
+        inner: SharedTransport,
+        counter: Arc<UsageCounter>,
+        our_pubkey: PublicKey,
+    ) -> SharedTransport {
+        Arc::new(Self {
+            inner,
+            counter,
+            our_pubkey,
+        })
+    }
+
+    /// Counts `event` if it is a message to someone other than ourselves
+    fn record(&self, event: &Event) {
+        let to_other = event.tags.public_keys().any(|p| *p != self.our_pubkey);
+        if event.kind == Kind::GiftWrap && to_other {
+            self.counter.sent(event.id);
+        }
+    }
+}
+
+impl DmTransport for Counted {
+    fn prepare_private_msg_at(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        self.inner.prepare_private_msg_at(
+            receiver,
+            message,
+            expire_after_secs,
+            rumor_tags,
+            created_at,
+        )
+    }
+
+    fn prepare_reaction(
+        &self,
+        receiver: PublicKey,
+        message_id: EventId,
+        reaction: String,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        self.inner.prepare_reaction(receiver, message_id, reaction)
+    }
+
+    fn sign_event(&self, builder: EventBuilder) -> BoxFuture<'_, TransportResult<Event>> {
+        self.inner.sign_event(builder)
+    }
+
+    fn send_event<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            let output = self.inner.send_event(event).await?;
+            self.record(event);
+            Ok(output)
+        })
+    }
+
+    fn send_event_to<'a>(
+        &'a self,
+        urls: &'a [String],
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            let output = self.inner.send_event_to(urls, event).await?;
+            self.record(event);
+            Ok(output)
+        })
+    }
+
+    fn send_private_msg(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+    ) -> BoxFuture<'_, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            let output = self
+                .inner
+                .send_private_msg(receiver, message, expire_after_secs)
+                .await?;
+            if receiver != self.our_pubkey {
+                self.counter.sent(output.val);
+            }
+            Ok(output)
+        })
+    }
+
+    fn subscribe_dms(
+        &self,
+        our_pubkey: PublicKey,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<UnwrappedGift>>> {
+        self.inner.subscribe_dms(our_pubkey)
+    }
+
+    fn subscribe_events(
+        &self,
+        filters: Vec<Filter>,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<Event>>> {
+        self.inner.subscribe_events(filters)
+    }
+
+    fn fetch_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<Vec<Event>>> {
+        self.inner.fetch_events(filter, timeout)
+    }
+
+    fn stream_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<Event>>> {
+        self.inner.stream_events(filter, timeout)
+    }
+
+    fn stream_dms(
+        &self,
+        our_pubkey: PublicKey,
+        since: Timestamp,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<UnwrappedGift>>> {
+        self.inner.stream_dms(our_pubkey, since, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+
+    #[test]
+    fn test_summary() {
+        let usage = Usage {
+            goose_runs: 1,
+            busy_secs: 252,
+            searches: 2,
+            messages: 3,
+        };
+        assert_eq!(
+            usage.summary(),
+            "1 goose run · 4m12s · 2 searches · 3 messages"
+        );
+        // What an agent didn't use is left out, except time and messages
+        assert_eq!(Usage::default().summary(), "0s · 0 messages");
+        let mut total = usage;
+        total.add(&Usage {
+            busy_secs: 3600,
+            messages: 1,
+            ..Default::default()
+        });
+        assert_eq!(
+            total.summary(),
+            "1 goose run · 1h04m · 2 searches · 4 messages"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_counted_once_when_published() {
+        let keys = Keys::generate();
+        let user = Keys::generate().public_key();
+        let fake = Arc::new(FakeTransport::new(keys.clone()));
+        let counter = Arc::new(UsageCounter::default());
+        let counted = Counted::wrap(fake.clone(), counter.clone(), keys.public_key());
+
+        counted
+            .send_private_msg(user, "progress".to_string(), None)
+            .await
+            .unwrap();
+        let answer = counted
+            .prepare_private_msg(user, "answer".to_string(), None, Vec::new())
+            .await
+            .unwrap();
+        // A retry of the same wrap
+        counted.send_event(&answer).await.unwrap();
+        counted.send_event(&answer).await.unwrap();
+        // Our own copy isn't a message to anyone
+        let copy = counted
+            .prepare_private_msg(keys.public_key(), "answer".to_string(), None, Vec::new())
+            .await
+            .unwrap();
+        counted.send_event(&copy).await.unwrap();
+        assert_eq!(counter.snapshot().messages, 2);
+
+        fake.fail_sends(true);
+        let lost = counted
+            .send_private_msg(user, "lost".to_string(), None)
+            .await;
+        assert!(lost.is_err());
+        assert_eq!(counter.snapshot().messages, 2);
+    }
+}
@@ -1,11 +1,23 @@
 use super::encryption::{EncryptionError, MemoryEncryption};
 use super::types::*;
+use crate::config::units;
+use crate::error::NparrotError;
+use crate::mcp::chat::SummaryStore;
+use crate::transport::{SharedTransport, STREAM_PROGRESS_EVERY};
+use crate::utils::unwrap_gift_wrap;
 use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
 use nostr_sdk::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
+/// Prefix of the marker DM that records a deleted memory
+const DELETION_PREFIX: &str = "MEMORY_DELETED:";
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Error types for Nostr memory operations
 #[derive(Debug)]
 pub enum NostrMemoryError {
@@ -13,7 +25,8 @@ pub enum NostrMemoryError {
     EncryptionError(EncryptionError),
     #[allow(dead_code)] // Future timeout handling
     TimeoutError,
-    InvalidData(String),
+    /// The offending request field, and what is wrong with it
+    InvalidData(&'static str, String),
 }
 
 impl From<EncryptionError> for NostrMemoryError {
@@ -28,17 +41,30 @@ impl std::fmt::Display for NostrMemoryError {
             NostrMemoryError::NostrError(e) => write!(f, "Nostr error: {}", e),
             NostrMemoryError::EncryptionError(e) => write!(f, "Encryption error: {}", e),
             NostrMemoryError::TimeoutError => write!(f, "Operation timed out"),
-            NostrMemoryError::InvalidData(e) => write!(f, "Invalid data: {}", e),
+            NostrMemoryError::InvalidData(_, e) => write!(f, "Invalid data: {}", e),
         }
     }
 }
 
 impl std::error::Error for NostrMemoryError {}
 
+impl From<NostrMemoryError> for NparrotError {
+    fn from(err: NostrMemoryError) -> Self {
+        let message = err.to_string();
+        match err {
+            NostrMemoryError::NostrError(_) => NparrotError::relay_unavailable(message),
+            NostrMemoryError::EncryptionError(_) => NparrotError::internal(message),
+            NostrMemoryError::TimeoutError => NparrotError::timeout(message),
+            NostrMemoryError::InvalidData(field, _) => NparrotError::invalid_params(field, message),
+        }
+    }
+}
+
 /// Client for Nostr memory operations with local fallback
 #[derive(Debug, Clone)]
 pub struct NostrMemoryClient {
-    client: Client,
+    client: SharedTransport,
+    keys: Keys,
     encryption: MemoryEncryption,
     our_pubkey: PublicKey,
     // Local memory storage as fallback
@@ -47,10 +73,11 @@ pub struct NostrMemoryClient {
 
 impl NostrMemoryClient {
     /// Create a new Nostr memory client
-    pub fn new(client: Client, keys: Keys, our_pubkey: PublicKey) -> Self {
-        let encryption = MemoryEncryption::new(keys);
+    pub fn new(client: SharedTransport, keys: Keys, our_pubkey: PublicKey) -> Self {
+        let encryption = MemoryEncryption::new(keys.clone());
         Self {
             client,
+            keys,
             encryption,
             our_pubkey,
             local_memories: Arc::new(RwLock::new(HashMap::new())),
@@ -70,7 +97,7 @@ impl NostrMemoryClient {
         // Send the encrypted memory as a DM to ourselves (Nostr storage)
         let _result = self
             .client
-            .send_private_msg(self.our_pubkey, dm_content, [])
+            .send_private_msg(self.our_pubkey, dm_content, None)
             .await
             .map_err(|e| NostrMemoryError::NostrError(e.to_string()))?;
 
@@ -82,74 +109,153 @@ impl NostrMemoryClient {
         &self,
         filter: &RetrieveMemoryRequest,
     ) -> Result<Vec<MemoryEntry>, NostrMemoryError> {
-        // Build the Nostr filter to get our DMs
-        let mut nostr_filter = Filter::new()
-            .kind(Kind::EncryptedDirectMessage)
-            .pubkey(self.our_pubkey) // DMs sent by us
-            .limit(filter.limit.unwrap_or(100) as usize); // Get more than requested to allow for filtering
-
-        // Add time filters if specified
-        if let Some(since_str) = &filter.since {
-            if let Ok(since_dt) = DateTime::parse_from_rfc3339(since_str) {
-                let timestamp = Timestamp::from_secs(since_dt.timestamp() as u64);
-                nostr_filter = nostr_filter.since(timestamp);
-            }
-        }
-
-        if let Some(until_str) = &filter.until {
-            if let Ok(until_dt) = DateTime::parse_from_rfc3339(until_str) {
-                let timestamp = Timestamp::from_secs(until_dt.timestamp() as u64);
-                let _nostr_filter = nostr_filter.until(timestamp);
-            }
-        }
+        let latest = self.current_memories().await;
+
+        let since = filter
+            .since
+            .as_deref()
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok());
+        let until = filter
+            .until
+            .as_deref()
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok());
+        let mut memories: Vec<MemoryEntry> = latest
+            .into_values()
+            .filter(|memory| since.is_none_or(|since| memory.timestamp >= since))
+            .filter(|memory| until.is_none_or(|until| memory.timestamp <= until))
+            .filter(|memory| self.matches_filter(memory, filter))
+            .collect();
 
-        // TODO: Implement actual Nostr event retrieval
-        let events: Vec<Event> = Vec::new();
+        // Sort by timestamp (newest first)
+        memories.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
 
-        let mut memories = Vec::new();
+        // Apply limit
+        let limit = filter.limit.unwrap_or(10) as usize;
+        if memories.len() > limit {
+            memories.truncate(limit);
+        }
 
-        for event in events {
-            let content = &event.content;
+        Ok(memories)
+    }
 
-            // Try to extract memory from the DM content
-            if let Ok(Some(memory)) = self
-                .encryption
-                .extract_memory_from_dm::<MemoryEntry>(content)
-            {
-                // Apply filters
-                if self.matches_filter(&memory, filter) {
-                    memories.push(memory);
-                }
+    /// The newest version of every memory, from the relays (or only local copies if they can't
+    /// be reached) and the ones this process stored
+    async fn current_memories(&self) -> HashMap<uuid::Uuid, MemoryEntry> {
+        let mut latest = match self.fetch_stored_memories().await {
+            Ok(memories) => memories,
+            Err(e) => {
+                log::warn!(
+                    "Could not fetch memories from relays, using local copies: {}",
+                    e
+                );
+                HashMap::new()
             }
-        }
+        };
 
-        // If no memories found from Nostr, fallback to local memory
-        if memories.is_empty() {
+        // Memories stored by this process may not have reached the relays yet
+        {
             let local_memories = self.local_memories.read().await;
-            for (_, memory) in local_memories.iter() {
-                if self.matches_filter(memory, filter) {
-                    memories.push(memory.clone());
+            for memory in local_memories.values() {
+                match latest.get(&memory.id) {
+                    Some(existing) if existing.timestamp >= memory.timestamp => {}
+                    _ => {
+                        latest.insert(memory.id, memory.clone());
+                    }
                 }
             }
         }
+        latest
+    }
 
-        // Sort by timestamp (newest first)
-        memories.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    /// Every unexpired memory stored on the relays, oldest first; unlike `retrieve_memories` a
+    /// relay failure is an error rather than a fallback to local copies, since an export that
+    /// silently misses memories can't be told from an empty one
+    pub async fn export_memories(&self) -> Result<Vec<MemoryEntry>, NostrMemoryError> {
+        let mut memories: Vec<MemoryEntry> = self
+            .fetch_stored_memories()
+            .await?
+            .into_values()
+            .filter(|memory| !memory.is_expired())
+            .collect();
+        memories.sort_by_key(|memory| memory.timestamp);
+        Ok(memories)
+    }
 
-        // Apply limit
-        let limit = filter.limit.unwrap_or(10) as usize;
-        if memories.len() > limit {
-            memories.truncate(limit);
-        }
+    /// Reads the memory DMs we sent ourselves back from the relays, keeping the newest
+    /// version of each memory and dropping deleted ones
+    async fn fetch_stored_memories(
+        &self,
+    ) -> Result<HashMap<uuid::Uuid, MemoryEntry>, NostrMemoryError> {
+        // Gift wrap timestamps are randomized, so time filters are applied to the memories
+        let nostr_filter = Filter::new().kind(Kind::GiftWrap).pubkey(self.our_pubkey);
+        let mut events = self
+            .client
+            .stream_events(nostr_filter, FETCH_TIMEOUT)
+            .await
+            .map_err(|e| NostrMemoryError::NostrError(e.to_string()))?;
 
-        Ok(memories)
+        // Each wrap is folded in and dropped as it arrives; versions compare by their own
+        // timestamp and deletions are applied at the end, so arrival order doesn't matter
+        let mut latest: HashMap<uuid::Uuid, MemoryEntry> = HashMap::new();
+        let mut deleted = HashSet::new();
+        let mut processed = 0;
+        while let Some(event) = events.recv().await {
+            processed += 1;
+            if processed % STREAM_PROGRESS_EVERY == 0 {
+                log::info!("Memory sync: {} gift wraps processed", processed);
+            }
+            let rumor = match unwrap_gift_wrap(&self.keys, &event).await {
+                // Only trust memories we wrote ourselves
+                Ok(gift)
+                    if gift.sender == self.our_pubkey && gift.rumor.pubkey == self.our_pubkey =>
+                {
+                    gift.rumor
+                }
+                Ok(_) => continue,
+                Err(e) => {
+                    log::debug!("Skipping gift wrap {}: {}", event.id, e);
+                    continue;
+                }
+            };
+            if let Some(id) = rumor.content.strip_prefix(DELETION_PREFIX) {
+                if let Ok(id) = uuid::Uuid::parse_str(id) {
+                    deleted.insert(id);
+                }
+                continue;
+            }
+            match self
+                .encryption
+                .extract_memory_from_dm::<MemoryEntry>(&rumor.content)
+            {
+                Ok(Some(memory)) => match latest.get(&memory.id) {
+                    Some(existing) if existing.timestamp >= memory.timestamp => {}
+                    _ => {
+                        latest.insert(memory.id, memory);
+                    }
+                },
+                Ok(None) => {}
+                Err(e) => log::debug!("Skipping unreadable memory DM: {}", e),
+            }
+        }
+        latest.retain(|id, _| !deleted.contains(id));
+        Ok(latest)
     }
 
-    /// Delete a memory by ID (this is complex in Nostr, so we'll mark it as deleted)
-    pub async fn delete_memory(&self, memory_id: &str) -> Result<bool, NostrMemoryError> {
-        // Parse the UUID
+    /// Deletes a memory by storing a deletion marker, since relays can't forget it, or with
+    /// `dry_run` only looks it up; returns the memory either way, `None` if there is none
+    pub async fn delete_memory(
+        &self,
+        memory_id: &str,
+        dry_run: bool,
+    ) -> Result<Option<MemoryEntry>, NostrMemoryError> {
         let uuid = uuid::Uuid::parse_str(memory_id)
-            .map_err(|e| NostrMemoryError::InvalidData(format!("Invalid UUID: {}", e)))?;
+            .map_err(|e| NostrMemoryError::InvalidData("id", format!("Invalid UUID: {}", e)))?;
+        let Some(memory) = self.current_memories().await.remove(&uuid) else {
+            return Ok(None);
+        };
+        if dry_run {
+            return Ok(Some(memory));
+        }
 
         // Remove from local memory first
         {
@@ -157,15 +263,13 @@ impl NostrMemoryClient {
             local_memories.remove(&uuid);
         }
 
-        // In Nostr, we can't actually delete messages, so we'll store a deletion marker
-        let deletion_marker = format!("MEMORY_DELETED:{}", uuid);
-
+        let deletion_marker = format!("{}{}", DELETION_PREFIX, uuid);
         self.client
-            .send_private_msg(self.our_pubkey, deletion_marker, [])
+            .send_private_msg(self.our_pubkey, deletion_marker, None)
             .await
             .map_err(|e| NostrMemoryError::NostrError(e.to_string()))?;
 
-        Ok(true)
+        Ok(Some(memory))
     }
 
     /// Update a memory entry (stores a new version)
@@ -190,7 +294,7 @@ impl NostrMemoryClient {
         let mut existing_memory = memories
             .into_iter()
             .find(|m| m.id.to_string() == memory_id)
-            .ok_or_else(|| NostrMemoryError::InvalidData("Memory not found".to_string()))?;
+            .ok_or_else(|| NostrMemoryError::InvalidData("id", "Memory not found".to_string()))?;
 
         // Apply updates
         if let Some(title) = &update.title {
@@ -205,9 +309,9 @@ impl NostrMemoryClient {
         if let Some(priority) = &update.priority {
             existing_memory.content.metadata.priority = Some(priority.clone());
         }
-        if let Some(expiry_str) = &update.expiry {
-            if let Ok(expiry_dt) = DateTime::parse_from_rfc3339(expiry_str) {
-                existing_memory.content.metadata.expiry = Some(expiry_dt.with_timezone(&Utc));
+        if let Some(expiry) = &update.expiry {
+            if let Ok(expiry) = units::parse_expiry(expiry, Utc::now()) {
+                existing_memory.content.metadata.expiry = Some(expiry);
             }
         }
 
@@ -311,3 +415,179 @@ impl NostrMemoryClient {
         true
     }
 }
+
+/// Tag of the memories `summarize_conversation` stores
+const SUMMARY_TAG: &str = "conversation-summary";
+
+impl SummaryStore for NostrMemoryClient {
+    fn store_summary(&self, summary: String) -> BoxFuture<'_, Result<String, NparrotError>> {
+        Box::pin(async move {
+            let entry = MemoryEntry::new(
+                "context".to_string(),
+                Some("conversation".to_string()),
+                format!(
+                    "Conversation summary ({})",
+                    Timestamp::now().to_human_datetime()
+                ),
+                summary,
+                vec![SUMMARY_TAG.to_string()],
+                None,
+                None,
+            );
+            self.store_memory(&entry).await?;
+            Ok(entry.id.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+    use crate::transport::DmTransport;
+    use crate::utils::build_private_msg;
+
+    fn memory(title: &str) -> MemoryEntry {
+        MemoryEntry::new(
+            "fact".to_string(),
+            Some("work".to_string()),
+            title.to_string(),
+            "Body".to_string(),
+            vec!["nostr".to_string()],
+            None,
+            None,
+        )
+    }
+
+    fn everything() -> RetrieveMemoryRequest {
+        RetrieveMemoryRequest {
+            query: None,
+            memory_type: None,
+            category: None,
+            tags: None,
+            limit: Some(100),
+            since: None,
+            until: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memories_round_trip_through_relays() {
+        let keys = Keys::generate();
+        let transport: SharedTransport = Arc::new(FakeTransport::new(keys.clone()));
+        let writer = NostrMemoryClient::new(transport.clone(), keys.clone(), keys.public_key());
+
+        let kept = memory("Kept");
+        let removed = memory("Removed");
+        writer.store_memory(&kept).await.unwrap();
+        writer.store_memory(&removed).await.unwrap();
+        writer
+            .delete_memory(&removed.id.to_string(), false)
+            .await
+            .unwrap();
+
+        // A fresh client has no local copies, so everything comes back from the relay
+        let reader = NostrMemoryClient::new(transport, keys.clone(), keys.public_key());
+        let memories = reader.retrieve_memories(&everything()).await.unwrap();
+        assert_eq!(memories.len(), 1);
+        assert_eq!(memories[0].id, kept.id);
+        assert_eq!(memories[0].content.title, "Kept");
+
+        let update = UpdateMemoryRequest {
+            id: kept.id.to_string(),
+            title: Some("Kept, renamed".to_string()),
+            description: None,
+            tags: None,
+            priority: None,
+            expiry: None,
+        };
+        reader
+            .update_memory(&kept.id.to_string(), &update)
+            .await
+            .unwrap();
+        let memories = writer.retrieve_memories(&everything()).await.unwrap();
+        assert_eq!(memories.len(), 1);
+        assert_eq!(memories[0].content.title, "Kept, renamed");
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_delete_keeps_the_memory() {
+        let keys = Keys::generate();
+        let transport: SharedTransport = Arc::new(FakeTransport::new(keys.clone()));
+        let client = NostrMemoryClient::new(transport, keys.clone(), keys.public_key());
+        let stored = memory("Stored");
+        client.store_memory(&stored).await.unwrap();
+        let id = stored.id.to_string();
+
+        let preview = client.delete_memory(&id, true).await.unwrap().unwrap();
+        assert_eq!(preview.content.title, "Stored");
+        assert_eq!(
+            client.retrieve_memories(&everything()).await.unwrap().len(),
+            1
+        );
+
+        assert!(client.delete_memory(&id, false).await.unwrap().is_some());
+        assert!(client
+            .retrieve_memories(&everything())
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(client.delete_memory(&id, true).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memories_from_other_authors_are_ignored() {
+        let keys = Keys::generate();
+        let fake = FakeTransport::new(keys.clone());
+
+        // Someone else gift-wraps a well-formed memory DM to us
+        let stranger = Keys::generate();
+        let content = MemoryEncryption::new(stranger.clone())
+            .create_memory_dm_content(&memory("Forged"))
+            .unwrap();
+        let event = build_private_msg(&stranger, keys.public_key(), content, None, vec![], 0)
+            .await
+            .unwrap();
+        fake.send_event(&event).await.unwrap();
+
+        let reader = NostrMemoryClient::new(Arc::new(fake), keys.clone(), keys.public_key());
+        assert!(reader
+            .retrieve_memories(&everything())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    /// Run with `cargo test --release -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_memory_sync_over_fifty_thousand_gift_wraps() {
+        let keys = Keys::generate();
+        let transport: SharedTransport = Arc::new(FakeTransport::new(keys.clone()));
+        let writer = NostrMemoryClient::new(transport.clone(), keys.clone(), keys.public_key());
+        // 1000 memories with 50 versions each
+        let mut memories: Vec<MemoryEntry> =
+            (0..1000).map(|i| memory(&format!("M{}", i))).collect();
+        for version in 0..50 {
+            for memory in &mut memories {
+                memory.content.description = format!("Version {}", version);
+                memory.timestamp += chrono::Duration::seconds(1);
+                writer.store_memory(memory).await.unwrap();
+            }
+        }
+
+        let reader = NostrMemoryClient::new(transport, keys.clone(), keys.public_key());
+        let started = std::time::Instant::now();
+        let exported = reader.export_memories().await.unwrap();
+        let elapsed = started.elapsed();
+        println!(
+            "memory sync over 50000 gift wraps: {:?}, {} memories",
+            elapsed,
+            exported.len()
+        );
+        assert_eq!(exported.len(), 1000);
+        assert!(exported
+            .iter()
+            .all(|memory| memory.content.description == "Version 49"));
+    }
+}
@@ -1,6 +1,8 @@
 use super::client::{NostrMemoryClient, NostrMemoryError};
 use super::types::*;
-use chrono::{DateTime, Utc};
+use crate::config::units;
+use crate::dry_run;
+use chrono::Utc;
 
 /// High-level memory manager that handles business logic
 #[derive(Debug, Clone)]
@@ -20,18 +22,12 @@ impl MemoryManager {
         request: &StoreMemoryRequest,
     ) -> Result<MemoryEntry, NostrMemoryError> {
         // Parse expiry if provided
-        let expiry = if let Some(expiry_str) = &request.expiry {
-            match DateTime::parse_from_rfc3339(expiry_str) {
-                Ok(dt) => Some(dt.with_timezone(&Utc)),
-                Err(_) => {
-                    return Err(NostrMemoryError::InvalidData(
-                        "Invalid expiry date format. Use ISO 8601 format.".to_string(),
-                    ))
-                }
-            }
-        } else {
-            None
-        };
+        let expiry = request
+            .expiry
+            .as_deref()
+            .map(|expiry| units::parse_expiry(expiry, Utc::now()))
+            .transpose()
+            .map_err(|e| NostrMemoryError::InvalidData("expiry", e))?;
 
         // Create the memory entry
         let memory = MemoryEntry::new(
@@ -77,12 +73,13 @@ impl MemoryManager {
         self.client.update_memory(&request.id, request).await
     }
 
-    /// Delete a memory by ID
+    /// Delete a memory by ID, or with `dry_run` only look it up; `None` if there is none
     pub async fn delete_memory(
         &self,
         request: &DeleteMemoryRequest,
-    ) -> Result<bool, NostrMemoryError> {
-        self.client.delete_memory(&request.id).await
+        dry_run: bool,
+    ) -> Result<Option<MemoryEntry>, NostrMemoryError> {
+        self.client.delete_memory(&request.id, dry_run).await
     }
 
     /// Get memory statistics
@@ -90,6 +87,21 @@ impl MemoryManager {
         self.client.get_memory_stats().await
     }
 
+    /// All unexpired memories, newest first
+    pub async fn all_memories(&self) -> Result<Vec<MemoryEntry>, NostrMemoryError> {
+        let filter = RetrieveMemoryRequest {
+            query: None,
+            memory_type: None,
+            category: None,
+            tags: None,
+            limit: Some(10000),
+            since: None,
+            until: None,
+        };
+        let memories = self.client.retrieve_memories(&filter).await?;
+        Ok(memories.into_iter().filter(|m| !m.is_expired()).collect())
+    }
+
     /// Search for memories by content (convenience method)
     #[allow(dead_code)] // Convenience method for future use
     pub async fn search_memories(
@@ -189,7 +201,8 @@ impl MemoryManager {
         self.client.retrieve_memories(&request).await
     }
 
-    /// Clean up expired memories (returns count of expired memories found)
+    /// Clean up expired memories (returns count of expired memories found); a forced dry run
+    /// only counts them
     pub async fn cleanup_expired_memories(&self) -> Result<usize, NostrMemoryError> {
         let request = RetrieveMemoryRequest {
             query: None,
@@ -209,8 +222,10 @@ impl MemoryManager {
                 // Mark as deleted
                 let delete_request = DeleteMemoryRequest {
                     id: memory.id.to_string(),
+                    dry_run: None,
                 };
-                self.delete_memory(&delete_request).await?;
+                self.delete_memory(&delete_request, dry_run::forced())
+                    .await?;
                 expired_count += 1;
             }
         }
@@ -1,6 +1,7 @@
 pub mod client;
 pub mod encryption;
 pub mod memory_manager;
+pub mod resources;
 pub mod server;
 pub mod types;
 
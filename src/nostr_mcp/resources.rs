@@ -0,0 +1,118 @@
+//! Memories exposed as MCP resources
+//!
+//! Each memory is listed as `memory://<category>/<uuid>` (or `memory://<uuid>` when it has no
+//! category), so clients that browse resources see them grouped by category.
+
+use super::types::MemoryEntry;
+use rmcp::model::{AnnotateAble, RawResource, Resource};
+use uuid::Uuid;
+
+pub const URI_SCHEME: &str = "memory://";
+
+/// Resources returned per `resources/list` page
+pub const PAGE_SIZE: usize = 50;
+
+pub fn memory_uri(memory: &MemoryEntry) -> String {
+    match memory.category.as_deref().map(path_segment) {
+        Some(category) if !category.is_empty() => {
+            format!("{}{}/{}", URI_SCHEME, category, memory.id)
+        }
+        _ => format!("{}{}", URI_SCHEME, memory.id),
+    }
+}
+
+/// Extracts the memory id from a `memory://` URI; the category segment is informational
+pub fn parse_memory_uri(uri: &str) -> Option<Uuid> {
+    let path = uri.strip_prefix(URI_SCHEME)?;
+    Uuid::parse_str(path.rsplit('/').next()?).ok()
+}
+
+fn path_segment(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+pub fn to_resource(memory: &MemoryEntry) -> Resource {
+    let mut resource = RawResource::new(memory_uri(memory), memory.content.title.clone());
+    resource.description = Some(format!(
+        "{} memory from {}",
+        memory.memory_type,
+        memory.timestamp.format("%Y-%m-%d")
+    ));
+    resource.mime_type = Some("application/json".to_string());
+    resource.no_annotation()
+}
+
+/// Returns the page of `memories` starting at `cursor` and the cursor of the next page
+pub fn page<'a>(
+    memories: &'a [MemoryEntry],
+    cursor: Option<&str>,
+) -> Result<(&'a [MemoryEntry], Option<String>), String> {
+    let start = match cursor {
+        Some(cursor) => cursor
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid cursor '{}'", cursor))?,
+        None => 0,
+    }
+    .min(memories.len());
+    let end = (start + PAGE_SIZE).min(memories.len());
+    let next = (end < memories.len()).then(|| end.to_string());
+    Ok((&memories[start..end], next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(category: Option<&str>) -> MemoryEntry {
+        MemoryEntry::new(
+            "fact".to_string(),
+            category.map(str::to_string),
+            "Title".to_string(),
+            "Body".to_string(),
+            Vec::new(),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_uri_round_trip() {
+        let with_category = memory(Some("Side Project/Ideas"));
+        let uri = memory_uri(&with_category);
+        assert_eq!(
+            uri,
+            format!("memory://side-project-ideas/{}", with_category.id)
+        );
+        assert_eq!(parse_memory_uri(&uri), Some(with_category.id));
+
+        let plain = memory(None);
+        assert_eq!(memory_uri(&plain), format!("memory://{}", plain.id));
+        assert_eq!(parse_memory_uri(&memory_uri(&plain)), Some(plain.id));
+        assert_eq!(parse_memory_uri("file:///etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_pagination() {
+        let memories: Vec<MemoryEntry> = (0..PAGE_SIZE + 5).map(|_| memory(None)).collect();
+
+        let (first, next) = page(&memories, None).unwrap();
+        assert_eq!(first.len(), PAGE_SIZE);
+        assert_eq!(next.as_deref(), Some("50"));
+
+        let (second, next) = page(&memories, Some("50")).unwrap();
+        assert_eq!(second.len(), 5);
+        assert!(next.is_none());
+
+        assert!(page(&memories, Some("abc")).is_err());
+    }
+}
@@ -1,14 +1,22 @@
 use super::client::NostrMemoryClient;
 use super::memory_manager::MemoryManager;
+use super::resources;
 use super::types::*;
-use crate::mcp::chat::{Chat, ProgressMessageRequest, SendMessageRequest};
+use crate::dry_run;
+use crate::mcp::chat::Chat;
+use crate::mcp::server_common::{reply_and_result, reply_error, with_progress, ServerInfoBuilder};
+use crate::progress_channels::ProgressChannels;
+use crate::timezone;
 use nostr_sdk::prelude::*;
 use rmcp::{
     model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
+        CallToolResult, ListResourcesResult, PaginatedRequestParam, ReadResourceRequestParam,
+        ReadResourceResult, ResourceContents, ServerInfo,
     },
-    tool, Error as RmcpError, ServerHandler,
+    service::RequestContext,
+    tool, Error as RmcpError, RoleServer, ServerHandler,
 };
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct NostrMemoryServer {
@@ -21,14 +29,15 @@ impl NostrMemoryServer {
     /// Create a new Nostr Memory MCP server
     pub fn new(
         nostr_client: Client,
-        progress_client: Option<Client>,
+        progress_clients: ProgressChannels<Client>,
         keys: Keys,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
     ) -> Self {
-        let memory_client = NostrMemoryClient::new(nostr_client.clone(), keys, our_pubkey);
+        let memory_client =
+            NostrMemoryClient::new(Arc::new(nostr_client.clone()), keys, our_pubkey);
         let memory_manager = MemoryManager::new(memory_client);
-        let chat = Chat::new(nostr_client, progress_client, our_pubkey, target_pubkey);
+        let chat = Chat::new(nostr_client, progress_clients, our_pubkey, target_pubkey);
 
         Self {
             memory_manager,
@@ -36,22 +45,37 @@ impl NostrMemoryServer {
         }
     }
 
+    /// Same as `new`, for any transport (e.g. the in-memory relays in tests)
+    #[cfg(test)]
+    pub fn with_transport(
+        transport: crate::transport::SharedTransport,
+        keys: Keys,
+        our_pubkey: PublicKey,
+        target_pubkey: PublicKey,
+    ) -> Self {
+        let memory_client = NostrMemoryClient::new(transport.clone(), keys, our_pubkey);
+        Self {
+            memory_manager: MemoryManager::new(memory_client),
+            chat: Chat::with_transport(
+                transport,
+                ProgressChannels::default(),
+                our_pubkey,
+                target_pubkey,
+            ),
+        }
+    }
+
     #[tool(description = "Store a new memory entry in Nostr")]
     pub async fn store_memory(
         &self,
         #[tool(aggr)] request: StoreMemoryRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Storing memory: {}", request.title),
-            })
-            .await;
-
-        match self
-            .memory_manager
-            .store_memory_from_request(&request)
-            .await
+        match with_progress(
+            &self.chat,
+            format!("Storing memory: {}", request.title),
+            self.memory_manager.store_memory_from_request(&request),
+        )
+        .await
         {
             Ok(memory) => {
                 let message = format!(
@@ -63,7 +87,7 @@ impl NostrMemoryServer {
                      {}{}",
                     memory.content.title,
                     memory.id,
-                    memory.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                    timezone::default_zone().format(memory.timestamp, "%Y-%m-%d %H:%M:%S"),
                     memory.memory_type,
                     memory
                         .category
@@ -77,23 +101,14 @@ impl NostrMemoryServer {
                     }
                 );
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Memory stored with ID: {}",
-                    memory.id
-                ))]))
-            }
-            Err(e) => {
-                let error_message = format!("❌ Failed to store memory: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_message.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_message)]))
+                reply_and_result(
+                    &self.chat,
+                    message,
+                    format!("Memory stored with ID: {}", memory.id),
+                )
+                .await
             }
+            Err(e) => reply_error(&self.chat, format!("❌ Failed to store memory: {}", e), e).await,
         }
     }
 
@@ -108,14 +123,13 @@ impl NostrMemoryServer {
             "Retrieving memories".to_string()
         };
 
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: query_desc,
-            })
-            .await;
-
-        match self.memory_manager.retrieve_memories(&request).await {
+        match with_progress(
+            &self.chat,
+            query_desc,
+            self.memory_manager.retrieve_memories(&request),
+        )
+        .await
+        {
             Ok(response) => {
                 let message = if response.memories.is_empty() {
                     "🔍 No memories found matching your criteria.".to_string()
@@ -134,7 +148,7 @@ impl NostrMemoryServer {
                             i + 1,
                             memory.content.title,
                             memory.id,
-                            memory.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                            timezone::default_zone().format(memory.timestamp, "%Y-%m-%d %H:%M:%S"),
                             memory.memory_type,
                             memory
                                 .category
@@ -153,22 +167,20 @@ impl NostrMemoryServer {
                     message
                 };
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Retrieved {} memories",
-                    response.memories.len()
-                ))]))
+                reply_and_result(
+                    &self.chat,
+                    message,
+                    format!("Retrieved {} memories", response.memories.len()),
+                )
+                .await
             }
             Err(e) => {
-                let error_message = format!("❌ Failed to retrieve memories: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_message.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_message)]))
+                reply_error(
+                    &self.chat,
+                    format!("❌ Failed to retrieve memories: {}", e),
+                    e,
+                )
+                .await
             }
         }
     }
@@ -178,14 +190,13 @@ impl NostrMemoryServer {
         &self,
         #[tool(aggr)] request: UpdateMemoryRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Updating memory: {}", request.id),
-            })
-            .await;
-
-        match self.memory_manager.update_memory(&request).await {
+        match with_progress(
+            &self.chat,
+            format!("Updating memory: {}", request.id),
+            self.memory_manager.update_memory(&request),
+        )
+        .await
+        {
             Ok(memory) => {
                 let message = format!(
                     "✅ Memory updated successfully!\n\n\
@@ -196,7 +207,7 @@ impl NostrMemoryServer {
                      {}{}",
                     memory.content.title,
                     memory.id,
-                    memory.timestamp.format("%Y-%m-%d %H:%M:%S UTC"),
+                    timezone::default_zone().format(memory.timestamp, "%Y-%m-%d %H:%M:%S"),
                     memory.memory_type,
                     memory
                         .category
@@ -210,71 +221,76 @@ impl NostrMemoryServer {
                     }
                 );
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Memory {} updated successfully",
-                    memory.id
-                ))]))
+                reply_and_result(
+                    &self.chat,
+                    message,
+                    format!("Memory {} updated successfully", memory.id),
+                )
+                .await
             }
             Err(e) => {
-                let error_message = format!("❌ Failed to update memory: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_message.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_message)]))
+                reply_error(&self.chat, format!("❌ Failed to update memory: {}", e), e).await
             }
         }
     }
 
-    #[tool(description = "Delete a memory entry by ID")]
+    #[tool(description = "Delete a memory entry by ID; dry_run only reports what would be deleted")]
     pub async fn delete_memory(
         &self,
         #[tool(aggr)] request: DeleteMemoryRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Deleting memory: {}", request.id),
-            })
-            .await;
-
-        match self.memory_manager.delete_memory(&request).await {
-            Ok(_) => {
-                let message = format!("🗑️ Memory {} deleted successfully", request.id);
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Memory {} deleted",
-                    request.id
-                ))]))
+        let dry_run = dry_run::requested(request.dry_run);
+        let label = if dry_run {
+            format!("Previewing deletion of memory: {}", request.id)
+        } else {
+            format!("Deleting memory: {}", request.id)
+        };
+        match with_progress(
+            &self.chat,
+            label,
+            self.memory_manager.delete_memory(&request, dry_run),
+        )
+        .await
+        {
+            Ok(memory) => {
+                let (message, result) = match memory {
+                    Some(memory) if dry_run => {
+                        let result = format!(
+                            "Would delete 1 memory: {}: {}",
+                            memory.id, memory.content.title
+                        );
+                        (dry_run::label(&result), dry_run::label(&result))
+                    }
+                    Some(_) => (
+                        format!("🗑️ Memory {} deleted successfully", request.id),
+                        format!("Memory {} deleted", request.id),
+                    ),
+                    None if dry_run => (
+                        dry_run::label("no memory with that ID, nothing would be deleted"),
+                        dry_run::label(&format!("Memory {} not found", request.id)),
+                    ),
+                    None => (
+                        format!("❌ Memory {} not found", request.id),
+                        format!("Memory {} not found", request.id),
+                    ),
+                };
+                reply_and_result(&self.chat, message, result).await
             }
             Err(e) => {
-                let error_message = format!("❌ Failed to delete memory: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_message.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_message)]))
+                reply_error(&self.chat, format!("❌ Failed to delete memory: {}", e), e).await
             }
         }
     }
 
     #[tool(description = "Get statistics about stored memories")]
     pub async fn memory_stats(&self) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Gathering memory statistics...".to_string(),
-            })
-            .await;
-
-        match self.memory_manager.get_memory_stats().await {
+        match with_progress(
+            &self.chat,
+            "Gathering memory statistics...",
+            self.memory_manager.get_memory_stats(),
+        )
+        .await
+        {
             Ok(stats) => {
                 let mut message = "📊 **Memory Statistics**\n\n".to_string();
                 message.push_str(&format!(
@@ -301,85 +317,135 @@ impl NostrMemoryServer {
                 if let Some(oldest) = stats.oldest {
                     message.push_str(&format!(
                         "📅 **Oldest:** {}\n",
-                        oldest.format("%Y-%m-%d %H:%M:%S UTC")
+                        timezone::default_zone().format(oldest, "%Y-%m-%d %H:%M:%S")
                     ));
                 }
 
                 if let Some(newest) = stats.newest {
                     message.push_str(&format!(
                         "📅 **Newest:** {}\n",
-                        newest.format("%Y-%m-%d %H:%M:%S UTC")
+                        timezone::default_zone().format(newest, "%Y-%m-%d %H:%M:%S")
                     ));
                 }
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Memory statistics: {} total memories",
-                    stats.total_memories
-                ))]))
+                reply_and_result(
+                    &self.chat,
+                    message,
+                    format!("Memory statistics: {} total memories", stats.total_memories),
+                )
+                .await
             }
             Err(e) => {
-                let error_message = format!("❌ Failed to get memory statistics: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_message.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_message)]))
+                reply_error(
+                    &self.chat,
+                    format!("❌ Failed to get memory statistics: {}", e),
+                    e,
+                )
+                .await
             }
         }
     }
 
     #[tool(description = "Clean up expired memories")]
     pub async fn cleanup_expired_memories(&self) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: "Cleaning up expired memories...".to_string(),
-            })
-            .await;
-
-        match self.memory_manager.cleanup_expired_memories().await {
+        match with_progress(
+            &self.chat,
+            "Cleaning up expired memories...",
+            self.memory_manager.cleanup_expired_memories(),
+        )
+        .await
+        {
             Ok(expired_count) => {
                 let message = if expired_count == 0 {
                     "✅ No expired memories found. All memories are current.".to_string()
+                } else if dry_run::forced() {
+                    dry_run::label(&format!(
+                        "{} expired memories would be cleaned up",
+                        expired_count
+                    ))
                 } else {
                     format!("🧹 Cleaned up {} expired memories", expired_count)
                 };
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
-                Ok(CallToolResult::success(vec![Content::text(format!(
-                    "Cleaned up {} expired memories",
-                    expired_count
-                ))]))
+                let result = if dry_run::forced() {
+                    dry_run::label(&format!("{} expired memories found", expired_count))
+                } else {
+                    format!("Cleaned up {} expired memories", expired_count)
+                };
+                reply_and_result(&self.chat, message, result).await
             }
             Err(e) => {
-                let error_message = format!("❌ Failed to cleanup expired memories: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_message.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_message)]))
+                reply_error(
+                    &self.chat,
+                    format!("❌ Failed to cleanup expired memories: {}", e),
+                    e,
+                )
+                .await
             }
         }
     }
 }
 
+impl NostrMemoryServer {
+    /// The tools this server offers
+    pub fn tools(&self) -> Vec<rmcp::model::Tool> {
+        Self::tool_box().list()
+    }
+}
+
 #[tool(tool_box)]
 impl ServerHandler for NostrMemoryServer {
     fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some("This Nostr Memory MCP server provides persistent memory storage for AI agents using encrypted Nostr direct messages.\n\n🧠 **MEMORY OPERATIONS**:\n\n📝 **store_memory**: Store new memory entries with type, category, tags, and optional expiry\n🔍 **retrieve_memory**: Search and filter memories by query, type, category, tags, or date range\n✏️ **update_memory**: Modify existing memory entries\n🗑️ **delete_memory**: Remove memory entries by ID\n📊 **memory_stats**: Get statistics about stored memories\n🧹 **cleanup_expired_memories**: Remove expired memory entries\n\n🔐 **PRIVACY & SECURITY**:\n• All memories are encrypted using Nostr NIP-17 private messages\n• Memories are stored as DMs to yourself for maximum privacy\n• Each memory has a unique UUID for precise identification\n• Memories can have expiry dates for automatic cleanup\n\n📋 **MEMORY TYPES**:\n• user_preference: User preferences and settings\n• context: Contextual information about conversations\n• fact: Important facts to remember\n• instruction: Instructions or commands to remember\n• note: General notes and observations\n\n📂 **CATEGORIES**:\n• personal: Personal information\n• work: Work-related memories\n• project: Project-specific information\n• general: General purpose memories\n\n🏷️ **FEATURES**:\n• Full-text search across titles and descriptions\n• Tag-based organization and filtering\n• Priority levels (high, medium, low)\n• Date range filtering\n• Automatic expiry handling\n• Comprehensive statistics\n\n💡 **USAGE TIPS**:\n• Use descriptive titles for easy searching\n• Add relevant tags for better organization\n• Set expiry dates for temporary information\n• Use appropriate types and categories for filtering\n• Regular cleanup of expired memories keeps storage optimal".to_string()),
-        }
+        ServerInfoBuilder::new()
+            .with_resources()
+            .instructions("This Nostr Memory MCP server provides persistent memory storage for AI agents using encrypted Nostr direct messages.\n\n🧠 **MEMORY OPERATIONS**:\n\n📝 **store_memory**: Store new memory entries with type, category, tags, and optional expiry\n🔍 **retrieve_memory**: Search and filter memories by query, type, category, tags, or date range\n✏️ **update_memory**: Modify existing memory entries\n🗑️ **delete_memory**: Remove memory entries by ID\n📊 **memory_stats**: Get statistics about stored memories\n🧹 **cleanup_expired_memories**: Remove expired memory entries\n\n🔐 **PRIVACY & SECURITY**:\n• All memories are encrypted using Nostr NIP-17 private messages\n• Memories are stored as DMs to yourself for maximum privacy\n• Each memory has a unique UUID for precise identification\n• Memories can have expiry dates for automatic cleanup\n\n📋 **MEMORY TYPES**:\n• user_preference: User preferences and settings\n• context: Contextual information about conversations\n• fact: Important facts to remember\n• instruction: Instructions or commands to remember\n• note: General notes and observations\n\n📂 **CATEGORIES**:\n• personal: Personal information\n• work: Work-related memories\n• project: Project-specific information\n• general: General purpose memories\n\n🏷️ **FEATURES**:\n• Full-text search across titles and descriptions\n• Tag-based organization and filtering\n• Priority levels (high, medium, low)\n• Date range filtering\n• Automatic expiry handling\n• Comprehensive statistics\n\n💡 **USAGE TIPS**:\n• Use descriptive titles for easy searching\n• Add relevant tags for better organization\n• Set expiry dates for temporary information\n• Use appropriate types and categories for filtering\n• Regular cleanup of expired memories keeps storage optimal")
+            .build()
+    }
+
+    async fn list_resources(
+        &self,
+        request: PaginatedRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, RmcpError> {
+        let memories = self
+            .memory_manager
+            .all_memories()
+            .await
+            .map_err(|e| RmcpError::internal_error(e.to_string(), None))?;
+        let cursor = request.and_then(|r| r.cursor);
+        let (page, next_cursor) = resources::page(&memories, cursor.as_deref())
+            .map_err(|e| RmcpError::invalid_params(e, None))?;
+
+        Ok(ListResourcesResult {
+            resources: page.iter().map(resources::to_resource).collect(),
+            next_cursor,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        ReadResourceRequestParam { uri }: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, RmcpError> {
+        let id = resources::parse_memory_uri(&uri)
+            .ok_or_else(|| RmcpError::invalid_params(format!("Not a memory URI: {}", uri), None))?;
+        let memory = self
+            .memory_manager
+            .all_memories()
+            .await
+            .map_err(|e| RmcpError::internal_error(e.to_string(), None))?
+            .into_iter()
+            .find(|m| m.id == id)
+            .ok_or_else(|| RmcpError::resource_not_found(format!("No memory {}", id), None))?;
+        let text = serde_json::to_string_pretty(&memory)
+            .map_err(|e| RmcpError::internal_error(e.to_string(), None))?;
+
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri,
+                mime_type: Some("application/json".to_string()),
+                text,
+            }],
+        })
     }
 }
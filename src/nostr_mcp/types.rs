@@ -13,6 +13,9 @@ pub struct MemoryEntry {
     pub content: MemoryContent,
     pub encrypted: bool,
     pub version: String,
+    /// The enhanced server's note this memory mirrors, if it came from `syncnotes_to_memory`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_note: Option<String>,
 }
 
 /// Memory content structure
@@ -48,7 +51,9 @@ pub struct StoreMemoryRequest {
     pub tags: Option<Vec<String>>,
     #[schemars(description = "Optional priority level (high, medium, low)")]
     pub priority: Option<String>,
-    #[schemars(description = "Optional expiry date (ISO 8601 format)")]
+    #[schemars(
+        description = "Optional expiry: how long to keep the memory (\"7d\", \"12h\") or an ISO 8601 date"
+    )]
     pub expiry: Option<String>,
 }
 
@@ -86,7 +91,9 @@ pub struct UpdateMemoryRequest {
     pub tags: Option<Vec<String>>,
     #[schemars(description = "New priority (optional, high, medium, low)")]
     pub priority: Option<String>,
-    #[schemars(description = "New expiry date (optional, ISO 8601)")]
+    #[schemars(
+        description = "New expiry (optional): how long from now (\"7d\") or an ISO 8601 date"
+    )]
     pub expiry: Option<String>,
 }
 
@@ -95,6 +102,10 @@ pub struct UpdateMemoryRequest {
 pub struct DeleteMemoryRequest {
     #[schemars(description = "UUID of the memory to delete")]
     pub id: String,
+    #[schemars(
+        description = "Only report what would be deleted, without deleting it (optional, defaults to false)"
+    )]
+    pub dry_run: Option<bool>,
 }
 
 /// Response for memory operations
@@ -142,6 +153,7 @@ impl MemoryEntry {
             },
             encrypted: true,
             version: "1.0".to_string(),
+            source_note: None,
         }
     }
 
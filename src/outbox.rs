@@ -0,0 +1,123 @@
+//! `nparrot outbox`: the resend queue from the shell
+//!
+//! `list` shows the events waiting to be resent, `flush` tries each of them once right away and
+//! `drop` discards one that will never get through. They work on the data dir's
+//! `redelivery.json` under its lock, so they are safe to run next to a daemon or MCP server
+//! using the same data dir.
+
+use crate::redelivery::PendingMessage;
+use nostr_sdk::prelude::*;
+
+/// The queued event `id` names: its hex id, a unique prefix of it, or its `note1…`
+pub fn resolve(pending: &[PendingMessage], id: &str) -> Result<EventId, String> {
+    let id = id.trim();
+    if let Ok(exact) = EventId::parse(id) {
+        return pending
+            .iter()
+            .find(|message| message.id == exact)
+            .map(|message| message.id)
+            .ok_or_else(|| format!("No queued message {}", id));
+    }
+    let prefix = id.to_lowercase();
+    let matches: Vec<EventId> = pending
+        .iter()
+        .map(|message| message.id)
+        .filter(|queued| !prefix.is_empty() && queued.to_hex().starts_with(&prefix))
+        .collect();
+    match matches.as_slice() {
+        [only] => Ok(*only),
+        [] => Err(format!("No queued message {}", id)),
+        _ => Err(format!(
+            "{} queued messages start with {}, give more of the id",
+            matches.len(),
+            id
+        )),
+    }
+}
+
+/// How long ago `then` was, in the largest whole unit
+fn age(then: Timestamp, now: Timestamp) -> String {
+    let secs = now.as_u64().saturating_sub(then.as_u64());
+    match secs {
+        s if s >= 86400 => format!("{}d", s / 86400),
+        s if s >= 3600 => format!("{}h", s / 3600),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// One line per queued message: id, age, recipient, channel and attempts
+pub fn format_list(pending: &[PendingMessage], now: Timestamp) -> String {
+    if pending.is_empty() {
+        return "No messages waiting to be resent\n".to_string();
+    }
+    pending
+        .iter()
+        .map(|message| {
+            format!(
+                "{}  {:>4}  {}  {}  {} attempt(s)\n",
+                message.id.to_hex(),
+                message
+                    .queued_at
+                    .map(|queued_at| age(queued_at, now))
+                    .unwrap_or_else(|| "?".to_string()),
+                message
+                    .recipient
+                    .to_bech32()
+                    .unwrap_or_else(|_| message.recipient.to_hex()),
+                message.channel,
+                message.attempts
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: EventId, queued_at: Option<u64>) -> PendingMessage {
+        PendingMessage {
+            id,
+            recipient: Keys::generate().public_key(),
+            channel: "main".to_string(),
+            attempts: 2,
+            queued_at: queued_at.map(Timestamp::from),
+            next_attempt_at: Timestamp::from(0),
+        }
+    }
+
+    #[test]
+    fn test_resolve_ids() {
+        let first = EventId::all_zeros();
+        let second = EventId::from_byte_array([0x01; 32]);
+        let pending = [message(first, None), message(second, None)];
+
+        assert_eq!(resolve(&pending, &second.to_hex()), Ok(second));
+        assert_eq!(resolve(&pending, &second.to_bech32().unwrap()), Ok(second));
+        assert_eq!(resolve(&pending, "0101"), Ok(second));
+        assert!(resolve(&pending, "0")
+            .unwrap_err()
+            .contains("2 queued messages"));
+        assert!(resolve(&pending, "ff").unwrap_err().contains("No queued"));
+        assert!(resolve(&pending, "").is_err());
+    }
+
+    #[test]
+    fn test_list_shows_age_and_attempts() {
+        let now = Timestamp::from(10_000);
+        let list = format_list(
+            &[
+                message(EventId::all_zeros(), Some(10_000 - 7200)),
+                message(EventId::from_byte_array([0x01; 32]), None),
+            ],
+            now,
+        );
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("    2h  npub1"));
+        assert!(lines[0].ends_with("main  2 attempt(s)"));
+        assert!(lines[1].contains("     ?  npub1"));
+        assert_eq!(format_list(&[], now), "No messages waiting to be resent\n");
+    }
+}
@@ -0,0 +1,113 @@
+//! How chatty the plain CLI commands are on stderr, set by the global `-q` / `-v` flags
+//!
+//! stdout only ever carries the essentials (decrypted messages, event ids), so scripts can rely
+//! on it in every mode. Errors are always printed.
+
+use crate::logging::{apply_format, LogFormat};
+use nostr_sdk::prelude::*;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+lazy_static::lazy_static! {
+    static ref STARTED: Instant = Instant::now();
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        if quiet {
+            Self::Quiet
+        } else if verbose {
+            Self::Verbose
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+/// Installs the process-wide verbosity used by `status!` and `detail!`
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    VERBOSITY.load(Ordering::Relaxed) == Verbosity::Quiet as u8
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSITY.load(Ordering::Relaxed) == Verbosity::Verbose as u8
+}
+
+/// A progress or status line on stderr, hidden by `-q`
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Extra detail on stderr, shown only with `-v`
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        if $crate::output::is_verbose() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use detail;
+pub(crate) use status;
+
+/// Sets up stderr logging for the plain CLI commands: `RUST_LOG` as usual, except that `-v`
+/// raises our own modules to at least info
+pub fn init_logger(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    apply_format(&mut builder, format);
+    let env_is_more_verbose = std::env::var("RUST_LOG")
+        .is_ok_and(|filter| filter.contains("debug") || filter.contains("trace"));
+    if is_verbose() && !env_is_more_verbose {
+        builder.filter_module(env!("CARGO_CRATE_NAME"), log::LevelFilter::Info);
+    }
+    builder.init();
+}
+
+/// With `-v`, one step of the startup timing breakdown and how long after the first step it
+/// finished
+pub fn timing(step: &str) {
+    let elapsed = STARTED.elapsed();
+    if is_verbose() {
+        eprintln!("[{:>5} ms] {}", elapsed.as_millis(), step);
+    }
+}
+
+/// With `-v`, how each relay answered a publish and how long it took
+pub fn publish_results(output: &Output<EventId>, elapsed: Duration) {
+    if !is_verbose() {
+        return;
+    }
+    eprintln!("Published {} in {} ms", output.val, elapsed.as_millis());
+    let mut lines: Vec<String> = output
+        .success
+        .iter()
+        .map(|url| format!("  ok      {}", url))
+        .chain(
+            output
+                .failed
+                .iter()
+                .map(|(url, reason)| format!("  failed  {}: {}", url, reason)),
+        )
+        .collect();
+    lines.sort();
+    for line in lines {
+        eprintln!("{}", line);
+    }
+}
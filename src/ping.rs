@@ -0,0 +1,122 @@
+//! The `ping` subcommand: per-relay connect time, round-trip time and NIP-11 details
+
+use crate::relays::{self, RelayProbe};
+use nostr_sdk::prelude::*;
+use std::time::Duration;
+
+struct PingResult {
+    url: String,
+    probe: Result<RelayProbe, String>,
+    info: Option<RelayInformationDocument>,
+}
+
+/// Pings every relay once, or every `watch` seconds until interrupted
+pub async fn run(urls: &[String], timeout: Duration, watch: Option<u64>) {
+    loop {
+        let results = ping_all(urls, timeout).await;
+        print_results(&results);
+
+        match watch {
+            Some(secs) => {
+                println!();
+                tokio::time::sleep(Duration::from_secs(secs.max(1))).await;
+            }
+            None => break,
+        }
+    }
+}
+
+async fn ping_all(urls: &[String], timeout: Duration) -> Vec<PingResult> {
+    let handles: Vec<_> = urls
+        .iter()
+        .cloned()
+        .map(|url| {
+            tokio::spawn(async move {
+                let (probe, info) = tokio::join!(
+                    relays::probe_relay(&url, timeout),
+                    relays::fetch_relay_info(&url, timeout)
+                );
+                PingResult { url, probe, info }
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        if let Ok(result) = handle.await {
+            results.push(result);
+        }
+    }
+    results
+}
+
+fn print_results(results: &[PingResult]) {
+    println!("{}", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC"));
+
+    for result in results {
+        match &result.probe {
+            Ok(probe) => println!(
+                "✅ {}  connect {}ms  rtt {}ms",
+                result.url,
+                probe.connect_time.as_millis(),
+                probe.rtt.as_millis()
+            ),
+            Err(e) => println!("❌ {}  {}", result.url, e),
+        }
+
+        if let Some(info) = &result.info {
+            for line in describe_info(info) {
+                println!("     {}", line);
+            }
+        }
+    }
+}
+
+fn describe_info(info: &RelayInformationDocument) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    if let Some(name) = &info.name {
+        match &info.software {
+            Some(software) => lines.push(format!("name: {} ({})", name, software)),
+            None => lines.push(format!("name: {}", name)),
+        }
+    }
+
+    if let Some(nips) = &info.supported_nips {
+        let nips: Vec<String> = nips.iter().map(|n| n.to_string()).collect();
+        lines.push(format!("nips: {}", nips.join(", ")));
+    }
+
+    if let Some(limitation) = &info.limitation {
+        let mut limits = Vec::new();
+        if let Some(v) = limitation.max_message_length {
+            limits.push(format!("max_message_length={}", v));
+        }
+        if let Some(v) = limitation.max_subscriptions {
+            limits.push(format!("max_subscriptions={}", v));
+        }
+        if let Some(v) = limitation.max_limit {
+            limits.push(format!("max_limit={}", v));
+        }
+        if let Some(v) = limitation.max_content_length {
+            limits.push(format!("max_content_length={}", v));
+        }
+        if let Some(v) = limitation.min_pow_difficulty {
+            limits.push(format!("min_pow_difficulty={}", v));
+        }
+        if limitation.auth_required == Some(true) {
+            limits.push("auth_required".to_string());
+        }
+        if limitation.payment_required == Some(true) {
+            limits.push("payment_required".to_string());
+        }
+        if !limits.is_empty() {
+            lines.push(format!("limits: {}", limits.join(" ")));
+        }
+        if limitation.auth_required == Some(true) {
+            lines.push("auth: NIP-42, answered automatically with the server's key".to_string());
+        }
+    }
+
+    lines
+}
@@ -0,0 +1,315 @@
+//! NIP-13 proof of work for outgoing events
+//!
+//! Some relays only accept events whose id carries a minimum number of leading zero bits.
+//! The policy is configured once at startup (`--pow` / `NPARROT_POW`) and consulted by every
+//! send path, so the CLI and the MCP servers mine the same way.
+//!
+//! Gift wraps are prepared unmined. When one is published, each relay that requires proof of
+//! work is sent a wrap of the same seal mined to its own difficulty, so relays without a
+//! requirement neither wait for the mining nor get a mined copy.
+
+use nostr_sdk::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+lazy_static::lazy_static! {
+    static ref POW_POLICY: RwLock<PowPolicy> = RwLock::new(PowPolicy::default());
+    // Mining is CPU bound, so it runs on blocking threads and never more than one per core
+    static ref MINING_SLOTS: Arc<Semaphore> = Arc::new(Semaphore::new(
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    ));
+    static ref SEALS: Mutex<HashMap<EventId, (Instant, Sealed)>> = Mutex::new(HashMap::new());
+}
+
+/// Prepared wraps whose seals are kept for mining at once; the oldest go first beyond this
+const MAX_SEALS: usize = 1024;
+
+/// What an unmined gift wrap was made of, enough to wrap the same message again
+#[derive(Debug, Clone)]
+pub struct Sealed {
+    pub seal: Event,
+    pub receiver: PublicKey,
+    pub tags: Vec<Tag>,
+}
+
+/// Required proof-of-work difficulty, globally and per relay
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PowPolicy {
+    default_difficulty: u8,
+    per_relay: HashMap<RelayUrl, u8>,
+}
+
+impl PowPolicy {
+    /// Parses a policy such as `20` or `wss://nos.lol=24,wss://relay.damus.io=0`.
+    ///
+    /// A bare number applies to every relay without an explicit entry.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut policy = PowPolicy::default();
+
+        for item in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match item.rsplit_once('=') {
+                Some((url, difficulty)) => {
+                    let url = RelayUrl::parse(url.trim()).map_err(|e| {
+                        format!("Invalid relay URL in PoW setting '{}': {}", item, e)
+                    })?;
+                    policy
+                        .per_relay
+                        .insert(url, parse_difficulty(difficulty.trim())?);
+                }
+                None => policy.default_difficulty = parse_difficulty(item)?,
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// Returns the difficulty `relay` requires
+    pub fn difficulty(&self, relay: &RelayUrl) -> u8 {
+        self.per_relay
+            .get(relay)
+            .copied()
+            .unwrap_or(self.default_difficulty)
+    }
+
+    /// Returns the difficulty needed for an event to be accepted by all of the given relays
+    pub fn difficulty_for<'a, I>(&self, relays: I) -> u8
+    where
+        I: IntoIterator<Item = &'a RelayUrl>,
+    {
+        relays
+            .into_iter()
+            .map(|url| self.difficulty(url))
+            .max()
+            .unwrap_or(self.default_difficulty)
+    }
+
+    /// Groups `relays` by the difficulty they require
+    pub fn by_difficulty<I>(&self, relays: I) -> BTreeMap<u8, Vec<RelayUrl>>
+    where
+        I: IntoIterator<Item = RelayUrl>,
+    {
+        let mut groups: BTreeMap<u8, Vec<RelayUrl>> = BTreeMap::new();
+        for url in relays {
+            groups.entry(self.difficulty(&url)).or_default().push(url);
+        }
+        groups
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.default_difficulty > 0 || self.per_relay.values().any(|d| *d > 0)
+    }
+}
+
+fn parse_difficulty(value: &str) -> Result<u8, String> {
+    value.parse::<u8>().map_err(|_| {
+        format!(
+            "Invalid PoW difficulty '{}': expected a number of leading zero bits (0-255)",
+            value
+        )
+    })
+}
+
+/// Installs the process-wide PoW policy used by all send paths
+pub fn set_policy(policy: PowPolicy) {
+    if let Ok(mut guard) = POW_POLICY.write() {
+        *guard = policy;
+    }
+}
+
+fn enabled_policy() -> Option<PowPolicy> {
+    match POW_POLICY.read() {
+        Ok(guard) if guard.is_enabled() => Some(guard.clone()),
+        _ => None,
+    }
+}
+
+async fn write_relays(client: &Client) -> Vec<RelayUrl> {
+    client
+        .pool()
+        .relays_with_flag(RelayServiceFlags::WRITE, FlagCheck::All)
+        .await
+        .into_keys()
+        .collect()
+}
+
+/// Returns the highest difficulty required by the relays this client publishes to
+pub async fn difficulty_for_client(client: &Client) -> u8 {
+    match enabled_policy() {
+        Some(policy) => policy.difficulty_for(&write_relays(client).await),
+        None => 0,
+    }
+}
+
+/// Keeps the seal behind the unmined gift wrap `wrap_id` until `publish` has sent it
+pub fn remember(wrap_id: EventId, sealed: Sealed) {
+    let mut seals = SEALS.lock().unwrap_or_else(|e| e.into_inner());
+    if seals.len() >= MAX_SEALS {
+        if let Some(oldest) = seals
+            .iter()
+            .min_by_key(|(_, (kept_at, _))| *kept_at)
+            .map(|(id, _)| *id)
+        {
+            seals.remove(&oldest);
+        }
+    }
+    seals.insert(wrap_id, (Instant::now(), sealed));
+}
+
+/// Publishes `event` to `urls`, or to every write relay when `None`.
+///
+/// If `event` is a prepared gift wrap and some of those relays require proof of work, each
+/// of them is sent a wrap of the same seal mined to its difficulty instead, while the others
+/// get `event` itself. The outcome is reported under `event`'s id. The seal is kept until
+/// every relay has accepted a wrap, so a redelivery mines again.
+pub async fn publish(
+    client: &Client,
+    urls: Option<Vec<RelayUrl>>,
+    event: &Event,
+) -> Result<Output<EventId>, Box<dyn std::error::Error + Send + Sync>> {
+    let sealed = SEALS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&event.id)
+        .map(|(_, sealed)| sealed.clone());
+    let (Some(policy), Some(sealed)) = (enabled_policy(), sealed) else {
+        return Ok(match urls {
+            Some(urls) => client.send_event_to(urls, event).await?,
+            None => client.send_event(event).await?,
+        });
+    };
+    let urls = match urls {
+        Some(urls) => urls,
+        None => write_relays(client).await,
+    };
+
+    let mut output = Output {
+        val: event.id,
+        success: Default::default(),
+        failed: Default::default(),
+    };
+    for (difficulty, relays) in policy.by_difficulty(urls) {
+        let wrap = if difficulty == 0 {
+            Ok(event.clone())
+        } else {
+            crate::utils::wrap_seal(
+                &sealed.seal,
+                sealed.receiver,
+                sealed.tags.clone(),
+                difficulty,
+            )
+            .await
+        };
+        let sent = match wrap {
+            Ok(wrap) => client
+                .send_event_to(relays.clone(), &wrap)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match sent {
+            Ok(sent) => {
+                output.success.extend(sent.success);
+                output.failed.extend(sent.failed);
+            }
+            Err(e) => {
+                for relay in relays {
+                    output.failed.insert(relay, e.clone());
+                }
+            }
+        }
+    }
+
+    if output.failed.is_empty() {
+        SEALS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&event.id);
+    }
+    Ok(output)
+}
+
+/// Mines and signs an event on a blocking worker, keeping the async runtime responsive
+pub async fn mine(
+    builder: EventBuilder,
+    keys: Keys,
+    difficulty: u8,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>> {
+    let _slot = MINING_SLOTS.clone().acquire_owned().await?;
+
+    let started = Instant::now();
+    let event = tokio::task::spawn_blocking(move || builder.pow(difficulty).sign_with_keys(&keys))
+        .await??;
+    log::debug!(
+        "Mined {} event {} to difficulty {} in {:?}",
+        event.kind,
+        event.id,
+        difficulty,
+        started.elapsed()
+    );
+
+    Ok(event)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> RelayUrl {
+        RelayUrl::parse(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_global_difficulty() {
+        let policy = PowPolicy::parse("20").unwrap();
+        assert!(policy.is_enabled());
+        assert_eq!(policy.difficulty_for([&url("wss://relay.damus.io")]), 20);
+    }
+
+    #[test]
+    fn test_per_relay_difficulty_skips_relays_without_pow() {
+        let policy = PowPolicy::parse("wss://nos.lol=24, wss://relay.damus.io=0").unwrap();
+        let damus = url("wss://relay.damus.io");
+        let nos = url("wss://nos.lol");
+        let other = url("wss://relay.example.com");
+
+        assert_eq!(policy.difficulty_for([&damus]), 0);
+        assert_eq!(policy.difficulty_for([&other]), 0);
+        assert_eq!(policy.difficulty_for([&damus, &nos]), 24);
+    }
+
+    #[test]
+    fn test_relays_are_grouped_by_their_difficulty() {
+        let policy = PowPolicy::parse("wss://nos.lol=24, wss://relay.example.com=16").unwrap();
+        let groups = policy.by_difficulty([
+            url("wss://relay.damus.io"),
+            url("wss://nos.lol"),
+            url("wss://relay.example.com"),
+        ]);
+
+        assert_eq!(groups[&0], vec![url("wss://relay.damus.io")]);
+        assert_eq!(groups[&16], vec![url("wss://relay.example.com")]);
+        assert_eq!(groups[&24], vec![url("wss://nos.lol")]);
+        assert_eq!(groups.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_values() {
+        assert!(PowPolicy::parse("hard").is_err());
+        assert!(PowPolicy::parse("300").is_err());
+        assert!(PowPolicy::parse("not a url=8").is_err());
+        assert!(!PowPolicy::parse("").unwrap().is_enabled());
+    }
+
+    #[tokio::test]
+    async fn test_mined_event_meets_difficulty() {
+        let keys = Keys::generate();
+        let event = mine(EventBuilder::text_note("hello"), keys, 8)
+            .await
+            .unwrap();
+
+        assert!(event.id.check_pow(8));
+        assert!(event.verify().is_ok());
+    }
+}
@@ -0,0 +1,375 @@
+//! One answering instance per identity (`NPARROT_MULTI_INSTANCE`)
+//!
+//! Two servers running with the same nsec both receive every DM and both answer it. While a
+//! server or `onmessage` runs it keeps a NIP-78 application-data event (kind 30078, `d` tag
+//! `nparrot/presence`) up to date on its relays, and holds `instance.lock` in its data dir. At
+//! startup another instance's lock, or its presence refreshed within `REFRESH_INTERVAL`, means
+//! the identity is taken: by default nparrot refuses to start, `passive` starts it without ever
+//! publishing (it receives and logs only) and `ignore` skips the check. Presence older than the
+//! interval, marked stopped on shutdown, or left on this host by a process that is gone doesn't
+//! count.
+//!
+//! The check is not a lock across machines: two instances starting within the few seconds it
+//! takes to look up and publish presence both see none and both run. It catches the instance
+//! started next to one that is already running, which is how the double answers happen.
+
+use crate::transport::DmTransport;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The `d` tag of the presence event
+pub const D_TAG: &str = "nparrot/presence";
+/// Presence older than this is stale; running instances refresh theirs twice as often
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const LOCK_FILE: &str = "instance.lock";
+/// How long to look for another instance's presence at startup
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+static PASSIVE: AtomicBool = AtomicBool::new(false);
+
+/// Held by the tests that switch `PASSIVE`, which every test in the process sees
+#[cfg(test)]
+pub static PASSIVE_TESTS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// What to do when another instance already answers for this identity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MultiInstance {
+    /// Refuse to start
+    #[default]
+    Refuse,
+    /// Start, but only receive and log: nothing is published
+    Passive,
+    /// Start anyway and answer too
+    Ignore,
+}
+
+/// Makes every publish through the `Client` transport fail while `passive`
+pub fn set_passive(passive: bool) {
+    PASSIVE.store(passive, Ordering::Relaxed);
+}
+
+pub fn is_passive() -> bool {
+    PASSIVE.load(Ordering::Relaxed)
+}
+
+/// Fails while this instance is passive, so it publishes nothing
+pub fn ensure_active() -> Result<(), String> {
+    if is_passive() {
+        return Err(
+            "This instance is passive: another instance answers for this identity \
+             (NPARROT_MULTI_INSTANCE=passive)"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum State {
+    Active,
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Presence {
+    instance: String,
+    host: String,
+    pid: u32,
+    state: State,
+}
+
+/// Why this instance can't have the identity to itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// Another process holds the lock in the same data dir
+    SameDataDir(String),
+    /// Another instance refreshed its presence `age` ago
+    Elsewhere { host: String, pid: u32, age: u64 },
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Conflict::SameDataDir(dir) => {
+                write!(
+                    f,
+                    "Another nparrot instance is running with data dir {}",
+                    dir
+                )
+            }
+            Conflict::Elsewhere { host, pid, age } => write!(
+                f,
+                "Another nparrot instance (PID {} on {}) answered for this identity {}s ago",
+                pid, host, age
+            ),
+        }
+    }
+}
+
+/// This instance's claim on the identity, held until the process exits
+#[derive(Debug)]
+pub struct Claim {
+    presence: Presence,
+    _lock: Option<File>,
+}
+
+/// Claims the identity of `client` for this instance, unless another instance holds the lock in
+/// `data_dir` or has fresh presence on the relays
+pub async fn claim(client: &Client, data_dir: &str) -> Result<Claim, Conflict> {
+    let lock = lock(data_dir)?;
+    let host = hostname();
+    if let Some(other) = find_other(client, &host).await {
+        return Err(other);
+    }
+    Ok(Claim {
+        presence: Presence {
+            instance: uuid::Uuid::new_v4().to_string(),
+            host,
+            pid: std::process::id(),
+            state: State::Active,
+        },
+        _lock: lock,
+    })
+}
+
+/// Takes `instance.lock` in `data_dir`; `None` where locks aren't supported
+fn lock(data_dir: &str) -> Result<Option<File>, Conflict> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        let path = Path::new(data_dir).join(LOCK_FILE);
+        let opened = std::fs::create_dir_all(data_dir).and_then(|_| {
+            File::options()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+        });
+        let file = match opened {
+            Ok(file) => file,
+            Err(e) => {
+                log::warn!("Could not open {}: {}", path.display(), e);
+                return Ok(None);
+            }
+        };
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            return Err(Conflict::SameDataDir(data_dir.to_string()));
+        }
+        Ok(Some(file))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (data_dir, LOCK_FILE, Path::new);
+        Ok(None)
+    }
+}
+
+async fn find_other(client: &Client, host: &str) -> Option<Conflict> {
+    let author = client.signer().await.ok()?.get_public_key().await.ok()?;
+    let filter = Filter::new()
+        .author(author)
+        .kind(Kind::ApplicationSpecificData)
+        .identifier(D_TAG)
+        .limit(1);
+    let events = match DmTransport::fetch_events(client, filter, LOOKUP_TIMEOUT).await {
+        Ok(events) => events,
+        Err(e) => {
+            log::warn!("Could not look for other instances: {}", e);
+            return None;
+        }
+    };
+    let event = events.into_iter().max_by_key(|event| event.created_at)?;
+    let presence: Presence = serde_json::from_str(&event.content).ok()?;
+    conflict(
+        &presence,
+        event.created_at,
+        Timestamp::now(),
+        host,
+        pid_alive,
+    )
+}
+
+/// The conflict `presence`, published at `created_at`, means at `now` for an instance on `host`
+fn conflict(
+    presence: &Presence,
+    created_at: Timestamp,
+    now: Timestamp,
+    host: &str,
+    alive: impl Fn(u32) -> bool,
+) -> Option<Conflict> {
+    let age = now.as_u64().saturating_sub(created_at.as_u64());
+    if presence.state == State::Stopped || age >= REFRESH_INTERVAL.as_secs() {
+        return None;
+    }
+    // A crashed instance on this host left its presence behind
+    if presence.host == host && !alive(presence.pid) {
+        return None;
+    }
+    Some(Conflict::Elsewhere {
+        host: presence.host.clone(),
+        pid: presence.pid,
+        age,
+    })
+}
+
+impl Claim {
+    fn event(&self, state: State) -> EventBuilder {
+        let presence = Presence {
+            state,
+            ..self.presence.clone()
+        };
+        EventBuilder::new(
+            Kind::ApplicationSpecificData,
+            serde_json::to_string(&presence).unwrap_or_default(),
+        )
+        .tags([
+            Tag::identifier(D_TAG),
+            Tag::expiration(Timestamp::now() + REFRESH_INTERVAL.as_secs() * 2),
+        ])
+    }
+
+    /// Refreshes the presence until the process exits
+    pub async fn run(self: Arc<Self>, client: Client) {
+        loop {
+            if let Err(e) = client.send_event_builder(self.event(State::Active)).await {
+                log::warn!("Could not refresh the instance presence: {}", e);
+            }
+            tokio::time::sleep(REFRESH_INTERVAL / 2).await;
+        }
+    }
+
+    /// Marks the presence stopped, so the next instance doesn't wait for it to go stale
+    pub async fn release(&self, client: &Client) {
+        let stopped = client.send_event_builder(self.event(State::Stopped));
+        match tokio::time::timeout(LOOKUP_TIMEOUT, stopped).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => log::warn!("Could not mark the instance presence stopped: {}", e),
+            Err(_) => log::warn!("Could not mark the instance presence stopped in time"),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    if result != 0 {
+        return "unknown".to_string();
+    }
+    let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..end]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn pid_alive(pid: u32) -> bool {
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn pid_alive(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presence(host: &str, state: State) -> Presence {
+        Presence {
+            instance: "a".to_string(),
+            host: host.to_string(),
+            pid: 42,
+            state,
+        }
+    }
+
+    #[test]
+    fn test_only_fresh_presence_of_a_live_instance_conflicts() {
+        let now = Timestamp::from(10_000);
+        let alive = |_| true;
+        let fresh = Timestamp::from(10_000 - 20);
+        assert_eq!(
+            conflict(
+                &presence("box-b", State::Active),
+                fresh,
+                now,
+                "box-a",
+                alive
+            ),
+            Some(Conflict::Elsewhere {
+                host: "box-b".to_string(),
+                pid: 42,
+                age: 20
+            })
+        );
+        // Older than the refresh interval: that instance is gone
+        let stale = Timestamp::from(10_000 - REFRESH_INTERVAL.as_secs());
+        assert_eq!(
+            conflict(
+                &presence("box-b", State::Active),
+                stale,
+                now,
+                "box-a",
+                alive
+            ),
+            None
+        );
+        assert_eq!(
+            conflict(
+                &presence("box-b", State::Stopped),
+                fresh,
+                now,
+                "box-a",
+                alive
+            ),
+            None
+        );
+        // Left behind on this host by a process that no longer exists
+        assert_eq!(
+            conflict(
+                &presence("box-a", State::Active),
+                fresh,
+                now,
+                "box-a",
+                |_| { false }
+            ),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_second_instance_in_a_data_dir_is_locked_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dir = dir.path().to_string_lossy().to_string();
+        let first = lock(&data_dir).unwrap();
+        assert!(first.is_some());
+        assert_eq!(
+            lock(&data_dir).unwrap_err(),
+            Conflict::SameDataDir(data_dir.clone())
+        );
+        drop(first);
+        assert!(lock(&data_dir).is_ok());
+    }
+
+    #[test]
+    fn test_passive_instances_publish_nothing() {
+        let _serial = PASSIVE_TESTS.lock().unwrap_or_else(|e| e.into_inner());
+        assert!(ensure_active().is_ok());
+        set_passive(true);
+        assert!(ensure_active().unwrap_err().contains("passive"));
+        set_passive(false);
+    }
+}
@@ -0,0 +1,190 @@
+//! What spawned processes see of nparrot's environment
+//!
+//! Goose runs, the agents' Goose tasks and `onmessage` commands inherit the environment minus
+//! nparrot's own secrets: private keys (the progress channels' included), the wallet URI, the
+//! data key and the MCP bearer token. Other `*_TOKEN`, `*_SECRET` and `*_PASSWORD` variables
+//! are withheld too, unless the allowlist names them exactly. With an allowlist
+//! (`--env-allowlist`), children only get the variables it matches. Variables nparrot sets for
+//! a child itself are always passed.
+
+use std::ffi::OsString;
+use std::process::Command as StdCommand;
+use std::sync::RwLock;
+
+/// Never passed on, whatever the allowlist says
+const SECRETS: &[&str] = &[
+    "NSEC",
+    "*_NSEC",
+    // `CHANNEL=NSEC` pairs of the progress identities
+    "NPARROT_PROGRESS",
+    "NWC_URI",
+    "NPARROT_DATA_KEY",
+    "NPARROT_NEW_DATA_KEY",
+    "NPARROT_MCP_TOKEN",
+];
+/// Withheld unless allowlisted by exact name
+const SECRET_LIKE: &[&str] = &["*_TOKEN", "*_SECRET", "*_PASSWORD"];
+
+lazy_static::lazy_static! {
+    static ref POLICY: RwLock<EnvPolicy> = RwLock::new(EnvPolicy::default());
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvPolicy {
+    /// Names or `*` patterns of the only variables passed on, `None` for all but the secrets
+    pub allowlist: Option<Vec<String>>,
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of characters
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*`: exact match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+impl EnvPolicy {
+    /// Whether a child may see the inherited variable `name`
+    pub fn passes(&self, name: &str) -> bool {
+        if SECRETS.iter().any(|pattern| matches(pattern, name)) {
+            return false;
+        }
+        let named = self
+            .allowlist
+            .as_ref()
+            .is_some_and(|allowlist| allowlist.iter().any(|allowed| allowed == name));
+        if SECRET_LIKE.iter().any(|pattern| matches(pattern, name)) && !named {
+            return false;
+        }
+        match &self.allowlist {
+            Some(allowlist) => allowlist.iter().any(|pattern| matches(pattern, name)),
+            None => true,
+        }
+    }
+
+    /// Removes what `cmd` would inherit but may not see; returns the withheld names
+    pub fn apply(&self, cmd: &mut StdCommand) -> Vec<String> {
+        let explicit: Vec<OsString> = cmd.get_envs().map(|(key, _)| key.to_owned()).collect();
+        let mut withheld: Vec<String> = std::env::vars_os()
+            .map(|(key, _)| key)
+            .filter(|key| !explicit.contains(key))
+            .filter_map(|key| key.into_string().ok())
+            .filter(|key| !self.passes(key))
+            .collect();
+        withheld.sort();
+        for key in &withheld {
+            cmd.env_remove(key);
+        }
+        withheld
+    }
+}
+
+/// Sets the policy for every process spawned from now on
+pub fn set_policy(policy: EnvPolicy) {
+    if let Ok(mut guard) = POLICY.write() {
+        *guard = policy;
+    }
+}
+
+/// Applies the configured policy to `cmd` before it is spawned as `label`
+pub fn scrub(cmd: &mut StdCommand, label: &str) {
+    let policy = POLICY.read().map(|guard| guard.clone()).unwrap_or_default();
+    let withheld = policy.apply(cmd);
+    if !withheld.is_empty() {
+        log::debug!("Withholding from '{}': {}", label, withheld.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        assert!(matches("NSEC", "NSEC"));
+        assert!(!matches("NSEC", "NSEC_FILE"));
+        assert!(matches("*_NSEC", "PROGRESS_NSEC"));
+        assert!(!matches("*_NSEC", "NSEC"));
+        assert!(matches("GOOSE_*", "GOOSE_PROVIDER"));
+        assert!(matches("*API*", "OPENAI_API_KEY"));
+        assert!(!matches("A*A", "A"));
+    }
+
+    #[test]
+    fn test_policy() {
+        let open = EnvPolicy::default();
+        for secret in [
+            "NSEC",
+            "PROGRESS_NSEC",
+            "NPARROT_PROGRESS",
+            "NWC_URI",
+            "NPARROT_MCP_TOKEN",
+            "NPARROT_NEW_DATA_KEY",
+            "GITHUB_TOKEN",
+        ] {
+            assert!(!open.passes(secret), "{}", secret);
+        }
+        assert!(open.passes("PATH"));
+        assert!(open.passes("ANTHROPIC_API_KEY"));
+
+        let strict = EnvPolicy {
+            allowlist: Some(vec![
+                "PATH".to_string(),
+                "GOOSE_*".to_string(),
+                "GITHUB_TOKEN".to_string(),
+                "NSEC".to_string(),
+                "NPARROT_PROGRESS".to_string(),
+            ]),
+        };
+        assert!(strict.passes("PATH"));
+        assert!(strict.passes("GOOSE_MODEL"));
+        assert!(!strict.passes("HOME"));
+        // Named exactly, so a token may pass; nparrot's own secrets never do
+        assert!(strict.passes("GITHUB_TOKEN"));
+        assert!(!strict.passes("NSEC"));
+        assert!(!strict.passes("NPARROT_PROGRESS"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_child_does_not_see_secrets() {
+        // Unique names, so other tests' children are unaffected
+        std::env::set_var("ENVTEST_NSEC", "nsec1secret");
+        std::env::set_var("ENVTEST_API_TOKEN", "tok");
+        std::env::set_var("ENVTEST_VISIBLE", "yes");
+
+        let mut cmd = StdCommand::new("env");
+        cmd.env("ENVTEST_EXPLICIT_TOKEN", "set by nparrot");
+        let withheld = EnvPolicy::default().apply(&mut cmd);
+        assert!(withheld.contains(&"ENVTEST_NSEC".to_string()));
+
+        let output = cmd.output().unwrap();
+        let env = String::from_utf8(output.stdout).unwrap();
+        assert!(!env.contains("nsec1secret"));
+        assert!(!env.contains("ENVTEST_API_TOKEN"));
+        assert!(env.contains("ENVTEST_VISIBLE=yes"));
+        assert!(env.contains("ENVTEST_EXPLICIT_TOKEN=set by nparrot"));
+
+        let mut cmd = StdCommand::new("env");
+        EnvPolicy {
+            allowlist: Some(vec!["PATH".to_string()]),
+        }
+        .apply(&mut cmd);
+        let env = String::from_utf8(cmd.output().unwrap().stdout).unwrap();
+        assert!(!env.contains("ENVTEST_VISIBLE"));
+        assert!(env.contains("PATH="));
+    }
+}
@@ -0,0 +1,503 @@
+pub mod env;
+pub mod stats;
+pub mod terminate;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{
+    io::{self, Write},
+    process::{Child, Command as StdCommand, ExitStatus, Output, Stdio},
+};
+use terminate::Terminator;
+use tokio::sync::Mutex;
+
+// Type alias for clarity
+pub type ChildHandle = Arc<Mutex<Option<Child>>>;
+
+/// How many finished processes are kept around for `list_processes`
+const MAX_FINISHED_ENTRIES: usize = 50;
+
+lazy_static::lazy_static! {
+    static ref PROCESS_MANAGER: ProcessManager = ProcessManager::new();
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "state", content = "code")]
+pub enum ProcessStatus {
+    Running,
+    Exited(i32),
+    Killed,
+}
+
+#[derive(Debug, Clone)]
+struct ManagedProcess {
+    label: String,
+    started_at: Instant,
+    status: ProcessStatus,
+    usage: stats::Usage,
+}
+
+/// Snapshot of a tracked process for reporting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub label: String,
+    pub uptime_secs: u64,
+    pub status: ProcessStatus,
+    /// CPU usage over the last sampling interval, 100.0 being one full core
+    #[serde(default)]
+    pub cpu_percent: Option<f64>,
+    #[serde(default)]
+    pub rss_bytes: Option<u64>,
+    #[serde(default)]
+    pub peak_rss_bytes: Option<u64>,
+}
+
+/// Owns every child process the tool spawns.
+///
+/// Each child runs in its own process group so terminating it also takes down anything it
+/// started (Goose spawns its own helpers), and every pid is tracked with a purpose label
+/// until it has been waited on.
+#[derive(Debug)]
+pub struct ProcessManager {
+    processes: std::sync::Mutex<HashMap<u32, ManagedProcess>>,
+    terminator: Box<dyn Terminator>,
+}
+
+impl Default for ProcessManager {
+    fn default() -> Self {
+        Self {
+            processes: Default::default(),
+            terminator: Box::new(terminate::System),
+        }
+    }
+}
+
+impl ProcessManager {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// The process-wide manager
+    pub fn global() -> &'static ProcessManager {
+        &PROCESS_MANAGER
+    }
+
+    /// Spawns `cmd` in a new process group and starts tracking it under `label`
+    pub fn spawn(&self, cmd: &mut StdCommand, label: &str) -> io::Result<Child> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            // Keeps the console's Ctrl+C to ourselves, as the process group does on Unix
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+            cmd.creation_flags(CREATE_NEW_PROCESS_GROUP);
+        }
+        env::scrub(cmd, label);
+
+        let child = cmd.spawn()?;
+        log::debug!("Spawned '{}' (PID: {})", label, child.id());
+
+        if let Ok(mut processes) = self.processes.lock() {
+            processes.insert(
+                child.id(),
+                ManagedProcess {
+                    label: label.to_string(),
+                    started_at: Instant::now(),
+                    status: ProcessStatus::Running,
+                    usage: stats::Usage::default(),
+                },
+            );
+        }
+        Ok(child)
+    }
+
+    /// Spawns `cmd`, collects its output on a blocking worker and reaps it.
+    ///
+    /// If `limit` elapses first the whole process group is terminated and `None` is returned.
+    pub async fn output(
+        &self,
+        mut cmd: StdCommand,
+        label: &str,
+        limit: Duration,
+    ) -> io::Result<Option<Output>> {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let child = self.spawn(&mut cmd, label)?;
+        let pid = child.id();
+
+        let waiter = tokio::task::spawn_blocking(move || child.wait_with_output());
+        match tokio::time::timeout(limit, waiter).await {
+            Ok(joined) => {
+                let output = joined.map_err(io::Error::other)??;
+                self.mark_exited(pid, &output.status);
+                Ok(Some(output))
+            }
+            Err(_) => {
+                log::warn!(
+                    "'{}' (PID: {}) exceeded {:?}, terminating",
+                    label,
+                    pid,
+                    limit
+                );
+                // The blocking waiter reaps the child once the group is gone
+                self.terminate(pid, Duration::from_secs(5)).await;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Records that a tracked process has been waited on
+    pub fn mark_exited(&self, pid: u32, status: &ExitStatus) {
+        if let Ok(mut processes) = self.processes.lock() {
+            if let Some(process) = processes.get_mut(&pid) {
+                if process.status == ProcessStatus::Running {
+                    process.status = match status.code() {
+                        Some(code) => ProcessStatus::Exited(code),
+                        None => ProcessStatus::Killed,
+                    };
+                }
+            }
+            Self::prune(&mut processes);
+        }
+    }
+
+    fn mark_killed(&self, pid: u32) {
+        if let Ok(mut processes) = self.processes.lock() {
+            if let Some(process) = processes.get_mut(&pid) {
+                process.status = ProcessStatus::Killed;
+            }
+        }
+    }
+
+    fn prune(processes: &mut HashMap<u32, ManagedProcess>) {
+        let mut finished: Vec<(u32, Instant)> = processes
+            .iter()
+            .filter(|(_, p)| p.status != ProcessStatus::Running)
+            .map(|(pid, p)| (*pid, p.started_at))
+            .collect();
+        if finished.len() <= MAX_FINISHED_ENTRIES {
+            return;
+        }
+        finished.sort_by_key(|(_, started_at)| *started_at);
+        for (pid, _) in finished.iter().take(finished.len() - MAX_FINISHED_ENTRIES) {
+            processes.remove(pid);
+        }
+    }
+
+    pub fn is_running(&self, pid: u32) -> bool {
+        self.processes
+            .lock()
+            .map(|p| p.get(&pid).map(|p| p.status == ProcessStatus::Running))
+            .ok()
+            .flatten()
+            .unwrap_or(false)
+    }
+
+    /// Lists tracked processes, running ones first
+    pub fn list(&self) -> Vec<ProcessInfo> {
+        let Ok(processes) = self.processes.lock() else {
+            return Vec::new();
+        };
+        let mut list: Vec<ProcessInfo> = processes
+            .iter()
+            .map(|(pid, p)| ProcessInfo {
+                pid: *pid,
+                label: p.label.clone(),
+                uptime_secs: p.started_at.elapsed().as_secs(),
+                status: p.status.clone(),
+                cpu_percent: p.usage.latest().map(|s| s.cpu_percent),
+                rss_bytes: p.usage.latest().map(|s| s.rss_bytes),
+                peak_rss_bytes: p.usage.peak_rss_bytes(),
+            })
+            .collect();
+        list.sort_by_key(|p| (p.status != ProcessStatus::Running, p.uptime_secs));
+        list
+    }
+
+    /// Asks the process tree to exit (SIGTERM to the group on Unix), then kills it if it is
+    /// still alive after `grace`
+    pub async fn terminate(&self, pid: u32, grace: Duration) {
+        if !self.is_running(pid) {
+            return;
+        }
+
+        self.terminator.signal(pid, false);
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if !self.terminator.alive(pid) {
+                self.mark_killed(pid);
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        log::warn!("PID {} did not exit, killing it", pid);
+        self.terminator.signal(pid, true);
+        self.mark_killed(pid);
+    }
+
+    /// Terminates every running process group spawned under `label`, returning how many
+    pub async fn terminate_label(&self, label: &str, grace: Duration) -> usize {
+        let running: Vec<u32> = self
+            .list()
+            .into_iter()
+            .filter(|p| p.status == ProcessStatus::Running && p.label == label)
+            .map(|p| p.pid)
+            .collect();
+        for pid in &running {
+            self.terminate(*pid, grace).await;
+        }
+        running.len()
+    }
+
+    /// Terminates every running process group; used during shutdown
+    pub async fn terminate_all(&self, grace: Duration) {
+        let running: Vec<u32> = self
+            .list()
+            .into_iter()
+            .filter(|p| p.status == ProcessStatus::Running)
+            .map(|p| p.pid)
+            .collect();
+        if running.is_empty() {
+            return;
+        }
+
+        log::info!("Terminating {} child process(es)", running.len());
+        let tasks: Vec<_> = running
+            .into_iter()
+            .map(|pid| tokio::spawn(async move { Self::global().terminate(pid, grace).await }))
+            .collect();
+        for task in tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+/// Renders tracked processes for the `list_processes` tool
+pub fn format_process_list(processes: &[ProcessInfo]) -> String {
+    if processes.is_empty() {
+        return "No managed processes".to_string();
+    }
+
+    let mut out = format!("{:<8} {:<10} {:<16} LABEL\n", "PID", "UPTIME", "STATUS");
+    for p in processes {
+        let status = match &p.status {
+            ProcessStatus::Running => "running".to_string(),
+            ProcessStatus::Exited(code) => format!("exited({})", code),
+            ProcessStatus::Killed => "killed".to_string(),
+        };
+        out.push_str(&format!(
+            "{:<8} {:<10} {:<16} {}\n",
+            p.pid,
+            format!("{}s", p.uptime_secs),
+            status,
+            p.label
+        ));
+    }
+    out
+}
+
+/// Kill any process in `slot` if it exists.
+pub async fn kill_existing(slot: &mut Option<Child>) {
+    if let Some(mut child) = slot.take() {
+        let pid = child.id();
+
+        // Already finished: just reap it
+        if let Ok(Some(status)) = child.try_wait() {
+            ProcessManager::global().mark_exited(pid, &status);
+            return;
+        }
+
+        log::debug!("Interrupting previous command (PID: {})", pid);
+        ProcessManager::global()
+            .terminate(pid, Duration::from_secs(2))
+            .await;
+        match child.wait() {
+            Ok(status) => ProcessManager::global().mark_exited(pid, &status),
+            Err(e) => log::error!("Warning: failed to reap PID {}: {}", pid, e),
+        }
+    }
+}
+
+/// Give the process in `slot` up to `grace` to exit on its own, then kill it.
+pub async fn wait_or_kill(slot: &mut Option<Child>, grace: Duration) {
+    let Some(child) = slot.as_mut() else {
+        return;
+    };
+    let pid = child.id();
+    let deadline = tokio::time::Instant::now() + grace;
+
+    while tokio::time::Instant::now() < deadline {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                log::debug!("PID {} exited with {}", pid, status);
+                ProcessManager::global().mark_exited(pid, &status);
+                slot.take();
+                return;
+            }
+            Ok(None) => tokio::time::sleep(Duration::from_millis(100)).await,
+            Err(e) => {
+                log::warn!("Could not poll PID {}: {}", pid, e);
+                break;
+            }
+        }
+    }
+
+    kill_existing(slot).await;
+}
+
+/// Spawn `sh -c <cmd>` with the extra `env` vars, pipe in `message` on stdin, and return the new Child.
+pub fn spawn_and_pipe(
+    cmd: &str,
+    message: Vec<u8>,
+    env: &[(&'static str, String)],
+) -> io::Result<Child> {
+    let mut command = StdCommand::new("sh");
+    command
+        .arg("-c")
+        .arg(cmd)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    let mut child = ProcessManager::global().spawn(&mut command, "onmessage")?;
+    let pid = child.id();
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Fire-and-forget task that writes the decrypted message into the child's stdin.
+        tokio::spawn(async move {
+            if let Err(e) = stdin.write_all(&message) {
+                log::error!("Error writing to stdin of PID {}: {}", pid, e);
+            }
+            // flush and drop to close the pipe
+            if let Err(e) = stdin.flush() {
+                log::warn!("flush failed on PID {}: {}", pid, e);
+            }
+            log::debug!("Wrote {} bytes to PID {}", message.len(), pid);
+        });
+    } else {
+        log::error!("Failed to open stdin for PID {}", pid);
+        let _ = child.kill();
+    }
+
+    Ok(child)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A process that exits when asked, or only once forced with `stubborn`
+    #[derive(Debug, Default)]
+    struct MockProcess {
+        stubborn: bool,
+        signals: std::sync::Mutex<Vec<bool>>,
+        exited: AtomicBool,
+    }
+
+    impl Terminator for Arc<MockProcess> {
+        fn signal(&self, _pid: u32, force: bool) {
+            self.signals.lock().unwrap().push(force);
+            if force || !self.stubborn {
+                self.exited.store(true, Ordering::SeqCst);
+            }
+        }
+
+        fn alive(&self, _pid: u32) -> bool {
+            !self.exited.load(Ordering::SeqCst)
+        }
+    }
+
+    fn managing(process: &Arc<MockProcess>, pid: u32) -> ProcessManager {
+        let manager = ProcessManager {
+            processes: Default::default(),
+            terminator: Box::new(process.clone()),
+        };
+        manager.processes.lock().unwrap().insert(
+            pid,
+            ManagedProcess {
+                label: "goose".to_string(),
+                started_at: Instant::now(),
+                status: ProcessStatus::Running,
+                usage: stats::Usage::default(),
+            },
+        );
+        manager
+    }
+
+    #[tokio::test]
+    async fn test_terminate_asks_first() {
+        let process = Arc::new(MockProcess::default());
+        let manager = managing(&process, 7);
+        assert_eq!(
+            manager
+                .terminate_label("goose", Duration::from_secs(5))
+                .await,
+            1
+        );
+        assert_eq!(*process.signals.lock().unwrap(), [false]);
+        assert_eq!(manager.list()[0].status, ProcessStatus::Killed);
+
+        // Nothing left to terminate
+        manager.terminate(7, Duration::from_secs(5)).await;
+        assert_eq!(process.signals.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_terminate_forces_a_process_that_stays() {
+        let process = Arc::new(MockProcess {
+            stubborn: true,
+            ..Default::default()
+        });
+        let manager = managing(&process, 7);
+        manager.terminate(7, Duration::from_millis(250)).await;
+        assert_eq!(*process.signals.lock().unwrap(), [false, true]);
+        assert!(!manager.is_running(7));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_output_tracks_and_reaps_child() {
+        let manager = ProcessManager::new();
+        let mut cmd = StdCommand::new("sh");
+        cmd.arg("-c").arg("echo hello");
+
+        let output = manager
+            .output(cmd, "test-echo", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "hello");
+        let list = manager.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].label, "test-echo");
+        assert_eq!(list[0].status, ProcessStatus::Exited(0));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_timeout_terminates_process_group() {
+        let manager = ProcessManager::new();
+        let mut cmd = StdCommand::new("sh");
+        // The grandchild would be orphaned if only `sh` were killed
+        cmd.arg("-c").arg("sleep 30 & sleep 30");
+
+        let output = manager
+            .output(cmd, "test-sleep", Duration::from_millis(200))
+            .await
+            .unwrap();
+
+        assert!(output.is_none());
+        let pid = manager.list()[0].pid;
+        assert_eq!(manager.list()[0].status, ProcessStatus::Killed);
+        assert!(!terminate::System.alive(pid));
+    }
+}
@@ -0,0 +1,435 @@
+//! CPU and memory sampling for managed processes
+//!
+//! Samples come from `/proc` on Linux. Other platforms (macOS, Windows) still get process
+//! tracking, but no resource figures: `ps` and `process_stats` show none, and a memory limit
+//! can't be enforced, which the sampler warns about when it starts.
+
+use super::{ProcessInfo, ProcessManager, ProcessStatus};
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Samples kept per process (five minutes at the default interval)
+const HISTORY_LEN: usize = 60;
+
+/// How long an offender gets to exit after SIGTERM before it is killed
+const LIMIT_KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Whether this platform can measure a process at all (see `read_proc`)
+const SAMPLING_SUPPORTED: bool = cfg!(target_os = "linux");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceSample {
+    pub unix_secs: u64,
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+}
+
+/// Sampling state of a single process
+#[derive(Debug, Clone, Default)]
+pub(super) struct Usage {
+    history: VecDeque<ResourceSample>,
+    peak_rss_bytes: u64,
+    last_cpu: Option<(u64, Instant)>,
+}
+
+impl Usage {
+    pub(super) fn latest(&self) -> Option<&ResourceSample> {
+        self.history.back()
+    }
+
+    pub(super) fn peak_rss_bytes(&self) -> Option<u64> {
+        Some(self.peak_rss_bytes).filter(|peak| *peak > 0)
+    }
+
+    fn record(&mut self, reading: ProcReading, now: Instant) {
+        let cpu_percent = match self.last_cpu {
+            Some((ticks, at)) => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                let used = reading.cpu_ticks.saturating_sub(ticks) as f64 / clock_ticks();
+                if elapsed > 0.0 {
+                    used / elapsed * 100.0
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+        self.last_cpu = Some((reading.cpu_ticks, now));
+        self.peak_rss_bytes = self.peak_rss_bytes.max(reading.rss_bytes);
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(ResourceSample {
+            unix_secs: chrono::Utc::now().timestamp().max(0) as u64,
+            cpu_percent,
+            rss_bytes: reading.rss_bytes,
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ProcReading {
+    cpu_ticks: u64,
+    rss_bytes: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn read_proc(pid: u32) -> Option<ProcReading> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_proc_stat(&stat, page_size())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_proc(_pid: u32) -> Option<ProcReading> {
+    None
+}
+
+/// Parses utime, stime and rss out of `/proc/<pid>/stat`
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_proc_stat(stat: &str, page_size: u64) -> Option<ProcReading> {
+    // The command name may contain spaces and parentheses, so fields are counted after the last ')'
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let rss_pages: u64 = fields.get(21)?.parse().ok()?;
+    Some(ProcReading {
+        cpu_ticks: utime + stime,
+        rss_bytes: rss_pages * page_size,
+    })
+}
+
+#[cfg(unix)]
+fn clock_ticks() -> f64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100.0,
+    }
+}
+
+#[cfg(not(unix))]
+fn clock_ticks() -> f64 {
+    100.0
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as u64,
+        _ => 4096,
+    }
+}
+
+/// Optional per-child limits enforced by the sampler
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    pub max_rss_bytes: Option<u64>,
+}
+
+/// A child that was terminated for exceeding a limit
+#[derive(Debug, Clone)]
+pub struct LimitViolation {
+    pub process: ProcessInfo,
+    pub limit_bytes: u64,
+}
+
+impl LimitViolation {
+    /// Message for the progress channel
+    pub fn notice(&self) -> String {
+        let p = &self.process;
+        format!(
+            "⚠️ Terminated '{}' (PID {}): RSS {} exceeded the {} limit (CPU {:.0}%, up {}s)",
+            p.label,
+            p.pid,
+            format_bytes(p.rss_bytes.unwrap_or(0)),
+            format_bytes(self.limit_bytes),
+            p.cpu_percent.unwrap_or(0.0),
+            p.uptime_secs
+        )
+    }
+}
+
+impl ProcessManager {
+    /// Takes one sample of every running process and returns the ones over `limits`
+    pub fn sample(&self, limits: &ResourceLimits) -> Vec<LimitViolation> {
+        let Ok(mut processes) = self.processes.lock() else {
+            return Vec::new();
+        };
+
+        let now = Instant::now();
+        let mut violations = Vec::new();
+        for (pid, process) in processes.iter_mut() {
+            if process.status != ProcessStatus::Running {
+                continue;
+            }
+            let Some(reading) = read_proc(*pid) else {
+                continue;
+            };
+            process.usage.record(reading, now);
+
+            if let Some(limit) = limits.max_rss_bytes {
+                if reading.rss_bytes > limit {
+                    violations.push(LimitViolation {
+                        process: ProcessInfo {
+                            pid: *pid,
+                            label: process.label.clone(),
+                            uptime_secs: process.started_at.elapsed().as_secs(),
+                            status: process.status.clone(),
+                            cpu_percent: process.usage.latest().map(|s| s.cpu_percent),
+                            rss_bytes: Some(reading.rss_bytes),
+                            peak_rss_bytes: process.usage.peak_rss_bytes(),
+                        },
+                        limit_bytes: limit,
+                    });
+                }
+            }
+        }
+        violations
+    }
+
+    /// Label and sample history of a tracked process
+    pub fn history(&self, pid: u32) -> Option<(String, Vec<ResourceSample>)> {
+        let processes = self.processes.lock().ok()?;
+        let process = processes.get(&pid)?;
+        Some((
+            process.label.clone(),
+            process.usage.history.iter().cloned().collect(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SamplerConfig {
+    pub interval: Duration,
+    pub limits: ResourceLimits,
+    /// Where to publish snapshots for `nparrot ps`
+    pub snapshot_dir: Option<PathBuf>,
+}
+
+/// Starts sampling the global manager in the background.
+///
+/// Offenders are terminated by the sampler itself; the returned receiver only reports them.
+pub fn start_sampler(config: SamplerConfig) -> mpsc::UnboundedReceiver<LimitViolation> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    if !SAMPLING_SUPPORTED && config.limits.max_rss_bytes.is_some() {
+        log::warn!(
+            "NPARROT_MAX_CHILD_RSS is set, but child memory is only measured on Linux: the limit is not enforced on this host"
+        );
+    }
+
+    tokio::spawn(async move {
+        let manager = ProcessManager::global();
+        let mut ticker = tokio::time::interval(config.interval.max(Duration::from_secs(1)));
+        loop {
+            ticker.tick().await;
+
+            for violation in manager.sample(&config.limits) {
+                log::warn!("{}", violation.notice());
+                manager
+                    .terminate(violation.process.pid, LIMIT_KILL_GRACE)
+                    .await;
+                let _ = sender.send(violation);
+            }
+
+            if let Some(dir) = &config.snapshot_dir {
+                if let Err(e) = write_snapshot(dir, &manager.list()) {
+                    log::debug!("Could not write process snapshot: {}", e);
+                }
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Processes of one running nparrot instance, as published for `nparrot ps`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceSnapshot {
+    pub owner_pid: u32,
+    pub updated_unix_secs: u64,
+    pub processes: Vec<ProcessInfo>,
+}
+
+/// Directory holding per-instance snapshots inside the data dir
+pub fn snapshot_dir(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join("processes")
+}
+
+fn snapshot_path(dir: &Path, owner_pid: u32) -> PathBuf {
+    dir.join(format!("{}.json", owner_pid))
+}
+
+fn write_snapshot(dir: &Path, processes: &[ProcessInfo]) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let snapshot = InstanceSnapshot {
+        owner_pid: std::process::id(),
+        updated_unix_secs: chrono::Utc::now().timestamp().max(0) as u64,
+        processes: processes.to_vec(),
+    };
+    let path = snapshot_path(dir, snapshot.owner_pid);
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(&snapshot)?)?;
+    std::fs::rename(tmp, path)
+}
+
+/// Removes this instance's snapshot; called on exit
+pub fn remove_snapshot(dir: &Path) {
+    let _ = std::fs::remove_file(snapshot_path(dir, std::process::id()));
+}
+
+/// Reads snapshots of live instances, deleting those left behind by dead ones
+pub fn read_snapshots(dir: &Path) -> Vec<InstanceSnapshot> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut snapshots: Vec<InstanceSnapshot> = entries
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|e| {
+            let snapshot: InstanceSnapshot =
+                serde_json::from_slice(&std::fs::read(e.path()).ok()?).ok()?;
+            if instance_alive(snapshot.owner_pid) {
+                Some(snapshot)
+            } else {
+                let _ = std::fs::remove_file(e.path());
+                None
+            }
+        })
+        .collect();
+    snapshots.sort_by_key(|s| s.owner_pid);
+    snapshots
+}
+
+#[cfg(unix)]
+fn instance_alive(pid: u32) -> bool {
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn instance_alive(_pid: u32) -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ProcessStatsRequest {
+    #[schemars(description = "PID to show the sample history for (omit for a summary of all)")]
+    pub pid: Option<u32>,
+}
+
+/// Human readable byte count (KiB/MiB/GiB)
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Renders the latest usage of each process for `process_stats` and `nparrot ps`
+pub fn format_stats(processes: &[ProcessInfo]) -> String {
+    if processes.is_empty() {
+        return "No managed processes".to_string();
+    }
+
+    let mut out = format!(
+        "{:<8} {:<10} {:<12} {:>7} {:>11} {:>11}  LABEL\n",
+        "PID", "UPTIME", "STATUS", "CPU", "RSS", "PEAK RSS"
+    );
+    for p in processes {
+        let status = match &p.status {
+            ProcessStatus::Running => "running".to_string(),
+            ProcessStatus::Exited(code) => format!("exited({})", code),
+            ProcessStatus::Killed => "killed".to_string(),
+        };
+        out.push_str(&format!(
+            "{:<8} {:<10} {:<12} {:>7} {:>11} {:>11}  {}\n",
+            p.pid,
+            format!("{}s", p.uptime_secs),
+            status,
+            p.cpu_percent
+                .map(|c| format!("{:.1}%", c))
+                .unwrap_or_else(|| "-".to_string()),
+            p.rss_bytes
+                .map(format_bytes)
+                .unwrap_or_else(|| "-".to_string()),
+            p.peak_rss_bytes
+                .map(format_bytes)
+                .unwrap_or_else(|| "-".to_string()),
+            p.label
+        ));
+    }
+    out
+}
+
+/// Renders the sample history of one process, oldest first
+pub fn format_history(pid: u32, label: &str, samples: &[ResourceSample]) -> String {
+    if samples.is_empty() {
+        return format!("No samples recorded for '{}' (PID {}) yet", label, pid);
+    }
+
+    let mut out = format!("'{}' (PID {}), {} samples\n", label, pid, samples.len());
+    for sample in samples {
+        let time = chrono::DateTime::from_timestamp(sample.unix_secs as i64, 0)
+            .map(|t| t.format("%H:%M:%S").to_string())
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "{}  cpu {:>6.1}%  rss {}\n",
+            time,
+            sample.cpu_percent,
+            format_bytes(sample.rss_bytes)
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_stat_with_spaces_in_name() {
+        let stat = "4242 (goose (run) x) S 1 4242 4242 0 -1 4194560 1200 0 0 0 \
+                    150 50 0 0 20 0 4 0 123456 987654321 2560 18446744073709551615";
+        let reading = parse_proc_stat(stat, 4096).unwrap();
+        assert_eq!(reading.cpu_ticks, 200);
+        assert_eq!(reading.rss_bytes, 2560 * 4096);
+        assert!(parse_proc_stat("garbage", 4096).is_none());
+    }
+
+    #[test]
+    fn test_history_is_bounded_and_tracks_peak() {
+        let mut usage = Usage::default();
+        let start = Instant::now();
+        for i in 0..(HISTORY_LEN as u64 + 10) {
+            usage.record(
+                ProcReading {
+                    cpu_ticks: i * 10,
+                    rss_bytes: if i == 3 { 10_000 } else { 1_000 },
+                },
+                start + Duration::from_secs(i),
+            );
+        }
+        assert_eq!(usage.history.len(), HISTORY_LEN);
+        assert_eq!(usage.peak_rss_bytes(), Some(10_000));
+        assert_eq!(usage.latest().unwrap().rss_bytes, 1_000);
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
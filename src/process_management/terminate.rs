@@ -0,0 +1,126 @@
+//! Terminating a spawned process together with everything it started
+//!
+//! On Unix every child leads its own process group (see `ProcessManager::spawn`), which is
+//! signalled as a whole. Windows has no signals: `taskkill /T` ends the process tree, politely
+//! first and with `/F` when forced, and `tasklist` tells whether the process is still there.
+
+use std::fmt::Debug;
+#[cfg(not(unix))]
+use std::process::Command;
+
+/// How a process tree is asked, then made, to exit
+pub trait Terminator: Debug + Send + Sync {
+    /// Asks the process `pid` and its descendants to exit; with `force` they are killed
+    fn signal(&self, pid: u32, force: bool);
+    /// Whether the process `pid` (or, on Unix, anything in its group) is still running
+    fn alive(&self, pid: u32) -> bool;
+}
+
+/// The platform's own way
+#[derive(Debug, Default)]
+pub struct System;
+
+#[cfg(unix)]
+impl Terminator for System {
+    fn signal(&self, pid: u32, force: bool) {
+        let signal = if force { libc::SIGKILL } else { libc::SIGTERM };
+        // Negative pid addresses the whole process group created in `spawn`
+        let result = unsafe { libc::kill(-(pid as libc::pid_t), signal) };
+        if result != 0 {
+            log::debug!(
+                "Signal {} to group {} failed: {}",
+                signal,
+                pid,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    fn alive(&self, pid: u32) -> bool {
+        unsafe { libc::kill(-(pid as libc::pid_t), 0) == 0 }
+    }
+}
+
+#[cfg(not(unix))]
+impl Terminator for System {
+    fn signal(&self, pid: u32, force: bool) {
+        let mut cmd = Command::new("taskkill");
+        cmd.args(taskkill_args(pid, force));
+        if let Err(e) = cmd.output() {
+            log::debug!("taskkill for {} failed: {}", pid, e);
+        }
+    }
+
+    fn alive(&self, pid: u32) -> bool {
+        let filter = format!("PID eq {}", pid);
+        match Command::new("tasklist")
+            .args(["/FI", &filter, "/FO", "CSV", "/NH"])
+            .output()
+        {
+            Ok(output) => tasklist_shows(&String::from_utf8_lossy(&output.stdout), pid),
+            // Can't tell, so wait out the grace period and force it
+            Err(_) => true,
+        }
+    }
+}
+
+/// The `taskkill` arguments ending `pid` and its descendants
+#[cfg(any(not(unix), test))]
+fn taskkill_args(pid: u32, force: bool) -> Vec<String> {
+    let mut args = vec!["/PID".to_string(), pid.to_string(), "/T".to_string()];
+    if force {
+        args.push("/F".to_string());
+    }
+    args
+}
+
+/// Whether `tasklist /FO CSV /NH` output lists `pid`; with no match it prints an info line
+#[cfg(any(not(unix), test))]
+fn tasklist_shows(output: &str, pid: u32) -> bool {
+    let pid = format!("\"{}\"", pid);
+    output
+        .lines()
+        .any(|line| line.split(',').nth(1) == Some(pid.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_taskkill_ends_the_tree() {
+        assert_eq!(taskkill_args(42, false), ["/PID", "42", "/T"]);
+        assert_eq!(taskkill_args(42, true), ["/PID", "42", "/T", "/F"]);
+    }
+
+    #[test]
+    fn test_tasklist_output() {
+        let listed = "\"goose.exe\",\"4242\",\"Console\",\"1\",\"52,104 K\"\r\n";
+        assert!(tasklist_shows(listed, 4242));
+        assert!(!tasklist_shows(listed, 424));
+        let none = "INFO: No tasks are running which match the specified criteria.\r\n";
+        assert!(!tasklist_shows(none, 4242));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_system_signals_the_group() {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.arg("-c").arg("sleep 30 & sleep 30");
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        let mut child = cmd.spawn().unwrap();
+        let pid = child.id();
+
+        assert!(System.alive(pid));
+        System.signal(pid, true);
+        child.wait().unwrap();
+        // The orphaned `sleep` went with the group
+        for _ in 0..50 {
+            if !System.alive(pid) {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("process group {} survived SIGKILL", pid);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::config::ProfileConfig;
+use crate::output::status;
 use nostr_sdk::prelude::*;
 use std::collections::HashMap;
 
@@ -167,11 +169,61 @@ impl AgentProfile {
         profiles
     }
 
+    /// Replaces any fields set in the config file's `[profiles.<name>]` section
+    pub fn with_overrides(mut self, overrides: Option<&ProfileConfig>) -> Self {
+        let Some(overrides) = overrides else {
+            return self;
+        };
+        if let Some(name) = &overrides.name {
+            self.name = name.clone();
+        }
+        if let Some(display_name) = &overrides.display_name {
+            self.display_name = display_name.clone();
+        }
+        if let Some(about) = &overrides.about {
+            self.about = about.clone();
+        }
+        if overrides.picture.is_some() {
+            self.picture = overrides.picture.clone();
+        }
+        if overrides.banner.is_some() {
+            self.banner = overrides.banner.clone();
+        }
+        if overrides.nip05.is_some() {
+            self.nip05 = overrides.nip05.clone();
+        }
+        if overrides.lud16.is_some() {
+            self.lud16 = overrides.lud16.clone();
+        }
+        self
+    }
+
+    /// Builds a profile from published metadata, e.g. to edit the current one
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        Self {
+            name: metadata.name.clone().unwrap_or_default(),
+            display_name: metadata.display_name.clone().unwrap_or_default(),
+            about: metadata.about.clone().unwrap_or_default(),
+            picture: metadata.picture.clone(),
+            banner: metadata.banner.clone(),
+            nip05: metadata.nip05.clone(),
+            lud16: metadata.lud16.clone(),
+        }
+    }
+
     pub fn to_metadata(&self) -> Metadata {
-        let mut metadata = Metadata::new()
-            .name(&self.name)
-            .display_name(&self.display_name)
-            .about(&self.about);
+        let mut metadata = Metadata::new();
+
+        // Skip empty fields so a partially filled profile doesn't publish blank values
+        if !self.name.is_empty() {
+            metadata = metadata.name(&self.name);
+        }
+        if !self.display_name.is_empty() {
+            metadata = metadata.display_name(&self.display_name);
+        }
+        if !self.about.is_empty() {
+            metadata = metadata.about(&self.about);
+        }
 
         if let Some(ref picture) = self.picture {
             if let Ok(url) = picture.parse() {
@@ -200,7 +252,7 @@ impl AgentProfile {
 pub async fn setup_agent_profile(
     client: &Client,
     profile: &AgentProfile,
-) -> Result<(), nostr_sdk::client::Error> {
+) -> Result<EventId, nostr_sdk::client::Error> {
     log::info!("Setting up profile for {}", profile.display_name);
 
     let metadata = profile.to_metadata();
@@ -208,22 +260,36 @@ pub async fn setup_agent_profile(
     // Create and send the metadata event
     let event = EventBuilder::metadata(&metadata);
     let signed_event = client.sign_event_builder(event).await?;
-    let _ = client.send_event(&signed_event).await?;
+    let output = client.send_event(&signed_event).await?;
 
     log::info!("✅ Profile setup complete for {}", profile.display_name);
-    Ok(())
+    Ok(*output.id())
 }
 
-pub async fn setup_main_client_profile(client: &Client) -> Result<(), nostr_sdk::client::Error> {
-    let profile = AgentProfile::main_orchestrator();
-    setup_agent_profile(client, &profile).await
+pub async fn setup_main_client_profile(
+    client: &Client,
+    overrides: Option<&ProfileConfig>,
+) -> Result<(), nostr_sdk::client::Error> {
+    let profile = AgentProfile::main_orchestrator().with_overrides(overrides);
+    setup_agent_profile(client, &profile).await.map(|_| ())
 }
 
 pub async fn setup_progress_client_profile(
     client: &Client,
+    overrides: Option<&ProfileConfig>,
 ) -> Result<(), nostr_sdk::client::Error> {
-    let profile = AgentProfile::progress_reporter();
-    setup_agent_profile(client, &profile).await
+    let profile = AgentProfile::progress_reporter().with_overrides(overrides);
+    setup_agent_profile(client, &profile).await.map(|_| ())
+}
+
+/// Fetches the currently published kind-0 profile for the client's identity
+pub async fn fetch_current_profile(
+    client: &Client,
+    timeout: std::time::Duration,
+) -> Result<Option<Metadata>, nostr_sdk::client::Error> {
+    let signer = client.signer().await?;
+    let public_key = signer.get_public_key().await?;
+    client.fetch_metadata(public_key, timeout).await
 }
 
 #[allow(dead_code)] // Future profile selection
@@ -257,3 +323,37 @@ pub fn get_agent_profile_for_type(agent_type: &str) -> AgentProfile {
             .clone(),
     }
 }
+
+/// Shows the current profile, merges in the requested changes and publishes after confirmation
+pub async fn set_profile_interactive(
+    client: &Client,
+    changes: &ProfileConfig,
+    assume_yes: bool,
+) -> Result<EventId, Box<dyn std::error::Error + Send + Sync>> {
+    let current = fetch_current_profile(client, std::time::Duration::from_secs(10)).await?;
+
+    match &current {
+        Some(metadata) => status!("Current profile:\n{}\n", metadata.as_pretty_json()),
+        None => status!("No profile currently published.\n"),
+    }
+
+    let base = current
+        .as_ref()
+        .map(AgentProfile::from_metadata)
+        .unwrap_or_else(|| AgentProfile::from_metadata(&Metadata::new()));
+    let updated = base.with_overrides(Some(changes));
+    status!("New profile:\n{}\n", updated.to_metadata().as_pretty_json());
+
+    if !assume_yes {
+        eprint!("Publish this profile? [y/N] ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+            return Err("Aborted, profile not published".into());
+        }
+    }
+
+    let event_id = setup_agent_profile(client, &updated).await?;
+    println!("{}", event_id.to_bech32()?);
+    Ok(event_id)
+}
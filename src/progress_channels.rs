@@ -0,0 +1,138 @@
+//! Named progress identities, e.g. `--progress status=nsec1… --progress debug=nsec2…`
+//!
+//! Every channel sends from its own key, so a Nostr client can notify for one contact and mute
+//! the other. `status` is the default and carries milestones; the servers send their internal
+//! chatter to `debug`. A message for a channel that isn't configured goes to the default
+//! channel instead of being dropped.
+
+/// The default channel, also what `PROGRESS_NSEC` configures
+pub const STATUS: &str = "status";
+/// Where the servers send verbose internal progress
+pub const DEBUG: &str = "debug";
+
+#[derive(Debug, Clone)]
+pub struct ProgressChannels<T> {
+    /// In the order they were configured
+    channels: Vec<(String, T)>,
+}
+
+impl<T> Default for ProgressChannels<T> {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+        }
+    }
+}
+
+impl<T> ProgressChannels<T> {
+    /// Adds a channel, replacing any with the same name
+    pub fn insert(&mut self, name: &str, sender: T) {
+        match self.channels.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => *existing = sender,
+            None => self.channels.push((name.to_string(), sender)),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> {
+        self.channels
+            .iter()
+            .map(|(name, sender)| (name.as_str(), sender))
+    }
+
+    /// `status`, or the first configured channel if there is no `status`
+    pub fn default_sender(&self) -> Option<&T> {
+        self.default_entry().map(|(_, sender)| sender)
+    }
+
+    fn default_entry(&self) -> Option<(&str, &T)> {
+        self.iter()
+            .find(|(name, _)| *name == STATUS)
+            .or_else(|| self.iter().next())
+    }
+
+    /// The sender for `name` (the default when `None`), falling back to the default channel
+    pub fn resolve(&self, name: Option<&str>) -> Option<(&str, &T)> {
+        let name = name.unwrap_or(STATUS);
+        if let Some(found) = self.iter().find(|(n, _)| *n == name) {
+            return Some(found);
+        }
+
+        let fallback = self.default_entry()?;
+        if name == STATUS || name == DEBUG {
+            // Setups with a single progress identity get everything there
+            log::debug!("No '{}' progress channel, using '{}'", name, fallback.0);
+        } else {
+            log::warn!(
+                "Unknown progress channel '{}', sending to '{}' instead",
+                name,
+                fallback.0
+            );
+        }
+        Some(fallback)
+    }
+
+    pub fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> ProgressChannels<U> {
+        ProgressChannels {
+            channels: self
+                .channels
+                .iter()
+                .map(|(name, sender)| (name.clone(), f(sender)))
+                .collect(),
+        }
+    }
+}
+
+/// Parses one `--progress <name>=<nsec>` value
+pub fn parse_channel_arg(value: &str) -> Result<(String, String), String> {
+    let (name, nsec) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <name>=<nsec>, e.g. {}=nsec1…", STATUS))?;
+    let name = name.trim();
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "Invalid progress channel name '{}': use letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok((name.to_string(), nsec.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_falls_back_to_default() {
+        let mut channels = ProgressChannels::default();
+        assert!(channels.resolve(None).is_none());
+
+        channels.insert(DEBUG, 2);
+        // Without `status` the first configured channel is the default
+        assert_eq!(channels.resolve(Some("alerts")), Some((DEBUG, &2)));
+
+        channels.insert(STATUS, 1);
+        assert_eq!(channels.resolve(None), Some((STATUS, &1)));
+        assert_eq!(channels.resolve(Some(DEBUG)), Some((DEBUG, &2)));
+        assert_eq!(channels.resolve(Some("alerts")), Some((STATUS, &1)));
+
+        let mut single = ProgressChannels::default();
+        single.insert(STATUS, 1);
+        assert_eq!(single.resolve(Some(DEBUG)), Some((STATUS, &1)));
+        assert_eq!(single.map(|n| n * 10).default_sender(), Some(&10));
+    }
+
+    #[test]
+    fn test_parse_channel_arg() {
+        assert_eq!(
+            parse_channel_arg("debug=nsec1abc").unwrap(),
+            ("debug".to_string(), "nsec1abc".to_string())
+        );
+        assert!(parse_channel_arg("nsec1abc").is_err());
+        assert!(parse_channel_arg("=nsec1abc").is_err());
+        assert!(parse_channel_arg("a b=nsec1abc").is_err());
+    }
+}
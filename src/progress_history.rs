@@ -0,0 +1,327 @@
+//! Searchable history of the progress messages sent (`progress_history` tool)
+//!
+//! Progress scrolls by quickly and users ask what the agent said it was doing an hour ago. Every
+//! progress message `Chat` or an agent sends is kept with its time and source (`orchestrator`, or
+//! the id of the agent that sent it), and `progress_history` filters them by time and text. The
+//! history is bounded by `NPARROT_PROGRESS_HISTORY_SIZE` and drops its oldest entries first.
+//!
+//! With the transcript on (`NPARROT_TRANSCRIPT=1`) the progress lines are already stored there,
+//! so the history is rebuilt from the transcript at startup instead of being written twice.
+//! Otherwise it is kept in `progress_history.json` in the data dir, sealed with the data key like
+//! the notes and events files.
+
+use crate::at_rest::Vault;
+use crate::error::NparrotError;
+use crate::transcript;
+use chrono::{DateTime, TimeDelta, Utc};
+use rmcp::model::{CallToolResult, Content};
+use rmcp::{schemars, Error as RmcpError};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const FILE: &str = "progress_history.json";
+/// Bytes of progress text kept unless configured otherwise
+pub const DEFAULT_MAX_BYTES: usize = 256 * 1024;
+/// Entries `progress_history` returns unless asked for another number
+const DEFAULT_LIMIT: usize = 20;
+/// What an entry costs beyond its text and source: the timestamp and the JSON around it
+const ENTRY_OVERHEAD: usize = 64;
+
+/// The source of progress sent by a server's own `Chat` rather than by an agent
+pub const ORCHESTRATOR: &str = "orchestrator";
+
+lazy_static::lazy_static! {
+    static ref HISTORY: Mutex<History> = Mutex::new(History::new(DEFAULT_MAX_BYTES, None));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgressEntry {
+    pub timestamp: DateTime<Utc>,
+    pub source: String,
+    pub text: String,
+}
+
+impl ProgressEntry {
+    fn size(&self) -> usize {
+        self.text.len() + self.source.len() + ENTRY_OVERHEAD
+    }
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ProgressHistoryRequest {
+    #[serde(default)]
+    #[schemars(
+        description = "Only entries from this far back (\"90m\", \"2h\", \"1d\") or since this ISO 8601 time (optional)"
+    )]
+    pub since: Option<String>,
+    #[serde(default)]
+    #[schemars(description = "Only entries containing this text, case-insensitively (optional)")]
+    pub contains: Option<String>,
+    #[serde(default)]
+    #[schemars(description = "How many of the latest matching entries to return (default 20)")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug)]
+struct History {
+    entries: VecDeque<ProgressEntry>,
+    bytes: usize,
+    max_bytes: usize,
+    /// Where the history is saved, `None` while the transcript keeps it or nothing is configured
+    file: Option<PathBuf>,
+}
+
+impl History {
+    fn new(max_bytes: usize, file: Option<PathBuf>) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            bytes: 0,
+            max_bytes,
+            file,
+        }
+    }
+
+    /// Adds `entry`, dropping the oldest entries while the history is over its size
+    fn push(&mut self, entry: ProgressEntry) {
+        self.bytes += entry.size();
+        self.entries.push_back(entry);
+        while self.bytes > self.max_bytes {
+            let Some(oldest) = self.entries.pop_front() else {
+                break;
+            };
+            self.bytes -= oldest.size();
+        }
+    }
+
+    fn save(&self, vault: &Vault) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let result = serde_json::to_vec(&self.entries)
+            .map_err(|e| e.to_string())
+            .and_then(|json| vault.write(file, &json));
+        if let Err(e) = result {
+            log::error!(
+                "Failed to save the progress history {}: {}",
+                file.display(),
+                e
+            );
+        }
+    }
+
+    /// The latest `limit` entries after `since` that contain `contains`, oldest first
+    fn find(
+        &self,
+        since: Option<DateTime<Utc>>,
+        contains: Option<&str>,
+        limit: usize,
+    ) -> Vec<ProgressEntry> {
+        let contains = contains.map(str::to_lowercase);
+        let mut found: Vec<ProgressEntry> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| since.is_none_or(|since| entry.timestamp >= since))
+            .filter(|entry| {
+                contains
+                    .as_deref()
+                    .is_none_or(|text| entry.text.to_lowercase().contains(text))
+            })
+            .take(limit)
+            .cloned()
+            .collect();
+        found.reverse();
+        found
+    }
+}
+
+/// Keeps the history of `data_dir` within `max_bytes`: rebuilt from the transcript if it is on,
+/// otherwise loaded from and saved to its own file
+pub fn init(data_dir: &str, max_bytes: usize, from_transcript: bool) {
+    let path = Path::new(data_dir).join(FILE);
+    let vault = Vault::global();
+    let entries = if from_transcript {
+        load_transcript(data_dir)
+    } else {
+        load_file(&path, vault)
+    };
+    let mut history = History::new(max_bytes, (!from_transcript).then_some(path));
+    for entry in entries {
+        history.push(entry);
+    }
+    *HISTORY.lock().unwrap_or_else(|e| e.into_inner()) = history;
+}
+
+fn load_file(path: &Path, vault: &Vault) -> Vec<ProgressEntry> {
+    match vault.read(path) {
+        Ok(Some(json)) => serde_json::from_slice(&json).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable {}: {}", path.display(), e);
+            Vec::new()
+        }),
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            log::warn!("Ignoring unreadable {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+fn load_transcript(data_dir: &str) -> Vec<ProgressEntry> {
+    let lines = match transcript::search(data_dir, None, None) {
+        Ok(lines) => lines,
+        Err(e) => {
+            log::warn!(
+                "Failed to read the transcript for the progress history: {}",
+                e
+            );
+            return Vec::new();
+        }
+    };
+    lines
+        .into_iter()
+        .filter(|(line, _)| {
+            line.direction == transcript::Direction::Sent && line.channel == "progress"
+        })
+        .filter_map(|(line, text)| {
+            Some(ProgressEntry {
+                timestamp: line.timestamp,
+                source: line.source.unwrap_or_else(|| ORCHESTRATOR.to_string()),
+                text: text.ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Keeps a progress message `source` sent
+pub fn record(source: &str, text: &str) {
+    let mut history = HISTORY.lock().unwrap_or_else(|e| e.into_inner());
+    history.push(ProgressEntry {
+        timestamp: Utc::now(),
+        source: source.to_string(),
+        text: text.to_string(),
+    });
+    history.save(Vault::global());
+}
+
+/// When `since` is: a duration back from `now` or an ISO 8601 time
+fn parse_since(since: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, NparrotError> {
+    let since = since.trim();
+    if since.ends_with(|c: char| c.is_ascii_alphabetic()) {
+        if let Ok(secs) = crate::config::units::parse_duration_secs(since) {
+            return Ok(now - TimeDelta::seconds(secs as i64));
+        }
+    }
+    DateTime::parse_from_rfc3339(since)
+        .map(|time| time.to_utc())
+        .map_err(|_| {
+            NparrotError::invalid_params(
+                "since",
+                format!(
+                    "Invalid since '{}': use a duration like \"2h\" or an ISO 8601 time",
+                    since
+                ),
+            )
+        })
+}
+
+/// What `progress_history` returns
+pub fn tool_result(
+    ProgressHistoryRequest {
+        since,
+        contains,
+        limit,
+    }: ProgressHistoryRequest,
+) -> Result<CallToolResult, RmcpError> {
+    let since = since
+        .as_deref()
+        .map(|since| parse_since(since, Utc::now()))
+        .transpose()?;
+    let entries = HISTORY.lock().unwrap_or_else(|e| e.into_inner()).find(
+        since,
+        contains.as_deref(),
+        limit.unwrap_or(DEFAULT_LIMIT),
+    );
+    Ok(CallToolResult::success(vec![
+        Content::text(format!(
+            "{} progress message(s), oldest first",
+            entries.len()
+        )),
+        Content::json(entries)?,
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(minutes_ago: i64, source: &str, text: &str) -> ProgressEntry {
+        ProgressEntry {
+            timestamp: Utc::now() - TimeDelta::minutes(minutes_ago),
+            source: source.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut history = History::new(DEFAULT_MAX_BYTES, None);
+        history.push(entry(120, ORCHESTRATOR, "Cloning the repository"));
+        history.push(entry(50, "agent-1", "Running the test suite"));
+        history.push(entry(10, "agent-1", "Tests passed, deploying"));
+        history.push(entry(1, ORCHESTRATOR, "Deployed"));
+
+        let since = parse_since("1h", Utc::now()).unwrap();
+        let texts = |entries: Vec<ProgressEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.text).collect()
+        };
+        assert_eq!(
+            texts(history.find(Some(since), None, 20)),
+            [
+                "Running the test suite",
+                "Tests passed, deploying",
+                "Deployed"
+            ]
+        );
+        assert_eq!(
+            texts(history.find(None, Some("TEST"), 20)),
+            ["Running the test suite", "Tests passed, deploying"]
+        );
+        // The latest ones, still oldest first
+        assert_eq!(
+            texts(history.find(None, None, 2)),
+            ["Tests passed, deploying", "Deployed"]
+        );
+
+        assert!(parse_since("2025-07-01T09:00:00Z", Utc::now()).is_ok());
+        assert!(parse_since("yesterday-ish", Utc::now()).is_err());
+    }
+
+    #[test]
+    fn test_oldest_entries_are_evicted_first() {
+        let size = entry(0, "a", "0123456789").size();
+        let mut history = History::new(size * 3, None);
+        for i in 0..5 {
+            history.push(entry(5 - i, "a", &format!("message {:02}", i)));
+        }
+        assert_eq!(history.entries.len(), 3);
+        assert_eq!(history.entries[0].text, "message 02");
+        assert!(history.bytes <= size * 3);
+    }
+
+    #[test]
+    fn test_history_is_saved_and_loaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = Vault::new(Some(crate::at_rest::DataKey::derive(b"history test")));
+        let path = dir.path().join(FILE);
+        let mut history = History::new(DEFAULT_MAX_BYTES, Some(path.clone()));
+        history.push(entry(3, "agent-7", "Compiling"));
+        history.save(&vault);
+
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap_or_default()
+            .contains("Compiling"));
+        assert_eq!(history.entries, load_file(&path, &vault));
+    }
+}
@@ -0,0 +1,218 @@
+//! Telling a supervisor when an MCP server is ready to serve
+//!
+//! With `--health-file`, the file is written once the server is up (relays connected, Goose
+//! found where the server needs it) and rewritten every `LIVENESS_INTERVAL`, so a watchdog can
+//! restart the process when its mtime goes stale. It is removed on shutdown, and any file left
+//! by an earlier run is removed at startup, so a failed start never looks ready. Under systemd
+//! (`NOTIFY_SOCKET` set, `Type=notify`) the same moments are reported with sd_notify, and
+//! `WATCHDOG=1` is sent at half of `WatchdogSec=`.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often the health file is rewritten without a systemd watchdog
+pub const LIVENESS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Removes a health file left behind by an earlier run
+pub fn clear(health_file: Option<&Path>) {
+    if let Some(path) = health_file {
+        match std::fs::remove_file(path) {
+            Ok(()) => log::info!("Removed stale health file {}", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!("Could not remove health file {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Whether anyone is waiting for the readiness signal
+pub fn requested(health_file: Option<&Path>) -> bool {
+    health_file.is_some() || notify_socket().is_some()
+}
+
+fn notify_socket() -> Option<String> {
+    std::env::var("NOTIFY_SOCKET")
+        .ok()
+        .filter(|socket| !socket.is_empty())
+}
+
+/// Half the systemd watchdog timeout, if one is configured for this process
+fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Some(pid) = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+    {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+#[derive(Debug)]
+pub struct Readiness {
+    health_file: Option<PathBuf>,
+    notify_socket: Option<String>,
+    liveness: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl Readiness {
+    pub fn new(health_file: Option<PathBuf>) -> Self {
+        Self {
+            health_file,
+            notify_socket: notify_socket(),
+            liveness: None,
+        }
+    }
+
+    /// Signals that initialization finished and starts the liveness touches
+    pub fn ready(&mut self) -> io::Result<()> {
+        if let Some(path) = &self.health_file {
+            write_health_file(path)?;
+            log::info!("Ready, wrote {}", path.display());
+        }
+        if let Some(socket) = &self.notify_socket {
+            if let Err(e) = notify(socket, "READY=1\nSTATUS=Serving") {
+                log::warn!("sd_notify failed: {}", e);
+            }
+        }
+
+        let watchdog = self.notify_socket.as_ref().and(watchdog_interval());
+        if self.health_file.is_none() && watchdog.is_none() {
+            return Ok(());
+        }
+        let interval = watchdog.map_or(LIVENESS_INTERVAL, |half| half.min(LIVENESS_INTERVAL));
+        let health_file = self.health_file.clone();
+        let socket = self.notify_socket.clone().filter(|_| watchdog.is_some());
+        self.liveness = Some(tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                if let Some(path) = &health_file {
+                    if let Err(e) = write_health_file(path) {
+                        log::warn!("Could not touch health file {}: {}", path.display(), e);
+                    }
+                }
+                if let Some(socket) = &socket {
+                    let _ = notify(socket, "WATCHDOG=1");
+                }
+            }
+        }));
+        Ok(())
+    }
+
+    /// Withdraws the readiness signal; also done on drop
+    pub fn stopping(&mut self) {
+        if let Some(liveness) = self.liveness.take() {
+            liveness.abort();
+        }
+        if let Some(path) = self.health_file.take() {
+            if let Err(e) = std::fs::remove_file(&path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    log::warn!("Could not remove health file {}: {}", path.display(), e);
+                }
+            }
+        }
+        if let Some(socket) = self.notify_socket.take() {
+            let _ = notify(&socket, "STOPPING=1");
+        }
+    }
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        self.stopping();
+    }
+}
+
+fn write_health_file(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(
+        &tmp,
+        format!(
+            "pid={}\nupdated_at={}\n",
+            std::process::id(),
+            chrono::Utc::now().to_rfc3339()
+        ),
+    )?;
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(unix)]
+fn notify(socket: &str, state: &str) -> io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract NOTIFY_SOCKET needs Linux",
+            ))
+        }
+        None => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn notify(_socket: &str, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sd_notify needs Unix domain sockets",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[tokio::test]
+    async fn test_health_file_and_notify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run").join("ready");
+        let socket_path = dir.path().join("notify.sock");
+        let listener = UnixDatagram::bind(&socket_path).unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let mut readiness = Readiness::new(Some(path.clone()));
+        readiness.notify_socket = Some(socket_path.to_string_lossy().to_string());
+        assert!(!path.exists());
+
+        readiness.ready().unwrap();
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(&format!("pid={}", std::process::id())));
+        let mut buf = [0; 64];
+        let n = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=Serving");
+
+        drop(readiness);
+        assert!(!path.exists());
+        let n = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+
+        // A file from a crashed run doesn't survive the next start
+        std::fs::write(&path, "stale").unwrap();
+        clear(Some(&path));
+        assert!(!path.exists());
+    }
+}
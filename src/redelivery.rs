@@ -0,0 +1,628 @@
+//! Automatic resend of events that no relay accepted
+//!
+//! `Chat` hands events the `DeliveryTracker` marked failed to this queue. A background task
+//! republishes them with exponential backoff, adding the target's NIP-65 read relays on later
+//! attempts, and reports on the progress channel once it gives up. The queue lives in the data
+//! dir so pending resends survive restarts. A passive instance (`NPARROT_MULTI_INSTANCE=passive`)
+//! resends nothing and leaves the queue as it is.
+//!
+//! `nparrot outbox` works on the same file while a server runs. Every change to the queue holds
+//! an exclusive lock on `redelivery.json.lock` and re-reads the file first, so a message dropped
+//! from the shell stays dropped and one the server queued meanwhile isn't lost.
+
+use crate::envelope::{self, MessageType};
+use crate::presence;
+use crate::relays;
+use crate::response_tracker::DeliveryTracker;
+use crate::retry::{self, Outcome, RetryPolicy};
+use crate::transport::DmTransport;
+use crate::utils::send_private_msg;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub const FILE: &str = "redelivery.json";
+
+/// How often the queue is checked for due resends
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long to wait for the target's relay list and for extra relays to connect
+const RELAY_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref QUEUE: RwLock<Option<Arc<RedeliveryQueue>>> = RwLock::new(None);
+}
+
+#[derive(Debug, Clone)]
+pub struct ResendPolicy {
+    /// Resends before giving up and the delays between them; 0 attempts disables automatic
+    /// resend
+    pub retry: RetryPolicy,
+    /// From this attempt on the target's NIP-65 read relays are used as well
+    pub expand_from_attempt: u32,
+}
+
+impl Default for ResendPolicy {
+    fn default() -> Self {
+        Self {
+            retry: RetryPolicy::default_for(retry::RESEND),
+            expand_from_attempt: 2,
+        }
+    }
+}
+
+impl ResendPolicy {
+    /// Delay before resend number `attempt` (1-based)
+    fn delay_before(&self, attempt: u32) -> Duration {
+        self.retry.delay(attempt)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QueuedEvent {
+    event: Event,
+    recipient: PublicKey,
+    channel: String,
+    /// Resends made so far
+    attempts: u32,
+    next_attempt_at: u64,
+    /// When the event was queued; 0 for entries queued before this was recorded
+    #[serde(default)]
+    queued_at: u64,
+}
+
+/// A queued event as `nparrot outbox list` shows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PendingMessage {
+    pub id: EventId,
+    pub recipient: PublicKey,
+    pub channel: String,
+    pub attempts: u32,
+    pub queued_at: Option<Timestamp>,
+    pub next_attempt_at: Timestamp,
+}
+
+impl From<&QueuedEvent> for PendingMessage {
+    fn from(entry: &QueuedEvent) -> Self {
+        Self {
+            id: entry.event.id,
+            recipient: entry.recipient,
+            channel: entry.channel.clone(),
+            attempts: entry.attempts,
+            queued_at: (entry.queued_at > 0).then(|| Timestamp::from(entry.queued_at)),
+            next_attempt_at: Timestamp::from(entry.next_attempt_at),
+        }
+    }
+}
+
+/// What a flush did with each queued event
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FlushReport {
+    pub delivered: Vec<EventId>,
+    pub pending: Vec<EventId>,
+}
+
+#[derive(Debug)]
+pub struct RedeliveryQueue {
+    path: PathBuf,
+    policy: ResendPolicy,
+    entries: Mutex<Vec<QueuedEvent>>,
+    /// Events taken for a resend that hasn't finished; they stay in the file meanwhile
+    in_flight: Mutex<HashSet<EventId>>,
+}
+
+/// Holds the exclusive lock on the queue file until dropped
+struct SpoolLock(#[allow(dead_code)] File);
+
+impl SpoolLock {
+    fn acquire(path: &Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_extension("json.lock"))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::io::AsRawFd;
+            // Released when the file is closed
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(Self(file))
+    }
+}
+
+/// Loads the persisted queue from `data_dir` and makes it the one `enqueue` feeds
+pub fn init(data_dir: &str, policy: ResendPolicy) -> Arc<RedeliveryQueue> {
+    let queue = Arc::new(RedeliveryQueue::load(
+        Path::new(data_dir).join(FILE),
+        policy,
+    ));
+    if let Ok(mut guard) = QUEUE.write() {
+        *guard = Some(queue.clone());
+    }
+    queue
+}
+
+/// Queues an event every relay rejected; returns false when automatic resend is off
+pub fn enqueue(event: Event, recipient: PublicKey, channel: &str) -> bool {
+    let queue = match QUEUE.read() {
+        Ok(guard) => guard.clone(),
+        Err(_) => None,
+    };
+    match queue {
+        Some(queue) if queue.policy.retry.max_attempts > 0 => {
+            queue.push(vec![event], recipient, channel);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Queues the failed messages of a batch in a single write, so a crash can't persist only some
+/// of them; false (and nothing queued) if resending is disabled
+pub fn enqueue_batch(events: Vec<Event>, recipient: PublicKey, channel: &str) -> bool {
+    let queue = match QUEUE.read() {
+        Ok(guard) => guard.clone(),
+        Err(_) => None,
+    };
+    match queue {
+        Some(queue) if queue.policy.retry.max_attempts > 0 => {
+            queue.push(events, recipient, channel);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Events waiting to be resent
+pub fn backlog() -> usize {
+    match QUEUE.read() {
+        Ok(guard) => guard
+            .as_ref()
+            .and_then(|queue| queue.entries.lock().ok().map(|entries| entries.len()))
+            .unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+/// Tries every queued event once right away and saves whatever is still pending, so events a
+/// stopping daemon could not deliver are not left waiting for their backoff to expire first
+pub async fn flush(client: &Client, progress_client: Option<&Client>) -> FlushReport {
+    let queue = match QUEUE.read() {
+        Ok(guard) => guard.clone(),
+        Err(_) => None,
+    };
+    let Some(queue) = queue else {
+        return FlushReport::default();
+    };
+    let pending = queue.take_due_if_active(u64::MAX);
+    let mut report = FlushReport::default();
+    if pending.is_empty() {
+        return report;
+    }
+
+    log::info!("Flushing {} queued resend(s)", pending.len());
+    for entry in pending {
+        let sender = match (entry.channel.as_str(), progress_client) {
+            ("progress", Some(progress)) => progress,
+            _ => client,
+        };
+        let id = entry.event.id;
+        if resend(sender, &entry, false).await {
+            log::info!("Event {} delivered while flushing", id);
+            queue.finish(&id, |entries| entries.retain(|e| e.event.id != id));
+            report.delivered.push(id);
+        } else {
+            // Not an attempt against the policy: the next run picks it up as scheduled
+            queue.finish(&id, |_| ());
+            report.pending.push(id);
+        }
+    }
+    report
+}
+
+fn read_entries(path: &Path) -> Result<Vec<QueuedEvent>, String> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl RedeliveryQueue {
+    fn load(path: PathBuf, policy: ResendPolicy) -> Self {
+        let entries = read_entries(&path).unwrap_or_else(|e| {
+            log::warn!("Ignoring unreadable resend queue {}: {}", path.display(), e);
+            Vec::new()
+        });
+
+        if !entries.is_empty() {
+            log::info!("Resuming {} queued resend(s)", entries.len());
+        }
+        // Resumed events show up as pending again in `delivery_status`
+        for entry in &entries {
+            DeliveryTracker::global().track(entry.event.id, entry.recipient, &entry.channel);
+        }
+
+        Self {
+            path,
+            policy,
+            entries: Mutex::new(entries),
+            in_flight: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Runs `change` on the entries as the file has them, holding its lock, and saves the result
+    fn update<R>(&self, change: impl FnOnce(&mut Vec<QueuedEvent>) -> R) -> R {
+        let lock = SpoolLock::acquire(&self.path);
+        if let Err(e) = &lock {
+            log::warn!("Could not lock the resend queue: {}", e);
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        match read_entries(&self.path) {
+            Ok(stored) => *entries = stored,
+            Err(e) => log::warn!("Keeping the resend queue in memory, file unreadable: {}", e),
+        }
+        let result = change(&mut entries);
+        self.save(&entries);
+        result
+    }
+
+    fn push(&self, events: Vec<Event>, recipient: PublicKey, channel: &str) {
+        let now = Timestamp::now().as_u64();
+        self.update(|entries| {
+            for event in events {
+                if entries.iter().any(|e| e.event.id == event.id) {
+                    continue;
+                }
+                log::warn!(
+                    "Event {} was rejected by all relays, queued for resend",
+                    event.id
+                );
+                entries.push(QueuedEvent {
+                    event,
+                    recipient,
+                    channel: channel.to_string(),
+                    attempts: 0,
+                    next_attempt_at: now + self.policy.delay_before(1).as_secs(),
+                    queued_at: now,
+                });
+            }
+        })
+    }
+
+    fn save(&self, entries: &[QueuedEvent]) {
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = self.path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+            std::fs::rename(tmp, &self.path)
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to save resend queue: {}", e);
+        }
+    }
+
+    /// Returns the entries whose next attempt is due at `now` and marks them in flight; they stay
+    /// queued until `finish` or `reschedule` says what became of them
+    fn take_due(&self, now: u64) -> Vec<QueuedEvent> {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        let due: Vec<QueuedEvent> = self.update(|entries| {
+            entries
+                .iter()
+                .filter(|e| e.next_attempt_at <= now && !in_flight.contains(&e.event.id))
+                .cloned()
+                .collect()
+        });
+        in_flight.extend(due.iter().map(|e| e.event.id));
+        due
+    }
+
+    /// `take_due`, except that a passive instance takes nothing: it publishes nothing, so its
+    /// queue is left as it is for when it becomes the answering instance
+    fn take_due_if_active(&self, now: u64) -> Vec<QueuedEvent> {
+        if presence::is_passive() {
+            return Vec::new();
+        }
+        self.take_due(now)
+    }
+
+    /// Applies the outcome of the attempt at `id` to the queue and clears it from the in-flight set
+    fn finish<R>(&self, id: &EventId, change: impl FnOnce(&mut Vec<QueuedEvent>) -> R) -> R {
+        let result = self.update(change);
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+        result
+    }
+
+    /// Counts a failed attempt at `entry`, or removes and returns it if no attempts are left.
+    /// Nothing happens to an entry dropped from the queue in the meantime.
+    fn reschedule(&self, entry: QueuedEvent, now: u64) -> Option<QueuedEvent> {
+        let id = entry.event.id;
+        self.finish(&id, |entries| {
+            let index = entries.iter().position(|e| e.event.id == id)?;
+            let entry = &mut entries[index];
+            entry.attempts += 1;
+            let max_attempts = self.policy.retry.max_attempts;
+            let error = format!("event {} rejected by all relays", id);
+            if entry.attempts >= max_attempts {
+                retry::record(
+                    retry::RESEND,
+                    entry.attempts,
+                    max_attempts,
+                    Outcome::Exhausted,
+                    &error,
+                );
+                return Some(entries.remove(index));
+            }
+            let delay = self.policy.delay_before(entry.attempts + 1);
+            retry::record(
+                retry::RESEND,
+                entry.attempts,
+                max_attempts,
+                Outcome::Retry(delay),
+                &error,
+            );
+            entry.next_attempt_at = now + delay.as_secs();
+            None
+        })
+    }
+
+    /// The queued events, oldest first, as the file has them now
+    pub fn pending(&self) -> Vec<PendingMessage> {
+        self.update(|entries| entries.iter().map(PendingMessage::from).collect())
+    }
+
+    /// Discards the queued event `id`; false if it isn't queued
+    pub fn remove(&self, id: &EventId) -> bool {
+        self.update(|entries| {
+            let before = entries.len();
+            entries.retain(|e| e.event.id != *id);
+            entries.len() < before
+        })
+    }
+
+    /// Resends due events until the process exits
+    pub async fn run(
+        self: Arc<Self>,
+        client: Client,
+        progress_client: Option<Client>,
+        notice_expire_after_secs: Option<u64>,
+    ) {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let due = self.take_due_if_active(Timestamp::now().as_u64());
+            if due.is_empty() {
+                continue;
+            }
+
+            for entry in due {
+                let sender = match (entry.channel.as_str(), &progress_client) {
+                    ("progress", Some(progress)) => progress,
+                    _ => &client,
+                };
+                let expand = entry.attempts + 1 >= self.policy.expand_from_attempt;
+                if resend(sender, &entry, expand).await {
+                    let id = entry.event.id;
+                    log::info!("Event {} delivered on resend {}", id, entry.attempts + 1);
+                    self.finish(&id, |entries| entries.retain(|e| e.event.id != id));
+                    continue;
+                }
+
+                let Some(failed) = self.reschedule(entry, Timestamp::now().as_u64()) else {
+                    continue;
+                };
+                let notice = format!(
+                    "⚠️ Couldn't deliver message {} after {} attempts",
+                    failed
+                        .event
+                        .id
+                        .to_bech32()
+                        .unwrap_or_else(|_| failed.event.id.to_hex()),
+                    failed.attempts
+                );
+                log::error!("{}", notice);
+                DeliveryTracker::global().record_error(&failed.event.id);
+                let notifier = progress_client.as_ref().unwrap_or(&client);
+                let notice = envelope::wrap(MessageType::Progress, notice);
+                if let Err(e) =
+                    send_private_msg(notifier, failed.recipient, notice, notice_expire_after_secs)
+                        .await
+                {
+                    log::error!("Could not send delivery failure notice: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Publishes `entry` once, also to the recipient's NIP-65 read relays if `expand`
+async fn resend(client: &Client, entry: &QueuedEvent, expand: bool) -> bool {
+    let tracker = DeliveryTracker::global();
+
+    // The queue only tells main from progress, so a resend follows the `progress` route
+    // rather than a per-channel override
+    let route = match entry.channel.as_str() {
+        "progress" => relays::PROGRESS,
+        _ => relays::MAIN,
+    };
+    let sent = match relays::targets_for(route) {
+        Some(urls) => client.send_event_to(urls, &entry.event).await,
+        None => client.send_event(&entry.event).await,
+    };
+    match sent {
+        Ok(output) => {
+            tracker.record_output(&output);
+            if !output.success.is_empty() {
+                return true;
+            }
+        }
+        Err(e) => log::warn!("Resend of {} failed: {}", entry.event.id, e),
+    }
+
+    if !expand {
+        return false;
+    }
+
+    let relays = inbox_relays(client, entry.recipient).await;
+    if relays.is_empty() {
+        return false;
+    }
+    log::info!(
+        "Trying {} NIP-65 relay(s) of the recipient for {}",
+        relays.len(),
+        entry.event.id
+    );
+
+    match relays::publish_via(&relays, &entry.event, RELAY_LOOKUP_TIMEOUT).await {
+        Ok(output) => {
+            tracker.record_output(&output);
+            !output.success.is_empty()
+        }
+        Err(e) => {
+            log::warn!(
+                "Resend of {} via NIP-65 relays failed: {}",
+                entry.event.id,
+                e
+            );
+            false
+        }
+    }
+}
+
+/// The relays `recipient` reads from according to their NIP-65 relay list
+async fn inbox_relays(client: &Client, recipient: PublicKey) -> Vec<RelayUrl> {
+    let filter = Filter::new()
+        .author(recipient)
+        .kind(Kind::RelayList)
+        .limit(1);
+    // Through the transport, so the lookup counts against the relays' subscription caps
+    let events = match DmTransport::fetch_events(client, filter, RELAY_LOOKUP_TIMEOUT).await {
+        Ok(events) => events,
+        Err(e) => {
+            log::debug!("Could not fetch relay list of {}: {}", recipient, e);
+            return Vec::new();
+        }
+    };
+
+    let Some(event) = events.into_iter().max_by_key(|e| e.created_at) else {
+        return Vec::new();
+    };
+    nip65::extract_relay_list(&event)
+        .filter(|(_, metadata)| !matches!(metadata, Some(RelayMetadata::Write)))
+        .map(|(url, _)| url.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_event() -> Event {
+        EventBuilder::text_note("hello")
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        let policy = ResendPolicy::default();
+        assert_eq!(policy.delay_before(1), Duration::from_secs(30));
+        assert_eq!(policy.delay_before(2), Duration::from_secs(60));
+        assert_eq!(policy.delay_before(3), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn test_queue_survives_restart_and_gives_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("redelivery.json");
+        let recipient = Keys::generate().public_key();
+        let event = signed_event();
+
+        let queue = RedeliveryQueue::load(path.clone(), ResendPolicy::default());
+        queue.push(vec![event.clone()], recipient, "main");
+        queue.push(vec![event.clone()], recipient, "main");
+
+        let queue = RedeliveryQueue::load(path, ResendPolicy::default());
+        let now = Timestamp::now().as_u64();
+        assert!(queue.take_due(now).is_empty());
+
+        let mut entry = queue.take_due(now + 31).pop().unwrap();
+        assert_eq!(entry.event.id, event.id);
+        assert!(queue.take_due(now + 31).is_empty());
+
+        for _ in 0..2 {
+            assert!(queue.reschedule(entry, now).is_none());
+            entry = queue.take_due(u64::MAX).pop().unwrap();
+        }
+        let failed = queue.reschedule(entry, now).unwrap();
+        assert_eq!(failed.attempts, 3);
+        assert!(queue.take_due(u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn test_passive_instance_leaves_the_queue_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let event = signed_event();
+        let queue = RedeliveryQueue::load(path.clone(), ResendPolicy::default());
+        queue.push(vec![event.clone()], Keys::generate().public_key(), "main");
+        let saved = std::fs::read(&path).unwrap();
+
+        let _serial = presence::PASSIVE_TESTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        presence::set_passive(true);
+        let taken = queue.take_due_if_active(u64::MAX);
+        presence::set_passive(false);
+        assert!(taken.is_empty());
+        assert_eq!(std::fs::read(&path).unwrap(), saved);
+
+        let due = queue.take_due_if_active(u64::MAX);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].event.id, event.id);
+    }
+
+    #[test]
+    fn test_outbox_and_server_share_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let recipient = Keys::generate().public_key();
+        let (kept, dropped) = (signed_event(), signed_event());
+
+        let server = RedeliveryQueue::load(path.clone(), ResendPolicy::default());
+        server.push(vec![kept.clone(), dropped.clone()], recipient, "main");
+        let due = server.take_due(u64::MAX);
+        assert_eq!(due.len(), 2);
+
+        // `nparrot outbox` sees the events in flight and drops one of them
+        let outbox = RedeliveryQueue::load(path.clone(), ResendPolicy::default());
+        let pending = outbox.pending();
+        assert_eq!(pending.len(), 2);
+        assert!(pending[0].queued_at.is_some());
+        assert!(outbox.remove(&dropped.id));
+        assert!(!outbox.remove(&dropped.id));
+
+        // The failed attempt doesn't bring the dropped event back
+        for entry in due {
+            assert!(server.reschedule(entry, 0).is_none());
+        }
+        let ids: Vec<EventId> = outbox.pending().iter().map(|m| m.id).collect();
+        assert_eq!(ids, vec![kept.id]);
+        assert_eq!(server.take_due(u64::MAX)[0].attempts, 1);
+    }
+}
@@ -0,0 +1,662 @@
+//! Relay connection helpers shared by the main client setup, `doctor` and `ping`
+//!
+//! Relays in `RELAY_URL` can carry tags after a `#` (`wss://paid#primary,wss://free1#bulk`).
+//! The routing policy installed at startup uses them to pick where each channel publishes:
+//! user-facing messages go to every relay, progress only to `bulk` relays when there are any.
+//! `--relay-routes` overrides this per channel (`main=primary,debug=bulk,status=all`).
+//! `PROGRESS_RELAY_URL` gives the progress identities relays of their own, in which case
+//! progress channels route among those instead.
+//!
+//! At startup each relay's NIP-11 document is fetched and its limits kept here: message sizes
+//! for the outgoing size checks, the subscription cap `transport::subscriptions` budgets
+//! against, and whether it wants NIP-42 auth. Clients
+//! with a signer answer auth challenges themselves; the outcome is recorded next to the limits,
+//! so a relay that refuses us shows up in `relay_info` instead of just going quiet.
+
+use crate::transport::subscriptions;
+use nostr_sdk::prelude::*;
+use rmcp::schemars;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// The tag progress channels publish to by default
+pub const BULK: &str = "bulk";
+/// The channel name of the main identity in routes
+pub const MAIN: &str = "main";
+/// The route key that applies to every progress channel without its own route
+pub const PROGRESS: &str = "progress";
+
+/// How long to wait for a relay's NIP-11 document at startup
+const INFO_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref ROUTING: RwLock<RelayRouting> = RwLock::new(RelayRouting::default());
+    /// The relays passed to `learn_info`, with what is known about each
+    static ref INFO: RwLock<Vec<RelayInfo>> = RwLock::new(Vec::new());
+}
+
+/// A relay from `RELAY_URL` together with its tags
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelaySpec {
+    pub url: String,
+    pub tags: Vec<String>,
+}
+
+/// Splits a comma-separated relay list (as given in `RELAY_URL`) into relays and their tags
+pub fn parse_relay_specs(spec: &str) -> Vec<RelaySpec> {
+    spec.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|item| {
+            let mut parts = item.split('#');
+            let url = parts.next().unwrap_or_default().trim().to_string();
+            let tags = parts
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            RelaySpec { url, tags }
+        })
+        .filter(|relay| !relay.url.is_empty())
+        .collect()
+}
+
+/// Splits a comma-separated relay list (as given in `RELAY_URL`) into URLs, without tags
+pub fn parse_relay_urls(spec: &str) -> Vec<String> {
+    parse_relay_specs(spec)
+        .into_iter()
+        .map(|relay| relay.url)
+        .collect()
+}
+
+/// Where a channel publishes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    All,
+    Tag(String),
+}
+
+/// Per-channel route overrides, e.g. `main=primary,debug=bulk,status=all`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayRoutes(HashMap<String, Route>);
+
+impl RelayRoutes {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut routes = HashMap::new();
+        for item in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let (channel, target) = item.split_once('=').ok_or_else(|| {
+                format!(
+                    "Invalid relay route '{}': expected <channel>=<tag> or <channel>=all",
+                    item
+                )
+            })?;
+            let (channel, target) = (channel.trim(), target.trim());
+            if channel.is_empty() || target.is_empty() {
+                return Err(format!("Invalid relay route '{}'", item));
+            }
+            let route = match target {
+                "all" => Route::All,
+                tag => Route::Tag(tag.to_string()),
+            };
+            routes.insert(channel.to_string(), route);
+        }
+        Ok(Self(routes))
+    }
+}
+
+/// The tagged relay pool and which channel publishes where
+#[derive(Debug, Clone, Default)]
+pub struct RelayRouting {
+    relays: Vec<RelaySpec>,
+    /// The progress identities' own relays (`PROGRESS_RELAY_URL`), if they differ from `relays`
+    progress_relays: Option<Vec<RelaySpec>>,
+    routes: HashMap<String, Route>,
+}
+
+impl RelayRouting {
+    /// Fails if a route names a tag no relay of its channel carries, which would leave the
+    /// channel mute
+    pub fn new(
+        relays: Vec<RelaySpec>,
+        progress_relays: Option<Vec<RelaySpec>>,
+        routes: RelayRoutes,
+    ) -> Result<Self, String> {
+        let routing = Self {
+            relays,
+            progress_relays,
+            routes: HashMap::new(),
+        };
+        for (channel, route) in &routes.0 {
+            if let Route::Tag(tag) = route {
+                if !routing
+                    .pool(channel)
+                    .iter()
+                    .any(|relay| relay.tags.contains(tag))
+                {
+                    return Err(format!(
+                        "Relay route {}={}: no relay is tagged '{}'",
+                        channel, tag, tag
+                    ));
+                }
+            }
+        }
+        Ok(Self {
+            routes: routes.0,
+            ..routing
+        })
+    }
+
+    /// The relays `channel` chooses from: the progress relays for progress channels, if set
+    fn pool(&self, channel: &str) -> &[RelaySpec] {
+        match &self.progress_relays {
+            Some(progress) if channel != MAIN => progress,
+            _ => &self.relays,
+        }
+    }
+
+    /// The relays `channel` publishes to, or `None` for every relay of its identity
+    pub fn targets(&self, channel: &str) -> Option<Vec<String>> {
+        let pool = self.pool(channel);
+        let route = self.routes.get(channel).cloned().or_else(|| {
+            if channel == MAIN {
+                return None;
+            }
+            self.routes.get(PROGRESS).cloned().or_else(|| {
+                pool.iter()
+                    .any(|relay| relay.tags.iter().any(|tag| tag == BULK))
+                    .then(|| Route::Tag(BULK.to_string()))
+            })
+        });
+        match route? {
+            Route::All => None,
+            Route::Tag(tag) => Some(
+                pool.iter()
+                    .filter(|relay| relay.tags.contains(&tag))
+                    .map(|relay| relay.url.clone())
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn is_routed(&self) -> bool {
+        !self.routes.is_empty()
+            || self.progress_relays.is_some()
+            || self.relays.iter().any(|relay| !relay.tags.is_empty())
+    }
+}
+
+/// Installs the process-wide routing policy used by the send paths
+pub fn set_routing(routing: RelayRouting) {
+    if let Ok(mut guard) = ROUTING.write() {
+        *guard = routing;
+    }
+}
+
+/// The relays `channel` (`main` or a progress channel name) publishes to; `None` means all
+pub fn targets_for(channel: &str) -> Option<Vec<String>> {
+    ROUTING.read().ok()?.targets(channel)
+}
+
+/// Adds all relays to the client and starts connecting to them
+pub async fn connect_client(
+    client: &Client,
+    urls: &[String],
+) -> Result<(), nostr_sdk::client::Error> {
+    let added = futures::future::join_all(urls.iter().map(|url| client.add_relay(url.as_str())));
+    for result in added.await {
+        result?;
+    }
+    client.connect().await;
+    Ok(())
+}
+
+/// Publishes `event` to `urls` through a throwaway client, which keeps relays we only need for
+/// this one event (a recipient's inbox relays or relay hints) out of our own pool
+pub async fn publish_via(
+    urls: &[RelayUrl],
+    event: &Event,
+    timeout: Duration,
+) -> Result<Output<EventId>, nostr_sdk::client::Error> {
+    let extra = Client::default();
+    for url in urls {
+        let _ = extra.add_relay(url.clone()).await;
+    }
+    extra.connect().await;
+    extra.wait_for_connection(timeout).await;
+    let result = extra.send_event(event).await;
+    extra.disconnect().await;
+    result
+}
+
+/// Publishes `event` to the relays connected right now and returns their answers, leaving the
+/// ones still connecting to a background task that publishes to each once it is up (for at most
+/// `timeout`) and resolves to their answers
+pub async fn publish_early(
+    client: &Client,
+    event: &Event,
+    timeout: Duration,
+) -> Result<(Output<EventId>, JoinHandle<Vec<Output<EventId>>>), nostr_sdk::client::Error> {
+    let (ready, mut pending) = split_connected(client).await;
+    let first = if ready.is_empty() {
+        client.send_event(event).await?
+    } else {
+        client.send_event_to(ready, event).await?
+    };
+
+    let client = client.clone();
+    let event = event.clone();
+    let later = tokio::spawn(async move {
+        let deadline = Instant::now() + timeout;
+        let mut outputs = Vec::new();
+        while !pending.is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            let (ready, still_pending) = split_connected(&client).await;
+            let ready: Vec<_> = ready
+                .into_iter()
+                .filter(|url| pending.contains(url))
+                .collect();
+            if !ready.is_empty() {
+                match client.send_event_to(&ready, &event).await {
+                    Ok(output) => outputs.push(output),
+                    Err(e) => log::debug!("Late publish of {} failed: {}", event.id, e),
+                }
+            }
+            pending.retain(|url| still_pending.contains(url));
+        }
+        outputs
+    });
+    Ok((first, later))
+}
+
+/// The client's relays, connected ones first
+async fn split_connected(client: &Client) -> (Vec<RelayUrl>, Vec<RelayUrl>) {
+    let (ready, pending): (Vec<_>, Vec<_>) = client
+        .relays()
+        .await
+        .into_iter()
+        .partition(|(_, relay)| relay.status() == RelayStatus::Connected);
+    (
+        ready.into_iter().map(|(url, _)| url).collect(),
+        pending.into_iter().map(|(url, _)| url).collect(),
+    )
+}
+
+/// Timings from a single relay round trip
+#[derive(Debug, Clone)]
+pub struct RelayProbe {
+    pub connect_time: Duration,
+    pub rtt: Duration,
+}
+
+/// Connects to a relay on a throwaway client and measures connect time and a REQ/EOSE round trip
+pub async fn probe_relay(url: &str, timeout: Duration) -> Result<RelayProbe, String> {
+    let client = Client::default();
+
+    let started = Instant::now();
+    connect_relay(&client, url, timeout).await?;
+    let connect_time = started.elapsed();
+
+    let started = Instant::now();
+    let result = round_trip(&client, url, timeout).await;
+    let rtt = started.elapsed();
+    client.disconnect().await;
+
+    result.map(|_| RelayProbe { connect_time, rtt })
+}
+
+async fn connect_relay(client: &Client, url: &str, timeout: Duration) -> Result<(), String> {
+    client
+        .add_relay(url)
+        .await
+        .map_err(|e| format!("Invalid relay URL: {}", e))?;
+    client
+        .try_connect_relay(url, timeout)
+        .await
+        .map_err(|e| format!("Could not connect: {}", e))
+}
+
+async fn round_trip(client: &Client, url: &str, timeout: Duration) -> Result<(), String> {
+    let relay = client
+        .relay(url)
+        .await
+        .map_err(|e| format!("Relay unavailable: {}", e))?;
+
+    // A relay that accepts the connection but never answers a REQ is just as broken
+    let filter = Filter::new().kind(Kind::Metadata).limit(1);
+    match tokio::time::timeout(
+        timeout,
+        relay.fetch_events(filter, timeout * 2, ReqExitPolicy::ExitOnEOSE),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(format!("REQ failed: {}", e)),
+        Err(_) => Err(format!("No answer to REQ within {}s", timeout.as_secs())),
+    }
+}
+
+/// Fetches the relay's NIP-11 information document, if it publishes one
+pub async fn fetch_relay_info(url: &str, timeout: Duration) -> Option<RelayInformationDocument> {
+    let url = Url::parse(url).ok()?;
+    match tokio::time::timeout(timeout, RelayInformationDocument::get(url, None)).await {
+        Ok(Ok(document)) => Some(document),
+        Ok(Err(e)) => {
+            log::debug!("No NIP-11 document: {}", e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// How NIP-42 authentication with a relay went
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthState {
+    Authenticated,
+    Failed,
+}
+
+/// What a relay published about itself and how authenticating with it went
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RelayInfo {
+    pub url: String,
+    /// Whether a NIP-11 document was fetched yet
+    pub fetched: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub software: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub supported_nips: Vec<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_message_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_content_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_subscriptions: Option<usize>,
+    pub auth_required: bool,
+    pub payment_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthState>,
+    /// Subscriptions open on the relay right now
+    pub open_subscriptions: usize,
+    /// REQs waiting for the relay to have room
+    pub queued_subscriptions: usize,
+}
+
+impl RelayInfo {
+    pub fn from_document(url: &str, document: &RelayInformationDocument) -> Self {
+        let limitation = document.limitation.clone().unwrap_or_default();
+        let positive = |value: Option<i32>| value.filter(|v| *v > 0).map(|v| v as usize);
+        Self {
+            url: normalize(url),
+            fetched: true,
+            name: document.name.clone(),
+            software: document.software.clone(),
+            supported_nips: document.supported_nips.clone().unwrap_or_default(),
+            max_message_length: positive(limitation.max_message_length),
+            max_content_length: positive(limitation.max_content_length),
+            max_subscriptions: positive(limitation.max_subscriptions),
+            auth_required: limitation.auth_required == Some(true),
+            payment_required: limitation.payment_required == Some(true),
+            auth: None,
+            open_subscriptions: 0,
+            queued_subscriptions: 0,
+        }
+    }
+
+    /// The largest message the relay accepts, if it says
+    pub fn message_limit(&self) -> Option<usize> {
+        [self.max_message_length, self.max_content_length]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// The limits and flags on one line, for `doctor`
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(limit) = self.message_limit() {
+            parts.push(format!("max message {} bytes", limit));
+        }
+        if let Some(max) = self.max_subscriptions {
+            parts.push(format!("max {} subscriptions", max));
+        }
+        if self.auth_required {
+            parts.push("NIP-42 auth required".to_string());
+        }
+        if self.payment_required {
+            parts.push("payment required".to_string());
+        }
+        parts.join(", ")
+    }
+}
+
+fn normalize(url: &str) -> String {
+    url.trim_end_matches('/').to_string()
+}
+
+/// Fetches the NIP-11 documents of `urls`, the configured relays, in the background
+pub fn learn_info(urls: Vec<String>) {
+    if let Ok(mut info) = INFO.write() {
+        *info = urls
+            .iter()
+            .map(|url| RelayInfo {
+                url: normalize(url),
+                ..Default::default()
+            })
+            .collect();
+    }
+    for url in urls {
+        tokio::spawn(async move {
+            let Some(document) = fetch_relay_info(&url, INFO_TIMEOUT).await else {
+                return;
+            };
+            let info = RelayInfo::from_document(&url, &document);
+            if info.auth_required {
+                log::info!("{} requires NIP-42 auth; authenticating when asked", url);
+            }
+            if let Some(limit) = info.message_limit() {
+                log::debug!("{} accepts messages of up to {} bytes", url, limit);
+            }
+            remember_info(info);
+        });
+    }
+}
+
+/// Stores what is known about a relay, keeping its auth outcome
+pub fn remember_info(mut info: RelayInfo) {
+    if let Ok(mut known) = INFO.write() {
+        match known.iter_mut().find(|known| known.url == info.url) {
+            Some(existing) => {
+                info.auth = info.auth.or(existing.auth);
+                *existing = info;
+            }
+            None => known.push(info),
+        }
+    }
+}
+
+/// What is known about `url`, if it is a configured relay
+pub fn relay_info(url: &str) -> Option<RelayInfo> {
+    let url = normalize(url);
+    INFO.read()
+        .ok()?
+        .iter()
+        .find(|info| info.url == url)
+        .cloned()
+        .map(with_usage)
+}
+
+/// Every configured relay and what is known about it
+pub fn known_info() -> Vec<RelayInfo> {
+    INFO.read()
+        .map(|info| info.iter().cloned().map(with_usage).collect())
+        .unwrap_or_default()
+}
+
+fn with_usage(mut info: RelayInfo) -> RelayInfo {
+    let usage = subscriptions::usage(&info.url);
+    info.open_subscriptions = usage.open;
+    info.queued_subscriptions = usage.queued;
+    info
+}
+
+fn record_auth(url: &str, state: AuthState) {
+    let url = normalize(url);
+    if let Ok(mut known) = INFO.write() {
+        if let Some(info) = known.iter_mut().find(|info| info.url == url) {
+            info.auth = Some(state);
+        }
+    }
+}
+
+/// Makes `client` answer NIP-42 challenges when it has a signer, and records how each of its
+/// relays' challenges went
+pub async fn watch_auth(client: &Client) {
+    if !client.has_signer().await {
+        return;
+    }
+    client.automatic_authentication(true);
+    for (url, relay) in client.relays().await {
+        let mut notifications = relay.notifications();
+        tokio::spawn(async move {
+            loop {
+                match notifications.recv().await {
+                    Ok(RelayNotification::Authenticated) => {
+                        log::debug!("Authenticated to {}", url);
+                        record_auth(url.as_str(), AuthState::Authenticated);
+                    }
+                    Ok(RelayNotification::AuthenticationFailed) => {
+                        log::warn!(
+                            "NIP-42 authentication to {} failed; it may send us nothing",
+                            url
+                        );
+                        record_auth(url.as_str(), AuthState::Failed);
+                    }
+                    Ok(RelayNotification::Shutdown) => break,
+                    Ok(_) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize, schemars::JsonSchema)]
+pub struct RelayInfoRequest {
+    #[serde(default)]
+    #[schemars(description = "Only this relay URL (default: every configured relay)")]
+    pub relay: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POOL: &str = "wss://paid#primary, wss://free1#bulk,wss://free2#bulk#cheap";
+
+    #[test]
+    fn test_parse_tagged_relays() {
+        let relays = parse_relay_specs(POOL);
+        assert_eq!(relays[0].url, "wss://paid");
+        assert_eq!(relays[0].tags, vec!["primary"]);
+        assert_eq!(relays[2].tags, vec!["bulk", "cheap"]);
+        assert_eq!(
+            parse_relay_urls(POOL),
+            vec!["wss://paid", "wss://free1", "wss://free2"]
+        );
+        assert!(RelayRoutes::parse("main").is_err());
+    }
+
+    #[test]
+    fn test_progress_goes_to_bulk_relays_by_default() {
+        let routing =
+            RelayRouting::new(parse_relay_specs(POOL), None, RelayRoutes::default()).unwrap();
+        assert_eq!(routing.targets(MAIN), None);
+        assert_eq!(
+            routing.targets("status"),
+            Some(vec!["wss://free1".to_string(), "wss://free2".to_string()])
+        );
+
+        // Untagged pools broadcast everything
+        let plain = RelayRouting::new(
+            parse_relay_specs("wss://a,wss://b"),
+            None,
+            RelayRoutes::default(),
+        )
+        .unwrap();
+        assert_eq!(plain.targets("status"), None);
+        assert!(!plain.is_routed());
+    }
+
+    #[test]
+    fn test_progress_relays_of_their_own() {
+        let progress = parse_relay_specs("wss://private#quiet,wss://backup");
+        let routing = RelayRouting::new(
+            parse_relay_specs(POOL),
+            Some(progress),
+            RelayRoutes::default(),
+        )
+        .unwrap();
+        assert_eq!(routing.targets(MAIN), None);
+        // No bulk relay among the progress relays, so progress goes to all of them
+        assert_eq!(routing.targets("status"), None);
+        assert!(routing.is_routed());
+
+        let routes = RelayRoutes::parse("status=quiet").unwrap();
+        let progress = parse_relay_specs("wss://private#quiet,wss://backup");
+        let routing = RelayRouting::new(parse_relay_specs(POOL), Some(progress), routes).unwrap();
+        assert_eq!(
+            routing.targets("status"),
+            Some(vec!["wss://private".to_string()])
+        );
+        // Tags of the main relays don't exist for progress channels
+        let routes = RelayRoutes::parse("status=bulk").unwrap();
+        let progress = parse_relay_specs("wss://private");
+        assert!(RelayRouting::new(parse_relay_specs(POOL), Some(progress), routes).is_err());
+    }
+
+    #[test]
+    fn test_route_overrides() {
+        let routes = RelayRoutes::parse("main=primary,status=all,progress=cheap").unwrap();
+        let routing = RelayRouting::new(parse_relay_specs(POOL), None, routes).unwrap();
+        assert_eq!(routing.targets(MAIN), Some(vec!["wss://paid".to_string()]));
+        assert_eq!(routing.targets("status"), None);
+        assert_eq!(
+            routing.targets("debug"),
+            Some(vec!["wss://free2".to_string()])
+        );
+
+        let unknown = RelayRoutes::parse("debug=archive").unwrap();
+        assert!(RelayRouting::new(parse_relay_specs(POOL), None, unknown).is_err());
+    }
+
+    #[test]
+    fn test_relay_info_from_nip11() {
+        let document: RelayInformationDocument = serde_json::from_str(
+            r#"{"name":"Paid","supported_nips":[1,42],"limitation":{"max_message_length":131072,"max_content_length":8196,"max_subscriptions":20,"auth_required":true}}"#,
+        )
+        .unwrap();
+        let info = RelayInfo::from_document("wss://auth.example/", &document);
+        assert_eq!(info.url, "wss://auth.example");
+        assert_eq!(info.message_limit(), Some(8196));
+        assert_eq!(info.max_subscriptions, Some(20));
+        assert!(info.auth_required && !info.payment_required);
+        assert_eq!(
+            info.summary(),
+            "max message 8196 bytes, max 20 subscriptions, NIP-42 auth required"
+        );
+
+        // A refetched document keeps how authenticating went
+        remember_info(info.clone());
+        record_auth("wss://auth.example/", AuthState::Failed);
+        remember_info(info);
+        let known = relay_info("wss://auth.example").unwrap();
+        assert_eq!(known.auth, Some(AuthState::Failed));
+        assert!(known_info().contains(&known));
+    }
+}
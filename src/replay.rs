@@ -0,0 +1,361 @@
+//! Replay protection for incoming DMs (`NPARROT_REPLAY_MAX_AGE`)
+//!
+//! Relays re-deliver old events after a reconnect, and a hostile relay can replay a week-old
+//! command on purpose. The DM subscription checks every rumor against the ones processed before,
+//! and once a listener has authenticated its sender the rumor is remembered by id with its
+//! `created_at` in `processed_rumors.json` in the data dir, so a rumor seen before is rejected even
+//! after a restart. Strangers never get an entry, so they can neither cause disk writes nor push
+//! real messages out of the file. Rumors older than the maximum age are rejected as well; NIP-17
+//! only backdates the wrap and the seal, so the rumor's own time is the one checked. Rumors dated
+//! more than `MAX_CLOCK_SKEW` ahead are rejected too, since their entry would outlive the age
+//! check. Entries past the maximum age can't pass the age check anyway and are pruned, and at
+//! most `MAX_ENTRIES` are kept, oldest dropped first. Each rejection is logged with the relay
+//! that sent the event. Recorded rumors reach the file at most `SAVE_DELAY` later, in batches.
+//!
+//! The file is read once at startup: processes sharing a data dir don't see each other's
+//! messages until they restart.
+
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+pub const FILE: &str = "processed_rumors.json";
+/// Rumor ids remembered at most
+const MAX_ENTRIES: usize = 10_000;
+/// How far ahead of our clock a rumor may be dated
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(10 * 60);
+/// How long newly recorded rumors wait to be written, so a burst is saved once
+const SAVE_DELAY: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    static ref GUARD: RwLock<Option<Arc<ReplayGuard>>> = RwLock::new(None);
+}
+
+/// Why a rumor was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// A rumor with this id was already processed
+    Duplicate,
+    /// The rumor is older than the maximum age
+    TooOld { age: Duration },
+    /// The rumor is dated further ahead than clocks drift apart
+    FromTheFuture { ahead: Duration },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Duplicate => write!(f, "already processed"),
+            Rejection::TooOld { age } => write!(f, "written {}h ago", age.as_secs() / 3600),
+            Rejection::FromTheFuture { ahead } => {
+                write!(f, "dated {}s in the future", ahead.as_secs())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplayGuard {
+    path: Option<PathBuf>,
+    /// `None` turns the age check off
+    max_age: Option<Duration>,
+    /// Processed rumor ids and their `created_at`
+    processed: Mutex<HashMap<EventId, u64>>,
+    /// Whether `processed` has entries the file doesn't have yet
+    dirty: AtomicBool,
+}
+
+/// Loads the processed rumors of `data_dir` and makes the DM subscription check incoming rumors
+/// against them; a zero `max_age` only rejects duplicates
+pub fn init(data_dir: &str, max_age: Duration) {
+    let guard = ReplayGuard::load(Some(Path::new(data_dir).join(FILE)), max_age);
+    if let Ok(mut current) = GUARD.write() {
+        *current = Some(Arc::new(guard));
+    }
+}
+
+fn current() -> Option<Arc<ReplayGuard>> {
+    match GUARD.read() {
+        Ok(guard) => guard.clone(),
+        Err(_) => None,
+    }
+}
+
+/// Lets `rumor`, received via `relay`, through unless it is a replay; rejections are logged.
+/// Nothing is recorded: see `record`
+pub fn check(rumor: &UnsignedEvent, relay: &RelayUrl) -> Result<(), Rejection> {
+    let Some(guard) = current() else {
+        return Ok(());
+    };
+    let Some(id) = rumor.id else {
+        return Ok(());
+    };
+    let result = guard.check(id, rumor.created_at, Timestamp::now());
+    if let Err(rejection) = result {
+        log::warn!(
+            "Rejecting message {} from {} via {}: {}",
+            id,
+            rumor.pubkey,
+            relay,
+            rejection
+        );
+    }
+    result
+}
+
+/// Remembers `rumor` as processed; call it once its sender has been authenticated
+pub fn record(rumor: &UnsignedEvent) {
+    let Some(guard) = current() else {
+        return;
+    };
+    let Some(id) = rumor.id else {
+        return;
+    };
+    if !guard.record(id, rumor.created_at, Timestamp::now()) {
+        return;
+    }
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn(async move {
+                tokio::time::sleep(SAVE_DELAY).await;
+                guard.flush();
+            });
+        }
+        Err(_) => guard.flush(),
+    }
+}
+
+impl ReplayGuard {
+    fn load(path: Option<PathBuf>, max_age: Duration) -> Self {
+        let processed = path
+            .as_deref()
+            .and_then(|path| match std::fs::read(path) {
+                Ok(bytes) => serde_json::from_slice(&bytes)
+                    .map_err(|e| {
+                        log::warn!("Ignoring unreadable {}: {}", path.display(), e);
+                    })
+                    .ok(),
+                Err(_) => None,
+            })
+            .unwrap_or_default();
+        Self {
+            path,
+            max_age: (!max_age.is_zero()).then_some(max_age),
+            processed: Mutex::new(processed),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Says why the rumor `id` written at `created_at` is a replay, if it is one
+    fn check(&self, id: EventId, created_at: Timestamp, now: Timestamp) -> Result<(), Rejection> {
+        let ahead = created_at.as_u64().saturating_sub(now.as_u64());
+        if ahead > MAX_CLOCK_SKEW.as_secs() {
+            return Err(Rejection::FromTheFuture {
+                ahead: Duration::from_secs(ahead),
+            });
+        }
+        if let Some(max_age) = self.max_age {
+            let age = Duration::from_secs(now.as_u64().saturating_sub(created_at.as_u64()));
+            if age > max_age {
+                return Err(Rejection::TooOld { age });
+            }
+        }
+        let processed = self.processed.lock().unwrap_or_else(|e| e.into_inner());
+        if processed.contains_key(&id) {
+            return Err(Rejection::Duplicate);
+        }
+        Ok(())
+    }
+
+    /// Remembers the rumor `id` written at `created_at`; true if it is the first entry since
+    /// the last save, so a save has to be scheduled
+    fn record(&self, id: EventId, created_at: Timestamp, now: Timestamp) -> bool {
+        let mut processed = self.processed.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(max_age) = self.max_age {
+            let cutoff = now.as_u64().saturating_sub(max_age.as_secs());
+            processed.retain(|_, created_at| *created_at >= cutoff);
+        }
+        // Within the clock skew, a rumor dated ahead counts as written now
+        processed.insert(id, created_at.as_u64().min(now.as_u64()));
+        if processed.len() > MAX_ENTRIES {
+            let mut by_age: Vec<(EventId, u64)> =
+                processed.iter().map(|(id, at)| (*id, *at)).collect();
+            by_age.sort_by_key(|(_, created_at)| *created_at);
+            for (id, _) in by_age.into_iter().take(processed.len() - MAX_ENTRIES) {
+                processed.remove(&id);
+            }
+        }
+        !self.dirty.swap(true, Ordering::SeqCst)
+    }
+
+    /// Writes the processed rumors if any were recorded since the last save
+    fn flush(&self) {
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let Some(path) = &self.path else {
+            return;
+        };
+        let json = {
+            let processed = self.processed.lock().unwrap_or_else(|e| e.into_inner());
+            serde_json::to_vec(&*processed)
+        };
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, json?)?;
+            std::fs::rename(tmp, path)
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to save the processed rumors: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3600;
+    const MAX_AGE: Duration = Duration::from_secs(48 * HOUR);
+
+    fn id(byte: u8) -> EventId {
+        EventId::from_byte_array([byte; 32])
+    }
+
+    /// What the subscription and an authenticating listener do together
+    fn accept(
+        guard: &ReplayGuard,
+        id: EventId,
+        created_at: Timestamp,
+        now: Timestamp,
+    ) -> Result<(), Rejection> {
+        guard.check(id, created_at, now)?;
+        guard.record(id, created_at, now);
+        guard.flush();
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicates_and_old_rumors_are_rejected() {
+        let guard = ReplayGuard::load(None, MAX_AGE);
+        let now = Timestamp::from(100 * HOUR);
+        assert_eq!(
+            accept(&guard, id(1), Timestamp::from(99 * HOUR), now),
+            Ok(())
+        );
+        assert_eq!(
+            accept(&guard, id(1), Timestamp::from(99 * HOUR), now),
+            Err(Rejection::Duplicate)
+        );
+        assert_eq!(
+            accept(&guard, id(2), Timestamp::from(40 * HOUR), now),
+            Err(Rejection::TooOld {
+                age: Duration::from_secs(60 * HOUR)
+            })
+        );
+
+        // Without a maximum age only duplicates are rejected
+        let guard = ReplayGuard::load(None, Duration::ZERO);
+        assert_eq!(accept(&guard, id(2), Timestamp::from(0), now), Ok(()));
+        assert_eq!(
+            accept(&guard, id(2), Timestamp::from(0), now),
+            Err(Rejection::Duplicate)
+        );
+    }
+
+    #[test]
+    fn test_processed_rumors_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let now = Timestamp::from(100 * HOUR);
+        let guard = ReplayGuard::load(Some(path.clone()), MAX_AGE);
+        accept(&guard, id(1), Timestamp::from(90 * HOUR), now).unwrap();
+        accept(&guard, id(2), Timestamp::from(99 * HOUR), now).unwrap();
+
+        let restarted = ReplayGuard::load(Some(path), MAX_AGE);
+        assert_eq!(
+            accept(&restarted, id(2), Timestamp::from(99 * HOUR), now),
+            Err(Rejection::Duplicate)
+        );
+        // Entries past the maximum age are pruned once they can't pass the age check anyway
+        accept(
+            &restarted,
+            id(3),
+            Timestamp::from(140 * HOUR),
+            Timestamp::from(140 * HOUR),
+        )
+        .unwrap();
+        let processed = restarted.processed.lock().unwrap();
+        assert!(!processed.contains_key(&id(1)));
+        assert!(processed.contains_key(&id(2)));
+    }
+
+    #[test]
+    fn test_checking_alone_records_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE);
+        let guard = ReplayGuard::load(Some(path.clone()), MAX_AGE);
+        let now = Timestamp::from(100 * HOUR);
+
+        // A stranger's rumor passes the check but is dropped by the listener unrecorded
+        assert_eq!(guard.check(id(1), now, now), Ok(()));
+        guard.flush();
+        assert_eq!(guard.check(id(1), now, now), Ok(()));
+        assert!(!path.exists());
+
+        // Only a recorded rumor is a duplicate, and a burst of them is saved once
+        assert!(guard.record(id(1), now, now));
+        assert!(!guard.record(id(2), now, now));
+        assert!(!path.exists());
+        guard.flush();
+        assert_eq!(guard.check(id(1), now, now), Err(Rejection::Duplicate));
+        let restarted = ReplayGuard::load(Some(path), MAX_AGE);
+        assert_eq!(restarted.check(id(2), now, now), Err(Rejection::Duplicate));
+    }
+
+    #[test]
+    fn test_rumors_dated_ahead_are_rejected() {
+        let guard = ReplayGuard::load(None, MAX_AGE);
+        let now = Timestamp::from(100 * HOUR);
+        assert_eq!(
+            accept(&guard, id(1), Timestamp::from(101 * HOUR), now),
+            Err(Rejection::FromTheFuture {
+                ahead: Duration::from_secs(HOUR)
+            })
+        );
+
+        // Slight clock skew is fine, and the entry is pruned like one written now
+        accept(&guard, id(2), Timestamp::from(100 * HOUR + 60), now).unwrap();
+        assert_eq!(guard.processed.lock().unwrap()[&id(2)], 100 * HOUR);
+        // Even without an age limit
+        let guard = ReplayGuard::load(None, Duration::ZERO);
+        assert!(accept(&guard, id(3), Timestamp::from(1000 * HOUR), now).is_err());
+    }
+
+    #[test]
+    fn test_oldest_entries_are_dropped_past_the_bound() {
+        let guard = ReplayGuard::load(None, Duration::ZERO);
+        let now = Timestamp::from(MAX_ENTRIES as u64 + 10);
+        for i in 0..=MAX_ENTRIES as u64 {
+            let mut bytes = [0u8; 32];
+            bytes[..8].copy_from_slice(&i.to_be_bytes());
+            accept(
+                &guard,
+                EventId::from_byte_array(bytes),
+                Timestamp::from(i),
+                now,
+            )
+            .unwrap();
+        }
+        let processed = guard.processed.lock().unwrap();
+        assert_eq!(processed.len(), MAX_ENTRIES);
+        assert!(!processed.contains_key(&EventId::all_zeros()));
+    }
+}
@@ -0,0 +1,120 @@
+//! `research_and_build`: a web search whose results are handed to a Goose task
+//!
+//! The results are written into the task instructions where `{{search_results}}` appears, or
+//! appended when it doesn't. If the search fails or finds nothing, no task is started unless the
+//! caller confirmed with `proceed_without_results`, so a build never silently runs on nothing.
+
+use crate::searxng_mcp::SearchResult;
+use rmcp::schemars;
+
+pub const PLACEHOLDER: &str = "{{search_results}}";
+pub const DEFAULT_TOP_N: u32 = 5;
+pub const MAX_TOP_N: u32 = 10;
+/// Characters of each result's snippet passed on to the task
+const SNIPPET_CHARS: usize = 300;
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ResearchAndBuildRequest {
+    #[schemars(description = "What to search the web for")]
+    pub query: String,
+    #[schemars(
+        description = "Goose task instructions; {{search_results}} is replaced by the results, which are appended if it is missing"
+    )]
+    pub instructions: String,
+    #[serde(default)]
+    #[schemars(description = "How many results to pass on (default 5, at most 10)")]
+    pub top_n: Option<u32>,
+    #[serde(default)]
+    #[schemars(
+        description = "Run the task even if the search fails or finds nothing; only set after the user agreed"
+    )]
+    pub proceed_without_results: Option<bool>,
+    #[serde(default)]
+    pub max_turns: Option<u32>,
+    #[serde(default)]
+    pub debug: Option<bool>,
+}
+
+impl ResearchAndBuildRequest {
+    pub fn top_n(&self) -> u32 {
+        self.top_n.unwrap_or(DEFAULT_TOP_N).clamp(1, MAX_TOP_N)
+    }
+}
+
+/// The results as a numbered list with title, URL and snippet
+pub fn format_results(results: &[SearchResult]) -> String {
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            let mut entry = format!("{}. {}\n   URL: {}", i + 1, result.title, result.url);
+            if let Some(content) = result.content.as_deref().map(str::trim) {
+                if !content.is_empty() {
+                    let mut snippet: String = content.chars().take(SNIPPET_CHARS).collect();
+                    if content.chars().count() > SNIPPET_CHARS {
+                        snippet.push('…');
+                    }
+                    entry.push_str(&format!("\n   {}", snippet));
+                }
+            }
+            entry
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `instructions` with `results` in place of every placeholder, or appended without one
+pub fn inject(instructions: &str, results: &str) -> String {
+    if instructions.contains(PLACEHOLDER) {
+        instructions.replace(PLACEHOLDER, results)
+    } else {
+        format!(
+            "{}\n\nSearch results:\n{}",
+            instructions.trim_end(),
+            results
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(title: &str, content: Option<&str>) -> SearchResult {
+        SearchResult {
+            title: title.to_string(),
+            url: format!("https://example.com/{}", title.to_lowercase()),
+            content: content.map(str::to_string),
+            engine: None,
+            score: None,
+            category: None,
+        }
+    }
+
+    #[test]
+    fn test_results_are_injected() {
+        let results = format_results(&[
+            result("Axum", Some("Ergonomic web framework")),
+            result("Tower", None),
+        ]);
+        assert_eq!(
+            results,
+            "1. Axum\n   URL: https://example.com/axum\n   Ergonomic web framework\n2. Tower\n   URL: https://example.com/tower"
+        );
+
+        assert_eq!(
+            inject(
+                "Use these docs:\n{{search_results}}\nThen build.",
+                "1. Axum"
+            ),
+            "Use these docs:\n1. Axum\nThen build."
+        );
+        assert_eq!(
+            inject("Build the server\n", "1. Axum"),
+            "Build the server\n\nSearch results:\n1. Axum"
+        );
+
+        let long = "x".repeat(SNIPPET_CHARS + 10);
+        assert!(format_results(&[result("Long", Some(&long))]).ends_with('…'));
+    }
+}
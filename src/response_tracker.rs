@@ -1,28 +1,46 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use nostr_sdk::prelude::*;
+use rmcp::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
 use tokio::time::{timeout, Duration};
 
+/// How long settled deliveries are remembered unless configured otherwise
+pub const DEFAULT_DELIVERY_RETENTION: Duration = Duration::from_secs(60 * 60);
+/// User messages whose answer counts are kept for `delivery_status`
+const ANSWERED_KEPT: usize = 50;
+
+lazy_static::lazy_static! {
+    static ref DELIVERY_TRACKER: DeliveryTracker = DeliveryTracker::new(DEFAULT_DELIVERY_RETENTION);
+    static ref ANSWERS: AnswerLedger = AnswerLedger::new(ResponsePolicy::default());
+}
+
+/// Whether each sender's latest message was answered
 #[derive(Debug, Clone)]
 pub struct ResponseTracker {
-    has_sent_response: Arc<AtomicBool>,
-    conversation_active: Arc<AtomicBool>,
+    /// Senders with an active conversation, and whether it got its answer
+    conversations: Arc<Mutex<HashMap<PublicKey, bool>>>,
 }
 
 impl ResponseTracker {
     pub fn new() -> Self {
         Self {
-            has_sent_response: Arc::new(AtomicBool::new(false)),
-            conversation_active: Arc::new(AtomicBool::new(false)),
+            conversations: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    pub fn start_conversation(&self) {
-        self.has_sent_response.store(false, Ordering::Relaxed);
-        self.conversation_active.store(true, Ordering::Relaxed);
+    pub fn start_conversation(&self, sender: PublicKey) {
+        if let Ok(mut conversations) = self.conversations.lock() {
+            conversations.insert(sender, false);
+        }
     }
 
-    pub fn mark_response_sent(&self) {
-        self.has_sent_response.store(true, Ordering::Relaxed);
+    pub fn mark_response_sent(&self, sender: PublicKey) {
+        if let Ok(mut conversations) = self.conversations.lock() {
+            if let Some(answered) = conversations.get_mut(&sender) {
+                *answered = true;
+            }
+        }
     }
 
     pub fn mark_progress_sent(&self) {
@@ -31,46 +49,397 @@ impl ResponseTracker {
     }
 
     #[allow(dead_code)]
-    pub fn end_conversation(&self) {
-        self.conversation_active.store(false, Ordering::Relaxed);
+    pub fn end_conversation(&self, sender: PublicKey) {
+        if let Ok(mut conversations) = self.conversations.lock() {
+            conversations.remove(&sender);
+        }
     }
 
     #[allow(dead_code)]
-    pub fn has_sent_final_response(&self) -> bool {
-        self.has_sent_response.load(Ordering::Relaxed)
+    pub fn has_sent_final_response(&self, sender: PublicKey) -> bool {
+        self.conversations
+            .lock()
+            .is_ok_and(|conversations| conversations.get(&sender) == Some(&true))
     }
 
     #[allow(dead_code)]
-    pub fn is_conversation_active(&self) -> bool {
-        self.conversation_active.load(Ordering::Relaxed)
+    pub fn is_conversation_active(&self, sender: PublicKey) -> bool {
+        self.conversations
+            .lock()
+            .is_ok_and(|conversations| conversations.contains_key(&sender))
     }
 
     #[allow(dead_code)]
-    pub async fn ensure_response_sent<F, Fut>(&self, send_fallback: F) -> Result<(), String>
+    pub async fn ensure_response_sent<F, Fut>(
+        &self,
+        sender: PublicKey,
+        send_fallback: F,
+    ) -> Result<(), String>
     where
         F: FnOnce() -> Fut,
         Fut: std::future::Future<Output = Result<(), String>>,
     {
         // Wait a bit to see if the agent sends a response naturally
         let wait_result = timeout(Duration::from_secs(2), async {
-            while self.is_conversation_active() && !self.has_sent_final_response() {
+            while self.is_conversation_active(sender) && !self.has_sent_final_response(sender) {
                 tokio::time::sleep(Duration::from_millis(100)).await;
             }
         })
         .await;
 
         // If timeout expired and no response was sent, send fallback
-        if wait_result.is_err() && self.is_conversation_active() && !self.has_sent_final_response()
+        if wait_result.is_err()
+            && self.is_conversation_active(sender)
+            && !self.has_sent_final_response(sender)
         {
             log::warn!("Agent did not send final response - sending fallback message");
             send_fallback().await?;
-            self.mark_response_sent();
+            self.mark_response_sent(sender);
         }
 
         Ok(())
     }
 }
 
+/// What happens to a second `send` answering the same user message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ResponsePolicy {
+    /// Reroute it to the progress channel with a note
+    Strict,
+    /// Send it, but log a warning
+    #[default]
+    Warn,
+    /// Send it
+    Off,
+}
+
+/// Whether a `send` may go to the main channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    /// The first answer to the message, or a send no user message is waiting on
+    First,
+    /// Another answer, to be sent anyway
+    Again(u32),
+    /// Another answer, to be rerouted to the progress channel
+    Reroute(u32),
+}
+
+/// How often one user message was answered on the main channel
+#[derive(Debug, Clone, Serialize)]
+pub struct AnswerCount {
+    /// Event id of the user message, assigned by `wait`
+    pub correlation_id: String,
+    /// Who sent it
+    pub sender: String,
+    pub sent: u32,
+    pub rerouted: u32,
+}
+
+/// Counts the main-channel sends per user message, shared by the orchestrator and its agents
+#[derive(Debug)]
+pub struct AnswerLedger {
+    policy: RwLock<ResponsePolicy>,
+    /// The user message `wait` returned last
+    current: RwLock<Option<EventId>>,
+    /// The message `wait` returned last per sender
+    current_by_sender: RwLock<HashMap<PublicKey, EventId>>,
+    /// Newest last, at most `ANSWERED_KEPT`
+    counts: Mutex<VecDeque<(EventId, PublicKey, AnswerCount)>>,
+}
+
+impl AnswerLedger {
+    pub fn new(policy: ResponsePolicy) -> Self {
+        Self {
+            policy: RwLock::new(policy),
+            current: RwLock::new(None),
+            current_by_sender: RwLock::new(HashMap::new()),
+            counts: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn global() -> &'static AnswerLedger {
+        &ANSWERS
+    }
+
+    pub fn set_policy(&self, policy: ResponsePolicy) {
+        if let Ok(mut guard) = self.policy.write() {
+            *guard = policy;
+        }
+    }
+
+    /// Makes `correlation_id`, sent by `sender`, the message later sends answer, unless they
+    /// carry their own
+    pub fn begin(&self, correlation_id: EventId, sender: PublicKey) {
+        if let Ok(mut current) = self.current.write() {
+            *current = Some(correlation_id);
+        }
+        if let Ok(mut current) = self.current_by_sender.write() {
+            current.insert(sender, correlation_id);
+        }
+        if let Ok(mut counts) = self.counts.lock() {
+            if !counts.iter().any(|(id, _, _)| *id == correlation_id) {
+                counts.push_back((
+                    correlation_id,
+                    sender,
+                    AnswerCount {
+                        correlation_id: correlation_id.to_hex(),
+                        sender: sender.to_hex(),
+                        sent: 0,
+                        rerouted: 0,
+                    },
+                ));
+                while counts.len() > ANSWERED_KEPT {
+                    counts.pop_front();
+                }
+            }
+        }
+    }
+
+    pub fn current(&self) -> Option<EventId> {
+        self.current.read().ok().and_then(|current| *current)
+    }
+
+    /// The message of `sender` that `wait` returned last
+    pub fn current_from(&self, sender: &PublicKey) -> Option<EventId> {
+        self.current_by_sender
+            .read()
+            .ok()
+            .and_then(|current| current.get(sender).copied())
+    }
+
+    /// Who sent `correlation_id`, if it is one of the recent user messages
+    pub fn sender_of(&self, correlation_id: EventId) -> Option<PublicKey> {
+        self.counts.lock().ok().and_then(|counts| {
+            counts
+                .iter()
+                .find(|(id, _, _)| *id == correlation_id)
+                .map(|(_, sender, _)| *sender)
+        })
+    }
+
+    /// Who sent the message `wait` returned last
+    pub fn current_sender(&self) -> Option<PublicKey> {
+        self.current().and_then(|id| self.sender_of(id))
+    }
+
+    /// Counts a `send` answering `correlation_id` before it goes out and says where it goes
+    pub fn claim(&self, correlation_id: Option<EventId>) -> Answer {
+        let policy = self.policy.read().map(|p| *p).unwrap_or_default();
+        let (Some(correlation_id), Ok(mut counts)) = (correlation_id, self.counts.lock()) else {
+            return Answer::First;
+        };
+        let Some((_, _, count)) = counts.iter_mut().find(|(id, _, _)| *id == correlation_id) else {
+            return Answer::First;
+        };
+        let earlier = count.sent;
+        if earlier == 0 || policy == ResponsePolicy::Off {
+            count.sent += 1;
+            return Answer::First;
+        }
+        match policy {
+            ResponsePolicy::Strict => {
+                count.rerouted += 1;
+                Answer::Reroute(earlier)
+            }
+            _ => {
+                count.sent += 1;
+                Answer::Again(earlier)
+            }
+        }
+    }
+
+    /// Takes back a claimed main-channel send that failed, so a retry still counts as the first
+    pub fn release(&self, correlation_id: Option<EventId>) {
+        let (Some(correlation_id), Ok(mut counts)) = (correlation_id, self.counts.lock()) else {
+            return;
+        };
+        if let Some((_, _, count)) = counts.iter_mut().find(|(id, _, _)| *id == correlation_id) {
+            count.sent = count.sent.saturating_sub(1);
+        }
+    }
+
+    /// Answer counts of the recent user messages, oldest first
+    pub fn counts(&self) -> Vec<AnswerCount> {
+        self.counts
+            .lock()
+            .map(|counts| counts.iter().map(|(_, _, count)| count.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryState {
+    /// Handed to the relay pool, no answer yet
+    Pending,
+    /// At least one relay accepted the event
+    Acknowledged,
+    /// Every relay rejected the event or timed out
+    Failed,
+}
+
+/// An outgoing event and what the relays said about it
+#[derive(Debug, Clone, Serialize)]
+pub struct DeliveryRecord {
+    pub event_id: EventId,
+    pub recipient: PublicKey,
+    /// Which identity sent it (`main` or `progress`)
+    pub channel: String,
+    pub sent_at: Timestamp,
+    pub acked_by: Vec<RelayUrl>,
+    pub rejected_by: HashMap<RelayUrl, String>,
+    pub state: DeliveryState,
+}
+
+/// Tracks outgoing events until they settle and for `retention` afterwards
+#[derive(Debug)]
+pub struct DeliveryTracker {
+    records: RwLock<HashMap<EventId, DeliveryRecord>>,
+    retention: RwLock<Duration>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeliverySummary {
+    pub pending: usize,
+    pub acknowledged: usize,
+    pub failed: usize,
+    pub oldest_pending_age_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_event_ids: Option<Vec<String>>,
+    /// Main-channel answers per recent user message
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub answers: Vec<AnswerCount>,
+}
+
+impl DeliveryTracker {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            records: RwLock::new(HashMap::new()),
+            retention: RwLock::new(retention),
+        }
+    }
+
+    /// The tracker shared by every `Chat`
+    pub fn global() -> &'static DeliveryTracker {
+        &DELIVERY_TRACKER
+    }
+
+    pub fn set_retention(&self, retention: Duration) {
+        if let Ok(mut guard) = self.retention.write() {
+            *guard = retention;
+        }
+    }
+
+    /// Starts tracking an event that is about to be published
+    pub fn track(&self, event_id: EventId, recipient: PublicKey, channel: &str) {
+        self.prune(Timestamp::now());
+        if let Ok(mut records) = self.records.write() {
+            records.insert(
+                event_id,
+                DeliveryRecord {
+                    event_id,
+                    recipient,
+                    channel: channel.to_string(),
+                    sent_at: Timestamp::now(),
+                    acked_by: Vec::new(),
+                    rejected_by: HashMap::new(),
+                    state: DeliveryState::Pending,
+                },
+            );
+        }
+    }
+
+    /// Records the relay answers for a publish attempt and returns the resulting state
+    pub fn record_output(&self, output: &Output<EventId>) -> Option<DeliveryState> {
+        let mut records = self.records.write().ok()?;
+        let record = records.get_mut(output.id())?;
+
+        for relay in &output.success {
+            if !record.acked_by.contains(relay) {
+                record.acked_by.push(relay.clone());
+            }
+            record.rejected_by.remove(relay);
+        }
+        for (relay, reason) in &output.failed {
+            if !record.acked_by.contains(relay) {
+                record.rejected_by.insert(relay.clone(), reason.clone());
+            }
+        }
+        record.state = if record.acked_by.is_empty() {
+            DeliveryState::Failed
+        } else {
+            DeliveryState::Acknowledged
+        };
+        Some(record.state)
+    }
+
+    /// Marks an event failed when publishing errored before any relay answered
+    pub fn record_error(&self, event_id: &EventId) {
+        if let Ok(mut records) = self.records.write() {
+            if let Some(record) = records.get_mut(event_id) {
+                if record.state == DeliveryState::Pending {
+                    record.state = DeliveryState::Failed;
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, event_id: &EventId) -> Option<DeliveryRecord> {
+        self.records.read().ok()?.get(event_id).cloned()
+    }
+
+    pub fn summary(&self, list_pending: bool) -> DeliverySummary {
+        let now = Timestamp::now();
+        self.prune(now);
+        let Ok(records) = self.records.read() else {
+            return DeliverySummary::default();
+        };
+
+        let mut summary = DeliverySummary::default();
+        let mut pending: Vec<&DeliveryRecord> = Vec::new();
+        for record in records.values() {
+            match record.state {
+                DeliveryState::Pending => pending.push(record),
+                DeliveryState::Acknowledged => summary.acknowledged += 1,
+                DeliveryState::Failed => summary.failed += 1,
+            }
+        }
+        pending.sort_by_key(|r| r.sent_at);
+
+        summary.pending = pending.len();
+        summary.oldest_pending_age_secs = pending
+            .first()
+            .map(|r| now.as_u64().saturating_sub(r.sent_at.as_u64()));
+        if list_pending {
+            summary.pending_event_ids = Some(pending.iter().map(|r| r.event_id.to_hex()).collect());
+        }
+        summary
+    }
+
+    /// Forgets settled events older than the retention; pending ones are kept until they settle
+    fn prune(&self, now: Timestamp) {
+        let retention = self
+            .retention
+            .read()
+            .map(|r| r.as_secs())
+            .unwrap_or(DEFAULT_DELIVERY_RETENTION.as_secs());
+        let cutoff = now.as_u64().saturating_sub(retention);
+        if let Ok(mut records) = self.records.write() {
+            records
+                .retain(|_, r| r.state == DeliveryState::Pending || r.sent_at.as_u64() >= cutoff);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct DeliveryStatusRequest {
+    #[serde(default)]
+    #[schemars(
+        description = "Also list the ids of events still waiting for a relay acknowledgement"
+    )]
+    pub list_pending: bool,
+}
+
 pub fn create_response_reminder() -> String {
     "🚨 CRITICAL MANDATORY WORKFLOW - NO EXCEPTIONS:\n\
     \n\
@@ -90,3 +459,124 @@ pub fn create_response_reminder() -> String {
     \n\
     ⚠️ This applies to EVERY response: simple answers, complex operations, errors, confirmations - ALL must follow this pattern.".to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn output(id: EventId, success: &[&str], failed: &[&str]) -> Output<EventId> {
+        Output {
+            val: id,
+            success: success
+                .iter()
+                .map(|u| RelayUrl::parse(u).unwrap())
+                .collect::<HashSet<_>>(),
+            failed: failed
+                .iter()
+                .map(|u| (RelayUrl::parse(u).unwrap(), "blocked".to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_delivery_states() {
+        let tracker = DeliveryTracker::new(DEFAULT_DELIVERY_RETENTION);
+        let recipient = Keys::generate().public_key();
+        let acked = EventId::all_zeros();
+        let failed = EventId::from_byte_array([1; 32]);
+        let pending = EventId::from_byte_array([2; 32]);
+        for id in [acked, failed, pending] {
+            tracker.track(id, recipient, "main");
+        }
+
+        assert_eq!(
+            tracker.record_output(&output(acked, &["wss://a.example"], &["wss://b.example"])),
+            Some(DeliveryState::Acknowledged)
+        );
+        assert_eq!(
+            tracker.record_output(&output(failed, &[], &["wss://a.example"])),
+            Some(DeliveryState::Failed)
+        );
+
+        let summary = tracker.summary(true);
+        assert_eq!(summary.acknowledged, 1);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.pending, 1);
+        assert_eq!(summary.pending_event_ids, Some(vec![pending.to_hex()]));
+        assert!(tracker.summary(false).pending_event_ids.is_none());
+    }
+
+    #[test]
+    fn test_settled_deliveries_expire() {
+        let tracker = DeliveryTracker::new(Duration::from_secs(60));
+        let recipient = Keys::generate().public_key();
+        let settled = EventId::all_zeros();
+        let pending = EventId::from_byte_array([1; 32]);
+        tracker.track(settled, recipient, "progress");
+        tracker.track(pending, recipient, "progress");
+        tracker.record_output(&output(settled, &["wss://a.example"], &[]));
+
+        tracker.prune(Timestamp::now() + 120);
+        assert!(tracker.get(&settled).is_none());
+        assert!(tracker.get(&pending).is_some());
+    }
+
+    #[test]
+    fn test_one_answer_per_message() {
+        let ledger = AnswerLedger::new(ResponsePolicy::Strict);
+        let question = EventId::all_zeros();
+        // Nothing asked yet, nothing to enforce
+        assert_eq!(ledger.claim(Some(question)), Answer::First);
+
+        let sender = Keys::generate().public_key();
+        ledger.begin(question, sender);
+        assert_eq!(ledger.current(), Some(question));
+        assert_eq!(ledger.claim(Some(question)), Answer::First);
+        assert_eq!(ledger.claim(Some(question)), Answer::Reroute(1));
+        assert_eq!(ledger.claim(None), Answer::First);
+
+        // A failed first send doesn't use up the answer
+        let next = EventId::from_byte_array([1; 32]);
+        ledger.begin(next, sender);
+        assert_eq!(ledger.claim(Some(next)), Answer::First);
+        ledger.release(Some(next));
+        assert_eq!(ledger.claim(Some(next)), Answer::First);
+
+        ledger.set_policy(ResponsePolicy::Warn);
+        assert_eq!(ledger.claim(Some(next)), Answer::Again(1));
+        ledger.set_policy(ResponsePolicy::Off);
+        assert_eq!(ledger.claim(Some(next)), Answer::First);
+
+        let counts = ledger.counts();
+        assert_eq!((counts[0].sent, counts[0].rerouted), (1, 1));
+        assert_eq!((counts[1].sent, counts[1].rerouted), (3, 0));
+    }
+
+    #[test]
+    fn test_current_message_per_sender() {
+        let ledger = AnswerLedger::new(ResponsePolicy::Warn);
+        let (alice, bob) = (Keys::generate().public_key(), Keys::generate().public_key());
+        let from_alice = EventId::all_zeros();
+        let from_bob = EventId::from_byte_array([1; 32]);
+        ledger.begin(from_alice, alice);
+        ledger.begin(from_bob, bob);
+
+        assert_eq!(ledger.current(), Some(from_bob));
+        assert_eq!(ledger.current_sender(), Some(bob));
+        assert_eq!(ledger.current_from(&alice), Some(from_alice));
+        assert_eq!(ledger.sender_of(from_alice), Some(alice));
+        assert_eq!(ledger.counts()[1].sender, bob.to_hex());
+    }
+
+    #[test]
+    fn test_responses_are_tracked_per_sender() {
+        let tracker = ResponseTracker::new();
+        let (alice, bob) = (Keys::generate().public_key(), Keys::generate().public_key());
+        tracker.start_conversation(alice);
+        tracker.start_conversation(bob);
+        tracker.mark_response_sent(bob);
+        assert!(!tracker.has_sent_final_response(alice));
+        assert!(tracker.has_sent_final_response(bob));
+    }
+}
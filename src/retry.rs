@@ -0,0 +1,339 @@
+//! Retries with exponential backoff, shared by the subsystems that talk to something flaky
+//!
+//! Each subsystem (`goose`, `relay`, `resend`, `searxng`) has a built-in policy. The `[retry]`
+//! config table overrides fields for all of them and `[retry.<subsystem>]` for one. Every failed
+//! attempt is logged under the `nparrot::retry` target as `key=value` fields and counted in
+//! `nparrot_retries_total`.
+
+use crate::config::RetryConfig;
+use crate::metrics;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Goose command runs
+pub const GOOSE: &str = "goose";
+/// Publishing a message to the relays
+pub const RELAY: &str = "relay";
+/// The background queue resending events no relay took
+pub const RESEND: &str = "resend";
+/// SearXNG search requests
+pub const SEARXNG: &str = "searxng";
+
+pub const SUBSYSTEMS: &[&str] = &[GOOSE, RELAY, RESEND, SEARXNG];
+
+lazy_static::lazy_static! {
+    static ref POLICIES: RwLock<HashMap<&'static str, RetryPolicy>> = RwLock::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included
+    pub max_attempts: u32,
+    /// Delay before the first retry
+    pub base_delay: Duration,
+    /// Factor the delay grows by with each further retry
+    pub multiplier: f64,
+    /// Longest delay between two attempts
+    pub max_delay: Duration,
+    /// Fraction of the delay added or taken away at random, 0 to 1
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// The built-in policy of `subsystem`
+    pub fn default_for(subsystem: &str) -> Self {
+        let policy = Self {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(60),
+            jitter: 0.2,
+        };
+        match subsystem {
+            GOOSE => Self {
+                base_delay: Duration::from_secs(5),
+                multiplier: 1.0,
+                ..policy
+            },
+            RESEND => Self {
+                base_delay: Duration::from_secs(30),
+                max_delay: Duration::from_secs(3600),
+                jitter: 0.0,
+                ..policy
+            },
+            SEARXNG => Self {
+                base_delay: Duration::from_millis(500),
+                ..policy
+            },
+            _ => policy,
+        }
+    }
+
+    /// `self` with the fields `overrides` sets replaced
+    fn with(self, overrides: &RetryConfig) -> Self {
+        Self {
+            max_attempts: overrides.max_attempts.unwrap_or(self.max_attempts),
+            base_delay: overrides.base_delay.unwrap_or(self.base_delay),
+            multiplier: overrides.multiplier.unwrap_or(self.multiplier),
+            max_delay: overrides.max_delay.unwrap_or(self.max_delay),
+            jitter: overrides
+                .jitter_percent
+                .map_or(self.jitter, |percent| f64::from(percent.min(100)) / 100.0),
+        }
+    }
+
+    /// Delay before retry number `retry` (1-based), without jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(retry.saturating_sub(1).min(64) as i32);
+        self.base_delay.mul_f64(factor).min(self.max_delay)
+    }
+
+    /// Delay before retry number `retry` (1-based), jittered
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+        backoff.mul_f64(factor).min(self.max_delay)
+    }
+
+    /// Runs `attempt` (given the 1-based attempt number) until it succeeds, fails with an error
+    /// `retryable` rejects, or the attempts run out; returns the last result
+    pub async fn run<T, E, F, Fut>(
+        &self,
+        subsystem: &str,
+        mut attempt: F,
+        retryable: impl Fn(&E) -> bool,
+    ) -> Result<T, E>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Display,
+    {
+        let max_attempts = self.max_attempts.max(1);
+        let mut number = 1;
+        loop {
+            let error = match attempt(number).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if !retryable(&error) {
+                record(subsystem, number, max_attempts, Outcome::Fatal, &error);
+                return Err(error);
+            }
+            if number >= max_attempts {
+                record(subsystem, number, max_attempts, Outcome::Exhausted, &error);
+                return Err(error);
+            }
+            let delay = self.delay(number);
+            record(
+                subsystem,
+                number,
+                max_attempts,
+                Outcome::Retry(delay),
+                &error,
+            );
+            tokio::time::sleep(delay).await;
+            number += 1;
+        }
+    }
+}
+
+/// What happens after a failed attempt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// Another attempt follows after the delay
+    Retry(Duration),
+    /// That was the last attempt
+    Exhausted,
+    /// The error is not worth retrying
+    Fatal,
+}
+
+impl Outcome {
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Retry(_) => "retry",
+            Outcome::Exhausted => "exhausted",
+            Outcome::Fatal => "fatal",
+        }
+    }
+}
+
+/// Logs and counts a failed attempt
+pub fn record(
+    subsystem: &str,
+    attempt: u32,
+    max_attempts: u32,
+    outcome: Outcome,
+    error: &dyn Display,
+) {
+    metrics::retry(subsystem, outcome.label());
+    match outcome {
+        Outcome::Retry(delay) => log::warn!(
+            target: "nparrot::retry",
+            "subsystem={} attempt={} max_attempts={} outcome=retry delay_ms={} error=\"{}\"",
+            subsystem,
+            attempt,
+            max_attempts,
+            delay.as_millis(),
+            error
+        ),
+        outcome => log::error!(
+            target: "nparrot::retry",
+            "subsystem={} attempt={} max_attempts={} outcome={} error=\"{}\"",
+            subsystem,
+            attempt,
+            max_attempts,
+            outcome.label(),
+            error
+        ),
+    }
+}
+
+/// Builds every subsystem's policy from the `[retry]` tables; `""` holds the shared overrides
+pub fn configure(tables: &HashMap<String, RetryConfig>) {
+    let shared = tables.get("").cloned().unwrap_or_default();
+    let policies = SUBSYSTEMS
+        .iter()
+        .map(|&subsystem| {
+            let mut policy = RetryPolicy::default_for(subsystem).with(&shared);
+            if let Some(own) = tables.get(subsystem) {
+                policy = policy.with(own);
+            }
+            (subsystem, policy)
+        })
+        .collect();
+    if let Ok(mut guard) = POLICIES.write() {
+        *guard = policies;
+    }
+}
+
+/// The configured policy of `subsystem`, or its built-in one
+pub fn policy(subsystem: &str) -> RetryPolicy {
+    POLICIES
+        .read()
+        .ok()
+        .and_then(|policies| policies.get(subsystem).cloned())
+        .unwrap_or_else(|| RetryPolicy::default_for(subsystem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_jittered_backoff_stays_in_bounds() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(10),
+            jitter: 0.25,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(9), Duration::from_secs(10));
+
+        for _ in 0..200 {
+            let delay = policy.delay(3);
+            assert!(delay >= Duration::from_secs(3), "{:?}", delay);
+            assert!(delay <= Duration::from_secs(5), "{:?}", delay);
+            // Jitter never pushes past the cap
+            assert!(policy.delay(9) <= Duration::from_secs(10));
+        }
+
+        let flat = RetryPolicy {
+            jitter: 0.0,
+            ..policy
+        };
+        assert_eq!(flat.delay(2), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_non_retryable_errors_stop_immediately() {
+        let policy = RetryPolicy::default_for(RELAY);
+        let attempts = AtomicU32::new(0);
+        let started = tokio::time::Instant::now();
+        let result: Result<(), String> = policy
+            .run(
+                RELAY,
+                |_| async {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    Err("invalid".to_string())
+                },
+                |_| false,
+            )
+            .await;
+        assert_eq!(result, Err("invalid".to_string()));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_until_success_or_exhaustion() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default_for(RELAY)
+        };
+        let started = tokio::time::Instant::now();
+        let result: Result<u32, String> = policy
+            .run(
+                RELAY,
+                |attempt| async move {
+                    if attempt < 3 {
+                        Err("busy".to_string())
+                    } else {
+                        Ok(attempt)
+                    }
+                },
+                |_| true,
+            )
+            .await;
+        assert_eq!(result, Ok(3));
+        // 1s, then 2s
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), String> = policy
+            .run(
+                RELAY,
+                |_| async {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    Err("down".to_string())
+                },
+                |_| true,
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), policy.max_attempts);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let shared = RetryConfig {
+            max_attempts: Some(5),
+            jitter_percent: Some(10),
+            ..Default::default()
+        };
+        let goose = RetryConfig {
+            base_delay: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        let policy = RetryPolicy::default_for(GOOSE).with(&shared).with(&goose);
+        assert_eq!(policy.max_attempts, 5);
+        assert_eq!(policy.base_delay, Duration::from_secs(2));
+        assert_eq!(policy.multiplier, 1.0);
+        assert_eq!(policy.jitter, 0.1);
+    }
+}
@@ -1,9 +1,8 @@
+use crate::mcp::server_common::ServerInfoBuilder;
 use crate::utils::wait_for_message;
 use nostr_sdk::prelude::*;
 use rmcp::{
-    model::{
-        CallToolResult, Content, Implementation, ProtocolVersion, ServerCapabilities, ServerInfo,
-    },
+    model::{CallToolResult, Content, ServerInfo},
     schemars, tool, Error as RmcpError, ServerHandler,
 };
 
@@ -55,13 +54,8 @@ impl Chat {
 #[tool(tool_box)]
 impl ServerHandler for Chat {
     fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            protocol_version: ProtocolVersion::V_2024_11_05,
-            capabilities: ServerCapabilities::builder()
-                .enable_tools()
-                .build(),
-            server_info: Implementation::from_build_env(),
-            instructions: Some("This server provides tools for talking to a specific user over the Nostr protocol via encrypted DMs.".to_string()),
-        }
+        ServerInfoBuilder::new()
+            .instructions("This server provides tools for talking to a specific user over the Nostr protocol via encrypted DMs.")
+            .build()
     }
 }
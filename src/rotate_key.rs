@@ -0,0 +1,581 @@
+//! `nparrot rotate-key --new-nsec <nsec>`: moves nparrot to a new identity
+//!
+//! The steps run in order: a last DM from the old key telling the user the new npub, the
+//! memories re-published under the new key, the data files (notes, events, transcripts)
+//! re-encrypted with the key derived from the new nsec, the old profile published from the new
+//! key and, with `--update-config`, the nsec replaced in the config and `.env` files.
+//!
+//! Relays can fail midway, so what is finished is saved to `rotate-key.json` in the data dir
+//! after every step and every memory; running the same command again skips it. `--dry-run`
+//! lists what each remaining step would migrate and changes nothing.
+
+use crate::at_rest::{DataKey, Vault};
+use crate::config::ProfileConfig;
+use crate::envelope::{self, MessageType};
+use crate::mcp::notebook;
+#[cfg(feature = "memory")]
+use crate::nostr_mcp::client::NostrMemoryClient;
+use crate::profile::AgentProfile;
+use crate::progress_history;
+use crate::transcript;
+use crate::transport::SharedTransport;
+use crate::utils::send_private_msg;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const STATE_FILE: &str = "rotate-key.json";
+const PROFILE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    Announce,
+    Memories,
+    DataFiles,
+    Profile,
+    Config,
+}
+
+impl Step {
+    pub const ALL: [Step; 5] = [
+        Step::Announce,
+        Step::Memories,
+        Step::DataFiles,
+        Step::Profile,
+        Step::Config,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Announce => "announce",
+            Self::Memories => "memories",
+            Self::DataFiles => "data files",
+            Self::Profile => "profile",
+            Self::Config => "config",
+        }
+    }
+}
+
+/// What earlier runs of a rotation to `new_pubkey` finished
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub new_pubkey: String,
+    #[serde(default)]
+    pub done: Vec<Step>,
+    /// Memories already stored under the new key
+    #[serde(default)]
+    pub migrated_memories: Vec<uuid::Uuid>,
+}
+
+impl State {
+    /// The saved state of the rotation to `new_pubkey`; a rotation to another key starts over
+    pub fn load(path: &Path, new_pubkey: &PublicKey) -> Self {
+        let fresh = Self {
+            new_pubkey: new_pubkey.to_hex(),
+            ..Self::default()
+        };
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return fresh;
+        };
+        match serde_json::from_str::<Self>(&text) {
+            Ok(state) if state.new_pubkey == fresh.new_pubkey => state,
+            Ok(state) => {
+                log::warn!(
+                    "{} belongs to a rotation to {}, starting over",
+                    path.display(),
+                    state.new_pubkey
+                );
+                fresh
+            }
+            Err(e) => {
+                log::warn!("Ignoring unreadable {}: {}", path.display(), e);
+                fresh
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let partial = path.with_extension("json.partial");
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(&partial, json)
+            .and_then(|_| std::fs::rename(&partial, path))
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+    }
+
+    pub fn is_done(&self, step: Step) -> bool {
+        self.done.contains(&step)
+    }
+
+    fn finish(&mut self, step: Step) {
+        if !self.is_done(step) {
+            self.done.push(step);
+        }
+    }
+}
+
+/// Everything a rotation touches
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    /// Relays signing as the old identity
+    pub old: SharedTransport,
+    /// Relays signing as the new identity
+    pub new: SharedTransport,
+    pub old_keys: Keys,
+    pub new_keys: Keys,
+    pub target: PublicKey,
+    pub data_dir: PathBuf,
+    /// Whether `NPARROT_DATA_KEY` is set, so the data files don't depend on the nsec
+    pub explicit_data_key: bool,
+    /// `[profiles.main]`, for a new profile when the old key never published one
+    pub profile_overrides: Option<ProfileConfig>,
+    /// Files that may hold the nsec: the config file and `.env`
+    pub config_files: Vec<PathBuf>,
+    pub update_config: bool,
+}
+
+impl Rotation {
+    /// Runs (or with `dry_run`, describes) every step not finished yet, printing one line per
+    /// step; stops at the first failure, which the next run retries
+    pub async fn run(&self, dry_run: bool) -> Result<(), String> {
+        let state_path = self.data_dir.join(STATE_FILE);
+        let mut state = State::load(&state_path, &self.new_keys.public_key());
+        if !dry_run {
+            std::fs::create_dir_all(&self.data_dir).map_err(|e| e.to_string())?;
+        }
+        for step in Step::ALL {
+            if state.is_done(step) {
+                println!("✅ {}: done in an earlier run", step.as_str());
+                continue;
+            }
+            let outcome = match step {
+                Step::Announce => self.announce(dry_run).await,
+                Step::Memories => self.memories(dry_run, &mut state, &state_path).await,
+                Step::DataFiles => self.data_files(dry_run),
+                Step::Profile => self.profile(dry_run).await,
+                Step::Config => self.config(dry_run),
+            };
+            match outcome {
+                Ok(report) if dry_run => println!("📋 {}: {}", step.as_str(), report),
+                Ok(report) => {
+                    println!("✅ {}: {}", step.as_str(), report);
+                    state.finish(step);
+                    state.save(&state_path)?;
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "{} failed: {}; run the command again to resume",
+                        step.as_str(),
+                        e
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn new_npub(&self) -> String {
+        self.new_keys
+            .public_key()
+            .to_bech32()
+            .expect("a public key always encodes")
+    }
+
+    pub fn announcement(&self) -> String {
+        format!(
+            "🔑 This key is being retired. From now on I am {}; please send your messages there.",
+            self.new_npub()
+        )
+    }
+
+    async fn announce(&self, dry_run: bool) -> Result<String, String> {
+        if dry_run {
+            return Ok(format!(
+                "would send from the old key to {}: {}",
+                self.target.to_hex(),
+                self.announcement()
+            ));
+        }
+        let message = envelope::wrap(MessageType::Chat, self.announcement());
+        send_private_msg(self.old.as_ref(), self.target, message, None)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(format!(
+            "told {} about {}",
+            self.target.to_hex(),
+            self.new_npub()
+        ))
+    }
+
+    #[cfg(feature = "memory")]
+    async fn memories(
+        &self,
+        dry_run: bool,
+        state: &mut State,
+        state_path: &Path,
+    ) -> Result<String, String> {
+        let old = NostrMemoryClient::new(
+            self.old.clone(),
+            self.old_keys.clone(),
+            self.old_keys.public_key(),
+        );
+        let memories = old.export_memories().await.map_err(|e| e.to_string())?;
+        let pending: Vec<_> = memories
+            .iter()
+            .filter(|memory| !state.migrated_memories.contains(&memory.id))
+            .collect();
+        if dry_run {
+            let mut report = format!(
+                "would re-publish {} of {} memories under the new key",
+                pending.len(),
+                memories.len()
+            );
+            for memory in &pending {
+                report.push_str(&format!("\n  {} {}", memory.id, memory.content.title));
+            }
+            return Ok(report);
+        }
+
+        let new = NostrMemoryClient::new(
+            self.new.clone(),
+            self.new_keys.clone(),
+            self.new_keys.public_key(),
+        );
+        for memory in &pending {
+            new.store_memory(memory)
+                .await
+                .map_err(|e| format!("memory {}: {}", memory.id, e))?;
+            state.migrated_memories.push(memory.id);
+            state.save(state_path)?;
+        }
+        Ok(format!(
+            "re-published {} memories ({} in total)",
+            pending.len(),
+            state.migrated_memories.len()
+        ))
+    }
+
+    #[cfg(not(feature = "memory"))]
+    async fn memories(
+        &self,
+        _dry_run: bool,
+        _state: &mut State,
+        _state_path: &Path,
+    ) -> Result<String, String> {
+        Ok("nothing to do, built without the memory feature".to_string())
+    }
+
+    fn data_files(&self, dry_run: bool) -> Result<String, String> {
+        if self.explicit_data_key {
+            return Ok("nothing to do, NPARROT_DATA_KEY does not depend on the nsec".to_string());
+        }
+        let paths: Vec<PathBuf> = [
+            notebook::NOTES_FILE,
+            notebook::EVENTS_FILE,
+            progress_history::FILE,
+        ]
+        .iter()
+        .map(|file| self.data_dir.join(file))
+        .filter(|path| path.exists())
+        .collect();
+        let data_dir = self.data_dir.to_string_lossy();
+        let transcripts = transcript::files(&data_dir).map_err(|e| e.to_string())?;
+        if dry_run {
+            let mut report = format!(
+                "would re-encrypt {} store file(s) and {} transcript file(s) with the new key",
+                paths.len(),
+                transcripts.len()
+            );
+            for path in paths.iter().chain(&transcripts) {
+                report.push_str(&format!("\n  {}", path.display()));
+            }
+            return Ok(report);
+        }
+
+        let new_key = DataKey::derive(self.new_keys.secret_key().as_secret_bytes());
+        // A run that stopped after the switch finds the files already readable with the new key
+        let new_vault = Vault::new(Some(new_key.clone()));
+        let (rotated, resealed) = if paths.iter().all(|path| new_vault.read(path).is_ok()) {
+            let resealed = transcript::reseal(&data_dir, Vault::global(), &new_key)?;
+            Vault::global().set_key(Some(new_key));
+            (0, resealed)
+        } else {
+            transcript::rotate_with_stores(&data_dir, Vault::global(), &paths, new_key)?
+        };
+        Ok(format!(
+            "re-encrypted {} store file(s) and {} transcript line(s)",
+            rotated, resealed
+        ))
+    }
+
+    /// The old key's published profile, or the configured main profile if it has none
+    async fn profile_to_publish(&self) -> Result<(Metadata, bool), String> {
+        let filter = Filter::new()
+            .author(self.old_keys.public_key())
+            .kind(Kind::Metadata)
+            .limit(1);
+        let events = self
+            .old
+            .fetch_events(filter, PROFILE_TIMEOUT)
+            .await
+            .map_err(|e| e.to_string())?;
+        let published = events
+            .into_iter()
+            .max_by_key(|event| event.created_at)
+            .and_then(|event| Metadata::from_json(&event.content).ok());
+        Ok(match published {
+            Some(metadata) => (metadata, true),
+            None => (
+                AgentProfile::main_orchestrator()
+                    .with_overrides(self.profile_overrides.as_ref())
+                    .to_metadata(),
+                false,
+            ),
+        })
+    }
+
+    async fn profile(&self, dry_run: bool) -> Result<String, String> {
+        let (metadata, copied) = self.profile_to_publish().await?;
+        let name = metadata
+            .display_name
+            .clone()
+            .or_else(|| metadata.name.clone())
+            .unwrap_or_default();
+        let source = if copied {
+            "the old key's profile"
+        } else {
+            "the configured main profile"
+        };
+        if dry_run {
+            return Ok(format!(
+                "would publish {} ({}) from the new key",
+                source, name
+            ));
+        }
+        let event = self
+            .new
+            .sign_event(EventBuilder::metadata(&metadata))
+            .await
+            .map_err(|e| e.to_string())?;
+        self.new
+            .send_event(&event)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(format!("published {} ({}) from the new key", source, name))
+    }
+
+    fn config(&self, dry_run: bool) -> Result<String, String> {
+        let nsec = self
+            .new_keys
+            .secret_key()
+            .to_bech32()
+            .map_err(|e| e.to_string())?;
+        let mut rewrites = Vec::new();
+        for path in &self.config_files {
+            let Ok(text) = std::fs::read_to_string(path) else {
+                continue;
+            };
+            let is_env = path.file_name().is_some_and(|name| name == ".env");
+            let rewritten = if is_env {
+                replace_env_nsec(&text, &nsec)
+            } else {
+                replace_config_nsec(&text, &nsec)
+            };
+            if let Some(rewritten) = rewritten {
+                rewrites.push((path, rewritten));
+            }
+        }
+        if rewrites.is_empty() {
+            return Ok(
+                "no config or .env file holds the nsec; set NSEC to the new one".to_string(),
+            );
+        }
+        let names: Vec<String> = rewrites
+            .iter()
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        if !self.update_config {
+            return Ok(format!(
+                "left {} alone; pass --update-config to replace the nsec there",
+                names.join(", ")
+            ));
+        }
+        if dry_run {
+            return Ok(format!("would replace the nsec in {}", names.join(", ")));
+        }
+        for (path, rewritten) in rewrites {
+            let partial = path.with_extension("nparrot-rotating");
+            std::fs::write(&partial, rewritten)
+                .and_then(|_| std::fs::rename(&partial, path))
+                .map_err(|e| format!("Failed to update {}: {}", path.display(), e))?;
+        }
+        Ok(format!("replaced the nsec in {}", names.join(", ")))
+    }
+}
+
+/// `text` with `rewrite` applied to every line, or `None` if it changed none
+fn rewrite_lines(text: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> Option<String> {
+    let mut changed = false;
+    let mut out: Vec<String> = Vec::new();
+    for line in text.split('\n') {
+        match rewrite(line) {
+            Some(new_line) => {
+                changed = true;
+                out.push(new_line);
+            }
+            None => out.push(line.to_string()),
+        }
+    }
+    changed.then(|| out.join("\n"))
+}
+
+/// The config file with `nsec` in `[identity]` set to `nsec`
+fn replace_config_nsec(text: &str, nsec: &str) -> Option<String> {
+    let mut section = String::new();
+    rewrite_lines(text, |line| {
+        let trimmed = line.trim();
+        if let Some(header) = trimmed.strip_prefix('[') {
+            section = header.trim_end_matches(']').trim().to_string();
+            return None;
+        }
+        let value = trimmed
+            .strip_prefix("nsec")?
+            .trim_start()
+            .strip_prefix('=')?;
+        if section != "identity" || value.trim().is_empty() {
+            return None;
+        }
+        let indent = &line[..line.len() - line.trim_start().len()];
+        Some(format!("{}nsec = \"{}\"", indent, nsec))
+    })
+}
+
+/// The `.env` file with `NSEC=` set to `nsec`
+fn replace_env_nsec(text: &str, nsec: &str) -> Option<String> {
+    rewrite_lines(text, |line| {
+        let trimmed = line.trim_start();
+        let (export, rest) = match trimmed.strip_prefix("export ") {
+            Some(rest) => ("export ", rest.trim_start()),
+            None => ("", trimmed),
+        };
+        rest.strip_prefix("NSEC=")?;
+        Some(format!("{}NSEC={}", export, nsec))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "memory")]
+    use crate::nostr_mcp::types::MemoryEntry;
+    use crate::transport::fake::FakeTransport;
+    use std::sync::Arc;
+
+    #[cfg(feature = "memory")]
+    fn memory(title: &str) -> MemoryEntry {
+        MemoryEntry::new(
+            "fact".to_string(),
+            None,
+            title.to_string(),
+            format!("{} details", title),
+            Vec::new(),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_config_and_env_rewrites() {
+        let config = "data_dir = \"data\"\n\n[identity]\nnsec = \"nsec1old\"\ntarget_pubkey = \"npub1x\"\n\n[goose]\nnsec = \"unrelated\"\n";
+        assert_eq!(
+            replace_config_nsec(config, "nsec1new").unwrap(),
+            "data_dir = \"data\"\n\n[identity]\nnsec = \"nsec1new\"\ntarget_pubkey = \"npub1x\"\n\n[goose]\nnsec = \"unrelated\"\n"
+        );
+        assert_eq!(
+            replace_config_nsec("[identity]\nprogress_nsec = \"x\"\n", "n"),
+            None
+        );
+
+        let env = "RUST_LOG=info\nexport NSEC=nsec1old\nPROGRESS_NSEC=nsec1other\n";
+        assert_eq!(
+            replace_env_nsec(env, "nsec1new").unwrap(),
+            "RUST_LOG=info\nexport NSEC=nsec1new\nPROGRESS_NSEC=nsec1other\n"
+        );
+        assert_eq!(replace_env_nsec("RUST_LOG=info\n", "n"), None);
+    }
+
+    #[test]
+    fn test_state_belongs_to_one_new_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(STATE_FILE);
+        let key = Keys::generate().public_key();
+        let mut state = State::load(&path, &key);
+        state.finish(Step::Announce);
+        state.finish(Step::Announce);
+        state.save(&path).unwrap();
+
+        let loaded = State::load(&path, &key);
+        assert_eq!(loaded.done, vec![Step::Announce]);
+        assert!(!State::load(&path, &Keys::generate().public_key()).is_done(Step::Announce));
+    }
+
+    #[cfg(feature = "memory")]
+    #[tokio::test]
+    async fn test_memories_resume_after_a_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old_keys, new_keys) = (Keys::generate(), Keys::generate());
+        let old_transport = Arc::new(FakeTransport::new(old_keys.clone()));
+        let new_transport = Arc::new(FakeTransport::new(new_keys.clone()));
+        let writer = NostrMemoryClient::new(
+            old_transport.clone(),
+            old_keys.clone(),
+            old_keys.public_key(),
+        );
+        let (first, second) = (memory("First"), memory("Second"));
+        writer.store_memory(&first).await.unwrap();
+        writer.store_memory(&second).await.unwrap();
+
+        let rotation = Rotation {
+            old: old_transport,
+            new: new_transport.clone(),
+            old_keys,
+            new_keys: new_keys.clone(),
+            target: Keys::generate().public_key(),
+            data_dir: dir.path().to_path_buf(),
+            explicit_data_key: true,
+            profile_overrides: None,
+            config_files: Vec::new(),
+            update_config: false,
+        };
+        let path = dir.path().join(STATE_FILE);
+        let mut state = State::load(&path, &new_keys.public_key());
+
+        // The dry run lists both and sends nothing
+        let report = rotation.memories(true, &mut state, &path).await.unwrap();
+        assert!(report.contains("2 of 2") && report.contains("First"));
+        assert!(new_transport.published().is_empty());
+
+        // An earlier run got the first one across before the relays failed
+        state.migrated_memories.push(first.id);
+        new_transport.fail_sends(true);
+        assert!(rotation.memories(false, &mut state, &path).await.is_err());
+        new_transport.fail_sends(false);
+        rotation.memories(false, &mut state, &path).await.unwrap();
+        assert_eq!(
+            State::load(&path, &new_keys.public_key())
+                .migrated_memories
+                .len(),
+            2
+        );
+
+        let reader = NostrMemoryClient::new(
+            new_transport.clone(),
+            new_keys.clone(),
+            new_keys.public_key(),
+        );
+        let migrated = reader.export_memories().await.unwrap();
+        assert_eq!(migrated.len(), 1);
+        assert_eq!(migrated[0].id, second.id);
+    }
+}
@@ -0,0 +1,456 @@
+//! Messages the agent asked to send later (`send_later`), delivered by the long-running MCP
+//! servers
+//!
+//! The schedule lives in `schedule.json` in the data dir. When a message falls due its gift
+//! wrap is built and saved before it is published, so a restart in between republishes the
+//! same event, which relays and clients see as one message, instead of sending it twice.
+//! Times are stored in UTC; times without an offset are read in the configured time zone.
+
+use crate::config::units::parse_duration_secs;
+use crate::error::NparrotError;
+use crate::mcp::chat::Chat;
+use crate::timezone::{self, Zone};
+use chrono::{DateTime, Duration as TimeDelta, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// How often the schedule is checked for due messages
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Delay before retrying a message no relay took, multiplied by the failed attempts so far
+const RETRY_DELAY_SECS: i64 = 60;
+
+/// How long delivered messages stay listed in the schedule file
+const KEEP_SENT_DAYS: i64 = 7;
+
+lazy_static::lazy_static! {
+    static ref SCHEDULE: RwLock<Option<Arc<Schedule>>> = RwLock::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleState {
+    Pending,
+    Sent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub id: String,
+    pub message: String,
+    pub deliver_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub state: ScheduleState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<DateTime<Utc>>,
+    /// The gift wrap built when the message fell due, republished as is until it is sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    event: Option<Event>,
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_at: Option<DateTime<Utc>>,
+}
+
+impl ScheduledMessage {
+    fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.state == ScheduleState::Pending
+            && self.deliver_at <= now
+            && self.retry_at.is_none_or(|retry_at| retry_at <= now)
+    }
+}
+
+#[derive(Debug)]
+pub struct Schedule {
+    path: PathBuf,
+    entries: Mutex<Vec<ScheduledMessage>>,
+}
+
+/// Loads the schedule from `data_dir` and makes it the one the tools use
+pub fn init(data_dir: &str) -> Arc<Schedule> {
+    let schedule = Arc::new(Schedule::load(Path::new(data_dir).join("schedule.json")));
+    if let Ok(mut guard) = SCHEDULE.write() {
+        *guard = Some(schedule.clone());
+    }
+    schedule
+}
+
+pub fn global() -> Option<Arc<Schedule>> {
+    SCHEDULE.read().ok()?.clone()
+}
+
+impl Schedule {
+    fn load(path: PathBuf) -> Self {
+        let entries: Vec<ScheduledMessage> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable schedule {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        let pending = entries
+            .iter()
+            .filter(|entry| entry.state == ScheduleState::Pending)
+            .count();
+        if pending > 0 {
+            log::info!("Resuming {} scheduled message(s)", pending);
+        }
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Schedules `message` for `deliver_at`, which must be in the future
+    pub fn add(
+        &self,
+        message: String,
+        deliver_at: DateTime<Utc>,
+    ) -> Result<ScheduledMessage, NparrotError> {
+        let now = Utc::now();
+        if deliver_at <= now {
+            return Err(NparrotError::invalid_params(
+                "when",
+                format!("{} is in the past", timezone::format(deliver_at)),
+            ));
+        }
+        let entry = ScheduledMessage {
+            id: uuid::Uuid::new_v4().simple().to_string()[..8].to_string(),
+            message,
+            deliver_at,
+            created_at: now,
+            state: ScheduleState::Pending,
+            sent_at: None,
+            event: None,
+            attempts: 0,
+            retry_at: None,
+        };
+        let mut entries = self.lock()?;
+        entries.push(entry.clone());
+        self.save(&entries);
+        Ok(entry)
+    }
+
+    /// Messages still to be sent, soonest first
+    pub fn pending(&self) -> Vec<ScheduledMessage> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut pending: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.state == ScheduleState::Pending)
+            .cloned()
+            .collect();
+        pending.sort_by_key(|entry| entry.deliver_at);
+        pending
+    }
+
+    /// Removes a pending message, unless it is already on its way
+    pub fn cancel(&self, id: &str) -> Result<ScheduledMessage, NparrotError> {
+        let mut entries = self.lock()?;
+        let Some(position) = entries.iter().position(|entry| entry.id == id.trim()) else {
+            return Err(NparrotError::invalid_params(
+                "id",
+                format!("No scheduled message with id {}", id),
+            ));
+        };
+        let entry = &entries[position];
+        if entry.state == ScheduleState::Sent || entry.event.is_some() {
+            return Err(NparrotError::invalid_params(
+                "id",
+                format!("Message {} has already been sent", id),
+            ));
+        }
+        let entry = entries.remove(position);
+        self.save(&entries);
+        Ok(entry)
+    }
+
+    /// Sends every due message through `chat` once
+    pub async fn deliver_due(&self, chat: &Chat) {
+        let now = Utc::now();
+        let due: Vec<ScheduledMessage> = match self.entries.lock() {
+            Ok(entries) => entries
+                .iter()
+                .filter(|entry| entry.is_due(now))
+                .cloned()
+                .collect(),
+            Err(_) => return,
+        };
+        for entry in due {
+            let event = match entry.event {
+                Some(event) => event,
+                None => match chat.prepare_scheduled(&entry.message).await {
+                    Ok(event) => {
+                        // Saved before publishing, so a restart resends this very event
+                        let stored = self.update(&entry.id, |stored| {
+                            stored.event = Some(event.clone());
+                        });
+                        if !stored {
+                            // Cancelled in the meantime
+                            continue;
+                        }
+                        event
+                    }
+                    Err(e) => {
+                        log::error!("Could not build scheduled message {}: {}", entry.id, e);
+                        self.failed(&entry.id);
+                        continue;
+                    }
+                },
+            };
+            match chat.deliver_scheduled(&entry.message, event).await {
+                Ok(()) => {
+                    log::info!("Delivered scheduled message {}", entry.id);
+                    self.update(&entry.id, |stored| {
+                        stored.state = ScheduleState::Sent;
+                        stored.sent_at = Some(Utc::now());
+                        stored.event = None;
+                        stored.retry_at = None;
+                    });
+                }
+                Err(e) => {
+                    log::warn!("Scheduled message {} not delivered yet: {}", entry.id, e);
+                    self.failed(&entry.id);
+                }
+            }
+        }
+    }
+
+    /// Delivers due messages until the process exits
+    pub async fn run(self: Arc<Self>, chat: Chat) {
+        loop {
+            self.deliver_due(&chat).await;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    fn failed(&self, id: &str) {
+        self.update(id, |stored| {
+            stored.attempts += 1;
+            stored.retry_at =
+                Some(Utc::now() + TimeDelta::seconds(RETRY_DELAY_SECS * stored.attempts as i64));
+        });
+    }
+
+    /// Applies `change` to the entry `id` and saves; false if it is gone
+    fn update(&self, id: &str, change: impl FnOnce(&mut ScheduledMessage)) -> bool {
+        let Ok(mut entries) = self.entries.lock() else {
+            return false;
+        };
+        let Some(entry) = entries.iter_mut().find(|entry| entry.id == id) else {
+            return false;
+        };
+        change(entry);
+        self.save(&entries);
+        true
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Vec<ScheduledMessage>>, NparrotError> {
+        self.entries
+            .lock()
+            .map_err(|_| NparrotError::internal("Schedule lock poisoned"))
+    }
+
+    fn save(&self, entries: &[ScheduledMessage]) {
+        let cutoff = Utc::now() - TimeDelta::days(KEEP_SENT_DAYS);
+        let kept: Vec<&ScheduledMessage> = entries
+            .iter()
+            .filter(|entry| entry.sent_at.is_none_or(|sent_at| sent_at > cutoff))
+            .collect();
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = self.path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(&kept)?)?;
+            std::fs::rename(tmp, &self.path)
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to save schedule: {}", e);
+        }
+    }
+}
+
+/// Parses when to send: a delay ("in 2h", "30m"), an ISO 8601 time with an offset, or a
+/// wall-clock time in `zone` ("2025-07-01 09:00", "tomorrow 9:00", "17:30" for the next one)
+pub fn parse_when(input: &str, now: DateTime<Utc>, zone: &Zone) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+    let lower = input.to_lowercase();
+    let invalid = || {
+        format!(
+            "Invalid time '{}': use e.g. \"in 2h\", \"tomorrow 9:00\", \"17:30\", \"2025-07-01 09:00\" or ISO 8601",
+            input
+        )
+    };
+
+    if let Some(delay) = lower.strip_prefix("in ") {
+        let secs = parse_duration_secs(&delay.replace(' ', ""))?;
+        return Ok(now + TimeDelta::seconds(secs as i64));
+    }
+    if let Ok(secs) = parse_duration_secs(&lower) {
+        // A bare number is ambiguous next to "17:30"; durations need a unit
+        if lower.ends_with(|c: char| c.is_ascii_alphabetic()) {
+            return Ok(now + TimeDelta::seconds(secs as i64));
+        }
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.to_utc());
+    }
+    for format in [
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+    ] {
+        if let Ok(local) = NaiveDateTime::parse_from_str(input, format) {
+            return Ok(zone.to_utc(local));
+        }
+    }
+
+    let today = local_date(now, zone);
+    let (date, time) = match lower.strip_prefix("tomorrow") {
+        Some(rest) => {
+            let rest = rest.trim_start();
+            let rest = rest.strip_prefix("at ").unwrap_or(rest);
+            (Some(today + TimeDelta::days(1)), rest.trim())
+        }
+        None => (None, lower.strip_prefix("at ").unwrap_or(&lower).trim()),
+    };
+    let time = parse_clock(time).ok_or_else(invalid)?;
+    let at = zone.to_utc(date.unwrap_or(today).and_time(time));
+    match date {
+        None if at <= now => Ok(zone.to_utc((today + TimeDelta::days(1)).and_time(time))),
+        _ => Ok(at),
+    }
+}
+
+/// "9", "9:00", "09:30", "9am", "5:30pm"
+fn parse_clock(input: &str) -> Option<NaiveTime> {
+    let (input, offset) = match input.strip_suffix("am") {
+        Some(rest) => (rest.trim(), Some(0)),
+        None => match input.strip_suffix("pm") {
+            Some(rest) => (rest.trim(), Some(12)),
+            None => (input, None),
+        },
+    };
+    let (hour, minute) = match input.split_once(':') {
+        Some((hour, minute)) => (hour.parse::<u32>().ok()?, minute.parse::<u32>().ok()?),
+        None => (input.parse::<u32>().ok()?, 0),
+    };
+    let hour = match offset {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+fn local_date(now: DateTime<Utc>, zone: &Zone) -> NaiveDate {
+    let offset = zone.local_time(now.timestamp()).offset as i64;
+    (now + TimeDelta::seconds(offset)).date_naive()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress_channels::ProgressChannels;
+    use crate::transport::fake::FakeTransport;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_parse_when() {
+        let now = at("2025-07-01T10:00:00Z");
+        let utc = Zone::utc();
+        assert_eq!(
+            parse_when("in 2h", now, &utc).unwrap(),
+            at("2025-07-01T12:00:00Z")
+        );
+        assert_eq!(
+            parse_when("30m", now, &utc).unwrap(),
+            at("2025-07-01T10:30:00Z")
+        );
+        assert_eq!(
+            parse_when("2025-07-03T08:00:00+02:00", now, &utc).unwrap(),
+            at("2025-07-03T06:00:00Z")
+        );
+        assert_eq!(
+            parse_when("2025-07-03 08:00", now, &utc).unwrap(),
+            at("2025-07-03T08:00:00Z")
+        );
+        assert_eq!(
+            parse_when("tomorrow at 9", now, &utc).unwrap(),
+            at("2025-07-02T09:00:00Z")
+        );
+        assert_eq!(
+            parse_when("5:30pm", now, &utc).unwrap(),
+            at("2025-07-01T17:30:00Z")
+        );
+        // Already past today, so tomorrow
+        assert_eq!(
+            parse_when("09:00", now, &utc).unwrap(),
+            at("2025-07-02T09:00:00Z")
+        );
+        for invalid in ["soon", "25:00", "13pm", "90"] {
+            assert!(parse_when(invalid, now, &utc).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_restart_neither_loses_nor_repeats_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schedule.json");
+        let ours = Keys::generate();
+        let transport = FakeTransport::new(ours.clone());
+        let chat = Chat::with_transport(
+            Arc::new(transport.clone()),
+            ProgressChannels::default(),
+            ours.public_key(),
+            Keys::generate().public_key(),
+        );
+
+        let schedule = Schedule::load(path.clone());
+        let later = schedule
+            .add("later".to_string(), Utc::now() + TimeDelta::hours(1))
+            .unwrap();
+        let soon = schedule
+            .add("soon".to_string(), Utc::now() + TimeDelta::seconds(1))
+            .unwrap();
+        let cancelled = schedule
+            .add("never".to_string(), Utc::now() + TimeDelta::seconds(1))
+            .unwrap();
+        schedule.cancel(&cancelled.id).unwrap();
+        assert!(schedule
+            .add("too late".to_string(), Utc::now() - TimeDelta::seconds(1))
+            .is_err());
+
+        // Crash after the due message was built but before it was published
+        let event = chat.prepare_scheduled(&soon.message).await.unwrap();
+        schedule.update(&soon.id, |stored| {
+            stored.deliver_at = Utc::now();
+            stored.event = Some(event.clone());
+        });
+        assert!(schedule.cancel(&soon.id).is_err());
+        drop(schedule);
+
+        let schedule = Schedule::load(path.clone());
+        schedule.deliver_due(&chat).await;
+        assert_eq!(transport.published().len(), 1);
+        assert_eq!(transport.published()[0].id, event.id);
+        let pending = schedule.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, later.id);
+
+        // Nothing is sent twice after another restart
+        drop(schedule);
+        let schedule = Schedule::load(path);
+        schedule.deliver_due(&chat).await;
+        assert_eq!(transport.published().len(), 1);
+    }
+}
@@ -1,5 +1,33 @@
 use super::types::*;
-use std::error::Error;
+use crate::error::NparrotError;
+use crate::retry;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// How long a search request may take unless configured otherwise
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    static ref REQUEST_TIMEOUT: RwLock<Duration> = RwLock::new(DEFAULT_REQUEST_TIMEOUT);
+}
+
+/// Sets how long each request to SearXNG may take (from `--searxng-timeout`)
+pub fn set_request_timeout(timeout: Duration) {
+    if let Ok(mut guard) = REQUEST_TIMEOUT.write() {
+        *guard = timeout;
+    }
+}
+
+fn http_client() -> reqwest::Client {
+    let timeout = REQUEST_TIMEOUT
+        .read()
+        .map(|guard| *guard)
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT);
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .unwrap_or_default()
+}
 
 #[derive(Debug, Clone)]
 pub struct SearXNGClient {
@@ -10,7 +38,7 @@ pub struct SearXNGClient {
 impl SearXNGClient {
     pub fn new(base_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            client: http_client(),
             config: SearXNGConfig {
                 base_url,
                 default_count: 20,
@@ -22,7 +50,7 @@ impl SearXNGClient {
     #[allow(dead_code)] // Future configuration support
     pub fn with_config(config: SearXNGConfig) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            client: http_client(),
             config,
         }
     }
@@ -30,9 +58,12 @@ impl SearXNGClient {
     pub async fn search(
         &self,
         request: SearXNGWebSearchRequest,
-    ) -> Result<SearchResponse, Box<dyn Error + Send + Sync>> {
+    ) -> Result<SearchResponse, NparrotError> {
         if request.query.trim().is_empty() {
-            return Err("Search query cannot be empty".into());
+            return Err(NparrotError::invalid_params(
+                "query",
+                "Search query cannot be empty",
+            ));
         }
 
         let count = request
@@ -50,26 +81,14 @@ impl SearXNGClient {
             ("pageno", page.to_string()),
         ];
 
-        let response = self
-            .client
-            .get(&url)
-            .query(&params)
-            .header("Accept", "application/json")
-            .header("User-Agent", "Mozilla/5.0 (compatible; SearXNG-MCP/1.0)")
-            .send()
+        let json_response = retry::policy(retry::SEARXNG)
+            .run(
+                retry::SEARXNG,
+                |_| self.fetch(&url, &params),
+                NparrotError::retryable,
+            )
             .await?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_body = response
-                .text()
-                .await
-                .unwrap_or_else(|_| "Unable to read error response".to_string());
-            return Err(format!("SearXNG API error {}: {}", status, error_body).into());
-        }
-
-        let json_response: serde_json::Value = response.json().await?;
-
         let results: Vec<SearchResult> = json_response
             .get("results")
             .and_then(|r| r.as_array())
@@ -141,4 +160,47 @@ impl SearXNGClient {
             corrections,
         })
     }
+
+    /// One search request, answered with the JSON body
+    async fn fetch(
+        &self,
+        url: &str,
+        params: &[(&str, String)],
+    ) -> Result<serde_json::Value, NparrotError> {
+        let response = self
+            .client
+            .get(url)
+            .query(params)
+            .header("Accept", "application/json")
+            .header("User-Agent", "Mozilla/5.0 (compatible; SearXNG-MCP/1.0)")
+            .send()
+            .await
+            .map_err(request_error)?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_body = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unable to read error response".to_string());
+            let message = format!("SearXNG API error {}: {}", status, error_body);
+            return Err(if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                NparrotError::rate_limited(message)
+            } else {
+                NparrotError::internal(message)
+            });
+        }
+
+        response.json().await.map_err(request_error)
+    }
+}
+
+fn request_error(e: reqwest::Error) -> NparrotError {
+    if e.is_timeout() {
+        NparrotError::timeout(format!("SearXNG did not answer in time: {}", e))
+    } else if e.is_connect() {
+        NparrotError::backend_missing("searxng", format!("SearXNG is unreachable: {}", e))
+    } else {
+        NparrotError::internal(format!("SearXNG request failed: {}", e))
+    }
 }
@@ -1,11 +1,11 @@
 use super::client::SearXNGClient;
 use super::types::*;
-use crate::mcp::chat::{Chat, ProgressMessageRequest, SendMessageRequest};
+use crate::error::NparrotError;
+use crate::mcp::chat::Chat;
+use crate::mcp::server_common::{reply_and_result, reply_error, with_progress};
+use crate::progress_channels::ProgressChannels;
 use nostr_sdk::prelude::*;
-use rmcp::{
-    model::{CallToolResult, Content},
-    tool, Error as RmcpError,
-};
+use rmcp::{model::CallToolResult, tool, Error as RmcpError};
 
 #[derive(Debug, Clone)]
 pub struct SearXNGServer {
@@ -18,29 +18,44 @@ impl SearXNGServer {
     pub fn new(
         base_url: String,
         nostr_client: Client,
-        progress_client: Option<Client>,
+        progress_clients: ProgressChannels<Client>,
         our_pubkey: PublicKey,
         target_pubkey: PublicKey,
     ) -> Self {
+        Self::with_chat(
+            base_url,
+            Chat::new(nostr_client, progress_clients, our_pubkey, target_pubkey),
+        )
+    }
+
+    /// Builds the server around an existing chat, e.g. one on a shared transport
+    pub fn with_chat(base_url: String, chat: Chat) -> Self {
         Self {
             client: SearXNGClient::new(base_url),
-            chat: Chat::new(nostr_client, progress_client, our_pubkey, target_pubkey),
+            chat,
         }
     }
 
+    /// Runs a search without reporting anything to the user
+    pub async fn search(
+        &self,
+        request: SearXNGWebSearchRequest,
+    ) -> Result<SearchResponse, NparrotError> {
+        self.client.search(request).await
+    }
+
     #[tool(description = "Execute web searches with pagination")]
     pub async fn searxng_web_search(
         &self,
         #[tool(aggr)] request: SearXNGWebSearchRequest,
     ) -> Result<CallToolResult, RmcpError> {
-        let _ = self
-            .chat
-            .progress(ProgressMessageRequest {
-                message: format!("Searching for: {}", request.query),
-            })
-            .await;
-
-        match self.client.search(request).await {
+        match with_progress(
+            &self.chat,
+            format!("Searching for: {}", request.query),
+            self.client.search(request),
+        )
+        .await
+        {
             Ok(response) => {
                 let message = if response.results.is_empty() {
                     format!("🔍 No results found for query: {}", response.query)
@@ -111,24 +126,13 @@ impl SearXNGServer {
                     message
                 };
 
-                let _ = self.chat.send(SendMessageRequest { message }).await;
-
                 let search_summary = format!(
                     "Search completed: {} results found for '{}' (page {})",
                     response.total_results, response.query, response.page
                 );
-                Ok(CallToolResult::success(vec![Content::text(search_summary)]))
-            }
-            Err(e) => {
-                let error_message = format!("❌ Search failed: {}", e);
-                let _ = self
-                    .chat
-                    .send(SendMessageRequest {
-                        message: error_message.clone(),
-                    })
-                    .await;
-                Ok(CallToolResult::error(vec![Content::text(error_message)]))
+                reply_and_result(&self.chat, message, search_summary).await
             }
+            Err(e) => reply_error(&self.chat, format!("❌ Search failed: {}", e), e).await,
         }
     }
 }
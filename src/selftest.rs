@@ -0,0 +1,330 @@
+//! `selftest`: a DM from the main identity to itself, through the relays and back
+//!
+//! The probe is tagged on the inner message, so the inbox and the history drop it before it
+//! reaches the conversation, and its gift wrap expires on the relays after `PROBE_EXPIRY_SECS`.
+//! It arrives on a subscription of its own, so a test during a live conversation neither takes
+//! nor delays the user's messages.
+
+use crate::transport::DmTransport;
+use crate::utils::{is_authentic_dm, IncomingMessage};
+use nostr_sdk::prelude::*;
+use rmcp::schemars;
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Tag on the rumor of every probe, holding its nonce
+pub const PROBE_TAG: &str = "nparrot-selftest";
+pub const DEFAULT_TIMEOUT_SECS: u64 = 15;
+/// How long relays keep a probe
+const PROBE_EXPIRY_SECS: u64 = 600;
+/// How long to watch for a second copy of the probe after the first arrived
+const DEDUP_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default, serde::Deserialize, schemars::JsonSchema)]
+pub struct SelftestRequest {
+    #[serde(default)]
+    #[schemars(description = "Seconds to wait for the message to come back (default 15)")]
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stage {
+    pub name: &'static str,
+    pub ok: bool,
+    pub duration_ms: u64,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayResult {
+    pub url: String,
+    pub accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelftestReport {
+    pub ok: bool,
+    pub nonce: String,
+    pub stages: Vec<Stage>,
+    pub relays: Vec<RelayResult>,
+}
+
+/// Whether `rumor` is a self-test probe, which never belongs in the conversation
+pub fn is_probe(rumor: &UnsignedEvent) -> bool {
+    rumor
+        .tags
+        .iter()
+        .any(|tag| tag.kind() == TagKind::custom(PROBE_TAG))
+}
+
+fn probe_nonce(rumor: &UnsignedEvent) -> Option<&str> {
+    rumor
+        .tags
+        .iter()
+        .find(|tag| tag.kind() == TagKind::custom(PROBE_TAG))
+        .and_then(|tag| tag.content())
+}
+
+fn millis(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+struct Stages(Vec<Stage>);
+
+impl Stages {
+    fn record(&mut self, name: &'static str, started: Instant, result: Result<String, String>) {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        self.0.push(Stage {
+            name,
+            ok,
+            duration_ms: millis(started),
+            detail,
+        });
+    }
+
+    fn finish(self, nonce: String, relays: Vec<RelayResult>) -> SelftestReport {
+        SelftestReport {
+            ok: self.0.iter().all(|stage| stage.ok),
+            nonce,
+            stages: self.0,
+            relays,
+        }
+    }
+}
+
+/// Sends a probe to `our_pubkey` through `client` and waits up to `timeout` for it to return
+pub async fn run<T: DmTransport + ?Sized>(
+    client: &T,
+    our_pubkey: PublicKey,
+    timeout: Duration,
+) -> SelftestReport {
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let body = format!("nparrot self-test {}", nonce);
+    let mut stages = Stages(Vec::new());
+
+    // Subscribed before publishing, so a fast relay can't echo the probe unseen
+    let started = Instant::now();
+    let mut gifts = match client.subscribe_dms(our_pubkey).await {
+        Ok(gifts) => {
+            stages.record("subscribe", started, Ok("listening for DMs".to_string()));
+            gifts
+        }
+        Err(e) => {
+            stages.record("subscribe", started, Err(e.to_string()));
+            return stages.finish(nonce, Vec::new());
+        }
+    };
+
+    let started = Instant::now();
+    let tags = vec![Tag::custom(TagKind::custom(PROBE_TAG), [nonce.clone()])];
+    let event = match client
+        .prepare_private_msg(our_pubkey, body.clone(), Some(PROBE_EXPIRY_SECS), tags)
+        .await
+    {
+        Ok(event) => {
+            stages.record("encrypt", started, Ok(format!("gift wrap {}", event.id)));
+            event
+        }
+        Err(e) => {
+            stages.record("encrypt", started, Err(e.to_string()));
+            return stages.finish(nonce, Vec::new());
+        }
+    };
+
+    let started = Instant::now();
+    let relays = match client.send_event(&event).await {
+        Ok(output) => {
+            let mut relays: Vec<RelayResult> = output
+                .success
+                .iter()
+                .map(|url| RelayResult {
+                    url: url.to_string(),
+                    accepted: true,
+                    error: None,
+                })
+                .chain(output.failed.iter().map(|(url, error)| RelayResult {
+                    url: url.to_string(),
+                    accepted: false,
+                    error: Some(error.clone()),
+                }))
+                .collect();
+            relays.sort_by(|a, b| a.url.cmp(&b.url));
+            let accepted = relays.iter().filter(|relay| relay.accepted).count();
+            let result = format!("{} of {} relay(s) accepted", accepted, relays.len());
+            stages.record(
+                "publish",
+                started,
+                if accepted > 0 {
+                    Ok(result)
+                } else {
+                    Err(result)
+                },
+            );
+            relays
+        }
+        Err(e) => {
+            stages.record("publish", started, Err(e.to_string()));
+            return stages.finish(nonce, Vec::new());
+        }
+    };
+    if relays.iter().all(|relay| !relay.accepted) {
+        return stages.finish(nonce, relays);
+    }
+
+    // From the send, so the receive time is the round trip
+    let deadline = tokio::time::Instant::now() + timeout;
+    let received = loop {
+        match tokio::time::timeout_at(deadline, gifts.recv()).await {
+            Ok(Some(gift)) if probe_nonce(&gift.rumor) == Some(nonce.as_str()) => break Some(gift),
+            Ok(Some(_)) => continue,
+            Ok(None) | Err(_) => break None,
+        }
+    };
+    let Some(gift) = received else {
+        stages.record(
+            "receive",
+            started,
+            Err(format!("not back within {}s", timeout.as_secs())),
+        );
+        return stages.finish(nonce, relays);
+    };
+    stages.record("receive", started, Ok("round trip".to_string()));
+
+    let started = Instant::now();
+    let authentic = is_authentic_dm(&gift, &our_pubkey);
+    let message = IncomingMessage::from_rumor(gift.rumor);
+    let result = if !authentic {
+        Err(format!("sealed by {} instead of us", gift.sender))
+    } else if message.content != body {
+        Err(format!("content came back as {:?}", message.content))
+    } else {
+        Ok("seal and content match".to_string())
+    };
+    stages.record("decrypt", started, result);
+
+    // Every relay holds the same event; the subscription must hand it over once
+    let started = Instant::now();
+    let grace = tokio::time::Instant::now() + DEDUP_GRACE.min(timeout);
+    let mut copies = 1;
+    while let Ok(Some(gift)) = tokio::time::timeout_at(grace, gifts.recv()).await {
+        if probe_nonce(&gift.rumor) == Some(nonce.as_str()) {
+            copies += 1;
+        }
+    }
+    stages.record(
+        "dedup",
+        started,
+        if copies == 1 {
+            Ok("delivered once".to_string())
+        } else {
+            Err(format!("delivered {} times", copies))
+        },
+    );
+
+    stages.finish(nonce, relays)
+}
+
+impl fmt::Display for SelftestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stage in &self.stages {
+            writeln!(
+                f,
+                "{} {:<9} {:>6}ms  {}",
+                if stage.ok { "✅" } else { "❌" },
+                stage.name,
+                stage.duration_ms,
+                stage.detail
+            )?;
+        }
+        for relay in &self.relays {
+            match &relay.error {
+                None => writeln!(f, "   ✅ {}", relay.url)?,
+                Some(error) => writeln!(f, "   ❌ {}  {}", relay.url, error)?,
+            }
+        }
+        write!(
+            f,
+            "{}",
+            if self.ok {
+                "Self-test passed"
+            } else {
+                "Self-test failed"
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_round_trip() {
+        let keys = Keys::generate();
+        let transport = FakeTransport::new(keys.clone());
+        transport.loop_back(1);
+
+        let report = run(&transport, keys.public_key(), Duration::from_secs(5)).await;
+        assert!(report.ok, "{}", report);
+        let names: Vec<&str> = report.stages.iter().map(|stage| stage.name).collect();
+        assert_eq!(
+            names,
+            [
+                "subscribe",
+                "encrypt",
+                "publish",
+                "receive",
+                "decrypt",
+                "dedup"
+            ]
+        );
+        assert_eq!(report.relays.len(), 1);
+
+        // The probe is tagged and expires on the relays
+        let wrap = &transport.published()[0];
+        assert!(wrap.tags.expiration().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_are_reported() {
+        let keys = Keys::generate();
+        let transport = FakeTransport::new(keys.clone());
+
+        // Nothing comes back
+        let report = run(&transport, keys.public_key(), Duration::from_secs(3)).await;
+        assert!(!report.ok);
+        let receive = report.stages.last().unwrap();
+        assert_eq!((receive.name, receive.ok), ("receive", false));
+
+        // Every relay hands the probe over
+        transport.loop_back(2);
+        let report = run(&transport, keys.public_key(), Duration::from_secs(3)).await;
+        let dedup = report.stages.last().unwrap();
+        assert_eq!((dedup.name, dedup.ok), ("dedup", false));
+        assert!(report.to_string().ends_with("Self-test failed"));
+
+        transport.fail_sends(true);
+        let report = run(&transport, keys.public_key(), Duration::from_secs(3)).await;
+        assert_eq!(report.stages.last().unwrap().name, "publish");
+    }
+
+    #[test]
+    fn test_probe_is_recognised() {
+        let keys = Keys::generate();
+        let probe = EventBuilder::private_msg_rumor(keys.public_key(), "x")
+            .tag(Tag::custom(TagKind::custom(PROBE_TAG), ["abc"]))
+            .build(keys.public_key());
+        assert!(is_probe(&probe));
+        assert_eq!(probe_nonce(&probe), Some("abc"));
+        let plain =
+            EventBuilder::private_msg_rumor(keys.public_key(), "x").build(keys.public_key());
+        assert!(!is_probe(&plain));
+    }
+}
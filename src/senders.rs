@@ -0,0 +1,116 @@
+//! Who besides the target user may message `Chat` (`NPARROT_ALLOW_SENDERS`)
+//!
+//! DMs from anyone else are still dropped. Each sender gets their own inbox queue and message
+//! numbering, `wait` can be limited to one sender with `from`, and `send` answers whoever wrote
+//! the message it replies to. A `send` without `to` answering someone other than the target is
+//! refused rather than guessed, unless `NPARROT_UNADDRESSED_TO_TARGET` sends it to the target.
+//!
+//! `send` may only address the target and these senders. `ALLOW_ARBITRARY_RECIPIENTS` lifts
+//! that for one-off recipients, like forwarding a result to a colleague: letting a model DM
+//! anyone it likes is a footgun, so it is off unless the operator turns it on.
+
+use nostr_sdk::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+lazy_static::lazy_static! {
+    static ref ALLOWED: RwLock<Vec<PublicKey>> = RwLock::new(Vec::new());
+}
+
+static UNADDRESSED_TO_TARGET: AtomicBool = AtomicBool::new(false);
+static ARBITRARY_RECIPIENTS: AtomicBool = AtomicBool::new(false);
+
+/// Someone to send a message to, and the relays their nprofile says they read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    pub pubkey: PublicKey,
+    pub relays: Vec<RelayUrl>,
+}
+
+/// Parses an npub or hex pubkey, for the command line
+pub fn parse(value: &str) -> Result<PublicKey, String> {
+    PublicKey::parse(value.trim()).map_err(|e| format!("invalid pubkey '{}': {}", value, e))
+}
+
+/// Parses an npub, nprofile or hex pubkey, with or without `nostr:`
+pub fn parse_recipient(value: &str) -> Result<Recipient, String> {
+    let trimmed = value.trim();
+    let bare = trimmed.strip_prefix("nostr:").unwrap_or(trimmed);
+    if bare.starts_with("nprofile1") {
+        let profile = Nip19Profile::from_bech32(bare)
+            .map_err(|e| format!("invalid nprofile '{}': {}", value, e))?;
+        return Ok(Recipient {
+            pubkey: profile.public_key,
+            relays: profile.relays,
+        });
+    }
+    parse(bare).map(|pubkey| Recipient {
+        pubkey,
+        relays: Vec::new(),
+    })
+}
+
+/// Sets the senders heard besides the target, for inboxes started from now on
+pub fn set_allowed(senders: Vec<PublicKey>) {
+    if let Ok(mut guard) = ALLOWED.write() {
+        *guard = senders;
+    }
+}
+
+pub fn allowed() -> Vec<PublicKey> {
+    ALLOWED
+        .read()
+        .map(|guard| guard.clone())
+        .unwrap_or_default()
+}
+
+/// Installs `NPARROT_UNADDRESSED_TO_TARGET`
+pub fn set_unaddressed_to_target(enabled: bool) {
+    UNADDRESSED_TO_TARGET.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether a `send` without `to` answering another sender goes to the target instead of failing
+pub fn unaddressed_to_target() -> bool {
+    UNADDRESSED_TO_TARGET.load(Ordering::Relaxed)
+}
+
+/// Installs `ALLOW_ARBITRARY_RECIPIENTS`
+pub fn set_arbitrary_recipients(enabled: bool) {
+    ARBITRARY_RECIPIENTS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether `send` may address people who are neither the target nor an allowed sender
+pub fn arbitrary_recipients() -> bool {
+    ARBITRARY_RECIPIENTS.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_recipient() {
+        let keys = Keys::generate();
+        let npub = keys.public_key().to_bech32().unwrap();
+        assert_eq!(
+            parse_recipient(&npub).unwrap(),
+            Recipient {
+                pubkey: keys.public_key(),
+                relays: Vec::new(),
+            }
+        );
+        assert_eq!(
+            parse_recipient(&keys.public_key().to_hex()).unwrap().pubkey,
+            keys.public_key()
+        );
+
+        let profile = Nip19Profile::new(keys.public_key(), ["wss://relay.example"]).unwrap();
+        let nprofile = profile.to_bech32().unwrap();
+        let recipient = parse_recipient(&format!("nostr:{}", nprofile)).unwrap();
+        assert_eq!(recipient.pubkey, keys.public_key());
+        assert_eq!(recipient.relays, profile.relays);
+
+        assert!(parse_recipient("nprofile1nope").is_err());
+        assert!(parse_recipient("alice").is_err());
+    }
+}
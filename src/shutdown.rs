@@ -0,0 +1,74 @@
+//! Graceful shutdown on SIGINT/SIGTERM
+//!
+//! The first signal asks every long-running command to wind down (stop taking new messages,
+//! give in-flight work a grace period, disconnect relays). A second signal exits immediately.
+
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How long in-flight work gets to finish once shutdown starts
+pub const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Installs the signal handlers; call once from `main`
+    pub fn install() -> Self {
+        let (sender, receiver) = watch::channel(false);
+
+        tokio::spawn(async move {
+            let signal = wait_for_signal().await;
+            log::info!("Received {}, shutting down", signal);
+            let _ = sender.send(true);
+
+            let signal = wait_for_signal().await;
+            eprintln!("Received second {}, exiting immediately", signal);
+            std::process::exit(130);
+        });
+
+        Self { receiver }
+    }
+
+    /// A handle that only shuts down when the returned sender says so, for tests
+    #[cfg(test)]
+    pub fn manual() -> (Self, watch::Sender<bool>) {
+        let (sender, receiver) = watch::channel(false);
+        (Self { receiver }, sender)
+    }
+
+    /// Resolves once shutdown has been requested
+    pub async fn requested(&self) {
+        let mut receiver = self.receiver.clone();
+        // An error means the signal task is gone, which only happens at process exit
+        let _ = receiver.wait_for(|requested| *requested).await;
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(mut interrupt), Ok(mut terminate)) => tokio::select! {
+            _ = interrupt.recv() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        },
+        _ => {
+            log::warn!("Could not install SIGTERM handler, only Ctrl-C will shut down gracefully");
+            let _ = tokio::signal::ctrl_c().await;
+            "SIGINT"
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl-C"
+}
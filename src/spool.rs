@@ -0,0 +1,261 @@
+//! `listen --exec-dir`: every message as a JSON file in a spool directory, for tools that want
+//! files rather than stdin
+//!
+//! Files are named `<received>-<event id>.json` and hold the message as `listen --json` prints it.
+//! They are written under a temporary name and renamed, so a watcher never sees half a file.
+//! With a handler, a worker runs it on one file at a time with the path as its only argument and
+//! moves the file to `done/` if it exits 0, or to `failed/` next to its stderr otherwise. Files
+//! left in the directory by an earlier run are handled first. Filesystem errors (a full disk, a
+//! missing permission) are logged and cost the one message, never the listener.
+
+use crate::process_management::ProcessManager;
+use crate::utils::IncomingMessage;
+use chrono::Utc;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command as StdCommand;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+pub const DONE_DIR: &str = "done";
+pub const FAILED_DIR: &str = "failed";
+/// How long a handler may run before it is terminated and its file counts as failed
+const HANDLER_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    /// Files for the handler worker, if there is a handler
+    queue: Option<mpsc::UnboundedSender<PathBuf>>,
+}
+
+impl Spool {
+    /// Creates the directories and, with a `handler`, starts the worker on the files already there
+    pub fn start(dir: &Path, handler: Option<String>) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let queue = match handler {
+            Some(handler) => {
+                std::fs::create_dir_all(dir.join(DONE_DIR))?;
+                std::fs::create_dir_all(dir.join(FAILED_DIR))?;
+                let (queue, files) = mpsc::unbounded_channel();
+                for leftover in pending(dir) {
+                    let _ = queue.send(leftover);
+                }
+                tokio::spawn(run_handler(handler, files));
+                Some(queue)
+            }
+            None => None,
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            queue,
+        })
+    }
+
+    /// Writes `message` to the spool and queues it for the handler; errors are only logged
+    pub fn accept(&self, message: &IncomingMessage) {
+        match write(&self.dir, message) {
+            Ok(path) => {
+                log::debug!("Spooled message {} to {}", message.event_id, path.display());
+                if let Some(queue) = &self.queue {
+                    let _ = queue.send(path);
+                }
+            }
+            Err(e) => log::error!(
+                "Could not spool message {} to {}: {}",
+                message.event_id,
+                self.dir.display(),
+                e
+            ),
+        }
+    }
+}
+
+fn file_name(message: &IncomingMessage) -> String {
+    format!(
+        "{}-{}.json",
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+        message.event_id.to_hex()
+    )
+}
+
+fn write(dir: &Path, message: &IncomingMessage) -> io::Result<PathBuf> {
+    let path = dir.join(file_name(message));
+    let partial = path.with_extension("json.partial");
+    let mut bytes = serde_json::to_vec_pretty(message)?;
+    bytes.push(b'\n');
+    if let Err(e) = std::fs::write(&partial, &bytes).and_then(|_| std::fs::rename(&partial, &path))
+    {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    Ok(path)
+}
+
+/// Spooled files not handled yet, oldest first
+fn pending(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "json"))
+            .collect(),
+        Err(e) => {
+            log::error!("Could not read spool {}: {}", dir.display(), e);
+            Vec::new()
+        }
+    };
+    files.sort();
+    files
+}
+
+async fn run_handler(handler: String, mut files: mpsc::UnboundedReceiver<PathBuf>) {
+    while let Some(path) = files.recv().await {
+        let (handled, stderr) = handle(&handler, &path).await;
+        settle(&path, handled, &stderr);
+    }
+}
+
+/// Runs `handler` on `path`; whether it succeeded and what it wrote to stderr
+async fn handle(handler: &str, path: &Path) -> (bool, Vec<u8>) {
+    let mut command = StdCommand::new("sh");
+    // The path is passed as `$1`, so it is never parsed by the shell
+    command
+        .arg("-c")
+        .arg(format!("{} \"$1\"", handler))
+        .arg("sh")
+        .arg(path);
+    match ProcessManager::global()
+        .output(command, "exec-dir", HANDLER_TIMEOUT)
+        .await
+    {
+        Ok(Some(output)) => {
+            if !output.status.success() {
+                log::warn!("Handler failed on {}: {}", path.display(), output.status);
+            }
+            (output.status.success(), output.stderr)
+        }
+        Ok(None) => (
+            false,
+            format!("handler timed out after {}s\n", HANDLER_TIMEOUT.as_secs()).into_bytes(),
+        ),
+        Err(e) => {
+            log::error!("Could not run handler '{}': {}", handler, e);
+            (
+                false,
+                format!("could not run handler: {}\n", e).into_bytes(),
+            )
+        }
+    }
+}
+
+/// Moves a handled file to `done/` or `failed/`, with the stderr of a failure next to it
+fn settle(path: &Path, handled: bool, stderr: &[u8]) {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return;
+    };
+    let target = dir
+        .join(if handled { DONE_DIR } else { FAILED_DIR })
+        .join(name);
+    if let Err(e) = std::fs::rename(path, &target) {
+        log::error!(
+            "Could not move {} to {}: {}",
+            path.display(),
+            target.display(),
+            e
+        );
+        return;
+    }
+    if !handled {
+        let stderr_path = target.with_extension("stderr");
+        if let Err(e) = std::fs::write(&stderr_path, stderr) {
+            log::error!("Could not save {}: {}", stderr_path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::*;
+
+    fn message(content: &str) -> IncomingMessage {
+        let keys = Keys::generate();
+        IncomingMessage::from_rumor(
+            EventBuilder::private_msg_rumor(keys.public_key(), content).build(keys.public_key()),
+        )
+    }
+
+    fn json_files(dir: &Path) -> Vec<PathBuf> {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    async fn settled(dir: &Path, count: usize) {
+        for _ in 0..200 {
+            let moved =
+                json_files(&dir.join(DONE_DIR)).len() + json_files(&dir.join(FAILED_DIR)).len();
+            if moved >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("handler did not finish");
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_written_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::start(dir.path(), None).unwrap();
+        let sent = message("hello");
+        spool.accept(&sent);
+
+        let files = json_files(dir.path());
+        assert_eq!(files.len(), 1);
+        let name = files[0].file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.ends_with(&format!("-{}.json", sent.event_id.to_hex())));
+        let read: IncomingMessage =
+            serde_json::from_slice(&std::fs::read(&files[0]).unwrap()).unwrap();
+        assert_eq!(read, sent);
+        // Without a handler the files stay where they are
+        assert!(!dir.path().join(DONE_DIR).exists());
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_spool_is_not_fatal() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::start(&dir.path().join("spool"), None).unwrap();
+        std::fs::remove_dir(dir.path().join("spool")).unwrap();
+        spool.accept(&message("lost"));
+        // Still there for the next message once the directory is back
+        std::fs::create_dir(dir.path().join("spool")).unwrap();
+        spool.accept(&message("kept"));
+        assert_eq!(json_files(&dir.path().join("spool")).len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handler_outcomes() {
+        let dir = tempfile::tempdir().unwrap();
+        // A file from an earlier run is handled first
+        std::fs::write(dir.path().join("0-leftover.json"), "{}").unwrap();
+        let handler =
+            "handle() { grep -q fail \"$1\" && { echo broken >&2; exit 3; }; true; }; handle";
+        let spool = Spool::start(dir.path(), Some(handler.to_string())).unwrap();
+        spool.accept(&message("works"));
+        spool.accept(&message("fail please"));
+        settled(dir.path(), 3).await;
+
+        assert_eq!(json_files(&dir.path().join(DONE_DIR)).len(), 2);
+        let failed = json_files(&dir.path().join(FAILED_DIR));
+        assert_eq!(failed.len(), 1);
+        let stderr = std::fs::read_to_string(failed[0].with_extension("stderr")).unwrap();
+        assert_eq!(stderr, "broken\n");
+        assert!(json_files(dir.path()).is_empty());
+    }
+}
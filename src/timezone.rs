@@ -0,0 +1,648 @@
+//! Display time zones (`NPARROT_TZ`): stored times stay UTC, only what the user reads is local
+//!
+//! Zones are read from the system tz database (`$TZDIR`, else `/usr/share/zoneinfo`). The offset
+//! is looked up for every instant, using the file's transition table and, past its end, the POSIX
+//! rule in its footer, so times on either side of a DST change get the right offset and
+//! abbreviation.
+//!
+//! This stands in for chrono-tz, which the request asked for but which can't be added to this
+//! build yet. Until it is, hosts without a tz database (Windows, minimal containers) only have
+//! UTC, and naming another zone there fails with a pointer to `tzdata` / `TZDIR`.
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// How times are shown to the user, followed by the zone abbreviation
+pub const DISPLAY_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_ZONE: RwLock<Arc<Zone>> = RwLock::new(Arc::new(Zone::utc()));
+    static ref LOADED: Mutex<HashMap<String, Arc<Zone>>> = Mutex::new(HashMap::new());
+}
+
+/// Makes `zone` the one times are displayed in unless a request names another
+pub fn set_default(zone: Zone) {
+    if let Ok(mut guard) = DEFAULT_ZONE.write() {
+        *guard = Arc::new(zone);
+    }
+}
+
+pub fn default_zone() -> Arc<Zone> {
+    DEFAULT_ZONE
+        .read()
+        .map(|guard| guard.clone())
+        .unwrap_or_else(|_| Arc::new(Zone::utc()))
+}
+
+/// The zone a request asked for, or the default one; each named zone is only read once
+pub fn display_zone(name: Option<&str>) -> Result<Arc<Zone>, String> {
+    let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) else {
+        return Ok(default_zone());
+    };
+    if let Some(zone) = LOADED
+        .lock()
+        .ok()
+        .and_then(|loaded| loaded.get(name).cloned())
+    {
+        return Ok(zone);
+    }
+    let zone = Arc::new(Zone::load(name)?);
+    if let Ok(mut loaded) = LOADED.lock() {
+        loaded.insert(name.to_string(), zone.clone());
+    }
+    Ok(zone)
+}
+
+/// `time` in the default zone, e.g. "2025-07-01 14:00 CEST"
+pub fn format(time: DateTime<Utc>) -> String {
+    default_zone().format(time, DISPLAY_FORMAT)
+}
+
+/// Parses `--tz`/`NPARROT_TZ`
+pub fn parse_zone(name: &str) -> Result<Zone, String> {
+    Zone::load(name)
+}
+
+/// The offset from UTC in effect at some instant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalTime {
+    /// Seconds east of UTC
+    pub offset: i32,
+    pub dst: bool,
+    pub abbreviation: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Zone {
+    name: String,
+    /// Sorted UTC instants at which `types[indices[i]]` takes effect
+    transitions: Vec<i64>,
+    indices: Vec<u8>,
+    types: Vec<LocalTime>,
+    /// Applies after the last transition
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    pub fn utc() -> Self {
+        Self {
+            name: "UTC".to_string(),
+            transitions: Vec::new(),
+            indices: Vec::new(),
+            types: vec![LocalTime {
+                offset: 0,
+                dst: false,
+                abbreviation: "UTC".to_string(),
+            }],
+            rule: None,
+        }
+    }
+
+    /// Reads an IANA zone such as "Europe/Berlin" from the system tz database
+    pub fn load(name: &str) -> Result<Self, String> {
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("UTC") {
+            return Ok(Self::utc());
+        }
+        let valid = !name.is_empty()
+            && !name.starts_with('/')
+            && name.split('/').all(|part| !part.is_empty() && part != "..")
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+        if !valid {
+            return Err(format!("Invalid time zone name '{}'", name));
+        }
+
+        let dir = std::env::var_os("TZDIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/usr/share/zoneinfo"));
+        if !dir.is_dir() {
+            return Err(format!(
+                "Can't load time zone '{}': there is no tz database at {} (install tzdata or set TZDIR); only UTC is available",
+                name,
+                dir.display()
+            ));
+        }
+        let bytes = std::fs::read(dir.join(name))
+            .map_err(|e| format!("Unknown time zone '{}': {}", name, e))?;
+        Self::parse_tzif(name, &bytes)
+    }
+
+    /// Parses the contents of a TZif file (RFC 8536)
+    pub fn parse_tzif(name: &str, bytes: &[u8]) -> Result<Self, String> {
+        let invalid = |what: &str| format!("Invalid time zone file for '{}': {}", name, what);
+        let mut reader = Reader { bytes, position: 0 };
+
+        let header = reader.header().ok_or_else(|| invalid("bad header"))?;
+        let (header, time_size) = if header.version >= b'2' {
+            // Skip the 32-bit data; the 64-bit copy after it is complete
+            reader
+                .take(header.data_len(4))
+                .ok_or_else(|| invalid("truncated"))?;
+            (reader.header().ok_or_else(|| invalid("bad header"))?, 8)
+        } else {
+            (header, 4)
+        };
+
+        let mut transitions = Vec::with_capacity(header.timecnt);
+        for _ in 0..header.timecnt {
+            let raw = reader.take(time_size).ok_or_else(|| invalid("truncated"))?;
+            transitions.push(if time_size == 8 {
+                i64::from_be_bytes(raw.try_into().unwrap_or_default())
+            } else {
+                i32::from_be_bytes(raw.try_into().unwrap_or_default()) as i64
+            });
+        }
+        let indices = reader
+            .take(header.timecnt)
+            .ok_or_else(|| invalid("truncated"))?
+            .to_vec();
+        let raw_types = reader
+            .take(header.typecnt * 6)
+            .ok_or_else(|| invalid("truncated"))?;
+        let chars = reader
+            .take(header.charcnt)
+            .ok_or_else(|| invalid("truncated"))?;
+        reader
+            .take(header.leapcnt * (time_size + 4) + header.isstdcnt + header.isutcnt)
+            .ok_or_else(|| invalid("truncated"))?;
+
+        let mut types = Vec::with_capacity(header.typecnt);
+        for raw in raw_types.chunks_exact(6) {
+            let start = raw[5] as usize;
+            let abbreviation = chars
+                .get(start..)
+                .map(|rest| rest.split(|&b| b == 0).next().unwrap_or_default())
+                .ok_or_else(|| invalid("bad abbreviation index"))?;
+            types.push(LocalTime {
+                offset: i32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]),
+                dst: raw[4] != 0,
+                abbreviation: String::from_utf8_lossy(abbreviation).into_owned(),
+            });
+        }
+        if types.is_empty() || indices.iter().any(|&i| i as usize >= types.len()) {
+            return Err(invalid("bad local time types"));
+        }
+
+        let rule = if header.version >= b'2' {
+            let footer = String::from_utf8_lossy(reader.rest());
+            let footer = footer.trim_matches('\n');
+            if footer.is_empty() {
+                None
+            } else {
+                Some(Rule::parse(footer).ok_or_else(|| invalid("bad POSIX TZ footer"))?)
+            }
+        } else {
+            None
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            transitions,
+            indices,
+            types,
+            rule,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The offset and abbreviation in effect at `utc` (seconds since the epoch)
+    pub fn local_time(&self, utc: i64) -> LocalTime {
+        let after = self.transitions.partition_point(|&t| t <= utc);
+        if after == 0 {
+            return match (&self.rule, self.transitions.is_empty()) {
+                (Some(rule), true) => rule.local_time(utc),
+                _ => self.types[0].clone(),
+            };
+        }
+        if after == self.transitions.len() {
+            if let Some(rule) = &self.rule {
+                return rule.local_time(utc);
+            }
+        }
+        self.types[self.indices[after - 1] as usize].clone()
+    }
+
+    /// The instant a wall-clock time in this zone refers to; in a DST gap or overlap the
+    /// offset in effect just before is used
+    pub fn to_utc(&self, local: NaiveDateTime) -> DateTime<Utc> {
+        let naive = local.and_utc().timestamp();
+        // Offsets change at most once within a day around any instant we care about
+        let before = self.local_time(naive - 86_400).offset as i64;
+        let after = self.local_time(naive + 86_400).offset as i64;
+        let offset = [before, after]
+            .into_iter()
+            .find(|&offset| self.local_time(naive - offset).offset as i64 == offset)
+            .unwrap_or(before);
+        DateTime::from_timestamp(naive - offset, 0).unwrap_or_else(|| local.and_utc())
+    }
+
+    /// `time` rendered with `pattern` in this zone, followed by the zone abbreviation
+    pub fn format(&self, time: DateTime<Utc>, pattern: &str) -> String {
+        let local = self.local_time(time.timestamp());
+        let offset =
+            FixedOffset::east_opt(local.offset).unwrap_or(FixedOffset::east_opt(0).unwrap());
+        format!(
+            "{} {}",
+            time.with_timezone(&offset).format(pattern),
+            local.abbreviation
+        )
+    }
+}
+
+struct Header {
+    version: u8,
+    isutcnt: usize,
+    isstdcnt: usize,
+    leapcnt: usize,
+    timecnt: usize,
+    typecnt: usize,
+    charcnt: usize,
+}
+
+impl Header {
+    /// Length of the data block following this header
+    fn data_len(&self, time_size: usize) -> usize {
+        self.timecnt * (time_size + 1)
+            + self.typecnt * 6
+            + self.charcnt
+            + self.leapcnt * (time_size + 4)
+            + self.isstdcnt
+            + self.isutcnt
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(len)?;
+        let taken = self.bytes.get(self.position..end)?;
+        self.position = end;
+        Some(taken)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        self.bytes.get(self.position..).unwrap_or_default()
+    }
+
+    fn header(&mut self) -> Option<Header> {
+        let raw = self.take(44)?;
+        if &raw[..4] != b"TZif" {
+            return None;
+        }
+        let count = |i: usize| {
+            u32::from_be_bytes([
+                raw[20 + i * 4],
+                raw[21 + i * 4],
+                raw[22 + i * 4],
+                raw[23 + i * 4],
+            ]) as usize
+        };
+        Some(Header {
+            version: raw[4],
+            isutcnt: count(0),
+            isstdcnt: count(1),
+            leapcnt: count(2),
+            timecnt: count(3),
+            typecnt: count(4),
+            charcnt: count(5),
+        })
+    }
+}
+
+/// A POSIX TZ string such as "CET-1CEST,M3.5.0,M10.5.0/3"
+#[derive(Debug, Clone)]
+struct Rule {
+    standard: LocalTime,
+    daylight: Option<(LocalTime, RuleDate, RuleDate)>,
+}
+
+/// When a DST change happens: the day, and the local time of day in seconds
+#[derive(Debug, Clone, Copy)]
+struct RuleDate {
+    day: RuleDay,
+    time: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RuleDay {
+    /// `Jn`: 1..=365, February 29 is never counted
+    Julian(u32),
+    /// `n`: 0..=365, counting February 29 in leap years
+    ZeroBased(u32),
+    /// `Mm.w.d`: weekday `d` (0 = Sunday) of week `w` (5 = last) of month `m`
+    Month { month: u32, week: u32, weekday: u32 },
+}
+
+impl Rule {
+    fn parse(value: &str) -> Option<Self> {
+        let mut text = value;
+        let standard_name = take_name(&mut text)?;
+        let standard_offset = -take_offset(&mut text)?;
+        let standard = LocalTime {
+            offset: standard_offset as i32,
+            dst: false,
+            abbreviation: standard_name,
+        };
+        if text.is_empty() {
+            return Some(Self {
+                standard,
+                daylight: None,
+            });
+        }
+
+        let daylight_name = take_name(&mut text)?;
+        let daylight_offset = if text.is_empty() || text.starts_with(',') {
+            standard_offset + 3600
+        } else {
+            -take_offset(&mut text)?
+        };
+        // POSIX leaves the dates to the implementation when they are missing; use the US rules
+        let (start, end) = match text.strip_prefix(',') {
+            Some(rules) => {
+                let (start, end) = rules.split_once(',')?;
+                (RuleDate::parse(start)?, RuleDate::parse(end)?)
+            }
+            None if text.is_empty() => (RuleDate::parse("M3.2.0")?, RuleDate::parse("M11.1.0")?),
+            None => return None,
+        };
+        Some(Self {
+            standard,
+            daylight: Some((
+                LocalTime {
+                    offset: daylight_offset as i32,
+                    dst: true,
+                    abbreviation: daylight_name,
+                },
+                start,
+                end,
+            )),
+        })
+    }
+
+    fn local_time(&self, utc: i64) -> LocalTime {
+        let Some((daylight, start, end)) = &self.daylight else {
+            return self.standard.clone();
+        };
+        let Some(year) = DateTime::from_timestamp(utc, 0).map(|time| time.year()) else {
+            return self.standard.clone();
+        };
+        // The change to DST happens in standard time and the change back in DST
+        let (Some(start), Some(end)) = (
+            start.utc(year, self.standard.offset),
+            end.utc(year, daylight.offset),
+        ) else {
+            return self.standard.clone();
+        };
+        let in_dst = if start < end {
+            start <= utc && utc < end
+        } else {
+            // Southern hemisphere: DST spans the turn of the year
+            utc < end || start <= utc
+        };
+        if in_dst {
+            daylight.clone()
+        } else {
+            self.standard.clone()
+        }
+    }
+}
+
+impl RuleDate {
+    fn parse(value: &str) -> Option<Self> {
+        let (day, time) = match value.split_once('/') {
+            Some((day, time)) => {
+                let mut time = time;
+                (day, take_offset(&mut time).filter(|_| time.is_empty())?)
+            }
+            None => (value, 2 * 3600),
+        };
+        let day = if let Some(n) = day.strip_prefix('J') {
+            RuleDay::Julian(n.parse().ok().filter(|n| (1..=365).contains(n))?)
+        } else if let Some(spec) = day.strip_prefix('M') {
+            let mut parts = spec.split('.').map(|part| part.parse::<u32>().ok());
+            let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+            if parts.next().is_some()
+                || !(1..=12).contains(&month)
+                || !(1..=5).contains(&week)
+                || weekday > 6
+            {
+                return None;
+            }
+            RuleDay::Month {
+                month,
+                week,
+                weekday,
+            }
+        } else {
+            RuleDay::ZeroBased(day.parse().ok().filter(|n| *n <= 365)?)
+        };
+        Some(Self { day, time })
+    }
+
+    /// The UTC instant of this change in `year`, for a zone at `offset` just before it
+    fn utc(&self, year: i32, offset: i32) -> Option<i64> {
+        let date = match self.day {
+            RuleDay::Julian(n) => {
+                let date = NaiveDate::from_yo_opt(year, n)?;
+                // Days after February 28 skip the leap day
+                if date.leap_year() && n >= 60 {
+                    date.succ_opt()?
+                } else {
+                    date
+                }
+            }
+            RuleDay::ZeroBased(n) => NaiveDate::from_yo_opt(year, n + 1)?,
+            RuleDay::Month {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+                while NaiveDate::from_ymd_opt(year, month, day).is_none() {
+                    day -= 7;
+                }
+                NaiveDate::from_ymd_opt(year, month, day)?
+            }
+        };
+        let midnight = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp();
+        Some(midnight + self.time - offset as i64)
+    }
+}
+
+/// A zone abbreviation: letters, or anything quoted in `<…>`
+fn take_name(text: &mut &str) -> Option<String> {
+    let (name, rest) = if let Some(quoted) = text.strip_prefix('<') {
+        let (name, rest) = quoted.split_once('>')?;
+        (name, rest)
+    } else {
+        let end = text
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(text.len());
+        text.split_at(end)
+    };
+    if name.len() < 3 {
+        return None;
+    }
+    *text = rest;
+    Some(name.to_string())
+}
+
+/// `[+|-]hh[:mm[:ss]]` in seconds
+fn take_offset(text: &mut &str) -> Option<i64> {
+    let (sign, rest) = match text.as_bytes().first()? {
+        b'-' => (-1, &text[1..]),
+        b'+' => (1, &text[1..]),
+        _ => (1, *text),
+    };
+    let end = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ':')
+        .unwrap_or(rest.len());
+    let mut seconds = 0;
+    for (i, part) in rest[..end].split(':').enumerate() {
+        if i > 2 || part.is_empty() {
+            return None;
+        }
+        let value: i64 = part.parse().ok()?;
+        seconds += value * [3600, 60, 1][i];
+    }
+    *text = &rest[end..];
+    Some(sign * seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TZif v2 file with an empty 32-bit block
+    fn tzif(transitions: &[(i64, u8)], types: &[(i32, bool, &str)], footer: &str) -> Vec<u8> {
+        let header = |counts: [usize; 6]| {
+            let mut header = b"TZif2".to_vec();
+            header.extend([0; 15]);
+            for count in counts {
+                header.extend((count as u32).to_be_bytes());
+            }
+            header
+        };
+        let mut chars = Vec::new();
+        let mut infos = Vec::new();
+        for (offset, dst, abbreviation) in types {
+            infos.extend(offset.to_be_bytes());
+            infos.push(*dst as u8);
+            infos.push(chars.len() as u8);
+            chars.extend(abbreviation.bytes());
+            chars.push(0);
+        }
+
+        let mut bytes = header([0; 6]);
+        bytes.extend(header([
+            0,
+            0,
+            0,
+            transitions.len(),
+            types.len(),
+            chars.len(),
+        ]));
+        for (time, _) in transitions {
+            bytes.extend(time.to_be_bytes());
+        }
+        bytes.extend(transitions.iter().map(|(_, index)| index));
+        bytes.extend(infos);
+        bytes.extend(chars);
+        bytes.extend(format!("\n{}\n", footer).bytes());
+        bytes
+    }
+
+    fn at(value: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(value).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_rule_follows_dst_changes() {
+        let berlin = Zone::parse_tzif(
+            "Europe/Berlin",
+            &tzif(&[], &[(3600, false, "CET")], "CET-1CEST,M3.5.0,M10.5.0/3"),
+        )
+        .unwrap();
+        let local = |value: &str| berlin.local_time(at(value).timestamp());
+
+        // 2025: last Sunday of March at 02:00 CET, last Sunday of October at 03:00 CEST
+        assert_eq!(local("2025-03-30T00:59:59Z").abbreviation, "CET");
+        assert_eq!(local("2025-03-30T01:00:00Z").abbreviation, "CEST");
+        assert_eq!(local("2025-10-26T00:59:59Z").offset, 7200);
+        assert_eq!(local("2025-10-26T01:00:00Z").offset, 3600);
+        assert_eq!(
+            berlin.format(at("2025-07-01T12:00:00Z"), DISPLAY_FORMAT),
+            "2025-07-01 14:00 CEST"
+        );
+        assert_eq!(
+            berlin.format(at("2025-12-01T12:00:00Z"), DISPLAY_FORMAT),
+            "2025-12-01 13:00 CET"
+        );
+        let wall = |value: &str| {
+            berlin.to_utc(NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap())
+        };
+        assert_eq!(wall("2025-07-01 14:00"), at("2025-07-01T12:00:00Z"));
+        assert_eq!(wall("2025-12-01 13:00"), at("2025-12-01T12:00:00Z"));
+        // 02:30 does not exist on 2025-03-30 and is read with the winter offset
+        assert_eq!(wall("2025-03-30 02:30"), at("2025-03-30T01:30:00Z"));
+
+        let sydney = Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(
+            sydney
+                .local_time(at("2025-01-15T00:00:00Z").timestamp())
+                .offset,
+            39600
+        );
+        assert_eq!(
+            sydney
+                .local_time(at("2025-07-15T00:00:00Z").timestamp())
+                .offset,
+            36000
+        );
+
+        let fixed = Rule::parse("<+0530>-5:30").unwrap();
+        assert_eq!(fixed.local_time(0).offset, 19800);
+        assert_eq!(fixed.local_time(0).abbreviation, "+0530");
+    }
+
+    #[test]
+    fn test_transition_table_before_rule() {
+        let zone = Zone::parse_tzif(
+            "Test/Zone",
+            &tzif(
+                &[(1_000, 1), (2_000, 0)],
+                &[(0, false, "AAA"), (3600, true, "BBB")],
+                "CCC-2",
+            ),
+        )
+        .unwrap();
+        assert_eq!(zone.local_time(999).abbreviation, "AAA");
+        assert_eq!(zone.local_time(1_000).abbreviation, "BBB");
+        assert_eq!(zone.local_time(1_999).abbreviation, "BBB");
+        // After the last transition the footer takes over
+        assert_eq!(zone.local_time(2_000).abbreviation, "CCC");
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        assert!(Zone::load("../etc/passwd").is_err());
+        assert!(Zone::load("/etc/localtime").is_err());
+        assert_eq!(Zone::load("utc").unwrap().name(), "UTC");
+        let bytes = tzif(&[(1_000, 0)], &[(0, false, "AAA")], "");
+        assert!(Zone::parse_tzif("Test/Zone", &bytes[..bytes.len() - 8]).is_err());
+        assert!(Zone::parse_tzif("Test/Zone", b"not a zone").is_err());
+    }
+}
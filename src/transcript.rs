@@ -0,0 +1,469 @@
+//! Local transcript of every message sent and received (`NPARROT_TRANSCRIPT=1`)
+//!
+//! One JSON line per message goes to `transcripts/YYYY-MM-DD.jsonl` (UTC date) in the data dir,
+//! independent of what relays keep. The content is sealed with the data key, like the notes and
+//! events files, so only the metadata is readable without it; `nparrot transcript` decrypts and
+//! searches the files. The correlation id of a received message is its own event id, and sent
+//! messages carry the one of the last message received, tying replies to what they answer.
+
+use crate::at_rest::{self, DataKey, Vault};
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, Utc};
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+const DIR: &str = "transcripts";
+
+lazy_static::lazy_static! {
+    static ref LOGGER: RwLock<Option<PathBuf>> = RwLock::new(None);
+    /// Held while appending, so lines from concurrent sends never interleave
+    static ref WRITE: Mutex<()> = Mutex::new(());
+    static ref LAST_RECEIVED: Mutex<Option<EventId>> = Mutex::new(None);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptLine {
+    pub timestamp: DateTime<Utc>,
+    pub direction: Direction,
+    /// "main" or "progress"
+    pub channel: String,
+    /// Who sent a progress message: "orchestrator" or an agent id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub event_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+    /// Plain text, only when no data key is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Base64 of the content sealed with the data key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_content: Option<String>,
+}
+
+/// Starts logging into `data_dir`, or stops with `None`
+pub fn set_enabled(data_dir: Option<&str>) {
+    if let Ok(mut guard) = LOGGER.write() {
+        *guard = data_dir.map(|dir| Path::new(dir).join(DIR));
+    }
+}
+
+/// Logs a message we sent; `channel` is "main" or "progress", with the `source` that sent it
+pub fn sent(channel: &str, source: Option<&str>, event_id: EventId, content: &str) {
+    let correlation_id = LAST_RECEIVED.lock().ok().and_then(|last| *last);
+    append(
+        (Direction::Sent, channel, source),
+        event_id,
+        correlation_id,
+        content,
+    );
+}
+
+/// Logs a message from the user
+pub fn received(channel: &str, event_id: EventId, content: &str) {
+    if let Ok(mut last) = LAST_RECEIVED.lock() {
+        *last = Some(event_id);
+    }
+    append(
+        (Direction::Received, channel, None),
+        event_id,
+        Some(event_id),
+        content,
+    );
+}
+
+fn append(
+    (direction, channel, source): (Direction, &str, Option<&str>),
+    event_id: EventId,
+    correlation_id: Option<EventId>,
+    content: &str,
+) {
+    let Some(dir) = LOGGER.read().ok().and_then(|guard| guard.clone()) else {
+        return;
+    };
+    let line = (direction, channel, event_id, correlation_id, content);
+    if let Err(e) = write_line(&dir, Vault::global(), line, source, Utc::now()) {
+        log::error!("Failed to write transcript: {}", e);
+    }
+}
+
+fn write_line(
+    dir: &Path,
+    vault: &Vault,
+    (direction, channel, event_id, correlation_id, content): (
+        Direction,
+        &str,
+        EventId,
+        Option<EventId>,
+        &str,
+    ),
+    source: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let (content, sealed_content) = match vault.seal(content.as_bytes())? {
+        Some(sealed) => (
+            None,
+            Some(base64::engine::general_purpose::STANDARD.encode(sealed)),
+        ),
+        None => (Some(content.to_string()), None),
+    };
+    let line = TranscriptLine {
+        timestamp: now,
+        direction,
+        channel: channel.to_string(),
+        source: source.map(str::to_string),
+        event_id: event_id.to_hex(),
+        correlation_id: correlation_id.map(|id| id.to_hex()),
+        content,
+        sealed_content,
+    };
+    let result = (|| -> std::io::Result<()> {
+        let _guard = WRITE.lock().unwrap_or_else(|e| e.into_inner());
+        std::fs::create_dir_all(dir)?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(file_name(now.date_naive())))?;
+        let mut bytes = serde_json::to_vec(&line)?;
+        bytes.push(b'\n');
+        file.write_all(&bytes)
+    })();
+    result.map_err(|e| e.to_string())
+}
+
+fn file_name(date: NaiveDate) -> String {
+    format!("{}.jsonl", date.format("%Y-%m-%d"))
+}
+
+impl TranscriptLine {
+    /// The content, decrypted with `vault` if it was sealed
+    pub fn text(&self, vault: &Vault) -> Result<String, String> {
+        if let Some(content) = &self.content {
+            return Ok(content.clone());
+        }
+        let sealed = base64::engine::general_purpose::STANDARD
+            .decode(self.sealed_content.as_deref().unwrap_or_default())
+            .map_err(|e| format!("invalid sealed content: {}", e))?;
+        let plaintext = vault.open(&sealed)?;
+        String::from_utf8(plaintext).map_err(|e| e.to_string())
+    }
+}
+
+/// The lines logged on `date` (every day if `None`) whose content matches `pattern`, oldest
+/// first, with the decrypted content; lines that cannot be decrypted only match without a
+/// pattern
+pub fn search(
+    data_dir: &str,
+    date: Option<NaiveDate>,
+    pattern: Option<&regex::Regex>,
+) -> std::io::Result<Vec<(TranscriptLine, Result<String, String>)>> {
+    search_in(
+        &Path::new(data_dir).join(DIR),
+        Vault::global(),
+        date,
+        pattern,
+    )
+}
+
+fn search_in(
+    dir: &Path,
+    vault: &Vault,
+    date: Option<NaiveDate>,
+    pattern: Option<&regex::Regex>,
+) -> std::io::Result<Vec<(TranscriptLine, Result<String, String>)>> {
+    let files = match date {
+        Some(date) => vec![dir.join(file_name(date))],
+        None => files_in(dir)?,
+    };
+
+    let mut found = Vec::new();
+    for file in files {
+        let text = match std::fs::read_to_string(&file) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for raw in text.lines().filter(|raw| !raw.trim().is_empty()) {
+            let line: TranscriptLine = match serde_json::from_str(raw) {
+                Ok(line) => line,
+                Err(e) => {
+                    log::warn!("Skipping malformed line in {}: {}", file.display(), e);
+                    continue;
+                }
+            };
+            let content = line.text(vault);
+            let matches = match (pattern, &content) {
+                (None, _) => true,
+                (Some(pattern), Ok(content)) => pattern.is_match(content),
+                (Some(_), Err(_)) => false,
+            };
+            if matches {
+                found.push((line, content));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The transcript files in `data_dir`, oldest first
+pub fn files(data_dir: &str) -> std::io::Result<Vec<PathBuf>> {
+    files_in(&Path::new(data_dir).join(DIR))
+}
+
+fn files_in(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+            .collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    // The names sort by date
+    files.sort();
+    Ok(files)
+}
+
+/// Re-seals the content `vault` sealed in the transcript files with `new_key`, one file at a
+/// time; lines that already open with `new_key` are kept, so an interrupted run can be repeated.
+/// Returns how many lines were re-sealed.
+pub fn reseal(data_dir: &str, vault: &Vault, new_key: &DataKey) -> Result<usize, String> {
+    let mut resealed = 0;
+    for file in files(data_dir).map_err(|e| e.to_string())? {
+        let _guard = WRITE.lock().unwrap_or_else(|e| e.into_inner());
+        let text = std::fs::read_to_string(&file)
+            .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let mut changed = 0;
+        let mut out = String::with_capacity(text.len());
+        for raw in text.lines() {
+            let mut line: TranscriptLine = match serde_json::from_str(raw) {
+                Ok(line) => line,
+                Err(_) => {
+                    out.push_str(raw);
+                    out.push('\n');
+                    continue;
+                }
+            };
+            if let Some(sealed) = &line.sealed_content {
+                let engine = base64::engine::general_purpose::STANDARD;
+                let bytes = engine
+                    .decode(sealed)
+                    .map_err(|e| format!("{}: invalid sealed content: {}", file.display(), e))?;
+                if at_rest::open(Some(new_key), &bytes).is_err() {
+                    let plaintext = vault
+                        .open(&bytes)
+                        .map_err(|e| format!("{}: {}", file.display(), e))?;
+                    line.sealed_content = Some(engine.encode(at_rest::seal(new_key, &plaintext)?));
+                    changed += 1;
+                }
+            }
+            out.push_str(&serde_json::to_string(&line).map_err(|e| e.to_string())?);
+            out.push('\n');
+        }
+        if changed == 0 {
+            continue;
+        }
+        let staging = file.with_extension("jsonl.rotating");
+        std::fs::write(&staging, out)
+            .and_then(|_| std::fs::rename(&staging, &file))
+            .map_err(|e| format!("Failed to replace {}: {}", file.display(), e))?;
+        resealed += changed;
+    }
+    Ok(resealed)
+}
+
+/// Switches `vault` to `new_key`: the store files in `paths` (see `Vault::rotate`) and the
+/// transcripts of `data_dir` together. The transcripts are re-sealed first and put back on the
+/// old key if the store files can't be switched; lines written while the store files were
+/// switched are re-sealed afterwards. Returns the store files and transcript lines re-encrypted.
+pub fn rotate_with_stores(
+    data_dir: &str,
+    vault: &Vault,
+    paths: &[PathBuf],
+    new_key: DataKey,
+) -> Result<(usize, usize), String> {
+    let old_key = vault.key();
+    let resealed = reseal(data_dir, vault, &new_key)?;
+    let rotated = match vault.rotate(paths, new_key.clone()) {
+        Ok(rotated) => rotated,
+        Err(e) => {
+            if let Some(old_key) = &old_key {
+                if let Err(undo) = reseal(data_dir, &Vault::new(Some(new_key)), old_key) {
+                    log::error!(
+                        "Failed to put the transcripts back on the old key: {}",
+                        undo
+                    );
+                }
+            }
+            return Err(e);
+        }
+    };
+    let late = reseal(data_dir, &Vault::new(old_key), &new_key)?;
+    Ok((rotated, resealed + late))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transcript_is_sealed_and_searchable() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault = Vault::new(Some(DataKey::derive(b"transcript test")));
+        let now = Utc::now();
+        let question = EventId::all_zeros();
+        let answer = EventId::from_byte_array([1; 32]);
+        for line in [
+            (
+                Direction::Received,
+                "main",
+                question,
+                Some(question),
+                "deploy the staging branch?",
+            ),
+            (
+                Direction::Sent,
+                "progress",
+                answer,
+                Some(question),
+                "checking CI",
+            ),
+            (
+                Direction::Sent,
+                "main",
+                answer,
+                Some(question),
+                "Deployed staging",
+            ),
+        ] {
+            write_line(dir.path(), &vault, line, None, now).unwrap();
+        }
+
+        let raw = std::fs::read_to_string(dir.path().join(file_name(now.date_naive()))).unwrap();
+        assert_eq!(raw.lines().count(), 3);
+        assert!(!raw.contains("staging"));
+
+        let pattern = regex::RegexBuilder::new("STAGING")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let found = search_in(dir.path(), &vault, Some(now.date_naive()), Some(&pattern)).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0.direction, Direction::Received);
+        assert_eq!(found[1].0.correlation_id, Some(question.to_hex()));
+        assert_eq!(found[1].1.as_deref(), Ok("Deployed staging"));
+        assert_eq!(search_in(dir.path(), &vault, None, None).unwrap().len(), 3);
+
+        // Another key can read the metadata but not the content
+        let other = Vault::new(Some(DataKey::derive(b"another key")));
+        let found = search_in(dir.path(), &other, None, None).unwrap();
+        assert!(found.iter().all(|(_, content)| content.is_err()));
+        assert!(search_in(dir.path(), &other, None, Some(&pattern))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_reseal_is_repeatable() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let dir = data_dir.path().join(DIR);
+        let old = Vault::new(Some(DataKey::derive(b"old nsec")));
+        let new_key = DataKey::derive(b"new nsec");
+        let now = Utc::now();
+        let id = EventId::all_zeros();
+        for content in ["first", "second"] {
+            write_line(
+                &dir,
+                &old,
+                (Direction::Sent, "main", id, None, content),
+                None,
+                now,
+            )
+            .unwrap();
+        }
+        let data_dir = data_dir.path().to_str().unwrap();
+
+        assert_eq!(reseal(data_dir, &old, &new_key).unwrap(), 2);
+        let new = Vault::new(Some(new_key.clone()));
+        let found = search_in(&dir, &new, None, None).unwrap();
+        let texts: Vec<_> = found.into_iter().map(|(_, text)| text.unwrap()).collect();
+        assert_eq!(texts, ["first", "second"]);
+
+        // A second run finds nothing left to do, and later lines are sealed with the new key
+        assert_eq!(reseal(data_dir, &old, &new_key).unwrap(), 0);
+        write_line(
+            &dir,
+            &new,
+            (Direction::Sent, "main", id, None, "third"),
+            None,
+            now,
+        )
+        .unwrap();
+        assert_eq!(reseal(data_dir, &old, &new_key).unwrap(), 0);
+        assert_eq!(files(data_dir).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_data_key_rotation_takes_the_transcripts_along() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let dir = data_dir.path().join(DIR);
+        let notes = data_dir.path().join("notes.json");
+        let old_key = DataKey::derive(b"old passphrase");
+        let vault = Vault::new(Some(old_key.clone()));
+        vault.write(&notes, b"notes").unwrap();
+        write_line(
+            &dir,
+            &vault,
+            (
+                Direction::Received,
+                "main",
+                EventId::all_zeros(),
+                None,
+                "hello",
+            ),
+            None,
+            Utc::now(),
+        )
+        .unwrap();
+        let data_dir = data_dir.path().to_str().unwrap();
+        let texts = |vault: &Vault| -> Vec<Result<String, String>> {
+            search_in(&dir, vault, None, None)
+                .unwrap()
+                .into_iter()
+                .map(|(_, text)| text)
+                .collect()
+        };
+
+        // A store file the rotation can't read leaves the transcripts on the old key
+        let unreadable = Vault::new(Some(DataKey::derive(b"wrong passphrase")))
+            .seal(b"x")
+            .unwrap()
+            .unwrap();
+        let broken = Path::new(data_dir).join("events.json");
+        std::fs::write(&broken, unreadable).unwrap();
+        let new_key = DataKey::derive(b"new passphrase");
+        let paths = [notes.clone(), broken.clone()];
+        assert!(rotate_with_stores(data_dir, &vault, &paths, new_key.clone()).is_err());
+        assert_eq!(texts(&vault), [Ok("hello".to_string())]);
+
+        std::fs::remove_file(&broken).unwrap();
+        assert_eq!(
+            rotate_with_stores(data_dir, &vault, &paths, new_key.clone()).unwrap(),
+            (1, 1)
+        );
+        assert_eq!(vault.key(), Some(new_key));
+        assert_eq!(texts(&vault), [Ok("hello".to_string())]);
+        assert_eq!(vault.read(&notes).unwrap().unwrap(), b"notes");
+        assert!(texts(&Vault::new(Some(old_key)))[0].is_err());
+    }
+}
@@ -0,0 +1,318 @@
+//! In-memory `DmTransport` for tests: records what is sent and lets tests inject inbound DMs
+
+use super::{DmTransport, TransportResult, STREAM_BUFFER};
+use crate::utils::{build_private_msg_at, build_reaction, unwrap_gift_wrap};
+use futures::future::BoxFuture;
+use nostr_sdk::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const FAKE_RELAY: &str = "wss://fake.relay";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentMessage {
+    pub receiver: PublicKey,
+    pub content: String,
+    pub expire_after_secs: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    prepared: HashMap<EventId, SentMessage>,
+    sent: Vec<SentMessage>,
+    published: Vec<Event>,
+    inbox: VecDeque<UnwrappedGift>,
+    /// Every DM injected so far, as relays would have stored them
+    delivered: Vec<UnwrappedGift>,
+    subscribers: Vec<mpsc::UnboundedSender<UnwrappedGift>>,
+    event_subscribers: Vec<(Vec<Filter>, mpsc::UnboundedSender<Event>)>,
+    /// Relays each event was explicitly sent to, absent for broadcasts
+    targets: HashMap<EventId, Vec<String>>,
+    fail_sends: bool,
+    /// How long every publish takes, like a slow relay
+    send_delay: Duration,
+    /// Copies of each DM to ourselves handed back to subscribers, as relays echo them
+    loop_back: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeTransport {
+    keys: Keys,
+    state: Arc<Mutex<State>>,
+}
+
+impl FakeTransport {
+    /// A fake relay connection signing as `keys`
+    pub fn new(keys: Keys) -> Self {
+        Self {
+            keys,
+            state: Arc::default(),
+        }
+    }
+
+    /// Messages successfully sent so far, oldest first
+    pub fn sent(&self) -> Vec<SentMessage> {
+        self.state.lock().unwrap().sent.clone()
+    }
+
+    /// Every event published so far, oldest first
+    pub fn published(&self) -> Vec<Event> {
+        self.state.lock().unwrap().published.clone()
+    }
+
+    /// The relays `id` was sent to with `send_event_to`, `None` if it was broadcast
+    pub fn targets(&self, id: &EventId) -> Option<Vec<String>> {
+        self.state.lock().unwrap().targets.get(id).cloned()
+    }
+
+    /// Makes every following publish fail as if no relay were reachable
+    pub fn fail_sends(&self, fail: bool) {
+        self.state.lock().unwrap().fail_sends = fail;
+    }
+
+    /// Makes every following publish take `delay` before it succeeds or fails
+    pub fn delay_sends(&self, delay: Duration) {
+        self.state.lock().unwrap().send_delay = delay;
+    }
+
+    /// Hands every published DM to ourselves back `copies` times, 0 to stop
+    pub fn loop_back(&self, copies: usize) {
+        self.state.lock().unwrap().loop_back = copies;
+    }
+
+    /// Delivers a DM from `sender` to current subscribers, or to the next one if none is listening
+    pub fn inject(&self, sender: &Keys, content: &str) {
+        let rumor = EventBuilder::private_msg_rumor(self.keys.public_key(), content)
+            .build(sender.public_key());
+        self.inject_gift(UnwrappedGift {
+            sender: sender.public_key(),
+            rumor,
+        });
+    }
+
+    /// Delivers a plain event (e.g. a group message) to the subscriptions it matches
+    pub fn inject_event(&self, event: Event) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .event_subscribers
+            .retain(|(_, subscriber)| !subscriber.is_closed());
+        for (filters, subscriber) in &state.event_subscribers {
+            if filters.iter().any(|filter| filter.match_event(&event)) {
+                let _ = subscriber.send(event.clone());
+            }
+        }
+    }
+
+    /// Delivers an already unwrapped gift as is, e.g. one with a forged rumor
+    pub fn inject_gift(&self, gift: UnwrappedGift) {
+        let mut state = self.state.lock().unwrap();
+        state.delivered.push(gift.clone());
+        state
+            .subscribers
+            .retain(|subscriber| !subscriber.is_closed());
+        match state.subscribers.first() {
+            Some(subscriber) => {
+                let _ = subscriber.send(gift);
+            }
+            None => state.inbox.push_back(gift),
+        }
+    }
+}
+
+impl DmTransport for FakeTransport {
+    fn prepare_private_msg_at(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move {
+            let event = build_private_msg_at(
+                &self.keys,
+                receiver,
+                message.clone(),
+                expire_after_secs,
+                rumor_tags,
+                0,
+                created_at,
+            )
+            .await?;
+            self.state.lock().unwrap().prepared.insert(
+                event.id,
+                SentMessage {
+                    receiver,
+                    content: message,
+                    expire_after_secs,
+                },
+            );
+            Ok(event)
+        })
+    }
+
+    fn prepare_reaction(
+        &self,
+        receiver: PublicKey,
+        message_id: EventId,
+        reaction: String,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move { build_reaction(&self.keys, receiver, message_id, &reaction).await })
+    }
+
+    fn sign_event(&self, builder: EventBuilder) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move { Ok(builder.sign_with_keys(&self.keys)?) })
+    }
+
+    fn send_event<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            let delay = self.state.lock().unwrap().send_delay;
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            let copies = {
+                let mut state = self.state.lock().unwrap();
+                if state.fail_sends {
+                    return Err("fake relay unreachable".into());
+                }
+                if let Some(message) = state.prepared.remove(&event.id) {
+                    state.sent.push(message);
+                }
+                state.published.push(event.clone());
+                state.loop_back
+            };
+
+            let ours = event
+                .tags
+                .public_keys()
+                .any(|p| *p == self.keys.public_key());
+            if copies > 0 && event.kind == Kind::GiftWrap && ours {
+                let gift = unwrap_gift_wrap(&self.keys, event).await?;
+                for _ in 0..copies {
+                    self.inject_gift(gift.clone());
+                }
+            }
+            Ok(Output {
+                val: event.id,
+                success: HashSet::from([RelayUrl::parse(FAKE_RELAY)?]),
+                failed: HashMap::new(),
+            })
+        })
+    }
+
+    fn send_event_to<'a>(
+        &'a self,
+        urls: &'a [String],
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            let output = self.send_event(event).await?;
+            let mut state = self.state.lock().unwrap();
+            state.targets.insert(event.id, urls.to_vec());
+            Ok(Output {
+                success: urls
+                    .iter()
+                    .map(|url| RelayUrl::parse(url))
+                    .collect::<Result<_, _>>()?,
+                ..output
+            })
+        })
+    }
+
+    fn subscribe_dms(
+        &self,
+        _our_pubkey: PublicKey,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<UnwrappedGift>>> {
+        Box::pin(async move {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let mut state = self.state.lock().unwrap();
+            for gift in state.inbox.drain(..) {
+                let _ = sender.send(gift);
+            }
+            state.subscribers.push(sender);
+            Ok(receiver)
+        })
+    }
+
+    fn subscribe_events(
+        &self,
+        filters: Vec<Filter>,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<Event>>> {
+        Box::pin(async move {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            self.state
+                .lock()
+                .unwrap()
+                .event_subscribers
+                .push((filters, sender));
+            Ok(receiver)
+        })
+    }
+
+    fn fetch_events(
+        &self,
+        filter: Filter,
+        _timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<Vec<Event>>> {
+        Box::pin(async move {
+            let state = self.state.lock().unwrap();
+            Ok(state
+                .published
+                .iter()
+                .filter(|event| filter.match_event(event))
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn stream_events(
+        &self,
+        filter: Filter,
+        _timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<Event>>> {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+            tokio::spawn(async move {
+                // One at a time, like events coming in from a relay
+                for index in 0.. {
+                    let Some(event) = state.lock().unwrap().published.get(index).cloned() else {
+                        break;
+                    };
+                    if filter.match_event(&event) && sender.send(event).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(receiver)
+        })
+    }
+
+    fn stream_dms(
+        &self,
+        _our_pubkey: PublicKey,
+        since: Timestamp,
+        _timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<UnwrappedGift>>> {
+        let state = self.state.clone();
+        Box::pin(async move {
+            let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+            tokio::spawn(async move {
+                for index in 0.. {
+                    let Some(gift) = state.lock().unwrap().delivered.get(index).cloned() else {
+                        break;
+                    };
+                    if gift.rumor.created_at >= since && sender.send(gift).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(receiver)
+        })
+    }
+}
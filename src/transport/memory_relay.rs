@@ -0,0 +1,455 @@
+//! In-memory relays for end-to-end tests: the whole DM path without a network
+//!
+//! `FakeTransport` records what one side sends and hands it DMs that are already unwrapped. A
+//! `RelayNetwork` connects real parties instead. Each `RelayTransport` signs with its own keys and
+//! publishes real gift wraps, and unwraps what it receives and checks it against replays the way
+//! the shared DM subscription does. Every relay of the network keeps and delivers its own copy of
+//! an event. A subscription passes each event id on once, as nostr-sdk's pool does, so a listener
+//! gets a message twice only when it was wrapped twice, or when a relay replays it to a later
+//! subscription. `lose`, `hold` and `release` model relays that drop events or deliver them out
+//! of order.
+
+use super::{subscriptions, DmTransport, TransportResult, STREAM_BUFFER};
+use crate::utils::{build_private_msg_at, build_reaction, unwrap_gift_wrap};
+use futures::future::BoxFuture;
+use nostr_sdk::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(Debug)]
+struct Relay {
+    url: RelayUrl,
+    events: Vec<Event>,
+    /// Accepts events and loses them
+    lossy: bool,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    filter: Filter,
+    sender: mpsc::UnboundedSender<(RelayUrl, Event)>,
+}
+
+#[derive(Debug, Default)]
+struct Network {
+    relays: Vec<Relay>,
+    subscriptions: Vec<Subscription>,
+    /// Deliveries held back until `release`, oldest first; `None` delivers at once
+    held: Option<Vec<(RelayUrl, Event)>>,
+}
+
+impl Network {
+    fn deliver(&mut self, relay: RelayUrl, event: Event) {
+        if let Some(held) = &mut self.held {
+            held.push((relay, event));
+            return;
+        }
+        self.subscriptions
+            .retain(|subscription| !subscription.sender.is_closed());
+        for subscription in &self.subscriptions {
+            if subscription.filter.match_event(&event) {
+                let _ = subscription.sender.send((relay.clone(), event.clone()));
+            }
+        }
+    }
+}
+
+/// Relays shared by every transport connected to them
+#[derive(Debug, Clone, Default)]
+pub struct RelayNetwork {
+    network: Arc<Mutex<Network>>,
+}
+
+impl RelayNetwork {
+    /// A network of the relays `urls`
+    pub fn new(urls: &[&str]) -> Self {
+        let relays = urls
+            .iter()
+            .map(|url| Relay {
+                url: RelayUrl::parse(url).expect("valid relay url"),
+                events: Vec::new(),
+                lossy: false,
+            })
+            .collect();
+        Self {
+            network: Arc::new(Mutex::new(Network {
+                relays,
+                ..Network::default()
+            })),
+        }
+    }
+
+    /// A connection to every relay of the network, signing as `keys`
+    pub fn connect(&self, keys: Keys) -> RelayTransport {
+        RelayTransport {
+            keys,
+            network: self.clone(),
+        }
+    }
+
+    /// Makes the relay `url` accept every following event without storing or delivering it
+    pub fn lose(&self, url: &str, lose: bool) {
+        let mut network = self.network.lock().unwrap();
+        if let Some(relay) = network
+            .relays
+            .iter_mut()
+            .find(|relay| relay.url.as_str_without_trailing_slash() == url)
+        {
+            relay.lossy = lose;
+        }
+    }
+
+    /// Holds every following delivery back until `release`
+    pub fn hold(&self) {
+        self.network
+            .lock()
+            .unwrap()
+            .held
+            .get_or_insert_with(Vec::new);
+    }
+
+    /// Delivers the held events, newest first when `reversed`, and stops holding
+    pub fn release(&self, reversed: bool) {
+        let mut network = self.network.lock().unwrap();
+        let mut held = network.held.take().unwrap_or_default();
+        if reversed {
+            held.reverse();
+        }
+        for (relay, event) in held {
+            network.deliver(relay, event);
+        }
+    }
+
+    /// Delivers the stored event `id` again from `url`, as a relay replaying old events does
+    pub fn replay(&self, url: &str, id: &EventId) {
+        let mut network = self.network.lock().unwrap();
+        let Some(relay) = network
+            .relays
+            .iter()
+            .find(|relay| relay.url.as_str_without_trailing_slash() == url)
+        else {
+            return;
+        };
+        let url = relay.url.clone();
+        if let Some(event) = relay.events.iter().find(|event| event.id == *id).cloned() {
+            network.deliver(url, event);
+        }
+    }
+
+    /// The events the relay `url` stores, oldest first
+    pub fn stored(&self, url: &str) -> Vec<Event> {
+        let network = self.network.lock().unwrap();
+        network
+            .relays
+            .iter()
+            .find(|relay| relay.url.as_str_without_trailing_slash() == url)
+            .map(|relay| relay.events.clone())
+            .unwrap_or_default()
+    }
+
+    /// Hands `event` to the relays `urls`, or all of them; returns the ones that took it
+    fn publish(&self, urls: Option<&[String]>, event: &Event) -> HashSet<RelayUrl> {
+        let mut network = self.network.lock().unwrap();
+        let mut accepted = HashSet::new();
+        let mut deliveries = Vec::new();
+        for relay in &mut network.relays {
+            let url = relay.url.as_str_without_trailing_slash();
+            if urls.is_some_and(|urls| {
+                !urls
+                    .iter()
+                    .any(|wanted| wanted.trim_end_matches('/') == url)
+            }) {
+                continue;
+            }
+            accepted.insert(relay.url.clone());
+            if relay.lossy || relay.events.iter().any(|stored| stored.id == event.id) {
+                continue;
+            }
+            relay.events.push(event.clone());
+            deliveries.push(relay.url.clone());
+        }
+        for relay in deliveries {
+            network.deliver(relay, event.clone());
+        }
+        accepted
+    }
+
+    /// Every event matching `filter` as relays send it: once per relay, each id passed on once
+    fn subscribe(&self, filter: Filter) -> mpsc::UnboundedReceiver<(RelayUrl, Event)> {
+        let (sender, mut relayed) = mpsc::unbounded_channel();
+        self.network
+            .lock()
+            .unwrap()
+            .subscriptions
+            .push(Subscription { filter, sender });
+        let (deduplicated, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut seen = HashSet::new();
+            while let Some((relay, event)) = relayed.recv().await {
+                if seen.insert(event.id) && deduplicated.send((relay, event)).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// The stored events matching `filter` across all relays, each once
+    fn query(&self, filter: &Filter) -> Vec<Event> {
+        let network = self.network.lock().unwrap();
+        let mut seen = HashSet::new();
+        network
+            .relays
+            .iter()
+            .flat_map(|relay| relay.events.iter())
+            .filter(|event| filter.match_event(event) && seen.insert(event.id))
+            .cloned()
+            .collect()
+    }
+}
+
+/// One identity's connection to a `RelayNetwork`
+#[derive(Debug, Clone)]
+pub struct RelayTransport {
+    keys: Keys,
+    network: RelayNetwork,
+}
+
+impl RelayTransport {
+    async fn gift_wraps_to(&self, our_pubkey: PublicKey) -> Vec<UnwrappedGift> {
+        let filter = Filter::new().kind(Kind::GiftWrap).pubkey(our_pubkey);
+        let mut gifts = Vec::new();
+        for event in self.network.query(&filter) {
+            if let Ok(gift) = unwrap_gift_wrap(&self.keys, &event).await {
+                gifts.push(gift);
+            }
+        }
+        gifts
+    }
+}
+
+impl DmTransport for RelayTransport {
+    fn prepare_private_msg_at(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move {
+            build_private_msg_at(
+                &self.keys,
+                receiver,
+                message,
+                expire_after_secs,
+                rumor_tags,
+                0,
+                created_at,
+            )
+            .await
+        })
+    }
+
+    fn prepare_reaction(
+        &self,
+        receiver: PublicKey,
+        message_id: EventId,
+        reaction: String,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move { build_reaction(&self.keys, receiver, message_id, &reaction).await })
+    }
+
+    fn sign_event(&self, builder: EventBuilder) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move { Ok(builder.sign_with_keys(&self.keys)?) })
+    }
+
+    fn send_event<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            Ok(Output {
+                val: event.id,
+                success: self.network.publish(None, event),
+                failed: HashMap::new(),
+            })
+        })
+    }
+
+    fn send_event_to<'a>(
+        &'a self,
+        urls: &'a [String],
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            let success = self.network.publish(Some(urls), event);
+            if success.is_empty() {
+                return Err(format!("none of {} is in the network", urls.join(", ")).into());
+            }
+            Ok(Output {
+                val: event.id,
+                success,
+                failed: HashMap::new(),
+            })
+        })
+    }
+
+    fn subscribe_dms(
+        &self,
+        our_pubkey: PublicKey,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<UnwrappedGift>>> {
+        Box::pin(async move {
+            let filter = Filter::new().kind(Kind::GiftWrap).pubkey(our_pubkey);
+            let mut events = self.network.subscribe(filter);
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let keys = self.keys.clone();
+            tokio::spawn(async move {
+                while let Some((relay, event)) = events.recv().await {
+                    let Ok(mut gift) = unwrap_gift_wrap(&keys, &event).await else {
+                        continue;
+                    };
+                    if subscriptions::admit(&mut gift, &relay) && sender.send(gift).is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(receiver)
+        })
+    }
+
+    fn subscribe_events(
+        &self,
+        filters: Vec<Filter>,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<Event>>> {
+        Box::pin(async move {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            for filter in filters {
+                let mut events = self.network.subscribe(filter);
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    while let Some((_, event)) = events.recv().await {
+                        if sender.send(event).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            Ok(receiver)
+        })
+    }
+
+    fn fetch_events(
+        &self,
+        filter: Filter,
+        _timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<Vec<Event>>> {
+        Box::pin(async move { Ok(self.network.query(&filter)) })
+    }
+
+    fn stream_events(
+        &self,
+        filter: Filter,
+        _timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<Event>>> {
+        Box::pin(async move {
+            let events = self.network.query(&filter);
+            let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+            tokio::spawn(async move {
+                for event in events {
+                    if sender.send(event).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(receiver)
+        })
+    }
+
+    fn stream_dms(
+        &self,
+        our_pubkey: PublicKey,
+        since: Timestamp,
+        _timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<UnwrappedGift>>> {
+        Box::pin(async move {
+            let gifts = self.gift_wraps_to(our_pubkey).await;
+            let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+            tokio::spawn(async move {
+                for gift in gifts {
+                    if gift.rumor.created_at >= since && sender.send(gift).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(receiver)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "wss://a.relay";
+    const B: &str = "wss://b.relay";
+
+    async fn next(receiver: &mut mpsc::UnboundedReceiver<UnwrappedGift>) -> Option<String> {
+        tokio::time::timeout(Duration::from_millis(200), receiver.recv())
+            .await
+            .ok()
+            .flatten()
+            .map(|gift| gift.rumor.content)
+    }
+
+    #[tokio::test]
+    async fn test_each_relay_keeps_a_copy_delivered_once() {
+        let network = RelayNetwork::new(&[A, B]);
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let to_bob = network.connect(alice);
+        let bobs = network.connect(bob.clone());
+        let mut inbox = bobs.subscribe_dms(bob.public_key()).await.unwrap();
+
+        let sent = to_bob
+            .send_private_msg(bob.public_key(), "hi".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(sent.success.len(), 2);
+        assert_eq!(network.stored(A), network.stored(B));
+        assert_eq!(next(&mut inbox).await.as_deref(), Some("hi"));
+        assert_eq!(next(&mut inbox).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_lost_and_reordered_deliveries() {
+        let network = RelayNetwork::new(&[A, B]);
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let to_bob = network.connect(alice);
+        let bobs = network.connect(bob.clone());
+        let mut inbox = bobs.subscribe_dms(bob.public_key()).await.unwrap();
+
+        network.lose(A, true);
+        network.lose(B, true);
+        to_bob
+            .send_private_msg(bob.public_key(), "lost".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(next(&mut inbox).await, None);
+        assert!(network.stored(A).is_empty());
+
+        network.lose(A, false);
+        network.hold();
+        for message in ["first", "second"] {
+            to_bob
+                .send_private_msg(bob.public_key(), message.to_string(), None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(next(&mut inbox).await, None);
+        network.release(true);
+        assert_eq!(next(&mut inbox).await.as_deref(), Some("second"));
+        assert_eq!(next(&mut inbox).await.as_deref(), Some("first"));
+    }
+}
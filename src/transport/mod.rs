@@ -0,0 +1,344 @@
+//! The slice of Nostr the MCP tools depend on, behind a trait
+//!
+//! `Chat`, the memory client and the agent pool talk to relays only through `DmTransport`,
+//! so their logic can be exercised against the in-memory fake, or a network of in-memory relays
+//! (`memory_relay`), in tests instead of a live relay.
+
+#[cfg(test)]
+pub mod fake;
+#[cfg(test)]
+pub mod memory_relay;
+pub mod subscriptions;
+
+use crate::utils::{build_reaction, prepare_private_msg, prepare_private_msg_at, unwrap_gift_wrap};
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+pub type TransportResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub type SharedTransport = Arc<dyn DmTransport>;
+
+/// How far back NIP-59 lets a gift wrap's own timestamp be set
+const GIFT_WRAP_BACKDATE: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// Most fetched events (or unwrapped gifts) waiting for the reader of a stream at once
+pub const STREAM_BUFFER: usize = 256;
+
+/// Streamed items between two progress log lines of a long fetch
+pub const STREAM_PROGRESS_EVERY: usize = 5000;
+
+pub trait DmTransport: std::fmt::Debug + Send + Sync {
+    /// Builds the gift wrap for a NIP-17 message to `receiver` without publishing it;
+    /// `rumor_tags` go on the inner message (e.g. the `e` tag of a reply)
+    fn prepare_private_msg(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        self.prepare_private_msg_at(
+            receiver,
+            message,
+            expire_after_secs,
+            rumor_tags,
+            Timestamp::now(),
+        )
+    }
+
+    /// `prepare_private_msg` with the inner message dated `created_at`, which is what clients
+    /// order a conversation by
+    fn prepare_private_msg_at(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> BoxFuture<'_, TransportResult<Event>>;
+
+    /// Builds the gift-wrapped NIP-25 reaction to the DM `message_id` from `receiver`
+    fn prepare_reaction(
+        &self,
+        receiver: PublicKey,
+        message_id: EventId,
+        reaction: String,
+    ) -> BoxFuture<'_, TransportResult<Event>>;
+
+    /// Signs an event with our identity without publishing it
+    fn sign_event(&self, builder: EventBuilder) -> BoxFuture<'_, TransportResult<Event>>;
+
+    /// Publishes an already signed event
+    fn send_event<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>>;
+
+    /// Publishes an already signed event to the given relays of the pool only
+    fn send_event_to<'a>(
+        &'a self,
+        urls: &'a [String],
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>>;
+
+    /// Builds and publishes a NIP-17 message to `receiver`
+    fn send_private_msg(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+    ) -> BoxFuture<'_, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            let event = self
+                .prepare_private_msg(receiver, message, expire_after_secs, Vec::new())
+                .await?;
+            self.send_event(&event).await
+        })
+    }
+
+    /// Streams every gift wrap addressed to `our_pubkey` from now on, already unwrapped;
+    /// dropping the receiver ends the subscription
+    fn subscribe_dms(
+        &self,
+        our_pubkey: PublicKey,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<UnwrappedGift>>>;
+
+    /// Streams every event matching any of `filters`, e.g. a NIP-29 group's messages;
+    /// dropping the receiver ends the subscription
+    fn subscribe_events(
+        &self,
+        filters: Vec<Filter>,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<Event>>>;
+
+    /// Fetches stored events matching `filter`
+    fn fetch_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<Vec<Event>>>;
+
+    /// Streams the stored events matching `filter` as relays return them, for fetches too
+    /// large to hold at once; ends when every relay is done or after `timeout`
+    fn stream_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<Event>>>;
+
+    /// Streams the stored gift wraps addressed to `our_pubkey` whose message is dated `since`
+    /// or later, unwrapped one at a time; the ones we can't open are skipped
+    fn stream_dms(
+        &self,
+        our_pubkey: PublicKey,
+        since: Timestamp,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<UnwrappedGift>>>;
+}
+
+impl DmTransport for Client {
+    fn prepare_private_msg_at(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+        rumor_tags: Vec<Tag>,
+        created_at: Timestamp,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(prepare_private_msg_at(
+            self,
+            receiver,
+            message,
+            expire_after_secs,
+            rumor_tags,
+            created_at,
+        ))
+    }
+
+    fn prepare_reaction(
+        &self,
+        receiver: PublicKey,
+        message_id: EventId,
+        reaction: String,
+    ) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move {
+            let signer = self.signer().await?;
+            build_reaction(&signer, receiver, message_id, &reaction).await
+        })
+    }
+
+    fn sign_event(&self, builder: EventBuilder) -> BoxFuture<'_, TransportResult<Event>> {
+        Box::pin(async move { Ok(self.sign_event_builder(builder).await?) })
+    }
+
+    fn send_event<'a>(
+        &'a self,
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            crate::presence::ensure_active()?;
+            crate::pow::publish(self, None, event).await
+        })
+    }
+
+    fn send_event_to<'a>(
+        &'a self,
+        urls: &'a [String],
+        event: &'a Event,
+    ) -> BoxFuture<'a, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            crate::presence::ensure_active()?;
+            let urls = urls
+                .iter()
+                .map(|url| RelayUrl::parse(url))
+                .collect::<Result<Vec<_>, _>>()?;
+            crate::pow::publish(self, Some(urls), event).await
+        })
+    }
+
+    fn send_private_msg(
+        &self,
+        receiver: PublicKey,
+        message: String,
+        expire_after_secs: Option<u64>,
+    ) -> BoxFuture<'_, TransportResult<Output<EventId>>> {
+        Box::pin(async move {
+            crate::presence::ensure_active()?;
+            // Let nostr-sdk build plain messages itself unless we need to tag or mine the wrap
+            if expire_after_secs.is_none() && crate::pow::difficulty_for_client(self).await == 0 {
+                return Ok(Client::send_private_msg(self, receiver, message, []).await?);
+            }
+            let event =
+                prepare_private_msg(self, receiver, message, expire_after_secs, Vec::new()).await?;
+            crate::pow::publish(self, None, &event).await
+        })
+    }
+
+    fn subscribe_dms(
+        &self,
+        our_pubkey: PublicKey,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<UnwrappedGift>>> {
+        Box::pin(subscriptions::subscribe_dms(self, our_pubkey))
+    }
+
+    fn subscribe_events(
+        &self,
+        filters: Vec<Filter>,
+    ) -> BoxFuture<'_, TransportResult<mpsc::UnboundedReceiver<Event>>> {
+        Box::pin(async move {
+            let slot = subscriptions::acquire(self).await;
+            let mut notifications = self.notifications();
+            let mut subscription_ids = Vec::with_capacity(filters.len());
+            for filter in filters {
+                subscription_ids.push(self.subscribe(filter, None).await?.val);
+            }
+
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let client = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    let notification = tokio::select! {
+                        notification = notifications.recv() => notification,
+                        _ = sender.closed() => break,
+                    };
+                    let event = match notification {
+                        Ok(RelayPoolNotification::Event {
+                            subscription_id,
+                            event,
+                            ..
+                        }) if subscription_ids.contains(&subscription_id) => event,
+                        Ok(RelayPoolNotification::Shutdown) => break,
+                        Ok(_) => continue,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            log::warn!("Missed {} relay notifications", skipped);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if sender.send(*event).is_err() {
+                        break;
+                    }
+                }
+                for subscription_id in &subscription_ids {
+                    client.unsubscribe(subscription_id).await;
+                }
+                drop(slot);
+            });
+
+            Ok(receiver)
+        })
+    }
+
+    fn fetch_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<Vec<Event>>> {
+        Box::pin(async move {
+            let _slot = subscriptions::acquire(self).await;
+            Ok(Client::fetch_events(self, filter, timeout)
+                .await?
+                .into_iter()
+                .collect())
+        })
+    }
+
+    fn stream_events(
+        &self,
+        filter: Filter,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<Event>>> {
+        Box::pin(async move {
+            let slot = subscriptions::acquire(self).await;
+            let mut events = Client::stream_events(self, filter, timeout).await?;
+            let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+            tokio::spawn(async move {
+                while let Some(event) = events.next().await {
+                    if sender.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                drop(slot);
+            });
+            Ok(receiver)
+        })
+    }
+
+    fn stream_dms(
+        &self,
+        our_pubkey: PublicKey,
+        since: Timestamp,
+        timeout: Duration,
+    ) -> BoxFuture<'_, TransportResult<mpsc::Receiver<UnwrappedGift>>> {
+        Box::pin(async move {
+            // Gift wraps are backdated by up to two days (NIP-59), so the wrap filter reaches
+            // further back and the messages themselves are filtered once unwrapped
+            let filter = Filter::new()
+                .kind(Kind::GiftWrap)
+                .pubkey(our_pubkey)
+                .since(since - GIFT_WRAP_BACKDATE);
+            let slot = subscriptions::acquire(self).await;
+            let mut events = Client::stream_events(self, filter, timeout).await?;
+            let signer = self.signer().await?;
+            let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+            tokio::spawn(async move {
+                while let Some(event) = events.next().await {
+                    match unwrap_gift_wrap(&signer, &event).await {
+                        Ok(gift) if gift.rumor.created_at >= since => {
+                            if sender.send(gift).await.is_err() {
+                                break;
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => log::debug!("Skipping gift wrap {}: {}", event.id, e),
+                    }
+                }
+                drop(slot);
+            });
+            Ok(receiver)
+        })
+    }
+}
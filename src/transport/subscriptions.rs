@@ -0,0 +1,325 @@
+//! Every REQ the `Client` transport opens, counted per relay (`NPARROT_MAX_SUBSCRIPTIONS`)
+//!
+//! Relays cap how many subscriptions a connection may hold open and quietly ignore REQs past
+//! the cap, so with the inbox, memory fetches, history, zap receipts and relay list lookups all
+//! running at once some features used to receive nothing. A REQ now takes a slot on each relay
+//! of its client for as long as it is open, and waits while any of them is full. The cap is the
+//! relay's NIP-11 `max_subscriptions`, or the configured default for relays that publish none.
+//! DM listeners of one identity share a single gift wrap subscription that fans events out to
+//! each of them, and fetches give their slot back as soon as the relays have sent EOSE. Replayed
+//! rumors (see `replay`) are dropped there, before any listener sees them.
+
+use crate::relays;
+use crate::replay;
+use crate::utils::unwrap_gift_wrap;
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Notify};
+
+use super::TransportResult;
+
+/// The cap assumed for relays that don't publish one
+pub const DEFAULT_MAX_SUBSCRIPTIONS: usize = 20;
+
+/// How often the shared DM subscription checks whether anyone is still listening
+const LISTENER_CHECK: Duration = Duration::from_secs(5);
+
+lazy_static::lazy_static! {
+    static ref DEFAULT_LIMIT: RwLock<usize> = RwLock::new(DEFAULT_MAX_SUBSCRIPTIONS);
+    static ref BUDGET: Budget = Budget::default();
+    /// The listeners of each identity's shared gift wrap subscription
+    static ref DM_LISTENERS: Mutex<HashMap<PublicKey, Vec<mpsc::UnboundedSender<UnwrappedGift>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Sets the cap for relays without a NIP-11 one; 0 means no cap
+pub fn set_default_limit(limit: usize) {
+    if let Ok(mut guard) = DEFAULT_LIMIT.write() {
+        *guard = limit;
+    }
+}
+
+fn limit(url: &str) -> usize {
+    relays::relay_info(url)
+        .and_then(|info| info.max_subscriptions)
+        .unwrap_or_else(|| DEFAULT_LIMIT.read().map(|guard| *guard).unwrap_or_default())
+}
+
+/// Subscriptions open and waiting on a relay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub open: usize,
+    pub queued: usize,
+}
+
+#[derive(Default)]
+struct Budget {
+    usage: Mutex<HashMap<String, Usage>>,
+    freed: Notify,
+}
+
+impl Budget {
+    /// Waits until every relay in `urls` has room under `limit` and takes a slot on each
+    async fn acquire(&'static self, urls: Vec<String>, limit: impl Fn(&str) -> usize) -> Slot {
+        let mut queued = false;
+        loop {
+            let freed = self.freed.notified();
+            tokio::pin!(freed);
+            freed.as_mut().enable();
+            {
+                let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+                let full: Vec<&String> = urls
+                    .iter()
+                    .filter(|url| {
+                        let cap = limit(url);
+                        cap > 0 && usage.get(*url).map_or(0, |usage| usage.open) >= cap
+                    })
+                    .collect();
+                if full.is_empty() {
+                    for url in &urls {
+                        let entry = usage.entry(url.clone()).or_default();
+                        entry.open += 1;
+                        if queued {
+                            entry.queued -= 1;
+                        }
+                    }
+                    return Slot { budget: self, urls };
+                }
+                if !queued {
+                    log::info!(
+                        "Subscription limit reached on {}, queueing the REQ",
+                        full.iter()
+                            .map(|url| url.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                    for url in &urls {
+                        usage.entry(url.clone()).or_default().queued += 1;
+                    }
+                    queued = true;
+                }
+            }
+            freed.await;
+        }
+    }
+
+    fn usage(&self) -> HashMap<String, Usage> {
+        self.usage
+            .lock()
+            .map(|usage| usage.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// A subscription's place on its relays, given back when dropped
+pub struct Slot {
+    budget: &'static Budget,
+    urls: Vec<String>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut usage = self.budget.usage.lock().unwrap_or_else(|e| e.into_inner());
+        for url in &self.urls {
+            if let Some(entry) = usage.get_mut(url) {
+                entry.open = entry.open.saturating_sub(1);
+            }
+        }
+        drop(usage);
+        self.budget.freed.notify_waiters();
+    }
+}
+
+/// Takes a slot on every relay of `client`, waiting while any of them is at its cap
+pub async fn acquire(client: &Client) -> Slot {
+    let urls = client
+        .relays()
+        .await
+        .into_keys()
+        .map(|url| url.as_str().trim_end_matches('/').to_string())
+        .collect();
+    BUDGET.acquire(urls, limit).await
+}
+
+/// The subscriptions open and queued on `url`
+pub fn usage(url: &str) -> Usage {
+    BUDGET
+        .usage()
+        .get(url.trim_end_matches('/'))
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Streams the gift wraps addressed to `our_pubkey`, unwrapped, over the identity's one shared
+/// subscription, which is opened for the first listener and closed once the last one is gone
+pub async fn subscribe_dms(
+    client: &Client,
+    our_pubkey: PublicKey,
+) -> TransportResult<mpsc::UnboundedReceiver<UnwrappedGift>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    {
+        let mut listeners = DM_LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(listeners) = listeners.get_mut(&our_pubkey) {
+            listeners.push(sender);
+            return Ok(receiver);
+        }
+        listeners.insert(our_pubkey, vec![sender]);
+    }
+    if let Err(e) = start_dm_subscription(client, our_pubkey).await {
+        if let Ok(mut listeners) = DM_LISTENERS.lock() {
+            listeners.remove(&our_pubkey);
+        }
+        return Err(e);
+    }
+    Ok(receiver)
+}
+
+/// Hands `gift` to every listener of `our_pubkey`; false once none is left, which also ends
+/// the subscription for good
+fn fan_out(our_pubkey: &PublicKey, gift: Option<&UnwrappedGift>) -> bool {
+    let mut listeners = DM_LISTENERS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(current) = listeners.get_mut(our_pubkey) else {
+        return false;
+    };
+    match gift {
+        Some(gift) => current.retain(|listener| listener.send(gift.clone()).is_ok()),
+        None => current.retain(|listener| !listener.is_closed()),
+    }
+    if current.is_empty() {
+        listeners.remove(our_pubkey);
+        return false;
+    }
+    true
+}
+
+/// Gives `gift` its rumor id and checks it against replays; false if it is to be dropped
+pub fn admit(gift: &mut UnwrappedGift, relay_url: &RelayUrl) -> bool {
+    gift.rumor.ensure_id();
+    replay::check(&gift.rumor, relay_url).is_ok()
+}
+
+async fn start_dm_subscription(client: &Client, our_pubkey: PublicKey) -> TransportResult<()> {
+    let slot = acquire(client).await;
+    // Take the receiver before subscribing so nothing slips through in between
+    let mut notifications = client.notifications();
+    let filter = Filter::new()
+        .kind(Kind::GiftWrap)
+        .pubkey(our_pubkey)
+        .limit(0);
+    log::info!("Subscribing to GiftWrap events for pubkey: {}", our_pubkey);
+    let subscription_id = client.subscribe(filter, None).await?.val;
+    let signer = client.signer().await?;
+
+    let client = client.clone();
+    tokio::spawn(async move {
+        let mut check = tokio::time::interval(LISTENER_CHECK);
+        loop {
+            let notification = tokio::select! {
+                notification = notifications.recv() => notification,
+                _ = check.tick() => {
+                    if fan_out(&our_pubkey, None) {
+                        continue;
+                    }
+                    break;
+                }
+            };
+            let (relay_url, event) = match notification {
+                Ok(RelayPoolNotification::Event {
+                    relay_url, event, ..
+                }) => (relay_url, event),
+                Ok(RelayPoolNotification::Shutdown) => {
+                    if let Ok(mut listeners) = DM_LISTENERS.lock() {
+                        listeners.remove(&our_pubkey);
+                    }
+                    break;
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    log::warn!("Missed {} relay notifications", skipped);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if event.kind != Kind::GiftWrap {
+                continue;
+            }
+
+            log::debug!("Processing GiftWrap event {}", event.id);
+            match unwrap_gift_wrap(&signer, &event).await {
+                Ok(mut gift) => {
+                    if !admit(&mut gift, &relay_url) {
+                        continue;
+                    }
+                    if !fan_out(&our_pubkey, Some(&gift)) {
+                        break;
+                    }
+                }
+                Err(e) => log::warn!("Failed to unwrap gift wrap: {}", e),
+            }
+        }
+        client.unsubscribe(&subscription_id).await;
+        drop(slot);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget() -> &'static Budget {
+        Box::leak(Box::default())
+    }
+
+    #[tokio::test]
+    async fn test_requests_queue_at_the_relay_limit() {
+        let budget = budget();
+        let urls = vec!["wss://small.example".to_string()];
+        let cap = |_: &str| 2;
+        let first = budget.acquire(urls.clone(), cap).await;
+        let _second = budget.acquire(urls.clone(), cap).await;
+
+        let waiting = tokio::spawn(budget.acquire(urls.clone(), cap));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        assert_eq!(
+            budget.usage()["wss://small.example"],
+            Usage { open: 2, queued: 1 }
+        );
+
+        drop(first);
+        let third = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            budget.usage()["wss://small.example"],
+            Usage { open: 2, queued: 0 }
+        );
+        drop(third);
+        assert_eq!(budget.usage()["wss://small.example"].open, 1);
+    }
+
+    #[tokio::test]
+    async fn test_a_full_relay_holds_back_requests_to_all() {
+        let budget = budget();
+        let cap = |url: &str| if url.contains("small") { 1 } else { 0 };
+        let _small = budget
+            .acquire(vec!["wss://small.example".to_string()], cap)
+            .await;
+        let _uncapped = budget
+            .acquire(vec!["wss://large.example".to_string()], cap)
+            .await;
+
+        let both = vec![
+            "wss://large.example".to_string(),
+            "wss://small.example".to_string(),
+        ];
+        let waiting = tokio::spawn(budget.acquire(both, cap));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiting.is_finished());
+        assert_eq!(budget.usage()["wss://large.example"].open, 1);
+    }
+}
@@ -1,28 +1,52 @@
+use crate::ack;
+use crate::command_template::CommandTemplate;
+use crate::content_kind::{self, ContentKind};
+use crate::envelope::{Envelope, MessageType};
+use crate::filter::MessageFilter;
+use crate::language;
+use crate::output::status;
+use crate::pow;
 use crate::process_management;
+use crate::replay;
+use crate::selftest;
+use crate::shutdown::{Shutdown, GRACE_PERIOD};
+use crate::transcript;
+use crate::transport::DmTransport;
+use crate::zap;
 use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 
 /// Runs a shell command each time it receives a direct message
-pub async fn run_command_on_message(
-    client: &Client,
+pub async fn run_command_on_message<T: DmTransport + ?Sized>(
+    client: &T,
     our_pubkey: &PublicKey,
     sender_pubkey: &PublicKey,
     shell_command: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+    filter: Option<Arc<MessageFilter>>,
+    shutdown: &Shutdown,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Shared state for the current child process
     let process_handle: process_management::ChildHandle = Arc::new(Mutex::new(None));
-    let cmd = shell_command.to_string();
+    let cmd = Arc::new(CommandTemplate::parse(shell_command)?);
 
     // Build a callback that owns a clone of our shared state + command string
     let callback = {
         let handle_cloned = process_handle.clone();
-        move |decrypted_message: String| {
+        move |message: IncomingMessage| {
             let handle = handle_cloned.clone();
             let cmd = cmd.clone();
+            let filter = filter.clone();
             async move {
-                handle_message(&handle, &cmd, decrypted_message).await;
+                if filter.is_some_and(|filter| !filter.accepts(&message.content)) {
+                    return false;
+                }
+                handle_message(&handle, &cmd, message).await;
                 false // Never returns
             }
         }
@@ -31,18 +55,31 @@ pub async fn run_command_on_message(
     // We wrap the callback in a Mutex
     let callback_arc = Arc::new(Mutex::new(callback));
 
-    // Hand off to the listener
-    listen_for_messages(client, our_pubkey, sender_pubkey, callback_arc).await?;
+    // Hand off to the listener until we are asked to stop
+    tokio::select! {
+        result = listen_for_messages(client, our_pubkey, sender_pubkey, callback_arc) => result?,
+        _ = shutdown.requested() => {
+            status!("Shutting down, waiting for the running command to finish...");
+        }
+    }
+
+    let mut guard = process_handle.lock().await;
+    process_management::wait_or_kill(&mut guard, GRACE_PERIOD).await;
     Ok(())
 }
 
 /// This small message handler performs the “kill old, spawn new, store new” logic in one place.
-async fn handle_message(handle: &process_management::ChildHandle, cmd: &str, msg: String) {
+async fn handle_message(
+    handle: &process_management::ChildHandle,
+    template: &CommandTemplate,
+    message: IncomingMessage,
+) {
     let mut guard = handle.lock().await;
     process_management::kill_existing(&mut guard).await;
 
-    let bytes = msg.into_bytes();
-    match process_management::spawn_and_pipe(cmd, bytes) {
+    let cmd = template.render(&message);
+    let env = message.env_vars();
+    match process_management::spawn_and_pipe(&cmd, message.content.into_bytes(), &env) {
         Ok(child) => *guard = Some(child),
         Err(e) => {
             log::error!("Error spawning '{}': {}", cmd, e);
@@ -51,90 +88,250 @@ async fn handle_message(handle: &process_management::ChildHandle, cmd: &str, msg
     }
 }
 
+/// A decrypted DM as handed to listeners, with any envelope already unwrapped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IncomingMessage {
+    pub content: String,
+    /// Id of the rumor, the one replies (`send --reply-to`) refer to
+    pub event_id: EventId,
+    pub sender: PublicKey,
+    pub created_at: Timestamp,
+    /// Set only for enveloped messages
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub message_type: Option<MessageType>,
+    /// Set only for enveloped messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Map<String, Value>>,
+    /// ISO 639-1 code of the language the content is written in, or `unknown`
+    #[serde(default = "unknown_language")]
+    pub detected_language: String,
+    /// Ids of the messages merged into this one by the inbox, in order; empty otherwise
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fragment_ids: Vec<EventId>,
+    /// Whether the content is prose, JSON, code, a bare link or mentions `nostr:` entities
+    #[serde(default)]
+    pub content_kind: ContentKind,
+    /// One line per `nostr:` entity the content mentions, decoded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nostr_entities: Vec<String>,
+}
+
+fn unknown_language() -> String {
+    language::UNKNOWN.to_string()
+}
+
+impl IncomingMessage {
+    /// Takes the content of an authenticated rumor, unwrapping it if it is an envelope
+    pub fn from_rumor(mut rumor: UnsignedEvent) -> Self {
+        rumor.ensure_id();
+        let event_id = rumor.id.expect("ensure_id sets the id");
+        let (content, message_type, meta) = match Envelope::open(&rumor.content) {
+            Some(envelope) => (
+                envelope.body,
+                Some(envelope.message_type),
+                Some(envelope.meta),
+            ),
+            None => (rumor.content, None, None),
+        };
+        let (content_kind, nostr_entities) = content_kind::detect(&content);
+        Self {
+            detected_language: language::detect(&content).to_string(),
+            content,
+            event_id,
+            sender: rumor.pubkey,
+            created_at: rumor.created_at,
+            message_type,
+            meta,
+            fragment_ids: Vec::new(),
+            content_kind,
+            nostr_entities,
+        }
+    }
+
+    /// Envelope and language details for `Onmessage` commands
+    pub fn env_vars(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("NPARROT_DETECTED_LANGUAGE", self.detected_language.clone()),
+            ("NPARROT_CONVERSATION_LANGUAGE", language::conversation()),
+            (
+                "NPARROT_CONTENT_KIND",
+                self.content_kind.as_str().to_string(),
+            ),
+        ];
+        if let Some(message_type) = self.message_type {
+            vars.push(("NPARROT_MESSAGE_TYPE", message_type.as_str().to_string()));
+        }
+        if let Some(meta) = &self.meta {
+            vars.push((
+                "NPARROT_MESSAGE_META",
+                Value::Object(meta.clone()).to_string(),
+            ));
+        }
+        vars
+    }
+}
+
 /// Listens for Nostr messages (NIP-17 DMs) from a specific sender and calls a callback
-/// with the decrypted message content.
-pub async fn listen_for_messages<F, Fut>(
-    client: &Client,
+/// with each decrypted message until the callback returns true.
+pub async fn listen_for_messages<T, F, Fut>(
+    client: &T,
     our_pubkey: &PublicKey,
     sender_pubkey: &PublicKey,
     callback: Arc<Mutex<F>>,
-) -> Result<(), Box<dyn std::error::Error>>
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
 where
-    // Callback takes a String, returns a Future resolving to (), and is Send + Sync + 'static
-    F: Fn(String) -> Fut + Send + Sync + 'static,
+    T: DmTransport + ?Sized,
+    // Callback takes the message, returns a Future resolving to bool, and is Send + Sync + 'static
+    F: Fn(IncomingMessage) -> Fut + Send + Sync + 'static,
     Fut: Future<Output = bool> + Send + 'static,
 {
-    let subscription = Filter::new()
-        .kind(Kind::GiftWrap)
-        .pubkey(*our_pubkey)
-        .limit(0);
+    listen_for_messages_from(
+        client,
+        our_pubkey,
+        std::slice::from_ref(sender_pubkey),
+        callback,
+    )
+    .await
+}
 
-    log::info!("Subscribing to GiftWrap events for pubkey: {}", our_pubkey);
-    log::info!("Expected sender pubkey: {}", sender_pubkey);
-    client.subscribe(subscription, None).await?;
+/// Same as `listen_for_messages`, for DMs from any of `senders`; zaps are only taken from the
+/// first
+pub async fn listen_for_messages_from<T, F, Fut>(
+    client: &T,
+    our_pubkey: &PublicKey,
+    senders: &[PublicKey],
+    callback: Arc<Mutex<F>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    T: DmTransport + ?Sized,
+    F: Fn(IncomingMessage) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = bool> + Send + 'static,
+{
+    let Some(primary) = senders.first() else {
+        return Err("No sender to listen to".into());
+    };
+    log::info!(
+        "Expected sender pubkeys: {}",
+        senders
+            .iter()
+            .map(|sender| sender.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let mut messages = client.subscribe_dms(*our_pubkey).await?;
+    let mut zaps = match zap::subscribe(client, *our_pubkey, *primary).await {
+        Ok(zaps) => zaps,
+        Err(e) => {
+            log::warn!("Could not subscribe to zap receipts: {}", e);
+            None
+        }
+    };
 
-    let callback_clone = callback.clone();
-    client
-        .handle_notifications(move |notification| {
-            let callback_clone = callback_clone.clone();
-            let sender_pubkey = *sender_pubkey;
-            async move {
-                let event = match notification {
-                    RelayPoolNotification::Event { event, .. } => {
-                        log::debug!("Received event kind {} from {}", event.kind, event.pubkey);
-                        event
-                    }
-                    _ => {
-                        log::debug!("Non-event notification");
-                        return Ok(false);
-                    }
-                };
-
-                if event.kind != Kind::GiftWrap {
-                    return Ok(false);
+    loop {
+        let gift = tokio::select! {
+            gift = messages.recv() => gift,
+            Some(zap) = next_zap(&mut zaps) => {
+                log::info!("Received zap: {}", zap.content);
+                let guard = callback.lock().await;
+                if guard(zap).await {
+                    return Ok(());
                 }
+                continue;
+            }
+        };
+        let Some(gift) = gift else {
+            break;
+        };
+        log::debug!(
+            "Unwrapped gift from {} with kind {}",
+            gift.sender,
+            gift.rumor.kind
+        );
 
-                log::debug!("Processing GiftWrap event");
-                match client.unwrap_gift_wrap(&event).await {
-                    Ok(UnwrappedGift { rumor, sender }) => {
-                        log::debug!("Unwrapped gift from {} with kind {}", sender, rumor.kind);
-
-                        if sender == sender_pubkey && rumor.kind == Kind::PrivateDirectMessage {
-                            log::info!("Received DM from target sender: {}", rumor.content);
-                            let guard = callback_clone.lock().await;
-                            return Ok(guard(rumor.content).await);
-                        } else {
-                            log::debug!(
-                                "Ignoring message from {} (expected {})",
-                                sender,
-                                sender_pubkey
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        log::warn!("Failed to unwrap gift wrap: {}", e);
-                    }
-                }
+        if gift.rumor.kind != Kind::PrivateDirectMessage {
+            log::debug!("Ignoring rumor of kind {}", gift.rumor.kind);
+            continue;
+        }
+        if selftest::is_probe(&gift.rumor) {
+            log::debug!("Ignoring self-test probe");
+            continue;
+        }
+        if !is_authentic_dm_from(&gift, senders) {
+            continue;
+        }
+        // Only now that the sender is known is the rumor worth remembering
+        replay::record(&gift.rumor);
 
-                Ok(false)
-            }
-        })
-        .await?;
+        log::info!("Received DM from {}: {}", gift.sender, gift.rumor.content);
+        let message = IncomingMessage::from_rumor(gift.rumor);
+        transcript::received("main", message.event_id, &message.content);
+        language::observe(&message.detected_language);
+        // The read receipt goes out before any processing starts
+        ack::acknowledge(message.sender, message.event_id);
+        let guard = callback.lock().await;
+        if guard(message).await {
+            return Ok(());
+        }
+    }
 
     Ok(())
 }
 
+/// The next zap, pending forever without a zap subscription
+async fn next_zap(
+    zaps: &mut Option<tokio::sync::mpsc::UnboundedReceiver<IncomingMessage>>,
+) -> Option<IncomingMessage> {
+    match zaps {
+        Some(zaps) => zaps.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Accepts a DM only if the rumor was written by `expected` and sealed by that same key.
+///
+/// The rumor is unsigned, so its `pubkey` is only trustworthy once it matches the seal signer
+/// (NIP-59); anyone can otherwise gift-wrap a rumor claiming to come from `expected`.
+pub fn is_authentic_dm(gift: &UnwrappedGift, expected: &PublicKey) -> bool {
+    is_authentic_dm_from(gift, std::slice::from_ref(expected))
+}
+
+/// Same as `is_authentic_dm`, for a rumor written and sealed by any one of `expected`
+pub fn is_authentic_dm_from(gift: &UnwrappedGift, expected: &[PublicKey]) -> bool {
+    if gift.sender != gift.rumor.pubkey {
+        log::warn!(
+            "Dropping DM sealed by {} but claiming to be from {}",
+            gift.sender,
+            gift.rumor.pubkey
+        );
+        return false;
+    }
+    if !expected.contains(&gift.rumor.pubkey) {
+        log::warn!(
+            "Dropping DM from unexpected sender {} (expected {})",
+            gift.rumor.pubkey,
+            expected
+                .iter()
+                .map(|sender| sender.to_string())
+                .collect::<Vec<_>>()
+                .join(" or ")
+        );
+        return false;
+    }
+    true
+}
+
 /// Waits for a message from a specific user to our pubkey, and returns one once received
-pub async fn wait_for_message(
-    client: &Client,
+#[allow(dead_code)] // `Chat` keeps a standing subscription instead (see `mcp::inbox`)
+pub async fn wait_for_message<T: DmTransport + ?Sized>(
+    client: &T,
     our_pubkey: &PublicKey,
     from_user: &PublicKey,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<IncomingMessage, Box<dyn std::error::Error + Send + Sync>> {
     let message_mutex = Arc::new(Mutex::new(None));
 
     let message_callback = {
         let message_mutex = Arc::clone(&message_mutex);
-        move |message: String| {
+        move |message: IncomingMessage| {
             let message_mutex = Arc::clone(&message_mutex);
             async move {
                 let mut message_guard = message_mutex.lock().await;
@@ -159,3 +356,585 @@ pub async fn wait_for_message(
         .ok_or_else(|| std::io::Error::other("No message found"))?;
     Ok(result)
 }
+
+/// Waits for the next `count` messages from `from_user` on a single subscription, handing each
+/// to `on_message` as it arrives. Gives up after `timeout` if set and returns how many arrived.
+pub async fn wait_for_messages<T, F>(
+    client: &T,
+    our_pubkey: &PublicKey,
+    from_user: &PublicKey,
+    count: usize,
+    timeout: Option<Duration>,
+    on_message: F,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>>
+where
+    T: DmTransport + ?Sized,
+    F: Fn(IncomingMessage) + Send + Sync + 'static,
+{
+    if count == 0 {
+        return Ok(0);
+    }
+    let received = Arc::new(AtomicUsize::new(0));
+
+    let message_callback = {
+        let received = Arc::clone(&received);
+        let on_message = Arc::new(on_message);
+        move |message: IncomingMessage| {
+            let received = Arc::clone(&received);
+            let on_message = Arc::clone(&on_message);
+            async move {
+                on_message(message);
+                received.fetch_add(1, Ordering::SeqCst) + 1 >= count
+            }
+        }
+    };
+    let listening = listen_for_messages(
+        client,
+        our_pubkey,
+        from_user,
+        Arc::new(Mutex::new(message_callback)),
+    );
+
+    match timeout {
+        Some(timeout) => {
+            if let Ok(result) = tokio::time::timeout(timeout, listening).await {
+                result?;
+            }
+        }
+        None => listening.await?,
+    }
+    Ok(received.load(Ordering::SeqCst))
+}
+
+/// Sends a NIP-17 private message, optionally expiring after `expire_after_secs`.
+///
+/// The NIP-40 `expiration` tag goes on the outer gift wrap: the seal and rumor are
+/// encrypted, so the wrap is the only layer relays can actually see and honor. Relays that
+/// require NIP-13 proof of work get a wrap mined to their difficulty, the others one that
+/// isn't mined.
+pub async fn send_private_msg<T, S>(
+    client: &T,
+    receiver: PublicKey,
+    message: S,
+    expire_after_secs: Option<u64>,
+) -> Result<Output<EventId>, Box<dyn std::error::Error + Send + Sync>>
+where
+    T: DmTransport + ?Sized,
+    S: Into<String>,
+{
+    client
+        .send_private_msg(receiver, message.into(), expire_after_secs)
+        .await
+}
+
+/// Which layer of a NIP-59 gift wrap could not be opened, and why
+#[derive(Debug)]
+pub enum UnwrapError {
+    NotGiftWrap(Kind),
+    /// The outer wrap did not decrypt to a valid seal (usually: not addressed to us)
+    Wrap(String),
+    /// The seal did not decrypt to a rumor
+    Seal(String),
+}
+
+impl std::fmt::Display for UnwrapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotGiftWrap(kind) => write!(f, "not a gift wrap (kind {})", kind),
+            Self::Wrap(reason) => write!(f, "could not open the gift wrap: {}", reason),
+            Self::Seal(reason) => write!(f, "could not open the seal: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for UnwrapError {}
+
+/// Decrypts the outer gift wrap layer and returns the verified seal inside it
+pub async fn open_wrap<T>(signer: &T, gift_wrap: &Event) -> Result<Event, UnwrapError>
+where
+    T: NostrSigner + ?Sized,
+{
+    if gift_wrap.kind != Kind::GiftWrap {
+        return Err(UnwrapError::NotGiftWrap(gift_wrap.kind));
+    }
+    let json = signer
+        .nip44_decrypt(&gift_wrap.pubkey, &gift_wrap.content)
+        .await
+        .map_err(|e| UnwrapError::Wrap(e.to_string()))?;
+    let seal = Event::from_json(json).map_err(|e| UnwrapError::Wrap(e.to_string()))?;
+    seal.verify()
+        .map_err(|e| UnwrapError::Wrap(format!("invalid seal: {}", e)))?;
+    Ok(seal)
+}
+
+/// Decrypts a seal and returns the unsigned rumor inside it
+pub async fn open_seal<T>(signer: &T, seal: &Event) -> Result<UnsignedEvent, UnwrapError>
+where
+    T: NostrSigner + ?Sized,
+{
+    let json = signer
+        .nip44_decrypt(&seal.pubkey, &seal.content)
+        .await
+        .map_err(|e| UnwrapError::Seal(e.to_string()))?;
+    UnsignedEvent::from_json(json).map_err(|e| UnwrapError::Seal(e.to_string()))
+}
+
+/// Opens both layers of a gift wrap; the sender is the seal signer, which listeners still
+/// compare with the rumor author
+pub async fn unwrap_gift_wrap<T>(
+    signer: &T,
+    gift_wrap: &Event,
+) -> Result<UnwrappedGift, UnwrapError>
+where
+    T: NostrSigner + ?Sized,
+{
+    let seal = open_wrap(signer, gift_wrap).await?;
+    let rumor = open_seal(signer, &seal).await?;
+    Ok(UnwrappedGift {
+        sender: seal.pubkey,
+        rumor,
+    })
+}
+
+/// Rumor tags marking a message as a reply to `parent` (NIP-17)
+pub fn reply_tags(parent: EventId) -> Vec<Tag> {
+    vec![Tag::event(parent)]
+}
+
+/// Parses a hex or bech32 (`note1…`, `nevent1…`) event id
+pub fn parse_event_id(value: &str) -> Result<EventId, String> {
+    EventId::parse(value.trim()).map_err(|_| {
+        format!(
+            "Invalid event id '{}': expected hex or note1/nevent1",
+            value
+        )
+    })
+}
+
+/// Builds the gift wrap `send_private_msg` would publish, so callers can learn its id first
+pub async fn prepare_private_msg<S>(
+    client: &Client,
+    receiver: PublicKey,
+    message: S,
+    expire_after_secs: Option<u64>,
+    rumor_tags: Vec<Tag>,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>>
+where
+    S: Into<String>,
+{
+    prepare_private_msg_at(
+        client,
+        receiver,
+        message,
+        expire_after_secs,
+        rumor_tags,
+        Timestamp::now(),
+    )
+    .await
+}
+
+/// `prepare_private_msg` with the inner message dated `created_at`.
+///
+/// The wrap itself is never mined here: if some of the client's relays require proof of work,
+/// its seal is kept so `pow::publish` can send those relays a mined wrap of the same message.
+pub async fn prepare_private_msg_at<S>(
+    client: &Client,
+    receiver: PublicKey,
+    message: S,
+    expire_after_secs: Option<u64>,
+    rumor_tags: Vec<Tag>,
+    created_at: Timestamp,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>>
+where
+    S: Into<String>,
+{
+    let signer = client.signer().await?;
+    let seal = seal_private_msg(&signer, receiver, message, rumor_tags, created_at).await?;
+    let extra_tags = wrap_tags(expire_after_secs);
+    let wrap = wrap_seal(&seal, receiver, extra_tags.clone(), 0).await?;
+    if pow::difficulty_for_client(client).await > 0 {
+        pow::remember(
+            wrap.id,
+            pow::Sealed {
+                seal,
+                receiver,
+                tags: extra_tags,
+            },
+        );
+    }
+    Ok(wrap)
+}
+
+/// Builds a gift-wrapped private message, tagging the wrap with an expiration and mining
+/// it to `pow_difficulty` if requested
+#[cfg(test)]
+pub async fn build_private_msg<T, S>(
+    signer: &T,
+    receiver: PublicKey,
+    message: S,
+    expire_after_secs: Option<u64>,
+    rumor_tags: Vec<Tag>,
+    pow_difficulty: u8,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>>
+where
+    T: NostrSigner,
+    S: Into<String>,
+{
+    build_private_msg_at(
+        signer,
+        receiver,
+        message,
+        expire_after_secs,
+        rumor_tags,
+        pow_difficulty,
+        Timestamp::now(),
+    )
+    .await
+}
+
+/// Builds a gift-wrapped private message dated `created_at`, tagging the wrap with an expiration
+/// and mining it to `pow_difficulty` if requested. Clients order a conversation by the inner
+/// message's date; the wrap's own timestamp is randomized.
+#[cfg(test)]
+pub async fn build_private_msg_at<T, S>(
+    signer: &T,
+    receiver: PublicKey,
+    message: S,
+    expire_after_secs: Option<u64>,
+    rumor_tags: Vec<Tag>,
+    pow_difficulty: u8,
+    created_at: Timestamp,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>>
+where
+    T: NostrSigner,
+    S: Into<String>,
+{
+    let seal = seal_private_msg(signer, receiver, message, rumor_tags, created_at).await?;
+    wrap_seal(
+        &seal,
+        receiver,
+        wrap_tags(expire_after_secs),
+        pow_difficulty,
+    )
+    .await
+}
+
+/// Builds and signs the seal of a NIP-17 message dated `created_at`
+pub async fn seal_private_msg<T, S>(
+    signer: &T,
+    receiver: PublicKey,
+    message: S,
+    rumor_tags: Vec<Tag>,
+    created_at: Timestamp,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>>
+where
+    T: NostrSigner,
+    S: Into<String>,
+{
+    let public_key = signer.get_public_key().await?;
+    let mut rumor = EventBuilder::private_msg_rumor(receiver, message)
+        .tags(rumor_tags)
+        .custom_created_at(created_at)
+        .build(public_key);
+    // Replies reference the rumor id, so make sure the recipient gets one
+    rumor.ensure_id();
+    Ok(EventBuilder::seal(signer, &receiver, rumor)
+        .await?
+        .sign(signer)
+        .await?)
+}
+
+/// The tags of the outer gift wrap: the NIP-40 expiration, if any
+fn wrap_tags(expire_after_secs: Option<u64>) -> Vec<Tag> {
+    expire_after_secs
+        .map(|secs| expiration_tag(Timestamp::now(), secs))
+        .into_iter()
+        .collect()
+}
+
+/// Gift-wraps `seal` for `receiver`, mining the wrap to `pow_difficulty` if requested. Every
+/// call uses fresh wrap keys, so the same seal can be wrapped once per difficulty.
+pub async fn wrap_seal(
+    seal: &Event,
+    receiver: PublicKey,
+    extra_tags: Vec<Tag>,
+    pow_difficulty: u8,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>> {
+    if pow_difficulty == 0 {
+        return Ok(EventBuilder::gift_wrap_from_seal(
+            &receiver, seal, extra_tags,
+        )?);
+    }
+
+    // Same construction as `EventBuilder::gift_wrap_from_seal`, but the wrap is mined before
+    // signing
+    let wrap_keys = Keys::generate();
+    let content = nip44::encrypt(
+        wrap_keys.secret_key(),
+        &receiver,
+        seal.as_json(),
+        nip44::Version::default(),
+    )?;
+    let builder = EventBuilder::new(Kind::GiftWrap, content)
+        .tags(extra_tags)
+        .tag(Tag::public_key(receiver))
+        .custom_created_at(Timestamp::tweaked(nip59::RANGE_RANDOM_TIMESTAMP_TWEAK));
+
+    pow::mine(builder, wrap_keys, pow_difficulty).await
+}
+
+/// Builds a NIP-17 gift-wrapped NIP-25 reaction to the DM `message_id` from `receiver`
+pub async fn build_reaction<T>(
+    signer: &T,
+    receiver: PublicKey,
+    message_id: EventId,
+    reaction: &str,
+) -> Result<Event, Box<dyn std::error::Error + Send + Sync>>
+where
+    T: NostrSigner,
+{
+    let public_key = signer.get_public_key().await?;
+    let rumor = EventBuilder::new(Kind::Reaction, reaction)
+        .tag(Tag::event(message_id))
+        .tag(Tag::public_key(receiver))
+        .tag(Tag::custom(
+            TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::K)),
+            [Kind::PrivateDirectMessage.to_string()],
+        ))
+        .build(public_key);
+    Ok(EventBuilder::gift_wrap(signer, &receiver, rumor, []).await?)
+}
+
+/// Builds a NIP-40 expiration tag for `secs` seconds after `now`
+pub fn expiration_tag(now: Timestamp, secs: u64) -> Tag {
+    Tag::expiration(now + std::time::Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+
+    #[test]
+    fn test_parse_event_id() {
+        let hex = "8a612c6b8c09165fa41776cd4a9a0776278c20a0d5026f6eab95f0bc9bdf13cf";
+        let id = parse_event_id(hex).unwrap();
+        assert_eq!(parse_event_id(&id.to_bech32().unwrap()).unwrap(), id);
+        assert!(parse_event_id("8a612c6b").is_err());
+        assert!(parse_event_id("npub1xyz").is_err());
+        assert!(parse_event_id("").is_err());
+    }
+
+    #[test]
+    fn test_expiration_tag_timestamp() {
+        let now = Timestamp::from_secs(1_700_000_000);
+        let tag = expiration_tag(now, 86_400);
+        assert_eq!(tag.kind(), TagKind::Expiration);
+        assert_eq!(tag.content(), Some("1700086400"));
+    }
+
+    #[tokio::test]
+    async fn test_expiration_on_outer_gift_wrap() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+
+        let before = Timestamp::now();
+        let event = build_private_msg(
+            &sender,
+            receiver.public_key(),
+            "progress",
+            Some(3600),
+            vec![],
+            0,
+        )
+        .await
+        .unwrap();
+        let after = Timestamp::now();
+
+        assert_eq!(event.kind, Kind::GiftWrap);
+        let expiration = event
+            .tags
+            .expiration()
+            .expect("expiration tag on gift wrap");
+        assert!(expiration.as_u64() >= before.as_u64() + 3600);
+        assert!(expiration.as_u64() <= after.as_u64() + 3600);
+
+        let unwrapped = UnwrappedGift::from_gift_wrap(&receiver, &event)
+            .await
+            .unwrap();
+        assert_eq!(unwrapped.sender, sender.public_key());
+        assert_eq!(unwrapped.rumor.content, "progress");
+    }
+
+    #[tokio::test]
+    async fn test_no_expiration_by_default() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+
+        let event = build_private_msg(&sender, receiver.public_key(), "hello", None, vec![], 0)
+            .await
+            .unwrap();
+
+        assert!(event.tags.expiration().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mined_gift_wrap_still_unwraps() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+
+        let event = build_private_msg(&sender, receiver.public_key(), "mined", Some(60), vec![], 8)
+            .await
+            .unwrap();
+
+        assert_eq!(event.kind, Kind::GiftWrap);
+        assert!(event.id.check_pow(8));
+        assert!(event.tags.expiration().is_some());
+
+        let unwrapped = UnwrappedGift::from_gift_wrap(&receiver, &event)
+            .await
+            .unwrap();
+        assert_eq!(unwrapped.sender, sender.public_key());
+        assert_eq!(unwrapped.rumor.content, "mined");
+    }
+
+    #[tokio::test]
+    async fn test_one_seal_wraps_for_each_difficulty() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let seal = seal_private_msg(
+            &sender,
+            receiver.public_key(),
+            "per relay",
+            vec![],
+            Timestamp::now(),
+        )
+        .await
+        .unwrap();
+
+        let plain = wrap_seal(&seal, receiver.public_key(), vec![], 0)
+            .await
+            .unwrap();
+        let mined = wrap_seal(&seal, receiver.public_key(), vec![], 8)
+            .await
+            .unwrap();
+        assert_ne!(plain.id, mined.id);
+        assert!(mined.id.check_pow(8));
+
+        let plain = UnwrappedGift::from_gift_wrap(&receiver, &plain)
+            .await
+            .unwrap();
+        let mined = UnwrappedGift::from_gift_wrap(&receiver, &mined)
+            .await
+            .unwrap();
+        assert_eq!(plain.rumor.id, mined.rumor.id);
+        assert_eq!(mined.rumor.content, "per relay");
+    }
+
+    fn forged_gift(sealed_by: &Keys, claimed_author: &Keys, receiver: &Keys) -> UnwrappedGift {
+        UnwrappedGift {
+            sender: sealed_by.public_key(),
+            rumor: EventBuilder::private_msg_rumor(receiver.public_key(), "rm -rf ~")
+                .build(claimed_author.public_key()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_rejects_forged_rumor_author() {
+        let us = Keys::generate();
+        let target = Keys::generate();
+        let attacker = Keys::generate();
+        let transport = FakeTransport::new(us.clone());
+
+        // Sealed by the attacker but claiming the target wrote it
+        transport.inject_gift(forged_gift(&attacker, &target, &us));
+        // Sealed by the target but carrying someone else's rumor
+        transport.inject_gift(forged_gift(&target, &attacker, &us));
+        // Honest DM from a stranger
+        transport.inject(&attacker, "hello");
+        transport.inject(&target, "the real one");
+
+        let message = wait_for_message(&transport, &us.public_key(), &target.public_key())
+            .await
+            .unwrap();
+        assert_eq!(message.content, "the real one");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_messages_counts_and_times_out() {
+        let us = Keys::generate();
+        let target = Keys::generate();
+        let transport = FakeTransport::new(us.clone());
+        for content in ["one", "two", "three", "four"] {
+            transport.inject(&target, content);
+        }
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let record = {
+            let seen = Arc::clone(&seen);
+            move |message: IncomingMessage| seen.lock().unwrap().push(message.content)
+        };
+        let received = wait_for_messages(
+            &transport,
+            &us.public_key(),
+            &target.public_key(),
+            3,
+            None,
+            record.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(received, 3);
+        assert_eq!(*seen.lock().unwrap(), ["one", "two", "three"]);
+
+        // Only one more is coming, so asking for two runs into the timeout
+        seen.lock().unwrap().clear();
+        transport.inject(&target, "five");
+        let received = wait_for_messages(
+            &transport,
+            &us.public_key(),
+            &target.public_key(),
+            2,
+            Some(Duration::from_secs(30)),
+            record,
+        )
+        .await
+        .unwrap();
+        assert_eq!(received, 1);
+        assert_eq!(*seen.lock().unwrap(), ["five"]);
+    }
+
+    #[test]
+    fn test_is_authentic_dm() {
+        let us = Keys::generate();
+        let target = Keys::generate();
+        let attacker = Keys::generate();
+
+        assert!(is_authentic_dm(
+            &forged_gift(&target, &target, &us),
+            &target.public_key()
+        ));
+        assert!(!is_authentic_dm(
+            &forged_gift(&attacker, &target, &us),
+            &target.public_key()
+        ));
+        assert!(!is_authentic_dm(
+            &forged_gift(&target, &attacker, &us),
+            &target.public_key()
+        ));
+        assert!(!is_authentic_dm(
+            &forged_gift(&attacker, &attacker, &us),
+            &target.public_key()
+        ));
+
+        let allowed = [target.public_key(), attacker.public_key()];
+        assert!(is_authentic_dm_from(
+            &forged_gift(&attacker, &attacker, &us),
+            &allowed
+        ));
+        assert!(!is_authentic_dm_from(
+            &forged_gift(&attacker, &target, &us),
+            &allowed
+        ));
+    }
+}
@@ -0,0 +1,475 @@
+//! Paying small invoices through Nostr Wallet Connect (NIP-47)
+//!
+//! With `NWC_URI` set, the combined server gains `pay_invoice` and `get_balance`; without it
+//! they are not even listed. Every payment has to fit under `--wallet-max-payment` and, together
+//! with the payments of the last 24 hours, under `--wallet-daily-budget`. The amount is reserved
+//! before the payment is reported on the progress channel and only then sent to the wallet, and
+//! the ledger lives in the data dir so a restart doesn't reset the daily budget.
+
+use crate::error::NparrotError;
+use crate::transport::DmTransport;
+use crate::zap::invoice_amount_msats;
+use nostr_sdk::nips::nip47::{
+    self, ErrorCode, NostrWalletConnectURI, PayInvoiceRequest, Request, Response,
+};
+use nostr_sdk::prelude::*;
+use rmcp::schemars;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct PayRequest {
+    #[schemars(description = "The BOLT11 lightning invoice to pay")]
+    pub invoice: String,
+    #[serde(default)]
+    #[schemars(
+        description = "Amount in sats; required for invoices without an amount, and must match the invoice otherwise"
+    )]
+    pub amount_sats: Option<u64>,
+}
+
+/// Tools that only exist with a wallet configured
+pub const TOOLS: &[&str] = &["pay_invoice", "get_balance"];
+const DAY_SECS: u64 = 24 * 60 * 60;
+/// How long to wait for the wallet service to answer a request
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Budget {
+    pub max_payment_sats: u64,
+    pub daily_sats: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PaymentStatus {
+    /// Reserved, or sent to the wallet without a definite answer
+    Pending,
+    Paid,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LedgerEntry {
+    invoice: String,
+    amount_msats: u64,
+    at: u64,
+    status: PaymentStatus,
+}
+
+/// A payment that passed the budget checks and has its amount reserved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    pub invoice: String,
+    pub amount_msats: u64,
+    /// Set for invoices without an amount, which the wallet has to be told
+    amount_override: bool,
+}
+
+/// Payments of the last day, persisted so restarts don't reset the budget.
+///
+/// A ledger that exists but can't be read fails closed: every payment is refused and the file
+/// is left alone until it reads again, rather than starting over with nothing spent.
+#[derive(Debug)]
+struct Ledger {
+    path: PathBuf,
+    budget: Budget,
+    /// Why the file couldn't be read, instead of the entries
+    entries: Mutex<Result<Vec<LedgerEntry>, String>>,
+}
+
+impl Ledger {
+    fn load(path: PathBuf, budget: Budget) -> Self {
+        let entries = Self::read(&path);
+        if let Err(e) = &entries {
+            log::error!(
+                "Payment ledger {} is unreadable, no payments are made until it is fixed: {}",
+                path.display(),
+                e
+            );
+        }
+        Self {
+            path,
+            budget,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    fn read(path: &Path) -> Result<Vec<LedgerEntry>, String> {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Amount paid or possibly paid in the 24 hours before `now`
+    fn spent_msats(entries: &[LedgerEntry], now: u64) -> u64 {
+        entries
+            .iter()
+            .filter(|entry| entry.status != PaymentStatus::Failed)
+            .filter(|entry| entry.at + DAY_SECS > now)
+            .map(|entry| entry.amount_msats)
+            .sum()
+    }
+
+    fn reserve(&self, invoice: &str, amount_msats: u64, now: u64) -> Result<(), NparrotError> {
+        let max_payment_msats = self.budget.max_payment_sats.saturating_mul(1000);
+        if amount_msats > max_payment_msats {
+            return Err(NparrotError::invalid_params(
+                "invoice",
+                format!(
+                    "{} sats is more than the {} sats allowed per payment",
+                    amount_msats.div_ceil(1000),
+                    self.budget.max_payment_sats
+                ),
+            ));
+        }
+        let mut ledger = self
+            .entries
+            .lock()
+            .map_err(|_| NparrotError::internal("Payment ledger unavailable"))?;
+        if ledger.is_err() {
+            // It may have been fixed in the meantime
+            *ledger = Self::read(&self.path);
+        }
+        let entries = ledger.as_mut().map_err(|e| {
+            NparrotError::internal(format!(
+                "The payment ledger {} is unreadable, so nothing is paid: {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+        entries.retain(|entry| entry.at + DAY_SECS > now);
+        if entries
+            .iter()
+            .any(|entry| entry.invoice == invoice && entry.status != PaymentStatus::Failed)
+        {
+            return Err(NparrotError::invalid_params(
+                "invoice",
+                "This invoice was already paid or is being paid",
+            ));
+        }
+        let spent = Self::spent_msats(entries, now);
+        if spent + amount_msats > self.budget.daily_sats.saturating_mul(1000) {
+            return Err(NparrotError::rate_limited(format!(
+                "Paying {} sats would exceed the daily budget of {} sats ({} sats spent in the last 24 hours)",
+                amount_msats.div_ceil(1000),
+                self.budget.daily_sats,
+                spent.div_ceil(1000)
+            )));
+        }
+        entries.push(LedgerEntry {
+            invoice: invoice.to_string(),
+            amount_msats,
+            at: now,
+            status: PaymentStatus::Pending,
+        });
+        self.save(entries);
+        Ok(())
+    }
+
+    fn settle(&self, invoice: &str, status: PaymentStatus) {
+        let Ok(mut ledger) = self.entries.lock() else {
+            return;
+        };
+        let Ok(entries) = ledger.as_mut() else {
+            return;
+        };
+        if let Some(entry) = entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.invoice == invoice && entry.status == PaymentStatus::Pending)
+        {
+            entry.status = status;
+        }
+        self.save(entries);
+    }
+
+    /// `None` while the ledger is unreadable
+    fn spent_today_msats(&self, now: u64) -> Option<u64> {
+        let ledger = self.entries.lock().ok()?;
+        ledger
+            .as_ref()
+            .ok()
+            .map(|entries| Self::spent_msats(entries, now))
+    }
+
+    fn save(&self, entries: &[LedgerEntry]) {
+        let result = (|| -> std::io::Result<()> {
+            if let Some(parent) = self.path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = self.path.with_extension("json.tmp");
+            std::fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+            std::fs::rename(tmp, &self.path)
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to save payment ledger: {}", e);
+        }
+    }
+}
+
+/// What to pay for `invoice`: its own amount, or `amount_sats` for an invoice without one.
+/// Both at once must agree, so an agent can't mistake what it is paying.
+pub fn payment_amount_msats(
+    invoice: &str,
+    amount_sats: Option<u64>,
+) -> Result<(u64, bool), NparrotError> {
+    let invoice_msats = invoice_amount_msats(invoice)
+        .map_err(|e| NparrotError::invalid_params("invoice", format!("Invalid invoice: {}", e)))?;
+    let requested_msats = match amount_sats {
+        Some(0) => {
+            return Err(NparrotError::invalid_params(
+                "amount_sats",
+                "amount_sats must be more than 0",
+            ))
+        }
+        Some(sats) => Some(sats.checked_mul(1000).ok_or_else(|| {
+            NparrotError::invalid_params("amount_sats", "amount_sats is too large")
+        })?),
+        None => None,
+    };
+    match (invoice_msats, requested_msats) {
+        (Some(invoice_msats), None) => Ok((invoice_msats, false)),
+        (Some(invoice_msats), Some(requested)) if requested == invoice_msats => {
+            Ok((invoice_msats, false))
+        }
+        (Some(invoice_msats), Some(requested)) => Err(NparrotError::invalid_params(
+            "amount_sats",
+            format!(
+                "The invoice is for {} msats, not {} msats",
+                invoice_msats, requested
+            ),
+        )),
+        (None, Some(requested)) => Ok((requested, true)),
+        (None, None) => Err(NparrotError::invalid_params(
+            "amount_sats",
+            "The invoice has no amount; pass amount_sats to pay it",
+        )),
+    }
+}
+
+#[derive(Debug)]
+pub struct Wallet {
+    uri: NostrWalletConnectURI,
+    client: Client,
+    ledger: Ledger,
+}
+
+impl Wallet {
+    /// Connects to the wallet service's relays with the connection's own key
+    pub async fn connect(
+        uri: &str,
+        data_dir: &str,
+        budget: Budget,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let uri = NostrWalletConnectURI::parse(uri)
+            .map_err(|e| std::io::Error::other(format!("Invalid NWC_URI: {}", e)))?;
+        let client = Client::new(Keys::new(uri.secret.clone()));
+        for relay in &uri.relays {
+            client.add_relay(relay).await?;
+        }
+        client.connect().await;
+        Ok(Self {
+            uri,
+            client,
+            ledger: Ledger::load(Path::new(data_dir).join("payments.json"), budget),
+        })
+    }
+
+    /// Checks `invoice` against the budget and reserves its amount; nothing is paid yet
+    pub fn authorize(
+        &self,
+        invoice: &str,
+        amount_sats: Option<u64>,
+    ) -> Result<Payment, NparrotError> {
+        let invoice = invoice.trim();
+        let invoice = invoice.strip_prefix("lightning:").unwrap_or(invoice);
+        let (amount_msats, amount_override) = payment_amount_msats(invoice, amount_sats)?;
+        self.ledger
+            .reserve(invoice, amount_msats, Timestamp::now().as_u64())?;
+        log::info!("Authorized payment of {} msats", amount_msats);
+        Ok(Payment {
+            invoice: invoice.to_string(),
+            amount_msats,
+            amount_override,
+        })
+    }
+
+    /// Releases the reservation of a payment that will not be made
+    pub fn cancel(&self, payment: &Payment) {
+        log::info!("Cancelled payment of {} msats", payment.amount_msats);
+        self.ledger.settle(&payment.invoice, PaymentStatus::Failed);
+    }
+
+    /// Has the wallet pay an authorized payment; returns the preimage
+    pub async fn pay(&self, payment: &Payment) -> Result<String, NparrotError> {
+        let mut request = PayInvoiceRequest::new(payment.invoice.clone());
+        if payment.amount_override {
+            request.amount = Some(payment.amount_msats);
+        }
+        log::info!("Paying {} msats via NWC", payment.amount_msats);
+        let response = match self.request(Request::pay_invoice(request)).await {
+            Ok(response) => response,
+            Err(e) => {
+                // Without an answer the payment may still go through, so it stays counted
+                log::warn!("No definite answer to payment: {}", e);
+                return Err(e.context("The payment may or may not have been made"));
+            }
+        };
+        match response.to_pay_invoice() {
+            Ok(paid) => {
+                log::info!("Paid {} msats", payment.amount_msats);
+                self.ledger.settle(&payment.invoice, PaymentStatus::Paid);
+                Ok(paid.preimage)
+            }
+            Err(e) => {
+                log::warn!("Payment failed: {}", e);
+                // Only a refusal from the wallet means nothing was paid
+                if matches!(e, nip47::Error::ErrorCode(_)) {
+                    self.ledger.settle(&payment.invoice, PaymentStatus::Failed);
+                }
+                Err(wallet_error(e))
+            }
+        }
+    }
+
+    pub async fn balance_msats(&self) -> Result<u64, NparrotError> {
+        self.request(Request::get_balance())
+            .await?
+            .to_get_balance()
+            .map(|balance| balance.balance)
+            .map_err(wallet_error)
+    }
+
+    /// `None` while the payment ledger is unreadable, when no payment is made
+    pub fn spent_today_msats(&self) -> Option<u64> {
+        self.ledger.spent_today_msats(Timestamp::now().as_u64())
+    }
+
+    async fn request(&self, request: Request) -> Result<Response, NparrotError> {
+        let event = request
+            .to_event(&self.uri)
+            .map_err(|e| NparrotError::internal(e.to_string()))?;
+        let filter = Filter::new()
+            .kind(Kind::WalletConnectResponse)
+            .author(self.uri.public_key)
+            .event(event.id);
+        let mut responses = self
+            .client
+            .subscribe_events(vec![filter])
+            .await
+            .map_err(|e| NparrotError::relay_unavailable(e.to_string()))?;
+        self.client
+            .send_event(&event)
+            .await
+            .map_err(|e| NparrotError::relay_unavailable(e.to_string()))?;
+
+        let response = tokio::time::timeout(RESPONSE_TIMEOUT, responses.recv())
+            .await
+            .map_err(|_| NparrotError::timeout("The wallet did not answer in time"))?
+            .ok_or_else(|| NparrotError::relay_unavailable("The wallet subscription ended"))?;
+        Response::from_event(&self.uri, &response).map_err(|e| {
+            NparrotError::internal(format!("Unreadable answer from the wallet: {}", e))
+        })
+    }
+}
+
+fn wallet_error(error: nip47::Error) -> NparrotError {
+    match error {
+        nip47::Error::ErrorCode(error) => match error.code {
+            ErrorCode::RateLimited => NparrotError::rate_limited(error.message),
+            ErrorCode::NotImplemented | ErrorCode::Restricted | ErrorCode::Unauthorized => {
+                NparrotError::backend_missing("nwc", error.to_string())
+            }
+            _ => NparrotError::internal(format!("The wallet refused: {}", error)),
+        },
+        error => NparrotError::internal(format!("The wallet refused: {}", error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger(dir: &tempfile::TempDir) -> Ledger {
+        Ledger::load(
+            dir.path().join("payments.json"),
+            Budget {
+                max_payment_sats: 100,
+                daily_sats: 150,
+            },
+        )
+    }
+
+    #[test]
+    fn test_payment_amount_needs_no_guessing() {
+        assert_eq!(
+            payment_amount_msats("lnbc500n1pvjluez", None).unwrap(),
+            (50_000, false)
+        );
+        assert_eq!(
+            payment_amount_msats("lnbc500n1pvjluez", Some(50)).unwrap(),
+            (50_000, false)
+        );
+        assert!(payment_amount_msats("lnbc500n1pvjluez", Some(60)).is_err());
+        // Zero-amount invoices only with an explicit amount
+        assert!(payment_amount_msats("lnbc1pvjluez", None).is_err());
+        assert!(payment_amount_msats("lnbc1pvjluez", Some(0)).is_err());
+        assert_eq!(
+            payment_amount_msats("lnbc1pvjluez", Some(21)).unwrap(),
+            (21_000, true)
+        );
+    }
+
+    #[test]
+    fn test_budget_survives_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = 1_700_000_000;
+        let first = ledger(&dir);
+        assert_eq!(
+            first.reserve("a", 101_000, now).unwrap_err().code(),
+            "invalid_params"
+        );
+        first.reserve("a", 100_000, now).unwrap();
+        assert!(first.reserve("a", 100_000, now).is_err());
+        first.settle("a", PaymentStatus::Paid);
+
+        let restarted = ledger(&dir);
+        assert_eq!(
+            restarted.reserve("b", 60_000, now + 60).unwrap_err().code(),
+            "rate_limited"
+        );
+        // Failed payments don't count, and a day later the budget is back
+        restarted.reserve("c", 50_000, now + 60).unwrap();
+        restarted.settle("c", PaymentStatus::Failed);
+        restarted.reserve("d", 50_000, now + 60).unwrap();
+        restarted.reserve("b", 60_000, now + DAY_SECS).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_ledger_refuses_payments_and_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("payments.json");
+        std::fs::write(&path, b"[{\"invoice\": \"a\", trunc").unwrap();
+        let now = 1_700_000_000;
+
+        let broken = ledger(&dir);
+        assert_eq!(broken.spent_today_msats(now), None);
+        let refused = broken.reserve("b", 1_000, now).unwrap_err();
+        assert!(refused.to_string().contains("unreadable"));
+        broken.settle("b", PaymentStatus::Paid);
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"[{\"invoice\": \"a\", trunc"
+        );
+
+        // Once the file is repaired, payments go through again without a restart
+        std::fs::write(&path, b"[]").unwrap();
+        broken.reserve("b", 1_000, now).unwrap();
+        assert_eq!(broken.spent_today_msats(now), Some(1_000));
+    }
+}
@@ -0,0 +1,456 @@
+//! Zaps (NIP-57): hearing about the user's zaps and asking them for one
+//!
+//! With `--lud16` set, the listeners also take zap receipts (kind 9735) sent to us and hand them
+//! on as messages of type `zap`. A receipt only counts if our lightning address provider signed
+//! it and it carries the sender's signed zap request; its amount comes from the paid invoice.
+//! The `request_zap` tool fetches an invoice from the same provider and DMs it to the user.
+
+use crate::content_kind::ContentKind;
+use crate::envelope::MessageType;
+use crate::error::NparrotError;
+use crate::language;
+use crate::transport::{DmTransport, TransportResult};
+use crate::utils::IncomingMessage;
+use nostr_sdk::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MSATS_PER_BTC: u64 = 100_000_000_000;
+
+lazy_static::lazy_static! {
+    static ref PROVIDER: RwLock<Option<Provider>> = RwLock::new(None);
+}
+
+/// The LNURL-pay endpoint behind a lightning address, as far as we use it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPay {
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    #[serde(default)]
+    pub comment_allowed: usize,
+    #[serde(default)]
+    pub allows_nostr: bool,
+    /// Signs the zap receipts, if the provider supports zaps
+    pub nostr_pubkey: Option<PublicKey>,
+}
+
+/// Our lightning address and its resolved endpoint
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub address: String,
+    pub pay: LnurlPay,
+}
+
+impl Provider {
+    pub async fn resolve(address: &str) -> Result<Self, NparrotError> {
+        let url = endpoint(address)?;
+        let pay: LnurlPay = get_json(&url).await?;
+        Ok(Self {
+            address: address.to_string(),
+            pay,
+        })
+    }
+
+    /// The key zap receipts must be signed with, `None` if the provider doesn't do zaps
+    pub fn receipt_signer(&self) -> Option<PublicKey> {
+        self.pay.nostr_pubkey.filter(|_| self.pay.allows_nostr)
+    }
+
+    /// Asks the provider for an invoice of `amount_msats`, with `memo` as the LNURL comment when
+    /// it fits
+    pub async fn invoice(
+        &self,
+        amount_msats: u64,
+        memo: Option<&str>,
+    ) -> Result<String, NparrotError> {
+        if amount_msats < self.pay.min_sendable || amount_msats > self.pay.max_sendable {
+            return Err(NparrotError::invalid_params(
+                "amount_sats",
+                format!(
+                    "{} accepts {} to {} sats",
+                    self.address,
+                    self.pay.min_sendable.div_ceil(1000),
+                    self.pay.max_sendable / 1000
+                ),
+            ));
+        }
+        let mut url = reqwest::Url::parse(&self.pay.callback).map_err(|e| {
+            NparrotError::internal(format!(
+                "Invalid LNURL callback {}: {}",
+                self.pay.callback, e
+            ))
+        })?;
+        url.query_pairs_mut()
+            .append_pair("amount", &amount_msats.to_string());
+        if let Some(memo) = memo.filter(|memo| memo.chars().count() <= self.pay.comment_allowed) {
+            url.query_pairs_mut().append_pair("comment", memo);
+        }
+
+        #[derive(Deserialize)]
+        struct Invoice {
+            pr: Option<String>,
+            reason: Option<String>,
+        }
+        let invoice: Invoice = get_json(url.as_str()).await?;
+        let invoice = invoice.pr.ok_or_else(|| {
+            NparrotError::internal(format!(
+                "{} returned no invoice: {}",
+                self.address,
+                invoice.reason.unwrap_or_default()
+            ))
+        })?;
+        // Never pass on an invoice for a different amount than the one asked for
+        match invoice_amount_msats(&invoice) {
+            Ok(Some(amount)) if amount == amount_msats => Ok(invoice),
+            Ok(amount) => Err(NparrotError::internal(format!(
+                "{} returned an invoice for {:?} msats instead of {}",
+                self.address, amount, amount_msats
+            ))),
+            Err(e) => Err(NparrotError::internal(format!(
+                "{} returned an invalid invoice: {}",
+                self.address, e
+            ))),
+        }
+    }
+}
+
+/// Installs the process-wide lightning address provider
+pub fn set_provider(provider: Option<Provider>) {
+    if let Ok(mut guard) = PROVIDER.write() {
+        *guard = provider;
+    }
+}
+
+pub fn provider() -> Option<Provider> {
+    PROVIDER.read().ok()?.clone()
+}
+
+/// The LNURL-pay URL of a lightning address (`name@domain`), or the address itself if it
+/// already is an https URL
+pub fn endpoint(address: &str) -> Result<String, NparrotError> {
+    let address = address.trim();
+    if address.starts_with("https://") {
+        return Ok(address.to_string());
+    }
+    match address.split_once('@') {
+        Some((name, domain)) if !name.is_empty() && !domain.is_empty() && !domain.contains('/') => {
+            Ok(format!(
+                "https://{}/.well-known/lnurlp/{}",
+                domain,
+                name.to_lowercase()
+            ))
+        }
+        _ => Err(NparrotError::invalid_params(
+            "lud16",
+            format!(
+                "Expected a lightning address (name@domain) or https URL, got '{}'",
+                address
+            ),
+        )),
+    }
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, NparrotError> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| NparrotError::internal(e.to_string()))?;
+    let response = client.get(url).send().await.map_err(request_error)?;
+    if !response.status().is_success() {
+        return Err(NparrotError::relay_unavailable(format!(
+            "{} answered {}",
+            url,
+            response.status()
+        )));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| NparrotError::internal(format!("Unexpected answer from {}: {}", url, e)))
+}
+
+fn request_error(e: reqwest::Error) -> NparrotError {
+    if e.is_timeout() {
+        NparrotError::timeout(format!(
+            "The lightning address provider did not answer in time: {}",
+            e
+        ))
+    } else {
+        NparrotError::relay_unavailable(format!(
+            "The lightning address provider is unreachable: {}",
+            e
+        ))
+    }
+}
+
+/// The amount of a BOLT11 invoice in millisatoshis, `None` for an invoice without an amount
+pub fn invoice_amount_msats(invoice: &str) -> Result<Option<u64>, String> {
+    let invoice = invoice.trim().to_lowercase();
+    let invoice = invoice.strip_prefix("lightning:").unwrap_or(&invoice);
+    // The data part never contains a '1', so the last one ends the human readable part
+    let (hrp, _) = invoice
+        .rsplit_once('1')
+        .ok_or_else(|| "not a BOLT11 invoice".to_string())?;
+    let currency_and_amount = hrp
+        .strip_prefix("ln")
+        .ok_or_else(|| "not a BOLT11 invoice".to_string())?;
+    let amount = currency_and_amount.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    if amount.is_empty() {
+        return Ok(None);
+    }
+
+    let (digits, multiplier) = match amount.char_indices().last() {
+        Some((i, c)) if !c.is_ascii_digit() => (&amount[..i], Some(c)),
+        _ => (amount, None),
+    };
+    if digits.is_empty() || digits.starts_with('0') || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("invalid amount '{}'", amount));
+    }
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid amount '{}'", amount))?;
+    let msats = match multiplier {
+        None => value.checked_mul(MSATS_PER_BTC),
+        Some('m') => value.checked_mul(MSATS_PER_BTC / 1_000),
+        Some('u') => value.checked_mul(MSATS_PER_BTC / 1_000_000),
+        Some('n') => value.checked_mul(MSATS_PER_BTC / 1_000_000_000),
+        // A pico-bitcoin is a tenth of a millisatoshi
+        Some('p') if value.is_multiple_of(10) => Some(value / 10),
+        Some('p') => return Err(format!("'{}' is not a whole number of msats", amount)),
+        Some(other) => return Err(format!("unknown amount multiplier '{}'", other)),
+    };
+    msats
+        .map(Some)
+        .ok_or_else(|| format!("amount '{}' is too large", amount))
+}
+
+/// A zap that checked out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zap {
+    pub receipt_id: EventId,
+    pub sender: PublicKey,
+    pub amount_msats: u64,
+    pub comment: String,
+    pub zapped_event: Option<EventId>,
+    pub created_at: Timestamp,
+}
+
+impl Zap {
+    /// Checks a zap receipt to `our_pubkey` against the provider's key `signer`
+    pub fn verify(
+        receipt: &Event,
+        signer: &PublicKey,
+        our_pubkey: &PublicKey,
+    ) -> Result<Self, String> {
+        if receipt.kind != Kind::ZapReceipt {
+            return Err(format!("kind {} is not a zap receipt", receipt.kind));
+        }
+        if receipt.pubkey != *signer {
+            return Err(format!(
+                "receipt signed by {}, not our provider",
+                receipt.pubkey
+            ));
+        }
+        receipt
+            .verify()
+            .map_err(|e| format!("bad receipt signature: {}", e))?;
+        if !receipt
+            .tags
+            .public_keys()
+            .any(|pubkey| pubkey == our_pubkey)
+        {
+            return Err("receipt is for someone else".to_string());
+        }
+
+        let request = tag_value(receipt, "description")
+            .ok_or_else(|| "receipt without zap request".to_string())?;
+        let request =
+            Event::from_json(request).map_err(|e| format!("invalid zap request: {}", e))?;
+        if request.kind != Kind::ZapRequest {
+            return Err(format!("kind {} is not a zap request", request.kind));
+        }
+        request
+            .verify()
+            .map_err(|e| format!("bad zap request signature: {}", e))?;
+        if !request
+            .tags
+            .public_keys()
+            .any(|pubkey| pubkey == our_pubkey)
+        {
+            return Err("zap request is for someone else".to_string());
+        }
+
+        let invoice =
+            tag_value(receipt, "bolt11").ok_or_else(|| "receipt without invoice".to_string())?;
+        let amount_msats =
+            invoice_amount_msats(invoice)?.ok_or_else(|| "invoice without amount".to_string())?;
+        if let Some(requested) = tag_value(&request, "amount") {
+            if requested.parse::<u64>().ok() != Some(amount_msats) {
+                return Err(format!(
+                    "paid {} msats but the request asked for {}",
+                    amount_msats, requested
+                ));
+            }
+        }
+
+        Ok(Self {
+            receipt_id: receipt.id,
+            sender: request.pubkey,
+            amount_msats,
+            comment: request.content.clone(),
+            zapped_event: tag_value(receipt, "e").and_then(|id| EventId::from_hex(id).ok()),
+            created_at: receipt.created_at,
+        })
+    }
+
+    /// The zap as a message of type `zap`, with the amount and comment in `meta`
+    pub fn into_message(self) -> IncomingMessage {
+        let sats = self.amount_msats as f64 / 1000.0;
+        let mut content = format!("⚡ Zapped {} sats", sats);
+        if !self.comment.is_empty() {
+            content = format!("{}: {}", content, self.comment);
+        }
+        let mut meta = Map::new();
+        meta.insert("amount_msats".to_string(), json!(self.amount_msats));
+        meta.insert("comment".to_string(), json!(self.comment));
+        if let Some(zapped_event) = self.zapped_event {
+            meta.insert(
+                "zapped_event".to_string(),
+                Value::String(zapped_event.to_hex()),
+            );
+        }
+        IncomingMessage {
+            detected_language: language::detect(&self.comment).to_string(),
+            content,
+            event_id: self.receipt_id,
+            sender: self.sender,
+            created_at: self.created_at,
+            message_type: Some(MessageType::Zap),
+            meta: Some(meta),
+            fragment_ids: Vec::new(),
+            content_kind: ContentKind::Text,
+            nostr_entities: Vec::new(),
+        }
+    }
+}
+
+fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event.tags.iter().find_map(|tag| match tag.as_slice() {
+        [kind, value, ..] if kind == name => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+/// Streams the zaps `sender` sends us from now on, or `None` without a zap-capable provider
+pub async fn subscribe<T: DmTransport + ?Sized>(
+    client: &T,
+    our_pubkey: PublicKey,
+    sender: PublicKey,
+) -> TransportResult<Option<mpsc::UnboundedReceiver<IncomingMessage>>> {
+    let Some(signer) = provider().and_then(|provider| provider.receipt_signer()) else {
+        return Ok(None);
+    };
+    let filter = Filter::new()
+        .kind(Kind::ZapReceipt)
+        .author(signer)
+        .pubkey(our_pubkey)
+        .since(Timestamp::now());
+    let mut receipts = client.subscribe_events(vec![filter]).await?;
+    let (zaps, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(receipt) = receipts.recv().await {
+            let zap = match Zap::verify(&receipt, &signer, &our_pubkey) {
+                Ok(zap) => zap,
+                Err(e) => {
+                    log::warn!("Ignoring zap receipt {}: {}", receipt.id, e);
+                    continue;
+                }
+            };
+            // Same allowlist as DMs
+            if zap.sender != sender {
+                log::info!("Ignoring a zap from {}", zap.sender);
+                continue;
+            }
+            if zaps.send(zap.into_message()).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Some(receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoice_amounts() {
+        let amount = |invoice| invoice_amount_msats(invoice);
+        assert_eq!(amount("lnbc2500u1pvjluez"), Ok(Some(250_000_000)));
+        assert_eq!(amount("LNBC20M1PVJLUEZ"), Ok(Some(2_000_000_000)));
+        assert_eq!(amount("lnbc1pvjluez"), Ok(None));
+        assert_eq!(amount("lntb30n1pvjluez"), Ok(Some(3_000)));
+        assert_eq!(amount("lnbcrt10p1pvjluez"), Ok(Some(1)));
+        assert_eq!(amount("lightning:lnbc1u1pvjluez"), Ok(Some(100_000)));
+        assert!(amount("lnbc15p1pvjluez").is_err());
+        assert!(amount("lnbc025u1pvjluez").is_err());
+        assert!(amount("not an invoice").is_err());
+    }
+
+    #[test]
+    fn test_lightning_address_endpoint() {
+        assert_eq!(
+            endpoint("Bot@wallet.example").unwrap(),
+            "https://wallet.example/.well-known/lnurlp/bot"
+        );
+        assert!(endpoint("wallet.example").is_err());
+    }
+
+    fn receipt(signer: &Keys, request: &Event, to: PublicKey, invoice: &str) -> Event {
+        EventBuilder::new(Kind::ZapReceipt, "")
+            .tags([
+                Tag::public_key(to),
+                Tag::custom(TagKind::custom("bolt11"), [invoice]),
+                Tag::custom(TagKind::custom("description"), [request.as_json()]),
+            ])
+            .sign_with_keys(signer)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_verify_zap_receipt() {
+        let (provider, ours, user) = (Keys::generate(), Keys::generate(), Keys::generate());
+        let request = EventBuilder::new(Kind::ZapRequest, "thanks!")
+            .tags([
+                Tag::public_key(ours.public_key()),
+                Tag::custom(TagKind::custom("amount"), ["21000"]),
+            ])
+            .sign_with_keys(&user)
+            .unwrap();
+
+        let zap = Zap::verify(
+            &receipt(&provider, &request, ours.public_key(), "lnbc210n1pvjluez"),
+            &provider.public_key(),
+            &ours.public_key(),
+        )
+        .unwrap();
+        assert_eq!(zap.sender, user.public_key());
+        assert_eq!(zap.amount_msats, 21_000);
+        let message = zap.into_message();
+        assert_eq!(message.content, "⚡ Zapped 21 sats: thanks!");
+        assert_eq!(message.message_type, Some(MessageType::Zap));
+
+        // Anyone can publish a receipt, only the provider's count
+        let forged = receipt(&user, &request, ours.public_key(), "lnbc210n1pvjluez");
+        assert!(Zap::verify(&forged, &provider.public_key(), &ours.public_key()).is_err());
+        // The invoice has to match the requested amount
+        let short = receipt(&provider, &request, ours.public_key(), "lnbc10n1pvjluez");
+        assert!(Zap::verify(&short, &provider.public_key(), &ours.public_key()).is_err());
+    }
+}